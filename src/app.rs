@@ -1,25 +1,33 @@
 use crate::{
     call::{ActiveCallRef, sip::Invitation},
     callrecord::{
-        CallRecordFormatter, CallRecordManagerBuilder, CallRecordSender, DefaultCallRecordFormatter,
+        CallRecordFormatter, CallRecordManagerBuilder, CallRecordSender,
+        DefaultCallRecordFormatter, spool::CallRecordSpool,
     },
     config::Config,
     locator::RewriteTargetLocator,
     useragent::{
         RegisterOption,
+        inbound_guard::{InboundDecision, InboundGuard},
         invitation::{
             FnCreateInvitationHandler, PendingDialog, PendingDialogGuard,
             default_create_invite_handler,
         },
+        messaging::ChatManager,
         public_address::{
             LearningMessageInspector, SharedPublicAddress, build_contact, build_public_contact_uri,
             find_local_addr_for_uri,
         },
         registration::{RegistrationHandle, UserCredential},
+        subscription::SubscriptionManager,
     },
 };
 
-use crate::media::{cache::set_cache_dir, engine::StreamEngine};
+use crate::media::{
+    cache::{set_cache_dir, set_cache_limits},
+    conference::ConferenceManager,
+    engine::StreamEngine,
+};
 use anyhow::Result;
 use arc_swap::ArcSwap;
 use chrono::{DateTime, Local};
@@ -56,6 +64,9 @@ pub struct AppStateInner {
     pub token: CancellationToken,
     pub stream_engine: Arc<StreamEngine>,
     pub callrecord_sender: Option<CallRecordSender>,
+    pub callrecord_spool: Option<Arc<CallRecordSpool>>,
+    #[cfg(feature = "cdr-sql")]
+    pub call_history: Option<Arc<crate::callrecord::history::CallHistory>>,
     pub endpoint: Endpoint,
     pub registration_handles: Mutex<HashMap<String, CancellationToken>>,
     pub alive_users: Arc<RwLock<HashSet<String>>>,
@@ -65,8 +76,12 @@ pub struct AppStateInner {
     pub routing_state: Arc<crate::call::RoutingState>,
     pub pending_playbooks: Arc<Mutex<HashMap<String, (String, Instant)>>>,
     pub learned_public_address: SharedPublicAddress,
+    pub inbound_guard: Option<Arc<InboundGuard>>,
 
     pub active_calls: Arc<std::sync::Mutex<HashMap<String, ActiveCallRef>>>,
+    pub conferences: Arc<ConferenceManager>,
+    pub chats: ChatManager,
+    pub subscriptions: SubscriptionManager,
     pub total_calls: AtomicU64,
     pub total_failed_calls: AtomicU64,
     pub uptime: DateTime<Local>,
@@ -160,6 +175,17 @@ impl AppStateInner {
             }
         }
 
+        if let Err(e) = crate::media::cache::reconcile().await {
+            warn!("failed to reconcile media cache: {}", e);
+        }
+
+        crate::call::trunk::start_health_checks(
+            endpoint_inner.clone(),
+            &self.config.trunk_groups,
+            self.routing_state.clone(),
+            token.clone(),
+        );
+
         let pending_cleanup_state = self.clone();
         let pending_cleanup_token = token.clone();
         crate::spawn(async move {
@@ -176,6 +202,30 @@ impl AppStateInner {
                         if removed > 0 {
                             info!(removed, remaining = pending.len(), "cleaned up stale pending_playbooks entries");
                         }
+                        drop(pending);
+                        if let Some(guard) = &pending_cleanup_state.inbound_guard {
+                            guard.prune();
+                        }
+                        pending_cleanup_state.chats.prune();
+                        if let Err(e) = crate::media::cache::sweep().await {
+                            warn!("failed to sweep media cache: {}", e);
+                        }
+                    }
+                }
+            }
+        });
+
+        let notify_state = self.clone();
+        let notify_token = token.clone();
+        crate::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(2));
+            loop {
+                tokio::select! {
+                    _ = notify_token.cancelled() => break,
+                    _ = interval.tick() => {
+                        if !notify_state.subscriptions.is_empty() {
+                            notify_state.subscriptions.notify_changes(&notify_state);
+                        }
                     }
                 }
             }
@@ -224,6 +274,13 @@ impl AppStateInner {
             let key: &rsipstack::transaction::key::TransactionKey = &tx.key;
             info!(?key, "received transaction");
             if tx.original.to_header()?.tag()?.as_ref().is_some() {
+                if tx.original.method == rsipstack::rsip::Method::Subscribe {
+                    crate::spawn(crate::useragent::subscription::handle_subscribe(
+                        self.clone(),
+                        tx,
+                    ));
+                    continue;
+                }
                 match dialog_layer.match_dialog(&tx) {
                     Some(mut d) => {
                         crate::spawn(async move {
@@ -252,6 +309,34 @@ impl AppStateInner {
                 }
             }
             // out dialog, new server dialog
+            if let Some(guard) = &self.inbound_guard
+                && tx.original.method != rsipstack::rsip::Method::Ack
+            {
+                match guard.inspect(&tx.original) {
+                    InboundDecision::Accept { username, trunk } => {
+                        if username.is_some() || trunk.is_some() {
+                            info!(?key, ?username, ?trunk, "inbound request accepted");
+                        }
+                    }
+                    InboundDecision::Drop(reason) => {
+                        info!(?key, reason, "dropping inbound request");
+                        continue;
+                    }
+                    InboundDecision::Reject(code, reason) => {
+                        info!(?key, ?code, reason, "rejecting inbound request");
+                        if let Err(e) = tx.reply(code).await {
+                            info!("error replying to request: {:?}", e);
+                        }
+                        continue;
+                    }
+                    InboundDecision::Challenge(code, header) => {
+                        if let Err(e) = tx.reply_with(code, vec![header], None).await {
+                            info!("error replying to request: {:?}", e);
+                        }
+                        continue;
+                    }
+                }
+            }
             let (state_sender, state_receiver) = dialog_layer.new_dialog_state_channel();
             match tx.original.method {
                 rsipstack::rsip::Method::Invite | rsipstack::rsip::Method::Ack => {
@@ -511,6 +596,22 @@ impl AppStateInner {
                     }
                     continue;
                 }
+                rsipstack::rsip::Method::Message => {
+                    info!(?key, "received out-of-dialog MESSAGE");
+                    crate::spawn(crate::useragent::messaging::handle_message(
+                        self.clone(),
+                        tx,
+                    ));
+                    continue;
+                }
+                rsipstack::rsip::Method::Subscribe => {
+                    info!(?key, "received out-of-dialog SUBSCRIBE");
+                    crate::spawn(crate::useragent::subscription::handle_subscribe(
+                        self.clone(),
+                        tx,
+                    ));
+                    continue;
+                }
                 rsipstack::rsip::Method::Refer => {
                     info!(?key, "ignoring out-of-dialog REFER");
                     match tx.reply(rsipstack::rsip::StatusCode::BadRequest).await {
@@ -991,6 +1092,12 @@ impl AppStateBuilder {
             .cancel_token
             .unwrap_or_else(|| CancellationToken::new());
         let _ = set_cache_dir(&config.media_cache_path);
+        if let Some(media_cache) = &config.media_cache {
+            let _ = set_cache_limits(
+                media_cache.max_bytes,
+                media_cache.max_age_secs.map(Duration::from_secs),
+            );
+        }
         let local_ip = if !config.addr.is_empty() {
             std::net::IpAddr::from_str(config.addr.as_str())?
         } else {
@@ -1166,14 +1273,65 @@ impl AppStateBuilder {
             Arc::new(formatter)
         };
 
+        #[cfg(feature = "cdr-sql")]
+        let call_history = match config.call_history {
+            Some(ref history_config) => {
+                let url = history_config.url(config.callrecord.as_ref());
+                match crate::callrecord::history::CallHistory::open(&url).await {
+                    Ok(history) => {
+                        let history = Arc::new(history);
+                        // Calls saved by the local sink before the index existed
+                        let local_root = config.callrecord.as_ref().and_then(|c| c.local_root());
+                        if let Some(root) = local_root {
+                            let history = history.clone();
+                            let root = root.to_string();
+                            crate::spawn(async move {
+                                match history.import_local(&root).await {
+                                    Ok(0) => {}
+                                    Ok(imported) => info!(imported, "Indexed call history"),
+                                    Err(e) => warn!("Failed to index call history: {}", e),
+                                }
+                            });
+                        }
+                        Some(history)
+                    }
+                    Err(e) => {
+                        warn!("Failed to open call history: {}", e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let mut callrecord_spool = None;
         let callrecord_sender = if let Some(sender) = self.callrecord_sender {
             Some(sender)
         } else if let Some(ref callrecord) = config.callrecord {
-            let builder = CallRecordManagerBuilder::new()
+            let mut builder = CallRecordManagerBuilder::new()
                 .with_cancel_token(token.child_token())
                 .with_config(callrecord.clone())
                 .with_max_concurrent(32)
                 .with_formatter(callrecord_formatter.clone());
+            if let Some(ref analytics) = config.analytics {
+                builder = builder.with_analytics(analytics.clone());
+            }
+            if let Some(ref spool_config) = config.callrecord_spool {
+                match CallRecordSpool::new(spool_config) {
+                    Ok(spool) => {
+                        let spool = Arc::new(spool);
+                        builder = builder.with_spool(spool.clone());
+                        callrecord_spool = Some(spool);
+                    }
+                    Err(e) => {
+                        warn!("Failed to open call record spool: {}", e);
+                    }
+                }
+            }
+            #[cfg(feature = "cdr-sql")]
+            if let Some(ref history) = call_history {
+                builder = builder.with_history(history.clone());
+            }
 
             let mut callrecord_manager = builder.build();
             let sender = callrecord_manager.sender.clone();
@@ -1185,11 +1343,18 @@ impl AppStateBuilder {
             None
         };
 
+        let inbound_guard = config
+            .inbound
+            .clone()
+            .map(|inbound| Arc::new(InboundGuard::new(inbound)));
         let app_state = Arc::new(AppStateInner {
             config,
             token,
             stream_engine,
             callrecord_sender,
+            callrecord_spool,
+            #[cfg(feature = "cdr-sql")]
+            call_history,
             endpoint,
             registration_handles: Mutex::new(HashMap::new()),
             alive_users: Arc::new(RwLock::new(HashSet::new())),
@@ -1199,7 +1364,11 @@ impl AppStateBuilder {
             routing_state: Arc::new(crate::call::RoutingState::new()),
             pending_playbooks: Arc::new(Mutex::new(HashMap::new())),
             learned_public_address,
+            inbound_guard,
             active_calls: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            conferences: Arc::new(ConferenceManager::new()),
+            chats: ChatManager::new(),
+            subscriptions: SubscriptionManager::new(),
             total_calls: AtomicU64::new(0),
             total_failed_calls: AtomicU64::new(0),
             uptime: Local::now(),
@@ -1,14 +1,17 @@
-use super::Command;
+use super::{Command, SupervisorMode};
 use crate::{
     CallOption, ReferOption,
     event::{EventReceiver, EventSender, SessionEvent},
     media::{
         TrackId,
         ambiance::SharedAmbianceProcessor,
+        conference::ConferenceOption,
         engine::StreamEngine,
         negotiate::strip_ipv6_candidates,
         processor::SubscribeProcessor,
+        quality::QualityReports,
         recorder::RecorderOption,
+        redaction::{self, REASON_PAUSED, REASON_TRANSCRIPT, RedactionSpan, TranscriptRedactor},
         stream::{MediaStream, MediaStreamBuilder, SERVER_SIDE_TRACK_ID},
         track::{
             Track, TrackConfig,
@@ -27,9 +30,16 @@ use crate::{
     app::AppState,
     call::{
         CommandReceiver, CommandSender,
-        sip::{DialogStateReceiverGuard, Invitation, InviteDialogStates},
+        sip::{self, DialogStateReceiverGuard, Invitation, InviteDialogStates},
+        trunk::{self, FailoverGuard},
     },
-    callrecord::{CallRecord, CallRecordEvent, CallRecordEventType, CallRecordHangupReason},
+    callrecord::{
+        CallRecord, CallRecordEvent, CallRecordEventType, CallRecordHangupMessage,
+        CallRecordHangupReason, CallRecordMedia,
+        transcript::{TranscriptBuilder, TranscriptFormat},
+    },
+    config::TrunkGroup,
+    handler::media_streams::StreamProtocol,
     useragent::{
         invitation::PendingDialog,
         public_address::{
@@ -40,11 +50,13 @@ use crate::{
 use anyhow::Result;
 use audio_codec::CodecType;
 use chrono::{DateTime, Utc};
-use rsipstack::dialog::{invitation::InviteOption, invite_dialog::InviteDialog};
+use rsipstack::dialog::{
+    DialogId, dialog::TerminatedReason, invitation::InviteOption, invite_dialog::InviteDialog,
+};
 use rsipstack::rsip::prelude::HeadersExt;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::Path,
     sync::{
         Arc,
@@ -569,6 +581,120 @@ mod tests {
         cancel_token.cancel();
         Ok(())
     }
+    #[tokio::test]
+    async fn test_transcript_only_for_recorded_calls() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = Config::default();
+        config.udp_port = 0;
+        config.media_cache_path = "/tmp/mediacache".to_string();
+        let app_state = AppStateBuilder::new()
+            .with_config(config)
+            .with_stream_engine(Arc::new(StreamEngine::default()))
+            .build()
+            .await?;
+        let active_call = ActiveCall::new(
+            ActiveCallType::Sip,
+            CancellationToken::new(),
+            "test-transcript".to_string(),
+            app_state.invitation.clone(),
+            app_state.clone(),
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            None,
+            None,
+        );
+        active_call
+            .call_state
+            .write()
+            .await
+            .transcript
+            .user_final(100, 900, "hello");
+
+        active_call.write_transcript().await;
+        assert!(
+            active_call
+                .call_state
+                .read()
+                .await
+                .transcript_files
+                .is_empty()
+        );
+
+        let recording = dir.path().join("test-transcript.wav");
+        active_call.call_state.write().await.recorder =
+            Some(RecorderOption::new(recording.to_string_lossy().to_string()));
+        active_call.write_transcript().await;
+        let state = active_call.call_state.read().await;
+        assert_eq!(state.transcript_files.len(), 3);
+        assert!(dir.path().join("test-transcript.transcript.json").exists());
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_supervise_switch_to_supervised_call_keeps_current() -> Result<()> {
+        let mut config = Config::default();
+        config.udp_port = 0;
+        config.media_cache_path = "/tmp/mediacache".to_string();
+        let app_state = AppStateBuilder::new()
+            .with_config(config)
+            .with_stream_engine(Arc::new(StreamEngine::default()))
+            .build()
+            .await?;
+        let new_call = |session_id: &str| {
+            Arc::new(ActiveCall::new(
+                ActiveCallType::Sip,
+                CancellationToken::new(),
+                session_id.to_string(),
+                app_state.invitation.clone(),
+                app_state.clone(),
+                TrackConfig::default(),
+                None,
+                false,
+                None,
+                None,
+                None,
+            ))
+        };
+        let supervisor = new_call("supervisor");
+        let first = new_call("first");
+        let second = new_call("second");
+        {
+            let mut calls = app_state.active_calls.lock().unwrap();
+            for call in [&supervisor, &first, &second] {
+                calls.insert(call.session_id.clone(), call.clone());
+            }
+        }
+        second.call_state.write().await.supervisor_id = Some("other".to_string());
+
+        supervisor
+            .do_supervise("first".to_string(), SupervisorMode::Listen)
+            .await?;
+        assert!(
+            supervisor
+                .do_supervise("second".to_string(), SupervisorMode::Listen)
+                .await
+                .is_err()
+        );
+
+        let supervising = supervisor
+            .call_state
+            .read()
+            .await
+            .supervising
+            .as_ref()
+            .map(|link| link.target_session_id.clone());
+        assert_eq!(supervising.as_deref(), Some("first"));
+        assert_eq!(
+            first.call_state.read().await.supervisor_id.as_deref(),
+            Some("supervisor")
+        );
+        assert_eq!(
+            second.call_state.read().await.supervisor_id.as_deref(),
+            Some("other")
+        );
+        Ok(())
+    }
 }
 
 #[derive(Deserialize)]
@@ -589,6 +715,8 @@ pub struct CallParams {
     /// Ignored. Kept so older nodes that still send `visited=` can deserialize.
     #[serde(default)]
     pub visited: Option<String>,
+    /// Speak a platform's media stream protocol instead of ours
+    pub protocol: Option<StreamProtocol>,
 }
 
 impl CallParams {
@@ -608,6 +736,9 @@ impl CallParams {
         if let Some(track) = &self.server_side_track {
             parts.push(format!("server_side_track={}", urlencoding::encode(track)));
         }
+        if let Some(protocol) = &self.protocol {
+            parts.push(format!("protocol={}", protocol));
+        }
         parts.push("forward=true".to_string());
         parts.join("&")
     }
@@ -630,12 +761,29 @@ pub struct ActiveCallState {
     pub ring_time: Option<DateTime<Utc>>,
     pub answer_time: Option<DateTime<Utc>>,
     pub hangup_reason: Option<CallRecordHangupReason>,
+    /// One entry per trunk group attempt of an outbound call
+    pub hangup_messages: Vec<CallRecordHangupMessage>,
     pub last_status_code: u16,
     pub option: Option<CallOption>,
+    /// Recording as resolved for the media stream, with its final path
+    pub recorder: Option<RecorderOption>,
     pub answer: Option<String>,
     pub ssrc: u32,
     pub refer_callstate: Option<ActiveCallStateRef>,
     pub extras: Option<HashMap<String, serde_json::Value>>,
+    /// Voice quality of the call's RTP tracks, for the call record
+    pub quality: QualityReports,
+    /// Compiled transcript patterns of the call's redaction option
+    pub transcript_redactor: Option<Arc<TranscriptRedactor>>,
+    /// Epoch ms window and reason of the latest recording pause, open while paused
+    pub recording_pause: Option<(u64, Option<u64>, String)>,
+    /// Stretches of the recording paused or to redact, in ms from its start
+    pub redactions: Vec<RedactionSpan>,
+    /// Extras holding sensitive input, masked in the call record
+    pub sensitive_extras: HashSet<String>,
+    /// Caller and agent utterances, written out when the call ends
+    pub transcript: TranscriptBuilder,
+    pub transcript_files: Vec<CallRecordMedia>,
     pub is_refer: bool,
     pub sip_hangup_headers_template: Option<HashMap<String, String>>,
 
@@ -649,10 +797,29 @@ pub struct ActiveCallState {
     pub ready_to_answer: Option<(String, PendingCallerTrack, InviteDialog)>,
     pub pending_asr_resume: Option<(u32, TranscriptionOption)>,
     pub bridge_paused: Arc<AtomicBool>,
+    /// Set while the dialogue handler (LLM) is paused, e.g. on supervisor takeover
+    pub llm_paused: Arc<AtomicBool>,
+    /// The call this session currently supervises
+    pub supervising: Option<SupervisorLink>,
+    /// Session id of the supervisor attached to this call
+    pub supervisor_id: Option<String>,
     // Cancel this token to hang up only the refer call, leaving the main call alive
     pub refer_call_token: Option<CancellationToken>,
 }
 
+/// Track id of the supervisor's audio inside the supervised call's stream.
+pub const SUPERVISOR_TRACK_ID: &str = "supervisor-track";
+/// History speaker used for supervisor transcripts and handoff notes.
+pub const SUPERVISOR_SPEAKER: &str = "supervisor";
+
+pub struct SupervisorLink {
+    pub target_session_id: String,
+    pub mode: SupervisorMode,
+    /// True in listen mode: the supervisor's voice is not forwarded
+    pub listen_only: Arc<AtomicBool>,
+    pub cancel_token: CancellationToken,
+}
+
 pub type ActiveCallRef = Arc<ActiveCall>;
 pub type ActiveCallStateRef = Arc<RwLock<ActiveCallState>>;
 
@@ -868,17 +1035,66 @@ impl ActiveCall {
         };
         let server_side_track_id = self.server_side_track_id.clone();
         let event_hook_loop = async move {
+            let mut last_final_at = crate::media::get_timestamp();
             while let Ok(event) = event_receiver.recv().await {
                 match event {
                     SessionEvent::Speaking { .. }
                     | SessionEvent::Dtmf { .. }
-                    | SessionEvent::AsrDelta { .. }
-                    | SessionEvent::AsrFinal { .. }
-                    | SessionEvent::TrackStart { .. } => {
+                    | SessionEvent::AsrDelta { .. } => {
+                        *input_timeout_expire_ref.lock().await = (0, 0);
+                    }
+                    SessionEvent::TrackStart {
+                        track_id,
+                        timestamp,
+                        ..
+                    } => {
+                        *input_timeout_expire_ref.lock().await = (0, 0);
+                        if track_id == server_side_track_id {
+                            self.call_state
+                                .write()
+                                .await
+                                .transcript
+                                .agent_started(timestamp);
+                        }
+                    }
+                    SessionEvent::Interruption {
+                        track_id,
+                        timestamp,
+                        play_id,
+                        subtitle,
+                        position,
+                        total_duration,
+                        current,
+                    } if track_id == server_side_track_id => {
+                        self.call_state.write().await.transcript.agent_interrupted(
+                            timestamp,
+                            play_id.as_deref(),
+                            subtitle.as_deref(),
+                            position,
+                            current,
+                            total_duration,
+                        );
+                    }
+                    SessionEvent::AsrFinal {
+                        timestamp,
+                        start_time,
+                        end_time,
+                        text,
+                        ..
+                    } => {
                         *input_timeout_expire_ref.lock().await = (0, 0);
+                        self.call_state.write().await.transcript.user_final(
+                            start_time.unwrap_or(timestamp),
+                            end_time.unwrap_or(timestamp),
+                            &text,
+                        );
+                        self.flag_transcript(&text, start_time, end_time, last_final_at)
+                            .await;
+                        last_final_at = timestamp;
                     }
                     SessionEvent::TrackEnd {
                         track_id,
+                        timestamp,
                         play_id,
                         ssrc,
                         ..
@@ -889,6 +1105,7 @@ impl ActiveCall {
 
                         let (moh_path, auto_hangup, wait_timeout_val) = {
                             let mut state = self.call_state.write().await;
+                            state.transcript.agent_ended(timestamp, play_id.as_deref());
                             if play_id != state.current_play_id {
                                 debug!(
                                     session_id = self.session_id,
@@ -1169,6 +1386,47 @@ impl ActiveCall {
             } => self.do_message(body, content_type, headers, refer).await,
             Command::Bridge { target_session_id } => self.do_bridge(target_session_id).await,
             Command::Unbridge { target_session_id } => self.do_unbridge(target_session_id).await,
+            Command::ConferenceJoin {
+                room,
+                muted,
+                gain,
+                option,
+            } => {
+                self.do_conference_join(room, muted.unwrap_or_default(), gain, option)
+                    .await
+            }
+            Command::ConferenceLeave { room } => self.do_conference_leave(room).await,
+            Command::ConferenceUpdate {
+                room,
+                participant_id,
+                muted,
+                gain,
+            } => self.do_conference_update(room, participant_id, muted, gain),
+            Command::ConferenceTts {
+                room,
+                text,
+                speaker,
+                play_id,
+                participant_id,
+                option,
+            } => {
+                self.do_conference_tts(room, text, speaker, play_id, participant_id, option)
+                    .await
+            }
+            Command::Supervise {
+                target_session_id,
+                mode,
+            } => {
+                self.do_supervise(target_session_id, mode.unwrap_or_default())
+                    .await
+            }
+            Command::Unsupervise { target_session_id } => {
+                self.do_unsupervise(target_session_id).await
+            }
+            Command::PauseLlm {} => self.do_pause_llm().await,
+            Command::ResumeLlm { message } => self.do_resume_llm(message).await,
+            Command::PauseRecording { reason } => self.do_pause_recording(reason).await,
+            Command::ResumeRecording {} => self.do_resume_recording().await,
             Command::Mute { track_id } => self.do_mute(track_id).await,
             Command::Unmute { track_id } => self.do_unmute(track_id).await,
             Command::Pause {} => self.do_pause().await,
@@ -1233,11 +1491,15 @@ impl ActiveCall {
                     "Recorder format fallback to wav due to unsupported feature"
                 );
             }
+            let channels = recorder_option
+                .channels
+                .unwrap_or(self.app_state.config.recorder_channels());
             let mut recorder_config = RecorderOption {
                 recorder_file,
                 samplerate: recorder_samplerate,
                 ptime: recorder_ptime,
                 format: Some(format),
+                channels: Some(channels),
             };
             recorder_config.ensure_path_extension(format);
             Some(recorder_config)
@@ -1246,6 +1508,14 @@ impl ActiveCall {
         }
     }
 
+    async fn update_redaction(&self, option: &CallOption) {
+        self.call_state.write().await.transcript_redactor = option
+            .redaction
+            .as_ref()
+            .and_then(TranscriptRedactor::new)
+            .map(Arc::new);
+    }
+
     async fn invite_or_accept(&self, mut option: CallOption, sender: String) -> Result<CallOption> {
         // Merge with existing configuration (e.g., from playbook)
         {
@@ -1255,8 +1525,10 @@ impl ActiveCall {
 
         option.check_default();
         if let Some(opt) = self.build_record_option(&option) {
+            self.call_state.write().await.recorder = Some(opt.clone());
             self.media_stream.update_recorder_option(opt).await;
         }
+        self.update_redaction(&option).await;
         self.ensure_call_ambiance(&option).await;
 
         if let Some(opt) = &option.media_pass {
@@ -1341,8 +1613,10 @@ impl ActiveCall {
         } else {
             option.check_default();
             if let Some(opt) = self.build_record_option(&option) {
+                self.call_state.write().await.recorder = Some(opt.clone());
                 self.media_stream.update_recorder_option(opt).await;
             }
+            self.update_redaction(&option).await;
             self.call_state.write().await.option = Some(option.clone());
             self.ensure_call_ambiance(&option).await;
         }
@@ -1512,6 +1786,14 @@ impl ActiveCall {
             cache_key = play_command.cache_key.as_deref(),
             "new synthesis"
         );
+        if !base64 {
+            self.call_state.write().await.transcript.agent_text(
+                play_id.as_deref(),
+                &play_command.text,
+                streaming,
+                crate::media::get_timestamp(),
+            );
+        }
 
         let ssrc = rand::random::<u32>();
         let (should_interrupt, picked_ssrc) = {
@@ -2171,7 +2453,8 @@ impl ActiveCall {
             self.cancel_token.child_token(),
             rand::random::<u32>(),
             self_paused,
-        );
+        )
+        .with_extra_sources(vec![SUPERVISOR_TRACK_ID.to_string()]);
 
         let target_forwarding_track = ForwardingTrack::new(
             target_bridge_track_id.clone(),
@@ -2182,7 +2465,8 @@ impl ActiveCall {
             target.cancel_token.child_token(),
             rand::random::<u32>(),
             target_paused,
-        );
+        )
+        .with_extra_sources(vec![SUPERVISOR_TRACK_ID.to_string()]);
 
         self.media_stream
             .update_track(Box::new(self_forwarding_track), None)
@@ -2239,126 +2523,794 @@ impl ActiveCall {
         Ok(())
     }
 
-    async fn do_mute(&self, track_id: Option<String>) -> Result<()> {
-        self.media_stream.mute_track(track_id).await;
-        Ok(())
+    fn conference_track_id(room: &str) -> TrackId {
+        format!("conference:{}", room)
     }
 
-    async fn do_unmute(&self, track_id: Option<String>) -> Result<()> {
-        self.media_stream.unmute_track(track_id).await;
+    async fn do_conference_join(
+        &self,
+        room_name: String,
+        muted: bool,
+        gain: Option<f32>,
+        option: Option<ConferenceOption>,
+    ) -> Result<()> {
+        let mut option = option.unwrap_or_default();
+        if let Some(recorder) = option.recorder.as_mut()
+            && recorder.recorder_file.is_empty()
+        {
+            recorder.recorder_file = self
+                .app_state
+                .get_recorder_file(&format!("conference-{}", room_name));
+        }
+        let track_id = Self::conference_track_id(&room_name);
+        self.media_stream.remove_track(&track_id, false).await;
+
+        let (input_sender, input_receiver) = mpsc::channel(25);
+        let (output_sender, output_receiver) = mpsc::channel(25);
+        let paused = self.call_state.read().await.bridge_paused.clone();
+        let conference_track = ForwardingTrack::new(
+            track_id.clone(),
+            self.session_id.clone(),
+            input_sender,
+            output_receiver,
+            self.track_config.clone(),
+            self.cancel_token.child_token(),
+            rand::random::<u32>(),
+            paused,
+        );
+        // No await between creating the room and joining it
+        let room = self.app_state.conferences.get_or_create(
+            &room_name,
+            &option,
+            self.app_state.token.child_token(),
+        );
+        room.join(
+            self.session_id.clone(),
+            input_receiver,
+            output_sender,
+            self.event_sender.clone(),
+            muted,
+            gain.unwrap_or(1.0),
+        )?;
+        self.media_stream
+            .update_track(Box::new(conference_track), None)
+            .await;
+        info!(
+            session_id = self.session_id,
+            room = room_name,
+            track_id,
+            muted,
+            "joined conference"
+        );
         Ok(())
     }
 
-    pub async fn cleanup(&self) -> Result<()> {
-        if matches!(self.call_type, ActiveCallType::Sip | ActiveCallType::B2bua) {
-            self.do_reject(
-                Some(rsipstack::rsip::StatusCode::Decline),
-                Some("handler disconnected".to_string()),
-            )
-            .await
-            .ok();
+    async fn do_conference_leave(&self, room_name: String) -> Result<()> {
+        if let Some(room) = self.app_state.conferences.get(&room_name) {
+            room.leave(&self.session_id);
         }
-        self.call_state.write().await.tts_handle = None;
-        self.media_stream.cleanup().await.ok();
+        self.media_stream
+            .remove_track(&Self::conference_track_id(&room_name), false)
+            .await;
+        info!(
+            session_id = self.session_id,
+            room = room_name,
+            "left conference"
+        );
         Ok(())
     }
 
-    pub fn get_callrecord(&self) -> Option<CallRecord> {
-        self.call_state.try_read().ok().map(|call_state| {
-            call_state.build_callrecord(
-                self.app_state.clone(),
-                self.session_id.clone(),
-                self.call_type.clone(),
-            )
-        })
-    }
-
-    async fn dump_to_file(
+    fn do_conference_update(
         &self,
-        dump_file: &mut File,
-        cmd_receiver: &mut CommandReceiver,
-        event_receiver: &mut EventReceiver,
-    ) {
-        loop {
-            select! {
-                _ = self.cancel_token.cancelled() => {
-                    break;
-                }
-                Ok(cmd) = cmd_receiver.recv() => {
-                    CallRecordEvent::write(CallRecordEventType::Command, cmd, dump_file)
-                        .await;
-                }
-                Ok(event) = event_receiver.recv() => {
-                    if matches!(event, SessionEvent::Binary{..}) {
-                        continue;
-                    }
-                    CallRecordEvent::write(CallRecordEventType::Event, event, dump_file)
-                        .await;
-                }
-            };
-        }
+        room_name: String,
+        participant_id: Option<String>,
+        muted: Option<bool>,
+        gain: Option<f32>,
+    ) -> Result<()> {
+        let room = self
+            .app_state
+            .conferences
+            .get(&room_name)
+            .ok_or_else(|| anyhow::anyhow!("conference not found: {}", room_name))?;
+        let participant_id = participant_id.unwrap_or_else(|| self.session_id.clone());
+        room.update_participant(&participant_id, muted, gain)
     }
 
-    async fn dump_loop(
+    async fn do_conference_tts(
         &self,
-        dump_events: bool,
-        mut dump_cmd_receiver: CommandReceiver,
-        mut dump_event_receiver: EventReceiver,
-    ) {
-        if !dump_events {
-            return;
-        }
-
-        let file_name = self.app_state.get_dump_events_file(&self.session_id);
-        let mut dump_file = match File::options()
-            .create(true)
-            .append(true)
-            .open(&file_name)
-            .await
-        {
-            Ok(file) => file,
-            Err(e) => {
-                warn!(
-                    session_id = self.session_id,
-                    file_name, "failed to open dump events file: {}", e
-                );
-                return;
+        room_name: String,
+        text: String,
+        speaker: Option<String>,
+        play_id: Option<String>,
+        participant_id: Option<String>,
+        option: Option<SynthesisOption>,
+    ) -> Result<()> {
+        let room = self
+            .app_state
+            .conferences
+            .get(&room_name)
+            .ok_or_else(|| anyhow::anyhow!("conference not found: {}", room_name))?;
+        let tts_option = {
+            let call_state = self.call_state.read().await;
+            match call_state.option.clone().unwrap_or_default().tts {
+                Some(opt) => opt.merge_with(option),
+                None => option.ok_or_else(|| anyhow::anyhow!("no tts option available"))?,
             }
         };
-        self.dump_to_file(
-            &mut dump_file,
-            &mut dump_cmd_receiver,
-            &mut dump_event_receiver,
+        room.play_tts(
+            self.app_state.stream_engine.clone(),
+            text,
+            speaker,
+            play_id,
+            tts_option,
+            participant_id,
         )
-        .await;
+        .await?;
+        Ok(())
+    }
 
-        while let Ok(event) = dump_event_receiver.try_recv() {
-            if matches!(event, SessionEvent::Binary { .. }) {
-                continue;
-            }
-            CallRecordEvent::write(CallRecordEventType::Event, event, &mut dump_file).await;
-        }
+    fn supervise_track_id(target_session_id: &str) -> TrackId {
+        format!("supervise:{}", target_session_id)
     }
 
-    pub async fn create_rtp_track(
-        &self,
-        track_id: TrackId,
-        ssrc: u32,
-        enable_srtp: Option<bool>,
-    ) -> Result<RtcTrack> {
-        let mut rtc_config = RtcTrackConfig::default();
-        // Per-call flag takes precedence over global config.
-        let use_srtp = enable_srtp
-            .or(self.app_state.config.enable_srtp)
-            .unwrap_or(false);
-        rtc_config.mode = if use_srtp {
-            rustrtc::TransportMode::Srtp
-        } else {
-            rustrtc::TransportMode::Rtp
+    /// Routes the supervisor's audio inside the supervised stream: whisper
+    /// keeps it away from the caller leg, barge lets every leg hear it.
+    async fn apply_supervisor_mode(target: &ActiveCall, mode: SupervisorMode) {
+        let excluded = match mode {
+            SupervisorMode::Whisper => vec![target.session_id.clone()],
+            _ => vec![],
         };
+        target
+            .media_stream
+            .exclude_forwarding(&SUPERVISOR_TRACK_ID.to_string(), excluded)
+            .await;
+    }
 
-        if let Some(codecs) = &self.app_state.config.codecs {
-            let mut codec_types = Vec::new();
+    fn supervisor_event(
+        &self,
+        track_id: &str,
+        target_session_id: &str,
+        mode: Option<SupervisorMode>,
+    ) -> SessionEvent {
+        SessionEvent::Supervisor {
+            track_id: track_id.to_string(),
+            timestamp: crate::media::get_timestamp(),
+            supervisor_id: self.session_id.clone(),
+            target_session_id: target_session_id.to_string(),
+            mode,
+        }
+    }
+
+    async fn do_supervise(&self, target_session_id: String, mode: SupervisorMode) -> Result<()> {
+        let target = {
+            let calls = self.app_state.active_calls.lock().unwrap();
+            calls.get(&target_session_id).cloned()
+        };
+        let target = target.ok_or_else(|| {
+            anyhow::anyhow!("supervise target session not found: {}", target_session_id)
+        })?;
+        if target.session_id == self.session_id {
+            return Err(anyhow::anyhow!("cannot supervise a call from itself"));
+        }
+
+        let switched = {
+            let mut state = self.call_state.write().await;
+            match state.supervising.as_mut() {
+                Some(link) if link.target_session_id == target_session_id => {
+                    link.mode = mode;
+                    link.listen_only
+                        .store(mode == SupervisorMode::Listen, Ordering::Relaxed);
+                    true
+                }
+                _ => false,
+            }
+        };
+        if switched {
+            Self::apply_supervisor_mode(&target, mode).await;
+        } else {
+            // Reserve the new target before leaving the current one, so a
+            // rejected switch keeps the existing supervision intact.
+            {
+                let mut target_state = target.call_state.write().await;
+                if let Some(supervisor_id) = &target_state.supervisor_id {
+                    return Err(anyhow::anyhow!(
+                        "session {} is already supervised by {}",
+                        target_session_id,
+                        supervisor_id
+                    ));
+                }
+                target_state.supervisor_id = Some(self.session_id.clone());
+            }
+            let previous = self
+                .call_state
+                .read()
+                .await
+                .supervising
+                .as_ref()
+                .map(|link| link.target_session_id.clone());
+            if let Some(previous) = previous {
+                if let Err(e) = self.do_unsupervise(previous).await {
+                    target.call_state.write().await.supervisor_id = None;
+                    return Err(e);
+                }
+            }
+            self.attach_supervisor(&target, mode).await;
+        }
+
+        let event = self.supervisor_event(&target.session_id, &target_session_id, Some(mode));
+        target.event_sender.send(event).ok();
+        let event = self.supervisor_event(&self.session_id, &target_session_id, Some(mode));
+        self.event_sender.send(event).ok();
+        info!(
+            session_id = self.session_id,
+            target = target_session_id,
+            ?mode,
+            switched,
+            "supervisor attached"
+        );
+        Ok(())
+    }
+
+    async fn attach_supervisor(&self, target: &ActiveCallRef, mode: SupervisorMode) {
+        let target_session_id = target.session_id.clone();
+        // Target stream -> supervisor: everything heard on the call, mixed
+        // around the caller leg. Supervisor -> target stream: the supervisor's
+        // own voice, held back in listen mode.
+        let (to_supervisor_sender, to_supervisor_receiver) = mpsc::channel(25);
+        let (to_target_sender, to_target_receiver) = mpsc::channel(25);
+        let listen_only = Arc::new(AtomicBool::new(mode == SupervisorMode::Listen));
+        let track_id = Self::supervise_track_id(&target_session_id);
+
+        let target_track = ForwardingTrack::new(
+            SUPERVISOR_TRACK_ID.to_string(),
+            target.session_id.clone(),
+            to_supervisor_sender,
+            to_target_receiver,
+            target.track_config.clone(),
+            target.cancel_token.child_token(),
+            rand::random::<u32>(),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .with_forward_all(true);
+        let supervisor_track = ForwardingTrack::new(
+            track_id.clone(),
+            self.session_id.clone(),
+            to_target_sender,
+            to_supervisor_receiver,
+            self.track_config.clone(),
+            self.cancel_token.child_token(),
+            rand::random::<u32>(),
+            listen_only.clone(),
+        )
+        .with_inbound_mix(target.session_id.clone());
+
+        Self::apply_supervisor_mode(target, mode).await;
+        target
+            .media_stream
+            .update_track(Box::new(target_track), None)
+            .await;
+        self.media_stream
+            .update_track(Box::new(supervisor_track), None)
+            .await;
+
+        let cancel_token = self.cancel_token.child_token();
+        self.call_state.write().await.supervising = Some(SupervisorLink {
+            target_session_id: target_session_id.clone(),
+            mode,
+            listen_only: listen_only.clone(),
+            cancel_token: cancel_token.clone(),
+        });
+
+        // Relay the supervisor's transcript into the supervised dialogue while
+        // speaking, and detach from the target once the link ends.
+        let mut event_receiver = self.event_sender.subscribe();
+        let target_cmd_sender = target.cmd_sender.clone();
+        let weak_target = Arc::downgrade(target);
+        let supervisor_id = self.session_id.clone();
+        crate::spawn(async move {
+            loop {
+                select! {
+                    _ = cancel_token.cancelled() => break,
+                    event = event_receiver.recv() => match event {
+                        Ok(SessionEvent::AsrFinal { track_id, text, .. })
+                            if track_id == supervisor_id
+                                && !text.is_empty()
+                                && !listen_only.load(Ordering::Relaxed) =>
+                        {
+                            target_cmd_sender
+                                .send(Command::History {
+                                    speaker: SUPERVISOR_SPEAKER.to_string(),
+                                    text,
+                                })
+                                .ok();
+                        }
+                        Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+            if let Some(target) = weak_target.upgrade() {
+                Self::detach_supervisor(&target, &supervisor_id).await;
+            }
+        });
+    }
+
+    /// Removes the supervisor's track from the supervised call, if it is
+    /// still attached there.
+    async fn detach_supervisor(target: &ActiveCall, supervisor_id: &str) {
+        {
+            let mut target_state = target.call_state.write().await;
+            if target_state.supervisor_id.as_deref() != Some(supervisor_id) {
+                return;
+            }
+            target_state.supervisor_id = None;
+        }
+        let track_id = SUPERVISOR_TRACK_ID.to_string();
+        target.media_stream.remove_track(&track_id, false).await;
+        target
+            .media_stream
+            .exclude_forwarding(&track_id, vec![])
+            .await;
+        target
+            .event_sender
+            .send(SessionEvent::Supervisor {
+                track_id: target.session_id.clone(),
+                timestamp: crate::media::get_timestamp(),
+                supervisor_id: supervisor_id.to_string(),
+                target_session_id: target.session_id.clone(),
+                mode: None,
+            })
+            .ok();
+    }
+
+    async fn do_unsupervise(&self, target_session_id: String) -> Result<()> {
+        let link = {
+            let mut state = self.call_state.write().await;
+            match &state.supervising {
+                Some(link) if link.target_session_id == target_session_id => {
+                    state.supervising.take()
+                }
+                _ => None,
+            }
+        };
+        let Some(link) = link else {
+            return Err(anyhow::anyhow!(
+                "not supervising session: {}",
+                target_session_id
+            ));
+        };
+        link.cancel_token.cancel();
+        self.media_stream
+            .remove_track(&Self::supervise_track_id(&target_session_id), false)
+            .await;
+        let target = {
+            let calls = self.app_state.active_calls.lock().unwrap();
+            calls.get(&target_session_id).cloned()
+        };
+        if let Some(target) = target {
+            Self::detach_supervisor(&target, &self.session_id).await;
+        }
+        let event = self.supervisor_event(&self.session_id, &target_session_id, None);
+        self.event_sender.send(event).ok();
+        info!(
+            session_id = self.session_id,
+            target = target_session_id,
+            "supervisor detached"
+        );
+        Ok(())
+    }
+
+    async fn do_pause_llm(&self) -> Result<()> {
+        let paused = self.call_state.read().await.llm_paused.clone();
+        if paused.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.do_interrupt(false).await?;
+        self.event_sender
+            .send(SessionEvent::LlmState {
+                track_id: self.session_id.clone(),
+                timestamp: crate::media::get_timestamp(),
+                paused: true,
+            })
+            .ok();
+        Ok(())
+    }
+
+    async fn do_resume_llm(&self, message: Option<String>) -> Result<()> {
+        let paused = self.call_state.read().await.llm_paused.clone();
+        if let Some(message) = message.filter(|m| !m.is_empty()) {
+            self.do_history(SUPERVISOR_SPEAKER.to_string(), message)
+                .await?;
+        }
+        if !paused.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.event_sender
+            .send(SessionEvent::LlmState {
+                track_id: self.session_id.clone(),
+                timestamp: crate::media::get_timestamp(),
+                paused: false,
+            })
+            .ok();
+        Ok(())
+    }
+
+    async fn do_pause_recording(&self, reason: Option<String>) -> Result<()> {
+        if self.media_stream.pause_recording(true) {
+            return Ok(());
+        }
+        self.call_state.write().await.recording_pause = Some((
+            crate::media::get_timestamp(),
+            None,
+            reason.clone().unwrap_or_else(|| REASON_PAUSED.to_string()),
+        ));
+        info!(session_id = self.session_id, ?reason, "recording paused");
+        self.event_sender
+            .send(SessionEvent::RecordingState {
+                track_id: self.session_id.clone(),
+                timestamp: crate::media::get_timestamp(),
+                paused: true,
+                reason,
+            })
+            .ok();
+        Ok(())
+    }
+
+    async fn do_resume_recording(&self) -> Result<()> {
+        if !self.media_stream.pause_recording(false) {
+            return Ok(());
+        }
+        self.close_recording_pause().await;
+        info!(session_id = self.session_id, "recording resumed");
+        self.event_sender
+            .send(SessionEvent::RecordingState {
+                track_id: self.session_id.clone(),
+                timestamp: crate::media::get_timestamp(),
+                paused: false,
+                reason: None,
+            })
+            .ok();
+        Ok(())
+    }
+
+    /// Ends the open pause window, listing it among the redactions
+    async fn close_recording_pause(&self) {
+        let now = crate::media::get_timestamp();
+        let mut state = self.call_state.write().await;
+        let recording = state.recorder.is_some();
+        let Some((start, end @ None, reason)) = state.recording_pause.as_mut() else {
+            return;
+        };
+        *end = Some(now);
+        let span = RedactionSpan {
+            start: self.media_stream.recording_offset(*start).unwrap_or(0),
+            end: self.media_stream.recording_offset(now).unwrap_or(0),
+            reason: reason.clone(),
+        };
+        if recording {
+            state.redactions.push(span);
+        }
+    }
+
+    /// Flags the audio of a final transcript matching a redaction pattern.
+    /// `since` stands in for the start of the utterance when the ASR
+    /// provider does not report it.
+    async fn flag_transcript(
+        &self,
+        text: &str,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        since: u64,
+    ) {
+        let mut state = self.call_state.write().await;
+        let Some(redactor) = state.transcript_redactor.clone() else {
+            return;
+        };
+        if state.recorder.is_none() || !redactor.is_match(text) {
+            return;
+        }
+        let padding = state
+            .option
+            .as_ref()
+            .and_then(|option| option.redaction.as_ref())
+            .map(|redaction| redaction.padding())
+            .unwrap_or_default();
+        let end = end_time.unwrap_or_else(crate::media::get_timestamp);
+        let (Some(start), Some(end)) = (
+            self.media_stream
+                .recording_offset(start_time.unwrap_or(since)),
+            self.media_stream.recording_offset(end),
+        ) else {
+            return;
+        };
+        let span = RedactionSpan {
+            start: start.saturating_sub(padding),
+            end: end + padding,
+            reason: REASON_TRANSCRIPT.to_string(),
+        };
+        info!(
+            session_id = self.session_id,
+            start = span.start,
+            end = span.end,
+            "transcript flagged for redaction"
+        );
+        state.redactions.push(span);
+    }
+
+    /// Masks DTMF digits entered while recording was paused, or always with
+    /// `maskDtmf`, and transcript matches before the event is dumped
+    async fn mask_event(&self, mut event: SessionEvent) -> SessionEvent {
+        match &mut event {
+            SessionEvent::Dtmf {
+                timestamp, digit, ..
+            } => {
+                let state = self.call_state.read().await;
+                let mask_dtmf = state
+                    .option
+                    .as_ref()
+                    .and_then(|option| option.redaction.as_ref())
+                    .is_some_and(|redaction| redaction.mask_dtmf());
+                let paused = matches!(
+                    state.recording_pause,
+                    Some((start, end, _)) if *timestamp >= start && end.is_none_or(|end| *timestamp <= end)
+                );
+                if mask_dtmf || paused {
+                    *digit = redaction::mask(digit);
+                }
+            }
+            SessionEvent::AsrFinal { text, words, .. } => {
+                let redactor = self.call_state.read().await.transcript_redactor.clone();
+                if let Some(redactor) = redactor
+                    && redactor.is_match(text)
+                {
+                    *text = redactor.redact(text);
+                    *words = None;
+                }
+            }
+            SessionEvent::AsrDelta { text, .. } => {
+                let redactor = self.call_state.read().await.transcript_redactor.clone();
+                if let Some(redactor) = redactor {
+                    *text = redactor.redact(text);
+                }
+            }
+            _ => {}
+        }
+        event
+    }
+
+    /// Blanks the flagged spans out of the recording files once the
+    /// recorder has closed them
+    async fn redact_recording(&self) {
+        let (files, spans, mode) = {
+            let state = self.call_state.read().await;
+            let Some(recorder) = state.recorder.as_ref() else {
+                return;
+            };
+            if state.redactions.is_empty() {
+                return;
+            }
+            let mode = state
+                .option
+                .as_ref()
+                .and_then(|option| option.redaction.as_ref())
+                .map(|redaction| redaction.mode())
+                .unwrap_or_default();
+            (recorder.output_files(), state.redactions.clone(), mode)
+        };
+        let session_id = self.session_id.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            for (_, path) in files {
+                let path = Path::new(&path);
+                if !path.exists() {
+                    continue;
+                }
+                match redaction::redact_file(path, &spans, mode) {
+                    Ok(()) => info!(
+                        session_id,
+                        path = %path.display(),
+                        spans = spans.len(),
+                        "recording redacted"
+                    ),
+                    Err(e) => warn!(
+                        session_id,
+                        path = %path.display(),
+                        "failed to redact recording: {}", e
+                    ),
+                }
+            }
+        })
+        .await;
+        if let Err(e) = result {
+            warn!(session_id = self.session_id, "redaction task failed: {}", e);
+        }
+    }
+
+    /// Writes the transcript next to the recording, aligned to its start,
+    /// in every [`TranscriptFormat`]
+    async fn write_transcript(&self) {
+        let (transcript, base) = {
+            let mut state = self.call_state.write().await;
+            // Written next to the recording, so only for recorded calls
+            let Some(recording) = state
+                .recorder
+                .as_ref()
+                .map(|recorder| recorder.recorder_file.clone())
+            else {
+                return;
+            };
+            if state.transcript.is_empty() {
+                return;
+            }
+            let now = crate::media::get_timestamp();
+            let origin = self
+                .media_stream
+                .recording_started_at()
+                .unwrap_or(state.start_time.timestamp_millis() as u64);
+            let mut transcript = state
+                .transcript
+                .finish(self.session_id.clone(), origin, now);
+            if let Some(redactor) = &state.transcript_redactor {
+                for segment in transcript.segments.iter_mut() {
+                    segment.text = redactor.redact(&segment.text);
+                }
+            }
+            (transcript, Path::new(&recording).with_extension(""))
+        };
+
+        let mut files = Vec::new();
+        for format in TranscriptFormat::ALL {
+            let path = format!("{}.{}", base.display(), format.extension());
+            let content = transcript.render(format);
+            if let Err(e) = tokio::fs::write(&path, &content).await {
+                warn!(
+                    session_id = self.session_id,
+                    path, "failed to write transcript: {}", e
+                );
+                continue;
+            }
+            files.push(CallRecordMedia {
+                track_id: self.session_id.clone(),
+                path,
+                size: content.len() as u64,
+                extra: Some(HashMap::from([(
+                    "format".to_string(),
+                    serde_json::json!(format),
+                )])),
+            });
+        }
+        info!(
+            session_id = self.session_id,
+            segments = transcript.segments.len(),
+            "transcript written"
+        );
+        self.call_state.write().await.transcript_files = files;
+    }
+
+    async fn do_mute(&self, track_id: Option<String>) -> Result<()> {
+        self.media_stream.mute_track(track_id).await;
+        Ok(())
+    }
+
+    async fn do_unmute(&self, track_id: Option<String>) -> Result<()> {
+        self.media_stream.unmute_track(track_id).await;
+        Ok(())
+    }
+
+    pub async fn cleanup(&self) -> Result<()> {
+        if matches!(self.call_type, ActiveCallType::Sip | ActiveCallType::B2bua) {
+            self.do_reject(
+                Some(rsipstack::rsip::StatusCode::Decline),
+                Some("handler disconnected".to_string()),
+            )
+            .await
+            .ok();
+        }
+        self.call_state.write().await.tts_handle = None;
+        self.close_recording_pause().await;
+        self.media_stream.cleanup().await.ok();
+        self.redact_recording().await;
+        self.write_transcript().await;
+        Ok(())
+    }
+
+    pub fn get_callrecord(&self) -> Option<CallRecord> {
+        self.call_state.try_read().ok().map(|call_state| {
+            call_state.build_callrecord(
+                self.app_state.clone(),
+                self.session_id.clone(),
+                self.call_type.clone(),
+            )
+        })
+    }
+
+    async fn dump_to_file(
+        &self,
+        dump_file: &mut File,
+        cmd_receiver: &mut CommandReceiver,
+        event_receiver: &mut EventReceiver,
+    ) {
+        loop {
+            select! {
+                _ = self.cancel_token.cancelled() => {
+                    break;
+                }
+                Ok(cmd) = cmd_receiver.recv() => {
+                    CallRecordEvent::write(CallRecordEventType::Command, cmd, dump_file)
+                        .await;
+                }
+                Ok(event) = event_receiver.recv() => {
+                    if matches!(event, SessionEvent::Binary{..}) {
+                        continue;
+                    }
+                    let event = self.mask_event(event).await;
+                    CallRecordEvent::write(CallRecordEventType::Event, event, dump_file)
+                        .await;
+                }
+            };
+        }
+    }
+
+    async fn dump_loop(
+        &self,
+        dump_events: bool,
+        mut dump_cmd_receiver: CommandReceiver,
+        mut dump_event_receiver: EventReceiver,
+    ) {
+        if !dump_events {
+            return;
+        }
+
+        let file_name = self.app_state.get_dump_events_file(&self.session_id);
+        let mut dump_file = match File::options()
+            .create(true)
+            .append(true)
+            .open(&file_name)
+            .await
+        {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(
+                    session_id = self.session_id,
+                    file_name, "failed to open dump events file: {}", e
+                );
+                return;
+            }
+        };
+        self.dump_to_file(
+            &mut dump_file,
+            &mut dump_cmd_receiver,
+            &mut dump_event_receiver,
+        )
+        .await;
+
+        while let Ok(event) = dump_event_receiver.try_recv() {
+            if matches!(event, SessionEvent::Binary { .. }) {
+                continue;
+            }
+            let event = self.mask_event(event).await;
+            CallRecordEvent::write(CallRecordEventType::Event, event, &mut dump_file).await;
+        }
+    }
+
+    pub async fn create_rtp_track(
+        &self,
+        track_id: TrackId,
+        ssrc: u32,
+        enable_srtp: Option<bool>,
+    ) -> Result<RtcTrack> {
+        let mut rtc_config = RtcTrackConfig::default();
+        // Per-call flag takes precedence over global config.
+        let use_srtp = enable_srtp
+            .or(self.app_state.config.enable_srtp)
+            .unwrap_or(false);
+        rtc_config.mode = if use_srtp {
+            rustrtc::TransportMode::Srtp
+        } else {
+            rustrtc::TransportMode::Rtp
+        };
+
+        if let Some(codecs) = &self.app_state.config.codecs {
+            let mut codec_types = Vec::new();
             for c in codecs {
                 match c.to_lowercase().as_str() {
                     "pcmu" => codec_types.push(CodecType::PCMU),
@@ -2404,6 +3356,13 @@ impl ActiveCall {
             .as_ref()
             .and_then(|o| o.enable_ice_lite)
             .or(self.app_state.config.enable_ice_lite);
+        rtc_config.jitter_buffer = self
+            .call_state
+            .read()
+            .await
+            .option
+            .as_ref()
+            .and_then(|o| o.jitter_buffer.clone());
 
         let mut track = RtcTrack::new(
             self.cancel_token.child_token(),
@@ -2411,7 +3370,8 @@ impl ActiveCall {
             self.track_config.clone(),
             rtc_config,
         )
-        .with_ssrc(ssrc);
+        .with_ssrc(ssrc)
+        .with_quality_reports(self.call_state.read().await.quality.clone());
 
         track.create().await?;
 
@@ -2826,6 +3786,13 @@ impl ActiveCall {
         if let Some(ref bind_ip) = self.app_state.config.rtp_bind_ip {
             rtc_config.bind_ip = Some(bind_ip.clone());
         }
+        rtc_config.jitter_buffer = self
+            .call_state
+            .read()
+            .await
+            .option
+            .as_ref()
+            .and_then(|o| o.jitter_buffer.clone());
 
         let mut webrtc_track = RtcTrack::new(
             self.cancel_token.child_token(),
@@ -2833,7 +3800,8 @@ impl ActiveCall {
             self.track_config.clone(),
             rtc_config,
         )
-        .with_ssrc(ssrc);
+        .with_ssrc(ssrc)
+        .with_quality_reports(self.call_state.read().await.quality.clone());
 
         let timeout = option.handshake_timeout.map(|t| Duration::from_secs(t));
         let offer = match option.enable_ipv6 {
@@ -2878,7 +3846,11 @@ impl ActiveCall {
         // Apply trunk rules (match + rewrite caller/callee/contact) to the
         // outgoing INVITE/REFER before it is sent. Covers both normal invite
         // calls and refer legs since both flow through this function.
-        self.app_state.config.apply_trunk_rules(&mut invite_option);
+        let trunk_group = self
+            .app_state
+            .config
+            .apply_trunk_rules(&mut invite_option)
+            .cloned();
 
         let ssrc = call_state_ref.read().await.ssrc;
         let per_call_srtp = call_option.sip.as_ref().and_then(|s| s.enable_srtp);
@@ -2982,21 +3954,6 @@ impl ActiveCall {
             offer.as_ref().map(|s| s.as_str()).unwrap_or("<NO OFFER>")
         );
 
-        let (dlg_state_sender, dlg_state_receiver) =
-            self.invitation.dialog_layer.new_dialog_state_channel();
-
-        let states = InviteDialogStates {
-            is_client: true,
-            session_id: self.session_id.clone(),
-            track_id: track_id.clone(),
-            event_sender: self.event_sender.clone(),
-            media_stream: self.media_stream.clone(),
-            call_state: call_state_ref.clone(),
-            cancel_token,
-            terminated_reason: None,
-            has_early_media: false,
-        };
-
         let hangup_headers = call_option
             .sip
             .as_ref()
@@ -3008,20 +3965,30 @@ impl ActiveCall {
                     .collect::<Vec<rsipstack::rsip::Header>>()
             });
 
-        let mut client_dialog_handler = DialogStateReceiverGuard::new(
-            self.invitation.dialog_layer.clone(),
-            dlg_state_receiver,
-            hangup_headers,
-        );
-
-        crate::spawn(async move {
-            client_dialog_handler.process_dialog(states).await;
-        });
-
-        let (dialog_id, answer) = self
-            .invitation
-            .invite(invite_option, dlg_state_sender)
-            .await?;
+        let (dialog_id, answer) = match trunk_group {
+            Some(group) => {
+                self.invite_trunk_group(
+                    cancel_token,
+                    &call_state_ref,
+                    track_id,
+                    invite_option,
+                    hangup_headers,
+                    group,
+                )
+                .await?
+            }
+            None => {
+                self.send_invite(
+                    cancel_token,
+                    &call_state_ref,
+                    track_id,
+                    invite_option,
+                    hangup_headers,
+                    None,
+                )
+                .await?
+            }
+        };
 
         self.call_state.write().await.moh = None;
 
@@ -3110,6 +4077,178 @@ impl ActiveCall {
         Ok(answer)
     }
 
+    async fn send_invite(
+        &self,
+        cancel_token: CancellationToken,
+        call_state_ref: &ActiveCallStateRef,
+        track_id: &TrackId,
+        invite_option: InviteOption,
+        hangup_headers: Option<Vec<rsipstack::rsip::Header>>,
+        failover: Option<FailoverGuard>,
+    ) -> Result<(DialogId, Option<Vec<u8>>), rsipstack::Error> {
+        let (dlg_state_sender, dlg_state_receiver) =
+            self.invitation.dialog_layer.new_dialog_state_channel();
+
+        let states = InviteDialogStates {
+            is_client: true,
+            session_id: self.session_id.clone(),
+            track_id: track_id.clone(),
+            event_sender: self.event_sender.clone(),
+            media_stream: self.media_stream.clone(),
+            call_state: call_state_ref.clone(),
+            cancel_token,
+            terminated_reason: None,
+            has_early_media: false,
+            failover,
+        };
+
+        let mut client_dialog_handler = DialogStateReceiverGuard::new(
+            self.invitation.dialog_layer.clone(),
+            dlg_state_receiver,
+            hangup_headers,
+        );
+
+        crate::spawn(async move {
+            client_dialog_handler.process_dialog(states).await;
+        });
+
+        self.invitation
+            .invite(invite_option, dlg_state_sender)
+            .await
+    }
+
+    /// Try the members of a trunk group in turn until one answers, or fails
+    /// with a code that is not in the group's failover codes.
+    async fn invite_trunk_group(
+        &self,
+        cancel_token: CancellationToken,
+        call_state_ref: &ActiveCallStateRef,
+        track_id: &TrackId,
+        invite_option: InviteOption,
+        hangup_headers: Option<Vec<rsipstack::rsip::Header>>,
+        group: TrunkGroup,
+    ) -> Result<(DialogId, Option<Vec<u8>>), rsipstack::Error> {
+        let members =
+            trunk::plan_members(&group, &invite_option.callee, &self.app_state.routing_state);
+        if members.is_empty() {
+            warn!(
+                session_id = self.session_id,
+                group = group.name,
+                "no available member in trunk group"
+            );
+            return Err(rsipstack::Error::Error(format!(
+                "no available member in trunk group {}",
+                group.name
+            )));
+        }
+
+        let last = members.len() - 1;
+        let attempt_timeout = group.attempt_timeout();
+        let mut index = 0;
+        loop {
+            let member = &members[index];
+            let mut attempt = invite_option.clone();
+            trunk::apply_member(member, &mut attempt);
+            let target = attempt.callee.to_string();
+            {
+                let mut cs = call_state_ref.write().await;
+                cs.ring_time = None;
+                cs.answer = None;
+            }
+            info!(
+                session_id = self.session_id,
+                group = group.name,
+                trunk = member.name,
+                attempt = index + 1,
+                "trying trunk {}",
+                target
+            );
+
+            let guard = FailoverGuard::new(cancel_token.clone(), index < last);
+            let attempt_token = cancel_token.child_token();
+            // Give up on the member when it has not even started ringing
+            // within the attempt timeout.
+            let no_progress = async {
+                sleep(attempt_timeout).await;
+                if call_state_ref.read().await.ring_time.is_some() {
+                    std::future::pending::<()>().await;
+                }
+            };
+            let (result, timed_out) = select! {
+                result = self.send_invite(
+                    attempt_token.clone(),
+                    call_state_ref,
+                    track_id,
+                    attempt,
+                    hangup_headers.clone(),
+                    Some(guard.clone()),
+                ) => (result, false),
+                _ = no_progress => {
+                    attempt_token.cancel();
+                    (
+                        Err(rsipstack::Error::Error(format!(
+                            "no response from trunk {} within {:?}",
+                            member.name, attempt_timeout
+                        ))),
+                        true,
+                    )
+                }
+            };
+
+            let code = if timed_out {
+                408
+            } else {
+                trunk::attempt_status(&result)
+            };
+            call_state_ref
+                .write()
+                .await
+                .hangup_messages
+                .push(CallRecordHangupMessage {
+                    code,
+                    reason: result.as_ref().err().map(|e| e.to_string()),
+                    target: Some(target),
+                    trunk: Some(member.name.clone()),
+                });
+
+            let err = match result {
+                Ok(answered) => {
+                    guard.finish();
+                    return Ok(answered);
+                }
+                Err(err) => err,
+            };
+
+            let failover = timed_out
+                || !matches!(err, rsipstack::Error::DialogError(..))
+                || group.is_failover_code(code);
+            if index < last && failover && !cancel_token.is_cancelled() {
+                info!(
+                    session_id = self.session_id,
+                    group = group.name,
+                    trunk = member.name,
+                    code,
+                    "trunk attempt failed, failing over"
+                );
+                index += 1;
+                continue;
+            }
+
+            if guard.finish() {
+                // The dialog task stayed quiet expecting another attempt, so
+                // report the termination of the call here.
+                sip::report_terminated(
+                    call_state_ref,
+                    &self.event_sender,
+                    track_id,
+                    &Some(TerminatedReason::UasOther(code.into())),
+                );
+                cancel_token.cancel();
+            }
+            return Err(err);
+        }
+    }
+
     /// Detect if SDP is WebRTC format
     pub fn is_webrtc_sdp(sdp: &str) -> bool {
         (sdp.contains("a=ice-ufrag:") || sdp.contains("a=ice-pwd:"))
@@ -3148,6 +4287,7 @@ impl ActiveCall {
                 .as_ref()
                 .and_then(|o| o.enable_ice_lite)
                 .or(self.app_state.config.enable_ice_lite);
+            rtc_config.jitter_buffer = option.jitter_buffer.clone();
 
             let webrtc_track = RtcTrack::new(
                 self.cancel_token.child_token(),
@@ -3155,7 +4295,8 @@ impl ActiveCall {
                 self.track_config.clone(),
                 rtc_config,
             )
-            .with_ssrc(ssrc);
+            .with_ssrc(ssrc)
+            .with_quality_reports(self.call_state.read().await.quality.clone());
 
             Box::new(webrtc_track) as Box<dyn Track>
         } else {
@@ -3196,6 +4337,7 @@ impl ActiveCall {
             cancel_token,
             terminated_reason: None,
             has_early_media: false,
+            failover: None,
         };
 
         let initial_request = pending_dialog.dialog.initial_request();
@@ -3331,10 +4473,28 @@ impl ActiveCallState {
             if option.ringback_detection.is_none() {
                 option.ringback_detection = existing.ringback_detection.clone();
             }
+            if option.redaction.is_none() {
+                option.redaction = existing.redaction.clone();
+            }
         }
         option
     }
 
+    /// Extras with the values of [`Self::sensitive_extras`] masked
+    pub fn masked_extras(&self) -> Option<HashMap<String, serde_json::Value>> {
+        let mut extras = self.extras.clone()?;
+        for key in &self.sensitive_extras {
+            if let Some(value) = extras.get_mut(key) {
+                let text = match &*value {
+                    serde_json::Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                *value = serde_json::Value::String(redaction::mask(&text));
+            }
+        }
+        Some(extras)
+    }
+
     pub fn set_hangup_reason(&mut self, reason: CallRecordHangupReason) {
         if self.hangup_reason.is_none() {
             self.hangup_reason = Some(reason);
@@ -3348,7 +4508,7 @@ impl ActiveCallState {
     ) -> crate::event::SessionEvent {
         let from = self.option.as_ref().and_then(|o| o.caller.as_ref());
         let to = self.option.as_ref().and_then(|o| o.callee.as_ref());
-        let extra = self.extras.clone();
+        let extra = self.masked_extras();
 
         crate::event::SessionEvent::Hangup {
             track_id,
@@ -3373,24 +4533,42 @@ impl ActiveCallState {
         call_type: ActiveCallType,
     ) -> CallRecord {
         let option = self.option.clone().unwrap_or_default();
-        let recorder = if option.recorder.is_some() {
-            let recorder_file = app_state.get_recorder_file(&session_id);
-            if std::path::Path::new(&recorder_file).exists() {
-                let file_size = std::fs::metadata(&recorder_file)
-                    .map(|m| m.len())
-                    .unwrap_or(0);
-                vec![crate::callrecord::CallRecordMedia {
-                    track_id: session_id.clone(),
-                    path: recorder_file,
-                    size: file_size,
-                    extra: None,
-                }]
-            } else {
-                vec![]
-            }
-        } else {
-            vec![]
-        };
+        let recorder_option = self.recorder.clone().or_else(|| {
+            option
+                .recorder
+                .as_ref()
+                .map(|_| RecorderOption::new(app_state.get_recorder_file(&session_id)))
+        });
+        let recorder = recorder_option
+            .map(|recorder_option| {
+                let format = recorder_option.format.unwrap_or_default().effective();
+                let channels = recorder_option.channels.unwrap_or_default();
+                recorder_option
+                    .output_files()
+                    .into_iter()
+                    .filter_map(|(channel, path)| {
+                        let size = std::fs::metadata(&path).ok()?.len();
+                        let mut extra = HashMap::from([
+                            ("format".to_string(), serde_json::json!(format)),
+                            ("channels".to_string(), serde_json::json!(channels)),
+                            (
+                                "samplerate".to_string(),
+                                serde_json::json!(recorder_option.samplerate),
+                            ),
+                        ]);
+                        if let Some(channel) = channel {
+                            extra.insert("channel".to_string(), serde_json::json!(channel));
+                        }
+                        Some(crate::callrecord::CallRecordMedia {
+                            track_id: session_id.clone(),
+                            path,
+                            size,
+                            extra: Some(extra),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
 
         let dump_event_file = app_state.get_dump_events_file(&session_id);
         let dump_event_file = if std::path::Path::new(&dump_event_file).exists() {
@@ -3425,9 +4603,13 @@ impl ActiveCallState {
             caller,
             callee,
             hangup_reason: self.hangup_reason.clone(),
-            hangup_messages: Vec::new(),
+            hangup_messages: self.hangup_messages.clone(),
             status_code: self.last_status_code,
-            extras: self.extras.clone(),
+            extras: self.masked_extras(),
+            quality: self.quality.lock().unwrap().clone(),
+            redactions: self.redactions.clone(),
+            transcript: self.transcript_files.clone(),
+            analytics: None,
             dump_event_file,
             recorder,
             refer_callrecord,
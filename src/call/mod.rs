@@ -1,4 +1,8 @@
-use crate::{CallOption, ReferOption, media::recorder::RecorderOption, synthesis::SynthesisOption};
+use crate::{
+    CallOption, ReferOption,
+    media::{conference::ConferenceOption, recorder::RecorderOption},
+    synthesis::SynthesisOption,
+};
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::{
@@ -8,6 +12,7 @@ use std::{
 
 pub mod active_call;
 pub mod sip;
+pub mod trunk;
 pub use active_call::ActiveCall;
 pub use active_call::ActiveCallRef;
 pub use active_call::ActiveCallType;
@@ -15,6 +20,21 @@ pub use active_call::ActiveCallType;
 pub type CommandSender = tokio::sync::broadcast::Sender<Command>;
 pub type CommandReceiver = tokio::sync::broadcast::Receiver<Command>;
 
+/// How a supervisor is attached to a call.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SupervisorMode {
+    /// Hear both sides, not heard by anyone.
+    #[default]
+    Listen,
+    /// Heard only by the agent side: the AI dialogue receives the supervisor's
+    /// transcript, and a human agent leg (transferred or bridged call) hears
+    /// the audio. The caller does not hear the supervisor.
+    Whisper,
+    /// Heard by everyone on the call.
+    Barge,
+}
+
 // WebSocket Commands
 #[skip_serializing_none]
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -107,6 +127,64 @@ pub enum Command {
         /// session_id of the other call to unbridge from
         target_session_id: String,
     },
+    /// Join a named conference room. The room is created on the first join and
+    /// mixes the audio of all participants server-side; each participant hears
+    /// every other participant but not itself.
+    ConferenceJoin {
+        room: String,
+        /// Join muted: hear the room without being heard.
+        muted: Option<bool>,
+        /// Gain applied to this participant's voice in the mix, default 1.0
+        gain: Option<f32>,
+        /// Room options, only used when this join creates the room
+        option: Option<ConferenceOption>,
+    },
+    /// Leave a conference room.
+    ConferenceLeave {
+        room: String,
+    },
+    /// Change mute state and/or gain of a participant, defaults to this session.
+    ConferenceUpdate {
+        room: String,
+        participant_id: Option<String>,
+        muted: Option<bool>,
+        gain: Option<f32>,
+    },
+    /// Synthesize text into a conference room.
+    /// If participant_id is set only that participant hears it.
+    ConferenceTts {
+        room: String,
+        text: String,
+        speaker: Option<String>,
+        play_id: Option<String>,
+        participant_id: Option<String>,
+        option: Option<SynthesisOption>,
+    },
+    /// Attach this session as supervisor of another call, or switch the mode
+    /// of an existing attachment. A call has at most one supervisor.
+    Supervise {
+        /// session_id of the call to supervise
+        target_session_id: String,
+        mode: Option<SupervisorMode>,
+    },
+    /// Detach this session from the call it supervises.
+    Unsupervise {
+        target_session_id: String,
+    },
+    /// Pause the dialogue handler (LLM) of this call, e.g. while a supervisor
+    /// takes over. Current playback is interrupted.
+    PauseLlm {},
+    /// Hand the call back to the dialogue handler.
+    ResumeLlm {
+        /// Handoff note added to the LLM history as a supervisor message
+        message: Option<String>,
+    },
+    /// Stop recording until resumed; the gap is kept as silence and listed
+    /// in the call record's redactions.
+    PauseRecording {
+        reason: Option<String>,
+    },
+    ResumeRecording {},
     Mute {
         track_id: Option<String>,
     },
@@ -134,6 +212,8 @@ pub enum Command {
 pub struct RoutingState {
     /// Round-robin counters for each destination group
     round_robin_counters: Arc<Mutex<HashMap<String, usize>>>,
+    /// Consecutive failed health checks per trunk host
+    trunk_failures: Arc<Mutex<HashMap<String, u32>>>,
 }
 
 impl Default for RoutingState {
@@ -146,6 +226,7 @@ impl RoutingState {
     pub fn new() -> Self {
         Self {
             round_robin_counters: Arc::new(Mutex::new(HashMap::new())),
+            trunk_failures: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -163,6 +244,33 @@ impl RoutingState {
         *counter += 1;
         return r;
     }
+
+    /// Record a trunk health check result. Returns the new availability when
+    /// it changed.
+    pub fn record_trunk_check(&self, host: &str, ok: bool, failure_threshold: u32) -> Option<bool> {
+        let threshold = failure_threshold.max(1);
+        let mut failures = self.trunk_failures.lock().unwrap();
+        let count = failures.entry(host.to_string()).or_insert(0);
+        let was_alive = *count < threshold;
+        if ok {
+            *count = 0;
+        } else {
+            *count = count.saturating_add(1);
+        }
+        let alive = *count < threshold;
+        (alive != was_alive).then_some(alive)
+    }
+
+    /// Whether a trunk host is in rotation. Hosts without health checks are
+    /// always available.
+    pub fn is_trunk_alive(&self, host: &str, failure_threshold: u32) -> bool {
+        self.trunk_failures
+            .lock()
+            .unwrap()
+            .get(host)
+            .map(|count| *count < failure_threshold.max(1))
+            .unwrap_or(true)
+    }
 }
 
 #[cfg(test)]
@@ -188,6 +296,43 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn supervise_command_deserializes_mode() {
+        let command: Command = serde_json::from_value(serde_json::json!({
+            "command": "supervise",
+            "targetSessionId": "s.1",
+            "mode": "whisper"
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            command,
+            Command::Supervise {
+                target_session_id,
+                mode: Some(super::SupervisorMode::Whisper),
+            } if target_session_id == "s.1"
+        ));
+    }
+
+    #[test]
+    fn recording_commands_deserialize() {
+        let command: Command = serde_json::from_value(serde_json::json!({
+            "command": "pauseRecording",
+            "reason": "card_number"
+        }))
+        .unwrap();
+        assert!(matches!(
+            command,
+            Command::PauseRecording { reason: Some(reason) } if reason == "card_number"
+        ));
+
+        let command: Command = serde_json::from_value(serde_json::json!({
+            "command": "resumeRecording"
+        }))
+        .unwrap();
+        assert!(matches!(command, Command::ResumeRecording {}));
+    }
+
     #[test]
     fn message_command_deserializes_legacy_text() {
         let command: Command = serde_json::from_value(serde_json::json!({
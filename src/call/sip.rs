@@ -1,4 +1,5 @@
 use crate::call::active_call::ActiveCallStateRef;
+use crate::call::trunk::FailoverGuard;
 use crate::callrecord::CallRecordHangupReason;
 use crate::event::EventSender;
 use crate::media::TrackId;
@@ -93,76 +94,99 @@ pub(super) struct InviteDialogStates {
     pub media_stream: Arc<MediaStream>,
     pub terminated_reason: Option<TerminatedReason>,
     pub has_early_media: bool,
+    /// Set for trunk group attempts, see [`FailoverGuard`]
+    pub failover: Option<FailoverGuard>,
 }
 
 impl InviteDialogStates {
     pub(super) fn on_terminated(&mut self) {
-        let mut call_state_ref = match self.call_state.try_write() {
-            Ok(cs) => cs,
-            Err(_) => {
-                return;
-            }
-        };
-        let reason = &self.terminated_reason;
-        call_state_ref.last_status_code = match reason {
-            Some(TerminatedReason::UacCancel) => 487,
-            Some(TerminatedReason::UacBye) => 200,
-            Some(TerminatedReason::UacBusy) => 486,
-            Some(TerminatedReason::UasBye) => 200,
-            Some(TerminatedReason::UasBusy) => 486,
-            Some(TerminatedReason::UasDecline) => 603,
-            Some(TerminatedReason::UacOther(code)) => code.code(),
-            Some(TerminatedReason::UasOther(code)) => code.code(),
-            _ => 500, // Default to internal server error
-        };
+        report_terminated(
+            &self.call_state,
+            &self.event_sender,
+            &self.track_id,
+            &self.terminated_reason,
+        );
+    }
+}
 
-        if call_state_ref.hangup_reason.is_none() {
-            call_state_ref.hangup_reason.replace(match reason {
-                Some(TerminatedReason::UacCancel) => CallRecordHangupReason::Canceled,
-                Some(TerminatedReason::UacBye) | Some(TerminatedReason::UacBusy) => {
-                    CallRecordHangupReason::ByCaller
-                }
-                Some(TerminatedReason::UasBye) | Some(TerminatedReason::UasBusy) => {
-                    CallRecordHangupReason::ByCallee
-                }
-                Some(TerminatedReason::UasDecline) => CallRecordHangupReason::ByCallee,
-                Some(TerminatedReason::UacOther(_)) => CallRecordHangupReason::ByCaller,
-                Some(TerminatedReason::UasOther(_)) => CallRecordHangupReason::ByCallee,
-                _ => CallRecordHangupReason::BySystem,
-            });
-        };
-        let initiator = match reason {
-            Some(TerminatedReason::UacCancel) => "caller".to_string(),
+/// Record the status and hangup reason of a terminated dialog, and send the
+/// track end and hangup events of the call.
+pub(super) fn report_terminated(
+    call_state: &ActiveCallStateRef,
+    event_sender: &EventSender,
+    track_id: &TrackId,
+    reason: &Option<TerminatedReason>,
+) {
+    let mut call_state_ref = match call_state.try_write() {
+        Ok(cs) => cs,
+        Err(_) => {
+            return;
+        }
+    };
+    call_state_ref.last_status_code = match reason {
+        Some(TerminatedReason::UacCancel) => 487,
+        Some(TerminatedReason::UacBye) => 200,
+        Some(TerminatedReason::UacBusy) => 486,
+        Some(TerminatedReason::UasBye) => 200,
+        Some(TerminatedReason::UasBusy) => 486,
+        Some(TerminatedReason::UasDecline) => 603,
+        Some(TerminatedReason::UacOther(code)) => code.code(),
+        Some(TerminatedReason::UasOther(code)) => code.code(),
+        _ => 500, // Default to internal server error
+    };
+
+    if call_state_ref.hangup_reason.is_none() {
+        call_state_ref.hangup_reason.replace(match reason {
+            Some(TerminatedReason::UacCancel) => CallRecordHangupReason::Canceled,
             Some(TerminatedReason::UacBye) | Some(TerminatedReason::UacBusy) => {
-                "caller".to_string()
+                CallRecordHangupReason::ByCaller
             }
-            Some(TerminatedReason::UasBye)
-            | Some(TerminatedReason::UasBusy)
-            | Some(TerminatedReason::UasDecline) => "callee".to_string(),
-            _ => "system".to_string(),
-        };
-        self.event_sender
-            .send(crate::event::SessionEvent::TrackEnd {
-                track_id: self.track_id.clone(),
-                timestamp: crate::media::get_timestamp(),
-                duration: call_state_ref
-                    .answer_time
-                    .map(|t| (Utc::now() - t).num_milliseconds())
-                    .unwrap_or_default() as u64,
-                ssrc: call_state_ref.ssrc,
-                play_id: None,
-            })
-            .ok();
-        let hangup_event =
-            call_state_ref.build_hangup_event(self.track_id.clone(), Some(initiator));
-        self.event_sender.send(hangup_event).ok();
-    }
+            Some(TerminatedReason::UasBye) | Some(TerminatedReason::UasBusy) => {
+                CallRecordHangupReason::ByCallee
+            }
+            Some(TerminatedReason::UasDecline) => CallRecordHangupReason::ByCallee,
+            Some(TerminatedReason::UacOther(_)) => CallRecordHangupReason::ByCaller,
+            Some(TerminatedReason::UasOther(_)) => CallRecordHangupReason::ByCallee,
+            _ => CallRecordHangupReason::BySystem,
+        });
+    };
+    let initiator = match reason {
+        Some(TerminatedReason::UacCancel) => "caller".to_string(),
+        Some(TerminatedReason::UacBye) | Some(TerminatedReason::UacBusy) => "caller".to_string(),
+        Some(TerminatedReason::UasBye)
+        | Some(TerminatedReason::UasBusy)
+        | Some(TerminatedReason::UasDecline) => "callee".to_string(),
+        _ => "system".to_string(),
+    };
+    event_sender
+        .send(crate::event::SessionEvent::TrackEnd {
+            track_id: track_id.clone(),
+            timestamp: crate::media::get_timestamp(),
+            duration: call_state_ref
+                .answer_time
+                .map(|t| (Utc::now() - t).num_milliseconds())
+                .unwrap_or_default() as u64,
+            ssrc: call_state_ref.ssrc,
+            play_id: None,
+        })
+        .ok();
+    let hangup_event = call_state_ref.build_hangup_event(track_id.clone(), Some(initiator));
+    event_sender.send(hangup_event).ok();
 }
 
 impl Drop for InviteDialogStates {
     fn drop(&mut self) {
+        if let Some(failover) = &self.failover
+            && failover.suppress()
+        {
+            self.cancel_token.cancel();
+            return;
+        }
         self.on_terminated();
         self.cancel_token.cancel();
+        if let Some(failover) = &self.failover {
+            failover.parent.cancel();
+        }
     }
 }
 
@@ -682,6 +706,7 @@ mod tests {
             media_stream: media_stream.clone(),
             terminated_reason: None,
             has_early_media: false,
+            failover: None,
         };
 
         // Simulate DialogState::Early with SDP body (183 Session Progress)
@@ -745,6 +770,7 @@ mod tests {
             media_stream: media_stream.clone(),
             terminated_reason: None,
             has_early_media: false,
+            failover: None,
         };
 
         // Step 1: simulate 183 with SDP → set has_early_media and cs.answer
@@ -0,0 +1,387 @@
+use crate::{
+    call::RoutingState,
+    config::{TrunkGroup, TrunkMember, TrunkStrategy},
+};
+use anyhow::Result;
+use rsipstack::{
+    dialog::{DialogId, invitation::InviteOption},
+    rsip::{self, HostWithPort, Method, Param, Uri, typed},
+    transaction::{endpoint::EndpointInnerRef, make_tag},
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicU8, Ordering},
+};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Order the members of `group` for a call to `callee`, leaving out members
+/// taken out of rotation by their health check.
+pub fn plan_members(group: &TrunkGroup, callee: &Uri, routing: &RoutingState) -> Vec<TrunkMember> {
+    let mut members: Vec<&TrunkMember> = match group.strategy {
+        TrunkStrategy::Ordered => group.members.iter().collect(),
+        TrunkStrategy::Weighted => weighted_order(&group.members),
+        TrunkStrategy::RoundRobin => {
+            let mut members: Vec<&TrunkMember> = group.members.iter().collect();
+            let key = format!("trunk-group:{}", group.name);
+            let start = routing.next_round_robin_index(&key, members.len());
+            members.rotate_left(start);
+            members
+        }
+        TrunkStrategy::LeastCost => least_cost_order(&group.members, &callee_digits(callee)),
+    };
+    if let Some(check) = &group.health_check {
+        members.retain(|m| routing.is_trunk_alive(&m.host, check.failure_threshold));
+    }
+    members.into_iter().cloned().collect()
+}
+
+/// Point the callee URI at the member's host, keeping user and parameters.
+pub fn apply_member(member: &TrunkMember, invite: &mut InviteOption) {
+    match HostWithPort::try_from(member.host.as_str()) {
+        Ok(host_with_port) => invite.callee.host_with_port = host_with_port,
+        Err(e) => warn!(
+            trunk = member.name,
+            host = member.host,
+            "invalid trunk host: {}",
+            e
+        ),
+    }
+}
+
+fn callee_digits(callee: &Uri) -> String {
+    callee
+        .auth
+        .as_ref()
+        .map(|auth| auth.user.trim_start_matches('+').to_string())
+        .unwrap_or_default()
+}
+
+fn weighted_order(members: &[TrunkMember]) -> Vec<&TrunkMember> {
+    let mut pool: Vec<&TrunkMember> = members.iter().collect();
+    let mut ordered = Vec::with_capacity(pool.len());
+    while !pool.is_empty() {
+        let total: u64 = pool.iter().map(|m| m.weight.unwrap_or(1) as u64).sum();
+        let index = if total == 0 {
+            0
+        } else {
+            let mut pick = rand::random::<u64>() % total;
+            pool.iter()
+                .position(|m| {
+                    let weight = m.weight.unwrap_or(1) as u64;
+                    if pick < weight {
+                        true
+                    } else {
+                        pick -= weight;
+                        false
+                    }
+                })
+                .unwrap_or(0)
+        };
+        ordered.push(pool.remove(index));
+    }
+    ordered
+}
+
+fn least_cost_order<'a>(members: &'a [TrunkMember], digits: &str) -> Vec<&'a TrunkMember> {
+    let mut priced: Vec<(&TrunkMember, f64)> = members
+        .iter()
+        .filter_map(|member| {
+            member
+                .rates
+                .iter()
+                .filter(|rate| digits.starts_with(rate.prefix.trim_start_matches('+')))
+                .max_by_key(|rate| rate.prefix.trim_start_matches('+').len())
+                .map(|rate| (member, rate.cost))
+        })
+        .collect();
+    priced.sort_by(|a, b| a.1.total_cmp(&b.1));
+    priced.into_iter().map(|(member, _)| member).collect()
+}
+
+const FAILOVER_ARMED: u8 = 0;
+const FAILOVER_SUPPRESSED: u8 = 1;
+const FAILOVER_DISARMED: u8 = 2;
+
+/// Shared between a trunk group attempt and its dialog task.
+///
+/// While armed, a failed attempt ends without hangup events so the next member
+/// can be tried. Whichever side runs last reports the termination: the dialog
+/// task once the attempt is disarmed, or the caller via [`FailoverGuard::finish`].
+#[derive(Clone)]
+pub(crate) struct FailoverGuard {
+    state: Arc<AtomicU8>,
+    /// Token of the call, cancelled when the final attempt ends
+    pub parent: CancellationToken,
+}
+
+impl FailoverGuard {
+    pub fn new(parent: CancellationToken, armed: bool) -> Self {
+        let state = if armed {
+            FAILOVER_ARMED
+        } else {
+            FAILOVER_DISARMED
+        };
+        Self {
+            state: Arc::new(AtomicU8::new(state)),
+            parent,
+        }
+    }
+
+    /// Called by the dialog task on termination, true if it must stay quiet.
+    pub fn suppress(&self) -> bool {
+        !self.parent.is_cancelled()
+            && self
+                .state
+                .compare_exchange(
+                    FAILOVER_ARMED,
+                    FAILOVER_SUPPRESSED,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+    }
+
+    /// Disarm the attempt. Returns true if its termination was already
+    /// suppressed and must now be reported by the caller.
+    pub fn finish(&self) -> bool {
+        self.state.swap(FAILOVER_DISARMED, Ordering::SeqCst) == FAILOVER_SUPPRESSED
+    }
+}
+
+/// Final status code of a failed attempt, 503 when the INVITE never got a
+/// response (transport errors).
+pub fn attempt_status(result: &Result<(DialogId, Option<Vec<u8>>), rsipstack::Error>) -> u16 {
+    match result {
+        Ok(_) => 200,
+        Err(rsipstack::Error::DialogError(_, _, code)) => code.code(),
+        Err(_) => 503,
+    }
+}
+
+/// Spawn OPTIONS pings for every trunk group member with a health check.
+pub fn start_health_checks(
+    endpoint: EndpointInnerRef,
+    groups: &[TrunkGroup],
+    routing: Arc<RoutingState>,
+    token: CancellationToken,
+) {
+    for group in groups {
+        let Some(check) = group.health_check.clone() else {
+            continue;
+        };
+        for member in &group.members {
+            let endpoint = endpoint.clone();
+            let routing = routing.clone();
+            let token = token.clone();
+            let check = check.clone();
+            let group_name = group.name.clone();
+            let member = member.clone();
+            crate::spawn(async move {
+                let mut interval =
+                    tokio::time::interval(Duration::from_secs(check.interval_secs.max(1)));
+                loop {
+                    tokio::select! {
+                        _ = token.cancelled() => break,
+                        _ = interval.tick() => {}
+                    }
+                    let timeout = Duration::from_secs(check.timeout_secs.max(1));
+                    let ok = match ping(&endpoint, &member.host, timeout).await {
+                        Ok(code) => code != 503,
+                        Err(e) => {
+                            info!(
+                                group = group_name,
+                                trunk = member.name,
+                                "trunk ping failed: {}",
+                                e
+                            );
+                            false
+                        }
+                    };
+                    match routing.record_trunk_check(&member.host, ok, check.failure_threshold) {
+                        Some(true) => {
+                            info!(
+                                group = group_name,
+                                trunk = member.name,
+                                "trunk back in rotation"
+                            )
+                        }
+                        Some(false) => {
+                            warn!(
+                                group = group_name,
+                                trunk = member.name,
+                                "trunk taken out of rotation"
+                            )
+                        }
+                        None => {}
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Send an OPTIONS request to `host` and return the final status code.
+async fn ping(endpoint: &EndpointInnerRef, host: &str, timeout: Duration) -> Result<u16> {
+    let uri = Uri::try_from(format!("sip:{}", host).as_str())?;
+    let via = endpoint.get_via(None, None)?;
+    let from = typed::From {
+        display_name: None,
+        uri: Uri {
+            auth: Some(rsip::Auth {
+                user: "active-call".to_string(),
+                password: None,
+            }),
+            ..Uri::from(via.uri.host_with_port.clone())
+        },
+        params: vec![Param::Tag(make_tag())],
+    };
+    let to = typed::To {
+        display_name: None,
+        uri: uri.clone(),
+        params: vec![],
+    };
+    let request = endpoint.make_request(Method::Options, uri, via, from, to, 1, None);
+    crate::useragent::messaging::send_request(endpoint, request, None, timeout).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TrunkHealthCheck, TrunkRate};
+
+    fn member(name: &str, rates: &[(&str, f64)]) -> TrunkMember {
+        TrunkMember {
+            name: name.to_string(),
+            host: format!("{}.example.com:5060", name),
+            weight: None,
+            rates: rates
+                .iter()
+                .map(|(prefix, cost)| TrunkRate {
+                    prefix: prefix.to_string(),
+                    cost: *cost,
+                })
+                .collect(),
+        }
+    }
+
+    fn names(members: &[TrunkMember]) -> Vec<&str> {
+        members.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    fn callee(uri: &str) -> Uri {
+        Uri::try_from(uri).unwrap()
+    }
+
+    #[test]
+    fn test_ordered_and_round_robin_plans() {
+        let routing = RoutingState::new();
+        let mut group = TrunkGroup {
+            name: "g".to_string(),
+            members: vec![member("a", &[]), member("b", &[]), member("c", &[])],
+            ..Default::default()
+        };
+        let to = callee("sip:100@example.com");
+        assert_eq!(names(&plan_members(&group, &to, &routing)), ["a", "b", "c"]);
+
+        group.strategy = TrunkStrategy::RoundRobin;
+        assert_eq!(names(&plan_members(&group, &to, &routing)), ["a", "b", "c"]);
+        assert_eq!(names(&plan_members(&group, &to, &routing)), ["b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_least_cost_uses_longest_prefix() {
+        let routing = RoutingState::new();
+        let group = TrunkGroup {
+            name: "g".to_string(),
+            strategy: TrunkStrategy::LeastCost,
+            members: vec![
+                member("a", &[("1", 0.01), ("1212", 0.05)]),
+                member("b", &[("1", 0.02)]),
+                member("c", &[("44", 0.001)]),
+            ],
+            ..Default::default()
+        };
+        let plan = plan_members(&group, &callee("sip:+12125550100@example.com"), &routing);
+        assert_eq!(names(&plan), ["b", "a"]);
+        let plan = plan_members(&group, &callee("sip:13105550100@example.com"), &routing);
+        assert_eq!(names(&plan), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_weighted_plan_contains_every_member() {
+        let routing = RoutingState::new();
+        let mut heavy = member("heavy", &[]);
+        heavy.weight = Some(1000);
+        let mut light = member("light", &[]);
+        light.weight = Some(0);
+        let group = TrunkGroup {
+            name: "g".to_string(),
+            strategy: TrunkStrategy::Weighted,
+            members: vec![light, heavy],
+            ..Default::default()
+        };
+        let plan = plan_members(&group, &callee("sip:100@example.com"), &routing);
+        assert_eq!(names(&plan), ["heavy", "light"]);
+    }
+
+    #[test]
+    fn test_unhealthy_members_are_skipped() {
+        let routing = RoutingState::new();
+        let group = TrunkGroup {
+            name: "g".to_string(),
+            health_check: Some(TrunkHealthCheck {
+                failure_threshold: 2,
+                ..Default::default()
+            }),
+            members: vec![member("a", &[]), member("b", &[])],
+            ..Default::default()
+        };
+        let to = callee("sip:100@example.com");
+        let host = &group.members[0].host;
+
+        assert_eq!(routing.record_trunk_check(host, false, 2), None);
+        assert_eq!(names(&plan_members(&group, &to, &routing)), ["a", "b"]);
+        assert_eq!(routing.record_trunk_check(host, false, 2), Some(false));
+        assert_eq!(names(&plan_members(&group, &to, &routing)), ["b"]);
+        assert_eq!(routing.record_trunk_check(host, true, 2), Some(true));
+        assert_eq!(names(&plan_members(&group, &to, &routing)), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_apply_member_and_failover_codes() {
+        let mut invite = InviteOption {
+            callee: callee("sip:+15550100@carriers;transport=tcp"),
+            ..Default::default()
+        };
+        apply_member(&member("a", &[]), &mut invite);
+        assert_eq!(
+            invite.callee.to_string(),
+            "sip:+15550100@a.example.com:5060;transport=TCP"
+        );
+
+        let mut group = TrunkGroup::default();
+        assert!(group.is_failover_code(408));
+        assert!(group.is_failover_code(503));
+        assert!(!group.is_failover_code(486));
+        group.failover_codes = Some(vec![503]);
+        assert!(!group.is_failover_code(408));
+    }
+
+    #[test]
+    fn test_failover_guard_reports_once() {
+        let parent = CancellationToken::new();
+        let guard = FailoverGuard::new(parent.clone(), true);
+        assert!(guard.suppress());
+        assert!(guard.finish());
+
+        let guard = FailoverGuard::new(parent.clone(), true);
+        assert!(!guard.finish());
+        assert!(!guard.suppress());
+
+        let guard = FailoverGuard::new(parent.clone(), true);
+        parent.cancel();
+        assert!(!guard.suppress());
+    }
+}
@@ -0,0 +1,767 @@
+//! Post-call analytics. Analyzers run against the call transcript once the
+//! call record reaches the [`super::CallRecordManager`] queue, so slow LLM
+//! requests never hold up hangup. Results land in the call record or in a
+//! `<name>.analytics.json` file next to the transcript.
+use super::{
+    CallRecord, CallRecordMedia,
+    transcript::{Transcript, TranscriptFormat, TranscriptSpeaker},
+};
+use crate::playbook::{
+    ChatMessage, LlmConfig, SummaryType,
+    handler::provider::{DefaultLlmProvider, LlmProvider},
+};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_with::skip_serializing_none;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+const ANALYST_PROMPT: &str = "You are a call center quality analyst. You read transcripts of phone calls between a user and an agent. Follow the instructions exactly and reply with the requested output only.";
+
+/// Post-call analytics, run after hangup against the call transcript.
+///
+/// ```toml
+/// [analytics]
+/// output = "cdr"        # or "sidecar"
+/// max_concurrent = 4
+/// max_retries = 2
+///
+/// [analytics.llm]
+/// provider = "openai"
+/// model = "gpt-4o-mini"
+/// apiKey = "sk-..."
+///
+/// [[analytics.analyzers]]
+/// type = "summary"
+/// summary = "short"
+///
+/// [[analytics.analyzers]]
+/// type = "disposition"
+/// labels = ["resolved", "callback", "escalated"]
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct AnalyticsConfig {
+    pub llm: LlmConfig,
+    #[serde(default)]
+    pub analyzers: Vec<AnalyzerConfig>,
+    #[serde(default)]
+    pub output: AnalyticsOutput,
+    /// Calls analyzed at the same time (default: 4)
+    pub max_concurrent: Option<usize>,
+    /// Retries of a failed analyzer, with exponential backoff (default: 2)
+    pub max_retries: Option<u32>,
+    /// Delay before the first retry (default: 1000)
+    pub retry_delay_ms: Option<u64>,
+    /// Timeout of a single LLM request (default: 60)
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalyticsOutput {
+    /// `analytics` of the call record
+    #[default]
+    Cdr,
+    /// `<name>.analytics.json` next to the transcript, listed and uploaded
+    /// with the transcript files
+    Sidecar,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnalyzerConfig {
+    Summary {
+        /// Defaults to `short`
+        summary: Option<SummaryType>,
+    },
+    /// Sentiment of each user utterance, from -1 to 1
+    Sentiment,
+    /// Outcome of the call, one of `labels`
+    Disposition { labels: Vec<String> },
+    /// Rubric scoring of the agent, with quotes backing each score
+    Qa { criteria: Vec<QaCriterion> },
+    /// Phrase spotting without the LLM. `required` phrases must be said by
+    /// the agent, e.g. a recording disclosure.
+    Keywords {
+        #[serde(default)]
+        phrases: Vec<String>,
+        #[serde(default)]
+        required: Vec<String>,
+    },
+}
+
+impl AnalyzerConfig {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnalyzerConfig::Summary { .. } => "summary",
+            AnalyzerConfig::Sentiment => "sentiment",
+            AnalyzerConfig::Disposition { .. } => "disposition",
+            AnalyzerConfig::Qa { .. } => "qa",
+            AnalyzerConfig::Keywords { .. } => "keywords",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct QaCriterion {
+    pub name: String,
+    pub description: String,
+    /// Points of the criterion (default: 1)
+    pub weight: Option<u32>,
+}
+
+impl QaCriterion {
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallAnalytics {
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sentiment: Vec<SentimentPoint>,
+    pub disposition: Option<Disposition>,
+    pub qa: Option<QaScore>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<KeywordHit>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compliance: Vec<ComplianceCheck>,
+    /// Analyzers that failed after all retries, by type
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub errors: HashMap<String, String>,
+}
+
+/// Sentiment of a user utterance, in milliseconds from the start of the
+/// recording
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SentimentPoint {
+    pub start: u64,
+    pub end: u64,
+    pub score: f32,
+    pub label: String,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Disposition {
+    pub label: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QaScore {
+    pub score: f32,
+    pub max_score: u32,
+    pub criteria: Vec<QaCriterionScore>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QaCriterionScore {
+    pub name: String,
+    pub score: f32,
+    pub max_score: u32,
+    /// Transcript quotes backing the score. Quotes not found in the
+    /// transcript are dropped.
+    #[serde(default)]
+    pub evidence: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeywordHit {
+    pub phrase: String,
+    pub speaker: TranscriptSpeaker,
+    pub start: u64,
+    pub end: u64,
+    pub text: String,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ComplianceCheck {
+    pub phrase: String,
+    pub found: bool,
+    /// Start of the first agent utterance saying the phrase
+    pub start: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RawSentiment {
+    index: usize,
+    score: f32,
+    #[serde(default)]
+    label: String,
+}
+
+#[derive(Deserialize)]
+struct RawDisposition {
+    label: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawQa {
+    criteria: Vec<RawQaCriterion>,
+}
+
+#[derive(Deserialize)]
+struct RawQaCriterion {
+    name: String,
+    score: f32,
+    #[serde(default)]
+    evidence: Vec<String>,
+}
+
+pub struct CallAnalyzer {
+    config: AnalyticsConfig,
+    provider: Arc<dyn LlmProvider>,
+    permits: Semaphore,
+}
+
+impl CallAnalyzer {
+    pub fn new(config: AnalyticsConfig) -> Self {
+        Self::with_provider(config, Arc::new(DefaultLlmProvider::new()))
+    }
+
+    pub fn with_provider(config: AnalyticsConfig, provider: Arc<dyn LlmProvider>) -> Self {
+        let permits = Semaphore::new(config.max_concurrent.unwrap_or(4).max(1));
+        Self {
+            config,
+            provider,
+            permits,
+        }
+    }
+
+    /// The JSON transcript of the record, if the call produced one
+    pub fn transcript_file(record: &CallRecord) -> Option<&CallRecordMedia> {
+        record.transcript.iter().find(|media| {
+            media
+                .extra
+                .as_ref()
+                .and_then(|extra| extra.get("format"))
+                .is_some_and(|format| format == &serde_json::json!(TranscriptFormat::Json))
+        })
+    }
+
+    /// Runs every analyzer against the transcript of `record` and stores
+    /// the results as configured. Failed analyzers are listed in `errors`.
+    pub async fn analyze(&self, record: &mut CallRecord) {
+        if self.config.analyzers.is_empty() {
+            return;
+        }
+        let Some(media) = Self::transcript_file(record).cloned() else {
+            return;
+        };
+        let transcript = match tokio::fs::read_to_string(&media.path)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(serde_json::from_str::<Transcript>(&content)?))
+        {
+            Ok(transcript) => transcript,
+            Err(e) => {
+                warn!(
+                    call_id = record.call_id,
+                    path = media.path,
+                    "failed to read transcript: {}",
+                    e
+                );
+                return;
+            }
+        };
+        let Ok(_permit) = self.permits.acquire().await else {
+            return;
+        };
+        let started = std::time::Instant::now();
+        let analytics = self.run(&transcript).await;
+        info!(
+            call_id = record.call_id,
+            elapsed = ?started.elapsed(),
+            errors = analytics.errors.len(),
+            "call analytics done"
+        );
+
+        match self.config.output {
+            AnalyticsOutput::Cdr => record.analytics = Some(analytics),
+            AnalyticsOutput::Sidecar => {
+                let path =
+                    media.path.trim_end_matches(".transcript.json").to_string() + ".analytics.json";
+                let content = serde_json::to_string_pretty(&analytics).unwrap_or_default();
+                if let Err(e) = tokio::fs::write(&path, &content).await {
+                    warn!(
+                        call_id = record.call_id,
+                        path, "failed to write analytics: {}", e
+                    );
+                    return;
+                }
+                record.transcript.push(CallRecordMedia {
+                    track_id: media.track_id,
+                    path,
+                    size: content.len() as u64,
+                    extra: Some(HashMap::from([(
+                        "format".to_string(),
+                        serde_json::json!("analytics"),
+                    )])),
+                });
+            }
+        }
+    }
+
+    pub async fn run(&self, transcript: &Transcript) -> CallAnalytics {
+        let mut analytics = CallAnalytics::default();
+        if transcript.segments.is_empty() {
+            return analytics;
+        }
+        let lines = transcript_lines(transcript);
+        for analyzer in &self.config.analyzers {
+            let result = match analyzer {
+                AnalyzerConfig::Summary { summary } => {
+                    let prompt = summary.as_ref().unwrap_or(&SummaryType::Short).prompt();
+                    self.complete(&format!("Please {}", prompt), &lines, |content| {
+                        Ok(content.trim().to_string())
+                    })
+                    .await
+                    .map(|summary| analytics.summary = Some(summary))
+                }
+                AnalyzerConfig::Sentiment => self
+                    .sentiment(transcript, &lines)
+                    .await
+                    .map(|points| analytics.sentiment = points),
+                AnalyzerConfig::Disposition { labels } => self
+                    .disposition(labels, &lines)
+                    .await
+                    .map(|disposition| analytics.disposition = Some(disposition)),
+                AnalyzerConfig::Qa { criteria } => self
+                    .qa(criteria, transcript, &lines)
+                    .await
+                    .map(|qa| analytics.qa = Some(qa)),
+                AnalyzerConfig::Keywords { phrases, required } => {
+                    analytics.keywords = spot_keywords(transcript, phrases);
+                    analytics.compliance = check_compliance(transcript, required);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                warn!(analyzer = analyzer.name(), "call analyzer failed: {}", e);
+                analytics
+                    .errors
+                    .insert(analyzer.name().to_string(), e.to_string());
+            }
+        }
+        analytics
+    }
+
+    async fn sentiment(&self, transcript: &Transcript, lines: &str) -> Result<Vec<SentimentPoint>> {
+        let instruction = "Rate the sentiment of every user line from -1 (very negative) to 1 (very positive). Reply with a JSON array only, one entry per user line: [{\"index\": 0, \"score\": 0.5, \"label\": \"positive\"}]";
+        self.complete(instruction, lines, |content| {
+            let raw: Vec<RawSentiment> = parse_json(content)?;
+            let mut points = raw
+                .into_iter()
+                .filter_map(|item| {
+                    let segment = transcript.segments.get(item.index)?;
+                    (segment.speaker == TranscriptSpeaker::User).then(|| SentimentPoint {
+                        start: segment.start,
+                        end: segment.end,
+                        score: item.score.clamp(-1.0, 1.0),
+                        label: item.label,
+                    })
+                })
+                .collect::<Vec<_>>();
+            points.sort_by_key(|point| point.start);
+            points.dedup_by_key(|point| point.start);
+            Ok(points)
+        })
+        .await
+    }
+
+    async fn disposition(&self, labels: &[String], lines: &str) -> Result<Disposition> {
+        if labels.is_empty() {
+            return Err(anyhow!("no disposition labels configured"));
+        }
+        let instruction = format!(
+            "Classify the outcome of the call as exactly one of: {}. Reply with JSON only: {{\"label\": \"...\", \"reason\": \"one sentence\"}}",
+            labels.join(", ")
+        );
+        self.complete(&instruction, lines, |content| {
+            let raw: RawDisposition = parse_json(content)?;
+            let label = labels
+                .iter()
+                .find(|label| label.eq_ignore_ascii_case(raw.label.trim()))
+                .ok_or_else(|| anyhow!("unknown disposition label: {}", raw.label))?;
+            Ok(Disposition {
+                label: label.clone(),
+                reason: raw.reason,
+            })
+        })
+        .await
+    }
+
+    async fn qa(
+        &self,
+        criteria: &[QaCriterion],
+        transcript: &Transcript,
+        lines: &str,
+    ) -> Result<QaScore> {
+        if criteria.is_empty() {
+            return Err(anyhow!("no QA criteria configured"));
+        }
+        let rubric = criteria
+            .iter()
+            .map(|c| {
+                format!(
+                    "- {} (0 to {} points): {}",
+                    c.name,
+                    c.weight(),
+                    c.description
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let instruction = format!(
+            "Score the agent on each criterion below, quoting the transcript lines that support each score word for word.\n{}\nReply with JSON only: {{\"criteria\": [{{\"name\": \"...\", \"score\": 0, \"evidence\": [\"...\"]}}]}}",
+            rubric
+        );
+        self.complete(&instruction, lines, |content| {
+            let raw: RawQa = parse_json(content)?;
+            let mut scores = Vec::with_capacity(criteria.len());
+            for criterion in criteria {
+                let item = raw
+                    .criteria
+                    .iter()
+                    .find(|item| item.name.trim().eq_ignore_ascii_case(&criterion.name))
+                    .ok_or_else(|| anyhow!("missing score for criterion {}", criterion.name))?;
+                scores.push(QaCriterionScore {
+                    name: criterion.name.clone(),
+                    score: item.score.clamp(0.0, criterion.weight() as f32),
+                    max_score: criterion.weight(),
+                    evidence: item
+                        .evidence
+                        .iter()
+                        .filter(|quote| is_quoted(transcript, quote))
+                        .cloned()
+                        .collect(),
+                });
+            }
+            Ok(QaScore {
+                score: scores.iter().map(|s| s.score).sum(),
+                max_score: scores.iter().map(|s| s.max_score).sum(),
+                criteria: scores,
+            })
+        })
+        .await
+    }
+
+    /// Asks the LLM about the transcript, retrying failed requests and
+    /// replies `parse` rejects
+    async fn complete<T>(
+        &self,
+        instruction: &str,
+        lines: &str,
+        parse: impl Fn(&str) -> Result<T>,
+    ) -> Result<T> {
+        let history = vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: ANALYST_PROMPT.to_string(),
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content: format!("{}\n\nTranscript:\n{}", instruction, lines),
+            },
+        ];
+        let timeout = Duration::from_secs(self.config.timeout_secs.unwrap_or(60));
+        let max_retries = self.config.max_retries.unwrap_or(2);
+        let mut delay = Duration::from_millis(self.config.retry_delay_ms.unwrap_or(1000));
+        let mut attempt = 0;
+        loop {
+            let result =
+                match tokio::time::timeout(timeout, self.provider.call(&self.config.llm, &history))
+                    .await
+                {
+                    Ok(result) => result.and_then(|content| parse(&content)),
+                    Err(_) => Err(anyhow!("LLM request timed out")),
+                };
+            match result {
+                Err(e) if attempt < max_retries => {
+                    attempt += 1;
+                    warn!(attempt, "call analytics request failed, retrying: {}", e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Numbered transcript lines the LLM refers back to by index
+fn transcript_lines(transcript: &Transcript) -> String {
+    transcript
+        .segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            format!(
+                "[{}] {:02}:{:02} {}: {}",
+                i,
+                segment.start / 60_000,
+                segment.start / 1000 % 60,
+                segment.speaker.as_str(),
+                segment.text
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// JSON out of an LLM reply, ignoring code fences and surrounding prose
+fn parse_json<T: DeserializeOwned>(content: &str) -> Result<T> {
+    let start = content
+        .find(['{', '['])
+        .ok_or_else(|| anyhow!("no JSON in reply"))?;
+    let end = content
+        .rfind(['}', ']'])
+        .filter(|end| *end > start)
+        .ok_or_else(|| anyhow!("no JSON in reply"))?;
+    Ok(serde_json::from_str(&content[start..=end])?)
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn is_quoted(transcript: &Transcript, quote: &str) -> bool {
+    let quote = normalize(quote.trim_matches(|c: char| c == '"' || c.is_whitespace()));
+    !quote.is_empty()
+        && transcript
+            .segments
+            .iter()
+            .any(|segment| normalize(&segment.text).contains(&quote))
+}
+
+fn spot_keywords(transcript: &Transcript, phrases: &[String]) -> Vec<KeywordHit> {
+    let mut hits = Vec::new();
+    for segment in &transcript.segments {
+        let text = normalize(&segment.text);
+        for phrase in phrases {
+            if text.contains(&normalize(phrase)) {
+                hits.push(KeywordHit {
+                    phrase: phrase.clone(),
+                    speaker: segment.speaker,
+                    start: segment.start,
+                    end: segment.end,
+                    text: segment.text.clone(),
+                });
+            }
+        }
+    }
+    hits
+}
+
+fn check_compliance(transcript: &Transcript, required: &[String]) -> Vec<ComplianceCheck> {
+    required
+        .iter()
+        .map(|phrase| {
+            let needle = normalize(phrase);
+            let start = transcript
+                .segments
+                .iter()
+                .find(|segment| {
+                    segment.speaker == TranscriptSpeaker::Agent
+                        && normalize(&segment.text).contains(&needle)
+                })
+                .map(|segment| segment.start);
+            ComplianceCheck {
+                phrase: phrase.clone(),
+                found: start.is_some(),
+                start,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callrecord::transcript::TranscriptSegment;
+    use crate::playbook::handler::provider::LlmStreamEvent;
+    use futures::Stream;
+    use std::{pin::Pin, sync::Mutex};
+
+    /// Replies in order, one per request
+    struct ScriptedProvider(Mutex<Vec<Result<String>>>);
+
+    #[async_trait::async_trait]
+    impl LlmProvider for ScriptedProvider {
+        async fn call(&self, _config: &LlmConfig, _history: &[ChatMessage]) -> Result<String> {
+            self.0.lock().unwrap().remove(0)
+        }
+
+        async fn call_stream(
+            &self,
+            _config: &LlmConfig,
+            _history: &[ChatMessage],
+        ) -> Result<Pin<Box<dyn Stream<Item = Result<LlmStreamEvent>> + Send>>> {
+            Err(anyhow!("not supported"))
+        }
+    }
+
+    fn segment(speaker: TranscriptSpeaker, start: u64, text: &str) -> TranscriptSegment {
+        TranscriptSegment {
+            speaker,
+            start,
+            end: start + 1000,
+            text: text.to_string(),
+            interrupted: false,
+        }
+    }
+
+    fn transcript() -> Transcript {
+        Transcript {
+            call_id: "c".to_string(),
+            segments: vec![
+                segment(
+                    TranscriptSpeaker::Agent,
+                    0,
+                    "Hi, this call may be recorded.",
+                ),
+                segment(
+                    TranscriptSpeaker::User,
+                    2000,
+                    "I want a refund, this is terrible",
+                ),
+                segment(
+                    TranscriptSpeaker::Agent,
+                    4000,
+                    "Sorry to hear that, I have issued the refund.",
+                ),
+                segment(TranscriptSpeaker::User, 6000, "Great, thank you"),
+            ],
+        }
+    }
+
+    fn analyzer(analyzers: Vec<AnalyzerConfig>, replies: Vec<Result<String>>) -> CallAnalyzer {
+        CallAnalyzer::with_provider(
+            AnalyticsConfig {
+                analyzers,
+                max_retries: Some(1),
+                retry_delay_ms: Some(0),
+                ..Default::default()
+            },
+            Arc::new(ScriptedProvider(Mutex::new(replies))),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_analyzers_parse_and_validate() {
+        let analyzer = analyzer(
+            vec![
+                AnalyzerConfig::Sentiment,
+                AnalyzerConfig::Disposition {
+                    labels: vec!["resolved".to_string(), "escalated".to_string()],
+                },
+                AnalyzerConfig::Qa {
+                    criteria: vec![QaCriterion {
+                        name: "empathy".to_string(),
+                        description: "Acknowledges the problem".to_string(),
+                        weight: Some(5),
+                    }],
+                },
+                AnalyzerConfig::Keywords {
+                    phrases: vec!["Refund".to_string()],
+                    required: vec!["may be recorded".to_string(), "survey".to_string()],
+                },
+            ],
+            vec![
+                Ok("```json\n[{\"index\": 1, \"score\": -0.8, \"label\": \"negative\"}, {\"index\": 3, \"score\": 1.4, \"label\": \"positive\"}, {\"index\": 0, \"score\": 0}]\n```".to_string()),
+                Ok("{\"label\": \"Resolved\", \"reason\": \"Refund issued\"}".to_string()),
+                Ok("{\"criteria\": [{\"name\": \"Empathy\", \"score\": 4, \"evidence\": [\"Sorry to hear that\", \"made up quote\"]}]}".to_string()),
+            ],
+        );
+        let analytics = analyzer.run(&transcript()).await;
+        assert!(analytics.errors.is_empty(), "{:?}", analytics.errors);
+
+        assert_eq!(analytics.sentiment.len(), 2);
+        assert_eq!(analytics.sentiment[0].start, 2000);
+        assert_eq!(analytics.sentiment[1].score, 1.0);
+
+        let disposition = analytics.disposition.unwrap();
+        assert_eq!(disposition.label, "resolved");
+
+        let qa = analytics.qa.unwrap();
+        assert_eq!((qa.score, qa.max_score), (4.0, 5));
+        assert_eq!(qa.criteria[0].evidence, vec!["Sorry to hear that"]);
+
+        assert_eq!(analytics.keywords.len(), 2);
+        assert_eq!(analytics.keywords[0].speaker, TranscriptSpeaker::User);
+        assert_eq!(analytics.compliance[0].start, Some(0));
+        assert!(!analytics.compliance[1].found);
+    }
+
+    #[tokio::test]
+    async fn test_retry_then_give_up() {
+        let labels = vec!["resolved".to_string()];
+        let analyzer = analyzer(
+            vec![
+                AnalyzerConfig::Disposition {
+                    labels: labels.clone(),
+                },
+                AnalyzerConfig::Summary { summary: None },
+            ],
+            vec![
+                Ok("{\"label\": \"unknown\"}".to_string()),
+                Ok("{\"label\": \"resolved\"}".to_string()),
+                Err(anyhow!("503")),
+                Err(anyhow!("503")),
+            ],
+        );
+        let analytics = analyzer.run(&transcript()).await;
+        assert_eq!(analytics.disposition.unwrap().label, "resolved");
+        assert!(analytics.summary.is_none());
+        assert_eq!(analytics.errors["summary"], "503");
+    }
+
+    #[tokio::test]
+    async fn test_analyze_record_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("c.transcript.json");
+        std::fs::write(&path, transcript().render(TranscriptFormat::Json)).unwrap();
+        let mut record = CallRecord {
+            call_id: "c".to_string(),
+            transcript: vec![CallRecordMedia {
+                track_id: "c".to_string(),
+                path: path.to_string_lossy().to_string(),
+                size: 0,
+                extra: Some(HashMap::from([(
+                    "format".to_string(),
+                    serde_json::json!(TranscriptFormat::Json),
+                )])),
+            }],
+            ..Default::default()
+        };
+        let mut analyzer = analyzer(
+            vec![AnalyzerConfig::Summary { summary: None }],
+            vec![Ok(" Refund issued. ".to_string())],
+        );
+        analyzer.config.output = AnalyticsOutput::Sidecar;
+        analyzer.analyze(&mut record).await;
+
+        assert!(record.analytics.is_none());
+        let sidecar = &record.transcript[1];
+        assert!(sidecar.path.ends_with("c.analytics.json"));
+        let analytics: CallAnalytics =
+            serde_json::from_str(&std::fs::read_to_string(&sidecar.path).unwrap()).unwrap();
+        assert_eq!(analytics.summary.as_deref(), Some("Refund issued."));
+    }
+}
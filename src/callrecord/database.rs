@@ -0,0 +1,152 @@
+//! SQL sink for call records, on SQLite or PostgreSQL. Each call is one row
+//! of `call_records`, with its media files in `call_record_media` and its
+//! hangup messages in `call_record_hangup_messages`. Saving a call again
+//! replaces its rows, so retried deliveries do not duplicate them.
+use super::CallRecord;
+use anyhow::Result;
+use sqlx::{
+    AnyPool,
+    any::{AnyPoolOptions, install_default_drivers},
+};
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Once},
+};
+use tokio::sync::Mutex;
+
+const SCHEMA: [&str; 5] = [
+    "CREATE TABLE IF NOT EXISTS call_records (
+        call_id TEXT PRIMARY KEY,
+        call_type TEXT NOT NULL,
+        caller TEXT NOT NULL,
+        callee TEXT NOT NULL,
+        start_time TEXT NOT NULL,
+        ring_time TEXT,
+        answer_time TEXT,
+        end_time TEXT NOT NULL,
+        status_code BIGINT NOT NULL,
+        hangup_reason TEXT,
+        record TEXT NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS call_record_media (
+        call_id TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        track_id TEXT NOT NULL,
+        path TEXT NOT NULL,
+        size BIGINT NOT NULL,
+        extra TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS call_record_media_call_id ON call_record_media (call_id)",
+    "CREATE TABLE IF NOT EXISTS call_record_hangup_messages (
+        call_id TEXT NOT NULL,
+        seq BIGINT NOT NULL,
+        code BIGINT NOT NULL,
+        reason TEXT,
+        target TEXT,
+        trunk TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS call_record_hangup_messages_call_id ON call_record_hangup_messages (call_id)",
+];
+
+/// Pools by url, with the schema in place
+static POOLS: LazyLock<Mutex<HashMap<String, AnyPool>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+async fn pool(url: &str) -> Result<AnyPool> {
+    static DRIVERS: Once = Once::new();
+    DRIVERS.call_once(install_default_drivers);
+
+    let mut pools = POOLS.lock().await;
+    if let Some(pool) = pools.get(url) {
+        return Ok(pool.clone());
+    }
+    let pool = AnyPoolOptions::new()
+        .max_connections(4)
+        .connect(url)
+        .await?;
+    for statement in SCHEMA {
+        sqlx::query(statement).execute(&pool).await?;
+    }
+    pools.insert(url.to_string(), pool.clone());
+    Ok(pool)
+}
+
+/// Strings as they are, other values as JSON
+pub(super) fn as_text<T: serde::Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        Ok(other) => other.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+pub async fn save(url: &str, record: &CallRecord, content: &str) -> Result<()> {
+    let pool = pool(url).await?;
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO call_records (call_id, call_type, caller, callee, start_time, ring_time,
+            answer_time, end_time, status_code, hangup_reason, record)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        ON CONFLICT (call_id) DO UPDATE SET call_type = excluded.call_type,
+            caller = excluded.caller, callee = excluded.callee,
+            start_time = excluded.start_time, ring_time = excluded.ring_time,
+            answer_time = excluded.answer_time, end_time = excluded.end_time,
+            status_code = excluded.status_code, hangup_reason = excluded.hangup_reason,
+            record = excluded.record",
+    )
+    .bind(record.call_id.clone())
+    .bind(as_text(&record.call_type))
+    .bind(record.caller.clone())
+    .bind(record.callee.clone())
+    .bind(record.start_time.to_rfc3339())
+    .bind(record.ring_time.map(|t| t.to_rfc3339()))
+    .bind(record.answer_time.map(|t| t.to_rfc3339()))
+    .bind(record.end_time.to_rfc3339())
+    .bind(record.status_code as i64)
+    .bind(record.hangup_reason.as_ref().map(|r| r.to_string()))
+    .bind(content.to_string())
+    .execute(&mut *tx)
+    .await?;
+
+    for table in ["call_record_media", "call_record_hangup_messages"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE call_id = $1", table))
+            .bind(record.call_id.clone())
+            .execute(&mut *tx)
+            .await?;
+    }
+    let media = record
+        .recorder
+        .iter()
+        .map(|media| ("recorder", media))
+        .chain(record.transcript.iter().map(|media| ("transcript", media)));
+    for (kind, media) in media {
+        sqlx::query(
+            "INSERT INTO call_record_media (call_id, kind, track_id, path, size, extra)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(record.call_id.clone())
+        .bind(kind)
+        .bind(media.track_id.clone())
+        .bind(media.path.clone())
+        .bind(media.size as i64)
+        .bind(media.extra.as_ref().map(as_text))
+        .execute(&mut *tx)
+        .await?;
+    }
+    for (seq, message) in record.hangup_messages.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO call_record_hangup_messages (call_id, seq, code, reason, target, trunk)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(record.call_id.clone())
+        .bind(seq as i64)
+        .bind(message.code as i64)
+        .bind(message.reason.clone())
+        .bind(message.target.clone())
+        .bind(message.trunk.clone())
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,666 @@
+//! Queryable index of past calls, on SQLite or PostgreSQL. Records are
+//! indexed as they leave the call record queue, whichever sink delivers
+//! them. Calls are listed newest first with an opaque cursor, and filtered
+//! on their times, parties, playbook, hangup reason, duration and extras.
+use super::{CallRecord, database::as_text};
+use crate::playbook::BUILTIN_PLAYBOOK;
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    AnyPool, Row,
+    any::{AnyPoolOptions, AnyRow, install_default_drivers},
+};
+use std::path::{Path, PathBuf};
+
+const SCHEMA: [&str; 6] = [
+    "CREATE TABLE IF NOT EXISTS call_history (
+        call_id TEXT PRIMARY KEY,
+        call_type TEXT NOT NULL,
+        caller TEXT NOT NULL,
+        callee TEXT NOT NULL,
+        start_time BIGINT NOT NULL,
+        answer_time BIGINT,
+        end_time BIGINT NOT NULL,
+        duration BIGINT NOT NULL,
+        status_code BIGINT NOT NULL,
+        hangup_reason TEXT,
+        playbook TEXT,
+        record TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS call_history_start_time ON call_history (start_time, call_id)",
+    "CREATE TABLE IF NOT EXISTS call_history_extras (
+        call_id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS call_history_extras_call_id ON call_history_extras (call_id)",
+    "CREATE INDEX IF NOT EXISTS call_history_extras_key ON call_history_extras (key, value)",
+    // Directories of local records already imported, by time of import
+    "CREATE TABLE IF NOT EXISTS call_history_imports (
+        root TEXT PRIMARY KEY,
+        imported_at BIGINT NOT NULL
+    )",
+];
+
+const SUMMARY_COLUMNS: &str = "call_id, call_type, caller, callee, start_time, answer_time, \
+    end_time, duration, status_code, hangup_reason, playbook";
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 500;
+
+/// Filters of `/api/calls`, all optional
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHistoryQuery {
+    /// Calls started at or after
+    pub from: Option<DateTime<Utc>>,
+    /// Calls started before
+    pub to: Option<DateTime<Utc>>,
+    /// Part of the caller
+    pub caller: Option<String>,
+    /// Part of the callee
+    pub callee: Option<String>,
+    pub playbook: Option<String>,
+    pub hangup_reason: Option<String>,
+    /// In seconds
+    pub min_duration: Option<u64>,
+    pub max_duration: Option<u64>,
+    /// `key=value` on the call's extras, or `key` for any value
+    pub extra: Option<String>,
+    pub limit: Option<usize>,
+    /// `nextCursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+impl CallHistoryQuery {
+    /// Start time and call id of the last call of the previous page
+    pub fn decode_cursor(&self) -> Result<Option<(i64, String)>> {
+        let Some(cursor) = self.cursor.as_deref().filter(|c| !c.is_empty()) else {
+            return Ok(None);
+        };
+        let decoded = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(|| anyhow!("invalid cursor"))?;
+        let (start_time, call_id) = decoded
+            .split_once(':')
+            .ok_or_else(|| anyhow!("invalid cursor"))?;
+        let start_time = start_time.parse().map_err(|_| anyhow!("invalid cursor"))?;
+        Ok(Some((start_time, call_id.to_string())))
+    }
+}
+
+fn encode_cursor(call: &CallSummary) -> String {
+    URL_SAFE_NO_PAD.encode(format!(
+        "{}:{}",
+        call.start_time.timestamp_millis(),
+        call.call_id
+    ))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CallSummary {
+    pub call_id: String,
+    pub call_type: String,
+    pub caller: String,
+    pub callee: String,
+    pub start_time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_time: Option<DateTime<Utc>>,
+    pub end_time: DateTime<Utc>,
+    /// In seconds
+    pub duration: u64,
+    pub status_code: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hangup_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playbook: Option<String>,
+}
+
+impl CallSummary {
+    fn from_row(row: &AnyRow) -> Result<Self> {
+        let time = |millis: i64| {
+            DateTime::from_timestamp_millis(millis).ok_or_else(|| anyhow!("invalid time"))
+        };
+        Ok(Self {
+            call_id: row.try_get("call_id")?,
+            call_type: row.try_get("call_type")?,
+            caller: row.try_get("caller")?,
+            callee: row.try_get("callee")?,
+            start_time: time(row.try_get("start_time")?)?,
+            answer_time: row
+                .try_get::<Option<i64>, _>("answer_time")?
+                .map(time)
+                .transpose()?,
+            end_time: time(row.try_get("end_time")?)?,
+            duration: row.try_get::<i64, _>("duration")?.max(0) as u64,
+            status_code: row.try_get::<i64, _>("status_code")? as u16,
+            hangup_reason: row.try_get("hangup_reason")?,
+            playbook: row.try_get("playbook")?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHistoryPage {
+    pub calls: Vec<CallSummary>,
+    /// Cursor of the next page, none on the last one
+    pub next_cursor: Option<String>,
+}
+
+enum Param {
+    Int(i64),
+    Text(String),
+}
+
+/// `WHERE` clause of a query with its parameters
+#[derive(Default)]
+struct Filter {
+    conditions: Vec<String>,
+    params: Vec<Param>,
+}
+
+impl Filter {
+    /// Placeholder of a new parameter
+    fn param(&mut self, param: Param) -> String {
+        self.params.push(param);
+        format!("${}", self.params.len())
+    }
+
+    fn new(query: &CallHistoryQuery) -> Result<Self> {
+        let mut filter = Self::default();
+        if let Some(from) = query.from {
+            let p = filter.param(Param::Int(from.timestamp_millis()));
+            filter.conditions.push(format!("start_time >= {}", p));
+        }
+        if let Some(to) = query.to {
+            let p = filter.param(Param::Int(to.timestamp_millis()));
+            filter.conditions.push(format!("start_time < {}", p));
+        }
+        for (column, value) in [("caller", &query.caller), ("callee", &query.callee)] {
+            if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+                let p = filter.param(Param::Text(format!("%{}%", escape_like(value))));
+                filter
+                    .conditions
+                    .push(format!("{} LIKE {} ESCAPE '\\'", column, p));
+            }
+        }
+        for (column, value) in [
+            ("playbook", &query.playbook),
+            ("hangup_reason", &query.hangup_reason),
+        ] {
+            if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+                let p = filter.param(Param::Text(value.to_string()));
+                filter.conditions.push(format!("{} = {}", column, p));
+            }
+        }
+        if let Some(min) = query.min_duration {
+            let p = filter.param(Param::Int(min as i64));
+            filter.conditions.push(format!("duration >= {}", p));
+        }
+        if let Some(max) = query.max_duration {
+            let p = filter.param(Param::Int(max as i64));
+            filter.conditions.push(format!("duration <= {}", p));
+        }
+        if let Some(extra) = query.extra.as_deref().filter(|e| !e.is_empty()) {
+            let condition = match extra.split_once('=') {
+                Some((key, value)) => {
+                    let key = filter.param(Param::Text(key.to_string()));
+                    let value = filter.param(Param::Text(value.to_string()));
+                    format!("key = {} AND value = {}", key, value)
+                }
+                None => format!("key = {}", filter.param(Param::Text(extra.to_string()))),
+            };
+            filter.conditions.push(format!(
+                "call_id IN (SELECT call_id FROM call_history_extras WHERE {})",
+                condition
+            ));
+        }
+        if let Some((start_time, call_id)) = query.decode_cursor()? {
+            let start_time = filter.param(Param::Int(start_time));
+            let call_id = filter.param(Param::Text(call_id));
+            filter.conditions.push(format!(
+                "(start_time < {0} OR (start_time = {0} AND call_id < {1}))",
+                start_time, call_id
+            ));
+        }
+        Ok(filter)
+    }
+
+    fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub struct CallHistory {
+    pool: AnyPool,
+}
+
+impl CallHistory {
+    pub async fn open(url: &str) -> Result<Self> {
+        install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .max_connections(4)
+            .connect(url)
+            .await?;
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        Ok(Self { pool })
+    }
+
+    /// Adds a call, or replaces it when already indexed
+    pub async fn index(&self, record: &CallRecord) -> Result<()> {
+        let playbook = record
+            .extras
+            .as_ref()
+            .and_then(|extras| extras.get(BUILTIN_PLAYBOOK))
+            .and_then(|playbook| playbook.as_str())
+            .map(|playbook| playbook.to_string());
+        let duration = (record.end_time - record.start_time).num_seconds().max(0);
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO call_history (call_id, call_type, caller, callee, start_time,
+                answer_time, end_time, duration, status_code, hangup_reason, playbook, record)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            ON CONFLICT (call_id) DO UPDATE SET call_type = excluded.call_type,
+                caller = excluded.caller, callee = excluded.callee,
+                start_time = excluded.start_time, answer_time = excluded.answer_time,
+                end_time = excluded.end_time, duration = excluded.duration,
+                status_code = excluded.status_code, hangup_reason = excluded.hangup_reason,
+                playbook = excluded.playbook, record = excluded.record",
+        )
+        .bind(record.call_id.clone())
+        .bind(as_text(&record.call_type))
+        .bind(record.caller.clone())
+        .bind(record.callee.clone())
+        .bind(record.start_time.timestamp_millis())
+        .bind(record.answer_time.map(|t| t.timestamp_millis()))
+        .bind(record.end_time.timestamp_millis())
+        .bind(duration)
+        .bind(record.status_code as i64)
+        .bind(record.hangup_reason.as_ref().map(|r| r.to_string()))
+        .bind(playbook)
+        .bind(serde_json::to_string(record)?)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM call_history_extras WHERE call_id = $1")
+            .bind(record.call_id.clone())
+            .execute(&mut *tx)
+            .await?;
+        // Internal extras, such as `_sip_header_keys`, are left out
+        let extras = record.extras.iter().flatten();
+        for (key, value) in extras.filter(|(key, _)| !key.starts_with('_')) {
+            sqlx::query(
+                "INSERT INTO call_history_extras (call_id, key, value) VALUES ($1, $2, $3)",
+            )
+            .bind(record.call_id.clone())
+            .bind(key.clone())
+            .bind(as_text(value))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// A page of matching calls, newest first
+    pub async fn list(&self, query: &CallHistoryQuery) -> Result<CallHistoryPage> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let filter = Filter::new(query)?;
+        let sql = format!(
+            "SELECT {} FROM call_history{} ORDER BY start_time DESC, call_id DESC LIMIT {}",
+            SUMMARY_COLUMNS,
+            filter.where_clause(),
+            limit + 1
+        );
+        let mut statement = sqlx::query(&sql);
+        for param in filter.params {
+            statement = match param {
+                Param::Int(value) => statement.bind(value),
+                Param::Text(value) => statement.bind(value),
+            };
+        }
+        let rows = statement.fetch_all(&self.pool).await?;
+        let mut calls = rows
+            .iter()
+            .map(CallSummary::from_row)
+            .collect::<Result<Vec<_>>>()?;
+        let next_cursor = if calls.len() > limit {
+            calls.truncate(limit);
+            calls.last().map(encode_cursor)
+        } else {
+            None
+        };
+        Ok(CallHistoryPage { calls, next_cursor })
+    }
+
+    /// The full record of a call
+    pub async fn get(&self, call_id: &str) -> Result<Option<CallRecord>> {
+        let row = sqlx::query("SELECT record FROM call_history WHERE call_id = $1")
+            .bind(call_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        match row {
+            Some(row) => Ok(Some(serde_json::from_str(&row.try_get::<String, _>(0)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every matching call as CSV, newest first. `limit` and `cursor` are
+    /// ignored.
+    pub async fn export_csv(&self, query: &CallHistoryQuery) -> Result<String> {
+        let mut query = CallHistoryQuery {
+            limit: Some(MAX_LIMIT),
+            cursor: None,
+            ..query.clone()
+        };
+        let mut csv = String::from(CSV_HEADER);
+        loop {
+            let page = self.list(&query).await?;
+            for call in &page.calls {
+                csv.push_str(&csv_row(call));
+            }
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        Ok(csv)
+    }
+
+    /// Indexes the JSON call records found under `root`, as written by the
+    /// `local` sink. Each directory is imported once, later calls are
+    /// indexed as they end.
+    pub async fn import_local(&self, root: &str) -> Result<usize> {
+        let done = sqlx::query("SELECT imported_at FROM call_history_imports WHERE root = $1")
+            .bind(root.to_string())
+            .fetch_optional(&self.pool)
+            .await?;
+        if done.is_some() {
+            return Ok(0);
+        }
+        let mut imported = 0;
+        let mut dirs = vec![PathBuf::from(root)];
+        while let Some(dir) = dirs.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                } else if let Some(record) = read_record(&path).await {
+                    self.index(&record).await?;
+                    imported += 1;
+                }
+            }
+        }
+        sqlx::query("INSERT INTO call_history_imports (root, imported_at) VALUES ($1, $2)")
+            .bind(root.to_string())
+            .bind(Utc::now().timestamp_millis())
+            .execute(&self.pool)
+            .await?;
+        Ok(imported)
+    }
+}
+
+async fn read_record(path: &Path) -> Option<CallRecord> {
+    if path.extension().is_none_or(|ext| ext != "json") {
+        return None;
+    }
+    let content = tokio::fs::read(path).await.ok()?;
+    let record: CallRecord = serde_json::from_slice(&content).ok()?;
+    (!record.call_id.is_empty()).then_some(record)
+}
+
+const CSV_HEADER: &str = "call_id,call_type,caller,callee,start_time,answer_time,end_time,\
+    duration,status_code,hangup_reason,playbook\r\n";
+
+/// A CSV cell, with a leading `'` on values a spreadsheet would run as a
+/// formula, such as a caller ID of `=HYPERLINK(...)`
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+fn csv_row(call: &CallSummary) -> String {
+    let fields = [
+        call.call_id.clone(),
+        call.call_type.clone(),
+        call.caller.clone(),
+        call.callee.clone(),
+        call.start_time.to_rfc3339(),
+        call.answer_time.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        call.end_time.to_rfc3339(),
+        call.duration.to_string(),
+        call.status_code.to_string(),
+        call.hangup_reason.clone().unwrap_or_default(),
+        call.playbook.clone().unwrap_or_default(),
+    ];
+    let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callrecord::CallRecordHangupReason;
+    use chrono::{Duration, TimeZone};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn record(call_id: &str, minute: u32, seconds: i64, extras: &[(&str, &str)]) -> CallRecord {
+        let start_time = Utc.with_ymd_and_hms(2025, 3, 1, 9, minute, 0).unwrap();
+        CallRecord {
+            call_id: call_id.to_string(),
+            caller: format!("sip:{}@example.com", call_id),
+            callee: "sip:support@example.com".to_string(),
+            start_time,
+            end_time: start_time + Duration::seconds(seconds),
+            status_code: 200,
+            hangup_reason: Some(CallRecordHangupReason::ByCaller),
+            extras: Some(
+                extras
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), json!(v)))
+                    .collect::<HashMap<_, _>>(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    async fn open() -> (tempfile::TempDir, CallHistory) {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!(
+            "sqlite://{}?mode=rwc",
+            dir.path().join("history.db").display()
+        );
+        (dir, CallHistory::open(&url).await.unwrap())
+    }
+
+    fn ids(page: &CallHistoryPage) -> Vec<&str> {
+        page.calls.iter().map(|c| c.call_id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_filters_and_pagination() {
+        let (_dir, history) = open().await;
+        history
+            .index(&record("a", 0, 30, &[("playbook", "sales.md")]))
+            .await
+            .unwrap();
+        history
+            .index(&record(
+                "b",
+                1,
+                300,
+                &[("playbook", "support.md"), ("tier", "gold")],
+            ))
+            .await
+            .unwrap();
+        let mut c = record("c", 2, 5, &[("tier", "silver"), ("_internal", "x")]);
+        c.hangup_reason = Some(CallRecordHangupReason::NoAnswer);
+        history.index(&c).await.unwrap();
+        // Indexing a call again replaces it
+        c.caller = "sip:100%_done@example.com".to_string();
+        history.index(&c).await.unwrap();
+
+        let mut query = CallHistoryQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = history.list(&query).await.unwrap();
+        assert_eq!(ids(&page), ["c", "b"]);
+        query.cursor = page.next_cursor;
+        let page = history.list(&query).await.unwrap();
+        assert_eq!(ids(&page), ["a"]);
+        assert!(page.next_cursor.is_none());
+
+        let list = |query: CallHistoryQuery| {
+            let history = &history;
+            async move { history.list(&query).await.unwrap() }
+        };
+        let page = list(CallHistoryQuery {
+            playbook: Some("support.md".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(ids(&page), ["b"]);
+        assert_eq!(page.calls[0].duration, 300);
+        assert_eq!(page.calls[0].playbook.as_deref(), Some("support.md"));
+
+        let page = list(CallHistoryQuery {
+            min_duration: Some(10),
+            max_duration: Some(60),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(ids(&page), ["a"]);
+
+        let page = list(CallHistoryQuery {
+            hangup_reason: Some("noAnswer".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(ids(&page), ["c"]);
+
+        let page = list(CallHistoryQuery {
+            from: Some(Utc.with_ymd_and_hms(2025, 3, 1, 9, 1, 0).unwrap()),
+            to: Some(Utc.with_ymd_and_hms(2025, 3, 1, 9, 2, 0).unwrap()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(ids(&page), ["b"]);
+
+        let page = list(CallHistoryQuery {
+            caller: Some("100%_".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(ids(&page), ["c"]);
+        let page = list(CallHistoryQuery {
+            caller: Some("1%0".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert!(page.calls.is_empty());
+
+        let page = list(CallHistoryQuery {
+            extra: Some("tier=gold".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(ids(&page), ["b"]);
+        let page = list(CallHistoryQuery {
+            extra: Some("tier".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(ids(&page), ["c", "b"]);
+        let page = list(CallHistoryQuery {
+            extra: Some("_internal".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert!(page.calls.is_empty());
+
+        let invalid = CallHistoryQuery {
+            cursor: Some("not a cursor".to_string()),
+            ..Default::default()
+        };
+        assert!(history.list(&invalid).await.is_err());
+
+        let stored = history.get("c").await.unwrap().unwrap();
+        assert_eq!(stored.caller, "sip:100%_done@example.com");
+        assert!(history.get("missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_export_csv() {
+        let (_dir, history) = open().await;
+        let mut a = record("a", 0, 42, &[("playbook", "sales.md")]);
+        a.caller = "\"Alice, Sales\" <sip:alice@example.com>".to_string();
+        history.index(&a).await.unwrap();
+        history.index(&record("b", 1, 7, &[])).await.unwrap();
+
+        let csv = history
+            .export_csv(&CallHistoryQuery {
+                limit: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let lines: Vec<_> = csv.split("\r\n").collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("call_id,call_type,caller,"));
+        assert!(lines[1].starts_with("b,sip,sip:b@example.com,"));
+        assert_eq!(
+            lines[2],
+            "a,sip,\"\"\"Alice, Sales\"\" <sip:alice@example.com>\",sip:support@example.com,\
+            2025-03-01T09:00:00+00:00,,2025-03-01T09:00:42+00:00,42,200,caller,sales.md"
+        );
+        assert_eq!(lines[3], "");
+    }
+
+    #[test]
+    fn test_csv_field_formulas() {
+        assert_eq!(csv_field("=1+1"), "'=1+1");
+        assert_eq!(csv_field("+15551234567"), "'+15551234567");
+        assert_eq!(csv_field("-2"), "'-2");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(
+            csv_field("=HYPERLINK(\"http://x\",\"y\")"),
+            "\"'=HYPERLINK(\"\"http://x\"\",\"\"y\"\")\""
+        );
+        assert_eq!(csv_field("\t=1+1"), "'\t=1+1");
+        assert_eq!(csv_field("\r=1+1"), "\"'\r=1+1\"");
+        assert_eq!(csv_field("sip:a=b@example.com"), "sip:a=b@example.com");
+    }
+}
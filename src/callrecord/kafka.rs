@@ -0,0 +1,533 @@
+//! Minimal Kafka protocol producer for call records: a Metadata v1 lookup of
+//! the partition leader followed by a Produce v3 request carrying a single
+//! record batch. Partitions are picked by murmur2 of the key, like the Java
+//! client's default partitioner. Leaders and broker connections are kept
+//! across records; a produce that hits a moved leader or a dropped
+//! connection looks the leader up again and is retried.
+//!
+//! Plaintext only: there is no TLS or SASL, so brokers must expose a
+//! `PLAINTEXT` listener.
+use anyhow::{Result, anyhow};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::warn;
+
+pub const API_PRODUCE: i16 = 0;
+pub const API_METADATA: i16 = 3;
+const CLIENT_ID: &str = "active-call";
+/// UNKNOWN_TOPIC_OR_PARTITION, LEADER_NOT_AVAILABLE, NOT_LEADER_OR_FOLLOWER,
+/// REQUEST_TIMED_OUT, NETWORK_EXCEPTION and NOT_ENOUGH_REPLICAS(_AFTER_APPEND)
+const RETRIABLE_ERRORS: [i16; 7] = [3, 5, 6, 7, 13, 19, 20];
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+pub struct KafkaProducer {
+    brokers: Vec<String>,
+    acks: i16,
+    timeout: Duration,
+    /// Partition leaders by topic, dropped when a produce to them fails
+    leaders: Mutex<HashMap<String, Vec<PartitionLeader>>>,
+    /// Idle connections by broker address
+    idle: Mutex<HashMap<String, Vec<TcpStream>>>,
+}
+
+#[derive(Clone)]
+struct PartitionLeader {
+    partition: i32,
+    address: Option<String>,
+}
+
+/// Why a produce attempt failed
+enum Failure {
+    /// A stale leader or connection, worth a new leader lookup
+    Retry(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Failure {
+    fn from(e: anyhow::Error) -> Self {
+        Failure::Retry(e)
+    }
+}
+
+fn error_code_failure(error_code: i16, e: anyhow::Error) -> Failure {
+    if RETRIABLE_ERRORS.contains(&error_code) {
+        Failure::Retry(e)
+    } else {
+        Failure::Fatal(e)
+    }
+}
+
+impl KafkaProducer {
+    pub fn new(brokers: Vec<String>, acks: Option<i16>, timeout_ms: Option<u64>) -> Self {
+        Self {
+            brokers,
+            acks: acks.unwrap_or(-1),
+            timeout: Duration::from_millis(timeout_ms.unwrap_or(10_000)),
+            leaders: Mutex::new(HashMap::new()),
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sends one record and returns its partition and offset
+    pub async fn send(&self, topic: &str, key: &[u8], value: &[u8]) -> Result<(i32, i64)> {
+        if self.acks == 0 {
+            return Err(anyhow!(
+                "acks = 0 is not supported, delivery must be confirmed"
+            ));
+        }
+        let mut attempt = 1;
+        loop {
+            match self.try_send(topic, key, value).await {
+                Ok(sent) => return Ok(sent),
+                Err(Failure::Retry(e)) if attempt < MAX_ATTEMPTS => {
+                    warn!(topic, attempt, "kafka produce failed, retrying: {}", e);
+                    self.leaders.lock().unwrap().remove(topic);
+                    tokio::time::sleep(RETRY_BACKOFF * attempt).await;
+                    attempt += 1;
+                }
+                Err(Failure::Retry(e) | Failure::Fatal(e)) => return Err(e),
+            }
+        }
+    }
+
+    async fn try_send(
+        &self,
+        topic: &str,
+        key: &[u8],
+        value: &[u8],
+    ) -> std::result::Result<(i32, i64), Failure> {
+        let leader = self.leader(topic, key).await?;
+        let address = leader.address.ok_or_else(|| {
+            anyhow!(
+                "kafka partition {}/{} has no leader",
+                topic,
+                leader.partition
+            )
+        })?;
+
+        let batch = record_batch(key, value, chrono::Utc::now().timestamp_millis());
+        let mut body = BytesMut::new();
+        body.put_i16(-1); // no transactional id
+        body.put_i16(self.acks);
+        body.put_i32(self.timeout.as_millis() as i32);
+        body.put_i32(1);
+        put_string(&mut body, topic);
+        body.put_i32(1);
+        body.put_i32(leader.partition);
+        body.put_i32(batch.len() as i32);
+        body.put_slice(&batch);
+
+        let mut response = self.send_request(&address, API_PRODUCE, 3, &body).await?;
+        let _topics = get_i32(&mut response)?;
+        let _name = get_string(&mut response)?;
+        let _partitions = get_i32(&mut response)?;
+        let partition = get_i32(&mut response)?;
+        let error_code = get_i16(&mut response)?;
+        if error_code != 0 {
+            let e = anyhow!(
+                "kafka produce to {}/{} failed with error code {}",
+                topic,
+                partition,
+                error_code
+            );
+            return Err(error_code_failure(error_code, e));
+        }
+        Ok((partition, get_i64(&mut response)?))
+    }
+
+    async fn leader(
+        &self,
+        topic: &str,
+        key: &[u8],
+    ) -> std::result::Result<PartitionLeader, Failure> {
+        let cached = self.leaders.lock().unwrap().get(topic).cloned();
+        let partitions = match cached {
+            Some(partitions) => partitions,
+            None => {
+                let partitions = self.lookup(topic).await?;
+                self.leaders
+                    .lock()
+                    .unwrap()
+                    .insert(topic.to_string(), partitions.clone());
+                partitions
+            }
+        };
+        let index = (murmur2(key) & 0x7fff_ffff) as usize % partitions.len();
+        Ok(partitions[index].clone())
+    }
+
+    async fn lookup(&self, topic: &str) -> std::result::Result<Vec<PartitionLeader>, Failure> {
+        let mut last_error = Failure::Fatal(anyhow!("no kafka brokers configured"));
+        for broker in &self.brokers {
+            match self.metadata(broker, topic).await {
+                Ok(partitions) => return Ok(partitions),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn metadata(
+        &self,
+        broker: &str,
+        topic: &str,
+    ) -> std::result::Result<Vec<PartitionLeader>, Failure> {
+        let mut body = BytesMut::new();
+        body.put_i32(1);
+        put_string(&mut body, topic);
+        let mut response = self.send_request(broker, API_METADATA, 1, &body).await?;
+
+        let mut brokers = Vec::new();
+        for _ in 0..get_i32(&mut response)? {
+            let node_id = get_i32(&mut response)?;
+            let host = get_string(&mut response)?;
+            let port = get_i32(&mut response)?;
+            let _rack = get_nullable_string(&mut response)?;
+            brokers.push((node_id, format!("{}:{}", host, port)));
+        }
+        let _controller_id = get_i32(&mut response)?;
+        let mut partitions = Vec::new();
+        for _ in 0..get_i32(&mut response)? {
+            let error_code = get_i16(&mut response)?;
+            let name = get_string(&mut response)?;
+            let _is_internal = get_i8(&mut response)?;
+            for _ in 0..get_i32(&mut response)? {
+                let _error_code = get_i16(&mut response)?;
+                let partition = get_i32(&mut response)?;
+                let leader = get_i32(&mut response)?;
+                for _ in 0..get_i32(&mut response)? {
+                    get_i32(&mut response)?;
+                }
+                for _ in 0..get_i32(&mut response)? {
+                    get_i32(&mut response)?;
+                }
+                partitions.push((partition, leader));
+            }
+            if name == topic && error_code != 0 {
+                let e = anyhow!(
+                    "kafka metadata of topic {} failed with error code {}",
+                    topic,
+                    error_code
+                );
+                return Err(error_code_failure(error_code, e));
+            }
+        }
+        if partitions.is_empty() {
+            return Err(anyhow!("kafka topic {} has no partitions", topic).into());
+        }
+        partitions.sort_unstable();
+        Ok(partitions
+            .into_iter()
+            .map(|(partition, leader)| PartitionLeader {
+                partition,
+                address: brokers
+                    .iter()
+                    .find(|(node_id, _)| *node_id == leader)
+                    .map(|(_, address)| address.clone()),
+            })
+            .collect())
+    }
+
+    /// Sends a request over an idle connection to `address`, or a new one,
+    /// and keeps the connection for later requests unless it failed
+    async fn send_request(
+        &self,
+        address: &str,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+    ) -> Result<Bytes> {
+        let idle = self
+            .idle
+            .lock()
+            .unwrap()
+            .get_mut(address)
+            .and_then(|streams| streams.pop());
+        let mut stream = match idle {
+            Some(stream) => stream,
+            None => connect(address, self.timeout).await?,
+        };
+        let response = request(&mut stream, api_key, api_version, body, self.timeout).await?;
+        let mut idle = self.idle.lock().unwrap();
+        let streams = idle.entry(address.to_string()).or_default();
+        if streams.len() < MAX_IDLE_CONNECTIONS {
+            streams.push(stream);
+        }
+        Ok(response)
+    }
+}
+
+async fn connect(address: &str, timeout: Duration) -> Result<TcpStream> {
+    tokio::time::timeout(timeout, TcpStream::connect(address))
+        .await
+        .map_err(|_| anyhow!("kafka connect to {} timed out", address))?
+        .map_err(|e| anyhow!("kafka connect to {} failed: {}", address, e))
+}
+
+/// Sends a request and returns the response body, past its correlation id
+async fn request(
+    stream: &mut TcpStream,
+    api_key: i16,
+    api_version: i16,
+    body: &[u8],
+    timeout: Duration,
+) -> Result<Bytes> {
+    let correlation_id = 1;
+    let mut frame = BytesMut::new();
+    frame.put_i32(0);
+    frame.put_i16(api_key);
+    frame.put_i16(api_version);
+    frame.put_i32(correlation_id);
+    put_string(&mut frame, CLIENT_ID);
+    frame.put_slice(body);
+    let size = (frame.len() - 4) as i32;
+    frame[..4].copy_from_slice(&size.to_be_bytes());
+
+    let exchange = async {
+        stream.write_all(&frame).await?;
+        let size = stream.read_i32().await?;
+        if !(4..=64 * 1024 * 1024).contains(&size) {
+            return Err(anyhow!("invalid kafka response size {}", size));
+        }
+        let mut response = vec![0u8; size as usize];
+        stream.read_exact(&mut response).await?;
+        Ok(Bytes::from(response))
+    };
+    let mut response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| anyhow!("kafka request timed out"))??;
+    if get_i32(&mut response)? != correlation_id {
+        return Err(anyhow!("kafka response correlation id mismatch"));
+    }
+    Ok(response)
+}
+
+/// A v2 record batch holding a single record
+pub fn record_batch(key: &[u8], value: &[u8], timestamp: i64) -> Bytes {
+    let mut record = BytesMut::new();
+    record.put_i8(0); // attributes
+    put_varint(&mut record, 0); // timestamp delta
+    put_varint(&mut record, 0); // offset delta
+    put_varint(&mut record, key.len() as i64);
+    record.put_slice(key);
+    put_varint(&mut record, value.len() as i64);
+    record.put_slice(value);
+    put_varint(&mut record, 0); // headers
+
+    // Everything the CRC covers, from the attributes on
+    let mut tail = BytesMut::new();
+    tail.put_i16(0); // attributes
+    tail.put_i32(0); // last offset delta
+    tail.put_i64(timestamp);
+    tail.put_i64(timestamp);
+    tail.put_i64(-1); // producer id
+    tail.put_i16(-1); // producer epoch
+    tail.put_i32(-1); // base sequence
+    tail.put_i32(1);
+    put_varint(&mut tail, record.len() as i64);
+    tail.put_slice(&record);
+
+    let mut batch = BytesMut::new();
+    batch.put_i64(0); // base offset
+    batch.put_i32((4 + 1 + 4 + tail.len()) as i32);
+    batch.put_i32(-1); // partition leader epoch
+    batch.put_i8(2); // magic
+    batch.put_u32(crc32c(&tail));
+    batch.put_slice(&tail);
+    batch.freeze()
+}
+
+/// Key and value of the single record in a v2 batch, checking its CRC
+pub fn parse_record_batch(mut batch: Bytes) -> Result<(Vec<u8>, Vec<u8>)> {
+    let _base_offset = get_i64(&mut batch)?;
+    let _length = get_i32(&mut batch)?;
+    let _leader_epoch = get_i32(&mut batch)?;
+    if get_i8(&mut batch)? != 2 {
+        return Err(anyhow!("unsupported record batch magic"));
+    }
+    let crc = get_u32(&mut batch)?;
+    if crc != crc32c(&batch) {
+        return Err(anyhow!("record batch CRC mismatch"));
+    }
+    if batch.len() < 2 + 4 + 8 + 8 + 8 + 2 + 4 + 4 {
+        return Err(anyhow!("truncated record batch"));
+    }
+    batch.advance(2 + 4 + 8 + 8 + 8 + 2 + 4 + 4);
+    let _length = get_varint(&mut batch)?;
+    let _attributes = get_i8(&mut batch)?;
+    let _timestamp_delta = get_varint(&mut batch)?;
+    let _offset_delta = get_varint(&mut batch)?;
+    let key = get_varint_bytes(&mut batch)?;
+    let value = get_varint_bytes(&mut batch)?;
+    Ok((key, value))
+}
+
+/// CRC-32C (Castagnoli), as used by record batches
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Murmur2 as implemented by the Kafka Java client
+pub fn murmur2(data: &[u8]) -> i32 {
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+    let mut h: u32 = 0x9747_b28c ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+pub fn put_string(buf: &mut BytesMut, value: &str) {
+    buf.put_i16(value.len() as i16);
+    buf.put_slice(value.as_bytes());
+}
+
+fn put_varint(buf: &mut BytesMut, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        buf.put_u8((zigzag as u8) | 0x80);
+        zigzag >>= 7;
+    }
+    buf.put_u8(zigzag as u8);
+}
+
+fn ensure(buf: &Bytes, len: usize) -> Result<()> {
+    if buf.remaining() < len {
+        return Err(anyhow!("truncated kafka message"));
+    }
+    Ok(())
+}
+
+pub fn get_i8(buf: &mut Bytes) -> Result<i8> {
+    ensure(buf, 1)?;
+    Ok(buf.get_i8())
+}
+
+pub fn get_i16(buf: &mut Bytes) -> Result<i16> {
+    ensure(buf, 2)?;
+    Ok(buf.get_i16())
+}
+
+pub fn get_i32(buf: &mut Bytes) -> Result<i32> {
+    ensure(buf, 4)?;
+    Ok(buf.get_i32())
+}
+
+fn get_u32(buf: &mut Bytes) -> Result<u32> {
+    ensure(buf, 4)?;
+    Ok(buf.get_u32())
+}
+
+pub fn get_i64(buf: &mut Bytes) -> Result<i64> {
+    ensure(buf, 8)?;
+    Ok(buf.get_i64())
+}
+
+pub fn get_string(buf: &mut Bytes) -> Result<String> {
+    get_nullable_string(buf)?.ok_or_else(|| anyhow!("unexpected null string"))
+}
+
+pub fn get_nullable_string(buf: &mut Bytes) -> Result<Option<String>> {
+    let len = get_i16(buf)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    ensure(buf, len as usize)?;
+    Ok(Some(
+        String::from_utf8_lossy(&buf.split_to(len as usize)).to_string(),
+    ))
+}
+
+fn get_varint(buf: &mut Bytes) -> Result<i64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        ensure(buf, 1)?;
+        let byte = buf.get_u8();
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((value >> 1) as i64) ^ -((value & 1) as i64));
+        }
+    }
+    Err(anyhow!("varint too long"))
+}
+
+fn get_varint_bytes(buf: &mut Bytes) -> Result<Vec<u8>> {
+    let len = get_varint(buf)?;
+    if len < 0 {
+        return Ok(Vec::new());
+    }
+    ensure(buf, len as usize)?;
+    Ok(buf.split_to(len as usize).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        // Reference values from the Kafka Java client
+        assert_eq!(murmur2(b"21"), -973932308);
+        assert_eq!(murmur2(b"foobar"), -790332482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985981536);
+        assert_eq!(murmur2(b"a-little-bit-longer-string"), -1486304829);
+        assert_eq!(
+            murmur2(b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8"),
+            -58897971
+        );
+        assert_eq!(murmur2(b"abc"), 479470107);
+    }
+
+    #[test]
+    fn test_record_batch_roundtrip() {
+        let value = "x".repeat(300);
+        let batch = record_batch(b"call-1", value.as_bytes(), 1_700_000_000_000);
+        let (key, parsed) = parse_record_batch(batch.clone()).unwrap();
+        assert_eq!(key, b"call-1");
+        assert_eq!(parsed, value.as_bytes());
+
+        let mut corrupted = BytesMut::from(&batch[..]);
+        let last = corrupted.len() - 2;
+        corrupted[last] ^= 0xff;
+        assert!(parse_record_batch(corrupted.freeze()).is_err());
+    }
+}
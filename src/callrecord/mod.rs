@@ -2,6 +2,7 @@ use crate::CallOption;
 use crate::{
     call::ActiveCallType,
     config::{CallRecordConfig, S3Vendor},
+    media::{quality::CallQuality, redaction::RedactionSpan},
 };
 use anyhow::Result;
 use chrono::{DateTime, Local, Utc};
@@ -15,13 +16,29 @@ use reqwest;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::{
-    collections::HashMap, future::Future, path::Path, pin::Pin, str::FromStr, sync::Arc,
-    time::Instant,
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::{fs::File, io::AsyncWriteExt};
 use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+pub mod analytics;
+#[cfg(feature = "cdr-sql")]
+pub mod database;
+#[cfg(feature = "cdr-sql")]
+pub mod history;
+pub mod kafka;
+pub mod nats;
+pub mod spool;
+pub mod syslog;
+pub mod transcript;
+
 pub type CallRecordSender = tokio::sync::mpsc::UnboundedSender<CallRecord>;
 pub type CallRecordReceiver = tokio::sync::mpsc::UnboundedReceiver<CallRecord>;
 
@@ -38,6 +55,69 @@ pub type FnSaveCallRecord = Arc<
     >,
 >;
 
+/// Kafka producers and NATS connections of the sinks, kept across records
+#[derive(Default)]
+pub struct SinkClients {
+    kafka: std::sync::Mutex<HashMap<String, Arc<kafka::KafkaProducer>>>,
+    nats: std::sync::Mutex<HashMap<String, Arc<nats::NatsPublisher>>>,
+}
+
+impl SinkClients {
+    fn kafka(
+        &self,
+        brokers: &[String],
+        acks: Option<i16>,
+        timeout_ms: Option<u64>,
+    ) -> Arc<kafka::KafkaProducer> {
+        let key = format!("{}|{:?}|{:?}", brokers.join(","), acks, timeout_ms);
+        self.kafka
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(kafka::KafkaProducer::new(
+                    brokers.to_vec(),
+                    acks,
+                    timeout_ms,
+                ))
+            })
+            .clone()
+    }
+
+    fn nats(&self, url: &str, token: Option<&str>) -> Arc<nats::NatsPublisher> {
+        let key = format!("{}|{:?}", url, token);
+        self.nats
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(nats::NatsPublisher::new(url, token)))
+            .clone()
+    }
+}
+
+/// A fanout save where some of the sinks failed
+#[derive(Debug)]
+pub struct FanoutError {
+    /// Indices of the failed sinks in the fanout
+    pub failed: Vec<usize>,
+    pub total: usize,
+    pub errors: Vec<String>,
+}
+
+impl std::fmt::Display for FanoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} call record sinks failed: {}",
+            self.failed.len(),
+            self.total,
+            self.errors.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for FanoutError {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum CallRecordEventType {
@@ -96,6 +176,21 @@ pub struct CallRecord {
     #[serde(default)]
     pub recorder: Vec<CallRecordMedia>,
     pub extras: Option<HashMap<String, serde_json::Value>>,
+    /// Voice quality of each RTP track at hangup, by track id
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    pub quality: HashMap<String, CallQuality>,
+    /// Paused or redacted stretches of the recording, for audit
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub redactions: Vec<RedactionSpan>,
+    /// Transcript files of the call, JSON, WebVTT and SRT, plus the
+    /// analytics sidecar when configured
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub transcript: Vec<CallRecordMedia>,
+    /// Post-call analytics, when written to the call record
+    pub analytics: Option<analytics::CallAnalytics>,
     pub dump_event_file: Option<String>,
     pub refer_callrecord: Option<Box<CallRecord>>,
 }
@@ -137,6 +232,9 @@ pub struct CallRecordHangupMessage {
     pub reason: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub target: Option<String>,
+    /// Trunk group member the attempt was routed through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trunk: Option<String>,
 }
 
 impl FromStr for CallRecordHangupReason {
@@ -219,6 +317,19 @@ impl DefaultCallRecordFormatter {
         let root = match config {
             CallRecordConfig::Local { root } => root.clone(),
             CallRecordConfig::S3 { root, .. } => root.clone(),
+            // Local and S3 paths of a fanout follow its first such sink
+            CallRecordConfig::Fanout { sinks } => {
+                return sinks
+                    .iter()
+                    .find(|sink| {
+                        matches!(
+                            sink,
+                            CallRecordConfig::Local { .. } | CallRecordConfig::S3 { .. }
+                        )
+                    })
+                    .map(Self::new_with_config)
+                    .unwrap_or_default();
+            }
             _ => "./config/cdr".to_string(),
         };
         Self { root }
@@ -330,6 +441,138 @@ pub struct CallRecordManager {
     receiver: CallRecordReceiver,
     saver_fn: FnSaveCallRecord,
     formatter: Arc<dyn CallRecordFormatter>,
+    analyzer: Option<Arc<analytics::CallAnalyzer>>,
+    spool: Option<Arc<spool::CallRecordSpool>>,
+    #[cfg(feature = "cdr-sql")]
+    history: Option<Arc<history::CallHistory>>,
+}
+
+/// For a fanout `config`, a fanout of the sinks `entry` is still to be
+/// delivered to, along with their indices in `config`
+fn pending_fanout(
+    config: &CallRecordConfig,
+    entry: &spool::SpoolEntry,
+) -> Option<(CallRecordConfig, Vec<usize>)> {
+    let CallRecordConfig::Fanout { sinks } = config else {
+        return None;
+    };
+    let pending: Vec<usize> = match &entry.sinks {
+        Some(pending) => pending
+            .iter()
+            .copied()
+            .filter(|&i| i < sinks.len())
+            .collect(),
+        None => (0..sinks.len()).collect(),
+    };
+    let fanout = CallRecordConfig::Fanout {
+        sinks: pending.iter().map(|&i| sinks[i].clone()).collect(),
+    };
+    Some((fanout, pending))
+}
+
+/// How often due spooled records are retried
+const SPOOL_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Saves records through the saver, via the spool when there is one
+#[derive(Clone)]
+struct CallRecordDelivery {
+    cancel_token: CancellationToken,
+    saver_fn: FnSaveCallRecord,
+    config: Arc<CallRecordConfig>,
+    formatter: Arc<dyn CallRecordFormatter>,
+    spool: Option<Arc<spool::CallRecordSpool>>,
+    #[cfg(feature = "cdr-sql")]
+    history: Option<Arc<history::CallHistory>>,
+}
+
+impl CallRecordDelivery {
+    /// Adds the record to the call history, whether or not it gets delivered
+    #[cfg_attr(not(feature = "cdr-sql"), allow(unused_variables))]
+    async fn index(&self, record: &CallRecord) {
+        #[cfg(feature = "cdr-sql")]
+        if let Some(history) = self.history.as_ref()
+            && let Err(e) = history.index(record).await
+        {
+            warn!(
+                call_id = record.call_id,
+                "Failed to index call record: {}", e
+            );
+        }
+    }
+
+    async fn enqueue(&self, record: &CallRecord) -> Option<spool::SpoolEntry> {
+        let spool = self.spool.as_ref()?;
+        match spool.enqueue(record.clone()).await {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!(
+                    call_id = record.call_id,
+                    "Failed to spool call record: {}", e
+                );
+                None
+            }
+        }
+    }
+
+    /// Delivers a spooled entry, rescheduling it on failure, or saves
+    /// `record` directly when it was not spooled. A fanout entry is only
+    /// retried against the sinks that failed.
+    async fn deliver(&self, entry: Option<spool::SpoolEntry>, record: CallRecord) {
+        let (Some(spool), Some(mut entry)) = (self.spool.as_ref(), entry) else {
+            if let Err(e) = self.save(self.config.clone(), record).await {
+                warn!("Failed to save call record: {}", e);
+            }
+            return;
+        };
+        let pending = pending_fanout(&self.config, &entry);
+        let config = match &pending {
+            Some((fanout, _)) => Arc::new(fanout.clone()),
+            None => self.config.clone(),
+        };
+        match self.save(config, spool.delivery_record(&entry)).await {
+            Ok(()) => spool.complete(&entry).await,
+            Err(e) => {
+                if let (Some((_, sinks)), Some(fanout)) =
+                    (&pending, e.downcast_ref::<FanoutError>())
+                {
+                    entry.sinks = Some(fanout.failed.iter().map(|&i| sinks[i]).collect());
+                }
+                if let Err(e) = spool.fail(entry, &e).await {
+                    warn!("Failed to reschedule spooled call record: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn save(&self, config: Arc<CallRecordConfig>, record: CallRecord) -> Result<()> {
+        (self.saver_fn)(
+            self.cancel_token.clone(),
+            self.formatter.clone(),
+            config,
+            record,
+        )
+        .await
+    }
+
+    async fn retry_due(&self) {
+        let Some(spool) = self.spool.as_ref() else {
+            return;
+        };
+        for entry in spool.take_due().await {
+            let record = entry.record.clone();
+            self.deliver(Some(entry), record).await;
+        }
+    }
+
+    async fn retry_loop(&self) {
+        if self.spool.is_none() {
+            return std::future::pending().await;
+        }
+        loop {
+            self.retry_due().await;
+            tokio::time::sleep(SPOOL_POLL_INTERVAL).await;
+        }
+    }
 }
 
 pub struct CallRecordManagerBuilder {
@@ -338,6 +581,10 @@ pub struct CallRecordManagerBuilder {
     pub max_concurrent: Option<usize>,
     saver_fn: Option<FnSaveCallRecord>,
     formatter: Option<Arc<dyn CallRecordFormatter>>,
+    analytics: Option<analytics::AnalyticsConfig>,
+    spool: Option<Arc<spool::CallRecordSpool>>,
+    #[cfg(feature = "cdr-sql")]
+    history: Option<Arc<history::CallHistory>>,
 }
 
 impl CallRecordManagerBuilder {
@@ -348,6 +595,10 @@ impl CallRecordManagerBuilder {
             max_concurrent: None,
             saver_fn: None,
             formatter: None,
+            analytics: None,
+            spool: None,
+            #[cfg(feature = "cdr-sql")]
+            history: None,
         }
     }
 
@@ -376,13 +627,38 @@ impl CallRecordManagerBuilder {
         self
     }
 
+    pub fn with_analytics(mut self, analytics: analytics::AnalyticsConfig) -> Self {
+        self.analytics = Some(analytics);
+        self
+    }
+
+    pub fn with_spool(mut self, spool: Arc<spool::CallRecordSpool>) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    #[cfg(feature = "cdr-sql")]
+    pub fn with_history(mut self, history: Arc<history::CallHistory>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
     pub fn build(self) -> CallRecordManager {
         let cancel_token = self.cancel_token.unwrap_or_default();
         let config = Arc::new(self.config.unwrap_or_default());
         let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
-        let saver_fn = self
-            .saver_fn
-            .unwrap_or_else(|| Arc::new(Box::new(CallRecordManager::default_saver)));
+        let saver_fn = self.saver_fn.unwrap_or_else(|| {
+            let clients = Arc::new(SinkClients::default());
+            Arc::new(Box::new(move |cancel_token, formatter, config, record| {
+                CallRecordManager::default_saver(
+                    clients.clone(),
+                    cancel_token,
+                    formatter,
+                    config,
+                    record,
+                )
+            }))
+        });
         let formatter = self
             .formatter
             .unwrap_or_else(|| Arc::new(DefaultCallRecordFormatter::default()));
@@ -412,12 +688,19 @@ impl CallRecordManagerBuilder {
             config,
             saver_fn,
             formatter,
+            analyzer: self
+                .analytics
+                .map(|config| Arc::new(analytics::CallAnalyzer::new(config))),
+            spool: self.spool,
+            #[cfg(feature = "cdr-sql")]
+            history: self.history,
         }
     }
 }
 
 impl CallRecordManager {
     fn default_saver(
+        clients: Arc<SinkClients>,
         _cancel_token: CancellationToken,
         formatter: Arc<dyn CallRecordFormatter>,
         config: Arc<CallRecordConfig>,
@@ -426,9 +709,35 @@ impl CallRecordManager {
         Box::pin(async move {
             let mut record = record;
             let start_time = Instant::now();
-            let result = match config.as_ref() {
+            let result = Self::save_to(formatter, &clients, &config, &mut record).await;
+            let file_name = match result {
+                Ok(file_name) => file_name,
+                Err(e) => {
+                    warn!("Failed to save call record: {}", e);
+                    return Err(e);
+                }
+            };
+            let elapsed = start_time.elapsed();
+            info!(
+                ?elapsed,
+                call_id = record.call_id,
+                file_name,
+                "CallRecordManager saved"
+            );
+            Ok(())
+        })
+    }
+
+    pub fn save_to<'a>(
+        formatter: Arc<dyn CallRecordFormatter>,
+        clients: &'a SinkClients,
+        config: &'a CallRecordConfig,
+        record: &'a mut CallRecord,
+    ) -> Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>> {
+        Box::pin(async move {
+            match config {
                 CallRecordConfig::Local { .. } => {
-                    Self::save_local_record(formatter.clone(), &mut record).await
+                    Self::save_local_record(formatter.clone(), record).await
                 }
                 CallRecordConfig::S3 {
                     vendor,
@@ -451,7 +760,7 @@ impl CallRecordManager {
                         endpoint,
                         with_media,
                         keep_media_copy,
-                        &record,
+                        record,
                     )
                     .await
                 }
@@ -467,26 +776,89 @@ impl CallRecordManager {
                         headers,
                         with_media,
                         keep_media_copy,
-                        &record,
+                        record,
                     )
                     .await
                 }
-            };
-            let file_name = match result {
-                Ok(file_name) => file_name,
-                Err(e) => {
-                    warn!("Failed to save call record: {}", e);
-                    return Err(e);
+                CallRecordConfig::Kafka {
+                    brokers,
+                    topic,
+                    acks,
+                    timeout_ms,
+                } => {
+                    let content = formatter.format(record)?;
+                    let (partition, offset) = clients
+                        .kafka(brokers, *acks, *timeout_ms)
+                        .send(topic, record.call_id.as_bytes(), content.as_bytes())
+                        .await?;
+                    Ok(format!("kafka {}/{}@{}", topic, partition, offset))
                 }
-            };
-            let elapsed = start_time.elapsed();
-            info!(
-                ?elapsed,
-                call_id = record.call_id,
-                file_name,
-                "CallRecordManager saved"
-            );
-            Ok(())
+                CallRecordConfig::Nats {
+                    url,
+                    subject,
+                    token,
+                } => {
+                    let content = formatter.format(record)?;
+                    clients
+                        .nats(url, token.as_deref())
+                        .publish(subject, content.as_bytes())
+                        .await?;
+                    Ok(format!("nats {}", subject))
+                }
+                #[cfg(feature = "cdr-sql")]
+                CallRecordConfig::Database { url } => {
+                    let content = formatter.format(record)?;
+                    database::save(url, record, &content).await?;
+                    Ok("database".to_string())
+                }
+                #[cfg(not(feature = "cdr-sql"))]
+                CallRecordConfig::Database { .. } => Err(anyhow::anyhow!(
+                    "database call records need the cdr-sql feature"
+                )),
+                CallRecordConfig::Syslog {
+                    address,
+                    facility,
+                    hostname,
+                    app_name,
+                } => {
+                    let content = formatter.format(record)?;
+                    syslog::send(
+                        address,
+                        record,
+                        &content,
+                        *facility,
+                        hostname.as_deref(),
+                        app_name.as_deref(),
+                    )
+                    .await?;
+                    Ok(format!("syslog {}", address))
+                }
+                // In order, as sinks uploading media may delete the local copies
+                CallRecordConfig::Fanout { sinks } => {
+                    let mut saved = Vec::new();
+                    let mut failed = Vec::new();
+                    let mut errors = Vec::new();
+                    for (index, sink) in sinks.iter().enumerate() {
+                        match Self::save_to(formatter.clone(), clients, sink, record).await {
+                            Ok(name) => saved.push(name),
+                            Err(e) => {
+                                failed.push(index);
+                                errors.push(e.to_string());
+                            }
+                        }
+                    }
+                    if failed.is_empty() {
+                        Ok(saved.join(", "))
+                    } else {
+                        Err(FanoutError {
+                            failed,
+                            total: sinks.len(),
+                            errors,
+                        }
+                        .into())
+                    }
+                }
+            }
         })
     }
 
@@ -560,6 +932,22 @@ impl CallRecordManager {
                     }
                 }
             }
+            for media in &record.transcript {
+                if Path::new(&media.path).exists() {
+                    let file_name = Path::new(&media.path)
+                        .file_name()
+                        .unwrap_or_else(|| std::ffi::OsStr::new("unknown"))
+                        .to_string_lossy()
+                        .to_string();
+                    if let Ok(part) =
+                        reqwest::multipart::Part::bytes(tokio::fs::read(&media.path).await?)
+                            .file_name(file_name.clone())
+                            .mime_str("application/octet-stream")
+                    {
+                        form = form.part(format!("transcript_{}", file_name), part);
+                    }
+                }
+            }
             if let Some(dump_events_file) = &record.dump_event_file {
                 if Path::new(&dump_events_file).exists() {
                     let file_name = Path::new(&dump_events_file)
@@ -590,7 +978,7 @@ impl CallRecordManager {
             let response_text = response.text().await.unwrap_or_default();
 
             if keep_media_copy.unwrap_or(false) {
-                for media in &record.recorder {
+                for media in record.recorder.iter().chain(&record.transcript) {
                     let p = Path::new(&media.path);
                     if p.exists() {
                         tokio::fs::remove_file(p).await.ok();
@@ -652,7 +1040,7 @@ impl CallRecordManager {
         // Upload media files if with_media is true
         if with_media.unwrap_or(false) {
             let mut media_files = vec![];
-            for media in &record.recorder {
+            for media in record.recorder.iter().chain(&record.transcript) {
                 if Path::new(&media.path).exists() {
                     let media_path = ObjectPath::from(formatter.format_media_path(record, media));
                     media_files.push((media.path.clone(), media_path));
@@ -695,7 +1083,7 @@ impl CallRecordManager {
         }
         // Optionally delete local media files if keep_media_copy is false
         if !keep_media_copy.unwrap_or(false) {
-            for media in &record.recorder {
+            for media in record.recorder.iter().chain(&record.transcript) {
                 let p = Path::new(&media.path);
                 if p.exists() {
                     tokio::fs::remove_file(p).await.ok();
@@ -716,12 +1104,26 @@ impl CallRecordManager {
         ))
     }
 
+    fn delivery(&self) -> CallRecordDelivery {
+        CallRecordDelivery {
+            cancel_token: self.cancel_token.clone(),
+            saver_fn: self.saver_fn.clone(),
+            config: self.config.clone(),
+            formatter: self.formatter.clone(),
+            spool: self.spool.clone(),
+            #[cfg(feature = "cdr-sql")]
+            history: self.history.clone(),
+        }
+    }
+
     pub async fn serve(&mut self) {
         let token = self.cancel_token.clone();
+        let delivery = self.delivery();
         info!("CallRecordManager serving");
         tokio::select! {
             _ = token.cancelled() => {}
             _ = self.recv_loop() => {}
+            _ = delivery.retry_loop() => {}
         }
         info!("CallRecordManager served");
     }
@@ -740,17 +1142,34 @@ impl CallRecordManager {
             }
 
             for record in buffer {
-                let cancel_token_ref = self.cancel_token.clone();
-                let save_fn_ref = self.saver_fn.clone();
-                let config_ref = self.config.clone();
-                let formatter_ref = self.formatter.clone();
+                let delivery = self.delivery();
+
+                // Analyzed records are saved once their analytics are done,
+                // without holding up the rest of the queue. They are spooled
+                // first, so a restart while analyzing does not lose them.
+                if let Some(analyzer) = self.analyzer.clone()
+                    && analytics::CallAnalyzer::transcript_file(&record).is_some()
+                {
+                    crate::spawn(async move {
+                        let mut record = record;
+                        let mut entry = delivery.enqueue(&record).await;
+                        analyzer.analyze(&mut record).await;
+                        if let (Some(spool), Some(entry)) =
+                            (delivery.spool.as_ref(), entry.as_mut())
+                            && let Err(e) = spool.update(entry, record.clone()).await
+                        {
+                            warn!("Failed to update spooled call record: {}", e);
+                        }
+                        delivery.index(&record).await;
+                        delivery.deliver(entry, record).await;
+                    });
+                    continue;
+                }
 
                 futures.push(async move {
-                    if let Err(e) =
-                        save_fn_ref(cancel_token_ref, formatter_ref, config_ref, record).await
-                    {
-                        warn!("Failed to save call record: {}", e);
-                    }
+                    let entry = delivery.enqueue(&record).await;
+                    delivery.index(&record).await;
+                    delivery.deliver(entry, record).await;
                 });
             }
             while let Some(_) = futures.next().await {}
@@ -0,0 +1,478 @@
+//! Durable delivery of call records. A record is written to
+//! `<root>/pending` together with links to its media before the first
+//! delivery attempt, and stays there until a backend accepts it. Records
+//! older than the configured max age move to `<root>/dead`.
+use super::CallRecord;
+use crate::config::CallRecordSpoolConfig;
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpoolState {
+    Pending,
+    Dead,
+}
+
+impl SpoolState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpoolState::Pending => "pending",
+            SpoolState::Dead => "dead",
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpoolEntry {
+    pub id: String,
+    pub enqueued_at: DateTime<Utc>,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    /// Spooled copies of the record's media, by original path
+    #[serde(default)]
+    pub media: HashMap<String, String>,
+    /// Indices of the fanout sinks still to deliver to, all when unset
+    #[serde(default)]
+    pub sinks: Option<Vec<usize>>,
+    pub record: CallRecord,
+}
+
+/// A spooled record without its content, as listed by the admin API
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpoolEntrySummary {
+    pub id: String,
+    pub call_id: String,
+    pub state: SpoolState,
+    pub enqueued_at: DateTime<Utc>,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+pub struct CallRecordSpool {
+    config: CallRecordSpoolConfig,
+    root: PathBuf,
+    /// Entries being delivered, skipped by retries and replays
+    in_flight: Mutex<HashSet<String>>,
+}
+
+impl CallRecordSpool {
+    pub fn new(config: &CallRecordSpoolConfig) -> Result<Self> {
+        let root = PathBuf::from(config.root());
+        for state in [SpoolState::Pending, SpoolState::Dead] {
+            std::fs::create_dir_all(root.join(state.as_str()))?;
+        }
+        Ok(Self {
+            config: config.clone(),
+            root,
+            in_flight: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn entry_path(&self, state: SpoolState, id: &str) -> PathBuf {
+        self.root.join(state.as_str()).join(format!("{}.json", id))
+    }
+
+    fn media_dir(&self, state: SpoolState, id: &str) -> PathBuf {
+        self.root.join(state.as_str()).join(format!("{}.media", id))
+    }
+
+    /// Persists `record` and its media, ahead of the first delivery attempt
+    /// made by the caller
+    pub async fn enqueue(&self, record: CallRecord) -> Result<SpoolEntry> {
+        let now = Utc::now();
+        let call_id: String = record
+            .call_id
+            .chars()
+            .map(|c| if is_id_char(c) { c } else { '_' })
+            .collect();
+        let mut entry = SpoolEntry {
+            id: format!("{}-{}", now.timestamp_millis(), call_id),
+            enqueued_at: now,
+            attempts: 0,
+            next_attempt_at: now + self.config.backoff(1),
+            last_error: None,
+            media: HashMap::new(),
+            sinks: None,
+            record,
+        };
+        self.in_flight.lock().unwrap().insert(entry.id.clone());
+        if let Err(e) = self.persist(&mut entry).await {
+            self.in_flight.lock().unwrap().remove(&entry.id);
+            return Err(e);
+        }
+        Ok(entry)
+    }
+
+    /// Rewrites a pending entry, after its record changed
+    pub async fn update(&self, entry: &mut SpoolEntry, record: CallRecord) -> Result<()> {
+        entry.record = record;
+        self.persist(entry).await
+    }
+
+    async fn persist(&self, entry: &mut SpoolEntry) -> Result<()> {
+        let media_dir = self.media_dir(SpoolState::Pending, &entry.id);
+        for path in media_paths(&entry.record) {
+            if entry.media.contains_key(&path) || !Path::new(&path).exists() {
+                continue;
+            }
+            let file_name = Path::new(&path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "media".to_string());
+            // Numbered directories keep the file names uploads are named after
+            let copy_dir = media_dir.join(entry.media.len().to_string());
+            let copy = copy_dir.join(file_name);
+            tokio::fs::create_dir_all(&copy_dir).await?;
+            if tokio::fs::hard_link(&path, &copy).await.is_err() {
+                tokio::fs::copy(&path, &copy).await?;
+            }
+            entry.media.insert(path, copy.to_string_lossy().to_string());
+        }
+        write_entry(&self.entry_path(SpoolState::Pending, &entry.id), entry).await
+    }
+
+    /// The record to deliver, pointing at the spooled copies of media whose
+    /// original is gone
+    pub fn delivery_record(&self, entry: &SpoolEntry) -> CallRecord {
+        let resolve = |path: &mut String| {
+            if !Path::new(path.as_str()).exists()
+                && let Some(copy) = entry.media.get(path.as_str())
+            {
+                *path = copy.clone();
+            }
+        };
+        let mut record = entry.record.clone();
+        for media in record
+            .recorder
+            .iter_mut()
+            .chain(record.transcript.iter_mut())
+        {
+            resolve(&mut media.path);
+        }
+        if let Some(path) = record.dump_event_file.as_mut() {
+            resolve(path);
+        }
+        record
+    }
+
+    /// Drops a delivered entry and its media
+    pub async fn complete(&self, entry: &SpoolEntry) {
+        tokio::fs::remove_file(self.entry_path(SpoolState::Pending, &entry.id))
+            .await
+            .ok();
+        tokio::fs::remove_dir_all(self.media_dir(SpoolState::Pending, &entry.id))
+            .await
+            .ok();
+        self.in_flight.lock().unwrap().remove(&entry.id);
+    }
+
+    /// Schedules the next attempt of a failed delivery, or moves the entry
+    /// to the dead-letter directory once it is older than the max age
+    pub async fn fail(&self, mut entry: SpoolEntry, error: &anyhow::Error) -> Result<SpoolState> {
+        let now = Utc::now();
+        entry.attempts += 1;
+        entry.last_error = Some(error.to_string());
+        let age = (now - entry.enqueued_at).to_std().unwrap_or_default();
+        let state = if age >= self.config.max_age() {
+            warn!(
+                id = entry.id,
+                call_id = entry.record.call_id,
+                attempts = entry.attempts,
+                "call record delivery expired, moving to dead letters: {}",
+                error
+            );
+            self.move_entry(&mut entry, SpoolState::Pending, SpoolState::Dead)
+                .await?;
+            SpoolState::Dead
+        } else {
+            entry.next_attempt_at = now + self.config.backoff(entry.attempts);
+            warn!(
+                id = entry.id,
+                call_id = entry.record.call_id,
+                attempts = entry.attempts,
+                next_attempt_at = %entry.next_attempt_at,
+                "call record delivery failed: {}",
+                error
+            );
+            write_entry(&self.entry_path(SpoolState::Pending, &entry.id), &entry).await?;
+            SpoolState::Pending
+        };
+        self.in_flight.lock().unwrap().remove(&entry.id);
+        Ok(state)
+    }
+
+    /// Pending entries whose next attempt is due, marked in flight
+    pub async fn take_due(&self) -> Vec<SpoolEntry> {
+        let now = Utc::now();
+        let mut due = Vec::new();
+        for entry in self.read_entries(SpoolState::Pending).await {
+            if entry.next_attempt_at > now {
+                continue;
+            }
+            if self.in_flight.lock().unwrap().insert(entry.id.clone()) {
+                due.push(entry);
+            }
+        }
+        due.sort_by_key(|entry| entry.next_attempt_at);
+        due
+    }
+
+    pub async fn list(&self, state: SpoolState) -> Vec<SpoolEntrySummary> {
+        let mut entries = self
+            .read_entries(state)
+            .await
+            .into_iter()
+            .map(|entry| SpoolEntrySummary {
+                id: entry.id,
+                call_id: entry.record.call_id,
+                state,
+                enqueued_at: entry.enqueued_at,
+                attempts: entry.attempts,
+                next_attempt_at: entry.next_attempt_at,
+                last_error: entry.last_error,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.enqueued_at);
+        entries
+    }
+
+    /// Queues a dead entry for delivery again, with a fresh max age, or
+    /// retries a pending one right away. `false` when `id` is unknown.
+    pub async fn replay(&self, id: &str) -> Result<bool> {
+        if !is_entry_id(id) {
+            bail!("invalid spool entry id {:?}", id);
+        }
+        if self.in_flight.lock().unwrap().contains(id) {
+            return Ok(true);
+        }
+        let now = Utc::now();
+        if let Ok(mut entry) = read_entry(&self.entry_path(SpoolState::Dead, id)).await {
+            entry.enqueued_at = now;
+            entry.next_attempt_at = now;
+            self.move_entry(&mut entry, SpoolState::Dead, SpoolState::Pending)
+                .await?;
+            info!(id, call_id = entry.record.call_id, "call record replayed");
+            return Ok(true);
+        }
+        let path = self.entry_path(SpoolState::Pending, id);
+        if let Ok(mut entry) = read_entry(&path).await {
+            entry.next_attempt_at = now;
+            write_entry(&path, &entry).await?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Replays every dead entry, returning their ids
+    pub async fn replay_dead(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for entry in self.read_entries(SpoolState::Dead).await {
+            if self.replay(&entry.id).await? {
+                ids.push(entry.id);
+            }
+        }
+        Ok(ids)
+    }
+
+    async fn move_entry(
+        &self,
+        entry: &mut SpoolEntry,
+        from: SpoolState,
+        to: SpoolState,
+    ) -> Result<()> {
+        let from_media = self.media_dir(from, &entry.id);
+        let to_media = self.media_dir(to, &entry.id);
+        if tokio::fs::rename(&from_media, &to_media).await.is_ok() {
+            let (from_media, to_media) = (
+                from_media.to_string_lossy().to_string(),
+                to_media.to_string_lossy().to_string(),
+            );
+            for copy in entry.media.values_mut() {
+                *copy = copy.replacen(&from_media, &to_media, 1);
+            }
+        }
+        write_entry(&self.entry_path(to, &entry.id), entry).await?;
+        tokio::fs::remove_file(self.entry_path(from, &entry.id)).await?;
+        Ok(())
+    }
+
+    async fn read_entries(&self, state: SpoolState) -> Vec<SpoolEntry> {
+        let mut entries = Vec::new();
+        let Ok(mut dir) = tokio::fs::read_dir(self.root.join(state.as_str())).await else {
+            return entries;
+        };
+        while let Ok(Some(item)) = dir.next_entry().await {
+            let path = item.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            match read_entry(&path).await {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!(path = %path.display(), "unreadable spooled call record: {}", e),
+            }
+        }
+        entries
+    }
+}
+
+fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
+}
+
+/// Whether `id` has the `<millis>-<sanitized call id>` form given by
+/// [`CallRecordSpool::enqueue`], so it names a file inside the spool
+pub fn is_entry_id(id: &str) -> bool {
+    let Some((millis, call_id)) = id.split_once('-') else {
+        return false;
+    };
+    !millis.is_empty()
+        && millis.chars().all(|c| c.is_ascii_digit())
+        && !call_id.is_empty()
+        && call_id.chars().all(is_id_char)
+}
+
+/// Local files referenced by the record
+fn media_paths(record: &CallRecord) -> Vec<String> {
+    record
+        .recorder
+        .iter()
+        .chain(&record.transcript)
+        .map(|media| media.path.clone())
+        .chain(record.dump_event_file.clone())
+        .collect()
+}
+
+async fn read_entry(path: &Path) -> Result<SpoolEntry> {
+    Ok(serde_json::from_slice(&tokio::fs::read(path).await?)?)
+}
+
+/// Writes through a temporary file, so a crash never leaves half an entry
+async fn write_entry(path: &Path, entry: &SpoolEntry) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec_pretty(entry)?).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callrecord::CallRecordMedia;
+
+    fn spool(root: &Path, max_age_secs: u64) -> CallRecordSpool {
+        CallRecordSpool::new(&CallRecordSpoolConfig {
+            root: Some(root.to_string_lossy().to_string()),
+            max_age_secs: Some(max_age_secs),
+            initial_backoff_secs: Some(0),
+            max_backoff_secs: Some(0),
+        })
+        .unwrap()
+    }
+
+    fn record(dir: &Path) -> CallRecord {
+        let path = dir.join("call 1.wav");
+        std::fs::write(&path, b"RIFF").unwrap();
+        CallRecord {
+            call_id: "call/1".to_string(),
+            recorder: vec![CallRecordMedia {
+                track_id: "call/1".to_string(),
+                path: path.to_string_lossy().to_string(),
+                size: 4,
+                extra: None,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = CallRecordSpoolConfig {
+            initial_backoff_secs: Some(5),
+            max_backoff_secs: Some(60),
+            ..Default::default()
+        };
+        let delays: Vec<u64> = (1..=6).map(|n| config.backoff(n).as_secs()).collect();
+        assert_eq!(delays, vec![5, 10, 20, 40, 60, 60]);
+        assert_eq!(config.backoff(100).as_secs(), 60);
+    }
+
+    #[tokio::test]
+    async fn test_retry_survives_restart_and_keeps_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("spool");
+        let record = record(dir.path());
+        let original = record.recorder[0].path.clone();
+
+        let entry = spool(&root, 3600).enqueue(record).await.unwrap();
+        assert!(entry.id.ends_with("-call_1"));
+        std::fs::remove_file(&original).unwrap();
+        let state = spool(&root, 3600)
+            .fail(entry, &anyhow::anyhow!("503"))
+            .await
+            .unwrap();
+        assert_eq!(state, SpoolState::Pending);
+
+        // A new process picks the entry up again
+        let spool = spool(&root, 3600);
+        let due = spool.take_due().await;
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].attempts, 1);
+        assert_eq!(due[0].last_error.as_deref(), Some("503"));
+        assert!(spool.take_due().await.is_empty(), "in flight");
+
+        let delivered = spool.delivery_record(&due[0]);
+        assert_ne!(delivered.recorder[0].path, original);
+        assert_eq!(std::fs::read(&delivered.recorder[0].path).unwrap(), b"RIFF");
+
+        spool.complete(&due[0]).await;
+        assert!(spool.list(SpoolState::Pending).await.is_empty());
+        assert!(!spool.media_dir(SpoolState::Pending, &due[0].id).exists());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(&dir.path().join("spool"), 0);
+        let entry = spool.enqueue(record(dir.path())).await.unwrap();
+        let id = entry.id.clone();
+        let state = spool
+            .fail(entry, &anyhow::anyhow!("timeout"))
+            .await
+            .unwrap();
+        assert_eq!(state, SpoolState::Dead);
+        assert!(spool.take_due().await.is_empty());
+
+        let dead = spool.list(SpoolState::Dead).await;
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].call_id, "call/1");
+
+        assert!(!spool.replay("1-unknown").await.unwrap());
+        assert!(is_entry_id(&id));
+        for id in ["../pending/1-x", "1-a/../../b", "x-1", "1-", "", "1-a\\b"] {
+            assert!(!is_entry_id(id), "{}", id);
+            assert!(spool.replay(id).await.is_err(), "{}", id);
+        }
+        assert_eq!(spool.replay_dead().await.unwrap(), vec![id.clone()]);
+        assert!(spool.list(SpoolState::Dead).await.is_empty());
+        let due = spool.take_due().await;
+        assert_eq!(due.len(), 1);
+        let copy = due[0].media.values().next().unwrap();
+        assert!(copy.contains("pending"));
+        assert!(Path::new(copy).exists());
+    }
+}
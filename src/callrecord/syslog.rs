@@ -0,0 +1,163 @@
+//! RFC 5424 syslog messages for call records. The key fields go into a
+//! structured data element, the full record into the message body. TCP
+//! uses octet-counting framing (RFC 6587).
+use super::CallRecord;
+use anyhow::{Result, anyhow};
+use std::time::Duration;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const SEVERITY_INFO: u8 = 6;
+const SEVERITY_WARNING: u8 = 4;
+/// Enterprise number reserved for documentation (RFC 5612)
+const SD_ID: &str = "cdr@32473";
+/// Beyond this a UDP datagram carries the structured data only
+const MAX_UDP_MESSAGE: usize = 65_000;
+
+fn escape_param(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// Printable US-ASCII header field, `-` when empty
+fn header_field(value: &str, max_len: usize) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value
+    }
+}
+
+pub fn format_message(
+    record: &CallRecord,
+    body: Option<&str>,
+    facility: u8,
+    hostname: &str,
+    app_name: &str,
+) -> String {
+    let severity = if (200..300).contains(&record.status_code) {
+        SEVERITY_INFO
+    } else {
+        SEVERITY_WARNING
+    };
+    let mut params = vec![
+        ("callId", record.call_id.clone()),
+        ("caller", record.caller.clone()),
+        ("callee", record.callee.clone()),
+        ("status", record.status_code.to_string()),
+        (
+            "duration",
+            (record.end_time - record.start_time)
+                .num_seconds()
+                .max(0)
+                .to_string(),
+        ),
+    ];
+    if let Some(reason) = &record.hangup_reason {
+        params.push(("hangupReason", reason.to_string()));
+    }
+    let structured_data = params
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape_param(value)))
+        .collect::<String>();
+    let mut message = format!(
+        "<{}>1 {} {} {} - cdr [{}{}]",
+        facility as u16 * 8 + severity as u16,
+        record
+            .end_time
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        header_field(hostname, 255),
+        header_field(app_name, 48),
+        SD_ID,
+        structured_data
+    );
+    if let Some(body) = body {
+        message.push_str(" \u{feff}");
+        message.push_str(body);
+    }
+    message
+}
+
+/// Sends a record to `udp://host:port` or `tcp://host:port`
+pub async fn send(
+    address: &str,
+    record: &CallRecord,
+    body: &str,
+    facility: Option<u8>,
+    hostname: Option<&str>,
+    app_name: Option<&str>,
+) -> Result<()> {
+    let facility = facility.unwrap_or(16);
+    if facility > 23 {
+        return Err(anyhow!("invalid syslog facility {}", facility));
+    }
+    let env_hostname = std::env::var("HOSTNAME").unwrap_or_default();
+    let hostname = hostname.unwrap_or(&env_hostname);
+    let app_name = app_name.unwrap_or("active-call");
+    let (scheme, target) = address.split_once("://").unwrap_or(("udp", address));
+
+    let exchange = async {
+        match scheme {
+            "udp" => {
+                let mut message = format_message(record, Some(body), facility, hostname, app_name);
+                if message.len() > MAX_UDP_MESSAGE {
+                    message = format_message(record, None, facility, hostname, app_name);
+                }
+                let socket = UdpSocket::bind(if target.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                })
+                .await?;
+                socket.send_to(message.as_bytes(), target).await?;
+            }
+            "tcp" => {
+                let message = format_message(record, Some(body), facility, hostname, app_name);
+                let mut stream = TcpStream::connect(target).await?;
+                stream
+                    .write_all(format!("{} {}", message.len(), message).as_bytes())
+                    .await?;
+                stream.shutdown().await?;
+            }
+            _ => return Err(anyhow!("unsupported syslog scheme {}", scheme)),
+        }
+        Ok(())
+    };
+    tokio::time::timeout(TIMEOUT, exchange)
+        .await
+        .map_err(|_| anyhow!("syslog send to {} timed out", address))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_format_message() {
+        let record = CallRecord {
+            call_id: "c1".to_string(),
+            caller: "sip:\"alice\"@example.com".to_string(),
+            callee: "sip:bob@example.com".to_string(),
+            status_code: 486,
+            start_time: Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap(),
+            end_time: Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 47).unwrap(),
+            ..Default::default()
+        };
+        let message = format_message(&record, Some("{}"), 16, "", "active call");
+        assert_eq!(
+            message,
+            "<132>1 2025-01-02T03:04:47.000Z - activecall - cdr [cdr@32473 callId=\"c1\" caller=\"sip:\\\"alice\\\"@example.com\" callee=\"sip:bob@example.com\" status=\"486\" duration=\"42\"] \u{feff}{}"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Speaker-attributed call transcript. The caller's side comes from final
+//! ASR results, the agent's from the text sent to TTS, timed by the
+//! server-side track and cut short where the caller barged in.
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptSpeaker {
+    User,
+    Agent,
+}
+
+impl TranscriptSpeaker {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TranscriptSpeaker::User => "user",
+            TranscriptSpeaker::Agent => "agent",
+        }
+    }
+}
+
+/// A single utterance, in milliseconds from the start of the recording
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptSegment {
+    pub speaker: TranscriptSpeaker,
+    pub start: u64,
+    pub end: u64,
+    pub text: String,
+    /// The agent was cut off by the caller; `text` is what was spoken
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+    Json,
+    Vtt,
+    Srt,
+}
+
+impl TranscriptFormat {
+    pub const ALL: [TranscriptFormat; 3] = [Self::Json, Self::Vtt, Self::Srt];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Json => "transcript.json",
+            TranscriptFormat::Vtt => "vtt",
+            TranscriptFormat::Srt => "srt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transcript {
+    pub call_id: String,
+    pub segments: Vec<TranscriptSegment>,
+}
+
+impl Transcript {
+    pub fn render(&self, format: TranscriptFormat) -> String {
+        match format {
+            TranscriptFormat::Json => serde_json::to_string_pretty(self).unwrap_or_default(),
+            TranscriptFormat::Vtt => self.to_webvtt(),
+            TranscriptFormat::Srt => self.to_srt(),
+        }
+    }
+
+    /// WebVTT cues with voice spans naming the speaker
+    pub fn to_webvtt(&self) -> String {
+        let mut out = String::from("WEBVTT\n");
+        for (i, segment) in self.segments.iter().enumerate() {
+            let text = segment
+                .text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            out.push_str(&format!(
+                "\n{}\n{} --> {}\n<v {}>{}\n",
+                i + 1,
+                timecode(segment.start, '.'),
+                timecode(segment.end, '.'),
+                segment.speaker.as_str(),
+                text
+            ));
+        }
+        out
+    }
+
+    pub fn to_srt(&self) -> String {
+        let mut out = String::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            out.push_str(&format!(
+                "{}\n{} --> {}\n{}: {}\n",
+                i + 1,
+                timecode(segment.start, ','),
+                timecode(segment.end, ','),
+                segment.speaker.as_str(),
+                segment.text
+            ));
+        }
+        out
+    }
+}
+
+fn timecode(ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+/// What the agent is saying, from its first TTS command to the end of the
+/// server-side track
+#[derive(Debug)]
+struct AgentTurn {
+    play_id: Option<String>,
+    text: String,
+    start: Option<u64>,
+}
+
+/// Segments as epoch ms, collected while the call runs
+#[derive(Debug, Default)]
+pub struct TranscriptBuilder {
+    segments: Vec<TranscriptSegment>,
+    agent: Option<AgentTurn>,
+}
+
+impl TranscriptBuilder {
+    pub fn user_final(&mut self, start: u64, end: u64, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        self.segments.push(TranscriptSegment {
+            speaker: TranscriptSpeaker::User,
+            start,
+            end: end.max(start),
+            text: text.to_string(),
+            interrupted: false,
+        });
+    }
+
+    /// Text sent to TTS; streaming chunks are joined as they come. A new
+    /// `play_id` cuts off the turn still playing.
+    pub fn agent_text(&mut self, play_id: Option<&str>, text: &str, streaming: bool, now: u64) {
+        if play_id.is_some()
+            && self
+                .agent
+                .as_ref()
+                .is_some_and(|turn| turn.play_id.as_deref() != play_id)
+            && let Some(turn) = self.agent.take()
+        {
+            self.push_agent(turn, now, true);
+        }
+        let turn = self.agent.get_or_insert_with(|| AgentTurn {
+            play_id: play_id.map(str::to_string),
+            text: String::new(),
+            start: None,
+        });
+        if !streaming && !turn.text.is_empty() && !turn.text.ends_with(char::is_whitespace) {
+            turn.text.push(' ');
+        }
+        turn.text.push_str(text);
+    }
+
+    /// The server-side track started playing
+    pub fn agent_started(&mut self, timestamp: u64) {
+        if let Some(turn) = &mut self.agent
+            && turn.start.is_none()
+        {
+            turn.start = Some(timestamp);
+        }
+    }
+
+    /// The server-side track of `play_id` ended
+    pub fn agent_ended(&mut self, timestamp: u64, play_id: Option<&str>) {
+        let Some(turn) = &self.agent else {
+            return;
+        };
+        if turn.start.is_none() || (play_id.is_some() && turn.play_id.as_deref() != play_id) {
+            return;
+        }
+        if let Some(turn) = self.agent.take() {
+            self.push_agent(turn, timestamp, false);
+        }
+    }
+
+    /// The caller barged in while `subtitle`, the text of the TTS command
+    /// playing, was spoken up to character `position`, or for `current` of
+    /// its `total` milliseconds when the provider gives no word timings
+    pub fn agent_interrupted(
+        &mut self,
+        timestamp: u64,
+        play_id: Option<&str>,
+        subtitle: Option<&str>,
+        position: Option<u32>,
+        current: u32,
+        total: u32,
+    ) {
+        let Some(turn) = &self.agent else {
+            return;
+        };
+        if turn.start.is_none() || (play_id.is_some() && turn.play_id.as_deref() != play_id) {
+            return;
+        }
+        let Some(mut turn) = self.agent.take() else {
+            return;
+        };
+        let (prefix, playing) = match subtitle.and_then(|s| turn.text.rfind(s).map(|i| (i, s))) {
+            Some((i, subtitle)) => (turn.text[..i].to_string(), subtitle.to_string()),
+            None => (String::new(), turn.text.clone()),
+        };
+        let spoken = match position {
+            Some(position) => playing.chars().take(position as usize).collect::<String>(),
+            None if total > 0 => {
+                let chars = playing.chars().count() as u64;
+                let spoken = chars * current.min(total) as u64 / total as u64;
+                playing.chars().take(spoken as usize).collect()
+            }
+            None => playing,
+        };
+        turn.text = prefix + &spoken;
+        self.push_agent(turn, timestamp, true);
+    }
+
+    fn push_agent(&mut self, turn: AgentTurn, end: u64, interrupted: bool) {
+        let text = turn.text.trim();
+        let Some(start) = turn.start else {
+            return;
+        };
+        if text.is_empty() {
+            return;
+        }
+        self.segments.push(TranscriptSegment {
+            speaker: TranscriptSpeaker::Agent,
+            start,
+            end: end.max(start),
+            text: text.to_string(),
+            interrupted,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty() && self.agent.as_ref().is_none_or(|turn| turn.start.is_none())
+    }
+
+    /// Closes the agent turn still playing at `end` and shifts every
+    /// segment to start from `origin`, both epoch ms
+    pub fn finish(&mut self, call_id: String, origin: u64, end: u64) -> Transcript {
+        if let Some(turn) = self.agent.take() {
+            self.push_agent(turn, end, false);
+        }
+        let mut segments: Vec<TranscriptSegment> = self
+            .segments
+            .drain(..)
+            .map(|mut segment| {
+                segment.start = segment.start.saturating_sub(origin);
+                segment.end = segment.end.saturating_sub(origin);
+                segment
+            })
+            .collect();
+        segments.sort_by_key(|segment| segment.start);
+        Transcript { call_id, segments }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_turns_and_barge_in() {
+        let mut builder = TranscriptBuilder::default();
+        assert!(builder.is_empty());
+        builder.agent_text(Some("p1"), "Hello, how can", true, 1000);
+        builder.agent_text(Some("p1"), " I help you?", true, 1050);
+        builder.agent_started(1100);
+        builder.agent_ended(3100, Some("p1"));
+        builder.user_final(3500, 4200, " I lost my card ");
+
+        builder.agent_text(Some("p2"), "Sorry to hear that.", false, 4300);
+        builder.agent_text(
+            Some("p2"),
+            "Let me block it for you right away.",
+            false,
+            4300,
+        );
+        builder.agent_started(4400);
+        // cut off at "Let me block"
+        builder.agent_interrupted(
+            6000,
+            Some("p2"),
+            Some("Let me block it for you right away."),
+            Some(12),
+            800,
+            2000,
+        );
+        // the interrupted track's end is ignored
+        builder.agent_ended(6020, Some("p2"));
+        builder.user_final(6100, 6900, "");
+
+        let transcript = builder.finish("call-1".to_string(), 1000, 7000);
+        let segments = &transcript.segments;
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].speaker, TranscriptSpeaker::Agent);
+        assert_eq!(segments[0].text, "Hello, how can I help you?");
+        assert_eq!((segments[0].start, segments[0].end), (100, 2100));
+        assert_eq!(segments[1].speaker, TranscriptSpeaker::User);
+        assert_eq!(segments[1].text, "I lost my card");
+        assert_eq!(segments[2].text, "Sorry to hear that. Let me block");
+        assert!(segments[2].interrupted);
+        assert_eq!(segments[2].end, 5000);
+    }
+
+    #[test]
+    fn test_interruption_without_word_timings() {
+        let mut builder = TranscriptBuilder::default();
+        builder.agent_text(None, "abcdefghij", false, 0);
+        builder.agent_started(0);
+        builder.agent_interrupted(500, None, None, None, 300, 1000);
+        let transcript = builder.finish("c".to_string(), 0, 1000);
+        assert_eq!(transcript.segments[0].text, "abc");
+
+        // never played, nothing to transcribe
+        builder.agent_text(None, "unplayed", false, 1000);
+        assert!(builder.is_empty());
+        // a late barge-in on the previous play keeps the queued turn
+        builder.agent_interrupted(1010, Some("old"), None, None, 0, 0);
+        builder.agent_started(1100);
+        assert!(!builder.is_empty());
+        builder.agent_text(Some("next"), "next", false, 1500);
+        let transcript = builder.finish("c".to_string(), 0, 2000);
+        assert_eq!(transcript.segments.len(), 1);
+        assert_eq!(transcript.segments[0].text, "unplayed");
+        assert!(transcript.segments[0].interrupted);
+    }
+
+    #[test]
+    fn test_render_subtitles() {
+        let transcript = Transcript {
+            call_id: "c".to_string(),
+            segments: vec![
+                TranscriptSegment {
+                    speaker: TranscriptSpeaker::Agent,
+                    start: 0,
+                    end: 1500,
+                    text: "Hi <there>".to_string(),
+                    interrupted: false,
+                },
+                TranscriptSegment {
+                    speaker: TranscriptSpeaker::User,
+                    start: 3_723_004,
+                    end: 3_724_000,
+                    text: "hello".to_string(),
+                    interrupted: true,
+                },
+            ],
+        };
+        assert_eq!(
+            transcript.to_webvtt(),
+            "WEBVTT\n\n1\n00:00:00.000 --> 00:00:01.500\n<v agent>Hi &lt;there&gt;\n\n2\n01:02:03.004 --> 01:02:04.000\n<v user>hello\n"
+        );
+        assert_eq!(
+            transcript.to_srt(),
+            "1\n00:00:00,000 --> 00:00:01,500\nagent: Hi <there>\n\n2\n01:02:03,004 --> 01:02:04,000\nuser: hello\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&transcript.render(TranscriptFormat::Json)).unwrap();
+        assert_eq!(json["callId"], "c");
+        assert_eq!(json["segments"][1]["interrupted"], true);
+        assert!(json["segments"][0].get("interrupted").is_none());
+    }
+}
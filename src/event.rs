@@ -22,6 +22,17 @@ impl From<&String> for Attendee {
     }
 }
 
+/// A recognized word, times in milliseconds since Unix epoch.
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AsrWord {
+    pub word: String,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub confidence: Option<f32>,
+}
+
 #[skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(
@@ -182,6 +193,8 @@ pub enum SessionEvent {
         confidence: Option<f32>,
         task_id: Option<String>,
         refer: Option<bool>,
+        /// Word timings, for providers that report them
+        words: Option<Vec<AsrWord>>,
     },
     AsrDelta {
         track_id: String,
@@ -195,6 +208,23 @@ pub enum SessionEvent {
         task_id: Option<String>,
         refer: Option<bool>,
     },
+    /// Paralinguistic tags of a recognized utterance, sent just before the
+    /// `AsrFinal` with the same index. Also sent alone for non-speech
+    /// sounds such as laughter or applause.
+    AsrTags {
+        track_id: String,
+        timestamp: u64,
+        index: u32,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+        /// Detected language, e.g. "en", "zh"
+        language: Option<String>,
+        /// happy, sad, angry, neutral, fearful, disgusted or surprised
+        emotion: Option<String>,
+        /// speech, bgm, applause, laughter, cry, sneeze, breath or cough
+        audio_event: Option<String>,
+        refer: Option<bool>,
+    },
     Metrics {
         timestamp: u64,
         key: String,
@@ -234,6 +264,45 @@ pub enum SessionEvent {
         sender: Option<String>,
         data: serde_json::Value,
     },
+    ConferenceJoin {
+        track_id: String,
+        timestamp: u64,
+        room: String,
+        participant_id: String,
+        /// Participants in the room after the join
+        participants: Vec<String>,
+    },
+    ConferenceLeave {
+        track_id: String,
+        timestamp: u64,
+        room: String,
+        participant_id: String,
+        /// Participants remaining in the room
+        participants: Vec<String>,
+    },
+    /// A supervisor attached to, changed mode on, or detached from a call.
+    /// Sent to both the supervised and the supervisor session.
+    Supervisor {
+        track_id: String,
+        timestamp: u64,
+        supervisor_id: String,
+        target_session_id: String,
+        /// None once the supervisor has detached
+        mode: Option<crate::call::SupervisorMode>,
+    },
+    /// The dialogue handler was paused or resumed on this call.
+    LlmState {
+        track_id: String,
+        timestamp: u64,
+        paused: bool,
+    },
+    /// Recording was paused or resumed on this call.
+    RecordingState {
+        track_id: String,
+        timestamp: u64,
+        paused: bool,
+        reason: Option<String>,
+    },
     RingbackState {
         track_id: String,
         timestamp: u64,
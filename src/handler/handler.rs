@@ -1,3 +1,4 @@
+use crate::callrecord::spool::SpoolState;
 use crate::media::cache;
 use crate::synthesis::{SynthesisEvent, SynthesisOption};
 use crate::{
@@ -7,7 +8,7 @@ use crate::{
         active_call::{ActiveCallGuard, CallParams},
     },
     handler::playbook,
-    playbook::{Playbook, PlaybookRunner},
+    playbook::{PLAYBOOK_DIR, Playbook, PlaybookRunner},
 };
 use crate::{event::SessionEvent, media::track::TrackConfig};
 use axum::{
@@ -45,7 +46,32 @@ pub fn call_router() -> Router<AppState> {
         .route("/kill/{id}", get(kill_active_call))
         .route("/events/{id}", get(stream_events))
         .route("/command/{id}", post(send_command))
-        .route("/precache", post(precache));
+        .route("/precache", post(precache))
+        .route("/cache", get(cache_stats))
+        .route(
+            "/cache/entries",
+            get(list_cache_entries).delete(purge_cache_prefix),
+        )
+        .route(
+            "/cache/entries/{key}",
+            get(get_cache_entry).delete(purge_cache_entry),
+        )
+        .route("/callrecord/spool", get(list_spooled_callrecords))
+        .route("/callrecord/spool/replay", post(replay_dead_callrecords))
+        .route(
+            "/callrecord/spool/{id}/replay",
+            post(replay_spooled_callrecord),
+        )
+        .route("/conference", get(list_conferences))
+        .route(
+            "/conference/{room}",
+            get(get_conference).delete(close_conference),
+        )
+        .route("/conference/{room}/tts", post(conference_tts))
+        .route(
+            "/conference/{room}/participants/{id}",
+            post(update_conference_participant),
+        );
     r
 }
 
@@ -68,6 +94,31 @@ pub fn playbook_router() -> Router<AppState> {
         .route("/api/records", get(playbook::list_records))
 }
 
+#[cfg(feature = "cdr-sql")]
+pub fn history_router() -> Router<AppState> {
+    use crate::handler::history;
+    Router::new()
+        .route("/api/calls", get(history::list_calls))
+        .route("/api/calls/export", get(history::export_calls))
+        .route("/api/calls/{id}", get(history::get_call))
+        .route(
+            "/api/calls/{id}/recordings/{index}",
+            get(history::get_call_recording),
+        )
+        .route(
+            "/api/calls/{id}/transcripts/{index}",
+            get(history::get_call_transcript),
+        )
+}
+
+#[cfg(feature = "offline")]
+pub fn voices_router() -> Router<AppState> {
+    use crate::handler::voices;
+    Router::new()
+        .route("/api/voices", get(voices::list_offline_voices))
+        .route("/api/voices/{name}", post(voices::clone_offline_voice))
+}
+
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
@@ -111,6 +162,7 @@ pub async fn call_handler_core(
     event_sender_to_client: tokio::sync::mpsc::UnboundedSender<crate::event::SessionEvent>,
     extras: Option<HashMap<String, serde_json::Value>>,
     playbook_name: Option<String>,
+    play_end_sender: Option<tokio::sync::mpsc::UnboundedSender<String>>,
 ) -> Option<HashMap<String, serde_json::Value>> {
     let _cancel_guard = cancel_token.clone().drop_guard();
     let track_config = TrackConfig::default();
@@ -128,6 +180,9 @@ pub async fn call_handler_core(
         extras,
         None,
     ));
+    if let Some(play_end_sender) = play_end_sender {
+        active_call.media_stream.hold_play_ends(play_end_sender);
+    }
 
     // Load playbook: prefer direct parameter, fall back to pending_playbooks
     // (pending_playbooks is used by the run_playbook HTTP endpoint)
@@ -141,7 +196,13 @@ pub async fn call_handler_core(
         });
         if let Some(name_or_content) = name_or_content {
             let playbook_result = if name_or_content.trim().starts_with("---") {
-                Playbook::parse(&name_or_content)
+                match Playbook::parse(&name_or_content) {
+                    Ok(mut playbook) => playbook
+                        .load_lexicon(std::path::Path::new(PLAYBOOK_DIR))
+                        .await
+                        .map(|_| playbook),
+                    Err(e) => Err(e),
+                }
             } else {
                 // If path already contains config/playbook, use it as-is; otherwise prepend it
                 let path = if name_or_content.starts_with("config/playbook/") {
@@ -179,6 +240,19 @@ pub async fn call_handler_core(
                         }
                     }
 
+                    // Name of the playbook file, for the call history
+                    if !name_or_content.trim().starts_with("---") {
+                        let name = name_or_content
+                            .strip_prefix("config/playbook/")
+                            .unwrap_or(&name_or_content);
+                        let mut state = active_call.call_state.write().await;
+                        state
+                            .extras
+                            .get_or_insert_default()
+                            .entry(crate::playbook::BUILTIN_PLAYBOOK.to_string())
+                            .or_insert_with(|| json!(name));
+                    }
+
                     match PlaybookRunner::new(playbook, active_call.clone()) {
                         Ok(runner) => {
                             crate::spawn(async move {
@@ -361,6 +435,12 @@ pub async fn call_handler(
         }
     }
 
+    if let (ActiveCallType::WebSocket, Some(protocol)) = (&call_type, params.protocol) {
+        return ws.on_upgrade(move |socket| {
+            crate::handler::media_streams::serve(socket, protocol, app_state, session_id, params)
+        });
+    }
+
     let resp = ws.on_upgrade(move |socket| async move {
         let (mut ws_sender, mut ws_receiver) = socket.split();
         let (audio_sender, audio_receiver) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
@@ -387,6 +467,7 @@ pub async fn call_handler(
                 event_sender_to_client,
                 None, // extras — not used for WebSocket calls
                 None, // playbook_name — falls back to pending_playbooks
+                None, // play ends are not held for WebSocket calls
             )
             .await;
         });
@@ -568,6 +649,116 @@ pub(crate) async fn send_command(
         .into_response()
 }
 
+pub(crate) async fn list_conferences(State(state): State<AppState>) -> Response {
+    Json(json!({ "conferences": state.conferences.list() })).into_response()
+}
+
+pub(crate) async fn get_conference(
+    Path(room): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.conferences.get(&room) {
+        Some(room) => Json(room.info()).into_response(),
+        None => (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(json!({ "status": "not_found", "room": room })),
+        )
+            .into_response(),
+    }
+}
+
+pub(crate) async fn close_conference(
+    Path(room): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    if state.conferences.close(&room) {
+        Json(json!({ "status": "closed", "room": room })).into_response()
+    } else {
+        (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(json!({ "status": "not_found", "room": room })),
+        )
+            .into_response()
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConferenceParticipantUpdate {
+    muted: Option<bool>,
+    gain: Option<f32>,
+}
+
+pub(crate) async fn update_conference_participant(
+    Path((room, id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Json(update): Json<ConferenceParticipantUpdate>,
+) -> Response {
+    let result = state
+        .conferences
+        .get(&room)
+        .ok_or_else(|| anyhow::anyhow!("conference not found: {}", room))
+        .and_then(|r| r.update_participant(&id, update.muted, update.gain));
+    match result {
+        Ok(_) => Json(json!({ "status": "updated", "room": room, "id": id })).into_response(),
+        Err(e) => (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(json!({ "status": "error", "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConferenceTtsRequest {
+    text: String,
+    speaker: Option<String>,
+    play_id: Option<String>,
+    participant_id: Option<String>,
+    option: SynthesisOption,
+}
+
+/// Play TTS into a conference room, to everyone or to `participantId` only.
+pub(crate) async fn conference_tts(
+    Path(room): Path<String>,
+    State(state): State<AppState>,
+    Json(request): Json<ConferenceTtsRequest>,
+) -> Response {
+    let conference = match state.conferences.get(&room) {
+        Some(conference) => conference,
+        None => {
+            return (
+                axum::http::StatusCode::NOT_FOUND,
+                Json(json!({ "status": "not_found", "room": room })),
+            )
+                .into_response();
+        }
+    };
+    let mut option = request.option;
+    option.check_default();
+    match conference
+        .play_tts(
+            state.stream_engine.clone(),
+            request.text,
+            request.speaker,
+            request.play_id,
+            option,
+            request.participant_id,
+        )
+        .await
+    {
+        Ok(play_id) => {
+            Json(json!({ "status": "playing", "room": room, "playId": play_id })).into_response()
+        }
+        Err(e) => (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(json!({ "status": "error", "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
 /// Pre-generate and cache TTS audio without an active call.
 ///
 /// Accepts a `Tts` command (same shape as `/command/{id}`): it synthesizes the
@@ -674,6 +865,138 @@ async fn precache_tts(
     Ok((cache_key, bytes, false))
 }
 
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct CacheQuery {
+    prefix: Option<String>,
+}
+
+fn cache_error(e: anyhow::Error) -> Response {
+    (
+        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+        Json(json!({ "status": "error", "error": e.to_string() })),
+    )
+        .into_response()
+}
+
+pub(crate) async fn cache_stats() -> Response {
+    match cache::cache_stats() {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => cache_error(e),
+    }
+}
+
+pub(crate) async fn list_cache_entries(Query(query): Query<CacheQuery>) -> Response {
+    let entries = cache::list_cache_entries(query.prefix.as_deref());
+    Json(json!({ "entries": entries })).into_response()
+}
+
+pub(crate) async fn get_cache_entry(Path(key): Path<String>) -> Response {
+    match cache::get_cache_entry(&key) {
+        Some(entry) => Json(entry).into_response(),
+        None => (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(json!({ "status": "not_found", "key": key })),
+        )
+            .into_response(),
+    }
+}
+
+pub(crate) async fn purge_cache_entry(Path(key): Path<String>) -> Response {
+    if cache::get_cache_entry(&key).is_none() {
+        return (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(json!({ "status": "not_found", "key": key })),
+        )
+            .into_response();
+    }
+    match cache::delete_from_cache(&key).await {
+        Ok(()) => Json(json!({ "status": "purged", "key": key })).into_response(),
+        Err(e) => cache_error(e),
+    }
+}
+
+/// Purging requires an explicit prefix, `?prefix=` purges the whole cache.
+pub(crate) async fn purge_cache_prefix(Query(query): Query<CacheQuery>) -> Response {
+    let Some(prefix) = query.prefix else {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(json!({ "status": "error", "error": "prefix is required" })),
+        )
+            .into_response();
+    };
+    match cache::purge_prefix(&prefix).await {
+        Ok(purged) => {
+            Json(json!({ "status": "purged", "prefix": prefix, "purged": purged })).into_response()
+        }
+        Err(e) => cache_error(e),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct SpoolQuery {
+    state: Option<SpoolState>,
+}
+
+fn spool_disabled() -> Response {
+    (
+        axum::http::StatusCode::NOT_FOUND,
+        Json(json!({ "status": "error", "error": "callrecord spool is not configured" })),
+    )
+        .into_response()
+}
+
+/// Spooled call records, both pending and dead unless `?state=` is given
+pub(crate) async fn list_spooled_callrecords(
+    State(state): State<AppState>,
+    Query(query): Query<SpoolQuery>,
+) -> Response {
+    let Some(spool) = state.callrecord_spool.as_ref() else {
+        return spool_disabled();
+    };
+    let mut entries = Vec::new();
+    for spool_state in [SpoolState::Pending, SpoolState::Dead] {
+        if query.state.is_none_or(|s| s == spool_state) {
+            entries.extend(spool.list(spool_state).await);
+        }
+    }
+    Json(json!({ "entries": entries })).into_response()
+}
+
+pub(crate) async fn replay_spooled_callrecord(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(spool) = state.callrecord_spool.as_ref() else {
+        return spool_disabled();
+    };
+    if !crate::callrecord::spool::is_entry_id(&id) {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(json!({ "status": "invalid_id", "id": id })),
+        )
+            .into_response();
+    }
+    match spool.replay(&id).await {
+        Ok(true) => Json(json!({ "status": "queued", "id": id })).into_response(),
+        Ok(false) => (
+            axum::http::StatusCode::NOT_FOUND,
+            Json(json!({ "status": "not_found", "id": id })),
+        )
+            .into_response(),
+        Err(e) => cache_error(e),
+    }
+}
+
+pub(crate) async fn replay_dead_callrecords(State(state): State<AppState>) -> Response {
+    let Some(spool) = state.callrecord_spool.as_ref() else {
+        return spool_disabled();
+    };
+    match spool.replay_dead().await {
+        Ok(ids) => Json(json!({ "status": "queued", "ids": ids })).into_response(),
+        Err(e) => cache_error(e),
+    }
+}
+
 trait IntoWsMessage {
     fn into_ws_message(self) -> Result<Message, serde_json::Error>;
 }
@@ -771,6 +1094,7 @@ mod tests {
             event_sender,
             Some(extras), // extras passed directly
             None,         // no playbook
+            None,
         )
         .await;
 
@@ -0,0 +1,168 @@
+use crate::{
+    app::AppState,
+    callrecord::{
+        CallRecordMedia,
+        history::CallHistoryQuery,
+        transcript::{Transcript, TranscriptFormat},
+    },
+};
+use axum::{
+    body::Body,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::json;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+fn error(status: StatusCode, error: impl std::fmt::Display) -> Response {
+    (
+        status,
+        Json(json!({ "status": "error", "error": error.to_string() })),
+    )
+        .into_response()
+}
+
+fn history_disabled() -> Response {
+    error(StatusCode::NOT_FOUND, "call history is not configured")
+}
+
+/// Past calls, newest first, a page at a time
+pub async fn list_calls(
+    State(state): State<AppState>,
+    Query(query): Query<CallHistoryQuery>,
+) -> Response {
+    let Some(history) = state.call_history.as_ref() else {
+        return history_disabled();
+    };
+    if let Err(e) = query.decode_cursor() {
+        return error(StatusCode::BAD_REQUEST, e);
+    }
+    match history.list(&query).await {
+        Ok(page) => Json(page).into_response(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// Every call matching the filters of `list_calls`, as CSV
+pub async fn export_calls(
+    State(state): State<AppState>,
+    Query(query): Query<CallHistoryQuery>,
+) -> Response {
+    let Some(history) = state.call_history.as_ref() else {
+        return history_disabled();
+    };
+    match history.export_csv(&query).await {
+        Ok(csv) => (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"calls.csv\"",
+                ),
+            ],
+            csv,
+        )
+            .into_response(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+fn media_links(call_id: &str, kind: &str, media: &[CallRecordMedia]) -> Vec<serde_json::Value> {
+    media
+        .iter()
+        .enumerate()
+        .map(|(index, media)| {
+            json!({
+                "trackId": media.track_id,
+                "size": media.size,
+                "extra": media.extra,
+                "url": format!("/api/calls/{}/{}/{}", urlencoding::encode(call_id), kind, index),
+            })
+        })
+        .collect()
+}
+
+/// The full record of a call with links to its recordings and transcript
+/// files, and the transcript itself while it is still on local disk
+pub async fn get_call(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let Some(history) = state.call_history.as_ref() else {
+        return history_disabled();
+    };
+    let record = match history.get(&id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "call not found"),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let json_format = json!(TranscriptFormat::Json);
+    let transcript = match record.transcript.iter().find(|media| {
+        media
+            .extra
+            .as_ref()
+            .is_some_and(|extra| extra.get("format") == Some(&json_format))
+    }) {
+        Some(media) => tokio::fs::read(&media.path)
+            .await
+            .ok()
+            .and_then(|content| serde_json::from_slice::<Transcript>(&content).ok()),
+        None => None,
+    };
+    Json(json!({
+        "recordings": media_links(&id, "recordings", &record.recorder),
+        "transcripts": media_links(&id, "transcripts", &record.transcript),
+        "transcript": transcript.map(|t| t.segments),
+        "record": record,
+    }))
+    .into_response()
+}
+
+async fn serve_media(
+    state: AppState,
+    id: String,
+    index: usize,
+    transcript: bool,
+    request: Request,
+) -> Response {
+    let Some(history) = state.call_history.as_ref() else {
+        return history_disabled();
+    };
+    let record = match history.get(&id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "call not found"),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let media = if transcript {
+        &record.transcript
+    } else {
+        &record.recorder
+    };
+    // Only files listed in the record are served
+    let Some(media) = media.get(index) else {
+        return error(StatusCode::NOT_FOUND, "media not found");
+    };
+    if !std::path::Path::new(&media.path).is_file() {
+        return error(StatusCode::NOT_FOUND, "media is not on local disk");
+    }
+    match ServeFile::new(&media.path).oneshot(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// A recording of the call, with range requests for seeking
+pub async fn get_call_recording(
+    State(state): State<AppState>,
+    Path((id, index)): Path<(String, usize)>,
+    request: Request,
+) -> Response {
+    serve_media(state, id, index, false, request).await
+}
+
+pub async fn get_call_transcript(
+    State(state): State<AppState>,
+    Path((id, index)): Path<(String, usize)>,
+    request: Request,
+) -> Response {
+    serve_media(state, id, index, true, request).await
+}
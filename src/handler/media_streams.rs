@@ -0,0 +1,485 @@
+//! Twilio Media Streams and Telnyx media streaming on `/call?protocol=...`.
+//!
+//! The platform's JSON envelope replaces our binary audio frames and
+//! commands: `start` places the call with the stream's codec, `media`
+//! carries base64 audio both ways, every finished play is sent as a `mark`
+//! named by its play id after its last media, and an interruption flushes
+//! the platform's buffer with `clear`. The play's `TrackEnd` is held back
+//! until the platform echoes its mark once played. Echoed marks and DTMF
+//! are emitted on the call's event bus.
+use super::handler::call_handler_core;
+use crate::{
+    CallOption,
+    app::AppState,
+    call::{ActiveCallType, Command, active_call::CallParams},
+    event::SessionEvent,
+};
+use anyhow::{Result, bail};
+use axum::extract::ws::{Message, WebSocket};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamProtocol {
+    Twilio,
+    Telnyx,
+}
+
+impl std::fmt::Display for StreamProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamProtocol::Twilio => write!(f, "twilio"),
+            StreamProtocol::Telnyx => write!(f, "telnyx"),
+        }
+    }
+}
+
+/// Messages from the platform. Twilio names fields in camelCase and
+/// Telnyx in snake_case, so both spellings are accepted.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum InboundMessage {
+    Start {
+        #[serde(default, rename = "streamSid", alias = "stream_id")]
+        stream_id: Option<String>,
+        start: StreamStart,
+    },
+    Media {
+        media: MediaPayload,
+    },
+    Mark {
+        mark: MarkName,
+    },
+    Dtmf {
+        dtmf: DtmfDigit,
+    },
+    Stop {},
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StreamStart {
+    #[serde(rename = "streamSid")]
+    stream_id: Option<String>,
+    #[serde(rename = "callSid", alias = "call_control_id")]
+    call_id: Option<String>,
+    #[serde(rename = "mediaFormat", alias = "media_format")]
+    media_format: MediaFormat,
+    #[serde(rename = "customParameters", alias = "custom_parameters")]
+    custom_parameters: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MediaFormat {
+    encoding: String,
+    #[serde(rename = "sampleRate", alias = "sample_rate")]
+    sample_rate: u32,
+}
+
+impl MediaFormat {
+    /// Our websocket codec for the stream encoding
+    fn codec(&self) -> Result<&'static str> {
+        match self.encoding.to_ascii_lowercase().as_str() {
+            "" | "audio/x-mulaw" | "pcmu" => Ok("pcmu"),
+            "audio/x-alaw" | "pcma" => Ok("pcma"),
+            "g722" => Ok("g722"),
+            _ => bail!(
+                "unsupported stream encoding {} at {}Hz",
+                self.encoding,
+                self.sample_rate
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaPayload {
+    #[serde(default)]
+    track: Option<String>,
+    payload: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MarkName {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DtmfDigit {
+    digit: String,
+}
+
+struct StreamSession {
+    protocol: StreamProtocol,
+    stream_id: Option<String>,
+}
+
+impl StreamSession {
+    fn message(&self, event: &str, body: Option<(&str, serde_json::Value)>) -> Message {
+        let mut message = json!({ "event": event });
+        if self.protocol == StreamProtocol::Twilio {
+            message["streamSid"] = json!(self.stream_id);
+        }
+        if let Some((key, value)) = body {
+            message[key] = value;
+        }
+        Message::Text(message.to_string().into())
+    }
+
+    /// Asks the platform to echo `play_id` back once the audio before it is played
+    fn mark(&self, play_id: &str) -> Message {
+        self.message("mark", Some(("mark", json!({ "name": play_id }))))
+    }
+
+    /// The platform message for an event of the call, if it has one
+    fn outbound(&self, event: SessionEvent) -> Option<Message> {
+        match event {
+            SessionEvent::Binary { data, .. } => Some(self.message(
+                "media",
+                Some(("media", json!({ "payload": STANDARD.encode(data) }))),
+            )),
+            SessionEvent::Interruption { .. } => Some(self.message("clear", None)),
+            SessionEvent::Ping { timestamp, payload } => {
+                let payload = payload.unwrap_or_else(|| timestamp.to_string());
+                Some(Message::Ping(payload.into()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Audio of the caller, as the platform may also stream our own audio back
+fn is_inbound(media: &MediaPayload) -> bool {
+    media
+        .track
+        .as_deref()
+        .is_none_or(|track| track.starts_with("inbound"))
+}
+
+/// Emits an event on the bus of a running call
+fn emit(app_state: &AppState, session_id: &str, event: SessionEvent) {
+    let call = app_state
+        .active_calls
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .cloned();
+    if let Some(call) = call {
+        call.event_sender.send(event).ok();
+    }
+}
+
+/// Completes the play the platform echoed the mark of
+fn release_play_end(app_state: &AppState, session_id: &str, play_id: &str) {
+    let call = app_state
+        .active_calls
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .cloned();
+    if let Some(call) = call
+        && !call.media_stream.release_play_end(play_id)
+    {
+        debug!(session_id, play_id, "mark of no held play");
+    }
+}
+
+async fn wait_for_start(
+    protocol: StreamProtocol,
+    socket: &mut futures::stream::SplitStream<WebSocket>,
+) -> Option<(Option<String>, StreamStart)> {
+    while let Some(Ok(message)) = socket.next().await {
+        match message {
+            Message::Text(text) => match serde_json::from_str::<InboundMessage>(&text) {
+                Ok(InboundMessage::Start { stream_id, start }) => return Some((stream_id, start)),
+                Ok(_) => {}
+                Err(e) => warn!(%protocol, %text, "invalid stream message: {}", e),
+            },
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    None
+}
+
+pub(crate) async fn serve(
+    socket: WebSocket,
+    protocol: StreamProtocol,
+    app_state: AppState,
+    session_id: String,
+    params: CallParams,
+) {
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+    let Some((stream_id, mut start)) = wait_for_start(protocol, &mut ws_receiver).await else {
+        info!(session_id, %protocol, "stream closed before start");
+        return;
+    };
+    let codec = match start.media_format.codec() {
+        Ok(codec) => codec,
+        Err(e) => {
+            warn!(session_id, %protocol, "{}", e);
+            ws_sender.close().await.ok();
+            return;
+        }
+    };
+    let session = StreamSession {
+        protocol,
+        stream_id: stream_id.or(start.stream_id),
+    };
+    info!(session_id, %protocol, stream_id = ?session.stream_id, codec, "media stream started");
+
+    let playbook = start.custom_parameters.remove("playbook");
+    let mut extras: HashMap<String, serde_json::Value> = start
+        .custom_parameters
+        .into_iter()
+        .map(|(k, v)| (k, v.into()))
+        .collect();
+    extras.insert("stream_protocol".to_string(), protocol.to_string().into());
+    if let Some(stream_id) = &session.stream_id {
+        extras.insert("stream_id".to_string(), stream_id.clone().into());
+    }
+    if let Some(call_id) = start.call_id {
+        extras.insert("provider_call_id".to_string(), call_id.into());
+    }
+
+    let (audio_sender, audio_receiver) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
+    let (command_sender, command_receiver) = tokio::sync::mpsc::unbounded_channel::<Command>();
+    let (event_sender_to_client, mut event_receiver_from_core) =
+        tokio::sync::mpsc::unbounded_channel::<SessionEvent>();
+    let (play_end_sender, mut play_end_receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
+    let cancel_token = CancellationToken::new();
+
+    let session_id_clone = session_id.clone();
+    let app_state_clone = app_state.clone();
+    let cancel_token_clone = cancel_token.clone();
+    crate::spawn(async move {
+        call_handler_core(
+            ActiveCallType::WebSocket,
+            session_id_clone,
+            app_state_clone,
+            cancel_token_clone,
+            audio_receiver,
+            params.server_side_track,
+            params.dump_events.unwrap_or(true),
+            params.ping_interval.unwrap_or(20).into(),
+            command_receiver,
+            event_sender_to_client,
+            Some(extras),
+            playbook,
+            Some(play_end_sender),
+        )
+        .await;
+    });
+    let option = CallOption {
+        codec: Some(codec.to_string()),
+        ..Default::default()
+    };
+    command_sender.send(Command::Invite { option }).ok();
+
+    let recv_from_ws_loop = async {
+        while let Some(Ok(message)) = ws_receiver.next().await {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => {
+                    info!(session_id, %protocol, "media stream closed by platform");
+                    break;
+                }
+                _ => continue,
+            };
+            match serde_json::from_str::<InboundMessage>(&text) {
+                Ok(InboundMessage::Media { media }) if is_inbound(&media) => {
+                    match STANDARD.decode(&media.payload) {
+                        Ok(audio) => {
+                            audio_sender.send(audio.into()).ok();
+                        }
+                        Err(e) => warn!(session_id, "invalid media payload: {}", e),
+                    }
+                }
+                Ok(InboundMessage::Mark { mark }) => {
+                    debug!(session_id, mark = mark.name, "mark played");
+                    release_play_end(&app_state, &session_id, &mark.name);
+                    let event = SessionEvent::Other {
+                        track_id: session_id.clone(),
+                        timestamp: crate::media::get_timestamp(),
+                        sender: protocol.to_string(),
+                        extra: Some(HashMap::from([("mark".to_string(), mark.name)])),
+                    };
+                    emit(&app_state, &session_id, event);
+                }
+                Ok(InboundMessage::Dtmf { dtmf }) => {
+                    let event = SessionEvent::Dtmf {
+                        track_id: session_id.clone(),
+                        timestamp: crate::media::get_timestamp(),
+                        digit: dtmf.digit,
+                        refer: None,
+                    };
+                    emit(&app_state, &session_id, event);
+                }
+                Ok(InboundMessage::Stop {}) => {
+                    info!(session_id, %protocol, "media stream stopped");
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => warn!(session_id, %text, "invalid stream message: {}", e),
+            }
+        }
+    };
+
+    let send_to_ws_loop = async {
+        loop {
+            let messages = select! {
+                biased;
+                event = event_receiver_from_core.recv() => match event {
+                    Some(event) => session.outbound(event).into_iter().collect(),
+                    None => break,
+                },
+                Some(play_id) = play_end_receiver.recv() => {
+                    // Media already queued for the play goes out before its mark
+                    let mut messages: Vec<Message> =
+                        std::iter::from_fn(|| event_receiver_from_core.try_recv().ok())
+                            .filter_map(|event| session.outbound(event))
+                            .collect();
+                    messages.push(session.mark(&play_id));
+                    messages
+                }
+            };
+            for message in messages {
+                if ws_sender.send(message).await.is_err() {
+                    info!(session_id, "WebSocket send failed, closing");
+                    return;
+                }
+            }
+        }
+    };
+
+    select! {
+        _ = recv_from_ws_loop => {},
+        _ = send_to_ws_loop => {},
+    }
+
+    cancel_token.cancel();
+    ws_sender.flush().await.ok();
+    ws_sender.close().await.ok();
+    debug!(session_id, "media stream connection closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(message: Option<Message>) -> serde_json::Value {
+        match message {
+            Some(Message::Text(text)) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_start() {
+        let twilio = r#"{"event":"start","sequenceNumber":"1","streamSid":"MZ1","start":{
+            "accountSid":"AC1","streamSid":"MZ1","callSid":"CA1","tracks":["inbound"],
+            "mediaFormat":{"encoding":"audio/x-mulaw","sampleRate":8000,"channels":1},
+            "customParameters":{"playbook":"demo.md"}}}"#;
+        let Ok(InboundMessage::Start { stream_id, start }) = serde_json::from_str(twilio) else {
+            panic!("not a start message");
+        };
+        assert_eq!(stream_id.as_deref(), Some("MZ1"));
+        assert_eq!(start.call_id.as_deref(), Some("CA1"));
+        assert_eq!(start.media_format.codec().unwrap(), "pcmu");
+        assert_eq!(start.custom_parameters["playbook"], "demo.md");
+
+        let telnyx = r#"{"event":"start","sequence_number":"1","stream_id":"st-1","start":{
+            "call_control_id":"v3:abc","media_format":{"encoding":"PCMA","sample_rate":8000,"channels":1}}}"#;
+        let Ok(InboundMessage::Start { stream_id, start }) = serde_json::from_str(telnyx) else {
+            panic!("not a start message");
+        };
+        assert_eq!(stream_id.as_deref(), Some("st-1"));
+        assert_eq!(start.call_id.as_deref(), Some("v3:abc"));
+        assert_eq!(start.media_format.codec().unwrap(), "pcma");
+
+        let opus = MediaFormat {
+            encoding: "OPUS".to_string(),
+            sample_rate: 16000,
+        };
+        assert!(opus.codec().is_err());
+    }
+
+    #[test]
+    fn test_parse_media() {
+        let media =
+            r#"{"event":"media","streamSid":"MZ1","media":{"track":"outbound","payload":"AAE="}}"#;
+        let Ok(InboundMessage::Media { media }) = serde_json::from_str(media) else {
+            panic!("not a media message");
+        };
+        assert!(!is_inbound(&media));
+        assert_eq!(STANDARD.decode(media.payload).unwrap(), vec![0, 1]);
+        assert!(matches!(
+            serde_json::from_str(r#"{"event":"connected","protocol":"Call","version":"1.0.0"}"#),
+            Ok(InboundMessage::Other)
+        ));
+    }
+
+    #[test]
+    fn test_outbound() {
+        let twilio = StreamSession {
+            protocol: StreamProtocol::Twilio,
+            stream_id: Some("MZ1".to_string()),
+        };
+        let media = twilio.outbound(SessionEvent::Binary {
+            track_id: "t".to_string(),
+            timestamp: 0,
+            data: vec![0xff, 0x7f],
+        });
+        assert_eq!(
+            text(media),
+            json!({"event": "media", "streamSid": "MZ1", "media": {"payload": "/38="}})
+        );
+        // Marks come from the held play ends, not from TrackEnd
+        let track_end = twilio.outbound(SessionEvent::TrackEnd {
+            track_id: "t".to_string(),
+            timestamp: 0,
+            duration: 0,
+            ssrc: 0,
+            play_id: Some("p1".to_string()),
+        });
+        assert!(track_end.is_none());
+        assert_eq!(
+            text(Some(twilio.mark("p1"))),
+            json!({"event": "mark", "streamSid": "MZ1", "mark": {"name": "p1"}})
+        );
+
+        let telnyx = StreamSession {
+            protocol: StreamProtocol::Telnyx,
+            stream_id: Some("st-1".to_string()),
+        };
+        let clear = telnyx.outbound(SessionEvent::Interruption {
+            track_id: "t".to_string(),
+            timestamp: 0,
+            play_id: None,
+            subtitle: None,
+            position: None,
+            total_duration: 0,
+            current: 0,
+        });
+        assert_eq!(text(clear), json!({"event": "clear"}));
+        let track_end = telnyx.outbound(SessionEvent::TrackEnd {
+            track_id: "t".to_string(),
+            timestamp: 0,
+            duration: 0,
+            ssrc: 0,
+            play_id: None,
+        });
+        assert!(track_end.is_none());
+    }
+}
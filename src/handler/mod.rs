@@ -1,6 +1,15 @@
 pub mod handler;
+#[cfg(feature = "cdr-sql")]
+pub mod history;
+pub mod media_streams;
 pub mod peer;
 pub mod playbook;
+#[cfg(feature = "offline")]
+pub mod voices;
 pub use handler::call_router;
+#[cfg(feature = "cdr-sql")]
+pub use handler::history_router;
 pub use handler::iceservers_router;
 pub use handler::playbook_router;
+#[cfg(feature = "offline")]
+pub use handler::voices_router;
@@ -165,6 +165,7 @@ fn tungstenite_to_axum(m: TungsteniteMessage) -> Option<AxumMessage> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::handler::media_streams::StreamProtocol;
 
     #[test]
     fn test_peer_ws_endpoint_normalization() {
@@ -196,12 +197,14 @@ mod tests {
             server_side_track: Some("t.1".to_string()),
             forward: None,
             visited: None,
+            protocol: Some(StreamProtocol::Twilio),
         };
         let q = params.to_forward_query();
         assert!(q.contains("id=s.a%2Fb%20c"));
         assert!(q.contains("dump=true"));
         assert!(q.contains("ping=30"));
         assert!(q.contains("server_side_track=t.1"));
+        assert!(q.contains("protocol=twilio"));
         assert!(q.contains("forward=true"));
         assert!(!q.contains("visited="));
     }
@@ -0,0 +1,133 @@
+use crate::offline::{
+    get_offline_models,
+    supertonic::{VoiceMetadata, list_voices},
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::Cursor;
+
+fn error(status: StatusCode, error: impl std::fmt::Display) -> Response {
+    (
+        status,
+        Json(json!({ "status": "error", "error": error.to_string() })),
+    )
+        .into_response()
+}
+
+/// Offline TTS voices, selectable as the `speaker` of a Supertonic option
+pub async fn list_offline_voices() -> Response {
+    let Some(models) = get_offline_models() else {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "offline models not initialized",
+        );
+    };
+    let dir = models.config().supertonic_voice_styles_dir();
+    let voices = match tokio::task::spawn_blocking(move || list_voices(&dir)).await {
+        Ok(Ok(voices)) => voices,
+        Ok(Err(e)) => return error(StatusCode::INTERNAL_SERVER_ERROR, e),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    };
+    let cloning = models.config().supertonic_style_encoder_path().exists();
+    Json(json!({ "voices": voices, "cloning": cloning })).into_response()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CloneVoiceQuery {
+    description: Option<String>,
+    gender: Option<String>,
+    language: Option<String>,
+}
+
+/// Derives voice `name` from a WAV of reference speech sent as the body
+pub async fn clone_offline_voice(
+    Path(name): Path<String>,
+    Query(query): Query<CloneVoiceQuery>,
+    body: Bytes,
+) -> Response {
+    let Some(models) = get_offline_models() else {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "offline models not initialized",
+        );
+    };
+    let (samples, sample_rate) = match read_reference(&body) {
+        Ok(audio) => audio,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    let tts = match models.get_supertonic().await {
+        Ok(tts) => tts,
+        Err(e) => return error(StatusCode::SERVICE_UNAVAILABLE, e),
+    };
+    let metadata = VoiceMetadata {
+        description: query.description,
+        gender: query.gender,
+        language: query.language,
+        cloned: true,
+    };
+    let result = tokio::task::spawn_blocking(move || {
+        let mut guard = tts.blocking_write();
+        let tts = guard
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("Supertonic TTS not initialized"))?;
+        tts.clone_voice(&name, &samples, sample_rate, metadata)
+    })
+    .await;
+    match result {
+        Ok(Ok(voice)) => Json(json!({ "status": "ok", "voice": voice })).into_response(),
+        Ok(Err(e)) => error(StatusCode::UNPROCESSABLE_ENTITY, e),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// Mono 16-bit samples of a WAV body, channels averaged
+fn read_reference(body: &[u8]) -> anyhow::Result<(Vec<i16>, u32)> {
+    let mut reader = hound::WavReader::new(Cursor::new(body))?;
+    let spec = reader.spec();
+    let samples: Vec<i16> = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 16) => reader.samples::<i16>().collect::<Result<_, _>>()?,
+        (hound::SampleFormat::Float, 32) => reader
+            .samples::<f32>()
+            .map(|s| s.map(|s| (s.clamp(-1.0, 1.0) * 32767.0) as i16))
+            .collect::<Result<_, _>>()?,
+        (format, bits) => anyhow::bail!("unsupported WAV format {:?} {}-bit", format, bits),
+    };
+    let channels = spec.channels.max(1) as usize;
+    let samples = samples
+        .chunks(channels)
+        .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / channels as i32) as i16)
+        .collect();
+    Ok((samples, spec.sample_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_stereo_reference() {
+        let mut body = Cursor::new(Vec::new());
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 24000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::new(&mut body, spec).unwrap();
+        for sample in [100i16, 300, -200, -400] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let (samples, sample_rate) = read_reference(body.get_ref()).unwrap();
+        assert_eq!((samples, sample_rate), (vec![200, -300], 24000));
+        assert!(read_reference(b"not a wav").is_err());
+    }
+}
@@ -6,8 +6,9 @@ use std::collections::HashMap;
 
 use crate::{
     media::{
-        agc::AGCOption, ambiance::AmbianceOption, recorder::RecorderOption,
-        track::media_pass::MediaPassOption, vad::VADOption,
+        agc::AGCOption, ambiance::AmbianceOption, jitter_buffer::JitterBufferOption,
+        recorder::RecorderOption, redaction::RedactionOption, track::media_pass::MediaPassOption,
+        vad::VADOption,
     },
     synthesis::SynthesisOption,
     transcription::TranscriptionOption,
@@ -72,6 +73,8 @@ pub struct CallOption {
     pub subscribe: Option<bool>,
     pub enable_ice_lite: Option<bool>,
     pub ringback_detection: Option<RingbackDetectionOption>,
+    pub jitter_buffer: Option<JitterBufferOption>,
+    pub redaction: Option<RedactionOption>,
 }
 
 impl Default for CallOption {
@@ -99,6 +102,8 @@ impl Default for CallOption {
             subscribe: None,
             enable_ice_lite: None,
             ringback_detection: None,
+            jitter_buffer: None,
+            redaction: None,
         }
     }
 }
@@ -150,7 +150,7 @@ impl MainBuilder {
 
             let model = ModelType::from_str(model_type).ok_or_else(|| {
                 anyhow::anyhow!(
-                    "Unknown model type: {}. Use: sensevoice, supertonic, or all",
+                    "Unknown model type: {}. Use: sensevoice, supertonic, supertonic-voices, whisper[-<variant>], or all",
                     model_type
                 )
             })?;
@@ -293,6 +293,7 @@ impl MainBuilder {
                     event_sender,
                     None,     // extras
                     playbook, // playbook_name — passed directly
+                    None,
                 )
                 .await;
             });
@@ -331,10 +332,14 @@ impl MainBuilder {
     fn default_router() -> Router<Arc<AppStateInner>> {
         let router = crate::handler::call_router()
             .merge(crate::handler::playbook_router())
-            .merge(crate::handler::iceservers_router())
-            .route("/", get(index))
-            .nest_service("/static", ServeDir::new("static"));
+            .merge(crate::handler::iceservers_router());
+        #[cfg(feature = "cdr-sql")]
+        let router = router.merge(crate::handler::history_router());
+        #[cfg(feature = "offline")]
+        let router = router.merge(crate::handler::voices_router());
         router
+            .route("/", get(index))
+            .nest_service("/static", ServeDir::new("static"))
     }
 
     async fn serve(
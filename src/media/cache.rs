@@ -1,29 +1,187 @@
 use anyhow::{Result, anyhow};
 use bytes::BytesMut;
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
+use serde::Serialize;
 use sha2::{Digest, Sha256};
-use std::sync::RwLock;
-use std::{
-    io::{IoSlice, SeekFrom},
-    path::PathBuf,
-};
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use std::{io::SeekFrom, path::Path, path::PathBuf};
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::{fs::create_dir_all, io::AsyncWriteExt};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 // Default cache directory
 static DEFAULT_CACHE_DIR: &str = "/tmp/mediacache";
+const CACHE_EXTENSION: &str = "pcm";
+const TEMP_EXTENSION: &str = "tmp";
 
 // Global cache configuration
 static CACHE_CONFIG: Lazy<RwLock<CacheConfig>> = Lazy::new(|| {
     RwLock::new(CacheConfig {
         cache_dir: PathBuf::from(DEFAULT_CACHE_DIR),
+        max_bytes: None,
+        max_age: None,
     })
 });
 
+// Entries of the cache directory, with access times for LRU eviction
+static CACHE_INDEX: Lazy<Mutex<CacheIndex>> = Lazy::new(|| Mutex::new(CacheIndex::default()));
+
 #[derive(Debug, Clone)]
 pub struct CacheConfig {
     pub cache_dir: PathBuf,
+    /// Total size the cache is trimmed to, unbounded when None
+    pub max_bytes: Option<u64>,
+    /// Entries older than this are dropped, kept forever when None
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntry {
+    pub key: String,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+    pub last_access: DateTime<Utc>,
+}
+
+impl CacheEntry {
+    fn from_metadata(key: &str, metadata: &std::fs::Metadata) -> Self {
+        let created_at = metadata
+            .modified()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+        // atime is not maintained on every filesystem, never go below mtime
+        let last_access = metadata
+            .accessed()
+            .map(DateTime::<Utc>::from)
+            .unwrap_or(created_at)
+            .max(created_at);
+        Self {
+            key: key.to_string(),
+            size: metadata.len(),
+            created_at,
+            last_access,
+        }
+    }
+
+    fn is_expired(&self, max_age: Option<Duration>, now: DateTime<Utc>) -> bool {
+        max_age.is_some_and(|max_age| {
+            (now - self.created_at)
+                .to_std()
+                .is_ok_and(|age| age >= max_age)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: usize,
+    pub total_bytes: u64,
+    pub max_bytes: Option<u64>,
+    pub max_age_secs: Option<u64>,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    total_bytes: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    expirations: u64,
+}
+
+impl CacheIndex {
+    fn insert(&mut self, entry: CacheEntry) {
+        self.total_bytes += entry.size;
+        if let Some(old) = self.entries.insert(entry.key.clone(), entry) {
+            self.total_bytes -= old.size;
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.total_bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn touch(&mut self, key: &str, now: DateTime<Utc>) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_access = now;
+        }
+    }
+
+    /// Drop expired entries, then the least recently used ones until the
+    /// cache fits `max_bytes`. Returns the keys whose files must be deleted.
+    fn evict(
+        &mut self,
+        max_bytes: Option<u64>,
+        max_age: Option<Duration>,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let mut victims: Vec<String> = self
+            .entries
+            .values()
+            .filter(|entry| entry.is_expired(max_age, now))
+            .map(|entry| entry.key.clone())
+            .collect();
+        for key in &victims {
+            self.remove(key);
+        }
+        self.expirations += victims.len() as u64;
+
+        if let Some(max_bytes) = max_bytes
+            && self.total_bytes > max_bytes
+        {
+            let mut by_access: Vec<(DateTime<Utc>, String)> = self
+                .entries
+                .values()
+                .map(|entry| (entry.last_access, entry.key.clone()))
+                .collect();
+            by_access.sort();
+            for (_, key) in by_access {
+                if self.total_bytes <= max_bytes {
+                    break;
+                }
+                self.remove(&key);
+                self.evictions += 1;
+                victims.push(key);
+            }
+        }
+        victims
+    }
+
+    fn stats(&self, config: &CacheConfig) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            total_bytes: self.total_bytes,
+            max_bytes: config.max_bytes,
+            max_age_secs: config.max_age.map(|age| age.as_secs()),
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            expirations: self.expirations,
+        }
+    }
+}
+
+fn cache_config() -> Result<CacheConfig> {
+    let config = CACHE_CONFIG
+        .read()
+        .map_err(|_| anyhow!("Failed to acquire read lock"))?;
+    Ok(config.clone())
+}
+
+fn lock_index() -> std::sync::MutexGuard<'static, CacheIndex> {
+    CACHE_INDEX.lock().unwrap_or_else(|e| e.into_inner())
 }
 
 /// Set the cache directory for the media cache
@@ -32,10 +190,26 @@ pub fn set_cache_dir(path: &str) -> Result<()> {
     let mut config = CACHE_CONFIG
         .write()
         .map_err(|_| anyhow!("Failed to acquire write lock"))?;
+    if config.cache_dir != path {
+        // The index describes the old directory
+        let mut index = lock_index();
+        index.entries.clear();
+        index.total_bytes = 0;
+    }
     config.cache_dir = path;
     Ok(())
 }
 
+/// Set the size and age limits enforced on every store and by [`sweep`]
+pub fn set_cache_limits(max_bytes: Option<u64>, max_age: Option<Duration>) -> Result<()> {
+    let mut config = CACHE_CONFIG
+        .write()
+        .map_err(|_| anyhow!("Failed to acquire write lock"))?;
+    config.max_bytes = max_bytes;
+    config.max_age = max_age;
+    Ok(())
+}
+
 /// Get the current cache directory
 pub fn get_cache_dir() -> Result<PathBuf> {
     let config = CACHE_CONFIG
@@ -85,40 +259,222 @@ pub fn generate_cache_key(
 
 /// Get the full path for a cached file
 pub fn get_cache_path(key: &str) -> Result<PathBuf> {
+    if key.is_empty() || key == ".." || key.contains(['/', '\\']) {
+        return Err(anyhow!("cache: invalid key: {:?}", key));
+    }
     let cache_dir = get_cache_dir()?;
-    Ok(cache_dir.join(key).with_extension("pcm"))
+    Ok(cache_dir.join(format!("{}.{}", key, CACHE_EXTENSION)))
 }
 
-/// Check if a file exists in the cache
+/// Check if a file exists in the cache, counting the lookup as a hit or miss
 pub async fn is_cached(key: &str) -> Result<bool> {
     let path = get_cache_path(key)?;
-    Ok(tokio::fs::try_exists(&path).await?)
+    let metadata = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let config = cache_config()?;
+    let now = Utc::now();
+    let expired = {
+        let mut index = lock_index();
+        let Some(metadata) = metadata else {
+            index.remove(key);
+            index.misses += 1;
+            return Ok(false);
+        };
+        if !index.entries.contains_key(key) {
+            index.insert(CacheEntry::from_metadata(key, &metadata));
+        }
+        let expired = index.entries[key].is_expired(config.max_age, now);
+        if expired {
+            index.remove(key);
+            index.expirations += 1;
+            index.misses += 1;
+        } else {
+            index.touch(key, now);
+            index.hits += 1;
+        }
+        expired
+    };
+    if expired {
+        debug!(key, "cache: entry expired");
+        tokio::fs::remove_file(&path).await.ok();
+    }
+    Ok(!expired)
+}
+
+fn touch(key: &str) {
+    lock_index().touch(key, Utc::now());
+}
+
+/// Write `data` next to `path` and rename it into place, so readers never see
+/// a partially written entry.
+async fn write_atomic(path: &Path, data: &[impl AsRef<[u8]>]) -> Result<u64> {
+    let tmp_path = path.with_extension(format!(
+        "{}.{}.{}",
+        CACHE_EXTENSION,
+        uuid::Uuid::new_v4().simple(),
+        TEMP_EXTENSION
+    ));
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        let mut written = 0;
+        for chunk in data {
+            file.write_all(chunk.as_ref()).await?;
+            written += chunk.as_ref().len() as u64;
+        }
+        file.flush().await?;
+        file.sync_data().await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok::<u64, anyhow::Error>(written)
+    }
+    .await;
+    if result.is_err() {
+        tokio::fs::remove_file(&tmp_path).await.ok();
+    }
+    result
+}
+
+/// Record a freshly stored entry and trim the cache to its limits
+async fn record_store(key: &str, size: u64) -> Result<()> {
+    let now = Utc::now();
+    lock_index().insert(CacheEntry {
+        key: key.to_string(),
+        size,
+        created_at: now,
+        last_access: now,
+    });
+    enforce_limits().await
+}
+
+async fn enforce_limits() -> Result<()> {
+    let config = cache_config()?;
+    if config.max_bytes.is_none() && config.max_age.is_none() {
+        return Ok(());
+    }
+    let victims = lock_index().evict(config.max_bytes, config.max_age, Utc::now());
+    for key in &victims {
+        let path = config
+            .cache_dir
+            .join(format!("{}.{}", key, CACHE_EXTENSION));
+        if let Err(e) = tokio::fs::remove_file(&path).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!(key, "cache: failed to evict: {}", e);
+        }
+    }
+    if !victims.is_empty() {
+        info!(evicted = victims.len(), "cache: trimmed to limits");
+    }
+    Ok(())
+}
+
+/// Drop expired and least recently used entries beyond the configured limits
+pub async fn sweep() -> Result<()> {
+    enforce_limits().await
+}
+
+/// Entries found in `dir`, removing temp files left by interrupted writes
+async fn scan_dir(dir: &Path) -> Result<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    let mut items = tokio::fs::read_dir(dir).await?;
+    while let Some(item) = items.next_entry().await? {
+        let path = item.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if name.ends_with(&format!(".{}", TEMP_EXTENSION)) {
+            debug!(name, "cache: removing stale temp file");
+            tokio::fs::remove_file(&path).await.ok();
+            continue;
+        }
+        let Some(key) = name.strip_suffix(&format!(".{}", CACHE_EXTENSION)) else {
+            continue;
+        };
+        if let Ok(metadata) = item.metadata().await
+            && metadata.is_file()
+        {
+            entries.push(CacheEntry::from_metadata(key, &metadata));
+        }
+    }
+    Ok(entries)
+}
+
+/// Rebuild the index from the cache directory and trim the cache to its
+/// limits, run once at startup.
+pub async fn reconcile() -> Result<CacheStats> {
+    ensure_cache_dir().await?;
+    let cache_dir = get_cache_dir()?;
+    let entries = scan_dir(&cache_dir).await?;
+    {
+        let mut index = lock_index();
+        index.entries.clear();
+        index.total_bytes = 0;
+        for entry in entries {
+            index.insert(entry);
+        }
+    }
+    enforce_limits().await?;
+    let stats = cache_stats()?;
+    info!(
+        entries = stats.entries,
+        total_bytes = stats.total_bytes,
+        "cache: reconciled {}",
+        cache_dir.display()
+    );
+    Ok(stats)
+}
+
+/// Size, limits and hit/miss counters of the cache
+pub fn cache_stats() -> Result<CacheStats> {
+    let config = cache_config()?;
+    Ok(lock_index().stats(&config))
+}
+
+/// Indexed entries whose key starts with `prefix`, most recently used first
+pub fn list_cache_entries(prefix: Option<&str>) -> Vec<CacheEntry> {
+    let mut entries: Vec<CacheEntry> = lock_index()
+        .entries
+        .values()
+        .filter(|entry| prefix.is_none_or(|prefix| entry.key.starts_with(prefix)))
+        .cloned()
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_access));
+    entries
+}
+
+pub fn get_cache_entry(key: &str) -> Option<CacheEntry> {
+    lock_index().entries.get(key).cloned()
+}
+
+/// Delete every entry whose key starts with `prefix`, returning how many
+pub async fn purge_prefix(prefix: &str) -> Result<usize> {
+    let keys: Vec<String> = lock_index()
+        .entries
+        .keys()
+        .filter(|key| key.starts_with(prefix))
+        .cloned()
+        .collect();
+    for key in &keys {
+        delete_from_cache(key).await?;
+    }
+    info!(prefix, purged = keys.len(), "cache: purged by prefix");
+    Ok(keys.len())
 }
 
 /// Store data in the cache
 pub async fn store_in_cache(key: &str, data: &Vec<u8>) -> Result<()> {
-    ensure_cache_dir().await?;
-    let path = get_cache_path(key)?;
-    tokio::fs::write(&path.with_extension(".tmp"), data).await?;
-    tokio::fs::rename(&path.with_extension(".tmp"), &path).await?;
-    info!("cache: Stored {} -> {} bytes", key, data.len());
-    Ok(())
+    store_in_cache_vectored(key, &[data]).await
 }
 
 // Store datas in the cache
 pub async fn store_in_cache_vectored(key: &str, data: &[impl AsRef<[u8]>]) -> Result<()> {
     ensure_cache_dir().await?;
     let path = get_cache_path(key)?;
-    let tmp_path = path.with_extension(".tmp");
-    let mut file = tokio::fs::File::create(tmp_path.clone()).await?;
-    let io_slices = data
-        .iter()
-        .map(|d| IoSlice::new(d.as_ref()))
-        .collect::<Vec<_>>();
-    let n = file.write_vectored(&io_slices).await?;
-    tokio::fs::rename(&tmp_path, &path).await?;
+    let n = write_atomic(&path, data).await?;
     info!("cache: Stored {} -> {} bytes", key, n);
-    Ok(())
+    record_store(key, n).await
 }
 
 /// Store decoded PCM samples (i16) in the cache as raw little-endian bytes
@@ -140,6 +496,7 @@ pub async fn retrieve_pcm_from_cache(key: &str) -> Result<Vec<i16>> {
 pub async fn retrieve_pcm_from_cache_at(key: &str, sample_offset: usize) -> Result<Vec<i16>> {
     let path = get_cache_path(key)?;
     let mut file = tokio::fs::File::open(&path).await?;
+    touch(key);
     let file_size = file.metadata().await?.len();
     let byte_offset = ((sample_offset as u64) * 2).min(file_size);
     if byte_offset > 0 {
@@ -173,6 +530,7 @@ pub async fn retrieve_from_cache(key: &str) -> Result<Vec<u8>> {
     }
 
     let data = tokio::fs::read(&path).await?;
+    touch(key);
     debug!(key, size = data.len(), "retrieved file from cache");
     Ok(data)
 }
@@ -181,6 +539,7 @@ pub async fn retrieve_from_cache(key: &str) -> Result<Vec<u8>> {
 pub async fn retrieve_from_cache_with_buffer(key: &str, buffer: &mut BytesMut) -> Result<()> {
     let path = get_cache_path(key)?;
     let mut file = tokio::fs::File::open(path).await?;
+    touch(key);
     let metadata = file.metadata().await?;
     let file_size = metadata.len() as usize;
     buffer.reserve(file_size);
@@ -192,6 +551,7 @@ pub async fn retrieve_from_cache_with_buffer(key: &str, buffer: &mut BytesMut) -
 /// Delete a specific file from the cache
 pub async fn delete_from_cache(key: &str) -> Result<()> {
     let path = get_cache_path(key)?;
+    lock_index().remove(key);
 
     if tokio::fs::try_exists(&path).await? {
         tokio::fs::remove_file(path).await?;
@@ -257,6 +617,69 @@ mod tests {
         Ok(())
     }
 
+    fn entry(key: &str, size: u64, age_secs: i64, idle_secs: i64) -> CacheEntry {
+        let now = Utc::now();
+        CacheEntry {
+            key: key.to_string(),
+            size,
+            created_at: now - chrono::Duration::seconds(age_secs),
+            last_access: now - chrono::Duration::seconds(idle_secs),
+        }
+    }
+
+    #[test]
+    fn test_index_evicts_least_recently_used() {
+        let mut index = CacheIndex::default();
+        index.insert(entry("a", 40, 100, 5));
+        index.insert(entry("b", 40, 100, 50));
+        index.insert(entry("c", 40, 100, 1));
+        assert_eq!(index.total_bytes, 120);
+
+        let victims = index.evict(Some(100), None, Utc::now());
+        assert_eq!(victims, vec!["b".to_string()]);
+        assert_eq!(index.total_bytes, 80);
+        assert_eq!(index.evictions, 1);
+
+        // Replacing an entry does not double count its size
+        index.insert(entry("a", 10, 0, 0));
+        assert_eq!(index.total_bytes, 50);
+    }
+
+    #[test]
+    fn test_index_expires_old_entries() {
+        let mut index = CacheIndex::default();
+        index.insert(entry("old", 10, 3600, 0));
+        index.insert(entry("new", 10, 10, 10));
+
+        let victims = index.evict(None, Some(Duration::from_secs(600)), Utc::now());
+        assert_eq!(victims, vec!["old".to_string()]);
+        assert_eq!(index.expirations, 1);
+        assert!(index.entries.contains_key("new"));
+    }
+
+    #[tokio::test]
+    async fn test_scan_dir_removes_temp_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        tokio::fs::write(dir.path().join("k1.pcm"), b"1234").await?;
+        tokio::fs::write(dir.path().join("k2.pcm.abc.tmp"), b"partial").await?;
+        tokio::fs::write(dir.path().join("notes.txt"), b"x").await?;
+
+        let entries = scan_dir(dir.path()).await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "k1");
+        assert_eq!(entries[0].size, 4);
+        assert!(!dir.path().join("k2.pcm.abc.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_path_rejects_traversal() {
+        assert!(get_cache_path("../etc/passwd").is_err());
+        assert!(get_cache_path("").is_err());
+        let path = get_cache_path("abc_16000_1.5").unwrap();
+        assert!(path.ends_with("abc_16000_1.5.pcm"));
+    }
+
     #[test]
     fn test_generate_cache_key() {
         let key1 = generate_cache_key("hello", 16000, None, None);
@@ -0,0 +1,887 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::engine::StreamEngine;
+use crate::media::recorder::{Recorder, RecorderOption};
+use crate::media::track::track_codec::TrackCodec;
+use crate::media::track::{TrackPacketReceiver, tts::SynthesisHandle};
+use crate::media::{AudioFrame, INTERNAL_SAMPLERATE, PcmBuf, Samples};
+use crate::synthesis::{SynthesisCommand, SynthesisOption};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Mixing interval of a conference room.
+pub const CONFERENCE_PTIME: Duration = Duration::from_millis(20);
+const FRAME_SAMPLES: usize = (INTERNAL_SAMPLERATE as usize / 1000) * 20;
+// Drop the oldest audio when a participant gets more than 200ms ahead of the mixer.
+const MAX_BUFFERED_SAMPLES: usize = FRAME_SAMPLES * 10;
+// Track id used for the second (duplicated) channel of the room recording.
+const RECORDER_MIX_TRACK_ID: &str = "conference-mix";
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConferenceOption {
+    /// Record the mixed room audio. `{room}` in `recorderFile` is replaced by the room name.
+    pub recorder: Option<RecorderOption>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConferenceParticipantInfo {
+    pub id: String,
+    pub muted: bool,
+    pub gain: f32,
+    pub joined_at: u64,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConferenceRoomInfo {
+    pub name: String,
+    pub created_at: u64,
+    pub participants: Vec<ConferenceParticipantInfo>,
+    pub playing: Vec<String>,
+    pub recorder_file: Option<String>,
+}
+
+struct Participant {
+    input: mpsc::Receiver<AudioFrame>,
+    output: mpsc::Sender<AudioFrame>,
+    event_sender: EventSender,
+    buffer: VecDeque<i16>,
+    codec: TrackCodec,
+    muted: bool,
+    gain: f32,
+    joined_at: u64,
+}
+
+/// Audio injected into the room that does not come from a participant,
+/// e.g. TTS played to everyone or to a single participant.
+struct RoomSource {
+    target: Option<String>,
+    input: TrackPacketReceiver,
+    buffer: VecDeque<i16>,
+    finished: bool,
+    _handle: SynthesisHandle,
+}
+
+/// A named room mixing audio of N sessions server-side.
+///
+/// Every participant hears the sum of all other unmuted participants (its own
+/// voice is removed) plus any room sources addressed to it.
+pub struct ConferenceRoom {
+    pub name: String,
+    pub cancel_token: CancellationToken,
+    pub created_at: u64,
+    participants: Mutex<HashMap<String, Participant>>,
+    // Set by the first join; a room only closes once it has been occupied.
+    occupied: AtomicBool,
+    sources: Mutex<HashMap<String, RoomSource>>,
+    recorder_sender: Option<mpsc::UnboundedSender<AudioFrame>>,
+    recorder_file: Option<String>,
+}
+
+pub type ConferenceRoomRef = Arc<ConferenceRoom>;
+
+impl ConferenceRoom {
+    pub fn new(name: String, cancel_token: CancellationToken, option: &ConferenceOption) -> Self {
+        let mut recorder_sender = None;
+        let mut recorder_file = None;
+        if let Some(recorder_option) = option.recorder.clone()
+            && !recorder_option.recorder_file.is_empty()
+        {
+            let mut recorder_option = recorder_option;
+            recorder_option.recorder_file = recorder_option.recorder_file.replace("{room}", &name);
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let path = recorder_option.recorder_file.clone();
+            let recorder = Recorder::new(cancel_token.child_token(), name.clone(), recorder_option);
+            let room_name = name.clone();
+            let record_path = path.clone();
+            crate::spawn(async move {
+                if let Err(e) = recorder
+                    .process_recording(Path::new(&record_path), receiver)
+                    .await
+                {
+                    warn!(room = room_name, "conference recorder failed: {}", e);
+                }
+            });
+            recorder_sender = Some(sender);
+            recorder_file = Some(path);
+        }
+        Self {
+            name,
+            cancel_token,
+            created_at: crate::media::get_timestamp(),
+            participants: Mutex::new(HashMap::new()),
+            occupied: AtomicBool::new(false),
+            sources: Mutex::new(HashMap::new()),
+            recorder_sender,
+            recorder_file,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.cancel_token.is_cancelled()
+    }
+
+    pub fn participant_count(&self) -> usize {
+        self.participants.lock().unwrap().len()
+    }
+
+    pub fn contains(&self, participant_id: &str) -> bool {
+        self.participants
+            .lock()
+            .unwrap()
+            .contains_key(participant_id)
+    }
+
+    /// Add a participant. `input` carries the participant's own audio into the room,
+    /// `output` receives the mix for that participant. Joining again replaces the
+    /// participant's previous legs. Fails once the room is closed.
+    pub fn join(
+        &self,
+        participant_id: String,
+        input: mpsc::Receiver<AudioFrame>,
+        output: mpsc::Sender<AudioFrame>,
+        event_sender: EventSender,
+        muted: bool,
+        gain: f32,
+    ) -> Result<()> {
+        let participants = {
+            let mut participants = self.participants.lock().unwrap();
+            if self.is_closed() {
+                return Err(anyhow::anyhow!("conference {} is closed", self.name));
+            }
+            self.occupied.store(true, Ordering::Relaxed);
+            participants.insert(
+                participant_id.clone(),
+                Participant {
+                    input,
+                    output,
+                    event_sender,
+                    buffer: VecDeque::with_capacity(MAX_BUFFERED_SAMPLES),
+                    codec: TrackCodec::new(),
+                    muted,
+                    gain,
+                    joined_at: crate::media::get_timestamp(),
+                },
+            );
+            participants.keys().cloned().collect::<Vec<_>>()
+        };
+        info!(
+            room = self.name,
+            participant_id, "conference participant joined"
+        );
+        self.broadcast(|track_id| SessionEvent::ConferenceJoin {
+            track_id,
+            timestamp: crate::media::get_timestamp(),
+            room: self.name.clone(),
+            participant_id: participant_id.clone(),
+            participants: participants.clone(),
+        });
+        Ok(())
+    }
+
+    pub fn leave(&self, participant_id: &str) -> bool {
+        let removed = self
+            .participants
+            .lock()
+            .unwrap()
+            .remove(participant_id)
+            .is_some();
+        if removed {
+            self.on_left(vec![participant_id.to_string()]);
+        }
+        removed
+    }
+
+    /// Update mute state and/or gain of a participant.
+    pub fn update_participant(
+        &self,
+        participant_id: &str,
+        muted: Option<bool>,
+        gain: Option<f32>,
+    ) -> Result<()> {
+        let mut participants = self.participants.lock().unwrap();
+        let participant = participants.get_mut(participant_id).ok_or_else(|| {
+            anyhow::anyhow!(
+                "participant {} not found in conference {}",
+                participant_id,
+                self.name
+            )
+        })?;
+        if let Some(muted) = muted {
+            participant.muted = muted;
+        }
+        if let Some(gain) = gain {
+            participant.gain = gain.max(0.0);
+        }
+        debug!(
+            room = self.name,
+            participant_id,
+            muted = participant.muted,
+            gain = participant.gain,
+            "conference participant updated"
+        );
+        Ok(())
+    }
+
+    /// Synthesize `text` into the room. With `target` set only that participant hears it.
+    pub async fn play_tts(
+        &self,
+        engine: Arc<StreamEngine>,
+        text: String,
+        speaker: Option<String>,
+        play_id: Option<String>,
+        option: SynthesisOption,
+        target: Option<String>,
+    ) -> Result<String> {
+        if let Some(target) = &target
+            && !self.contains(target)
+        {
+            return Err(anyhow::anyhow!(
+                "participant {} not found in conference {}",
+                target,
+                self.name
+            ));
+        }
+        let play_id = play_id.unwrap_or_else(|| format!("conference-tts-{}", uuid::Uuid::new_v4()));
+        let track_id = format!("conference:{}:tts:{}", self.name, play_id);
+        let ssrc = rand::random::<u32>();
+        let (handle, mut track) = StreamEngine::create_tts_track(
+            engine,
+            self.cancel_token.child_token(),
+            self.name.clone(),
+            track_id,
+            ssrc,
+            Some(play_id.clone()),
+            false,
+            &option,
+        )
+        .await?;
+        handle.try_send(SynthesisCommand {
+            text,
+            speaker: speaker.or(option.speaker.clone()),
+            play_id: Some(play_id.clone()),
+            streaming: false,
+            end_of_stream: true,
+            option,
+            base64: false,
+            cache_key: None,
+        })?;
+        let (packet_sender, packet_receiver) = mpsc::unbounded_channel();
+        let event_sender = self.source_event_sender(target.as_deref());
+        track.start(event_sender, packet_sender).await?;
+        info!(room = self.name, play_id, ?target, "conference tts started");
+        self.sources.lock().unwrap().insert(
+            play_id.clone(),
+            RoomSource {
+                target,
+                input: packet_receiver,
+                buffer: VecDeque::new(),
+                finished: false,
+                _handle: handle,
+            },
+        );
+        Ok(play_id)
+    }
+
+    /// Stop a playing room source.
+    pub fn stop_source(&self, play_id: &str) -> bool {
+        self.sources.lock().unwrap().remove(play_id).is_some()
+    }
+
+    pub fn info(&self) -> ConferenceRoomInfo {
+        let mut participants = self
+            .participants
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, p)| ConferenceParticipantInfo {
+                id: id.clone(),
+                muted: p.muted,
+                gain: p.gain,
+                joined_at: p.joined_at,
+            })
+            .collect::<Vec<_>>();
+        participants.sort_by_key(|p| p.joined_at);
+        ConferenceRoomInfo {
+            name: self.name.clone(),
+            created_at: self.created_at,
+            participants,
+            playing: self.sources.lock().unwrap().keys().cloned().collect(),
+            recorder_file: self.recorder_file.clone(),
+        }
+    }
+
+    /// Run the mixer until the room is cancelled or the last participant has left.
+    pub async fn serve(&self) {
+        let mut ticker = tokio::time::interval(CONFERENCE_PTIME);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = self.cancel_token.cancelled() => break,
+                _ = ticker.tick() => {
+                    if !self.mix_tick() {
+                        break;
+                    }
+                }
+            }
+        }
+        self.cancel_token.cancel();
+        let remaining = self
+            .participants
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        if !remaining.is_empty() {
+            self.on_left(remaining);
+        }
+        info!(room = self.name, "conference room closed");
+    }
+
+    /// Mix one ptime worth of audio. Returns false once the last participant has
+    /// left; a room nobody has joined yet keeps waiting.
+    pub(crate) fn mix_tick(&self) -> bool {
+        let mut left = Vec::new();
+        let mut participants = self.participants.lock().unwrap();
+        let mut sources = self.sources.lock().unwrap();
+
+        if participants.is_empty() {
+            if !self.occupied.load(Ordering::Relaxed) {
+                return true;
+            }
+            // Closed under the lock so a concurrent join fails instead of
+            // landing in a room whose mixer is stopping.
+            self.cancel_token.cancel();
+            return false;
+        }
+
+        let mut frames: HashMap<String, (PcmBuf, bool)> = HashMap::new();
+        let mut room_mix = vec![0i32; FRAME_SAMPLES];
+        let mut speakers = 0usize;
+        let mut broadcasting = false;
+
+        for (id, participant) in participants.iter_mut() {
+            loop {
+                match participant.input.try_recv() {
+                    Ok(frame) => participant.push_frame(frame),
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                    Err(mpsc::error::TryRecvError::Disconnected) => {
+                        left.push(id.clone());
+                        break;
+                    }
+                }
+            }
+            let speaking = !participant.buffer.is_empty() && !participant.muted;
+            let pcm = pop_frame(&mut participant.buffer);
+            if speaking {
+                accumulate(&mut room_mix, &pcm, participant.gain);
+                speakers += 1;
+            }
+            frames.insert(id.clone(), (pcm, speaking));
+        }
+
+        let mut targeted: HashMap<String, Vec<i32>> = HashMap::new();
+        sources.retain(|_, source| {
+            loop {
+                match source.input.try_recv() {
+                    Ok(frame) => {
+                        if let Samples::PCM { samples } = frame.samples {
+                            source.buffer.extend(samples);
+                        }
+                    }
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                    Err(mpsc::error::TryRecvError::Disconnected) => {
+                        source.finished = true;
+                        break;
+                    }
+                }
+            }
+            if source.buffer.is_empty() {
+                return !source.finished;
+            }
+            let pcm = pop_frame(&mut source.buffer);
+            match &source.target {
+                Some(target) => {
+                    let mix = targeted
+                        .entry(target.clone())
+                        .or_insert_with(|| vec![0i32; FRAME_SAMPLES]);
+                    accumulate(mix, &pcm, 1.0);
+                }
+                None => {
+                    accumulate(&mut room_mix, &pcm, 1.0);
+                    broadcasting = true;
+                }
+            }
+            true
+        });
+
+        let timestamp = crate::media::get_timestamp();
+        for (id, participant) in participants.iter_mut() {
+            if left.contains(id) {
+                continue;
+            }
+            let private = targeted.get(id);
+            let (own, speaking) = &frames[id];
+            // Skip silent ticks so the mix does not compete with the call's own playback.
+            let others = speakers - usize::from(*speaking);
+            if others == 0 && !broadcasting && private.is_none() {
+                continue;
+            }
+            let mut mix = room_mix.clone();
+            if *speaking {
+                for (m, s) in mix.iter_mut().zip(own.iter()) {
+                    *m -= (*s as f32 * participant.gain) as i32;
+                }
+            }
+            if let Some(private) = private {
+                for (m, s) in mix.iter_mut().zip(private.iter()) {
+                    *m += *s;
+                }
+            }
+            let frame = AudioFrame {
+                track_id: self.name.clone(),
+                samples: Samples::PCM {
+                    samples: saturate(&mix),
+                },
+                timestamp,
+                sample_rate: INTERNAL_SAMPLERATE,
+                channels: 1,
+                ..Default::default()
+            };
+            if let Err(mpsc::error::TrySendError::Closed(_)) = participant.output.try_send(frame) {
+                left.push(id.clone());
+            }
+        }
+
+        if let Some(recorder_sender) = &self.recorder_sender
+            && (speakers > 0 || broadcasting)
+        {
+            let samples = saturate(&room_mix);
+            for track_id in [self.name.as_str(), RECORDER_MIX_TRACK_ID] {
+                recorder_sender
+                    .send(AudioFrame {
+                        track_id: track_id.to_string(),
+                        samples: Samples::PCM {
+                            samples: samples.clone(),
+                        },
+                        timestamp,
+                        sample_rate: INTERNAL_SAMPLERATE,
+                        channels: 1,
+                        ..Default::default()
+                    })
+                    .ok();
+            }
+        }
+
+        for id in left.iter() {
+            participants.remove(id);
+        }
+        let empty = participants.is_empty();
+        drop(sources);
+        drop(participants);
+        if !left.is_empty() {
+            self.on_left(left);
+        }
+        !empty
+    }
+
+    fn on_left(&self, left: Vec<String>) {
+        let remaining = self
+            .participants
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for participant_id in left {
+            info!(
+                room = self.name,
+                participant_id, "conference participant left"
+            );
+            self.sources
+                .lock()
+                .unwrap()
+                .retain(|_, s| s.target.as_deref() != Some(participant_id.as_str()));
+            self.broadcast(|track_id| SessionEvent::ConferenceLeave {
+                track_id,
+                timestamp: crate::media::get_timestamp(),
+                room: self.name.clone(),
+                participant_id: participant_id.clone(),
+                participants: remaining.clone(),
+            });
+        }
+    }
+
+    fn broadcast(&self, build: impl Fn(String) -> SessionEvent) {
+        let senders = self
+            .participants
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, p)| (id.clone(), p.event_sender.clone()))
+            .collect::<Vec<_>>();
+        for (id, sender) in senders {
+            sender.send(build(id)).ok();
+        }
+    }
+
+    fn source_event_sender(&self, target: Option<&str>) -> EventSender {
+        let participants = self.participants.lock().unwrap();
+        if let Some(target) = target
+            && let Some(participant) = participants.get(target)
+        {
+            return participant.event_sender.clone();
+        }
+        // Room-wide playback: deliver track events to every participant.
+        let room_sender = crate::event::create_event_sender();
+        let mut receiver = room_sender.subscribe();
+        let senders = participants
+            .values()
+            .map(|p| p.event_sender.clone())
+            .collect::<Vec<_>>();
+        let cancel_token = self.cancel_token.clone();
+        crate::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    event = receiver.recv() => match event {
+                        Ok(event) => {
+                            for sender in senders.iter() {
+                                sender.send(event.clone()).ok();
+                            }
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    }
+                }
+            }
+        });
+        room_sender
+    }
+}
+
+impl Participant {
+    fn push_frame(&mut self, frame: AudioFrame) {
+        let samples = match frame.samples {
+            Samples::PCM { samples } => samples,
+            _ => return,
+        };
+        let samples = if frame.sample_rate != INTERNAL_SAMPLERATE {
+            self.codec
+                .resample(samples, frame.sample_rate, INTERNAL_SAMPLERATE)
+        } else {
+            samples
+        };
+        self.buffer.extend(samples);
+        if self.buffer.len() > MAX_BUFFERED_SAMPLES {
+            let excess = self.buffer.len() - MAX_BUFFERED_SAMPLES;
+            self.buffer.drain(..excess);
+        }
+    }
+}
+
+fn pop_frame(buffer: &mut VecDeque<i16>) -> PcmBuf {
+    let take = FRAME_SAMPLES.min(buffer.len());
+    let mut pcm: PcmBuf = buffer.drain(..take).collect();
+    pcm.resize(FRAME_SAMPLES, 0);
+    pcm
+}
+
+fn accumulate(mix: &mut [i32], pcm: &[i16], gain: f32) {
+    for (m, s) in mix.iter_mut().zip(pcm.iter()) {
+        *m += (*s as f32 * gain) as i32;
+    }
+}
+
+fn saturate(mix: &[i32]) -> PcmBuf {
+    mix.iter()
+        .map(|s| (*s).clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+        .collect()
+}
+
+/// Registry of active conference rooms.
+#[derive(Default)]
+pub struct ConferenceManager {
+    rooms: Arc<Mutex<HashMap<String, ConferenceRoomRef>>>,
+}
+
+impl ConferenceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<ConferenceRoomRef> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .get(name)
+            .filter(|room| !room.is_closed())
+            .cloned()
+    }
+
+    /// Return the named room, creating and starting its mixer when absent.
+    /// `option` only applies when the room is created.
+    pub fn get_or_create(
+        &self,
+        name: &str,
+        option: &ConferenceOption,
+        cancel_token: CancellationToken,
+    ) -> ConferenceRoomRef {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(room) = rooms.get(name)
+            && !room.is_closed()
+        {
+            return room.clone();
+        }
+        let room = Arc::new(ConferenceRoom::new(name.to_string(), cancel_token, option));
+        rooms.insert(name.to_string(), room.clone());
+        info!(room = name, "conference room created");
+
+        let serving = room.clone();
+        let rooms_ref = self.rooms.clone();
+        crate::spawn(async move {
+            serving.serve().await;
+            let mut rooms = rooms_ref.lock().unwrap();
+            if rooms
+                .get(&serving.name)
+                .map(|r| Arc::ptr_eq(r, &serving))
+                .unwrap_or(false)
+            {
+                rooms.remove(&serving.name);
+            }
+        });
+        room
+    }
+
+    pub fn list(&self) -> Vec<ConferenceRoomInfo> {
+        let mut rooms = self
+            .rooms
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|room| !room.is_closed())
+            .map(|room| room.info())
+            .collect::<Vec<_>>();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        rooms
+    }
+
+    /// Close a room; every participant receives a leave event.
+    pub fn close(&self, name: &str) -> bool {
+        match self.rooms.lock().unwrap().remove(name) {
+            Some(room) => {
+                room.cancel_token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm_frame(value: i16) -> AudioFrame {
+        AudioFrame {
+            track_id: "caller".to_string(),
+            samples: Samples::PCM {
+                samples: vec![value; FRAME_SAMPLES],
+            },
+            sample_rate: INTERNAL_SAMPLERATE,
+            ..Default::default()
+        }
+    }
+
+    struct TestLeg {
+        input: mpsc::Sender<AudioFrame>,
+        output: mpsc::Receiver<AudioFrame>,
+        events: tokio::sync::broadcast::Receiver<SessionEvent>,
+    }
+
+    fn join(room: &ConferenceRoom, id: &str, muted: bool, gain: f32) -> TestLeg {
+        let (input_tx, input_rx) = mpsc::channel(8);
+        let (output_tx, output_rx) = mpsc::channel(8);
+        let event_sender = crate::event::create_event_sender();
+        let events = event_sender.subscribe();
+        room.join(
+            id.to_string(),
+            input_rx,
+            output_tx,
+            event_sender,
+            muted,
+            gain,
+        )
+        .unwrap();
+        TestLeg {
+            input: input_tx,
+            output: output_rx,
+            events,
+        }
+    }
+
+    fn first_sample(leg: &mut TestLeg) -> Option<i16> {
+        leg.output.try_recv().ok().and_then(|f| match f.samples {
+            Samples::PCM { samples } => samples.first().cloned(),
+            _ => None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_mix_excludes_own_voice() {
+        let room = ConferenceRoom::new(
+            "room-a".to_string(),
+            CancellationToken::new(),
+            &ConferenceOption::default(),
+        );
+        let mut a = join(&room, "a", false, 1.0);
+        let mut b = join(&room, "b", false, 1.0);
+        let mut c = join(&room, "c", false, 1.0);
+
+        a.input.try_send(pcm_frame(100)).unwrap();
+        b.input.try_send(pcm_frame(200)).unwrap();
+        c.input.try_send(pcm_frame(400)).unwrap();
+        assert!(room.mix_tick());
+
+        assert_eq!(first_sample(&mut a), Some(600));
+        assert_eq!(first_sample(&mut b), Some(500));
+        assert_eq!(first_sample(&mut c), Some(300));
+    }
+
+    #[tokio::test]
+    async fn test_mute_and_gain() {
+        let room = ConferenceRoom::new(
+            "room-b".to_string(),
+            CancellationToken::new(),
+            &ConferenceOption::default(),
+        );
+        let mut a = join(&room, "a", false, 0.5);
+        let mut b = join(&room, "b", true, 1.0);
+        let mut c = join(&room, "c", false, 1.0);
+
+        a.input.try_send(pcm_frame(1000)).unwrap();
+        b.input.try_send(pcm_frame(1000)).unwrap();
+        c.input.try_send(pcm_frame(1000)).unwrap();
+        room.mix_tick();
+
+        // b is muted, a is attenuated
+        assert_eq!(first_sample(&mut a), Some(1000));
+        assert_eq!(first_sample(&mut b), Some(1500));
+        assert_eq!(first_sample(&mut c), Some(500));
+
+        room.update_participant("b", Some(false), None).unwrap();
+        a.input.try_send(pcm_frame(1000)).unwrap();
+        b.input.try_send(pcm_frame(1000)).unwrap();
+        room.mix_tick();
+        assert_eq!(first_sample(&mut c), Some(1500));
+        assert!(room.update_participant("x", Some(true), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mix_saturates() {
+        let room = ConferenceRoom::new(
+            "room-c".to_string(),
+            CancellationToken::new(),
+            &ConferenceOption::default(),
+        );
+        let mut a = join(&room, "a", false, 1.0);
+        let b = join(&room, "b", false, 1.0);
+        let c = join(&room, "c", false, 1.0);
+        b.input.try_send(pcm_frame(30000)).unwrap();
+        c.input.try_send(pcm_frame(30000)).unwrap();
+        room.mix_tick();
+        assert_eq!(first_sample(&mut a), Some(i16::MAX));
+    }
+
+    #[tokio::test]
+    async fn test_join_leave_events() {
+        let room = ConferenceRoom::new(
+            "room-d".to_string(),
+            CancellationToken::new(),
+            &ConferenceOption::default(),
+        );
+        let mut a = join(&room, "a", false, 1.0);
+        let b = join(&room, "b", false, 1.0);
+
+        assert!(matches!(
+            a.events.try_recv().unwrap(),
+            SessionEvent::ConferenceJoin { participant_id, .. } if participant_id == "a"
+        ));
+        assert!(matches!(
+            a.events.try_recv().unwrap(),
+            SessionEvent::ConferenceJoin { participant_id, participants, .. }
+                if participant_id == "b" && participants.len() == 2
+        ));
+
+        // Dropping the input sender means the leg's track stopped.
+        drop(b);
+        assert!(room.mix_tick());
+        assert!(matches!(
+            a.events.try_recv().unwrap(),
+            SessionEvent::ConferenceLeave { participant_id, participants, .. }
+                if participant_id == "b" && participants == vec!["a".to_string()]
+        ));
+        assert_eq!(room.participant_count(), 1);
+
+        assert!(room.leave("a"));
+        assert!(!room.mix_tick());
+    }
+
+    #[tokio::test]
+    async fn test_manager_room_waits_for_first_join() {
+        let manager = ConferenceManager::new();
+        let room = manager.get_or_create(
+            "room-e",
+            &ConferenceOption::default(),
+            CancellationToken::new(),
+        );
+        // The mixer ticks several times before the caller gets to join.
+        tokio::time::sleep(CONFERENCE_PTIME * 3).await;
+        assert!(!room.is_closed());
+        let a = join(&room, "a", false, 1.0);
+        assert!(Arc::ptr_eq(
+            &manager.get("room-e").unwrap(),
+            &manager.get_or_create(
+                "room-e",
+                &ConferenceOption::default(),
+                CancellationToken::new()
+            )
+        ));
+
+        drop(a);
+        for _ in 0..50 {
+            if manager.get("room-e").is_none() {
+                break;
+            }
+            tokio::time::sleep(CONFERENCE_PTIME).await;
+        }
+        assert!(room.is_closed());
+        assert!(manager.get("room-e").is_none());
+        let (_input_tx, input_rx) = mpsc::channel(8);
+        let (output_tx, _output_rx) = mpsc::channel(8);
+        assert!(
+            room.join(
+                "b".to_string(),
+                input_rx,
+                output_tx,
+                crate::event::create_event_sender(),
+                false,
+                1.0
+            )
+            .is_err()
+        );
+    }
+}
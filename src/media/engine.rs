@@ -15,17 +15,23 @@ use crate::{
     event::EventSender,
     media::TrackId,
     synthesis::{
-        AliyunTtsClient, DeepegramTtsClient, SynthesisClient, SynthesisOption, SynthesisType,
+        AliyunTtsClient, AzureTtsClient, DeepegramTtsClient, ElevenLabsTtsClient, GenericTtsClient,
+        OpenAiTtsClient, SynthesisClient, SynthesisOption, SynthesisType,
         TencentCloudTtsBasicClient, TencentCloudTtsClient,
     },
     transcription::{
-        AliyunAsrClientBuilder, DeepgramAsrClientBuilder, TencentCloudAsrClientBuilder,
-        TranscriptionClient, TranscriptionOption, TranscriptionType,
+        AliyunAsrClientBuilder, AsrClientFactory, AzureAsrClientBuilder, DeepgramAsrClientBuilder,
+        FailoverAsrClient, GenericAsrClientBuilder, OpenAiAsrClientBuilder, Replacer,
+        TencentCloudAsrClientBuilder, TranscriptionClient, TranscriptionOption, TranscriptionType,
+        WhisperAsrClientBuilder,
     },
 };
 
 #[cfg(feature = "offline")]
-use crate::{synthesis::SupertonicTtsClient, transcription::SensevoiceAsrClientBuilder};
+use crate::{
+    synthesis::SupertonicTtsClient,
+    transcription::{SensevoiceAsrClientBuilder, WhisperOfflineAsrClientBuilder},
+};
 
 use anyhow::Result;
 use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
@@ -100,12 +106,29 @@ impl Default for StreamEngine {
             TranscriptionType::Deepgram,
             Box::new(DeepgramAsrClientBuilder::create),
         );
+        engine.register_asr(
+            TranscriptionType::OpenAi,
+            Box::new(OpenAiAsrClientBuilder::create),
+        );
+        engine.register_asr(
+            TranscriptionType::Azure,
+            Box::new(AzureAsrClientBuilder::create),
+        );
+        engine.register_asr(
+            TranscriptionType::Whisper,
+            Box::new(WhisperAsrClientBuilder::create),
+        );
 
         #[cfg(feature = "offline")]
         engine.register_asr(
             TranscriptionType::Sensevoice,
             Box::new(SensevoiceAsrClientBuilder::create),
         );
+        #[cfg(feature = "offline")]
+        engine.register_asr(
+            TranscriptionType::WhisperOffline,
+            Box::new(WhisperOfflineAsrClientBuilder::create),
+        );
 
         engine.register_tts(SynthesisType::Aliyun, AliyunTtsClient::create);
         engine.register_tts(SynthesisType::TencentCloud, TencentCloudTtsClient::create);
@@ -114,6 +137,9 @@ impl Default for StreamEngine {
             TencentCloudTtsBasicClient::create,
         );
         engine.register_tts(SynthesisType::Deepgram, DeepegramTtsClient::create);
+        engine.register_tts(SynthesisType::OpenAi, OpenAiTtsClient::create);
+        engine.register_tts(SynthesisType::ElevenLabs, ElevenLabsTtsClient::create);
+        engine.register_tts(SynthesisType::Azure, AzureTtsClient::create);
 
         #[cfg(feature = "offline")]
         engine.register_tts(SynthesisType::Supertonic, SupertonicTtsClient::create);
@@ -190,23 +216,62 @@ impl StreamEngine {
         }
     }
 
-    pub async fn create_asr_processor(
+    pub async fn create_asr_client(
         &self,
         track_id: TrackId,
         cancel_token: CancellationToken,
         option: TranscriptionOption,
         event_sender: EventSender,
-    ) -> Result<Box<dyn Processor>> {
-        let asr_client = match option.provider {
+    ) -> Result<Box<dyn TranscriptionClient>> {
+        match option.provider {
             Some(ref provider) => {
                 let creator = self.asr_creators.get(&provider);
                 if let Some(creator) = creator {
-                    creator(track_id, cancel_token, option, event_sender).await?
+                    creator(track_id, cancel_token, option, event_sender).await
+                } else if option.template.is_some() {
+                    GenericAsrClientBuilder::create(track_id, cancel_token, option, event_sender)
+                        .await
                 } else {
-                    return Err(anyhow::anyhow!("ASR type not found: {}", provider));
+                    Err(anyhow::anyhow!("ASR type not found: {}", provider))
                 }
             }
-            None => return Err(anyhow::anyhow!("ASR type not found: {:?}", option.provider)),
+            None => Err(anyhow::anyhow!("ASR type not found: {:?}", option.provider)),
+        }
+    }
+
+    pub async fn create_asr_processor(
+        self: &Arc<Self>,
+        track_id: TrackId,
+        cancel_token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> Result<Box<dyn Processor>> {
+        let event_sender = match option.replacements.as_ref().and_then(Replacer::new) {
+            Some(replacer) => replacer.forward(event_sender, cancel_token.clone()),
+            None => event_sender,
+        };
+        let asr_client: Box<dyn TranscriptionClient> = if option.has_failover() {
+            let engine = self.clone();
+            let client_track_id = track_id.clone();
+            let factory: AsrClientFactory = Arc::new(move |option, token, sender| {
+                let engine = engine.clone();
+                let track_id = client_track_id.clone();
+                Box::pin(async move {
+                    engine
+                        .create_asr_client(track_id, token, option, sender)
+                        .await
+                })
+            });
+            Box::new(FailoverAsrClient::spawn(
+                track_id,
+                cancel_token,
+                option,
+                event_sender,
+                factory,
+            ))
+        } else {
+            self.create_asr_client(track_id, cancel_token, option, event_sender)
+                .await?
         };
         Ok(Box::new(AsrProcessor { asr_client }))
     }
@@ -221,6 +286,8 @@ impl StreamEngine {
                 let creator = self.tts_creators.get(&provider);
                 if let Some(creator) = creator {
                     creator(streaming, tts_option)
+                } else if tts_option.template.is_some() {
+                    GenericTtsClient::create(streaming, tts_option)
                 } else {
                     Err(anyhow::anyhow!("TTS type not found: {}", provider))
                 }
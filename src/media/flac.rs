@@ -0,0 +1,446 @@
+//! Streaming FLAC encoder for 16-bit PCM. Each block is coded with the best
+//! fixed linear predictor and Rice-coded residuals, falling back to constant
+//! or verbatim subframes, so memory stays at one block however long the
+//! stream runs. STREAMINFO is only complete once the stream ends: write
+//! [`FlacEncoder::header`] first and rewrite it over the start when done.
+use md5::{Digest, Md5};
+
+/// Samples per channel in every frame but the last
+pub const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: u32 = 16;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 6;
+/// Rice parameter 15 is the escape code of the 4-bit parameter method
+const MAX_RICE_PARAM: u32 = 14;
+/// `fLaC`, the metadata block header and the 34-byte STREAMINFO
+pub const HEADER_LEN: usize = 42;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1 << self.bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Frame number in the extended UTF-8 coding of the frame header
+fn write_utf8(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+    let len = match value {
+        0..0x800 => 2,
+        0x800..0x1_0000 => 3,
+        0x1_0000..0x20_0000 => 4,
+        0x20_0000..0x400_0000 => 5,
+        0x400_0000..0x8000_0000 => 6,
+        _ => 7,
+    };
+    let prefix = (0xFFu64 << (8 - len)) & 0xFF;
+    writer.write(prefix | (value >> (6 * (len - 1))), 8);
+    for i in (0..len - 1).rev() {
+        writer.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn sample_rate_code(sample_rate: u32) -> u64 {
+    match sample_rate {
+        88200 => 1,
+        176400 => 2,
+        192000 => 3,
+        8000 => 4,
+        16000 => 5,
+        22050 => 6,
+        24000 => 7,
+        32000 => 8,
+        44100 => 9,
+        48000 => 10,
+        96000 => 11,
+        // taken from STREAMINFO
+        _ => 0,
+    }
+}
+
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i64> {
+    let mut residual: Vec<i64> = samples.iter().map(|&s| s as i64).collect();
+    for _ in 0..order {
+        for i in (1..residual.len()).rev() {
+            residual[i] -= residual[i - 1];
+        }
+    }
+    residual.split_off(order)
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Best Rice parameter of a partition and its size in bits
+fn rice_param(values: &[u64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAM)
+        .map(|k| {
+            let bits = values.iter().map(|&v| (v >> k) + 1 + k as u64).sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+/// Partition order and Rice parameters coding `residual` in the fewest bits
+fn rice_partitions(residual: &[u64], order: usize, block_len: usize) -> (u32, Vec<u32>, u64) {
+    let mut best: Option<(u32, Vec<u32>, u64)> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << partition_order;
+        if !block_len.is_multiple_of(partitions) || block_len / partitions <= order {
+            break;
+        }
+        let partition_len = block_len / partitions;
+        let mut params = Vec::with_capacity(partitions);
+        let mut bits = 0;
+        let mut start = 0;
+        for i in 0..partitions {
+            let len = if i == 0 {
+                partition_len - order
+            } else {
+                partition_len
+            };
+            let (k, size) = rice_param(&residual[start..start + len]);
+            params.push(k);
+            bits += size + 4;
+            start += len;
+        }
+        if best.as_ref().is_none_or(|(_, _, b)| bits < *b) {
+            best = Some((partition_order, params, bits));
+        }
+    }
+    best.unwrap_or((0, vec![0], u64::MAX))
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i32]) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        writer.write(0, 8);
+        writer.write_signed(samples[0] as i64, BITS_PER_SAMPLE);
+        return;
+    }
+
+    let max_order = MAX_FIXED_ORDER.min(samples.len() - 1);
+    let (order, residual) = (0..=max_order)
+        .map(|order| (order, fixed_residual(samples, order)))
+        .min_by_key(|(_, residual)| residual.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+        .unwrap_or_else(|| (0, fixed_residual(samples, 0)));
+    let residual: Vec<u64> = residual.into_iter().map(zigzag).collect();
+    let (partition_order, params, residual_bits) = rice_partitions(&residual, order, samples.len());
+
+    let fixed_bits = order as u64 * BITS_PER_SAMPLE as u64 + 6 + residual_bits;
+    if fixed_bits >= samples.len() as u64 * BITS_PER_SAMPLE as u64 {
+        writer.write(0b0000_0010, 8);
+        for &sample in samples {
+            writer.write_signed(sample as i64, BITS_PER_SAMPLE);
+        }
+        return;
+    }
+
+    writer.write(((0b00_1000 | order) << 1) as u64, 8);
+    for &sample in &samples[..order] {
+        writer.write_signed(sample as i64, BITS_PER_SAMPLE);
+    }
+    writer.write(0, 2);
+    writer.write(partition_order as u64, 4);
+    let partition_len = samples.len() >> partition_order;
+    let mut values = residual.iter();
+    for (i, &k) in params.iter().enumerate() {
+        writer.write(k as u64, 4);
+        let len = if i == 0 {
+            partition_len - order
+        } else {
+            partition_len
+        };
+        for &value in values.by_ref().take(len) {
+            writer.write_unary(value >> k);
+            writer.write(value, k);
+        }
+    }
+}
+
+pub struct FlacEncoder {
+    sample_rate: u32,
+    channels: u16,
+    pending: Vec<i16>,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    md5: Md5,
+    finished: bool,
+}
+
+impl FlacEncoder {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            sample_rate,
+            channels: channels.clamp(1, 8),
+            pending: Vec::new(),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+            md5: Md5::new(),
+            finished: false,
+        }
+    }
+
+    /// Stream marker and STREAMINFO, exact only after [`FlacEncoder::finish`]
+    pub fn header(&self) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.bytes.extend_from_slice(b"fLaC");
+        // last metadata block, STREAMINFO, 34 bytes
+        writer.write(0x80, 8);
+        writer.write(34, 24);
+        writer.write(BLOCK_SIZE as u64, 16);
+        writer.write(BLOCK_SIZE as u64, 16);
+        writer.write(self.min_frame_size as u64, 24);
+        writer.write(self.max_frame_size as u64, 24);
+        writer.write(self.sample_rate as u64, 20);
+        writer.write(self.channels as u64 - 1, 3);
+        writer.write(BITS_PER_SAMPLE as u64 - 1, 5);
+        writer.write(self.total_samples >> 32, 4);
+        writer.write(self.total_samples & 0xFFFF_FFFF, 32);
+        // an all-zero signature means unknown
+        if self.finished {
+            writer.bytes.extend_from_slice(&self.md5.clone().finalize());
+        } else {
+            writer.bytes.extend_from_slice(&[0; 16]);
+        }
+        writer.bytes
+    }
+
+    /// Encodes interleaved samples, returning the frames completed so far
+    pub fn encode(&mut self, pcm: &[i16]) -> Vec<u8> {
+        self.pending.extend_from_slice(pcm);
+        let block = BLOCK_SIZE * self.channels as usize;
+        let mut out = Vec::new();
+        let mut start = 0;
+        while self.pending.len() - start >= block {
+            out.extend(self.encode_frame(start, block));
+            start += block;
+        }
+        self.pending.drain(..start);
+        out
+    }
+
+    /// Encodes the samples left as a last, shorter frame
+    pub fn finish(&mut self) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let len = self.pending.len() - self.pending.len() % channels;
+        let out = if len > 0 {
+            self.encode_frame(0, len)
+        } else {
+            Vec::new()
+        };
+        self.pending.clear();
+        self.finished = true;
+        out
+    }
+
+    fn encode_frame(&mut self, start: usize, len: usize) -> Vec<u8> {
+        let channels = self.channels as usize;
+        let interleaved = &self.pending[start..start + len];
+        let block_len = len / channels;
+        for sample in interleaved {
+            self.md5.update(sample.to_le_bytes());
+        }
+
+        let mut writer = BitWriter::new();
+        writer.write(0xFFF8, 16);
+        let short = block_len != BLOCK_SIZE;
+        writer.write(if short { 0b0111 } else { 0b1100 }, 4);
+        writer.write(sample_rate_code(self.sample_rate), 4);
+        // independent channels
+        writer.write(channels as u64 - 1, 4);
+        writer.write(0b100, 3);
+        writer.write(0, 1);
+        write_utf8(&mut writer, self.frame_number);
+        if short {
+            writer.write(block_len as u64 - 1, 16);
+        }
+        let crc = crc8(&writer.bytes);
+        writer.write(crc as u64, 8);
+
+        for channel in 0..channels {
+            let samples: Vec<i32> = interleaved
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|&s| s as i32)
+                .collect();
+            write_subframe(&mut writer, &samples);
+        }
+        writer.align();
+        let crc = crc16(&writer.bytes);
+        writer.write(crc as u64, 16);
+
+        let size = writer.bytes.len() as u32;
+        self.min_frame_size = match self.min_frame_size {
+            0 => size,
+            min => min.min(size),
+        };
+        self.max_frame_size = self.max_frame_size.max(size);
+        self.frame_number += 1;
+        self.total_samples += block_len as u64;
+        writer.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::{
+        codecs::{CodecParameters, audio::AudioDecoderOptions},
+        formats::{FormatOptions, TrackType, probe::Hint},
+        io::MediaSourceStream,
+        meta::MetadataOptions,
+    };
+    use symphonia::default::{get_codecs, get_probe};
+
+    fn decode(bytes: Vec<u8>) -> (u32, usize, Vec<i16>) {
+        let mss = MediaSourceStream::new(Box::new(std::io::Cursor::new(bytes)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("flac");
+        let mut format = get_probe()
+            .probe(
+                &hint,
+                mss,
+                FormatOptions::default(),
+                MetadataOptions::default(),
+            )
+            .unwrap();
+        let track = format.default_track(TrackType::Audio).unwrap();
+        let params = match track.codec_params.as_ref().unwrap() {
+            CodecParameters::Audio(params) => params.clone(),
+            _ => panic!("not audio"),
+        };
+        let mut decoder = get_codecs()
+            .make_audio_decoder(&params, &AudioDecoderOptions::default())
+            .unwrap();
+        let mut samples = Vec::new();
+        while let Ok(Some(packet)) = format.next_packet() {
+            let mut pcm: Vec<i16> = Vec::new();
+            decoder
+                .decode(&packet)
+                .unwrap()
+                .copy_to_vec_interleaved(&mut pcm);
+            samples.extend(pcm);
+        }
+        (
+            params.sample_rate.unwrap(),
+            params.channels.unwrap().count(),
+            samples,
+        )
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let frames = BLOCK_SIZE * 2 + 1000;
+        let pcm: Vec<i16> = (0..frames)
+            .flat_map(|i| {
+                let t = i as f64 / 16000.0;
+                let voice = (t * 440.0 * std::f64::consts::TAU).sin() * 12000.0;
+                let noise = ((i * 7919) % 61) as f64 - 30.0;
+                // a tone on the left, silence on the right after the first block
+                let right = if i < BLOCK_SIZE { -voice } else { 0.0 };
+                [(voice + noise) as i16, right as i16]
+            })
+            .collect();
+
+        let mut encoder = FlacEncoder::new(16000, 2);
+        let mut stream = encoder.header();
+        for chunk in pcm.chunks(3200) {
+            stream.extend(encoder.encode(chunk));
+        }
+        stream.extend(encoder.finish());
+        stream.splice(..HEADER_LEN, encoder.header());
+        assert!(stream.len() < pcm.len() * 2 / 2, "{}", stream.len());
+
+        let (sample_rate, channels, decoded) = decode(stream);
+        assert_eq!((sample_rate, channels), (16000, 2));
+        assert_eq!(decoded, pcm);
+    }
+
+    #[test]
+    fn test_utf8_frame_number() {
+        let mut writer = BitWriter::new();
+        write_utf8(&mut writer, 0x7F);
+        write_utf8(&mut writer, 0x80);
+        write_utf8(&mut writer, 0x1_0000);
+        assert_eq!(writer.bytes, vec![0x7F, 0xC2, 0x80, 0xF0, 0x90, 0x80, 0x80]);
+    }
+}
@@ -0,0 +1,349 @@
+//! Adaptive receive jitter buffer: packets of one RTP source are reordered
+//! by sequence number and released on a steady clock, one frame each packet
+//! time, after a playout delay that follows the measured interarrival jitter.
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_MIN_DELAY_MS: u32 = 40;
+const DEFAULT_MAX_DELAY_MS: u32 = 200;
+const DEFAULT_FRAME_MS: u64 = 20;
+/// Gaps up to this many frames are concealed, longer ones are skipped
+const MAX_CONCEAL_FRAMES: u64 = 5;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct JitterBufferOption {
+    /// Lowest playout delay in milliseconds. Default: 40.
+    pub min_delay: Option<u32>,
+    /// Highest playout delay in milliseconds, older audio is dropped. Default: 200.
+    pub max_delay: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct JitterStats {
+    pub received: u64,
+    /// Arrived after a packet with a higher sequence number
+    pub reordered: u64,
+    pub duplicated: u64,
+    /// Arrived after their playout time and dropped
+    pub late: u64,
+    /// Never arrived in time
+    pub lost: u64,
+    /// Lost frames replaced by concealment
+    pub concealed: u64,
+    /// Dropped to bring the delay back down to the target
+    pub discarded: u64,
+    /// Interarrival jitter estimate, RFC 3550 section 6.4.1
+    pub jitter_ms: f64,
+    /// Current target playout delay
+    pub delay_ms: u64,
+}
+
+pub enum JitterOutput<T> {
+    Packet(T),
+    /// The frame due now is missing and should be concealed
+    Lost,
+}
+
+pub struct JitterBuffer<T> {
+    min_delay: Duration,
+    max_delay: Duration,
+    frame: Duration,
+    delay: Duration,
+    packets: BTreeMap<u64, T>,
+    highest: Option<u64>,
+    next_seq: Option<u64>,
+    next_play_at: Option<Instant>,
+    stalled: bool,
+    last_arrival: Option<(Instant, u32, u32)>,
+    last_packet: Option<(u64, u32)>,
+    jitter: f64,
+    stats: JitterStats,
+}
+
+impl<T> JitterBuffer<T> {
+    pub fn new(option: &JitterBufferOption) -> Self {
+        let min_delay = option.min_delay.unwrap_or(DEFAULT_MIN_DELAY_MS);
+        let max_delay = option.max_delay.unwrap_or(DEFAULT_MAX_DELAY_MS);
+        let min_delay = Duration::from_millis(min_delay as u64);
+        let max_delay = Duration::from_millis(max_delay as u64).max(min_delay);
+        Self {
+            min_delay,
+            max_delay,
+            frame: Duration::from_millis(DEFAULT_FRAME_MS),
+            delay: min_delay,
+            packets: BTreeMap::new(),
+            highest: None,
+            next_seq: None,
+            next_play_at: None,
+            stalled: false,
+            last_arrival: None,
+            last_packet: None,
+            jitter: 0.0,
+            stats: JitterStats {
+                delay_ms: min_delay.as_millis() as u64,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats.clone()
+    }
+
+    /// Starts over for a new source, keeping the stats and jitter estimate
+    pub fn reset(&mut self) {
+        self.packets.clear();
+        self.highest = None;
+        self.next_seq = None;
+        self.next_play_at = None;
+        self.stalled = false;
+        self.last_arrival = None;
+        self.last_packet = None;
+    }
+
+    /// Extends a 16-bit sequence number to the one closest to the highest seen
+    fn extend(&self, seq: u16) -> u64 {
+        let Some(highest) = self.highest else {
+            return (1 << 16) + seq as u64;
+        };
+        let candidate = (highest & !0xFFFF) | seq as u64;
+        [candidate + (1 << 16), candidate.saturating_sub(1 << 16)]
+            .into_iter()
+            .fold(candidate, |best, c| {
+                if c.abs_diff(highest) < best.abs_diff(highest) {
+                    c
+                } else {
+                    best
+                }
+            })
+    }
+
+    fn target_delay(&self) -> Duration {
+        let jitter = Duration::from_secs_f64(self.jitter * 3.0 / 1000.0);
+        (self.frame + jitter).clamp(self.min_delay, self.max_delay)
+    }
+
+    fn update_jitter(&mut self, rtp_timestamp: u32, clock_rate: u32, now: Instant) {
+        if clock_rate == 0 {
+            return;
+        }
+        if let Some((arrival, timestamp, rate)) = self.last_arrival
+            && rate == clock_rate
+        {
+            let arrival_ms = now.duration_since(arrival).as_secs_f64() * 1000.0;
+            let timestamp_ms =
+                rtp_timestamp.wrapping_sub(timestamp) as i32 as f64 * 1000.0 / clock_rate as f64;
+            self.jitter += ((arrival_ms - timestamp_ms).abs() - self.jitter) / 16.0;
+            self.stats.jitter_ms = self.jitter;
+        }
+        self.last_arrival = Some((now, rtp_timestamp, clock_rate));
+    }
+
+    pub fn push(&mut self, seq: u16, rtp_timestamp: u32, clock_rate: u32, item: T, now: Instant) {
+        let seq = self.extend(seq);
+        self.stats.received += 1;
+        self.update_jitter(rtp_timestamp, clock_rate, now);
+
+        if self.next_seq.is_some_and(|next| seq < next) {
+            self.stats.late += 1;
+            return;
+        }
+        if self.packets.contains_key(&seq) {
+            self.stats.duplicated += 1;
+            return;
+        }
+        match self.highest {
+            Some(highest) if seq < highest => self.stats.reordered += 1,
+            _ => self.highest = Some(seq),
+        }
+        if let Some((last_seq, last_timestamp)) = self.last_packet
+            && seq == last_seq + 1
+            && clock_rate > 0
+        {
+            let ms = rtp_timestamp.wrapping_sub(last_timestamp) as u64 * 1000 / clock_rate as u64;
+            if (5..=120).contains(&ms) {
+                self.frame = Duration::from_millis(ms);
+            }
+        }
+        self.last_packet = Some((seq, rtp_timestamp));
+        self.packets.insert(seq, item);
+
+        if self.next_seq.is_none() || self.stalled {
+            // (re)start playout, the only point the delay grows without a glitch
+            self.delay = self.target_delay();
+            self.stats.delay_ms = self.delay.as_millis() as u64;
+            self.next_seq = Some(self.next_seq.unwrap_or(seq).min(seq));
+            self.next_play_at = Some(now + self.delay);
+            self.stalled = false;
+        }
+
+        // never hold more than the max delay
+        let max_frames = (self.max_delay.as_millis() / self.frame.as_millis().max(1)) as u64;
+        while let (Some((&first, _)), Some(highest)) =
+            (self.packets.first_key_value(), self.highest)
+            && highest - first + 1 > max_frames.max(1)
+        {
+            self.packets.pop_first();
+            self.stats.discarded += 1;
+            self.next_seq = self.packets.first_key_value().map(|(&seq, _)| seq);
+        }
+    }
+
+    /// When the next frame is due, none while waiting for packets
+    pub fn next_due(&self) -> Option<Instant> {
+        if self.stalled || self.packets.is_empty() {
+            return None;
+        }
+        self.next_play_at
+    }
+
+    /// The frame due at `now`, if any
+    pub fn pop(&mut self, now: Instant) -> Option<JitterOutput<T>> {
+        let play_at = self.next_play_at?;
+        if self.stalled || now < play_at {
+            return None;
+        }
+        loop {
+            let next = self.next_seq?;
+            let Some(&first) = self.packets.keys().next() else {
+                // underrun: wait for the next packet to resync
+                self.stalled = true;
+                return None;
+            };
+            if first > next + MAX_CONCEAL_FRAMES {
+                self.stats.lost += first - next;
+                self.next_seq = Some(first);
+                continue;
+            }
+            self.next_seq = Some(next + 1);
+            self.next_play_at = Some(play_at + self.frame);
+            if first > next {
+                self.stats.lost += 1;
+                self.stats.concealed += 1;
+                return Some(JitterOutput::Lost);
+            }
+            let item = self.packets.remove(&first)?;
+            // shrink a delay grown past the target by dropping a frame
+            let buffered = self.frame * self.packets.len() as u32;
+            if buffered > self.target_delay() + self.frame * 2
+                && let Some((&seq, _)) = self.packets.first_key_value()
+            {
+                self.packets.remove(&seq);
+                self.stats.discarded += 1;
+                self.next_seq = Some(seq + 1);
+            }
+            return Some(JitterOutput::Packet(item));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(buffer: &mut JitterBuffer<u16>, now: Instant) -> Vec<Option<u16>> {
+        let mut out = Vec::new();
+        while let Some(output) = buffer.pop(now) {
+            out.push(match output {
+                JitterOutput::Packet(seq) => Some(seq),
+                JitterOutput::Lost => None,
+            });
+        }
+        out
+    }
+
+    /// Feeds `(seq, arrival ms)` packets 20ms apart, draining every 5ms
+    fn simulate(
+        buffer: &mut JitterBuffer<u16>,
+        arrivals: &[(u16, u64)],
+        until: u64,
+    ) -> Vec<Option<u16>> {
+        let start = Instant::now();
+        let mut out = Vec::new();
+        for ms in (0..=until).step_by(5) {
+            let now = start + Duration::from_millis(ms);
+            for &(seq, _) in arrivals.iter().filter(|(_, at)| *at == ms) {
+                buffer.push(seq, seq as u32 * 160, 8000, seq, now);
+            }
+            out.extend(drain(buffer, now));
+        }
+        out
+    }
+
+    #[test]
+    fn test_reorder_and_conceal() {
+        let mut buffer = JitterBuffer::new(&JitterBufferOption::default());
+        let arrivals = [
+            (1, 0),
+            (3, 40),
+            (2, 45),
+            (4, 60),
+            (6, 100),
+            (7, 120),
+            (7, 120),
+            (5, 150),
+        ];
+        let out = simulate(&mut buffer, &arrivals, 200);
+        assert_eq!(
+            out,
+            vec![Some(1), Some(2), Some(3), Some(4), None, Some(6), Some(7)]
+        );
+
+        let stats = buffer.stats();
+        assert_eq!(
+            (stats.received, stats.reordered, stats.duplicated),
+            (8, 1, 1)
+        );
+        assert_eq!((stats.lost, stats.concealed, stats.late), (1, 1, 1));
+        assert_eq!(stats.delay_ms, 40);
+        assert!(buffer.next_due().is_none());
+    }
+
+    #[test]
+    fn test_underrun_and_wrap() {
+        let start = Instant::now();
+        let mut buffer = JitterBuffer::new(&JitterBufferOption {
+            min_delay: Some(20),
+            max_delay: Some(100),
+        });
+        buffer.push(65535, 0, 8000, 65535, start);
+        let later = start + Duration::from_millis(60);
+        assert_eq!(drain(&mut buffer, later), vec![Some(65535)]);
+        assert!(buffer.next_due().is_none());
+
+        // resyncs on the next packet, across the sequence wrap
+        buffer.push(0, 160, 8000, 0, later);
+        assert!(buffer.pop(later).is_none());
+        let out = drain(&mut buffer, later + Duration::from_millis(40));
+        assert_eq!(out, vec![Some(0)]);
+
+        // a long gap is skipped rather than concealed
+        buffer.push(20, 160 * 21, 8000, 20, later);
+        let out = drain(&mut buffer, later + Duration::from_millis(100));
+        assert_eq!(out, vec![Some(20)]);
+        assert_eq!(buffer.stats().lost, 19);
+        assert_eq!(buffer.stats().concealed, 0);
+    }
+
+    #[test]
+    fn test_max_delay() {
+        let start = Instant::now();
+        let mut buffer = JitterBuffer::new(&JitterBufferOption {
+            min_delay: Some(20),
+            max_delay: Some(100),
+        });
+        for seq in 0..10 {
+            buffer.push(seq, seq as u32 * 160, 8000, seq, start);
+        }
+        assert_eq!(buffer.stats().discarded, 5);
+        let out = drain(&mut buffer, start + Duration::from_millis(20));
+        assert_eq!(out, vec![Some(5)]);
+    }
+}
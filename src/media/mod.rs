@@ -4,15 +4,22 @@ pub mod agc;
 pub mod ambiance;
 pub mod asr_processor;
 pub mod cache;
+pub mod conference;
 pub mod denoiser;
 pub mod dtmf;
 pub mod engine;
+pub mod flac;
 pub mod inactivity;
+pub mod jitter_buffer;
 pub mod loader;
 pub mod negotiate;
+pub mod ogg_opus;
+pub mod plc;
 pub mod processor;
+pub mod quality;
 pub mod realtime_processor;
 pub mod recorder;
+pub mod redaction;
 #[cfg(feature = "ringback-detection")]
 pub mod ringback_detection;
 pub mod stream;
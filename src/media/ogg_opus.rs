@@ -0,0 +1,235 @@
+//! Streaming Ogg/Opus encoder (RFC 7845): 20ms Opus packets in Ogg pages,
+//! a page closed every second so a recording cut short stays playable.
+use anyhow::{Result, anyhow};
+use audio_codec::{BoxedResampler, opus::OpusEncoder};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+const FRAME_MS: u32 = 20;
+const PACKETS_PER_PAGE: u64 = 50;
+/// Largest Opus packet, RFC 6716
+const MAX_PACKET: usize = 1275;
+const BITRATE_PER_CHANNEL: i32 = 24_000;
+/// Granule positions always count 48 kHz samples
+const GRANULE_RATE: u32 = 48_000;
+
+fn is_opus_rate(sample_rate: u32) -> bool {
+    matches!(sample_rate, 8000 | 12000 | 16000 | 24000 | 48000)
+}
+
+pub struct OggOpusEncoder {
+    encoder: OpusEncoder,
+    writer: PacketWriter<'static, Vec<u8>>,
+    serial: u32,
+    input_rate: u32,
+    encode_rate: u32,
+    channels: u16,
+    /// Per channel, for input rates Opus cannot encode
+    resamplers: Vec<BoxedResampler>,
+    pending: Vec<i16>,
+    granule: u64,
+    packets: u64,
+    /// Held back so the last packet can end the stream
+    last: Option<Vec<u8>>,
+}
+
+impl OggOpusEncoder {
+    /// Starts a stream of interleaved `channels` audio at `sample_rate`,
+    /// resampled to 48 kHz when Opus has no mode for that rate
+    pub fn new(sample_rate: u32, channels: u16) -> Result<Self> {
+        let channels = channels.clamp(1, 2);
+        let encode_rate = if is_opus_rate(sample_rate) {
+            sample_rate
+        } else {
+            GRANULE_RATE
+        };
+        let resamplers = if encode_rate != sample_rate {
+            (0..channels)
+                .map(|_| {
+                    BoxedResampler::new(sample_rate as usize, encode_rate as usize)
+                        .map_err(|e| anyhow!("ogg/opus: resampler {:?}", e))
+                })
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+        let mut encoder = OpusEncoder::new(encode_rate, channels);
+        encoder.set_bitrate(BITRATE_PER_CHANNEL * channels as i32);
+
+        let mut this = Self {
+            encoder,
+            writer: PacketWriter::new(Vec::new()),
+            serial: rand::random(),
+            input_rate: sample_rate,
+            encode_rate,
+            channels,
+            resamplers,
+            pending: Vec::new(),
+            granule: 0,
+            packets: 0,
+            last: None,
+        };
+        this.write_headers()?;
+        Ok(this)
+    }
+
+    fn write_headers(&mut self) -> Result<()> {
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1);
+        head.push(self.channels as u8);
+        // pre-skip
+        head.extend_from_slice(&0u16.to_le_bytes());
+        head.extend_from_slice(&self.input_rate.to_le_bytes());
+        // output gain
+        head.extend_from_slice(&0i16.to_le_bytes());
+        // mapping family 0, mono or stereo
+        head.push(0);
+        self.writer
+            .write_packet(head, self.serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = concat!("active-call ", env!("CARGO_PKG_VERSION"));
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        self.writer
+            .write_packet(tags, self.serial, PacketWriteEndInfo::EndPage, 0)?;
+        Ok(())
+    }
+
+    fn frame_len(&self) -> usize {
+        (self.encode_rate * FRAME_MS / 1000) as usize * self.channels as usize
+    }
+
+    fn resample(&mut self, pcm: &[i16]) -> Vec<i16> {
+        if self.resamplers.is_empty() {
+            return pcm.to_vec();
+        }
+        let channels = self.channels as usize;
+        let planes: Vec<Vec<i16>> = self
+            .resamplers
+            .iter_mut()
+            .enumerate()
+            .map(|(channel, resampler)| {
+                let plane: Vec<i16> = pcm
+                    .iter()
+                    .skip(channel)
+                    .step_by(channels)
+                    .copied()
+                    .collect();
+                resampler.resample(&plane)
+            })
+            .collect();
+        let len = planes.iter().map(Vec::len).min().unwrap_or(0);
+        (0..len)
+            .flat_map(|i| planes.iter().map(move |plane| plane[i]))
+            .collect()
+    }
+
+    /// Encodes interleaved samples, returning the Ogg pages completed
+    pub fn encode(&mut self, pcm: &[i16]) -> Result<Vec<u8>> {
+        let resampled = self.resample(pcm);
+        self.pending.extend_from_slice(&resampled);
+        let frame_len = self.frame_len();
+        let mut start = 0;
+        while self.pending.len() - start >= frame_len {
+            let frame = self.pending[start..start + frame_len].to_vec();
+            self.encode_frame(&frame)?;
+            start += frame_len;
+        }
+        self.pending.drain(..start);
+        Ok(std::mem::take(self.writer.inner_mut()))
+    }
+
+    fn encode_frame(&mut self, frame: &[i16]) -> Result<()> {
+        let mut packet = [0u8; MAX_PACKET];
+        let len = self
+            .encoder
+            .encode_into_raw(frame, &mut packet)
+            .ok_or_else(|| anyhow!("ogg/opus: failed to encode frame"))?;
+        if let Some(last) = self.last.take() {
+            self.write_packet(last, PacketWriteEndInfo::NormalPacket)?;
+        }
+        self.last = Some(packet[..len].to_vec());
+        Ok(())
+    }
+
+    fn write_packet(&mut self, packet: Vec<u8>, end: PacketWriteEndInfo) -> Result<()> {
+        let frame = (self.encode_rate * FRAME_MS / 1000) as u64;
+        self.granule += frame * (GRANULE_RATE / self.encode_rate) as u64;
+        self.packets += 1;
+        let end = match end {
+            PacketWriteEndInfo::NormalPacket if self.packets.is_multiple_of(PACKETS_PER_PAGE) => {
+                PacketWriteEndInfo::EndPage
+            }
+            end => end,
+        };
+        self.writer
+            .write_packet(packet, self.serial, end, self.granule)?;
+        Ok(())
+    }
+
+    /// Encodes the samples left, padded with silence, and ends the stream
+    pub fn finish(&mut self) -> Result<Vec<u8>> {
+        if !self.pending.is_empty() || self.last.is_none() {
+            let mut frame = std::mem::take(&mut self.pending);
+            frame.resize(self.frame_len(), 0);
+            self.encode_frame(&frame)?;
+        }
+        if let Some(last) = self.last.take() {
+            self.write_packet(last, PacketWriteEndInfo::EndStream)?;
+        }
+        Ok(std::mem::take(self.writer.inner_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::loader::decode_audio;
+    use std::io::Write;
+
+    fn encode(sample_rate: u32, channels: u16, pcm: &[i16]) -> Vec<u8> {
+        let mut encoder = OggOpusEncoder::new(sample_rate, channels).unwrap();
+        let mut stream = Vec::new();
+        for chunk in pcm.chunks(3000) {
+            stream.extend(encoder.encode(chunk).unwrap());
+        }
+        stream.extend(encoder.finish().unwrap());
+        stream
+    }
+
+    fn decode(stream: &[u8], sample_rate: u32) -> Vec<i16> {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(stream).unwrap();
+        std::io::Seek::rewind(&mut file).unwrap();
+        decode_audio(file, "ogg", None, sample_rate).unwrap()
+    }
+
+    #[test]
+    fn test_ogg_opus_stream() {
+        // one second of a 440 Hz tone
+        let pcm: Vec<i16> = (0..16000)
+            .map(|i| ((i as f64 / 16000.0 * 440.0 * std::f64::consts::TAU).sin() * 12000.0) as i16)
+            .collect();
+        let stream = encode(16000, 1, &pcm);
+        assert!(stream.starts_with(b"OggS"));
+        assert!(stream.len() < pcm.len() / 4, "{}", stream.len());
+
+        let decoded = decode(&stream, 16000);
+        assert!(decoded.len().abs_diff(pcm.len()) < 640, "{}", decoded.len());
+        let energy = |pcm: &[i16]| pcm.iter().map(|&s| (s as f64).powi(2)).sum::<f64>();
+        let ratio = energy(&decoded) / energy(&pcm);
+        assert!((0.5..2.0).contains(&ratio), "{}", ratio);
+    }
+
+    #[test]
+    fn test_resampled_stereo() {
+        let pcm = vec![1000i16; 44100 * 2 / 2];
+        let stream = encode(44100, 2, &pcm);
+        let decoded = decode(&stream, 48000);
+        // half a second at 48 kHz, the two channels mixed down
+        assert!(decoded.len().abs_diff(24000) < 1920, "{}", decoded.len());
+    }
+}
@@ -0,0 +1,201 @@
+//! Waveform-repetition packet loss concealment, after ITU-T G.711 Appendix I:
+//! a lost frame repeats the last pitch period of the audio before it, fading
+//! out over consecutive losses, and the next good frame is cross-faded in.
+use crate::media::PcmBuf;
+
+const HISTORY_MS: u32 = 40;
+const MIN_PITCH_US: u32 = 2500;
+const MAX_PITCH_MS: u32 = 15;
+const CORRELATION_MS: u32 = 10;
+/// Concealment plays at full level for this long, then fades out
+const FADE_START_MS: u32 = 10;
+/// ...reaching silence this much later
+const FADE_MS: u32 = 50;
+const MERGE_MS: u32 = 4;
+const DEFAULT_FRAME_MS: u32 = 20;
+
+pub struct WaveformPlc {
+    sample_rate: u32,
+    history: Vec<i16>,
+    frame_len: usize,
+    /// Pitch period being repeated, empty unless concealing
+    pitch: Vec<i16>,
+    offset: usize,
+    concealed: usize,
+}
+
+impl Default for WaveformPlc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaveformPlc {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: 8000,
+            history: Vec::new(),
+            frame_len: 0,
+            pitch: Vec::new(),
+            offset: 0,
+            concealed: 0,
+        }
+    }
+
+    /// Sample rate of the audio seen so far, concealed frames use the same
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn samples(&self, ms: u32) -> usize {
+        (self.sample_rate * ms / 1000) as usize
+    }
+
+    /// Records a decoded mono frame, cross-fading it in after concealment
+    pub fn decoded(&mut self, pcm: &mut [i16], sample_rate: u32) {
+        if pcm.is_empty() {
+            return;
+        }
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.history.clear();
+            self.pitch.clear();
+        }
+        if !self.pitch.is_empty() {
+            let merge = pcm.len().min(self.samples(MERGE_MS));
+            for (i, sample) in pcm.iter_mut().take(merge).enumerate() {
+                let weight = (i + 1) as f32 / (merge + 1) as f32;
+                let continuation = self.next_sample() as f32;
+                *sample = (*sample as f32 * weight + continuation * (1.0 - weight)) as i16;
+            }
+            self.pitch.clear();
+        }
+        self.concealed = 0;
+        self.frame_len = pcm.len();
+        self.remember(pcm);
+    }
+
+    /// Synthesizes the frame of a lost packet
+    pub fn conceal(&mut self) -> PcmBuf {
+        let len = match self.frame_len {
+            0 => self.samples(DEFAULT_FRAME_MS),
+            len => len,
+        };
+        if self.pitch.is_empty() {
+            let period = self.pitch_period();
+            if period == 0 {
+                self.concealed += len;
+                return vec![0; len];
+            }
+            self.pitch = self.history[self.history.len() - period..].to_vec();
+            self.offset = 0;
+        }
+        let frame: PcmBuf = (0..len).map(|_| self.next_sample()).collect();
+        self.remember(&frame);
+        frame
+    }
+
+    fn remember(&mut self, pcm: &[i16]) {
+        self.history.extend_from_slice(pcm);
+        let keep = self.samples(HISTORY_MS);
+        if self.history.len() > keep {
+            self.history.drain(..self.history.len() - keep);
+        }
+    }
+
+    fn next_sample(&mut self) -> i16 {
+        let sample = self.pitch[self.offset] as f32 * self.gain();
+        self.offset = (self.offset + 1) % self.pitch.len();
+        self.concealed += 1;
+        sample as i16
+    }
+
+    fn gain(&self) -> f32 {
+        let start = self.samples(FADE_START_MS);
+        if self.concealed <= start {
+            return 1.0;
+        }
+        let faded = (self.concealed - start) as f32 / self.samples(FADE_MS) as f32;
+        (1.0 - faded).max(0.0)
+    }
+
+    /// Lag that best matches the most recent audio with what preceded it,
+    /// the whole history when there is too little for a search
+    fn pitch_period(&self) -> usize {
+        let min = (self.sample_rate * MIN_PITCH_US / 1_000_000) as usize;
+        let max = self.samples(MAX_PITCH_MS);
+        let window = self.samples(CORRELATION_MS);
+        let end = self.history.len();
+        if end < max + window {
+            return end.min(max);
+        }
+        let recent = &self.history[end - window..];
+        let mut best = (f64::MIN, max);
+        for lag in min..=max {
+            let earlier = &self.history[end - window - lag..end - lag];
+            let (mut correlation, mut energy) = (0f64, 0f64);
+            for (&a, &b) in recent.iter().zip(earlier) {
+                correlation += a as f64 * b as f64;
+                energy += b as f64 * b as f64;
+            }
+            let score = correlation / energy.max(1.0).sqrt();
+            if score > best.0 {
+                best = (score, lag);
+            }
+        }
+        best.1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(start: usize, len: usize, period: usize) -> Vec<i16> {
+        (start..start + len)
+            .map(|i| {
+                (8000.0 * (2.0 * std::f64::consts::PI * i as f64 / period as f64).sin()) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_conceal_repeats_pitch_period() {
+        let mut plc = WaveformPlc::new();
+        // 100 Hz at 8 kHz, an 80 sample pitch period
+        for frame in 0..3 {
+            plc.decoded(&mut sine(frame * 160, 160, 80), 8000);
+        }
+        let concealed = plc.conceal();
+        assert_eq!(concealed.len(), 160);
+        let expected = sine(480, 80, 80);
+        let error = concealed
+            .iter()
+            .zip(&expected)
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(error < 200, "max error {}", error);
+
+        // fades to silence over consecutive losses
+        for _ in 0..3 {
+            plc.conceal();
+        }
+        assert!(plc.conceal().iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_merge_after_concealment() {
+        let mut plc = WaveformPlc::new();
+        assert_eq!(plc.conceal(), vec![0; 160]);
+
+        plc.decoded(&mut vec![1000; 160], 8000);
+        plc.conceal();
+        let mut next = vec![-1000; 160];
+        plc.decoded(&mut next, 8000);
+        // starts near the concealed level and ends at the new frame
+        assert!(next[0] > 0);
+        assert!(next[..32].windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(next[32..], vec![-1000; 128][..]);
+    }
+}
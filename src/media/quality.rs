@@ -0,0 +1,229 @@
+//! Voice quality of an RTP leg from its RTCP statistics: loss, jitter and
+//! round-trip time per direction, rated with the ITU-T G.107 E-model.
+use audio_codec::CodecType;
+use rustrtc::stats::{StatsKind, StatsReport};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_with::skip_serializing_none;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// Latest quality of each RTP track of a call, by track id
+pub type QualityReports = Arc<Mutex<HashMap<String, CallQuality>>>;
+
+/// Delay added on top of the network: one packet time plus the decoder
+const PACKETIZATION_DELAY_MS: f64 = 20.0;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamQuality {
+    pub packets: u64,
+    pub lost: u64,
+    pub loss_percent: f64,
+    pub jitter_ms: f64,
+    pub r_factor: f64,
+    pub mos: f64,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallQuality {
+    /// Audio received from the remote party, as measured here
+    pub inbound: Option<StreamQuality>,
+    /// Audio sent to the remote party, from its RTCP reports
+    pub outbound: Option<StreamQuality>,
+    pub rtt_ms: Option<f64>,
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn sum(values: &[&BTreeMap<String, Value>], key: &str) -> i64 {
+    values
+        .iter()
+        .filter_map(|v| v.get(key).and_then(Value::as_i64))
+        .sum()
+}
+
+fn max(values: &[&BTreeMap<String, Value>], key: &str) -> Option<f64> {
+    values
+        .iter()
+        .filter_map(|v| v.get(key).and_then(Value::as_f64))
+        .reduce(f64::max)
+}
+
+/// Equipment impairment `Ie` and packet-loss robustness `Bpl` from ITU-T
+/// G.113 Appendix I. The narrowband model has no values for G.722 and Opus,
+/// so they are rated like G.711 with concealment.
+fn impairment(codec: CodecType) -> (f64, f64) {
+    match codec {
+        CodecType::G729 => (11.0, 19.0),
+        _ => (0.0, 25.1),
+    }
+}
+
+/// E-model transmission rating for a one-way delay and random packet loss
+pub fn r_factor(codec: CodecType, delay_ms: f64, loss_percent: f64) -> f64 {
+    let delay_impairment = if delay_ms > 177.3 {
+        0.024 * delay_ms + 0.11 * (delay_ms - 177.3)
+    } else {
+        0.024 * delay_ms
+    };
+    let (ie, bpl) = impairment(codec);
+    let loss = loss_percent.clamp(0.0, 100.0);
+    let effective_ie = ie + (95.0 - ie) * loss / (loss + bpl);
+    (93.2 - delay_impairment - effective_ie).clamp(0.0, 100.0)
+}
+
+/// Estimated mean opinion score, 1 to 4.5, for an R-factor
+pub fn mos(r: f64) -> f64 {
+    if r <= 0.0 {
+        1.0
+    } else if r >= 100.0 {
+        4.5
+    } else {
+        1.0 + 0.035 * r + r * (r - 60.0) * (100.0 - r) * 7e-6
+    }
+}
+
+impl StreamQuality {
+    fn rate(
+        codec: CodecType,
+        packets: u64,
+        lost: u64,
+        jitter_ms: f64,
+        rtt_ms: Option<f64>,
+    ) -> Self {
+        let loss_percent = match packets + lost {
+            0 => 0.0,
+            total => lost as f64 * 100.0 / total as f64,
+        };
+        let delay = rtt_ms.unwrap_or_default() / 2.0 + jitter_ms * 2.0 + PACKETIZATION_DELAY_MS;
+        let r = r_factor(codec, delay, loss_percent);
+        Self {
+            packets,
+            lost,
+            loss_percent: round2(loss_percent),
+            jitter_ms: round2(jitter_ms),
+            r_factor: round2(r),
+            mos: round2(mos(r)),
+        }
+    }
+}
+
+impl CallQuality {
+    /// Rates the RTCP statistics of a peer connection sending and receiving
+    /// `codec`, none before any audio flowed
+    pub fn from_stats(report: &StatsReport, codec: CodecType) -> Option<Self> {
+        let entries = |kind: StatsKind| {
+            report
+                .entries
+                .iter()
+                .filter(|e| e.kind == kind)
+                .map(|e| &e.values)
+                .collect::<Vec<_>>()
+        };
+        let units_to_ms = 1000.0 / codec.clock_rate().max(1) as f64;
+
+        let remote = entries(StatsKind::RemoteInboundRtp);
+        let rtt_ms = max(&remote, "roundTripTime").map(|rtt| round2(rtt * 1000.0));
+
+        let local = entries(StatsKind::InboundRtp);
+        let received = sum(&local, "packetsReceived").max(0) as u64;
+        let inbound = (received > 0).then(|| {
+            StreamQuality::rate(
+                codec,
+                received,
+                sum(&local, "packetsLost").max(0) as u64,
+                max(&local, "jitter").unwrap_or_default() * units_to_ms,
+                rtt_ms,
+            )
+        });
+
+        let sent = sum(&entries(StatsKind::OutboundRtp), "packetsSent").max(0) as u64;
+        let outbound = (sent > 0 && !remote.is_empty()).then(|| {
+            let lost = (sum(&remote, "packetsLost").max(0) as u64).min(sent);
+            StreamQuality::rate(
+                codec,
+                sent - lost,
+                lost,
+                max(&remote, "jitter").unwrap_or_default() * units_to_ms,
+                rtt_ms,
+            )
+        });
+
+        if inbound.is_none() && outbound.is_none() {
+            return None;
+        }
+        Some(Self {
+            inbound,
+            outbound,
+            rtt_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustrtc::stats::{StatsEntry, StatsId};
+    use serde_json::json;
+
+    #[test]
+    fn test_e_model() {
+        let clean = r_factor(CodecType::PCMU, 20.0, 0.0);
+        assert!((clean - 92.72).abs() < 0.01);
+        assert!((mos(clean) - 4.40).abs() < 0.01);
+
+        let lossy = r_factor(CodecType::PCMU, 20.0, 5.0);
+        assert!(lossy < 80.0 && lossy > 70.0, "{}", lossy);
+        assert!(r_factor(CodecType::G729, 20.0, 5.0) < lossy);
+        assert!(r_factor(CodecType::PCMU, 400.0, 0.0) < 70.0);
+        assert_eq!(mos(-5.0), 1.0);
+    }
+
+    #[test]
+    fn test_from_stats() {
+        let entry = |kind: StatsKind, values: Value| {
+            let mut entry = StatsEntry::new(StatsId::new("test"), kind);
+            for (key, value) in values.as_object().unwrap() {
+                entry = entry.with_value(key.clone(), value.clone());
+            }
+            entry
+        };
+        let mut report = StatsReport::new(vec![]);
+        assert_eq!(CallQuality::from_stats(&report, CodecType::PCMA), None);
+
+        report.entries = vec![
+            entry(
+                StatsKind::InboundRtp,
+                json!({"ssrc": 1, "packetsReceived": 950, "packetsLost": 50, "jitter": 80}),
+            ),
+            entry(
+                StatsKind::OutboundRtp,
+                json!({"ssrc": 2, "packetsSent": 1000}),
+            ),
+            entry(
+                StatsKind::RemoteInboundRtp,
+                json!({"ssrc": 2, "packetsLost": 0, "jitter": 16, "roundTripTime": 0.12}),
+            ),
+        ];
+        let quality = CallQuality::from_stats(&report, CodecType::PCMA).unwrap();
+        assert_eq!(quality.rtt_ms, Some(120.0));
+
+        let inbound = quality.inbound.unwrap();
+        assert_eq!((inbound.packets, inbound.lost), (950, 50));
+        assert_eq!(inbound.loss_percent, 5.0);
+        assert_eq!(inbound.jitter_ms, 10.0);
+
+        let outbound = quality.outbound.unwrap();
+        assert_eq!((outbound.packets, outbound.lost), (1000, 0));
+        assert_eq!(outbound.jitter_ms, 2.0);
+        assert!(outbound.mos > inbound.mos);
+        assert!(outbound.mos > 4.0);
+    }
+}
@@ -21,7 +21,10 @@ use tokio_stream::wrappers::IntervalStream;
 use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
-use crate::media::{AudioFrame, Samples};
+use crate::media::{
+    AudioFrame, Samples, flac::FlacEncoder, ogg_opus::OggOpusEncoder, processor::convert_to_mono,
+    track::track_codec::TrackCodec,
+};
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -31,11 +34,19 @@ pub enum RecorderFormat {
     Pcmu,
     Pcma,
     G722,
+    /// Opus in an Ogg container
+    #[serde(alias = "ogg")]
+    Opus,
+    Flac,
 }
 
 impl RecorderFormat {
     pub fn extension(&self) -> &'static str {
-        "wav"
+        match self {
+            RecorderFormat::Opus => "ogg",
+            RecorderFormat::Flac => "flac",
+            _ => "wav",
+        }
     }
 
     pub fn is_supported(&self) -> bool {
@@ -53,6 +64,57 @@ impl Default for RecorderFormat {
     }
 }
 
+/// Extensions replaced rather than appended to when the format changes
+const RECORDING_EXTENSIONS: [&str; 4] = ["wav", "ogg", "flac", "mp3"];
+
+pub const CALLER_CHANNEL: &str = "caller";
+pub const AGENT_CHANNEL: &str = "agent";
+
+/// How the caller (the session's own track) and everything else, the agent,
+/// are laid out in the recording
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecorderChannels {
+    /// Both parties mixed into one channel
+    Mono,
+    /// The caller on the left channel, the agent on the right
+    #[default]
+    Stereo,
+    /// One mono file per party, `<name>.caller.<ext>` and `<name>.agent.<ext>`
+    Separate,
+}
+
+impl RecorderChannels {
+    /// Files written for a recording at `path`, with the party each holds
+    /// when they are separate
+    pub fn files(&self, path: &str) -> Vec<(Option<&'static str>, String)> {
+        match self {
+            RecorderChannels::Separate => [CALLER_CHANNEL, AGENT_CHANNEL]
+                .into_iter()
+                .map(|channel| (Some(channel), channel_path(path, channel)))
+                .collect(),
+            _ => vec![(None, path.to_string())],
+        }
+    }
+}
+
+/// `name.wav` becomes `name.<channel>.wav`
+fn channel_path(path: &str, channel: &str) -> String {
+    let p = Path::new(path);
+    match (p.file_stem(), p.extension()) {
+        (Some(stem), Some(extension)) => p
+            .with_file_name(format!(
+                "{}.{}.{}",
+                stem.to_string_lossy(),
+                channel,
+                extension.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .to_string(),
+        _ => format!("{}.{}", path, channel),
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
@@ -65,6 +127,8 @@ pub struct RecorderOption {
     pub ptime: u32,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub format: Option<RecorderFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channels: Option<RecorderChannels>,
 }
 
 impl RecorderOption {
@@ -88,14 +152,22 @@ impl RecorderOption {
         }
 
         let extension = effective_format.extension();
-        if !self
-            .recorder_file
-            .to_lowercase()
-            .ends_with(&format!(".{}", extension.to_lowercase()))
-        {
-            self.recorder_file = format!("{}.{}", self.recorder_file, extension);
+        let path = Path::new(&self.recorder_file);
+        match path.extension().map(|e| e.to_string_lossy().to_lowercase()) {
+            Some(current) if current == extension => {}
+            Some(current) if RECORDING_EXTENSIONS.contains(&current.as_str()) => {
+                self.recorder_file = path.with_extension(extension).to_string_lossy().to_string();
+            }
+            _ => {
+                self.recorder_file = format!("{}.{}", self.recorder_file, extension);
+            }
         }
     }
+
+    /// Files this option records to, see [`RecorderChannels::files`]
+    pub fn output_files(&self) -> Vec<(Option<&'static str>, String)> {
+        self.channels.unwrap_or_default().files(&self.recorder_file)
+    }
 }
 
 impl Default for RecorderOption {
@@ -105,10 +177,48 @@ impl Default for RecorderOption {
             samplerate: 16000,
             ptime: 200,
             format: None,
+            channels: None,
         }
     }
 }
 
+pub(crate) fn wav_header(
+    format_tag: u16,
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u16,
+    data_size: usize,
+) -> Vec<u8> {
+    let mut header_buf = Vec::new();
+    header_buf.extend_from_slice(b"RIFF");
+    let file_size = data_size + 36;
+    header_buf.extend_from_slice(&(file_size as u32).to_le_bytes());
+    header_buf.extend_from_slice(b"WAVE");
+
+    header_buf.extend_from_slice(b"fmt ");
+    header_buf.extend_from_slice(&16u32.to_le_bytes());
+    header_buf.extend_from_slice(&format_tag.to_le_bytes());
+    header_buf.extend_from_slice(&channels.to_le_bytes());
+    header_buf.extend_from_slice(&sample_rate.to_le_bytes());
+
+    let bytes_per_sec: u32 = match format_tag {
+        0x0064 => 8000, // G.722 is 64kbps
+        _ => sample_rate * (channels as u32) * (bits_per_sample as u32 / 8),
+    };
+    header_buf.extend_from_slice(&bytes_per_sec.to_le_bytes());
+
+    let block_align: u16 = match format_tag {
+        0x0064 | 0x0007 | 0x0006 => channels,
+        _ => (bits_per_sample / 8) * channels,
+    };
+    header_buf.extend_from_slice(&block_align.to_le_bytes());
+    header_buf.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+    header_buf.extend_from_slice(b"data");
+    header_buf.extend_from_slice(&(data_size as u32).to_le_bytes());
+    header_buf
+}
+
 pub struct Recorder {
     session_id: String,
     option: RecorderOption,
@@ -163,33 +273,13 @@ impl Recorder {
             None => (0x0001, self.option.samplerate, 2, 16, total),
         };
 
-        let mut header_buf = Vec::new();
-        header_buf.extend_from_slice(b"RIFF");
-        let file_size = data_size + 36;
-        header_buf.extend_from_slice(&(file_size as u32).to_le_bytes());
-        header_buf.extend_from_slice(b"WAVE");
-
-        header_buf.extend_from_slice(b"fmt ");
-        header_buf.extend_from_slice(&16u32.to_le_bytes());
-        header_buf.extend_from_slice(&format_tag.to_le_bytes());
-        header_buf.extend_from_slice(&(channels as u16).to_le_bytes());
-        header_buf.extend_from_slice(&sample_rate.to_le_bytes());
-
-        let bytes_per_sec: u32 = match format_tag {
-            0x0064 => 8000, // G.722 is 64kbps
-            _ => sample_rate * (channels as u32) * (bits_per_sample as u32 / 8),
-        };
-        header_buf.extend_from_slice(&bytes_per_sec.to_le_bytes());
-
-        let block_align: u16 = match format_tag {
-            0x0064 | 0x0007 | 0x0006 => 1 * channels,
-            _ => (bits_per_sample / 8) * channels,
-        };
-        header_buf.extend_from_slice(&block_align.to_le_bytes());
-        header_buf.extend_from_slice(&bits_per_sample.to_le_bytes());
-
-        header_buf.extend_from_slice(b"data");
-        header_buf.extend_from_slice(&(data_size as u32).to_le_bytes());
+        let header_buf = wav_header(
+            format_tag,
+            sample_rate,
+            channels,
+            bits_per_sample,
+            data_size,
+        );
 
         file.seek(std::io::SeekFrom::Start(0)).await?;
         file.write_all(&header_buf).await?;
@@ -208,18 +298,24 @@ impl Recorder {
             None => return Ok(()),
         };
 
-        if let Samples::RTP { .. } = first_frame.samples {
+        // RTP payloads are kept as they are in a wav, and decoded for the
+        // encoded formats
+        if let Samples::RTP { .. } = first_frame.samples
+            && self.format().extension() == "wav"
+        {
             return self
                 .process_recording_rtp(file_path, receiver, first_frame)
                 .await;
         }
 
-        let _requested_format = self.option.format.unwrap_or(RecorderFormat::Wav);
-
-        self.process_recording_wav(file_path, receiver, first_frame)
+        self.process_recording_pcm(file_path, receiver, first_frame)
             .await
     }
 
+    fn format(&self) -> RecorderFormat {
+        self.option.format.unwrap_or_default().effective()
+    }
+
     fn ensure_parent_dir(&self, file_path: &Path) -> Result<()> {
         if let Some(parent) = file_path.parent() {
             if !parent.exists() {
@@ -302,21 +398,34 @@ impl Recorder {
         Ok(())
     }
 
-    async fn process_recording_wav(
+    async fn process_recording_pcm(
         &self,
         file_path: &Path,
         mut receiver: UnboundedReceiver<AudioFrame>,
         first_frame: AudioFrame,
     ) -> Result<()> {
-        let mut file = self.create_output_file(file_path).await?;
-        self.update_wav_header(&mut file, None).await?;
+        let format = self.format();
+        let layout = self.option.channels.unwrap_or_default();
+        let channels = match layout {
+            RecorderChannels::Stereo => 2,
+            _ => 1,
+        };
+        let mut outputs = Vec::new();
+        for (_, path) in layout.files(&file_path.to_string_lossy()) {
+            let file = self.create_output_file(Path::new(&path)).await?;
+            outputs
+                .push(RecordingOutput::new(file, format, self.option.samplerate, channels).await?);
+        }
 
+        let mut decoders = [TrackCodec::new(), TrackCodec::new()];
+        let first_frame = self.decode_frame(&mut decoders, first_frame);
         self.append_frame(first_frame).await.ok();
 
         let chunk_size = (self.option.samplerate / 1000 * self.option.ptime) as usize;
         info!(
             session_id = self.session_id,
-            format = "wav",
+            format = ?format,
+            channels = ?layout,
             "Recording to {} ptime: {}ms chunk_size: {}",
             file_path.display(),
             self.option.ptime,
@@ -329,16 +438,18 @@ impl Recorder {
         loop {
             select! {
                 Some(frame) = receiver.recv() => {
+                    let frame = self.decode_frame(&mut decoders, frame);
                     self.append_frame(frame).await.ok();
                 }
                 _ = interval.next() => {
                     let (mono_buf, stereo_buf) = self.pop(chunk_size).await;
-                    self.process_buffers(&mut file, mono_buf, stereo_buf).await?;
-                    self.update_wav_header(&mut file, None).await?;
+                    self.process_buffers(&mut outputs, layout, mono_buf, stereo_buf).await?;
                 }
                 _ = self.cancel_token.cancelled() => {
-                    self.flush_buffers(&mut file).await?;
-                    self.update_wav_header(&mut file, None).await?;
+                    self.flush_buffers(&mut outputs, layout).await?;
+                    for output in outputs.iter_mut() {
+                        output.finish().await?;
+                    }
                     return Ok(());
                 }
             }
@@ -353,6 +464,29 @@ impl Recorder {
         }
     }
 
+    /// Decodes an RTP audio frame to PCM at the recording's sample rate,
+    /// with a decoder per party
+    fn decode_frame(&self, decoders: &mut [TrackCodec; 2], mut frame: AudioFrame) -> AudioFrame {
+        if let Samples::RTP {
+            payload_type,
+            payload,
+            ..
+        } = &frame.samples
+        {
+            if !TrackCodec::is_audio(*payload_type) {
+                frame.samples = Samples::Empty;
+                return frame;
+            }
+            let decoder = &mut decoders[self.get_channel_index(&frame.track_id)];
+            let (sample_rate, channels, mut samples) =
+                decoder.decode(*payload_type, payload, self.option.samplerate);
+            convert_to_mono(&mut samples, channels);
+            frame.samples = Samples::PCM { samples };
+            frame.sample_rate = sample_rate;
+        }
+        frame
+    }
+
     async fn append_frame(&self, frame: AudioFrame) -> Result<()> {
         let buffer = match frame.samples {
             Samples::PCM { samples } => samples,
@@ -463,9 +597,19 @@ impl Recorder {
         mix_buff
     }
 
+    /// Caller and agent summed into one channel
+    pub(crate) fn mix_down(mono_buf: &PcmBuf, stereo_buf: &PcmBuf) -> Vec<i16> {
+        mono_buf
+            .iter()
+            .zip(stereo_buf)
+            .map(|(&a, &b)| a.saturating_add(b))
+            .collect()
+    }
+
     async fn write_audio_data(
         &self,
-        file: &mut File,
+        outputs: &mut [RecordingOutput],
+        layout: RecorderChannels,
         mono_buf: &PcmBuf,
         stereo_buf: &PcmBuf,
     ) -> Result<usize> {
@@ -474,24 +618,38 @@ impl Recorder {
             return Ok(0);
         }
 
-        let mix_buff = Self::mix_buffers(mono_buf, stereo_buf);
-
-        file.seek(std::io::SeekFrom::End(0)).await?;
-        file.write_all(&samples_to_bytes(&mix_buff)).await?;
+        match (layout, outputs) {
+            (RecorderChannels::Separate, [caller, agent]) => {
+                caller.write(mono_buf).await?;
+                agent.write(stereo_buf).await?;
+            }
+            (RecorderChannels::Mono, [output]) => {
+                output.write(&Self::mix_down(mono_buf, stereo_buf)).await?;
+            }
+            (_, [output]) => {
+                output
+                    .write(&Self::mix_buffers(mono_buf, stereo_buf))
+                    .await?;
+            }
+            _ => return Err(anyhow!("recorder: outputs do not match {:?}", layout)),
+        }
 
         Ok(max_len)
     }
 
     async fn process_buffers(
         &self,
-        file: &mut File,
+        outputs: &mut [RecordingOutput],
+        layout: RecorderChannels,
         mono_buf: PcmBuf,
         stereo_buf: PcmBuf,
     ) -> Result<()> {
         if mono_buf.is_empty() && stereo_buf.is_empty() {
             return Ok(());
         }
-        let samples_written = self.write_audio_data(file, &mono_buf, &stereo_buf).await?;
+        let samples_written = self
+            .write_audio_data(outputs, layout, &mono_buf, &stereo_buf)
+            .await?;
         if samples_written > 0 {
             self.samples_written
                 .fetch_add(samples_written * 4, Ordering::SeqCst);
@@ -499,7 +657,11 @@ impl Recorder {
         Ok(())
     }
 
-    async fn flush_buffers(&self, file: &mut File) -> Result<()> {
+    async fn flush_buffers(
+        &self,
+        outputs: &mut [RecordingOutput],
+        layout: RecorderChannels,
+    ) -> Result<()> {
         loop {
             let (mono_buf, stereo_buf) = self.pop(usize::MAX).await;
 
@@ -507,13 +669,104 @@ impl Recorder {
                 break;
             }
 
-            let samples_written = self.write_audio_data(file, &mono_buf, &stereo_buf).await?;
-            if samples_written > 0 {
-                self.samples_written
-                    .fetch_add(samples_written * 4, Ordering::SeqCst);
+            self.process_buffers(outputs, layout, mono_buf, stereo_buf)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+enum OutputEncoder {
+    Wav,
+    Opus(Box<OggOpusEncoder>),
+    Flac(FlacEncoder),
+}
+
+/// One recording file, encoded as it is written so only a chunk of audio is
+/// ever held in memory
+struct RecordingOutput {
+    file: File,
+    encoder: OutputEncoder,
+    sample_rate: u32,
+    channels: u16,
+    data_size: usize,
+}
+
+impl RecordingOutput {
+    async fn new(
+        mut file: File,
+        format: RecorderFormat,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self> {
+        let encoder = match format {
+            RecorderFormat::Opus => {
+                OutputEncoder::Opus(Box::new(OggOpusEncoder::new(sample_rate, channels)?))
             }
+            RecorderFormat::Flac => OutputEncoder::Flac(FlacEncoder::new(sample_rate, channels)),
+            _ => OutputEncoder::Wav,
+        };
+        let header = match &encoder {
+            OutputEncoder::Wav => wav_header(0x0001, sample_rate, channels, 16, 0),
+            OutputEncoder::Flac(flac) => flac.header(),
+            OutputEncoder::Opus(_) => Vec::new(),
+        };
+        file.write_all(&header).await?;
+        Ok(Self {
+            file,
+            encoder,
+            sample_rate,
+            channels,
+            data_size: 0,
+        })
+    }
+
+    /// Writes interleaved samples, keeping a WAV header current so the file
+    /// stays playable while the call goes on
+    async fn write(&mut self, pcm: &[i16]) -> Result<()> {
+        let bytes = match &mut self.encoder {
+            OutputEncoder::Wav => samples_to_bytes(pcm),
+            OutputEncoder::Opus(opus) => opus.encode(pcm)?,
+            OutputEncoder::Flac(flac) => flac.encode(pcm),
+        };
+        self.file.write_all(&bytes).await?;
+        self.data_size += bytes.len();
+        if let OutputEncoder::Wav = self.encoder {
+            self.rewrite_header(wav_header(
+                0x0001,
+                self.sample_rate,
+                self.channels,
+                16,
+                self.data_size,
+            ))
+            .await?;
         }
+        Ok(())
+    }
 
+    async fn rewrite_header(&mut self, header: Vec<u8>) -> Result<()> {
+        self.file.seek(std::io::SeekFrom::Start(0)).await?;
+        self.file.write_all(&header).await?;
+        self.file.seek(std::io::SeekFrom::End(0)).await?;
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        match &mut self.encoder {
+            OutputEncoder::Wav => {}
+            OutputEncoder::Opus(opus) => {
+                let tail = opus.finish()?;
+                self.file.write_all(&tail).await?;
+            }
+            OutputEncoder::Flac(flac) => {
+                let tail = flac.finish();
+                let header = flac.header();
+                self.file.write_all(&tail).await?;
+                self.rewrite_header(header).await?;
+            }
+        }
+        self.file.flush().await?;
         Ok(())
     }
 }
@@ -0,0 +1,447 @@
+//! Redaction of sensitive segments. Spans flagged during the call (a paused
+//! recording, a transcript matching a redaction pattern) are kept as offsets
+//! from the start of the recording, listed in the call record for audit and,
+//! once the recorder has closed its files, blanked out of the audio.
+use crate::media::{flac::FlacEncoder, ogg_opus::OggOpusEncoder};
+use anyhow::{Result, anyhow, bail};
+use audio_codec::opus::OpusDecoder;
+use ogg::reading::PacketReader;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+use tracing::warn;
+
+/// Span reason of a recording paused by command
+pub const REASON_PAUSED: &str = "paused";
+/// Span reason of a transcript matching a redaction pattern
+pub const REASON_TRANSCRIPT: &str = "transcript";
+
+const BEEP_HZ: f64 = 1000.0;
+const BEEP_AMPLITUDE: f64 = 6000.0;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionMode {
+    #[default]
+    Silence,
+    Beep,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionOption {
+    /// Regexes over final transcripts; a match redacts the utterance audio
+    /// and masks the matched text in event dumps
+    pub patterns: Option<Vec<String>>,
+    /// What replaces redacted audio, silence by default
+    pub mode: Option<RedactionMode>,
+    /// Mask DTMF digits in event dumps even while recording
+    pub mask_dtmf: Option<bool>,
+    /// Milliseconds added on both sides of a transcript span, 200 by default
+    pub padding: Option<u32>,
+}
+
+impl RedactionOption {
+    pub fn mode(&self) -> RedactionMode {
+        self.mode.unwrap_or_default()
+    }
+
+    pub fn mask_dtmf(&self) -> bool {
+        self.mask_dtmf.unwrap_or(false)
+    }
+
+    pub fn padding(&self) -> u64 {
+        self.padding.unwrap_or(200) as u64
+    }
+}
+
+/// A redacted stretch of the recording, in milliseconds from its start
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RedactionSpan {
+    pub start: u64,
+    pub end: u64,
+    pub reason: String,
+}
+
+/// Replaces every character with `*`, keeping the length
+pub fn mask(text: &str) -> String {
+    "*".repeat(text.chars().count())
+}
+
+/// Compiled [`RedactionOption::patterns`]
+#[derive(Debug)]
+pub struct TranscriptRedactor {
+    patterns: Vec<Regex>,
+}
+
+impl TranscriptRedactor {
+    /// `None` without valid patterns; invalid ones are logged and skipped
+    pub fn new(option: &RedactionOption) -> Option<Self> {
+        let patterns: Vec<Regex> = option
+            .patterns
+            .iter()
+            .flatten()
+            .filter_map(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    warn!(pattern, "redaction: invalid pattern: {}", e);
+                    None
+                }
+            })
+            .collect();
+        if patterns.is_empty() {
+            None
+        } else {
+            Some(Self { patterns })
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.patterns.iter().any(|regex| regex.is_match(text))
+    }
+
+    /// Masks every match, leaving the rest of the text readable
+    pub fn redact(&self, text: &str) -> String {
+        self.patterns.iter().fold(text.to_string(), |text, regex| {
+            regex
+                .replace_all(&text, |caps: &regex::Captures| mask(&caps[0]))
+                .into_owned()
+        })
+    }
+}
+
+/// Overwrites `pcm`, interleaved and starting at `first_frame`, where it
+/// falls inside `spans`
+fn apply(
+    pcm: &mut [i16],
+    first_frame: u64,
+    sample_rate: u32,
+    channels: usize,
+    spans: &[RedactionSpan],
+    mode: RedactionMode,
+) {
+    let channels = channels.max(1);
+    let frames = (pcm.len() / channels) as u64;
+    let rate = sample_rate as u64;
+    for span in spans {
+        let start = (span.start * rate / 1000).max(first_frame);
+        let end = (span.end * rate / 1000).min(first_frame + frames);
+        for frame in start..end {
+            let sample = match mode {
+                RedactionMode::Silence => 0,
+                RedactionMode::Beep => {
+                    let t = frame as f64 / sample_rate as f64;
+                    ((t * BEEP_HZ * std::f64::consts::TAU).sin() * BEEP_AMPLITUDE) as i16
+                }
+            };
+            let offset = (frame - first_frame) as usize * channels;
+            pcm[offset..offset + channels].fill(sample);
+        }
+    }
+}
+
+/// Blanks `spans` out of a finished recording, picking the codec from the
+/// file extension. WAV is patched in place, FLAC and Ogg/Opus re-encoded.
+pub fn redact_file(path: &Path, spans: &[RedactionSpan], mode: RedactionMode) -> Result<()> {
+    if spans.is_empty() {
+        return Ok(());
+    }
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("wav") => redact_wav(path, spans, mode),
+        Some("flac") => reencode(path, spans, mode, redact_flac),
+        Some("ogg") => reencode(path, spans, mode, redact_ogg_opus),
+        _ => bail!("redaction: unsupported recording {}", path.display()),
+    }
+}
+
+fn redact_wav(path: &Path, spans: &[RedactionSpan], mode: RedactionMode) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        bail!("redaction: {} is not a wav file", path.display());
+    }
+    let mut format = None;
+    let data = loop {
+        let mut chunk = [0u8; 8];
+        file.read_exact(&mut chunk)?;
+        let size = u32::from_le_bytes(chunk[4..8].try_into()?) as u64;
+        let offset = file.stream_position()?;
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                file.read_exact(&mut fmt)?;
+                let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let rate = u32::from_le_bytes(fmt[4..8].try_into()?);
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                if tag != 1 || bits != 16 {
+                    bail!("redaction: only 16-bit PCM wav is supported");
+                }
+                format = Some((rate, channels as usize));
+            }
+            // a recorder cut short may leave the size unset
+            b"data" if size == 0 => break (offset, file_len - offset),
+            b"data" => break (offset, size.min(file_len - offset)),
+            _ => {}
+        }
+        file.seek(SeekFrom::Start(offset + size + (size & 1)))?;
+    };
+    let (sample_rate, channels) =
+        format.ok_or_else(|| anyhow!("redaction: wav without fmt chunk"))?;
+    let (data_offset, data_len) = data;
+    let frame_bytes = 2 * channels as u64;
+    let total_frames = data_len / frame_bytes;
+
+    for span in spans {
+        let start = (span.start * sample_rate as u64 / 1000).min(total_frames);
+        let end = (span.end * sample_rate as u64 / 1000).min(total_frames);
+        if start >= end {
+            continue;
+        }
+        let mut pcm = vec![0i16; ((end - start) as usize) * channels];
+        apply(
+            &mut pcm,
+            start,
+            sample_rate,
+            channels,
+            std::slice::from_ref(span),
+            mode,
+        );
+        let bytes: Vec<u8> = pcm.iter().flat_map(|s| s.to_le_bytes()).collect();
+        file.seek(SeekFrom::Start(data_offset + start * frame_bytes))?;
+        file.write_all(&bytes)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Writes the redacted copy next to `path` and swaps it in once complete
+fn reencode(
+    path: &Path,
+    spans: &[RedactionSpan],
+    mode: RedactionMode,
+    redact: fn(File, &mut BufWriter<File>, &[RedactionSpan], RedactionMode) -> Result<()>,
+) -> Result<()> {
+    let tmp = path.with_extension("redacting");
+    let result = File::create(&tmp)
+        .map_err(anyhow::Error::from)
+        .and_then(|out| {
+            let mut out = BufWriter::new(out);
+            redact(File::open(path)?, &mut out, spans, mode)?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            Ok(())
+        });
+    match result {
+        Ok(()) => Ok(std::fs::rename(&tmp, path)?),
+        Err(e) => {
+            std::fs::remove_file(&tmp).ok();
+            Err(e)
+        }
+    }
+}
+
+fn redact_flac(
+    input: File,
+    out: &mut BufWriter<File>,
+    spans: &[RedactionSpan],
+    mode: RedactionMode,
+) -> Result<()> {
+    use symphonia::core::{
+        codecs::{CodecParameters, audio::AudioDecoderOptions},
+        formats::{FormatOptions, TrackType, probe::Hint},
+        io::MediaSourceStream,
+        meta::MetadataOptions,
+    };
+    use symphonia::default::{get_codecs, get_probe};
+
+    let mss = MediaSourceStream::new(Box::new(input), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension("flac");
+    let mut format = get_probe().probe(
+        &hint,
+        mss,
+        FormatOptions::default(),
+        MetadataOptions::default(),
+    )?;
+    let track = format
+        .default_track(TrackType::Audio)
+        .ok_or_else(|| anyhow!("redaction: flac without audio track"))?;
+    let params = match track.codec_params.as_ref() {
+        Some(CodecParameters::Audio(params)) => params.clone(),
+        _ => bail!("redaction: flac without audio parameters"),
+    };
+    let sample_rate = params
+        .sample_rate
+        .ok_or_else(|| anyhow!("redaction: flac without sample rate"))?;
+    let channels = params.channels.as_ref().map(|c| c.count()).unwrap_or(1);
+    let mut decoder = get_codecs().make_audio_decoder(&params, &AudioDecoderOptions::default())?;
+
+    let mut encoder = FlacEncoder::new(sample_rate, channels as u16);
+    out.write_all(&encoder.header())?;
+    let mut frame = 0u64;
+    while let Some(packet) = format.next_packet()? {
+        let mut pcm: Vec<i16> = Vec::new();
+        decoder.decode(&packet)?.copy_to_vec_interleaved(&mut pcm);
+        apply(&mut pcm, frame, sample_rate, channels, spans, mode);
+        frame += (pcm.len() / channels) as u64;
+        out.write_all(&encoder.encode(&pcm))?;
+    }
+    out.write_all(&encoder.finish())?;
+    out.seek(SeekFrom::Start(0))?;
+    out.write_all(&encoder.header())?;
+    Ok(())
+}
+
+fn redact_ogg_opus(
+    input: File,
+    out: &mut BufWriter<File>,
+    spans: &[RedactionSpan],
+    mode: RedactionMode,
+) -> Result<()> {
+    let mut reader = PacketReader::new(BufReader::new(input));
+    let head = reader.read_packet_expected()?;
+    if !head.data.starts_with(b"OpusHead") || head.data.len() < 19 {
+        bail!("redaction: not an Ogg/Opus stream");
+    }
+    let channels = head.data[9].clamp(1, 2) as usize;
+    let input_rate = u32::from_le_bytes(head.data[12..16].try_into()?);
+    // decode straight to the rate the recorder encoded at
+    let sample_rate = match input_rate {
+        8000 | 12000 | 16000 | 24000 | 48000 => input_rate,
+        _ => 48000,
+    };
+    // OpusTags
+    reader.read_packet_expected()?;
+
+    let mut decoder = OpusDecoder::new(sample_rate, channels as u16);
+    let mut encoder = OggOpusEncoder::new(sample_rate, channels as u16)?;
+    let mut buf = vec![0i16; (sample_rate as usize / 50) * 2];
+    let mut frame = 0u64;
+    while let Some(packet) = reader.read_packet()? {
+        let n = decoder.decode_into_raw(&packet.data, &mut buf);
+        // stereo streams may carry mono-coded packets
+        let mut pcm = if audio_codec::Decoder::channels(&decoder) as usize == channels {
+            buf[..n].to_vec()
+        } else if channels == 2 {
+            buf[..n].iter().flat_map(|&s| [s, s]).collect()
+        } else {
+            buf[..n]
+                .chunks_exact(2)
+                .map(|c| ((c[0] as i32 + c[1] as i32) / 2) as i16)
+                .collect()
+        };
+        apply(&mut pcm, frame, sample_rate, channels, spans, mode);
+        frame += (pcm.len() / channels) as u64;
+        out.write_all(&encoder.encode(&pcm)?)?;
+    }
+    out.write_all(&encoder.finish()?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::{loader::decode_audio, recorder::wav_header};
+
+    fn span(start: u64, end: u64) -> RedactionSpan {
+        RedactionSpan {
+            start,
+            end,
+            reason: REASON_TRANSCRIPT.to_string(),
+        }
+    }
+
+    fn tone(frames: usize) -> Vec<i16> {
+        (0..frames)
+            .map(|i| ((i as f64 / 8000.0 * 300.0 * std::f64::consts::TAU).sin() * 10000.0) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn test_transcript_redactor() {
+        let option = RedactionOption {
+            patterns: Some(vec![r"\d{4}".to_string(), "(".to_string()]),
+            ..Default::default()
+        };
+        let redactor = TranscriptRedactor::new(&option).unwrap();
+        assert!(redactor.is_match("card ending 4242"));
+        assert!(!redactor.is_match("no digits here"));
+        assert_eq!(redactor.redact("pin 1234 and 99"), "pin **** and 99");
+        assert!(TranscriptRedactor::new(&RedactionOption::default()).is_none());
+        assert_eq!(mask("59"), "**");
+    }
+
+    #[test]
+    fn test_redact_wav_in_place() {
+        let pcm = tone(8000);
+        let data: Vec<u8> = pcm.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.wav");
+        let mut bytes = wav_header(1, 8000, 1, 16, data.len());
+        bytes.extend_from_slice(&data);
+        std::fs::write(&path, &bytes).unwrap();
+
+        redact_file(&path, &[span(250, 500)], RedactionMode::Silence).unwrap();
+        let redacted = decode_audio(File::open(&path).unwrap(), "wav", None, 8000).unwrap();
+        assert_eq!(redacted.len(), pcm.len());
+        assert!(redacted[2000..4000].iter().all(|&s| s == 0));
+        assert_eq!(redacted[..2000], pcm[..2000]);
+        assert_eq!(redacted[4000..], pcm[4000..]);
+    }
+
+    #[test]
+    fn test_redact_flac_with_beep() {
+        let pcm: Vec<i16> = tone(8000).into_iter().flat_map(|s| [s, -s]).collect();
+        let mut encoder = FlacEncoder::new(8000, 2);
+        let mut bytes = encoder.header();
+        bytes.extend(encoder.encode(&pcm));
+        bytes.extend(encoder.finish());
+        bytes[..encoder.header().len()].copy_from_slice(&encoder.header());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.flac");
+        std::fs::write(&path, &bytes).unwrap();
+
+        redact_file(&path, &[span(100, 200)], RedactionMode::Beep).unwrap();
+        assert!(!path.with_extension("redacting").exists());
+        let decoded = decode_audio(File::open(&path).unwrap(), "flac", None, 8000).unwrap();
+        assert_eq!(decoded.len(), 8000);
+        // both channels beep in phase inside the span, so the mix keeps it
+        assert!(decoded[800..1600].iter().any(|&s| s.abs() > 3000));
+        assert!(decoded[..800].iter().all(|&s| s.abs() <= 1));
+    }
+
+    #[test]
+    fn test_redact_ogg_opus() {
+        let pcm = tone(16000);
+        let mut encoder = OggOpusEncoder::new(8000, 1).unwrap();
+        let mut bytes = encoder.encode(&pcm).unwrap();
+        bytes.extend(encoder.finish().unwrap());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.ogg");
+        std::fs::write(&path, &bytes).unwrap();
+
+        redact_file(&path, &[span(500, 1500)], RedactionMode::Silence).unwrap();
+        let decoded = decode_audio(File::open(&path).unwrap(), "ogg", None, 8000).unwrap();
+        assert!(decoded.len().abs_diff(pcm.len()) < 320, "{}", decoded.len());
+        let energy = |pcm: &[i16]| pcm.iter().map(|&s| (s as f64).powi(2)).sum::<f64>();
+        assert!(energy(&decoded[5000..11000]) < energy(&decoded[13000..15000]) / 100.0);
+    }
+
+    #[test]
+    fn test_unsupported_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("call.mp3");
+        assert!(redact_file(&path, &[span(0, 10)], RedactionMode::Silence).is_err());
+        // nothing to redact is never an error
+        assert!(redact_file(&path, &[], RedactionMode::Silence).is_ok());
+    }
+}
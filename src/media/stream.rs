@@ -19,7 +19,7 @@ use std::time::Duration;
 use tokio::task::JoinHandle;
 use tokio::{
     select,
-    sync::{Mutex, mpsc},
+    sync::{Mutex, broadcast, mpsc},
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
@@ -34,16 +34,26 @@ pub struct MediaStream {
     /// them to. Drained into the next track started.
     pending_ice_candidates: Mutex<Vec<(String, Option<String>, Option<u32>)>>,
     suppressed_sources: Mutex<HashSet<TrackId>>,
+    /// Per-source destinations that must not receive the source's packets.
+    forwarding_exclusions: Mutex<HashMap<TrackId, HashSet<TrackId>>>,
     event_sender: EventSender,
     pub packet_sender: TrackPacketSender,
     packet_receiver: Mutex<Option<TrackPacketReceiver>>,
     recorder_sender: mpsc::UnboundedSender<AudioFrame>,
     recorder_receiver: Mutex<Option<mpsc::UnboundedReceiver<AudioFrame>>>,
     recorder_handle: Mutex<Option<JoinHandle<()>>>,
+    /// Frames are dropped while set, the recorder filling the gap with silence
+    recorder_paused: Arc<AtomicBool>,
+    /// Epoch ms the recorder started at, 0 before
+    recorder_started_at: AtomicU64,
     ambiance: Mutex<Option<Arc<StdMutex<AmbianceProcessor>>>>,
     ambiance_source_id: StdMutex<Option<TrackId>>,
     last_server_packet_ts: Arc<AtomicU64>,
     ambiance_idle_started: AtomicBool,
+    /// Where the play ids of finished plays go, when their `TrackEnd` is
+    /// held back until the client confirms playback
+    play_end_sender: StdMutex<Option<mpsc::UnboundedSender<String>>>,
+    held_play_ends: Arc<StdMutex<HashMap<String, SessionEvent>>>,
 }
 
 const CALLEE_TRACK_ID: &str = "callee-track";
@@ -99,16 +109,21 @@ impl MediaStreamBuilder {
             tracks,
             pending_ice_candidates: Mutex::new(Vec::new()),
             suppressed_sources: Mutex::new(HashSet::new()),
+            forwarding_exclusions: Mutex::new(HashMap::new()),
             event_sender: self.event_sender,
             packet_sender: track_packet_sender,
             packet_receiver: Mutex::new(Some(track_packet_receiver)),
             recorder_sender,
             recorder_receiver: Mutex::new(Some(recorder_receiver)),
             recorder_handle: Mutex::new(None),
+            recorder_paused: Arc::new(AtomicBool::new(false)),
+            recorder_started_at: AtomicU64::new(0),
             ambiance: Mutex::new(None),
             ambiance_source_id: StdMutex::new(None),
             last_server_packet_ts: Arc::new(AtomicU64::new(0)),
             ambiance_idle_started: AtomicBool::new(false),
+            play_end_sender: StdMutex::new(None),
+            held_play_ends: Arc::new(StdMutex::new(HashMap::new())),
         }
     }
 }
@@ -326,12 +341,14 @@ impl MediaStream {
         if self.recorder_option.lock().await.is_some() {
             track.append_processor(Box::new(RecorderProcessor::new(
                 self.recorder_sender.clone(),
+                self.recorder_paused.clone(),
             )));
         }
-        match track
-            .start(self.event_sender.clone(), self.packet_sender.clone())
-            .await
-        {
+        let event_sender = match play_id.as_ref() {
+            Some(_) => self.play_event_sender(),
+            None => self.event_sender.clone(),
+        };
+        match track.start(event_sender, self.packet_sender.clone()).await {
             Ok(_) => {
                 info!(session_id = self.id, track_id = track.id(), "track started");
                 let track_id = track.id().clone();
@@ -374,6 +391,63 @@ impl MediaStream {
         }
     }
 
+    /// Holds back the `TrackEnd` of every play, sending its play id to
+    /// `sender` instead, until [`Self::release_play_end`] is called for it.
+    pub fn hold_play_ends(&self, sender: mpsc::UnboundedSender<String>) {
+        *self.play_end_sender.lock().unwrap() = Some(sender);
+    }
+
+    /// Emits the held `TrackEnd` of `play_id`, returns false if none was held
+    pub fn release_play_end(&self, play_id: &str) -> bool {
+        let Some(event) = self.held_play_ends.lock().unwrap().remove(play_id) else {
+            return false;
+        };
+        self.event_sender.send(event).ok();
+        true
+    }
+
+    /// Event sender for a play track, relaying its events to the bus except
+    /// for the held `TrackEnd`
+    fn play_event_sender(&self) -> EventSender {
+        let Some(play_end_sender) = self.play_end_sender.lock().unwrap().clone() else {
+            return self.event_sender.clone();
+        };
+        let relay = crate::event::create_event_sender();
+        let mut receiver = relay.subscribe();
+        let event_sender = self.event_sender.clone();
+        let held_play_ends = self.held_play_ends.clone();
+        let cancel_token = self.cancel_token.clone();
+        crate::spawn(async move {
+            loop {
+                let event = select! {
+                    _ = cancel_token.cancelled() => break,
+                    event = receiver.recv() => event,
+                };
+                match event {
+                    Ok(event) => {
+                        if let SessionEvent::TrackEnd {
+                            play_id: Some(play_id),
+                            ..
+                        } = &event
+                        {
+                            let play_id = play_id.clone();
+                            held_play_ends
+                                .lock()
+                                .unwrap()
+                                .insert(play_id.clone(), event);
+                            play_end_sender.send(play_id).ok();
+                        } else {
+                            event_sender.send(event).ok();
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        relay
+    }
+
     pub async fn mute_track(&self, id: Option<TrackId>) {
         if let Some(id) = id {
             if let Some((track, _)) = self.tracks.lock().await.get_mut(&id) {
@@ -488,6 +562,17 @@ impl MediaStream {
         self.suppressed_sources.lock().await.remove(track_id);
     }
 
+    /// Stop forwarding packets of `track_id` to the given destination tracks.
+    /// An empty list forwards to every track again.
+    pub async fn exclude_forwarding(&self, track_id: &TrackId, destinations: Vec<TrackId>) {
+        let mut exclusions = self.forwarding_exclusions.lock().await;
+        if destinations.is_empty() {
+            exclusions.remove(track_id);
+        } else {
+            exclusions.insert(track_id.clone(), destinations.into_iter().collect());
+        }
+    }
+
     pub async fn remove_processor<T: 'static>(&self, track_id: &TrackId) -> Result<()> {
         if let Some((track, _)) = self.tracks.lock().await.get_mut(track_id) {
             track.as_mut().processor_chain().remove_processor::<T>();
@@ -514,16 +599,20 @@ impl MediaStream {
 #[derive(Clone)]
 pub struct RecorderProcessor {
     sender: mpsc::UnboundedSender<AudioFrame>,
+    paused: Arc<AtomicBool>,
 }
 
 impl RecorderProcessor {
-    pub fn new(sender: mpsc::UnboundedSender<AudioFrame>) -> Self {
-        Self { sender }
+    pub fn new(sender: mpsc::UnboundedSender<AudioFrame>, paused: Arc<AtomicBool>) -> Self {
+        Self { sender, paused }
     }
 }
 
 impl Processor for RecorderProcessor {
     fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+        if self.paused.load(Ordering::Relaxed) {
+            return Ok(());
+        }
         let frame_clone = frame.clone();
         let _ = self.sender.send(frame_clone);
         Ok(())
@@ -557,6 +646,8 @@ impl MediaStream {
                 "start recorder",
             );
 
+            self.recorder_started_at
+                .store(crate::media::get_timestamp(), Ordering::Relaxed);
             let recorder_handle = crate::spawn(async move {
                 let recorder_file = recorder_option.recorder_file.clone();
                 let recorder =
@@ -580,12 +671,37 @@ impl MediaStream {
             for (track, _) in self.tracks.lock().await.values_mut() {
                 track.insert_processor(Box::new(RecorderProcessor::new(
                     self.recorder_sender.clone(),
+                    self.recorder_paused.clone(),
                 )));
             }
         }
         Ok(())
     }
 
+    /// Stops or resumes feeding the recorder, returning the previous state
+    pub fn pause_recording(&self, paused: bool) -> bool {
+        self.recorder_paused.swap(paused, Ordering::Relaxed)
+    }
+
+    pub fn is_recording_paused(&self) -> bool {
+        self.recorder_paused.load(Ordering::Relaxed)
+    }
+
+    /// Epoch ms the recorder started at
+    pub fn recording_started_at(&self) -> Option<u64> {
+        match self.recorder_started_at.load(Ordering::Relaxed) {
+            0 => None,
+            started_at => Some(started_at),
+        }
+    }
+
+    /// Milliseconds into the recording at epoch ms `timestamp`, `None`
+    /// before the recorder started
+    pub fn recording_offset(&self, timestamp: u64) -> Option<u64> {
+        self.recording_started_at()
+            .map(|started_at| timestamp.saturating_sub(started_at))
+    }
+
     pub async fn set_track_refer(&self, track_id: &TrackId, refer: Option<bool>) {
         if let Some((_, dtmf)) = self.tracks.lock().await.get_mut(track_id) {
             dtmf.refer = refer;
@@ -619,6 +735,12 @@ impl MediaStream {
                     .await
                     .contains(&packet.track_id)
             };
+            let excluded = self
+                .forwarding_exclusions
+                .lock()
+                .await
+                .get(&packet.track_id)
+                .cloned();
 
             let is_dtmf = matches!(&packet.samples,
                 Samples::RTP { payload_type, .. } if *payload_type >= 96 && *payload_type <= 127);
@@ -664,6 +786,12 @@ impl MediaStream {
                 if packet.track_id == QUEUE_HOLD_TRACK_ID && track.id() == CALLEE_TRACK_ID {
                     continue;
                 }
+                if excluded
+                    .as_ref()
+                    .is_some_and(|excluded| excluded.contains(track.id()))
+                {
+                    continue;
+                }
                 if let Err(e) = track.send_packet(&packet).await {
                     warn!(
                         id = track.id(),
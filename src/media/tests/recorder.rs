@@ -1,6 +1,7 @@
 use crate::media::{
     AudioFrame, PcmBuf, Sample, Samples,
-    recorder::{Recorder, RecorderOption},
+    loader::load_audio_as_pcm,
+    recorder::{Recorder, RecorderChannels, RecorderFormat, RecorderOption},
 };
 use anyhow::Result;
 use std::{path::Path, sync::Arc};
@@ -406,3 +407,158 @@ async fn test_recorder_200ms_timing() -> Result<()> {
     println!("200ms timing test completed successfully");
     Ok(())
 }
+
+/// Records one 200ms frame from the caller ("test") and one from the agent
+async fn record_two_parties(option: RecorderOption, caller: PcmBuf, agent: PcmBuf) -> Result<()> {
+    let recorder = Arc::new(Recorder::new(
+        CancellationToken::new(),
+        "test".to_string(),
+        option.clone(),
+    ));
+    let (tx, rx) = mpsc::unbounded_channel();
+    let recorder_clone = recorder.clone();
+    let handle = tokio::spawn(async move {
+        recorder_clone
+            .process_recording(Path::new(&option.recorder_file), rx)
+            .await
+    });
+    for (track_id, samples) in [("test", caller), ("agent", agent)] {
+        tx.send(AudioFrame {
+            track_id: track_id.to_string(),
+            samples: Samples::PCM { samples },
+            sample_rate: 16000,
+            channels: 1,
+            ..Default::default()
+        })?;
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+    recorder.stop_recording()?;
+    handle.await?
+}
+
+fn sawtooth(len: usize, period: usize) -> PcmBuf {
+    (0..len).map(|i| (i % period) as Sample * 100 + 1).collect()
+}
+
+#[tokio::test]
+async fn test_recorder_separate_flac() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let mut option = RecorderOption::new(
+        temp_dir
+            .path()
+            .join("call.wav")
+            .to_string_lossy()
+            .to_string(),
+    );
+    option.format = Some(RecorderFormat::Flac);
+    option.channels = Some(RecorderChannels::Separate);
+    option.ensure_path_extension(RecorderFormat::Wav);
+    assert!(option.recorder_file.ends_with("call.flac"));
+
+    let (caller, agent) = (sawtooth(3200, 40), sawtooth(3200, 90));
+    record_two_parties(option.clone(), caller.clone(), agent.clone()).await?;
+
+    let files = option.output_files();
+    assert_eq!(files[0].0, Some("caller"));
+    assert!(files[0].1.ends_with("call.caller.flac"));
+    assert!(files[1].1.ends_with("call.agent.flac"));
+    for ((_, path), expected) in files.iter().zip([caller, agent]) {
+        // lossless, though a party may start after a chunk of silence
+        let decoded = load_audio_as_pcm(path, 16000, false).await?;
+        let start = decoded.iter().position(|&s| s != 0).unwrap();
+        assert_eq!(decoded[start..start + expected.len()], expected[..]);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recorder_mono_opus() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let path = temp_dir.path().join("call.ogg");
+    let option = RecorderOption {
+        recorder_file: path.to_string_lossy().to_string(),
+        format: Some(RecorderFormat::Opus),
+        channels: Some(RecorderChannels::Mono),
+        ..Default::default()
+    };
+    record_two_parties(option, sawtooth(3200, 40), sawtooth(3200, 90)).await?;
+
+    let bytes = std::fs::read(&path)?;
+    assert!(bytes.starts_with(b"OggS"));
+    // 16 kHz 16-bit mono would take 6400 bytes per 200ms
+    let decoded = load_audio_as_pcm(&path.to_string_lossy(), 16000, false).await?;
+    assert!(decoded.len() >= 3200, "{}", decoded.len());
+    assert!(bytes.len() < decoded.len() * 2 / 4, "{}", bytes.len());
+    Ok(())
+}
+
+#[test]
+fn test_recorder_format_paths() {
+    // no MP3 encoder, so the format is refused where it is configured
+    assert!(serde_json::from_str::<RecorderOption>(r#"{"format":"mp3"}"#).is_err());
+    let mut option = RecorderOption::new("/tmp/call.mp3".to_string());
+    option.ensure_path_extension(RecorderFormat::Wav);
+    assert_eq!(option.format, Some(RecorderFormat::Wav));
+    assert_eq!(option.recorder_file, "/tmp/call.wav");
+
+    option.format = Some(RecorderFormat::Opus);
+    option.ensure_path_extension(RecorderFormat::Wav);
+    assert_eq!(option.recorder_file, "/tmp/call.ogg");
+
+    let mut option = RecorderOption::new("/tmp/call.v1".to_string());
+    option.ensure_path_extension(RecorderFormat::Flac);
+    assert_eq!(option.recorder_file, "/tmp/call.v1.flac");
+    assert_eq!(
+        option.output_files(),
+        vec![(None, option.recorder_file.clone())]
+    );
+}
+
+#[tokio::test]
+async fn test_recorder_rtp_to_flac() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let path = temp_dir.path().join("call.flac");
+    let option = RecorderOption {
+        recorder_file: path.to_string_lossy().to_string(),
+        samplerate: 8000,
+        format: Some(RecorderFormat::Flac),
+        channels: Some(RecorderChannels::Mono),
+        ..Default::default()
+    };
+    let cancel_token = CancellationToken::new();
+    let recorder = Arc::new(Recorder::new(
+        cancel_token.clone(),
+        "caller".to_string(),
+        option,
+    ));
+    let (tx, rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn({
+        let recorder = recorder.clone();
+        let path = path.clone();
+        async move { recorder.process_recording(&path, rx).await }
+    });
+
+    // PCMU 0x7f is silence, as raw bytes it would be loud
+    for sequence_number in 0..20 {
+        tx.send(AudioFrame {
+            track_id: "caller".to_string(),
+            samples: Samples::RTP {
+                payload_type: 0,
+                payload: vec![0x7f; 160],
+                sequence_number,
+            },
+            sample_rate: 8000,
+            ..Default::default()
+        })?;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    cancel_token.cancel();
+    handle.await??;
+
+    let bytes = std::fs::read(&path)?;
+    assert!(bytes.starts_with(b"fLaC"));
+    let decoded = load_audio_as_pcm(&path.to_string_lossy(), 8000, false).await?;
+    assert!(decoded.len() >= 3200, "{}", decoded.len());
+    assert!(decoded.iter().all(|s| s.abs() < 16));
+    Ok(())
+}
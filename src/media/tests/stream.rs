@@ -2,7 +2,7 @@ use crate::media::processor::ProcessorChain;
 use crate::media::recorder::RecorderOption;
 use crate::media::track::TrackConfig;
 use crate::{
-    event::EventSender,
+    event::{EventSender, SessionEvent},
     media::AudioFrame,
     media::Samples,
     media::TrackId,
@@ -1152,3 +1152,146 @@ async fn perf_forward_fanout_ambiance_on_vs_off() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_stream_forwarding_exclusions() -> Result<()> {
+    let event_sender = crate::event::create_event_sender();
+    let stream = Arc::new(MediaStreamBuilder::new(event_sender).build());
+    let serve_stream = stream.clone();
+    let handle = tokio::spawn(async move {
+        serve_stream.serve().await.ok();
+    });
+
+    let caller = Arc::new(Mutex::new(Vec::new()));
+    let agent = Arc::new(Mutex::new(Vec::new()));
+    stream
+        .update_track(
+            Box::new(CollectTrack::new("caller".to_string(), caller.clone())),
+            None,
+        )
+        .await;
+    stream
+        .update_track(
+            Box::new(CollectTrack::new("agent".to_string(), agent.clone())),
+            None,
+        )
+        .await;
+    stream
+        .exclude_forwarding(&"whisper".to_string(), vec!["caller".to_string()])
+        .await;
+
+    let frame = |track_id: &str| AudioFrame {
+        track_id: track_id.to_string(),
+        samples: Samples::PCM {
+            samples: vec![100; 320],
+        },
+        sample_rate: 16000,
+        ..Default::default()
+    };
+    stream.packet_sender.send(frame("whisper"))?;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(caller.lock().await.is_empty());
+    assert_eq!(agent.lock().await.len(), 1);
+
+    stream
+        .exclude_forwarding(&"whisper".to_string(), vec![])
+        .await;
+    stream.packet_sender.send(frame("whisper"))?;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(caller.lock().await.len(), 1);
+    assert_eq!(agent.lock().await.len(), 2);
+
+    stream.stop(None, None);
+    handle.abort();
+    Ok(())
+}
+
+/// Play track that finishes as soon as it starts.
+struct EndedTrack {
+    id: TrackId,
+    config: TrackConfig,
+    processor_chain: ProcessorChain,
+    play_id: String,
+}
+
+#[async_trait]
+impl Track for EndedTrack {
+    fn ssrc(&self) -> u32 {
+        0
+    }
+    fn id(&self) -> &TrackId {
+        &self.id
+    }
+    fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+    fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+    async fn handshake(&mut self, _offer: String, _timeout: Option<Duration>) -> Result<String> {
+        Ok("".to_string())
+    }
+    async fn update_remote_description(&mut self, _answer: &String) -> Result<()> {
+        Ok(())
+    }
+    async fn start(
+        &mut self,
+        event_sender: EventSender,
+        _packet_sender: TrackPacketSender,
+    ) -> Result<()> {
+        event_sender.send(SessionEvent::TrackEnd {
+            track_id: self.id.clone(),
+            timestamp: crate::media::get_timestamp(),
+            duration: 0,
+            ssrc: 0,
+            play_id: Some(self.play_id.clone()),
+        })?;
+        Ok(())
+    }
+    async fn stop(&self) -> Result<()> {
+        Ok(())
+    }
+    async fn send_packet(&mut self, _packet: &AudioFrame) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_held_play_end_released_by_mark() -> Result<()> {
+    let event_sender = crate::event::create_event_sender();
+    let mut events = event_sender.subscribe();
+    let stream = MediaStreamBuilder::new(event_sender).build();
+    let (play_end_sender, mut play_ends) = tokio::sync::mpsc::unbounded_channel();
+    stream.hold_play_ends(play_end_sender);
+
+    stream
+        .update_track(
+            Box::new(EndedTrack {
+                id: "server-side-track".to_string(),
+                config: TrackConfig::default(),
+                processor_chain: ProcessorChain::new(16000),
+                play_id: "p1".to_string(),
+            }),
+            Some("p1".to_string()),
+        )
+        .await;
+    let play_id = tokio::time::timeout(Duration::from_secs(1), play_ends.recv()).await?;
+    assert_eq!(play_id.as_deref(), Some("p1"));
+    assert!(matches!(
+        events.try_recv(),
+        Ok(SessionEvent::TrackStart { .. })
+    ));
+    assert!(
+        events.try_recv().is_err(),
+        "TrackEnd must wait for the mark"
+    );
+
+    assert!(!stream.release_play_end("p2"));
+    assert!(stream.release_play_end("p1"));
+    match events.try_recv() {
+        Ok(SessionEvent::TrackEnd { play_id, .. }) => assert_eq!(play_id.as_deref(), Some("p1")),
+        other => panic!("unexpected event {:?}", other),
+    }
+    assert!(!stream.release_play_end("p1"));
+    Ok(())
+}
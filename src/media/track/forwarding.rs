@@ -1,9 +1,11 @@
 use crate::event::EventSender;
-use crate::media::processor::ProcessorChain;
+use crate::media::processor::{ProcessorChain, convert_to_mono};
+use crate::media::track::track_codec::TrackCodec;
 use crate::media::track::{Track, TrackConfig, TrackPacketSender};
-use crate::media::{AudioFrame, Samples, TrackId};
+use crate::media::{AudioFrame, INTERNAL_SAMPLERATE, PcmBuf, Samples, TrackId};
 use anyhow::Result;
 use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
@@ -23,6 +25,14 @@ pub struct ForwardingTrack {
     cancel_token: CancellationToken,
     ssrc: u32,
     paused: Arc<AtomicBool>,
+    /// Forward packets from every other track in the stream, not only
+    /// `source_peer_track_id`.
+    forward_all: bool,
+    /// Additional source tracks forwarded besides `source_peer_track_id`.
+    extra_sources: Vec<TrackId>,
+    /// Mix inbound frames from several origin tracks into the frames of this
+    /// primary origin instead of injecting them one after another.
+    inbound_mix: Option<TrackId>,
 }
 
 impl ForwardingTrack {
@@ -46,8 +56,124 @@ impl ForwardingTrack {
             cancel_token,
             ssrc,
             paused,
+            forward_all: false,
+            extra_sources: Vec::new(),
+            inbound_mix: None,
         }
     }
+
+    pub fn with_forward_all(mut self, forward_all: bool) -> Self {
+        self.forward_all = forward_all;
+        self
+    }
+
+    pub fn with_extra_sources(mut self, extra_sources: Vec<TrackId>) -> Self {
+        self.extra_sources = extra_sources;
+        self
+    }
+
+    pub fn with_inbound_mix(mut self, primary_track_id: TrackId) -> Self {
+        self.inbound_mix = Some(primary_track_id);
+        self
+    }
+
+    fn is_source(&self, track_id: &TrackId) -> bool {
+        self.forward_all
+            || *track_id == self.source_peer_track_id
+            || self.extra_sources.contains(track_id)
+    }
+}
+
+// Secondary audio buffered beyond this is flushed on its own, so it keeps
+// playing when the primary origin stops sending (hold, WebSocket silence).
+const MIX_FLUSH_SAMPLES: usize = INTERNAL_SAMPLERATE as usize / 50 * 3;
+const MIX_MAX_SAMPLES: usize = INTERNAL_SAMPLERATE as usize / 5;
+
+/// Mixes frames arriving from several origin tracks over one channel.
+///
+/// Frames of the primary origin drive the output; audio of every other
+/// origin is buffered per origin and summed into the next primary frame.
+pub(crate) struct InboundMixer {
+    primary: TrackId,
+    codecs: HashMap<TrackId, TrackCodec>,
+    secondary: HashMap<TrackId, VecDeque<i16>>,
+}
+
+impl InboundMixer {
+    pub(crate) fn new(primary: TrackId) -> Self {
+        Self {
+            primary,
+            codecs: HashMap::new(),
+            secondary: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, mut frame: AudioFrame) -> Option<AudioFrame> {
+        let pcm = self.decode(&frame)?;
+        let mix_len = if frame.track_id == self.primary {
+            pcm.len()
+        } else {
+            let buffer = self.secondary.entry(frame.track_id.clone()).or_default();
+            buffer.extend(pcm.iter());
+            if buffer.len() > MIX_MAX_SAMPLES {
+                let excess = buffer.len() - MIX_MAX_SAMPLES;
+                buffer.drain(..excess);
+            }
+            if buffer.len() < MIX_FLUSH_SAMPLES {
+                return None;
+            }
+            pcm.len()
+        };
+
+        let mut mix: Vec<i32> = if frame.track_id == self.primary {
+            pcm.iter().map(|s| *s as i32).collect()
+        } else {
+            vec![0; mix_len]
+        };
+        for buffer in self.secondary.values_mut() {
+            let take = mix_len.min(buffer.len());
+            for (m, s) in mix.iter_mut().zip(buffer.drain(..take)) {
+                *m += s as i32;
+            }
+        }
+        self.secondary.retain(|_, buffer| !buffer.is_empty());
+
+        frame.samples = Samples::PCM {
+            samples: mix.into_iter().map(saturate).collect(),
+        };
+        frame.sample_rate = INTERNAL_SAMPLERATE;
+        frame.channels = 1;
+        Some(frame)
+    }
+
+    fn decode(&mut self, frame: &AudioFrame) -> Option<PcmBuf> {
+        let codec = self
+            .codecs
+            .entry(frame.track_id.clone())
+            .or_insert_with(TrackCodec::new);
+        match &frame.samples {
+            Samples::PCM { samples } => {
+                let mut samples = samples.clone();
+                convert_to_mono(&mut samples, frame.channels);
+                Some(codec.resample(samples, frame.sample_rate, INTERNAL_SAMPLERATE))
+            }
+            Samples::RTP {
+                payload_type,
+                payload,
+                ..
+            } if TrackCodec::is_audio(*payload_type) => {
+                let (_, channels, mut samples) =
+                    codec.decode(*payload_type, payload, INTERNAL_SAMPLERATE);
+                convert_to_mono(&mut samples, channels);
+                Some(samples)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn saturate(sample: i32) -> i16 {
+    sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16
 }
 
 #[async_trait]
@@ -88,6 +214,7 @@ impl Track for ForwardingTrack {
         let track_id = self.track_id.clone();
         let cancel_token = self.cancel_token.clone();
         let mut processor_chain = self.processor_chain.clone();
+        let mut mixer = self.inbound_mix.clone().map(InboundMixer::new);
 
         crate::spawn(async move {
             let stop_reason = loop {
@@ -97,7 +224,14 @@ impl Track for ForwardingTrack {
                     }
                     packet = inbound_receiver.recv() => {
                         match packet {
-                            Some(mut packet) => {
+                            Some(packet) => {
+                                let mut packet = match mixer.as_mut() {
+                                    Some(mixer) => match mixer.push(packet) {
+                                        Some(packet) => packet,
+                                        None => continue,
+                                    },
+                                    None => packet,
+                                };
                                 packet.track_id = track_id.clone();
                                 if let Err(e) = processor_chain.process_frame(&mut packet) {
                                     warn!(track_id, "processor_chain process_frame error: {:?}", e);
@@ -131,7 +265,7 @@ impl Track for ForwardingTrack {
     async fn send_packet(&mut self, packet: &AudioFrame) -> Result<()> {
         if self.cancel_token.is_cancelled()
             || self.paused.load(Ordering::Relaxed)
-            || packet.track_id != self.source_peer_track_id
+            || !self.is_source(&packet.track_id)
         {
             return Ok(());
         }
@@ -153,3 +287,58 @@ impl Track for ForwardingTrack {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm_frame(track_id: &str, value: i16) -> AudioFrame {
+        AudioFrame {
+            track_id: track_id.to_string(),
+            samples: Samples::PCM {
+                samples: vec![value; 320],
+            },
+            sample_rate: INTERNAL_SAMPLERATE,
+            ..Default::default()
+        }
+    }
+
+    fn first_sample(frame: &AudioFrame) -> i16 {
+        match &frame.samples {
+            Samples::PCM { samples } => samples[0],
+            _ => panic!("expected pcm"),
+        }
+    }
+
+    #[test]
+    fn test_inbound_mixer_sums_secondary_into_primary() {
+        let mut mixer = InboundMixer::new("caller".to_string());
+        assert!(mixer.push(pcm_frame("tts", 100)).is_none());
+        assert!(mixer.push(pcm_frame("agent", 50)).is_none());
+
+        let frame = mixer.push(pcm_frame("caller", 1000)).unwrap();
+        assert_eq!(frame.track_id, "caller");
+        assert_eq!(first_sample(&frame), 1150);
+
+        let frame = mixer.push(pcm_frame("caller", 1000)).unwrap();
+        assert_eq!(first_sample(&frame), 1000);
+    }
+
+    #[test]
+    fn test_inbound_mixer_flushes_without_primary() {
+        let mut mixer = InboundMixer::new("caller".to_string());
+        assert!(mixer.push(pcm_frame("tts", 100)).is_none());
+        assert!(mixer.push(pcm_frame("tts", 200)).is_none());
+        let frame = mixer.push(pcm_frame("tts", 300)).unwrap();
+        assert_eq!(first_sample(&frame), 100);
+        assert_eq!(frame.sample_rate, INTERNAL_SAMPLERATE);
+    }
+
+    #[test]
+    fn test_inbound_mixer_saturates() {
+        let mut mixer = InboundMixer::new("caller".to_string());
+        mixer.push(pcm_frame("tts", i16::MAX));
+        let frame = mixer.push(pcm_frame("caller", i16::MAX)).unwrap();
+        assert_eq!(first_sample(&frame), i16::MAX);
+    }
+}
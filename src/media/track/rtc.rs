@@ -3,7 +3,10 @@ use crate::{
     event::{EventSender, SessionEvent},
     media::AudioFrame,
     media::{
+        INTERNAL_SAMPLERATE,
+        jitter_buffer::{JitterBuffer, JitterBufferOption, JitterOutput, JitterStats},
         processor::ProcessorChain,
+        quality::{CallQuality, QualityReports},
         track::{Track, TrackConfig, TrackId, TrackPacketSender},
     },
 };
@@ -20,11 +23,12 @@ use rustrtc::{
         MediaStreamTrack, SampleStreamSource, frame::AudioFrame as RtcAudioFrame, sample_track,
         track::SampleStreamTrack,
     },
+    stats::StatsReport,
 };
 use std::{
     sync::{
         Arc,
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU8, Ordering},
     },
     time::{Duration, Instant},
 };
@@ -44,6 +48,7 @@ pub struct RtcTrackConfig {
     pub payload_type: Option<u8>,
     pub enable_latching: Option<bool>,
     pub enable_ice_lite: Option<bool>,
+    pub jitter_buffer: Option<JitterBufferOption>,
 }
 
 impl Default for RtcTrackConfig {
@@ -59,6 +64,7 @@ impl Default for RtcTrackConfig {
             payload_type: None,
             enable_latching: None,
             enable_ice_lite: None,
+            jitter_buffer: None,
         }
     }
 }
@@ -76,12 +82,16 @@ pub struct RtcTrack {
     encoder: TrackCodec,
     ssrc: u32,
     payload_type: Option<u8>,
+    /// `payload_type` for the stats loop, which rates quality by codec
+    negotiated_payload_type: Arc<AtomicU8>,
+    quality_reports: Option<QualityReports>,
     pub peer_connection: Option<Arc<PeerConnection>>,
     next_rtp_timestamp: u32,
     next_rtp_sequence_number: u16,
     last_packet_time: Option<Instant>,
     last_remote_sdp: Option<String>,
     need_marker: bool,
+    jitter_stats: Arc<std::sync::Mutex<JitterStats>>,
 }
 
 impl RtcTrack {
@@ -91,7 +101,10 @@ impl RtcTrack {
         track_config: TrackConfig,
         rtc_config: RtcTrackConfig,
     ) -> Self {
-        let processor_chain = ProcessorChain::new(track_config.samplerate);
+        let mut processor_chain = ProcessorChain::new(track_config.samplerate);
+        if rtc_config.jitter_buffer.is_some() {
+            processor_chain.codec.enable_plc();
+        }
         Self {
             track_id: id,
             track_config,
@@ -105,12 +118,15 @@ impl RtcTrack {
             encoder: TrackCodec::new(),
             ssrc: 0,
             payload_type: None,
+            negotiated_payload_type: Arc::new(AtomicU8::new(0)),
+            quality_reports: None,
             peer_connection: None,
             next_rtp_timestamp: 0,
             next_rtp_sequence_number: 0,
             last_packet_time: None,
             last_remote_sdp: None,
             need_marker: false,
+            jitter_stats: Arc::new(std::sync::Mutex::new(JitterStats::default())),
         }
     }
 
@@ -119,6 +135,18 @@ impl RtcTrack {
         self
     }
 
+    /// Keeps the latest voice quality of this track in `reports`
+    pub fn with_quality_reports(mut self, reports: QualityReports) -> Self {
+        self.quality_reports = Some(reports);
+        self
+    }
+
+    /// Receive jitter buffer and concealment counters, if one is enabled
+    pub fn jitter_stats(&self) -> Option<JitterStats> {
+        self.rtc_config.jitter_buffer.as_ref()?;
+        Some(self.jitter_stats.lock().unwrap().clone())
+    }
+
     pub fn create_audio_track(
         _codec: CodecType,
         _stream_id: Option<String>,
@@ -201,6 +229,8 @@ impl RtcTrack {
             .unwrap_or_else(|| codec.payload_type());
 
         self.payload_type = Some(payload_type);
+        self.negotiated_payload_type
+            .store(payload_type, Ordering::Relaxed);
 
         let params = RtpCodecParameters {
             clock_rate: codec.clock_rate(),
@@ -244,6 +274,10 @@ impl RtcTrack {
             TransportMode::Rtp | TransportMode::Srtp
         );
         let is_webrtc = self.rtc_config.mode != TransportMode::Rtp;
+        let jitter_buffer = self.rtc_config.jitter_buffer.clone();
+        let jitter_stats = self.jitter_stats.clone();
+        let negotiated_payload_type = self.negotiated_payload_type.clone();
+        let quality_reports = self.quality_reports.clone();
 
         crate::spawn(async move {
             info!(track_id=%track_id_log, "RtcTrack event/stats loop started");
@@ -306,6 +340,8 @@ impl RtcTrack {
                                     track_id_log.clone(),
                                     processor_chain.clone(),
                                     default_payload_type,
+                                    jitter_buffer.clone(),
+                                    jitter_stats.clone(),
                                 );
                                 workers.push(f1);
                                 workers.push(f2);
@@ -319,6 +355,23 @@ impl RtcTrack {
                         match pc_stats.get_stats().await {
                             Ok(stats) => {
                                 info!(track_id=%track_id_log, %stats, "RTCP Stats");
+                                if jitter_buffer.is_some() {
+                                    let stats = jitter_stats.lock().unwrap().clone();
+                                    info!(track_id=%track_id_log, ?stats, "Jitter buffer stats");
+                                }
+                                let codec = processor_chain
+                                    .codec
+                                    .get_codec_for_pt(negotiated_payload_type.load(Ordering::Relaxed))
+                                    .unwrap_or(CodecType::PCMU);
+                                Self::report_quality(
+                                    &track_id_log,
+                                    &stats,
+                                    codec,
+                                    quality_reports.as_ref(),
+                                    &event_sender,
+                                    "quality.rtp",
+                                )
+                                .await;
                             }
                             Err(e) => {
                                 debug!(track_id=%track_id_log, "Failed to get stats: {:?}", e);
@@ -357,6 +410,21 @@ impl RtcTrack {
                     }
                 }
             }
+            if jitter_buffer.is_some() {
+                let stats = jitter_stats.lock().unwrap().clone();
+                let mut data = serde_json::to_value(&stats).unwrap_or_default();
+                data["track_id"] = track_id_log.clone().into();
+                if let Some(sender) = event_sender.lock().await.as_ref() {
+                    sender
+                        .send(SessionEvent::Metrics {
+                            timestamp: crate::media::get_timestamp(),
+                            key: "completed.jitter_buffer".to_string(),
+                            duration: 0,
+                            data,
+                        })
+                        .ok();
+                }
+            }
             debug!(track_id=%track_id_log, "RtcTrack event/stats loop ended, total events: {}", event_count);
         });
     }
@@ -367,6 +435,8 @@ impl RtcTrack {
         track_id: TrackId,
         processor_chain: ProcessorChain,
         default_payload_type: u8,
+        jitter_buffer: Option<JitterBufferOption>,
+        jitter_stats: Arc<std::sync::Mutex<JitterStats>>,
     ) -> (
         std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>,
         std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>,
@@ -383,6 +453,8 @@ impl RtcTrack {
             packet_sender_proc,
             processor_chain_proc,
             default_payload_type,
+            jitter_buffer,
+            jitter_stats,
         );
 
         // Receiving Worker
@@ -398,34 +470,127 @@ impl RtcTrack {
         packet_sender: Arc<Mutex<Option<TrackPacketSender>>>,
         mut processor_chain: ProcessorChain,
         default_payload_type: u8,
+        jitter_buffer: Option<JitterBufferOption>,
+        jitter_stats: Arc<std::sync::Mutex<JitterStats>>,
     ) {
         info!(track_id=%track_id, "RtcTrack processing worker started");
-        while let Some(frame) = rx.recv().await {
-            let res = std::panic::AssertUnwindSafe(Self::process_audio_frame(
-                frame,
-                &track_id,
-                &packet_sender,
-                &mut processor_chain,
-                default_payload_type,
-            ))
-            .catch_unwind()
-            .await;
-
-            if let Err(cause) = res {
-                let msg = if let Some(s) = cause.downcast_ref::<&str>() {
-                    *s
-                } else if let Some(s) = cause.downcast_ref::<String>() {
-                    &s[..]
-                } else {
-                    "Unknown panic"
-                };
-                tracing::error!(track_id=%track_id, "RtcTrack processing worker PANIC: {}", msg);
-                break;
+        let res = std::panic::AssertUnwindSafe(async {
+            match jitter_buffer {
+                Some(option) => {
+                    Self::run_jitter_buffer(
+                        &mut rx,
+                        &track_id,
+                        &packet_sender,
+                        &mut processor_chain,
+                        default_payload_type,
+                        JitterBuffer::new(&option),
+                        &jitter_stats,
+                    )
+                    .await
+                }
+                None => {
+                    while let Some(frame) = rx.recv().await {
+                        Self::process_audio_frame(
+                            frame,
+                            &track_id,
+                            &packet_sender,
+                            &mut processor_chain,
+                            default_payload_type,
+                        )
+                        .await;
+                    }
+                }
             }
+        })
+        .catch_unwind()
+        .await;
+
+        if let Err(cause) = res {
+            let msg = if let Some(s) = cause.downcast_ref::<&str>() {
+                *s
+            } else if let Some(s) = cause.downcast_ref::<String>() {
+                &s[..]
+            } else {
+                "Unknown panic"
+            };
+            tracing::error!(track_id=%track_id, "RtcTrack processing worker PANIC: {}", msg);
         }
         info!(track_id=%track_id, "RtcTrack processing worker stopped");
     }
 
+    /// Plays received frames out of a jitter buffer, concealing lost ones
+    async fn run_jitter_buffer(
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<rustrtc::media::frame::AudioFrame>,
+        track_id: &TrackId,
+        packet_sender: &Arc<Mutex<Option<TrackPacketSender>>>,
+        processor_chain: &mut ProcessorChain,
+        default_payload_type: u8,
+        mut buffer: JitterBuffer<rustrtc::media::frame::AudioFrame>,
+        jitter_stats: &std::sync::Mutex<JitterStats>,
+    ) {
+        let mut ssrc = None;
+        loop {
+            let next_due = buffer.next_due();
+            tokio::select! {
+                frame = rx.recv() => {
+                    let Some(frame) = frame else {
+                        break;
+                    };
+                    let Some(sequence_number) = frame.sequence_number else {
+                        Self::process_audio_frame(
+                            frame,
+                            track_id,
+                            packet_sender,
+                            processor_chain,
+                            default_payload_type,
+                        )
+                        .await;
+                        continue;
+                    };
+                    let frame_ssrc = frame.raw_packet.as_ref().map(|p| p.header.ssrc);
+                    if frame_ssrc.is_some() && ssrc.is_some() && frame_ssrc != ssrc {
+                        debug!(track_id=%track_id, ?frame_ssrc, "new RTP source, resetting jitter buffer");
+                        buffer.reset();
+                    }
+                    ssrc = frame_ssrc.or(ssrc);
+                    buffer.push(
+                        sequence_number,
+                        frame.rtp_timestamp,
+                        frame.clock_rate,
+                        frame,
+                        Instant::now(),
+                    );
+                }
+                _ = async {
+                    match next_due {
+                        Some(due) => tokio::time::sleep_until(due.into()).await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+            }
+
+            while let Some(output) = buffer.pop(Instant::now()) {
+                match output {
+                    JitterOutput::Packet(frame) => {
+                        Self::process_audio_frame(
+                            frame,
+                            track_id,
+                            packet_sender,
+                            processor_chain,
+                            default_payload_type,
+                        )
+                        .await
+                    }
+                    JitterOutput::Lost => {
+                        Self::process_concealed_frame(track_id, packet_sender, processor_chain)
+                            .await
+                    }
+                }
+            }
+            *jitter_stats.lock().unwrap() = buffer.stats();
+        }
+    }
+
     async fn run_receiving_worker(
         track: Arc<SampleStreamTrack>,
         tx: tokio::sync::mpsc::UnboundedSender<rustrtc::media::frame::AudioFrame>,
@@ -488,6 +653,62 @@ impl RtcTrack {
         }
     }
 
+    /// Rates RTCP statistics, keeping the result for the call record
+    async fn report_quality(
+        track_id: &TrackId,
+        stats: &StatsReport,
+        codec: CodecType,
+        quality_reports: Option<&QualityReports>,
+        event_sender: &Arc<Mutex<Option<EventSender>>>,
+        key: &str,
+    ) {
+        let Some(quality) = CallQuality::from_stats(stats, codec) else {
+            return;
+        };
+        if let Some(reports) = quality_reports {
+            reports
+                .lock()
+                .unwrap()
+                .insert(track_id.clone(), quality.clone());
+        }
+        let mut data = serde_json::to_value(&quality).unwrap_or_default();
+        data["track_id"] = track_id.clone().into();
+        if let Some(sender) = event_sender.lock().await.as_ref() {
+            sender
+                .send(SessionEvent::Metrics {
+                    timestamp: crate::media::get_timestamp(),
+                    key: key.to_string(),
+                    duration: 0,
+                    data,
+                })
+                .ok();
+        }
+    }
+
+    async fn process_concealed_frame(
+        track_id: &TrackId,
+        packet_sender: &Arc<Mutex<Option<TrackPacketSender>>>,
+        processor_chain: &mut ProcessorChain,
+    ) {
+        let packet_sender = packet_sender.lock().await;
+        if let Some(sender) = packet_sender.as_ref() {
+            let (sample_rate, channels, samples) =
+                processor_chain.codec.conceal(INTERNAL_SAMPLERATE);
+            let mut af = AudioFrame {
+                track_id: track_id.clone(),
+                samples: crate::media::Samples::PCM { samples },
+                timestamp: crate::media::get_timestamp(),
+                sample_rate,
+                channels,
+                ..Default::default()
+            };
+            if let Err(e) = processor_chain.process_frame(&mut af) {
+                debug!(track_id=%track_id, "processor_chain process_frame error: {:?}", e);
+            }
+            sender.send(af).ok();
+        }
+    }
+
     pub fn parse_sdp_payload_types(&mut self, sdp_type: SdpType, sdp_str: &str) -> Result<()> {
         use crate::media::negotiate::parse_rtpmap;
         let sdp = rustrtc::SessionDescription::parse(sdp_type, sdp_str)?;
@@ -553,6 +774,7 @@ impl RtcTrack {
             if let Some((pt, codec)) = negotiated {
                 info!(track_id=%self.track_id, "Negotiated primary audio PT {} ({:?})", pt, codec);
                 self.payload_type = Some(pt);
+                self.negotiated_payload_type.store(pt, Ordering::Relaxed);
             }
         }
         Ok(())
@@ -754,6 +976,21 @@ impl Track for RtcTrack {
     async fn stop(&self) -> Result<()> {
         self.cancel_token.cancel();
         if let Some(pc) = &self.peer_connection {
+            if let Ok(stats) = pc.get_stats().await {
+                let codec = self
+                    .encoder
+                    .get_codec_for_pt(self.get_payload_type())
+                    .unwrap_or(CodecType::PCMU);
+                Self::report_quality(
+                    &self.track_id,
+                    &stats,
+                    codec,
+                    self.quality_reports.as_ref(),
+                    &self.event_sender,
+                    "completed.quality.rtp",
+                )
+                .await;
+            }
             pc.close();
         }
         Ok(())
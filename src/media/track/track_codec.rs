@@ -1,4 +1,4 @@
-use crate::{media::AudioFrame, media::PcmBuf, media::Samples};
+use crate::{media::AudioFrame, media::PcmBuf, media::Samples, media::plc::WaveformPlc};
 use audio_codec::{
     bytes_to_samples,
     g722::{G722Decoder, G722Encoder},
@@ -30,6 +30,9 @@ pub struct TrackCodec {
     resampler: Option<BoxedResampler>,
     resampler_in_rate: u32,
     resampler_out_rate: u32,
+    plc: WaveformPlc,
+    /// Decoded audio is only kept for concealment behind a jitter buffer
+    plc_enabled: bool,
     pub payload_type_map: Arc<RwLock<HashMap<u8, CodecType>>>,
 }
 
@@ -38,6 +41,7 @@ impl Clone for TrackCodec {
         let mut new = Self::new();
         // Share the same underlying map so reinvite PT updates are visible to all clones.
         new.payload_type_map = Arc::clone(&self.payload_type_map);
+        new.plc_enabled = self.plc_enabled;
         new
     }
 }
@@ -67,10 +71,17 @@ impl TrackCodec {
             resampler: None,
             resampler_in_rate: 0,
             resampler_out_rate: 0,
+            plc: WaveformPlc::new(),
+            plc_enabled: false,
             payload_type_map,
         }
     }
 
+    /// Keep decoded audio so lost packets can be concealed
+    pub fn enable_plc(&mut self) {
+        self.plc_enabled = true;
+    }
+
     pub fn set_payload_type(&mut self, pt: u8, codec: CodecType) {
         self.payload_type_map.write().unwrap().insert(pt, codec);
     }
@@ -116,7 +127,7 @@ impl TrackCodec {
                 _ => CodecType::PCMU,
             });
 
-        let pcm = match codec {
+        let mut pcm = match codec {
             CodecType::PCMU => self.pcmu_decoder.decode(payload),
             CodecType::PCMA => self.pcma_decoder.decode(payload),
             CodecType::G722 => self
@@ -148,6 +159,9 @@ impl TrackCodec {
             }
             _ => (8000, 1),
         };
+        if self.plc_enabled && channels == 1 {
+            self.plc.decoded(&mut pcm, in_rate);
+        }
 
         (
             target_sample_rate,
@@ -156,6 +170,20 @@ impl TrackCodec {
         )
     }
 
+    /// Audio for a lost packet, continuing what was last decoded.
+    ///
+    /// audio-codec does not expose the Opus and G.729 decoders' own
+    /// concealment, so every codec uses waveform repetition.
+    pub fn conceal(&mut self, target_sample_rate: u32) -> (u32, u16, PcmBuf) {
+        let in_rate = self.plc.sample_rate();
+        let pcm = self.plc.conceal();
+        (
+            target_sample_rate,
+            1,
+            self.resample(pcm, in_rate, target_sample_rate),
+        )
+    }
+
     pub fn resample(&mut self, pcm: PcmBuf, in_rate: u32, out_rate: u32) -> PcmBuf {
         if in_rate == out_rate {
             return pcm;
@@ -259,4 +287,26 @@ mod tests {
         // If this were raw PCM bytes, it would be 640 bytes.
         assert!(payload.len() < 640);
     }
+
+    #[test]
+    fn test_conceal_continues_decoded_audio() {
+        let mut codec = TrackCodec::new();
+        let mut encoder = PcmuEncoder::new();
+        let tone: PcmBuf = (0..160)
+            .map(|i| (8000.0 * (2.0 * std::f64::consts::PI * i as f64 / 40.0).sin()) as i16)
+            .collect();
+        // Without a jitter buffer nothing is kept to conceal from
+        codec.decode(0, &encoder.encode(&tone), 8000);
+        assert!(codec.conceal(8000).2.iter().all(|&s| s == 0));
+
+        let mut codec = TrackCodec::new();
+        codec.enable_plc();
+        let mut codec = codec.clone();
+        codec.decode(0, &encoder.encode(&tone), 8000);
+
+        let (sample_rate, channels, pcm) = codec.conceal(16000);
+        assert_eq!((sample_rate, channels), (16000, 1));
+        assert!(!pcm.is_empty());
+        assert!(pcm.iter().any(|&s| s.abs() > 4000));
+    }
 }
@@ -589,6 +589,19 @@ impl TtsTask {
             return;
         }
 
+        let text = crate::synthesis::normalize::pronounce(
+            &text,
+            &cmd.option,
+            self.client.markup_support(),
+        );
+        let text = crate::synthesis::normalize::normalize(&text, &cmd.option);
+        // Render SSML markup into whatever the provider understands
+        let text = crate::synthesis::ssml::prepare(
+            &text,
+            self.client.markup_support(),
+            cmd.option.language.as_deref(),
+        );
+
         if let Err(e) = self
             .client
             .synthesize(&text, cmd_seq, Some(cmd.option.clone()))
@@ -59,6 +59,27 @@ impl OfflineConfig {
         self.supertonic_dir().join("voice_styles")
     }
 
+    /// Optional, present in models that support voice cloning
+    pub fn supertonic_style_encoder_path(&self) -> PathBuf {
+        self.supertonic_onnx_dir().join("style_encoder.onnx")
+    }
+
+    pub fn whisper_dir(&self, variant: &str) -> PathBuf {
+        self.models_dir.join("whisper").join(variant)
+    }
+
+    pub fn whisper_encoder_path(&self, variant: &str) -> PathBuf {
+        self.whisper_dir(variant).join("encoder.int8.onnx")
+    }
+
+    pub fn whisper_decoder_path(&self, variant: &str) -> PathBuf {
+        self.whisper_dir(variant).join("decoder.int8.onnx")
+    }
+
+    pub fn whisper_tokens_path(&self, variant: &str) -> PathBuf {
+        self.whisper_dir(variant).join("tokens.txt")
+    }
+
     pub fn validate(&self) -> Result<()> {
         if !self.models_dir.exists() {
             anyhow::bail!(
@@ -73,6 +94,12 @@ impl OfflineConfig {
         self.sensevoice_model_path().exists() && self.sensevoice_tokens_path().exists()
     }
 
+    pub fn whisper_available(&self, variant: &str) -> bool {
+        self.whisper_encoder_path(variant).exists()
+            && self.whisper_decoder_path(variant).exists()
+            && self.whisper_tokens_path(variant).exists()
+    }
+
     pub fn supertonic_available(&self) -> bool {
         let onnx_dir = self.supertonic_onnx_dir();
         onnx_dir.join("duration_predictor.onnx").exists()
@@ -105,5 +132,9 @@ mod tests {
             config.supertonic_onnx_dir(),
             PathBuf::from("/test/models/supertonic/onnx")
         );
+        assert_eq!(
+            config.whisper_decoder_path("distil-small.en"),
+            PathBuf::from("/test/models/whisper/distil-small.en/decoder.int8.onnx")
+        );
     }
 }
@@ -9,10 +9,36 @@ pub struct ModelDownloader {
     api: Api,
 }
 
+/// Whisper variants published as int8 ONNX exports, `.en` ones are English-only
+pub const WHISPER_VARIANTS: &[&str] = &[
+    "tiny",
+    "tiny.en",
+    "base",
+    "base.en",
+    "small",
+    "small.en",
+    "medium",
+    "medium.en",
+    "distil-small.en",
+    "distil-medium.en",
+    "turbo",
+];
+
+pub const DEFAULT_WHISPER_VARIANT: &str = "base";
+
+const SUPERTONIC_REPO: &str = "Supertone/supertonic-2";
+/// Voice styles of the Supertonic repo, used when its file list is unavailable
+pub const SUPERTONIC_VOICES: &[&str] =
+    &["M1", "M2", "M3", "M4", "M5", "F1", "F2", "F3", "F4", "F5"];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ModelType {
     Sensevoice,
     Supertonic,
+    /// Every voice style of the Supertonic repo
+    SupertonicVoices,
+    /// One of [`WHISPER_VARIANTS`]
+    Whisper(&'static str),
     All,
 }
 
@@ -21,8 +47,16 @@ impl ModelType {
         match s.to_lowercase().as_str() {
             "sensevoice" => Some(Self::Sensevoice),
             "supertonic" => Some(Self::Supertonic),
+            "supertonic-voices" => Some(Self::SupertonicVoices),
+            "whisper" => Some(Self::Whisper(DEFAULT_WHISPER_VARIANT)),
             "all" => Some(Self::All),
-            _ => None,
+            s => {
+                let variant = s.strip_prefix("whisper-")?;
+                WHISPER_VARIANTS
+                    .iter()
+                    .find(|v| **v == variant)
+                    .map(|v| Self::Whisper(v))
+            }
         }
     }
 }
@@ -39,6 +73,8 @@ impl ModelDownloader {
         match model_type {
             ModelType::Sensevoice => self.download_sensevoice(dest_dir),
             ModelType::Supertonic => self.download_supertonic(dest_dir),
+            ModelType::SupertonicVoices => self.download_voice_pack(SUPERTONIC_REPO, dest_dir),
+            ModelType::Whisper(variant) => self.download_whisper(variant, dest_dir),
             ModelType::All => {
                 self.download_sensevoice(dest_dir)?;
                 self.download_supertonic(dest_dir)?;
@@ -137,12 +173,41 @@ impl ModelDownloader {
         Ok(())
     }
 
+    fn download_whisper(&self, variant: &str, dest_dir: &Path) -> Result<()> {
+        info!("Downloading Whisper {} model...", variant);
+        let whisper_dir = dest_dir.join("whisper").join(variant);
+        fs::create_dir_all(&whisper_dir).context("failed to create whisper directory")?;
+
+        let repo_id = format!("csukuangfj/sherpa-onnx-whisper-{}", variant);
+        let revision = "main";
+        // files are prefixed with the variant in the repo
+        let files = vec![
+            (
+                "encoder.int8.onnx",
+                format!("{}-encoder.int8.onnx", variant),
+            ),
+            (
+                "decoder.int8.onnx",
+                format!("{}-decoder.int8.onnx", variant),
+            ),
+            ("tokens.txt", format!("{}-tokens.txt", variant)),
+        ];
+
+        for (name, file) in files {
+            let dest = whisper_dir.join(name);
+            self.download_file(&repo_id, revision, &file, &dest)?;
+        }
+
+        info!("✓ Whisper {} model downloaded successfully", variant);
+        Ok(())
+    }
+
     fn download_supertonic(&self, dest_dir: &Path) -> Result<()> {
         info!("Downloading Supertonic model...");
         let supertonic_dir = dest_dir.join("supertonic");
         fs::create_dir_all(&supertonic_dir).context("failed to create supertonic directory")?;
 
-        let repo_id = "Supertone/supertonic-2";
+        let repo_id = SUPERTONIC_REPO;
         let revision = "main";
         let files = vec![
             "onnx/duration_predictor.onnx",
@@ -172,6 +237,46 @@ impl ModelDownloader {
         info!("✓ Supertonic model downloaded successfully");
         Ok(())
     }
+
+    /// Downloads the `voice_styles/*.json` of a Hugging Face repo into the
+    /// Supertonic voice catalog
+    pub fn download_voice_pack(&self, repo_id: &str, dest_dir: &Path) -> Result<()> {
+        info!("Downloading Supertonic voices from {}...", repo_id);
+        let voices_dir = dest_dir.join("supertonic").join("voice_styles");
+        fs::create_dir_all(&voices_dir).context("failed to create voice_styles directory")?;
+
+        let files = match self.api.model(repo_id.to_string()).info() {
+            Ok(info) => voice_pack_files(info.siblings.iter().map(|s| s.rfilename.as_str())),
+            Err(err) if repo_id == SUPERTONIC_REPO => {
+                warn!("  failed to list {}: {}, using known voices", repo_id, err);
+                SUPERTONIC_VOICES
+                    .iter()
+                    .map(|voice| format!("voice_styles/{}.json", voice))
+                    .collect()
+            }
+            Err(err) => return Err(err).context(format!("failed to list {}", repo_id)),
+        };
+        if files.is_empty() {
+            anyhow::bail!("{} has no voice styles", repo_id);
+        }
+        for file in &files {
+            let name = file.trim_start_matches("voice_styles/");
+            self.download_file(repo_id, "main", file, &voices_dir.join(name))?;
+        }
+
+        info!("✓ {} Supertonic voices downloaded", files.len());
+        Ok(())
+    }
+}
+
+/// Voice style files at the top of `voice_styles/`
+fn voice_pack_files<'a>(files: impl Iterator<Item = &'a str>) -> Vec<String> {
+    files
+        .filter_map(|file| {
+            let name = file.strip_prefix("voice_styles/")?.strip_suffix(".json")?;
+            (!name.is_empty() && !name.contains('/')).then(|| file.to_string())
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -189,6 +294,34 @@ mod tests {
             Some(ModelType::Supertonic)
         );
         assert_eq!(ModelType::from_str("all"), Some(ModelType::All));
+        assert_eq!(
+            ModelType::from_str("whisper"),
+            Some(ModelType::Whisper("base"))
+        );
+        assert_eq!(
+            ModelType::from_str("whisper-distil-small.en"),
+            Some(ModelType::Whisper("distil-small.en"))
+        );
+        assert_eq!(ModelType::from_str("whisper-huge"), None);
+        assert_eq!(
+            ModelType::from_str("supertonic-voices"),
+            Some(ModelType::SupertonicVoices)
+        );
         assert_eq!(ModelType::from_str("invalid"), None);
     }
+
+    #[test]
+    fn test_voice_pack_files() {
+        let files = [
+            "onnx/tts.json",
+            "voice_styles/M1.json",
+            "voice_styles/F2.json",
+            "voice_styles/extra/old.json",
+            "voice_styles/README.md",
+        ];
+        assert_eq!(
+            voice_pack_files(files.into_iter()),
+            vec!["voice_styles/M1.json", "voice_styles/F2.json"]
+        );
+    }
 }
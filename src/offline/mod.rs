@@ -4,9 +4,15 @@ pub mod downloader;
 #[cfg(feature = "offline")]
 pub mod sensevoice;
 
+#[cfg(feature = "offline")]
+mod session;
+
 #[cfg(feature = "offline")]
 pub mod supertonic;
 
+#[cfg(feature = "offline")]
+pub mod whisper;
+
 pub use config::OfflineConfig;
 pub use downloader::{ModelDownloader, ModelType};
 
@@ -16,17 +22,45 @@ pub use sensevoice::SensevoiceEncoder;
 #[cfg(feature = "offline")]
 pub use supertonic::SupertonicTts;
 
+#[cfg(feature = "offline")]
+pub use whisper::WhisperModel;
+
 use anyhow::{Result, anyhow};
 use once_cell::sync::OnceCell;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{Mutex, RwLock};
 use tracing::{debug, info};
 
+/// Text recognized in an utterance, and what the model could tell about it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recognition {
+    pub text: String,
+    /// Detected language, e.g. "en", "zh", "yue"
+    pub language: Option<String>,
+    /// Speaker emotion: happy, sad, angry, neutral, fearful, disgusted or surprised
+    pub emotion: Option<String>,
+    /// Audio event: speech, bgm, applause, laughter, cry, sneeze, breath or cough
+    pub audio_event: Option<String>,
+    /// Word timings, for models that report them
+    pub words: Vec<RecognizedWord>,
+}
+
+/// A word with times relative to the start of the audio.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecognizedWord {
+    pub word: String,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub confidence: Option<f32>,
+}
+
 #[cfg(feature = "offline")]
 pub struct OfflineModels {
     config: OfflineConfig,
     sensevoice: Arc<RwLock<Option<SensevoiceEncoder>>>,
     supertonic: Arc<RwLock<Option<SupertonicTts>>>,
+    /// Whisper models by variant, loaded on first use
+    whisper: Mutex<HashMap<String, Arc<Mutex<WhisperModel>>>>,
 }
 
 #[cfg(feature = "offline")]
@@ -36,6 +70,7 @@ impl OfflineModels {
             config,
             sensevoice: Arc::new(RwLock::new(None)),
             supertonic: Arc::new(RwLock::new(None)),
+            whisper: Mutex::new(HashMap::new()),
         }
     }
 
@@ -93,6 +128,32 @@ impl OfflineModels {
         Ok(self.supertonic.clone())
     }
 
+    pub async fn get_whisper(&self, variant: &str) -> Result<Arc<Mutex<WhisperModel>>> {
+        let mut models = self.whisper.lock().await;
+        if let Some(model) = models.get(variant) {
+            return Ok(model.clone());
+        }
+        if !self.config.whisper_available(variant) {
+            anyhow::bail!(
+                "Whisper {} model files not found. Please run with --download-models whisper-{}",
+                variant,
+                variant
+            );
+        }
+
+        info!("Initializing Whisper {} model...", variant);
+        let model = WhisperModel::new(
+            &self.config.whisper_encoder_path(variant),
+            &self.config.whisper_decoder_path(variant),
+            &self.config.whisper_tokens_path(variant),
+            self.config.threads,
+        )?;
+        let model = Arc::new(Mutex::new(model));
+        models.insert(variant.to_string(), model.clone());
+        info!("✓ Whisper {} model initialized", variant);
+        Ok(model)
+    }
+
     pub fn config(&self) -> &OfflineConfig {
         &self.config
     }
@@ -1,12 +1,12 @@
 use super::tokenizer::TokenDecoder;
-use anyhow::{Context, Result, anyhow, ensure};
+use crate::offline::{Recognition, session::build_session_with_ort_cache};
+use anyhow::{Result, anyhow, ensure};
 use ndarray::{Array3, Axis};
 use ort::{
-    session::{Session, builder::GraphOptimizationLevel},
+    session::Session,
     value::{DynValue, Tensor},
 };
-use std::{fs, path::Path};
-use tracing::warn;
+use std::path::Path;
 
 pub struct SensevoiceEncoder {
     session: Session,
@@ -42,7 +42,7 @@ impl SensevoiceEncoder {
         feats: ndarray::ArrayView3<'_, f32>, // [B=1, T, D]
         language_id: i32,
         use_itn: bool,
-    ) -> Result<String> {
+    ) -> Result<Recognition> {
         let b = feats.len_of(Axis(0));
         ensure!(b == 1, "batch=1 only");
         let t = feats.len_of(Axis(1));
@@ -109,70 +109,10 @@ impl SensevoiceEncoder {
         ensure!(dims[0] == 1, "expect batch=1 but got {}", dims[0]);
         let logits = Array3::from_shape_vec((dims[0], dims[1], dims[2]), data.to_vec())?;
         let ids = argmax_and_unique(logits.index_axis(Axis(0), 0));
-        Ok(self.decoder.decode_ids(&ids))
+        Ok(self.decoder.decode(&ids))
     }
 }
 
-fn build_session_with_ort_cache(model_path: &Path, intra_threads: usize) -> Result<Session> {
-    let ort_path = model_path.with_extension("ort");
-
-    if ort_path.exists() {
-        let session_attempt = Session::builder()
-            .map_err(|e| anyhow!("ORT session builder error: {e}"))?
-            .with_intra_threads(intra_threads)
-            .map_err(|e| anyhow!("ORT intra threads error: {e}"))?
-            .commit_from_file(&ort_path);
-
-        match session_attempt {
-            Ok(session) => return Ok(session),
-            Err(err) => {
-                warn!(
-                    ort = %ort_path.display(),
-                    model = %model_path.display(),
-                    error = %err,
-                    "failed to load cached ORT graph, regenerating"
-                );
-                let _ = fs::remove_file(&ort_path);
-            }
-        }
-    }
-
-    let builder = Session::builder()
-        .map_err(|e| anyhow!("ORT session builder error: {e}"))?
-        .with_optimization_level(GraphOptimizationLevel::Level2)
-        .map_err(|e| anyhow!("ORT optimization level error: {e}"))?
-        .with_intra_threads(intra_threads)
-        .map_err(|e| anyhow!("ORT intra threads error: {e}"))?;
-
-    if let Ok(builder_with_cache) = builder.with_optimized_model_path(&ort_path) {
-        match builder_with_cache.commit_from_file(model_path) {
-            Ok(session) => return Ok(session),
-            Err(err) => {
-                warn!(
-                    ort = %ort_path.display(),
-                    model = %model_path.display(),
-                    error = %err,
-                    "failed to build session with ORT cache, retrying without cache"
-                );
-                let _ = fs::remove_file(&ort_path);
-            }
-        }
-    }
-
-    let fallback_builder = Session::builder()
-        .map_err(|e| anyhow!("ORT session builder error: {e}"))?
-        .with_optimization_level(GraphOptimizationLevel::Level2)
-        .map_err(|e| anyhow!("ORT optimization level error: {e}"))?
-        .with_intra_threads(intra_threads)
-        .map_err(|e| anyhow!("ORT intra threads error: {e}"))?;
-
-    let model_bytes = fs::read(model_path)
-        .with_context(|| format!("read encoder model {}", model_path.display()))?;
-    fallback_builder
-        .commit_from_memory(&model_bytes)
-        .map_err(|e| anyhow!("ORT load model error: {e}"))
-}
-
 fn argmax_and_unique(logits: ndarray::ArrayView2<'_, f32>) -> Vec<i32> {
     let blank_id = 0i32;
     let mut prev: Option<i32> = None;
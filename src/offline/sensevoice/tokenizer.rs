@@ -2,12 +2,49 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 
+use crate::offline::Recognition;
 use anyhow::{anyhow, Context, Result};
 
 pub struct TokenDecoder {
     pieces: Vec<String>,
 }
 
+enum Tag {
+    Language(&'static str),
+    Emotion(&'static str),
+    Event(&'static str),
+    Other,
+}
+
+fn parse_tag(piece: &str) -> Option<Tag> {
+    let name = piece.strip_prefix("<|")?.strip_suffix("|>")?;
+    let tag = match name {
+        "zh" => Tag::Language("zh"),
+        "en" => Tag::Language("en"),
+        "yue" => Tag::Language("yue"),
+        "ja" => Tag::Language("ja"),
+        "ko" => Tag::Language("ko"),
+        "HAPPY" => Tag::Emotion("happy"),
+        "SAD" => Tag::Emotion("sad"),
+        "ANGRY" => Tag::Emotion("angry"),
+        "NEUTRAL" => Tag::Emotion("neutral"),
+        "FEARFUL" => Tag::Emotion("fearful"),
+        "DISGUSTED" => Tag::Emotion("disgusted"),
+        "SURPRISED" => Tag::Emotion("surprised"),
+        "Speech" => Tag::Event("speech"),
+        "BGM" => Tag::Event("bgm"),
+        "Applause" => Tag::Event("applause"),
+        "Laughter" => Tag::Event("laughter"),
+        "Cry" => Tag::Event("cry"),
+        "Sneeze" => Tag::Event("sneeze"),
+        "Breath" => Tag::Event("breath"),
+        "Cough" => Tag::Event("cough"),
+        // nospeech, EMO_UNKNOWN, Event_UNK, withitn, woitn
+        _ => Tag::Other,
+    };
+    Some(tag)
+}
+
 impl TokenDecoder {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::open(path.as_ref())
@@ -42,6 +79,11 @@ impl TokenDecoder {
     }
 
     pub fn decode_ids(&self, ids: &[i32]) -> String {
+        self.decode(ids).text
+    }
+
+    pub fn decode(&self, ids: &[i32]) -> Recognition {
+        let mut recognition = Recognition::default();
         let mut text = String::new();
         for &id in ids {
             if id < 0 {
@@ -55,6 +97,25 @@ impl TokenDecoder {
             if piece == "<unk>" || piece == "<s>" || piece == "</s>" {
                 continue;
             }
+            // the first tag of each kind wins
+            match parse_tag(piece) {
+                Some(Tag::Language(language)) => {
+                    recognition
+                        .language
+                        .get_or_insert_with(|| language.to_string());
+                }
+                Some(Tag::Emotion(emotion)) => {
+                    recognition
+                        .emotion
+                        .get_or_insert_with(|| emotion.to_string());
+                }
+                Some(Tag::Event(event)) => {
+                    recognition
+                        .audio_event
+                        .get_or_insert_with(|| event.to_string());
+                }
+                Some(Tag::Other) | None => {}
+            }
             if piece.starts_with('<') && piece.ends_with('>') {
                 continue;
             }
@@ -67,6 +128,50 @@ impl TokenDecoder {
                 text.push_str(piece);
             }
         }
-        text.trim().to_string()
+        recognition.text = text.trim().to_string();
+        recognition
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_rich_tags() {
+        let pieces = [
+            "<unk>",
+            "<|en|>",
+            "<|HAPPY|>",
+            "<|Laughter|>",
+            "<|withitn|>",
+            "▁Hello",
+            "▁world",
+            ".",
+            "<|EMO_UNKNOWN|>",
+            "<|Event_UNK|>",
+        ];
+        let decoder = TokenDecoder {
+            pieces: pieces.iter().map(|p| p.to_string()).collect(),
+        };
+
+        let recognition = decoder.decode(&[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(
+            recognition,
+            Recognition {
+                text: "Hello world.".to_string(),
+                language: Some("en".to_string()),
+                emotion: Some("happy".to_string()),
+                audio_event: Some("laughter".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(decoder.decode_ids(&[1, 2, 3, 4, 5, 6, 7]), "Hello world.");
+
+        // unknown emotion and event carry no tag
+        let recognition = decoder.decode(&[1, 8, 9, 4]);
+        assert_eq!(recognition.text, "");
+        assert_eq!(recognition.emotion, None);
+        assert_eq!(recognition.audio_event, None);
     }
 }
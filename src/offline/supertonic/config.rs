@@ -62,6 +62,23 @@ pub struct TTLConfig {
 pub struct VoiceStyleData {
     pub style_ttl: StyleComponent,
     pub style_dp: StyleComponent,
+    /// Catalog details, absent from the stock voice styles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<VoiceMetadata>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gender: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Derived from reference audio
+    #[serde(default)]
+    pub cloned: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
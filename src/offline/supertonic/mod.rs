@@ -2,5 +2,8 @@ mod config;
 mod model;
 mod processor;
 mod tts;
+mod voice;
 
-pub use tts::SupertonicTts;
+pub use config::VoiceMetadata;
+pub use tts::{SupertonicTts, supertonic_language};
+pub use voice::{VoiceInfo, is_valid_voice_name, list_voices};
@@ -1,13 +1,10 @@
 use super::config::{Config, VoiceStyleData};
 use super::processor::{UnicodeProcessor, sample_noisy_latent};
-use anyhow::{Context, Result, anyhow};
+use crate::offline::session::build_session_with_ort_cache;
+use anyhow::{Context, Result};
 use ndarray::{Array, Array3, Dimension};
-use ort::{
-    session::{Session, builder::GraphOptimizationLevel},
-    value::Value,
-};
+use ort::{session::Session, value::Value};
 use std::{fs, io::BufReader, path::Path};
-use tracing::warn;
 
 pub struct Style {
     pub ttl: Array3<f32>,
@@ -288,62 +285,3 @@ where
     let (data, _) = array.into_raw_vec_and_offset();
     Ok(Value::from_array((shape, data))?.into())
 }
-
-fn build_session_with_ort_cache(model_path: &Path, intra_threads: usize) -> Result<Session> {
-    let ort_path = model_path.with_extension("ort");
-
-    if ort_path.exists() {
-        let session_attempt = Session::builder()
-            .map_err(|e| anyhow!("ORT session builder error: {e}"))?
-            .with_intra_threads(intra_threads)
-            .map_err(|e| anyhow!("ORT intra threads error: {e}"))?
-            .commit_from_file(&ort_path);
-
-        match session_attempt {
-            Ok(session) => return Ok(session),
-            Err(err) => {
-                warn!(
-                    ort = %ort_path.display(),
-                    model = %model_path.display(),
-                    error = %err,
-                    "failed to load cached ORT graph, regenerating"
-                );
-                let _ = fs::remove_file(&ort_path);
-            }
-        }
-    }
-
-    let builder = Session::builder()
-        .map_err(|e| anyhow!("ORT session builder error: {e}"))?
-        .with_optimization_level(GraphOptimizationLevel::Level2)
-        .map_err(|e| anyhow!("ORT optimization level error: {e}"))?
-        .with_intra_threads(intra_threads)
-        .map_err(|e| anyhow!("ORT intra threads error: {e}"))?;
-
-    if let Ok(builder_with_cache) = builder.with_optimized_model_path(&ort_path) {
-        match builder_with_cache.commit_from_file(model_path) {
-            Ok(session) => return Ok(session),
-            Err(err) => {
-                warn!(
-                    ort = %ort_path.display(),
-                    model = %model_path.display(),
-                    error = %err,
-                    "failed to build session with ORT cache, retrying without cache"
-                );
-            }
-        }
-    }
-
-    let fallback_builder = Session::builder()
-        .map_err(|e| anyhow!("ORT session builder error: {e}"))?
-        .with_optimization_level(GraphOptimizationLevel::Level2)
-        .map_err(|e| anyhow!("ORT optimization level error: {e}"))?
-        .with_intra_threads(intra_threads)
-        .map_err(|e| anyhow!("ORT intra threads error: {e}"))?;
-
-    let model_bytes = fs::read(model_path)
-        .with_context(|| format!("read encoder model {}", model_path.display()))?;
-    fallback_builder
-        .commit_from_memory(&model_bytes)
-        .map_err(|e| anyhow!("ORT load model error: {e}"))
-}
@@ -1,13 +1,32 @@
+use super::config::VoiceMetadata;
 use super::model::{Style, SupertonicModel, load_voice_style};
-use super::processor::chunk_text;
-use anyhow::{Result, anyhow};
+use super::processor::{chunk_text, is_valid_lang};
+use super::voice::{StyleEncoder, VoiceInfo, is_valid_voice_name, list_voices};
+use anyhow::{Result, anyhow, ensure};
+use audio_codec::BoxedResampler;
 use std::collections::HashMap;
+use std::fs;
 use std::path::{Path, PathBuf};
 
+/// Reference audio for voice cloning, in seconds
+const MIN_REFERENCE_SECS: usize = 3;
+const MAX_REFERENCE_SECS: usize = 30;
+
 pub struct SupertonicTts {
     model: SupertonicModel,
     voice_styles_dir: PathBuf,
     style_cache: HashMap<String, Style>,
+    style_encoder: Option<StyleEncoder>,
+}
+
+/// Language tag for Supertonic from a code such as "en-US", English
+/// when the model does not speak it
+pub fn supertonic_language(language: Option<&str>) -> String {
+    language
+        .and_then(|l| l.split(['-', '_']).next())
+        .map(str::to_lowercase)
+        .filter(|l| is_valid_lang(l))
+        .unwrap_or_else(|| "en".to_string())
 }
 
 impl SupertonicTts {
@@ -19,11 +38,13 @@ impl SupertonicTts {
         _use_gpu: bool,
     ) -> Result<Self> {
         let model = SupertonicModel::new(&onnx_dir, &config_path, &voice_styles_dir, threads)?;
+        let style_encoder = StyleEncoder::load(onnx_dir.as_ref(), threads)?;
 
         Ok(Self {
             model,
             voice_styles_dir: voice_styles_dir.as_ref().to_path_buf(),
             style_cache: HashMap::new(),
+            style_encoder,
         })
     }
 
@@ -31,6 +52,61 @@ impl SupertonicTts {
         self.model.sample_rate
     }
 
+    /// Voices in the catalog, stock and cloned
+    pub fn voices(&self) -> Result<Vec<VoiceInfo>> {
+        list_voices(&self.voice_styles_dir)
+    }
+
+    pub fn supports_cloning(&self) -> bool {
+        self.style_encoder.is_some()
+    }
+
+    /// Derives a voice from a few seconds of mono reference speech and
+    /// saves it to the catalog as `name`, replacing a voice of that name
+    pub fn clone_voice(
+        &mut self,
+        name: &str,
+        samples: &[i16],
+        sample_rate: u32,
+        metadata: VoiceMetadata,
+    ) -> Result<VoiceInfo> {
+        ensure!(is_valid_voice_name(name), "invalid voice name: {}", name);
+        let encoder = self
+            .style_encoder
+            .as_mut()
+            .ok_or_else(|| anyhow!("this Supertonic model does not support voice cloning"))?;
+        let seconds = samples.len() / sample_rate.max(1) as usize;
+        ensure!(
+            (MIN_REFERENCE_SECS..=MAX_REFERENCE_SECS).contains(&seconds),
+            "reference audio must be {}s to {}s long",
+            MIN_REFERENCE_SECS,
+            MAX_REFERENCE_SECS
+        );
+
+        let model_rate = self.model.sample_rate as usize;
+        let samples = if sample_rate as usize != model_rate {
+            BoxedResampler::new(sample_rate as usize, model_rate)?.resample(samples)
+        } else {
+            samples.to_vec()
+        };
+        let samples: Vec<f32> = samples.iter().map(|&s| s as f32 / 32768.0).collect();
+        let metadata = VoiceMetadata {
+            cloned: true,
+            ..metadata
+        };
+        let data = encoder.encode(&samples, metadata.clone())?;
+
+        fs::create_dir_all(&self.voice_styles_dir)?;
+        let path = self.voice_styles_dir.join(format!("{}.json", name));
+        fs::write(&path, serde_json::to_vec(&data)?)?;
+        // the next synthesis reloads it
+        self.style_cache.remove(name);
+        Ok(VoiceInfo {
+            name: name.to_string(),
+            metadata,
+        })
+    }
+
     pub fn synthesize(
         &mut self,
         text: &str,
@@ -46,6 +122,11 @@ impl SupertonicTts {
         let speed = speed.unwrap_or(1.0);
         let style_name = voice_style.unwrap_or("M1");
 
+        ensure!(
+            is_valid_voice_name(style_name),
+            "invalid voice name: {}",
+            style_name
+        );
         // Ensure style is loaded
         if !self.style_cache.contains_key(style_name) {
             let path = self.voice_styles_dir.join(format!("{}.json", style_name));
@@ -0,0 +1,192 @@
+//! Voice catalog: every `voice_styles/<name>.json` is a speaker style that
+//! can be picked per synthesis while the ONNX sessions stay loaded.
+//!
+//! New styles can be derived from reference audio when the model ships a
+//! style encoder, `onnx/style_encoder.onnx`, taking `wav` ([1, samples] at
+//! the model sample rate) and returning `style_ttl` and `style_dp` shaped
+//! like the stock voice styles.
+use super::config::{StyleComponent, VoiceMetadata, VoiceStyleData};
+use crate::offline::session::build_session_with_ort_cache;
+use anyhow::{Result, anyhow, ensure};
+use ort::{session::Session, value::Tensor};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::BufReader, path::Path};
+use tracing::warn;
+
+// see `OfflineConfig::supertonic_style_encoder_path`
+const STYLE_ENCODER: &str = "style_encoder.onnx";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub metadata: VoiceMetadata,
+}
+
+/// Voice names are file stems, so only letters, digits, `-` and `_`
+pub fn is_valid_voice_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Stock voices are named by gender and number, e.g. "M1" or "F3"
+fn stock_gender(name: &str) -> Option<String> {
+    let (prefix, number) = name.split_at_checked(1)?;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    match prefix {
+        "M" => Some("male".to_string()),
+        "F" => Some("female".to_string()),
+        _ => None,
+    }
+}
+
+pub fn list_voices(dir: &Path) -> Result<Vec<VoiceInfo>> {
+    #[derive(Deserialize)]
+    struct Header {
+        #[serde(default)]
+        metadata: Option<VoiceMetadata>,
+    }
+
+    let mut voices = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(voices),
+        Err(e) => return Err(anyhow!("read {}: {}", dir.display(), e)),
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if !is_valid_voice_name(name) {
+            continue;
+        }
+        let header: Header = match fs::File::open(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| Ok(serde_json::from_reader(BufReader::new(file))?))
+        {
+            Ok(header) => header,
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "invalid voice style");
+                continue;
+            }
+        };
+        let mut metadata = header.metadata.unwrap_or_default();
+        if metadata.gender.is_none() && !metadata.cloned {
+            metadata.gender = stock_gender(name);
+        }
+        voices.push(VoiceInfo {
+            name: name.to_string(),
+            metadata,
+        });
+    }
+    voices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(voices)
+}
+
+fn component(shape: &[i64], data: &[f32]) -> Result<StyleComponent> {
+    ensure!(
+        shape.len() == 3 && shape[0] == 1,
+        "unexpected style shape {:?}",
+        shape
+    );
+    let (rows, cols) = (shape[1] as usize, shape[2] as usize);
+    ensure!(
+        data.len() == rows * cols,
+        "style data does not match its shape"
+    );
+    let rows = data.chunks(cols.max(1)).map(<[f32]>::to_vec).collect();
+    Ok(StyleComponent {
+        data: vec![rows],
+        dims: vec![1, shape[1] as usize, shape[2] as usize],
+        dtype: "float32".to_string(),
+    })
+}
+
+pub(super) struct StyleEncoder {
+    session: Session,
+}
+
+impl StyleEncoder {
+    /// Loads the style encoder if the model has one
+    pub fn load(onnx_dir: &Path, intra_threads: usize) -> Result<Option<Self>> {
+        let path = onnx_dir.join(STYLE_ENCODER);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let session = build_session_with_ort_cache(&path, intra_threads)?;
+        Ok(Some(Self { session }))
+    }
+
+    /// Derives a voice style from mono audio at the model sample rate
+    pub fn encode(&mut self, samples: &[f32], metadata: VoiceMetadata) -> Result<VoiceStyleData> {
+        let wav = Tensor::from_array(([1, samples.len()], samples.to_vec()))?;
+        let outputs = self.session.run(ort::inputs! { "wav" => wav })?;
+        let (shape, data) = outputs["style_ttl"].try_extract_tensor::<f32>()?;
+        let style_ttl = component(shape, data)?;
+        let (shape, data) = outputs["style_dp"].try_extract_tensor::<f32>()?;
+        let style_dp = component(shape, data)?;
+        Ok(VoiceStyleData {
+            style_ttl,
+            style_dp,
+            metadata: Some(metadata),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voice_names() {
+        assert!(is_valid_voice_name("M1"));
+        assert!(is_valid_voice_name("support-agent_2"));
+        assert!(!is_valid_voice_name("../M1"));
+        assert!(!is_valid_voice_name(""));
+        assert_eq!(stock_gender("F3").as_deref(), Some("female"));
+        assert_eq!(stock_gender("Max"), None);
+    }
+
+    #[test]
+    fn test_list_voices() {
+        let dir = tempfile::tempdir().unwrap();
+        let style = |metadata: Option<VoiceMetadata>| VoiceStyleData {
+            style_ttl: component(&[1, 1, 2], &[0.1, 0.2]).unwrap(),
+            style_dp: component(&[1, 2, 1], &[0.3, 0.4]).unwrap(),
+            metadata,
+        };
+        let write = |name: &str, data: &VoiceStyleData| {
+            let path = dir.path().join(format!("{}.json", name));
+            fs::write(path, serde_json::to_vec(data).unwrap()).unwrap();
+        };
+        write("M2", &style(None));
+        write(
+            "agent",
+            &style(Some(VoiceMetadata {
+                description: Some("calm".to_string()),
+                cloned: true,
+                ..Default::default()
+            })),
+        );
+        fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+        let voices = list_voices(dir.path()).unwrap();
+        let names: Vec<_> = voices.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["M2", "agent"]);
+        assert_eq!(voices[0].metadata.gender.as_deref(), Some("male"));
+        assert!(voices[1].metadata.cloned);
+        assert_eq!(voices[1].metadata.description.as_deref(), Some("calm"));
+        assert_eq!(style(None).style_dp.data, vec![vec![vec![0.3], vec![0.4]]]);
+        assert!(list_voices(&dir.path().join("missing")).unwrap().is_empty());
+    }
+}
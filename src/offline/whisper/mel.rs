@@ -0,0 +1,170 @@
+//! Whisper log-mel spectrogram: 25ms Hann windows every 10ms over 30s of
+//! 16kHz audio, Slaney mel filters up to 8kHz.
+use realfft::{RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+
+pub const SAMPLE_RATE: usize = 16000;
+const N_FFT: usize = 400;
+const HOP_LENGTH: usize = 160;
+/// Whisper always encodes 30s windows
+pub const CHUNK_SAMPLES: usize = 30 * SAMPLE_RATE;
+pub const N_FRAMES: usize = CHUNK_SAMPLES / HOP_LENGTH;
+
+pub struct LogMel {
+    n_mels: usize,
+    /// [n_mels, N_FFT / 2 + 1]
+    filters: Vec<f32>,
+    window: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+}
+
+fn hz_to_mel(hz: f64) -> f64 {
+    // linear below 1kHz, logarithmic above (Slaney)
+    let f_sp = 200.0 / 3.0;
+    let min_log_hz = 1000.0;
+    let min_log_mel = min_log_hz / f_sp;
+    let logstep = 6.4f64.ln() / 27.0;
+    if hz >= min_log_hz {
+        min_log_mel + (hz / min_log_hz).ln() / logstep
+    } else {
+        hz / f_sp
+    }
+}
+
+fn mel_to_hz(mel: f64) -> f64 {
+    let f_sp = 200.0 / 3.0;
+    let min_log_hz = 1000.0;
+    let min_log_mel = min_log_hz / f_sp;
+    let logstep = 6.4f64.ln() / 27.0;
+    if mel >= min_log_mel {
+        min_log_hz * (logstep * (mel - min_log_mel)).exp()
+    } else {
+        mel * f_sp
+    }
+}
+
+/// Slaney-normalized triangular filters, as `librosa.filters.mel`
+fn mel_filters(n_mels: usize) -> Vec<f32> {
+    let n_bins = N_FFT / 2 + 1;
+    let max_hz = SAMPLE_RATE as f64 / 2.0;
+    let max_mel = hz_to_mel(max_hz);
+    let points: Vec<f64> = (0..n_mels + 2)
+        .map(|i| mel_to_hz(max_mel * i as f64 / (n_mels + 1) as f64))
+        .collect();
+    let mut filters = vec![0.0f32; n_mels * n_bins];
+    for m in 0..n_mels {
+        let (lower, center, upper) = (points[m], points[m + 1], points[m + 2]);
+        let enorm = 2.0 / (upper - lower);
+        for k in 0..n_bins {
+            let hz = k as f64 * max_hz / (n_bins - 1) as f64;
+            let rising = (hz - lower) / (center - lower);
+            let falling = (upper - hz) / (upper - center);
+            let weight = rising.min(falling).max(0.0) * enorm;
+            filters[m * n_bins + k] = weight as f32;
+        }
+    }
+    filters
+}
+
+impl LogMel {
+    pub fn new(n_mels: usize) -> Self {
+        let window = (0..N_FFT)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / N_FFT as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Self {
+            n_mels,
+            filters: mel_filters(n_mels),
+            window,
+            fft: RealFftPlanner::<f32>::new().plan_fft_forward(N_FFT),
+        }
+    }
+
+    pub fn n_mels(&self) -> usize {
+        self.n_mels
+    }
+
+    /// Features of the audio padded or cut to 30s, [n_mels, N_FRAMES] row-major
+    pub fn compute(&self, samples: &[f32]) -> Vec<f32> {
+        let samples = &samples[..samples.len().min(CHUNK_SAMPLES)];
+        // centered frames: reflect-pad half a window on each side of the
+        // zero-padded chunk
+        let pad = N_FFT / 2;
+        let mut padded = vec![0.0f32; CHUNK_SAMPLES + 2 * pad];
+        padded[pad..pad + samples.len()].copy_from_slice(samples);
+        for i in 0..pad {
+            padded[pad - 1 - i] = padded[pad + 1 + i];
+        }
+        // the right edge is always zero padding, already in place
+
+        let n_bins = N_FFT / 2 + 1;
+        let mut mel = vec![0.0f32; self.n_mels * N_FRAMES];
+        let mut input = self.fft.make_input_vec();
+        let mut output = self.fft.make_output_vec();
+        let mut power = vec![0.0f32; n_bins];
+        // frames past the audio are silent
+        let frames = (samples.len().div_ceil(HOP_LENGTH) + 2).min(N_FRAMES);
+        for t in 0..frames {
+            let frame = &padded[t * HOP_LENGTH..t * HOP_LENGTH + N_FFT];
+            for ((dst, src), w) in input.iter_mut().zip(frame).zip(&self.window) {
+                *dst = src * w;
+            }
+            if self.fft.process(&mut input, &mut output).is_err() {
+                continue;
+            }
+            for (p, c) in power.iter_mut().zip(&output) {
+                *p = c.norm_sqr();
+            }
+            for m in 0..self.n_mels {
+                let filter = &self.filters[m * n_bins..(m + 1) * n_bins];
+                mel[m * N_FRAMES + t] = filter.iter().zip(&power).map(|(f, p)| f * p).sum();
+            }
+        }
+
+        for v in mel.iter_mut() {
+            *v = v.max(1e-10).log10();
+        }
+        let max = mel.iter().cloned().fold(f32::MIN, f32::max);
+        for v in mel.iter_mut() {
+            *v = (v.max(max - 8.0) + 4.0) / 4.0;
+        }
+        mel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mel_scale_round_trip() {
+        for hz in [0.0, 440.0, 1000.0, 4321.0, 8000.0] {
+            assert!((mel_to_hz(hz_to_mel(hz)) - hz).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_log_mel_of_tone() {
+        let mel = LogMel::new(80);
+        let samples: Vec<f32> = (0..SAMPLE_RATE)
+            .map(|i| 0.5 * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 16000.0).sin())
+            .collect();
+        let features = mel.compute(&samples);
+        assert_eq!(features.len(), 80 * N_FRAMES);
+
+        // the loudest band of a middle frame covers 1kHz
+        let t = 50;
+        let band = (0..80)
+            .max_by(|a, b| features[a * N_FRAMES + t].total_cmp(&features[b * N_FRAMES + t]))
+            .unwrap();
+        let filters = mel_filters(80);
+        let bin_1khz = 1000 * N_FFT / SAMPLE_RATE;
+        assert!(filters[band * (N_FFT / 2 + 1) + bin_1khz] > 0.0);
+        // the padding is at the floor, 8 decades below the peak
+        let peak = features.iter().cloned().fold(f32::MIN, f32::max);
+        let floor = features[10 * N_FRAMES + 2000];
+        assert!((floor - (peak - 2.0)).abs() < 1e-6);
+    }
+}
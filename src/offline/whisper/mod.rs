@@ -0,0 +1,512 @@
+//! Offline Whisper recognition with the int8 ONNX exports of sherpa-onnx:
+//! an encoder producing the cross-attention keys and values, and a decoder
+//! run one token at a time with a self-attention cache.
+//!
+//! Decoding is greedy with Whisper's timestamp rules. Word times are
+//! interpolated within each timestamped segment by text length, so they
+//! are approximate; there is no cross-attention alignment.
+mod mel;
+mod tokenizer;
+
+pub use mel::{CHUNK_SAMPLES, SAMPLE_RATE};
+pub use tokenizer::WhisperTokenizer;
+
+use super::{Recognition, RecognizedWord, session::build_session_with_ort_cache};
+use anyhow::{Result, anyhow, ensure};
+use mel::LogMel;
+use ort::{
+    session::Session,
+    value::{DynValue, Tensor},
+};
+use std::path::Path;
+use tracing::debug;
+
+/// Each timestamp token is 20ms after the previous one
+const TIMESTAMP_MS: u64 = 20;
+/// The first timestamp is at most 1s into the audio
+const MAX_INITIAL_TIMESTAMP: i64 = 50;
+
+/// Special tokens of a model, read from the encoder metadata
+#[derive(Debug, Clone, Default)]
+struct Vocab {
+    sot: i64,
+    eot: i64,
+    transcribe: i64,
+    timestamp_begin: i64,
+    blank: Option<i64>,
+    non_speech: Vec<i64>,
+    sot_sequence: Vec<i64>,
+    language_tokens: Vec<i64>,
+    language_codes: Vec<String>,
+    multilingual: bool,
+}
+
+impl Vocab {
+    fn is_timestamp(&self, token: i64) -> bool {
+        token >= self.timestamp_begin
+    }
+
+    fn timestamp_ms(&self, token: i64) -> u64 {
+        (token - self.timestamp_begin) as u64 * TIMESTAMP_MS
+    }
+}
+
+pub struct WhisperModel {
+    encoder: Session,
+    decoder: Session,
+    tokenizer: WhisperTokenizer,
+    mel: LogMel,
+    vocab: Vocab,
+    n_text_layer: usize,
+    n_text_ctx: usize,
+    n_text_state: usize,
+}
+
+fn parse_list<T: std::str::FromStr>(value: &str) -> Result<Vec<T>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| anyhow!("invalid list item {}", s)))
+        .collect()
+}
+
+impl WhisperModel {
+    pub fn new<P: AsRef<Path>>(
+        encoder_path: P,
+        decoder_path: P,
+        tokens_path: P,
+        intra_threads: usize,
+    ) -> Result<Self> {
+        let encoder = build_session_with_ort_cache(encoder_path.as_ref(), intra_threads)?;
+        let decoder = build_session_with_ort_cache(decoder_path.as_ref(), intra_threads)?;
+        let tokenizer = WhisperTokenizer::new(tokens_path)?;
+
+        let metadata = encoder
+            .metadata()
+            .map_err(|e| anyhow!("ORT metadata error: {e}"))?;
+        let get = |key: &str| {
+            metadata
+                .custom(key)
+                .ok_or_else(|| anyhow!("Whisper encoder metadata is missing {}", key))
+        };
+        let number = |key: &str| -> Result<i64> {
+            get(key)?
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid Whisper metadata {}", key))
+        };
+        let vocab = Vocab {
+            sot: number("sot")?,
+            eot: number("eot")?,
+            transcribe: number("transcribe")?,
+            timestamp_begin: number("no_timestamps")? + 1,
+            blank: number("blank_id").ok(),
+            non_speech: get("non_speech_tokens")
+                .and_then(|v| parse_list(&v))
+                .unwrap_or_default(),
+            sot_sequence: parse_list(&get("sot_sequence")?)?,
+            language_tokens: get("all_language_tokens")
+                .and_then(|v| parse_list(&v))
+                .unwrap_or_default(),
+            language_codes: get("all_language_codes")
+                .and_then(|v| parse_list(&v))
+                .unwrap_or_default(),
+            multilingual: number("is_multilingual")? != 0,
+        };
+        let n_mels = number("n_mels")? as usize;
+        let n_text_layer = number("n_text_layer")? as usize;
+        let n_text_ctx = number("n_text_ctx")? as usize;
+        let n_text_state = number("n_text_state")? as usize;
+        drop(metadata);
+        debug!(?vocab, n_mels, n_text_layer, "Whisper model loaded");
+
+        Ok(Self {
+            encoder,
+            decoder,
+            tokenizer,
+            mel: LogMel::new(n_mels),
+            vocab,
+            n_text_layer,
+            n_text_ctx,
+            n_text_state,
+        })
+    }
+
+    pub fn is_multilingual(&self) -> bool {
+        self.vocab.multilingual
+    }
+
+    /// Transcribes up to 30s of 16kHz audio. `language` is a code such as
+    /// "en" or "zh"; multilingual models detect it when not set or "auto".
+    pub fn transcribe(&mut self, samples: &[f32], language: Option<&str>) -> Result<Recognition> {
+        ensure!(!samples.is_empty(), "empty audio");
+        let samples = &samples[..samples.len().min(CHUNK_SAMPLES)];
+        let duration_ms = (samples.len() * 1000 / SAMPLE_RATE) as u64;
+
+        let n_mels = self.mel.n_mels();
+        let mel = Tensor::from_array(([1, n_mels, mel::N_FRAMES], self.mel.compute(samples)))?;
+        let mut outputs = self.encoder.run(ort::inputs! { "mel" => mel })?;
+        let cross_k = outputs
+            .remove("n_layer_cross_k")
+            .ok_or_else(|| anyhow!("Whisper encoder output n_layer_cross_k missing"))?;
+        let cross_v = outputs
+            .remove("n_layer_cross_v")
+            .ok_or_else(|| anyhow!("Whisper encoder output n_layer_cross_v missing"))?;
+        drop(outputs);
+
+        let (prompt, language) = if self.vocab.multilingual {
+            let code = language
+                .and_then(|l| l.split(['-', '_']).next())
+                .map(str::to_lowercase)
+                .filter(|code| self.vocab.language_codes.contains(code));
+            let code = match code {
+                Some(code) => code,
+                None => self.detect_language(&cross_k, &cross_v)?,
+            };
+            let position = self
+                .vocab
+                .language_codes
+                .iter()
+                .position(|c| *c == code)
+                .ok_or_else(|| anyhow!("unknown Whisper language {}", code))?;
+            let language_token = self.vocab.language_tokens[position];
+            (
+                vec![self.vocab.sot, language_token, self.vocab.transcribe],
+                Some(code),
+            )
+        } else {
+            (self.vocab.sot_sequence.clone(), Some("en".to_string()))
+        };
+
+        let tokens = self.decode(&prompt, &cross_k, &cross_v)?;
+        let text_tokens: Vec<i64> = tokens
+            .iter()
+            .map(|(token, _)| *token)
+            .filter(|token| *token < self.vocab.eot)
+            .collect();
+        Ok(Recognition {
+            text: self.tokenizer.decode(&text_tokens),
+            language,
+            words: words_from_tokens(&tokens, &self.vocab, &self.tokenizer, duration_ms),
+            ..Default::default()
+        })
+    }
+
+    fn empty_cache(&self) -> Result<DynValue> {
+        let shape = [self.n_text_layer, 1, self.n_text_ctx, self.n_text_state];
+        let cache = vec![0.0f32; shape.iter().product()];
+        Ok(Tensor::from_array((shape, cache))?.into_dyn())
+    }
+
+    /// Runs the decoder on `tokens` at `offset`, returning the logits of the
+    /// last token and the updated caches
+    fn step(
+        &mut self,
+        tokens: &[i64],
+        offset: usize,
+        cache: (DynValue, DynValue),
+        cross_k: &DynValue,
+        cross_v: &DynValue,
+    ) -> Result<(Vec<f32>, (DynValue, DynValue))> {
+        let tokens = Tensor::from_array(([1, tokens.len()], tokens.to_vec()))?;
+        let offset = Tensor::from_array(([1], vec![offset as i64]))?;
+        let mut outputs = self.decoder.run(ort::inputs! {
+            "tokens" => tokens,
+            "in_n_layer_self_k_cache" => cache.0,
+            "in_n_layer_self_v_cache" => cache.1,
+            "n_layer_cross_k" => cross_k,
+            "n_layer_cross_v" => cross_v,
+            "offset" => offset,
+        })?;
+        let (shape, logits) = outputs["logits"].try_extract_tensor::<f32>()?;
+        let n_vocab = *shape
+            .last()
+            .ok_or_else(|| anyhow!("invalid Whisper logits shape"))? as usize;
+        let last = logits[logits.len() - n_vocab..].to_vec();
+        let k = outputs
+            .remove("out_n_layer_self_k_cache")
+            .ok_or_else(|| anyhow!("Whisper decoder output out_n_layer_self_k_cache missing"))?;
+        let v = outputs
+            .remove("out_n_layer_self_v_cache")
+            .ok_or_else(|| anyhow!("Whisper decoder output out_n_layer_self_v_cache missing"))?;
+        Ok((last, (k, v)))
+    }
+
+    fn detect_language(&mut self, cross_k: &DynValue, cross_v: &DynValue) -> Result<String> {
+        let cache = (self.empty_cache()?, self.empty_cache()?);
+        let (logits, _) = self.step(&[self.vocab.sot], 0, cache, cross_k, cross_v)?;
+        let best = self
+            .vocab
+            .language_tokens
+            .iter()
+            .zip(&self.vocab.language_codes)
+            .filter_map(|(token, code)| Some((*logits.get(*token as usize)?, code)))
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, code)| code.clone())
+            .ok_or_else(|| anyhow!("Whisper model has no language tokens"))?;
+        debug!(language = %best, "Whisper detected language");
+        Ok(best)
+    }
+
+    /// Greedy decoding after the prompt, returns the sampled tokens and
+    /// their probabilities
+    fn decode(
+        &mut self,
+        prompt: &[i64],
+        cross_k: &DynValue,
+        cross_v: &DynValue,
+    ) -> Result<Vec<(i64, f32)>> {
+        let max_tokens = self.n_text_ctx / 2;
+        let mut cache = (self.empty_cache()?, self.empty_cache()?);
+        let mut generated: Vec<i64> = Vec::new();
+        let mut sampled = Vec::new();
+        let mut input = prompt.to_vec();
+        let mut offset = 0;
+        while sampled.len() < max_tokens && offset + input.len() < self.n_text_ctx {
+            let (mut logits, next_cache) = self.step(&input, offset, cache, cross_k, cross_v)?;
+            cache = next_cache;
+            offset += input.len();
+
+            apply_rules(&mut logits, &generated, &self.vocab);
+            let Some((token, probability)) = argmax_probability(&logits) else {
+                break;
+            };
+            let token = token as i64;
+            if token == self.vocab.eot {
+                break;
+            }
+            generated.push(token);
+            sampled.push((token, probability));
+            input = vec![token];
+        }
+        Ok(sampled)
+    }
+}
+
+fn log_sum_exp(values: &[f32]) -> f32 {
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if max == f32::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f32>().ln()
+}
+
+fn argmax_probability(logits: &[f32]) -> Option<(usize, f32)> {
+    let (index, max) = logits
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if max == f32::NEG_INFINITY {
+        return None;
+    }
+    Some((index, (max - log_sum_exp(logits)).exp()))
+}
+
+/// Whisper's logit filters for timestamped transcription: no special
+/// tokens, a timestamp first, timestamps in pairs around text and never
+/// going back, and a timestamp whenever one is more likely than any text.
+fn apply_rules(logits: &mut [f32], generated: &[i64], vocab: &Vocab) {
+    let n_vocab = logits.len();
+    let eot = (vocab.eot as usize).min(n_vocab);
+    let timestamp_begin = (vocab.timestamp_begin as usize).min(n_vocab);
+    logits[(eot + 1).min(n_vocab)..timestamp_begin].fill(f32::NEG_INFINITY);
+    for token in &vocab.non_speech {
+        if let Some(logit) = logits.get_mut(*token as usize) {
+            *logit = f32::NEG_INFINITY;
+        }
+    }
+
+    let Some(&last) = generated.last() else {
+        // start with a timestamp, at most 1s in
+        logits[..timestamp_begin].fill(f32::NEG_INFINITY);
+        let max_initial = (timestamp_begin + MAX_INITIAL_TIMESTAMP as usize + 1).min(n_vocab);
+        logits[max_initial..].fill(f32::NEG_INFINITY);
+        return;
+    };
+    if let Some(blank) = vocab.blank.filter(|_| generated.len() == 1)
+        && let Some(logit) = logits.get_mut(blank as usize)
+    {
+        *logit = f32::NEG_INFINITY;
+    }
+
+    let last_was_timestamp = vocab.is_timestamp(last);
+    let penultimate_was_timestamp =
+        generated.len() < 2 || vocab.is_timestamp(generated[generated.len() - 2]);
+    if last_was_timestamp {
+        if penultimate_was_timestamp {
+            // a segment opened, text comes next
+            logits[timestamp_begin..].fill(f32::NEG_INFINITY);
+        } else {
+            // a segment closed, another opens or the text ends
+            logits[..eot].fill(f32::NEG_INFINITY);
+        }
+    }
+    if let Some(&previous) = generated.iter().rev().find(|t| vocab.is_timestamp(**t)) {
+        // the closing timestamp may open the next segment
+        let min = if last_was_timestamp && !penultimate_was_timestamp {
+            previous
+        } else {
+            previous + 1
+        };
+        logits[timestamp_begin..(min as usize).min(n_vocab)].fill(f32::NEG_INFINITY);
+    }
+
+    let total = log_sum_exp(logits);
+    let timestamp = log_sum_exp(&logits[timestamp_begin..]) - total;
+    let text = logits[..timestamp_begin]
+        .iter()
+        .copied()
+        .fold(f32::NEG_INFINITY, f32::max)
+        - total;
+    if timestamp > text {
+        logits[..timestamp_begin].fill(f32::NEG_INFINITY);
+    }
+}
+
+/// Splits timestamped segments into words at leading spaces, spreading
+/// each segment's time over its words by byte length
+fn words_from_tokens(
+    tokens: &[(i64, f32)],
+    vocab: &Vocab,
+    tokenizer: &WhisperTokenizer,
+    duration_ms: u64,
+) -> Vec<RecognizedWord> {
+    let mut words = Vec::new();
+    let mut segment: Vec<(Vec<u8>, Vec<f32>)> = Vec::new();
+    let mut segment_start = 0;
+    let mut flush = |segment: &mut Vec<(Vec<u8>, Vec<f32>)>, start: u64, end: u64| {
+        let total: usize = segment.iter().map(|(bytes, _)| bytes.len()).sum();
+        let mut position = 0;
+        for (bytes, probabilities) in segment.drain(..) {
+            let span = end.saturating_sub(start);
+            let word_start = start + span * position as u64 / total.max(1) as u64;
+            position += bytes.len();
+            let word_end = start + span * position as u64 / total.max(1) as u64;
+            let word = String::from_utf8_lossy(&bytes).trim().to_string();
+            if word.is_empty() {
+                continue;
+            }
+            words.push(RecognizedWord {
+                word,
+                start_ms: word_start,
+                end_ms: word_end,
+                confidence: Some(probabilities.iter().sum::<f32>() / probabilities.len() as f32),
+            });
+        }
+    };
+    for (token, probability) in tokens {
+        if vocab.is_timestamp(*token) {
+            let time = vocab.timestamp_ms(*token).min(duration_ms);
+            if !segment.is_empty() {
+                flush(&mut segment, segment_start, time);
+            }
+            segment_start = time;
+            continue;
+        }
+        if *token >= vocab.eot {
+            continue;
+        }
+        let bytes = tokenizer.bytes(*token);
+        match segment.last_mut() {
+            Some((word, probabilities)) if !bytes.starts_with(b" ") => {
+                word.extend_from_slice(bytes);
+                probabilities.push(*probability);
+            }
+            _ => segment.push((bytes.to_vec(), vec![*probability])),
+        }
+    }
+    if !segment.is_empty() {
+        flush(&mut segment, segment_start, duration_ms.max(segment_start));
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 text tokens, eot 10, special tokens 11..14, timestamps from 15
+    fn vocab() -> Vocab {
+        Vocab {
+            sot: 11,
+            eot: 10,
+            transcribe: 12,
+            timestamp_begin: 15,
+            non_speech: vec![9],
+            ..Default::default()
+        }
+    }
+
+    fn allowed(logits: &[f32]) -> Vec<usize> {
+        (0..logits.len())
+            .filter(|i| logits[*i] > f32::NEG_INFINITY)
+            .collect()
+    }
+
+    #[test]
+    fn test_rules_timestamp_pairs() {
+        let vocab = vocab();
+        // starts with a timestamp within the first second
+        let mut logits = vec![0.0; 80];
+        apply_rules(&mut logits, &[], &vocab);
+        assert_eq!(allowed(&logits), (15..=65).collect::<Vec<_>>());
+
+        // after the opening timestamp: text, not eot-only, no special tokens
+        let mut logits = vec![0.0; 80];
+        apply_rules(&mut logits, &[15], &vocab);
+        assert_eq!(
+            allowed(&logits),
+            (0..=10).filter(|t| *t != 9).collect::<Vec<_>>()
+        );
+
+        // after text, timestamps must not go back
+        let mut logits = vec![0.0; 80];
+        logits[20] = 10.0;
+        apply_rules(&mut logits, &[18, 3], &vocab);
+        assert!(logits[18] == f32::NEG_INFINITY && logits[19] > f32::NEG_INFINITY);
+        // and a likely timestamp wins over text
+        assert!(allowed(&logits).iter().all(|t| *t >= 15));
+
+        // after a closing timestamp: the same time may open the next segment, or eot
+        let mut logits = vec![0.0; 80];
+        logits[10] = 5.0;
+        apply_rules(&mut logits, &[15, 3, 20], &vocab);
+        let allowed = allowed(&logits);
+        assert_eq!(allowed[0], 10);
+        assert_eq!(allowed[1], 20);
+    }
+
+    #[test]
+    fn test_words_from_timestamped_segments() {
+        // " Hello", " wor", "ld", " again"
+        let content = "IEhlbGxv 0\nIHdvcg== 1\nbGQ= 2\nIGFnYWlu 3\n";
+        let tokenizer = WhisperTokenizer::parse(content).unwrap();
+        let vocab = vocab();
+        // <0.00> Hello world <1.00> <1.00> again
+        let tokens = [
+            (15, 1.0),
+            (0, 0.9),
+            (1, 0.8),
+            (2, 0.6),
+            (65, 1.0),
+            (65, 1.0),
+            (3, 0.5),
+        ];
+        let words = words_from_tokens(&tokens, &vocab, &tokenizer, 1600);
+        let summary: Vec<_> = words
+            .iter()
+            .map(|w| (w.word.as_str(), w.start_ms, w.end_ms))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Hello", 0, 500),
+                ("world", 500, 1000),
+                ("again", 1000, 1600)
+            ]
+        );
+        assert!((words[1].confidence.unwrap() - 0.7).abs() < 1e-6);
+    }
+}
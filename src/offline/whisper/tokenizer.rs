@@ -0,0 +1,68 @@
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::{fs, path::Path};
+
+/// Byte-level BPE vocabulary of sherpa-onnx Whisper exports: one
+/// `<base64 bytes> <id>` line per token.
+pub struct WhisperTokenizer {
+    tokens: Vec<Vec<u8>>,
+}
+
+impl WhisperTokenizer {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            fs::read_to_string(path).with_context(|| format!("read tokens {}", path.display()))?;
+        Self::parse(&content)
+    }
+
+    pub(super) fn parse(content: &str) -> Result<Self> {
+        let mut tokens = Vec::new();
+        for line in content.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(token), Some(id)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let id: usize = id
+                .parse()
+                .map_err(|_| anyhow!("invalid token line: {}", line))?;
+            let bytes = STANDARD
+                .decode(token)
+                .map_err(|e| anyhow!("invalid token {}: {}", token, e))?;
+            if tokens.len() <= id {
+                tokens.resize(id + 1, Vec::new());
+            }
+            tokens[id] = bytes;
+        }
+        Ok(Self { tokens })
+    }
+
+    /// Bytes of a text token, special tokens have none
+    pub fn bytes(&self, id: i64) -> &[u8] {
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| self.tokens.get(id))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn decode(&self, ids: &[i64]) -> String {
+        let bytes: Vec<u8> = ids.iter().flat_map(|id| self.bytes(*id)).copied().collect();
+        String::from_utf8_lossy(&bytes).trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_bytes() {
+        // "Hello", " wor", "ld", and a multi-byte character split over two tokens
+        let content = "SGVsbG8= 0\nIHdvcg== 1\nbGQ= 2\n4pY= 3\nqw== 4\n";
+        let tokenizer = WhisperTokenizer::parse(content).unwrap();
+        assert_eq!(tokenizer.decode(&[0, 1, 2]), "Hello world");
+        assert_eq!(tokenizer.decode(&[3, 4]), "\u{25AB}");
+        assert!(tokenizer.bytes(99).is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+use super::{Playbook, dialogue::DialogueHandler, handler::LlmHandler};
+use crate::call::Command;
+use crate::event::SessionEvent;
+use anyhow::{Result, anyhow};
+
+/// Text-only conversation over a playbook, used for SIP MESSAGE chats.
+///
+/// Runs the same `LlmHandler` and scenes as a voice call, but without media:
+/// each incoming text is handed over as a final transcript and the spoken
+/// replies are collected into a single text reply.
+pub struct ChatSession {
+    handler: Box<dyn DialogueHandler>,
+    ended: bool,
+}
+
+impl ChatSession {
+    pub fn new(playbook: Playbook) -> Result<Self> {
+        let llm_config = playbook
+            .config
+            .llm
+            .clone()
+            .ok_or_else(|| anyhow!("playbook has no 'llm' section"))?;
+        let handler = LlmHandler::new(
+            llm_config,
+            playbook.config.interruption.clone().unwrap_or_default(),
+            None,
+            playbook.scenes.clone(),
+            None,
+            None,
+            playbook.initial_scene_id.clone(),
+            None,
+        );
+        Ok(Self::with_handler(Box::new(handler)))
+    }
+
+    pub fn with_handler(handler: Box<dyn DialogueHandler>) -> Self {
+        Self {
+            handler,
+            ended: false,
+        }
+    }
+
+    /// True once the playbook hung up, the next message starts a new chat.
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Feed one incoming text and return the reply, empty if the playbook
+    /// produced no text.
+    pub async fn reply(&mut self, text: &str) -> Result<String> {
+        let event = SessionEvent::AsrFinal {
+            track_id: "chat".to_string(),
+            timestamp: crate::media::get_timestamp(),
+            index: 0,
+            start_time: None,
+            end_time: None,
+            text: text.to_string(),
+            is_filler: None,
+            confidence: None,
+            task_id: None,
+            refer: None,
+            words: None,
+        };
+        let commands = self.handler.on_event(&event).await?;
+        Ok(self.collect_text(commands))
+    }
+
+    fn collect_text(&mut self, commands: Vec<Command>) -> String {
+        let mut reply = String::new();
+        for command in commands {
+            match command {
+                Command::Tts {
+                    text, auto_hangup, ..
+                } => {
+                    reply.push_str(&text);
+                    if auto_hangup == Some(true) {
+                        self.ended = true;
+                    }
+                }
+                Command::Hangup { .. } => self.ended = true,
+                _ => {}
+            }
+        }
+        reply.trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playbook::ChatMessage;
+    use async_trait::async_trait;
+
+    struct ScriptedHandler {
+        replies: Vec<Vec<Command>>,
+    }
+
+    fn tts(text: &str, auto_hangup: Option<bool>) -> Command {
+        Command::Tts {
+            text: text.to_string(),
+            speaker: None,
+            play_id: None,
+            auto_hangup,
+            streaming: Some(true),
+            end_of_stream: None,
+            option: None,
+            wait_input_timeout: None,
+            base64: None,
+            cache_key: None,
+        }
+    }
+
+    #[async_trait]
+    impl DialogueHandler for ScriptedHandler {
+        async fn on_start(&mut self) -> Result<Vec<Command>> {
+            Ok(vec![])
+        }
+
+        async fn on_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>> {
+            assert!(matches!(event, SessionEvent::AsrFinal { .. }));
+            Ok(if self.replies.is_empty() {
+                vec![]
+            } else {
+                self.replies.remove(0)
+            })
+        }
+
+        async fn get_history(&self) -> Vec<ChatMessage> {
+            vec![]
+        }
+
+        async fn summarize(&mut self, _prompt: &str) -> Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_session_joins_sentences_and_ends() -> Result<()> {
+        let mut session = ChatSession::with_handler(Box::new(ScriptedHandler {
+            replies: vec![
+                vec![tts("Hello there. ", None), tts("How can I help?", None)],
+                vec![tts("Goodbye.", Some(true))],
+            ],
+        }));
+
+        assert_eq!(session.reply("hi").await?, "Hello there. How can I help?");
+        assert!(!session.is_ended());
+        assert_eq!(session.reply("bye").await?, "Goodbye.");
+        assert!(session.is_ended());
+        Ok(())
+    }
+
+    #[test]
+    fn test_chat_session_requires_llm() {
+        let playbook =
+            Playbook::parse("---\nasr:\n  provider: aliyun\n---\n# Scene: main\nHi").unwrap();
+        assert!(ChatSession::new(playbook).is_err());
+    }
+}
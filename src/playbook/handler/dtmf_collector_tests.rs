@@ -63,6 +63,7 @@ fn create_phone_collector() -> super::super::DtmfCollectorConfig {
         }),
         retry_times: Some(3),
         interruptible: Some(false),
+        sensitive: None,
     }
 }
 
@@ -78,6 +79,7 @@ fn create_code_collector() -> super::super::DtmfCollectorConfig {
         validation: None,
         retry_times: Some(2),
         interruptible: Some(false),
+        sensitive: None,
     }
 }
 
@@ -459,6 +461,7 @@ async fn test_collector_on_event_ignores_asr() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -641,3 +644,48 @@ dtmfCollectors:
     assert_eq!(code.digits, Some(6));
     assert_eq!(code.finish_key, None); // Not specified
 }
+
+#[tokio::test]
+async fn test_sensitive_collector_pauses_recording() -> Result<()> {
+    let mut pin = create_code_collector();
+    pin.sensitive = Some(true);
+    let mut collectors = HashMap::new();
+    collectors.insert("pin".to_string(), pin);
+    let mut handler = create_test_handler(Some(collectors));
+
+    let dtmf = |digit: &str| SessionEvent::Dtmf {
+        track_id: "caller".to_string(),
+        timestamp: 0,
+        digit: digit.to_string(),
+        refer: None,
+    };
+
+    // the collector starts while the LLM response is handled
+    let was_sensitive = handler.is_collecting_sensitive();
+    assert!(handler.start_collector("pin", "card_pin"));
+    let commands = handler.sync_recording(was_sensitive, vec![]);
+    assert!(matches!(
+        commands.as_slice(),
+        [Command::PauseRecording { reason: Some(reason) }] if reason == SENSITIVE_DTMF_REASON
+    ));
+
+    for digit in ["1", "2", "3", "4", "5"] {
+        let commands = handler.on_event(&dtmf(digit)).await?;
+        assert!(commands.is_empty());
+    }
+    let commands = handler.on_event(&dtmf("6")).await?;
+    assert!(!handler.is_collecting());
+    assert!(matches!(
+        commands.first(),
+        Some(Command::ResumeRecording {})
+    ));
+
+    let history = handler.get_history().await;
+    let result = history
+        .iter()
+        .find(|m| m.content.contains("card_pin"))
+        .expect("collection result in history");
+    assert!(!result.content.contains("123456"));
+    assert!(result.content.contains("******"));
+    Ok(())
+}
@@ -85,6 +85,8 @@ pub use rag::*;
 pub use types::*;
 
 const MAX_RAG_ATTEMPTS: usize = 3;
+/// Reason of the recording pause while a sensitive collector is active
+pub const SENSITIVE_DTMF_REASON: &str = "sensitive_dtmf";
 
 /// Runtime state for an active DTMF digit collection session
 #[derive(Debug, Clone)]
@@ -387,7 +389,9 @@ impl LlmHandler {
         {
             info!(
                 "DTMF collector inter-digit timeout ({}s) for var={}, buffer={}",
-                inter_digit_timeout_secs, state.var_name, state.buffer
+                inter_digit_timeout_secs,
+                state.var_name,
+                state.config.loggable(&state.buffer)
             );
             let buffer = state.buffer.clone();
             let var_name = state.var_name.clone();
@@ -429,7 +433,8 @@ impl LlmHandler {
 
         info!(
             "DTMF collector: digit '{}', buffer now '{}'",
-            digit, state.buffer
+            state.config.loggable(digit),
+            state.config.loggable(&state.buffer)
         );
 
         // Check if we've reached the required digit count
@@ -497,11 +502,20 @@ impl LlmHandler {
         // Validation passed - store the variable
         info!(
             "DTMF collector: successfully collected '{}' for var '{}'",
-            buffer, var_name
+            config.loggable(&buffer),
+            var_name
         );
 
         if let Some(call) = &self.call {
             let mut state = call.call_state.write().await;
+            let mask_dtmf = state
+                .option
+                .as_ref()
+                .and_then(|option| option.redaction.as_ref())
+                .is_some_and(|redaction| redaction.mask_dtmf());
+            if config.is_sensitive() || mask_dtmf {
+                state.sensitive_extras.insert(var_name.clone());
+            }
             let mut extras = state.extras.take().unwrap_or_default();
             extras.insert(var_name.clone(), serde_json::Value::String(buffer.clone()));
             state.extras = Some(extras);
@@ -510,7 +524,11 @@ impl LlmHandler {
         // Notify LLM of the result
         self.history.push(ChatMessage {
             role: "system".to_string(),
-            content: format!("[DTMF collection completed for '{}': {}]", var_name, buffer),
+            content: format!(
+                "[DTMF collection completed for '{}': {}]",
+                var_name,
+                config.loggable(&buffer)
+            ),
         });
 
         // Let LLM continue
@@ -1953,9 +1971,8 @@ fn extract_json_block(raw: &str) -> Option<&str> {
     None
 }
 
-#[async_trait]
-impl DialogueHandler for LlmHandler {
-    async fn on_start(&mut self) -> Result<Vec<Command>> {
+impl LlmHandler {
+    async fn start_dialogue(&mut self) -> Result<Vec<Command>> {
         self.last_tts_start_at = Some(std::time::Instant::now());
 
         let mut commands = Vec::new();
@@ -1986,12 +2003,17 @@ impl DialogueHandler for LlmHandler {
         Ok(commands)
     }
 
-    async fn on_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>> {
+    async fn dispatch_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>> {
         // When in DTMF collection mode, only handle DTMF events and track lifecycle
         if self.collector_state.is_some() {
             match event {
                 SessionEvent::Dtmf { digit, .. } => {
-                    info!("DTMF received (collecting): {}", digit);
+                    if let Some(state) = &self.collector_state {
+                        info!(
+                            "DTMF received (collecting): {}",
+                            state.config.loggable(digit)
+                        );
+                    }
                     return self.handle_collector_digit(digit).await;
                 }
                 SessionEvent::Silence { .. } => {
@@ -2067,10 +2089,57 @@ impl DialogueHandler for LlmHandler {
             SessionEvent::FunctionCall {
                 name, arguments, ..
             } => self.handle_function_call(name, arguments).await,
+            SessionEvent::AddHistory { speaker, text, .. }
+                if speaker == crate::call::active_call::SUPERVISOR_SPEAKER =>
+            {
+                self.history.push(ChatMessage {
+                    role: "system".to_string(),
+                    content: format!("Supervisor: {}", text),
+                });
+                Ok(vec![])
+            }
             _ => Ok(vec![]),
         }
     }
 
+    /// True while a collector marked `sensitive` is active
+    fn is_collecting_sensitive(&self) -> bool {
+        self.collector_state
+            .as_ref()
+            .is_some_and(|state| state.config.is_sensitive())
+    }
+
+    /// Pauses recording ahead of the handler's commands when a sensitive
+    /// collector starts, and resumes it once the collector is done
+    fn sync_recording(&self, was_sensitive: bool, mut commands: Vec<Command>) -> Vec<Command> {
+        match (was_sensitive, self.is_collecting_sensitive()) {
+            (false, true) => commands.insert(
+                0,
+                Command::PauseRecording {
+                    reason: Some(SENSITIVE_DTMF_REASON.to_string()),
+                },
+            ),
+            (true, false) => commands.insert(0, Command::ResumeRecording {}),
+            _ => {}
+        }
+        commands
+    }
+}
+
+#[async_trait]
+impl DialogueHandler for LlmHandler {
+    async fn on_start(&mut self) -> Result<Vec<Command>> {
+        let was_sensitive = self.is_collecting_sensitive();
+        let commands = self.start_dialogue().await?;
+        Ok(self.sync_recording(was_sensitive, commands))
+    }
+
+    async fn on_event(&mut self, event: &SessionEvent) -> Result<Vec<Command>> {
+        let was_sensitive = self.is_collecting_sensitive();
+        let commands = self.dispatch_event(event).await?;
+        Ok(self.sync_recording(was_sensitive, commands))
+    }
+
     async fn get_history(&self) -> Vec<ChatMessage> {
         self.history.clone()
     }
@@ -146,6 +146,7 @@ async fn handler_applies_tool_instructions() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -202,6 +203,7 @@ async fn handler_requeries_after_rag() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -266,6 +268,7 @@ async fn test_full_dialogue_flow() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
     let commands = handler.on_event(&event).await?;
     // "Hello! How can I help you today?" -> split into two + EOS
@@ -288,6 +291,7 @@ async fn test_full_dialogue_flow() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
     let commands = handler.on_event(&event).await?;
     assert_eq!(commands.len(), 1);
@@ -315,6 +319,7 @@ async fn test_full_dialogue_flow() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
     let commands = handler.on_event(&event).await?;
     // Should have Tts with auto_hangup
@@ -364,6 +369,7 @@ async fn test_xml_tools_and_sentence_splitting() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -447,6 +453,7 @@ async fn test_interruption_logic() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
     handler.on_event(&event).await?;
     assert!(handler.is_speaking);
@@ -511,6 +518,7 @@ async fn test_rag_iteration_limit() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -654,6 +662,7 @@ async fn test_follow_up_logic() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let _ = handler.on_event(&event).await?;
@@ -696,6 +705,7 @@ async fn test_interruption_protection_period() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
     handler.on_event(&event).await?;
     assert!(handler.is_speaking);
@@ -753,6 +763,7 @@ async fn test_interruption_filler_word() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
     handler.on_event(&event).await?;
     assert!(handler.is_speaking);
@@ -872,6 +883,50 @@ async fn test_summary_and_history() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_supervisor_history_is_added() -> Result<()> {
+    let provider = Arc::new(TestProvider::new(vec![]));
+    let mut handler = LlmHandler::with_provider(
+        LlmConfig::default(),
+        provider,
+        Arc::new(NoopRagRetriever),
+        crate::playbook::InterruptionConfig::default(),
+        None,
+        HashMap::new(),
+        None,
+        None,
+        None,
+        None,
+    );
+    let before = handler.history.len();
+
+    let commands = handler
+        .on_event(&SessionEvent::AddHistory {
+            sender: Some("s.1".to_string()),
+            timestamp: 0,
+            speaker: "supervisor".to_string(),
+            text: "Offer the customer a refund".to_string(),
+        })
+        .await?;
+    assert!(commands.is_empty());
+    assert_eq!(handler.history.len(), before + 1);
+    let last = handler.history.last().unwrap();
+    assert_eq!(last.role, "system");
+    assert_eq!(last.content, "Supervisor: Offer the customer a refund");
+
+    // Other speakers are already part of the dialogue and are not duplicated.
+    handler
+        .on_event(&SessionEvent::AddHistory {
+            sender: Some("llm".to_string()),
+            timestamp: 0,
+            speaker: "assistant".to_string(),
+            text: "Hello".to_string(),
+        })
+        .await?;
+    assert_eq!(handler.history.len(), before + 1);
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_rolling_summary() -> Result<()> {
     let responses = vec![
@@ -915,6 +970,7 @@ async fn test_rolling_summary() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -0,0 +1,136 @@
+use super::PlaybookConfig;
+use crate::{
+    synthesis::Pronunciation,
+    transcription::{Hotword, TranscriptionType},
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+/// Pronunciation lexicon, a YAML file referenced by `lexicon` in a playbook.
+///
+/// Pronunciations go to the TTS option, hotwords and replacements to the
+/// ASR option. Entries already set inline in the playbook take precedence.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Lexicon {
+    pub pronunciations: Vec<Pronunciation>,
+    pub hotwords: Vec<Hotword>,
+    /// Recognized phrase to its correction
+    pub replacements: HashMap<String, String>,
+    /// Aliyun custom vocabulary, created in the DashScope console
+    pub vocabulary_id: Option<String>,
+    /// Tencent hotword table, created in the ASR console
+    pub hotword_id: Option<String>,
+}
+
+impl Lexicon {
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read lexicon {}", path.display()))?;
+        serde_yaml::from_str(&content)
+            .with_context(|| format!("invalid lexicon {}", path.display()))
+    }
+
+    /// Merge into the `tts` and `asr` sections of `config`, when present.
+    pub fn apply(&self, config: &mut PlaybookConfig) {
+        if let Some(tts) = config.tts.as_mut()
+            && !self.pronunciations.is_empty()
+        {
+            tts.pronunciations
+                .get_or_insert_with(Vec::new)
+                .extend(self.pronunciations.iter().cloned());
+        }
+        let Some(asr) = config.asr.as_mut() else {
+            return;
+        };
+        if !self.hotwords.is_empty() {
+            asr.hotwords
+                .get_or_insert_with(Vec::new)
+                .extend(self.hotwords.iter().cloned());
+        }
+        if !self.replacements.is_empty() {
+            let replacements = asr.replacements.get_or_insert_with(HashMap::new);
+            for (phrase, replacement) in &self.replacements {
+                replacements
+                    .entry(phrase.clone())
+                    .or_insert_with(|| replacement.clone());
+            }
+        }
+        let provider_id = match asr.provider {
+            Some(TranscriptionType::Aliyun) => Some(("vocabulary_id", self.vocabulary_id.as_ref())),
+            Some(TranscriptionType::TencentCloud) => Some(("hotword_id", self.hotword_id.as_ref())),
+            _ => None,
+        };
+        if let Some((key, Some(id))) = provider_id {
+            asr.extra
+                .get_or_insert_with(HashMap::new)
+                .entry(key.to_string())
+                .or_insert_with(|| id.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playbook::{PLAYBOOK_DIR, Playbook};
+
+    #[tokio::test]
+    async fn test_lexicon_applies_to_playbook() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("lexicon"))?;
+        std::fs::write(
+            dir.path().join("lexicon").join("acme.yaml"),
+            r#"
+pronunciations:
+  - word: active-call
+    respelling: active call
+hotwords:
+  - active-call
+  - { word: Acme, boost: 8 }
+replacements:
+  active coal: active-call
+  acne: Acme
+hotwordId: hw-1
+vocabularyId: vocab-1
+"#,
+        )?;
+        // Looked up next to the playbook file
+        let path = dir.path().join("support.md");
+        std::fs::write(
+            &path,
+            "---\nlexicon: lexicon/acme.yaml\nasr:\n  provider: tencent\n  replacements:\n    acne: ACME\ntts:\n  provider: aliyun\n---\n# Scene: main\nHi",
+        )?;
+        let playbook = Playbook::load(&path).await?;
+        // Parsing alone never reads the lexicon
+        assert!(
+            Playbook::parse(&playbook.raw_content)?
+                .config
+                .asr
+                .unwrap()
+                .hotwords
+                .is_none()
+        );
+        // and rendering keeps it
+        let playbook = playbook.render(&HashMap::new())?;
+
+        let asr = playbook.config.asr.unwrap();
+        assert_eq!(asr.hotwords.unwrap().len(), 2);
+        let replacements = asr.replacements.unwrap();
+        assert_eq!(replacements["active coal"], "active-call");
+        assert_eq!(replacements["acne"], "ACME");
+        let extra = asr.extra.unwrap();
+        assert_eq!(extra["hotword_id"], "hw-1");
+        assert!(!extra.contains_key("vocabulary_id"));
+        let pronunciations = playbook.config.tts.unwrap().pronunciations.unwrap();
+        assert_eq!(pronunciations[0].respelling.as_deref(), Some("active call"));
+
+        let mut inline =
+            Playbook::parse("---\nlexicon: /nonexistent.yaml\n---\n# Scene: main\nHi")?;
+        assert!(inline.load_lexicon(Path::new(PLAYBOOK_DIR)).await.is_err());
+        Ok(())
+    }
+}
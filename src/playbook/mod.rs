@@ -65,6 +65,9 @@ pub struct PlaybookConfig {
     pub posthook: Option<PostHookConfig>,
     pub follow_up: Option<FollowUpConfig>,
     pub sip: Option<SipOption>,
+    /// Path of a pronunciation lexicon, see [`Lexicon`]. A relative path is
+    /// resolved against the directory of the playbook file.
+    pub lexicon: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
@@ -154,6 +157,25 @@ pub struct DtmfCollectorConfig {
     pub retry_times: Option<u32>,
     /// Whether voice input (ASR) can interrupt collection (default: false)
     pub interruptible: Option<bool>,
+    /// Card numbers, PINs and the like (default: false): recording pauses
+    /// while collecting, and the digits are masked in logs, the LLM history
+    /// and the call record
+    pub sensitive: Option<bool>,
+}
+
+impl DtmfCollectorConfig {
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive.unwrap_or(false)
+    }
+
+    /// `digits` as they may appear in logs and the LLM history
+    pub fn loggable(&self, digits: &str) -> String {
+        if self.is_sensitive() {
+            crate::media::redaction::mask(digits)
+        } else {
+            digits.to_string()
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -200,6 +222,8 @@ pub const BUILTIN_CALL_TYPE: &str = "call_type";
 pub const BUILTIN_CALLER: &str = "caller";
 pub const BUILTIN_CALLEE: &str = "callee";
 pub const BUILTIN_START_TIME: &str = "start_time";
+/// File name of the playbook, under `config/playbook`, when it was loaded from one
+pub const BUILTIN_PLAYBOOK: &str = "playbook";
 
 /// Render a scene prompt template dynamically using the current variables.
 /// This allows `set_var` values set during conversation to be used in scene prompts.
@@ -246,12 +270,36 @@ pub struct Playbook {
     pub config: PlaybookConfig,
     pub scenes: HashMap<String, Scene>,
     pub initial_scene_id: Option<String>,
+    /// The `lexicon` file, once loaded
+    pub lexicon: Option<Lexicon>,
 }
 
+/// Directory of the playbook files, where lexicons of inline playbooks are
+/// looked up.
+pub const PLAYBOOK_DIR: &str = "config/playbook";
+
 impl Playbook {
     pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
         let content = fs::read_to_string(path).await?;
-        Self::parse(&content)
+        let mut playbook = Self::parse(&content)?;
+        playbook
+            .load_lexicon(path.parent().unwrap_or(Path::new(".")))
+            .await?;
+        Ok(playbook)
+    }
+
+    /// Load the `lexicon` of the front matter and merge it into the config.
+    /// A relative path is looked up in `base_dir`: the directory of the
+    /// playbook file, or [`PLAYBOOK_DIR`] for playbooks passed inline.
+    pub async fn load_lexicon(&mut self, base_dir: &Path) -> Result<()> {
+        let Some(path) = self.config.lexicon.as_ref() else {
+            return Ok(());
+        };
+        let lexicon = Lexicon::load(base_dir.join(path)).await?;
+        lexicon.apply(&mut self.config);
+        self.lexicon = Some(lexicon);
+        Ok(())
     }
 
     pub fn render(&self, vars: &HashMap<String, serde_json::Value>) -> Result<Self> {
@@ -287,6 +335,10 @@ impl Playbook {
                 res_scene.raw_prompt = scene.raw_prompt.clone();
             }
         }
+        if let Some(lexicon) = &self.lexicon {
+            lexicon.apply(&mut res.config);
+            res.lexicon = Some(lexicon.clone());
+        }
         res.config.sip.as_mut().map(|sip| {
             sip.hangup_headers = self
                 .config
@@ -456,16 +508,20 @@ impl Playbook {
             config,
             scenes,
             initial_scene_id: first_scene_id,
+            lexicon: None,
         })
     }
 }
 
+pub mod chat;
 pub mod dialogue;
 pub mod handler;
+pub mod lexicon;
 pub mod runner;
 
 pub use dialogue::DialogueHandler;
 pub use handler::{LlmHandler, RagRetriever};
+pub use lexicon::Lexicon;
 pub use runner::PlaybookRunner;
 
 #[cfg(test)]
@@ -148,7 +148,20 @@ impl PlaybookRunner {
             }
         }
 
+        let llm_paused = self.call.call_state.read().await.llm_paused.clone();
         while let Ok(event) = self.event_receiver.recv().await {
+            // While paused (supervisor takeover) only history updates reach the
+            // handler, so it can pick up the conversation on hand back.
+            let paused = llm_paused.load(std::sync::atomic::Ordering::Relaxed);
+            let forward = !paused
+                || matches!(
+                    event,
+                    crate::event::SessionEvent::AddHistory { .. }
+                        | crate::event::SessionEvent::Hangup { .. }
+                );
+            if !forward {
+                continue;
+            }
             if let Ok(commands) = self.handler.on_event(&event).await {
                 for cmd in commands {
                     if let Err(e) = self.call.enqueue_command(cmd).await {
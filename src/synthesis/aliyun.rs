@@ -1,4 +1,4 @@
-use super::{SynthesisClient, SynthesisOption, SynthesisType};
+use super::{MarkupSupport, SynthesisClient, SynthesisOption, SynthesisType};
 use crate::synthesis::SynthesisEvent;
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
@@ -41,7 +41,7 @@ enum CommandPayload {
 }
 
 impl Command {
-    fn run_task(option: &SynthesisOption, task_id: &str) -> Self {
+    fn run_task(option: &SynthesisOption, task_id: &str, ssml: bool) -> Self {
         let voice = option
             .speaker
             .clone()
@@ -75,6 +75,7 @@ impl Command {
                     sample_rate: Some(sample_rate),
                     volume: Some(volume),
                     rate: Some(rate),
+                    enable_ssml: ssml.then_some(true),
                 },
                 input: EmptyInput {},
             }),
@@ -136,6 +137,7 @@ struct RunTaskParameters {
     sample_rate: Option<u32>,
     volume: Option<u32>,
     rate: Option<f32>,
+    enable_ssml: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -171,7 +173,8 @@ struct EventHeader {
     error_message: Option<String>,
 }
 
-async fn connect(task_id: String, option: SynthesisOption) -> Result<WsStream> {
+// `ssml` enables SSML input, which must then arrive in a single continue-task
+async fn connect(task_id: String, option: SynthesisOption, ssml: bool) -> Result<WsStream> {
     let api_key = option
         .secret_key
         .clone()
@@ -188,7 +191,7 @@ async fn connect(task_id: String, option: SynthesisOption) -> Result<WsStream> {
     headers.insert("X-DashScope-DataInspection", "enable".parse()?);
 
     let (mut ws_stream, _) = connect_async(request).await?;
-    let run_task_cmd = Command::run_task(&option, task_id.as_str());
+    let run_task_cmd = Command::run_task(&option, task_id.as_str(), ssml);
     let run_task_json = serde_json::to_string(&run_task_cmd)?;
     ws_stream.send(Message::text(run_task_json)).await?;
     while let Some(message) = ws_stream.next().await {
@@ -314,7 +317,7 @@ impl SynthesisClient for StreamingClient {
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
-        let ws_stream = connect(self.task_id.clone(), self.option.clone()).await?;
+        let ws_stream = connect(self.task_id.clone(), self.option.clone(), false).await?;
         let (ws_sink, ws_source) = ws_stream.split();
         self.ws_sink.replace(ws_sink);
         Ok(event_stream(ws_source).map(move |x| (None, x)).boxed())
@@ -367,6 +370,10 @@ impl SynthesisClient for NonStreamingClient {
         SynthesisType::Aliyun
     }
 
+    fn markup_support(&self) -> MarkupSupport {
+        MarkupSupport::Aliyun
+    }
+
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
@@ -381,7 +388,8 @@ impl SynthesisClient for NonStreamingClient {
                 let task_id = Uuid::new_v4().to_string();
                 let text_clone = text.clone();
                 let task_id_clone = task_id.clone();
-                connect(task_id, option)
+                let ssml = text.starts_with("<speak");
+                connect(task_id, option, ssml)
                     .then(async move |res| match res {
                         Ok(mut ws_stream) => {
                             let continue_task_cmd =
@@ -0,0 +1,184 @@
+use crate::synthesis::{
+    MarkupSupport, SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType,
+    openai::{PcmResampler, TextSender},
+    ssml,
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{
+    FutureExt, Stream, StreamExt, TryStreamExt, future,
+    stream::{self, BoxStream},
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+const AZURE_DEFAULT_REGION: &str = "eastus";
+const AZURE_DEFAULT_VOICE: &str = "en-US-AvaMultilingualNeural";
+
+// Raw PCM output formats, other rates are resampled from 16kHz
+// https://learn.microsoft.com/azure/ai-services/speech-service/rest-text-to-speech#audio-outputs
+fn output_format(samplerate: usize) -> (&'static str, usize) {
+    match samplerate {
+        8000 => ("raw-8khz-16bit-mono-pcm", 8000),
+        22050 => ("raw-22050hz-16bit-mono-pcm", 22050),
+        24000 => ("raw-24khz-16bit-mono-pcm", 24000),
+        44100 => ("raw-44100hz-16bit-mono-pcm", 44100),
+        48000 => ("raw-48khz-16bit-mono-pcm", 48000),
+        _ => ("raw-16khz-16bit-mono-pcm", 16000),
+    }
+}
+
+fn request_url(option: &SynthesisOption) -> String {
+    if let Some(endpoint) = option.endpoint.as_ref() {
+        return endpoint.clone();
+    }
+    let region = option
+        .extra
+        .as_ref()
+        .and_then(|e| e.get("region"))
+        .map(String::as_str)
+        .unwrap_or(AZURE_DEFAULT_REGION);
+    format!(
+        "https://{}.tts.speech.microsoft.com/cognitiveservices/v1",
+        region
+    )
+}
+
+/// Wrap `text`, plain or prepared `<speak>` markup, in the `<voice>` element
+/// Azure requires.
+pub(super) fn ssml_body(option: &SynthesisOption, text: &str) -> String {
+    let voice = option
+        .speaker
+        .as_deref()
+        .or(option.model.as_deref())
+        .unwrap_or(AZURE_DEFAULT_VOICE);
+    // voice names start with the locale, e.g. `zh-CN-XiaoxiaoNeural`
+    let language = option.language.clone().unwrap_or_else(|| {
+        let parts: Vec<&str> = voice.splitn(3, '-').collect();
+        match parts.as_slice() {
+            [lang, region, _] => format!("{}-{}", lang, region),
+            _ => "en-US".to_string(),
+        }
+    });
+    let mut inner = match text.strip_prefix("<speak") {
+        Some(rest) => {
+            let start = rest.find('>').map(|i| i + 1).unwrap_or(0);
+            rest[start..].trim_end_matches("</speak>").to_string()
+        }
+        None => ssml::escape(text),
+    };
+    if let Some(speed) = option.speed.filter(|speed| *speed > 0.0 && *speed != 1.0) {
+        inner = format!("<prosody rate=\"{}\">{}</prosody>", speed, inner);
+    }
+    format!(
+        "<speak version=\"1.0\" xmlns=\"http://www.w3.org/2001/10/synthesis\" xml:lang=\"{}\"><voice name=\"{}\">{}</voice></speak>",
+        ssml::escape(&language),
+        ssml::escape(voice),
+        inner
+    )
+}
+
+async fn chunked_stream(
+    option: SynthesisOption,
+    text: String,
+) -> Result<impl Stream<Item = Result<Bytes>>> {
+    let token = option
+        .secret_key
+        .as_ref()
+        .ok_or_else(|| anyhow!("Azure TTS: missing subscription key"))?;
+    let samplerate = option.samplerate.unwrap_or(16000) as usize;
+    let (format, format_rate) = output_format(samplerate);
+    let mut resampler = PcmResampler::new(format_rate, samplerate)?;
+    let resp = reqwest::Client::new()
+        .post(request_url(&option))
+        .header("Ocp-Apim-Subscription-Key", token)
+        .header("Content-Type", "application/ssml+xml")
+        .header("X-Microsoft-OutputFormat", format)
+        .header("User-Agent", "active-call")
+        .body(ssml_body(&option, &text))
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(anyhow!("Azure TTS request failed: {} {}", status, body));
+    }
+    Ok(resp
+        .bytes_stream()
+        .map_err(anyhow::Error::from)
+        .map_ok(move |bytes| resampler.process(bytes))
+        .try_filter(|bytes| future::ready(!bytes.is_empty())))
+}
+
+// https://learn.microsoft.com/azure/ai-services/speech-service/rest-text-to-speech
+pub struct AzureTtsClient {
+    option: SynthesisOption,
+    tx: Option<TextSender>,
+}
+
+impl AzureTtsClient {
+    pub fn create(_streaming: bool, option: &SynthesisOption) -> Result<Box<dyn SynthesisClient>> {
+        Ok(Box::new(Self {
+            option: option.clone(),
+            tx: None,
+        }))
+    }
+}
+
+#[async_trait]
+impl SynthesisClient for AzureTtsClient {
+    fn provider(&self) -> SynthesisType {
+        SynthesisType::Azure
+    }
+
+    fn markup_support(&self) -> MarkupSupport {
+        MarkupSupport::Azure
+    }
+
+    async fn start(
+        &mut self,
+    ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.tx = Some(tx);
+        let max_concurrent_tasks = self.option.max_concurrent_tasks.unwrap_or(1);
+        let client_option = self.option.clone();
+        let stream = UnboundedReceiverStream::new(rx).flat_map_unordered(
+            max_concurrent_tasks,
+            move |(text, cmd_seq, cmd_option)| {
+                let option = client_option.merge_with(cmd_option);
+                chunked_stream(option, text)
+                    .map(move |res| match res {
+                        Ok(stream) => stream
+                            .map_ok(SynthesisEvent::AudioChunk)
+                            .chain(stream::once(future::ready(Ok(SynthesisEvent::Finished))))
+                            .boxed(),
+                        Err(e) => stream::once(future::ready(Err(e))).boxed(),
+                    })
+                    .flatten_stream()
+                    .map(move |res| (cmd_seq, res))
+                    .boxed()
+            },
+        );
+        Ok(stream.boxed())
+    }
+
+    async fn synthesize(
+        &mut self,
+        text: &str,
+        cmd_seq: Option<usize>,
+        option: Option<SynthesisOption>,
+    ) -> Result<()> {
+        if let Some(tx) = &self.tx {
+            tx.send((text.to_string(), cmd_seq, option))?;
+        } else {
+            return Err(anyhow!("Azure TTS: missing client sender"));
+        };
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.tx.take();
+        Ok(())
+    }
+}
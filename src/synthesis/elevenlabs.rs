@@ -0,0 +1,408 @@
+use crate::synthesis::{
+    Subtitle, SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType,
+    bytes_size_to_duration,
+    openai::{PcmResampler, TextSender},
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use futures::{
+    FutureExt, SinkExt, StreamExt, future,
+    stream::{self, BoxStream, SplitSink},
+};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{self, Message, client::IntoClientRequest},
+};
+use tracing::warn;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type WsSink = SplitSink<WsStream, Message>;
+
+const ELEVENLABS_BASE_URL: &str = "wss://api.elevenlabs.io";
+const ELEVENLABS_DEFAULT_VOICE: &str = "21m00Tcm4TlvDq8ikWAM";
+const TERMINATORS: [char; 6] = ['.', '?', '!', '。', '？', '！'];
+
+// https://elevenlabs.io/docs/api-reference/text-to-speech/v-1-text-to-speech-voice-id-stream-input
+#[skip_serializing_none]
+#[derive(Serialize)]
+struct Command {
+    text: String,
+    voice_settings: Option<VoiceSettings>,
+    flush: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct VoiceSettings {
+    speed: f32,
+}
+
+impl Command {
+    // the first message of a connection must be a single space
+    fn init(option: &SynthesisOption) -> Self {
+        let speed = option.speed.filter(|speed| *speed > 0.0 && *speed != 1.0);
+        Self {
+            text: " ".to_string(),
+            voice_settings: speed.map(|speed| VoiceSettings {
+                speed: speed.clamp(0.7, 1.2),
+            }),
+            flush: None,
+        }
+    }
+
+    // text must end with a space, flush forces generation of buffered text
+    fn text(text: &str, flush: bool) -> Self {
+        let mut text = text.to_string();
+        if !text.ends_with(char::is_whitespace) {
+            text.push(' ');
+        }
+        Self {
+            text,
+            voice_settings: None,
+            flush: flush.then_some(true),
+        }
+    }
+
+    fn close() -> Self {
+        Self {
+            text: String::new(),
+            voice_settings: None,
+            flush: None,
+        }
+    }
+
+    fn message(&self) -> Result<Message> {
+        Ok(Message::text(serde_json::to_string(self)?))
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    audio: Option<String>,
+    is_final: Option<bool>,
+    alignment: Option<Alignment>,
+    message: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Alignment {
+    chars: Vec<String>,
+    char_start_times_ms: Vec<u32>,
+    chars_durations_ms: Vec<u32>,
+}
+
+// Supported raw PCM rates, others are resampled from 16kHz
+fn output_format(samplerate: usize) -> (String, usize) {
+    let rate = match samplerate {
+        8000 | 16000 | 22050 | 24000 | 44100 | 48000 => samplerate,
+        _ => 16000,
+    };
+    (format!("pcm_{}", rate), rate)
+}
+
+async fn connect(option: &SynthesisOption) -> Result<WsStream> {
+    let base_url = option.endpoint.as_deref().unwrap_or(ELEVENLABS_BASE_URL);
+    let voice = option
+        .speaker
+        .as_deref()
+        .unwrap_or(ELEVENLABS_DEFAULT_VOICE);
+    let model = option.model.as_deref().unwrap_or("eleven_flash_v2_5");
+    let samplerate = option.samplerate.unwrap_or(16000) as usize;
+    let mut url = url::Url::parse(&format!(
+        "{}/v1/text-to-speech/{}/stream-input",
+        base_url.trim_end_matches('/'),
+        voice
+    ))?;
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("model_id", model);
+        query.append_pair("output_format", &output_format(samplerate).0);
+        query.append_pair("sync_alignment", "true");
+        if let Some(language) = option.language.as_ref() {
+            query.append_pair("language_code", language);
+        }
+        for (key, value) in option.extra.iter().flatten() {
+            query.append_pair(key, value);
+        }
+    }
+    let token = option
+        .secret_key
+        .as_ref()
+        .ok_or_else(|| anyhow!("ElevenLabs TTS: missing api key"))?;
+    let mut request = url.as_str().into_client_request()?;
+    request.headers_mut().insert("xi-api-key", token.parse()?);
+    let (ws_stream, _) = connect_async(request).await?;
+    Ok(ws_stream)
+}
+
+/// Turns server messages into audio chunks and word subtitles.
+struct Decoder {
+    samplerate: u32,
+    resampler: PcmResampler,
+    // audio emitted so far, alignment times are relative to their own chunk
+    audio_bytes: usize,
+    // characters aligned so far, for subtitle indexes
+    aligned_chars: u32,
+    finished: bool,
+}
+
+impl Decoder {
+    fn new(option: &SynthesisOption) -> Result<Self> {
+        let samplerate = option.samplerate.unwrap_or(16000) as usize;
+        Ok(Self {
+            samplerate: samplerate as u32,
+            resampler: PcmResampler::new(output_format(samplerate).1, samplerate)?,
+            audio_bytes: 0,
+            aligned_chars: 0,
+            finished: false,
+        })
+    }
+
+    fn decode(
+        &mut self,
+        message: Result<Message, tungstenite::Error>,
+    ) -> Vec<Result<SynthesisEvent>> {
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) if !self.finished => {
+                self.finished = true;
+                return vec![Ok(SynthesisEvent::Finished)];
+            }
+            Err(e) => {
+                return vec![Err(anyhow!("ElevenLabs TTS: websocket error: {:?}", e))];
+            }
+            _ => return vec![],
+        };
+        let response: Response = match serde_json::from_str(&text) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("ElevenLabs TTS: invalid message: {}, {}", e, text);
+                return vec![];
+            }
+        };
+        if let Some(error) = response.error {
+            let message = response.message.unwrap_or_default();
+            return vec![Err(anyhow!("ElevenLabs TTS: {}: {}", error, message))];
+        }
+
+        let mut events = Vec::new();
+        let offset = bytes_size_to_duration(self.audio_bytes, self.samplerate);
+        if let Some(audio) = response.audio.filter(|audio| !audio.is_empty()) {
+            match STANDARD.decode(audio) {
+                Ok(audio) => {
+                    let audio = self.resampler.process(Bytes::from(audio));
+                    self.audio_bytes += audio.len();
+                    events.push(Ok(SynthesisEvent::AudioChunk(audio)));
+                }
+                Err(e) => events.push(Err(anyhow!("ElevenLabs TTS: invalid audio: {}", e))),
+            }
+        }
+        if let Some(alignment) = response.alignment {
+            let subtitles = self.subtitles(&alignment, offset);
+            if !subtitles.is_empty() {
+                events.push(Ok(SynthesisEvent::Subtitles(subtitles)));
+            }
+        }
+        if response.is_final == Some(true) && !self.finished {
+            self.finished = true;
+            events.push(Ok(SynthesisEvent::Finished));
+        }
+        events
+    }
+
+    // group aligned characters into words
+    fn subtitles(&mut self, alignment: &Alignment, offset: u32) -> Vec<Subtitle> {
+        let mut subtitles = Vec::new();
+        let mut word: Option<Subtitle> = None;
+        let times = alignment
+            .char_start_times_ms
+            .iter()
+            .zip(alignment.chars_durations_ms.iter());
+        for (i, (ch, (start, duration))) in alignment.chars.iter().zip(times).enumerate() {
+            let index = self.aligned_chars + i as u32;
+            if ch.trim().is_empty() {
+                subtitles.extend(word.take());
+                continue;
+            }
+            let end_time = offset + start + duration;
+            match word.as_mut() {
+                Some(word) => {
+                    word.text.push_str(ch);
+                    word.end_time = end_time;
+                    word.end_index = index + 1;
+                }
+                None => {
+                    word = Some(Subtitle::new(
+                        ch.clone(),
+                        offset + start,
+                        end_time,
+                        index,
+                        index + 1,
+                    ))
+                }
+            }
+        }
+        subtitles.extend(word);
+        self.aligned_chars += alignment.chars.len() as u32;
+        subtitles
+    }
+}
+
+// Non streaming: one connection per text
+async fn session(
+    option: SynthesisOption,
+    text: String,
+) -> Result<BoxStream<'static, Result<SynthesisEvent>>> {
+    let mut decoder = Decoder::new(&option)?;
+    let (mut sink, source) = connect(&option).await?.split();
+    sink.send(Command::init(&option).message()?).await?;
+    sink.send(Command::text(&text, true).message()?).await?;
+    sink.send(Command::close().message()?).await?;
+    let stream = source
+        .map(move |message| stream::iter(decoder.decode(message)))
+        .flatten()
+        // the sink lives in the state so the connection stays open until
+        // the utterance is finished
+        .scan((false, sink), |(finished, _), event| {
+            if *finished {
+                return future::ready(None);
+            }
+            *finished = matches!(event, Ok(SynthesisEvent::Finished));
+            future::ready(Some(event))
+        })
+        .boxed();
+    Ok(stream)
+}
+
+struct NonStreamingClient {
+    option: SynthesisOption,
+    tx: Option<TextSender>,
+}
+
+#[async_trait]
+impl SynthesisClient for NonStreamingClient {
+    fn provider(&self) -> SynthesisType {
+        SynthesisType::ElevenLabs
+    }
+
+    async fn start(
+        &mut self,
+    ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.tx = Some(tx);
+        let max_concurrent_tasks = self.option.max_concurrent_tasks.unwrap_or(1);
+        let client_option = self.option.clone();
+        let stream = UnboundedReceiverStream::new(rx).flat_map_unordered(
+            max_concurrent_tasks,
+            move |(text, cmd_seq, cmd_option)| {
+                let option = client_option.merge_with(cmd_option);
+                session(option, text)
+                    .map(move |res| match res {
+                        Ok(stream) => stream,
+                        Err(e) => stream::once(future::ready(Err(e))).boxed(),
+                    })
+                    .flatten_stream()
+                    .map(move |res| (cmd_seq, res))
+                    .boxed()
+            },
+        );
+        Ok(stream.boxed())
+    }
+
+    async fn synthesize(
+        &mut self,
+        text: &str,
+        cmd_seq: Option<usize>,
+        option: Option<SynthesisOption>,
+    ) -> Result<()> {
+        if let Some(tx) = &self.tx {
+            tx.send((text.to_string(), cmd_seq, option))?;
+        } else {
+            return Err(anyhow!("ElevenLabs TTS: missing client sender"));
+        };
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.tx.take();
+        Ok(())
+    }
+}
+
+struct StreamingClient {
+    option: SynthesisOption,
+    sink: Option<WsSink>,
+}
+
+#[async_trait]
+impl SynthesisClient for StreamingClient {
+    fn provider(&self) -> SynthesisType {
+        SynthesisType::ElevenLabs
+    }
+
+    async fn start(
+        &mut self,
+    ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        let mut decoder = Decoder::new(&self.option)?;
+        let (mut sink, source) = connect(&self.option).await?.split();
+        sink.send(Command::init(&self.option).message()?).await?;
+        self.sink = Some(sink);
+        let stream = source
+            .map(move |message| stream::iter(decoder.decode(message)))
+            .flatten()
+            .map(|res| (None, res))
+            .boxed();
+        Ok(stream)
+    }
+
+    async fn synthesize(
+        &mut self,
+        text: &str,
+        _cmd_seq: Option<usize>,
+        _option: Option<SynthesisOption>,
+    ) -> Result<()> {
+        if let Some(sink) = &mut self.sink {
+            let flush = text.trim_end().ends_with(&TERMINATORS[..]);
+            sink.send(Command::text(text, flush).message()?).await?;
+        } else {
+            return Err(anyhow!("ElevenLabs TTS: missing sink"));
+        };
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(mut sink) = self.sink.take() {
+            sink.send(Command::close().message()?).await?;
+        } else {
+            warn!("ElevenLabs TTS: missing sink");
+        }
+        Ok(())
+    }
+}
+
+pub struct ElevenLabsTtsClient;
+
+impl ElevenLabsTtsClient {
+    pub fn create(streaming: bool, option: &SynthesisOption) -> Result<Box<dyn SynthesisClient>> {
+        if streaming {
+            Ok(Box::new(StreamingClient {
+                option: option.clone(),
+                sink: None,
+            }))
+        } else {
+            Ok(Box::new(NonStreamingClient {
+                option: option.clone(),
+                tx: None,
+            }))
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Adapter for TTS servers without a dedicated client, driven by a
+//! declarative [`TtsTemplate`] in the synthesis option.
+//!
+//! The URL, headers, body and WebSocket messages are minijinja templates
+//! rendered with the text and option, e.g. `{"input": {{ text | tojson }}}`.
+//! Audio must be 16-bit mono PCM, either raw (a chunked HTTP body or binary
+//! WebSocket frames) or base64 inside JSON (HTTP lines, optionally as SSE
+//! `data:` events, or WebSocket text frames).
+use crate::synthesis::{
+    SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType,
+    openai::{PcmResampler, TextSender},
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use futures::{
+    FutureExt, SinkExt, StreamExt, future,
+    stream::{self, BoxStream},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, Message, client::IntoClientRequest},
+};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateTransport {
+    /// One request per text, audio in the (chunked) response body
+    #[default]
+    Http,
+    /// One connection per text, audio in the frames
+    #[serde(alias = "ws")]
+    Websocket,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct TtsTemplate {
+    pub transport: TemplateTransport,
+    /// Server URL, `endpoint` when not set
+    pub url: Option<String>,
+    /// HTTP method, `POST` when not set
+    pub method: Option<String>,
+    pub headers: HashMap<String, String>,
+    /// HTTP request body or first WebSocket message, the bare text when not set
+    pub body: Option<String>,
+    /// WebSocket message sent after the body, e.g. an end of input marker
+    pub end_message: Option<String>,
+    /// JSON pointer to base64 audio, raw audio is expected when not set
+    pub audio_pointer: Option<String>,
+    /// JSON pointer to the field marking the end of the audio
+    pub done_pointer: Option<String>,
+    /// Value of `done_pointer` that ends the audio, any truthy value when not set
+    pub done_value: Option<Value>,
+    /// JSON pointer to an error message
+    pub error_pointer: Option<String>,
+    /// Sample rate of the returned audio, `samplerate` when not set
+    pub samplerate: Option<u32>,
+}
+
+fn render(template: &str, option: &SynthesisOption, text: &str) -> Result<String> {
+    let context = json!({
+        "text": text,
+        "speaker": option.speaker,
+        "model": option.model,
+        "language": option.language,
+        "speed": option.speed,
+        "volume": option.volume,
+        "samplerate": option.samplerate.unwrap_or(16000),
+        "codec": option.codec,
+        "emotion": option.emotion,
+        "app_id": option.app_id,
+        "secret_id": option.secret_id,
+        "secret_key": option.secret_key,
+        "endpoint": option.endpoint,
+        "extra": option.extra,
+    });
+    let env = minijinja::Environment::new();
+    env.render_str(template, &context)
+        .map_err(|e| anyhow!("generic TTS: invalid template: {}", e))
+}
+
+pub(crate) fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::String(s) => !s.is_empty(),
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        _ => true,
+    }
+}
+
+/// Turns response bodies or frames into audio chunks.
+struct Decoder {
+    template: TtsTemplate,
+    resampler: PcmResampler,
+    // partial line of a JSON lines body
+    buffer: Vec<u8>,
+    finished: bool,
+}
+
+impl Decoder {
+    fn new(template: &TtsTemplate, option: &SynthesisOption) -> Result<Self> {
+        let samplerate = option.samplerate.unwrap_or(16000) as usize;
+        let source_rate = template
+            .samplerate
+            .map(|rate| rate as usize)
+            .unwrap_or(samplerate);
+        Ok(Self {
+            template: template.clone(),
+            resampler: PcmResampler::new(source_rate, samplerate)?,
+            buffer: Vec::new(),
+            finished: false,
+        })
+    }
+
+    fn audio(&mut self, audio: Bytes) -> Option<Result<SynthesisEvent>> {
+        let audio = self.resampler.process(audio);
+        (!audio.is_empty()).then_some(Ok(SynthesisEvent::AudioChunk(audio)))
+    }
+
+    fn finish(&mut self) -> Option<Result<SynthesisEvent>> {
+        (!std::mem::replace(&mut self.finished, true)).then_some(Ok(SynthesisEvent::Finished))
+    }
+
+    fn json(&mut self, text: &str) -> Vec<Result<SynthesisEvent>> {
+        let value: Value = match serde_json::from_str(text) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("generic TTS: invalid message: {}, {}", e, text);
+                return vec![];
+            }
+        };
+        let pointer = |pointer: &Option<String>| {
+            pointer
+                .as_deref()
+                .and_then(|pointer| value.pointer(pointer))
+        };
+        if let Some(error) = pointer(&self.template.error_pointer).filter(|e| truthy(e)) {
+            return vec![Err(anyhow!("generic TTS: {}", error))];
+        }
+        let mut events = Vec::new();
+        if let Some(Value::String(audio)) = pointer(&self.template.audio_pointer) {
+            match STANDARD.decode(audio) {
+                Ok(audio) => events.extend(self.audio(Bytes::from(audio))),
+                Err(e) => events.push(Err(anyhow!("generic TTS: invalid audio: {}", e))),
+            }
+        }
+        let done = match (
+            pointer(&self.template.done_pointer),
+            &self.template.done_value,
+        ) {
+            (Some(value), Some(expected)) => value == expected,
+            (Some(value), None) => truthy(value),
+            (None, _) => false,
+        };
+        if done {
+            events.extend(self.finish());
+        }
+        events
+    }
+
+    // a chunk of the HTTP response body
+    fn body(&mut self, chunk: Bytes) -> Vec<Result<SynthesisEvent>> {
+        if self.template.audio_pointer.is_none() {
+            return self.audio(chunk).into_iter().collect();
+        }
+        self.buffer.extend_from_slice(&chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            events.extend(self.line(&line));
+        }
+        events
+    }
+
+    fn line(&mut self, line: &[u8]) -> Vec<Result<SynthesisEvent>> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        let line = line.strip_prefix("data:").map(str::trim).unwrap_or(line);
+        if line.is_empty() || line == "[DONE]" {
+            return vec![];
+        }
+        self.json(line)
+    }
+
+    fn end_of_body(&mut self) -> Vec<Result<SynthesisEvent>> {
+        let rest = std::mem::take(&mut self.buffer);
+        let mut events = self.line(&rest);
+        events.extend(self.finish());
+        events
+    }
+
+    fn frame(
+        &mut self,
+        message: Result<Message, tungstenite::Error>,
+    ) -> Vec<Result<SynthesisEvent>> {
+        match message {
+            Ok(Message::Binary(audio)) => self.audio(audio).into_iter().collect(),
+            Ok(Message::Text(text)) => self.json(&text),
+            Ok(Message::Close(_)) => self.finish().into_iter().collect(),
+            Err(e) => vec![Err(anyhow!("generic TTS: websocket error: {:?}", e))],
+            _ => vec![],
+        }
+    }
+}
+
+// stop after the first finished event
+fn until_finished(
+    stream: impl futures::Stream<Item = Result<SynthesisEvent>> + Send + 'static,
+    keep_alive: impl Send + 'static,
+) -> BoxStream<'static, Result<SynthesisEvent>> {
+    stream
+        .scan((false, keep_alive), |(finished, _), event| {
+            if *finished {
+                return future::ready(None);
+            }
+            *finished = matches!(event, Ok(SynthesisEvent::Finished));
+            future::ready(Some(event))
+        })
+        .boxed()
+}
+
+async fn http_stream(
+    template: TtsTemplate,
+    option: SynthesisOption,
+    text: String,
+) -> Result<BoxStream<'static, Result<SynthesisEvent>>> {
+    let mut decoder = Decoder::new(&template, &option)?;
+    let url = template
+        .url
+        .as_ref()
+        .or(option.endpoint.as_ref())
+        .ok_or_else(|| anyhow!("generic TTS: missing url"))?;
+    let method = template.method.as_deref().unwrap_or("POST");
+    let mut request = reqwest::Client::new().request(
+        reqwest::Method::from_bytes(method.to_uppercase().as_bytes())?,
+        render(url, &option, &text)?,
+    );
+    for (key, value) in &template.headers {
+        request = request.header(key, render(value, &option, &text)?);
+    }
+    if let Some(body) = template.body.as_ref() {
+        request = request.body(render(body, &option, &text)?);
+    } else if method.eq_ignore_ascii_case("POST") {
+        request = request.body(text.clone());
+    }
+    let resp = request.send().await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(anyhow!("generic TTS request failed: {} {}", status, body));
+    }
+    let mut body = resp.bytes_stream();
+    let stream = async_stream::stream! {
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    for event in decoder.body(chunk) {
+                        yield event;
+                    }
+                }
+                Err(e) => {
+                    yield Err(anyhow::Error::from(e));
+                    return;
+                }
+            }
+        }
+        for event in decoder.end_of_body() {
+            yield event;
+        }
+    };
+    Ok(until_finished(stream, ()))
+}
+
+async fn websocket_stream(
+    template: TtsTemplate,
+    option: SynthesisOption,
+    text: String,
+) -> Result<BoxStream<'static, Result<SynthesisEvent>>> {
+    let mut decoder = Decoder::new(&template, &option)?;
+    let url = template
+        .url
+        .as_ref()
+        .or(option.endpoint.as_ref())
+        .ok_or_else(|| anyhow!("generic TTS: missing url"))?;
+    let mut request = render(url, &option, &text)?.into_client_request()?;
+    for (key, value) in &template.headers {
+        request.headers_mut().insert(
+            http::HeaderName::from_bytes(key.as_bytes())?,
+            render(value, &option, &text)?.parse()?,
+        );
+    }
+    let (ws_stream, _) = connect_async(request).await?;
+    let (mut sink, source) = ws_stream.split();
+    let body = match template.body.as_ref() {
+        Some(body) => render(body, &option, &text)?,
+        None => text.clone(),
+    };
+    sink.send(Message::text(body)).await?;
+    if let Some(end_message) = template.end_message.as_ref() {
+        sink.send(Message::text(render(end_message, &option, &text)?))
+            .await?;
+    }
+    let stream = source
+        .map(move |message| stream::iter(decoder.frame(message)))
+        .flatten();
+    Ok(until_finished(stream, sink))
+}
+
+pub struct GenericTtsClient {
+    template: TtsTemplate,
+    option: SynthesisOption,
+    tx: Option<TextSender>,
+}
+
+impl GenericTtsClient {
+    pub fn create(_streaming: bool, option: &SynthesisOption) -> Result<Box<dyn SynthesisClient>> {
+        let template = option
+            .template
+            .clone()
+            .ok_or_else(|| anyhow!("generic TTS: missing template"))?;
+        Ok(Box::new(Self {
+            template,
+            option: option.clone(),
+            tx: None,
+        }))
+    }
+}
+
+#[async_trait]
+impl SynthesisClient for GenericTtsClient {
+    fn provider(&self) -> SynthesisType {
+        self.option
+            .provider
+            .clone()
+            .unwrap_or_else(|| SynthesisType::Other("generic".to_string()))
+    }
+
+    async fn start(
+        &mut self,
+    ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.tx = Some(tx);
+        let max_concurrent_tasks = self.option.max_concurrent_tasks.unwrap_or(1);
+        let client_option = self.option.clone();
+        let template = self.template.clone();
+        let stream = UnboundedReceiverStream::new(rx).flat_map_unordered(
+            max_concurrent_tasks,
+            move |(text, cmd_seq, cmd_option)| {
+                let option = client_option.merge_with(cmd_option);
+                let template = template.clone();
+                let stream = match template.transport {
+                    TemplateTransport::Http => http_stream(template, option, text).boxed(),
+                    TemplateTransport::Websocket => {
+                        websocket_stream(template, option, text).boxed()
+                    }
+                };
+                stream
+                    .map(move |res| match res {
+                        Ok(stream) => stream,
+                        Err(e) => stream::once(future::ready(Err(e))).boxed(),
+                    })
+                    .flatten_stream()
+                    .map(move |res| (cmd_seq, res))
+                    .boxed()
+            },
+        );
+        Ok(stream.boxed())
+    }
+
+    async fn synthesize(
+        &mut self,
+        text: &str,
+        cmd_seq: Option<usize>,
+        option: Option<SynthesisOption>,
+    ) -> Result<()> {
+        if let Some(tx) = &self.tx {
+            tx.send((text.to_string(), cmd_seq, option))?;
+        } else {
+            return Err(anyhow!("generic TTS: missing client sender"));
+        };
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.tx.take();
+        Ok(())
+    }
+}
@@ -7,7 +7,13 @@ use std::collections::HashMap;
 use tokio::sync::mpsc;
 
 mod aliyun;
+mod azure;
 mod deepgram;
+mod elevenlabs;
+pub mod generic;
+pub mod normalize;
+mod openai;
+pub mod ssml;
 mod tencent_cloud;
 mod tencent_cloud_basic;
 
@@ -15,7 +21,13 @@ mod tencent_cloud_basic;
 mod supertonic;
 
 pub use aliyun::AliyunTtsClient;
+pub use azure::AzureTtsClient;
 pub use deepgram::DeepegramTtsClient;
+pub use elevenlabs::ElevenLabsTtsClient;
+pub use generic::{GenericTtsClient, TtsTemplate};
+pub use normalize::Pronunciation;
+pub use openai::OpenAiTtsClient;
+pub use ssml::MarkupSupport;
 pub use tencent_cloud::TencentCloudTtsClient;
 pub use tencent_cloud_basic::TencentCloudTtsBasicClient;
 
@@ -44,6 +56,12 @@ pub enum SynthesisType {
     Aliyun,
     #[serde(rename = "deepgram")]
     Deepgram,
+    #[serde(rename = "openai")]
+    OpenAi,
+    #[serde(rename = "elevenlabs")]
+    ElevenLabs,
+    #[serde(rename = "azure")]
+    Azure,
     #[cfg(feature = "offline")]
     #[serde(rename = "supertonic")]
     Supertonic,
@@ -57,6 +75,9 @@ impl std::fmt::Display for SynthesisType {
             SynthesisType::TencentCloud => write!(f, "tencent"),
             SynthesisType::Aliyun => write!(f, "aliyun"),
             SynthesisType::Deepgram => write!(f, "deepgram"),
+            SynthesisType::OpenAi => write!(f, "openai"),
+            SynthesisType::ElevenLabs => write!(f, "elevenlabs"),
+            SynthesisType::Azure => write!(f, "azure"),
             #[cfg(feature = "offline")]
             SynthesisType::Supertonic => write!(f, "supertonic"),
             SynthesisType::Other(provider) => write!(f, "{}", provider),
@@ -74,6 +95,9 @@ impl<'de> Deserialize<'de> for SynthesisType {
             "tencent" => Ok(SynthesisType::TencentCloud),
             "aliyun" => Ok(SynthesisType::Aliyun),
             "deepgram" => Ok(SynthesisType::Deepgram),
+            "openai" => Ok(SynthesisType::OpenAi),
+            "elevenlabs" => Ok(SynthesisType::ElevenLabs),
+            "azure" => Ok(SynthesisType::Azure),
             #[cfg(feature = "offline")]
             "supertonic" => Ok(SynthesisType::Supertonic),
             _ => Ok(SynthesisType::Other(value)),
@@ -108,6 +132,16 @@ pub struct SynthesisOption {
     pub extra: Option<HashMap<String, String>>,
     pub max_concurrent_tasks: Option<usize>,
     pub session_id: Option<String>,
+    /// Expand numbers, dates, currency and units into words before synthesis,
+    /// on by default for Supertonic only
+    pub normalize: Option<bool>,
+    /// Words replaced before synthesis, e.g. brand names or acronyms
+    pub word_overrides: Option<HashMap<String, String>>,
+    /// Phonemes or respellings for words, usually from a playbook lexicon
+    pub pronunciations: Option<Vec<Pronunciation>>,
+    /// Request and response template for a provider without a built-in
+    /// client, see [`generic`]
+    pub template: Option<TtsTemplate>,
 }
 
 impl SynthesisOption {
@@ -131,6 +165,10 @@ impl SynthesisOption {
                 extra: other.extra.or(self.extra.clone()),
                 max_concurrent_tasks: other.max_concurrent_tasks.or(self.max_concurrent_tasks),
                 session_id: other.session_id.or(self.session_id.clone()),
+                normalize: other.normalize.or(self.normalize),
+                word_overrides: other.word_overrides.or(self.word_overrides.clone()),
+                pronunciations: other.pronunciations.or(self.pronunciations.clone()),
+                template: other.template.or(self.template.clone()),
             }
         } else {
             self.clone()
@@ -184,6 +222,12 @@ pub trait SynthesisClient: Send {
     // provider of the synthesis client.
     fn provider(&self) -> SynthesisType;
 
+    // how speech markup in the text passed to `synthesize` is consumed,
+    // see `ssml::prepare`.
+    fn markup_support(&self) -> MarkupSupport {
+        MarkupSupport::PlainText
+    }
+
     // connect to the synthesis service.
     // (cmd_seq, result), return the cmd_seq that passed from `synthesize`
     async fn start(
@@ -223,6 +267,10 @@ impl Default for SynthesisOption {
             extra: None,
             max_concurrent_tasks: None,
             session_id: None,
+            normalize: None,
+            word_overrides: None,
+            pronunciations: None,
+            template: None,
         }
     }
 }
@@ -266,6 +314,30 @@ impl SynthesisOption {
                         self.secret_key = std::env::var("DEEPGRAM_API_KEY").ok();
                     }
                 }
+                "openai" => {
+                    self.secret_key = self
+                        .secret_key
+                        .take()
+                        .or_else(|| std::env::var("OPENAI_API_KEY").ok());
+                }
+                "elevenlabs" => {
+                    self.secret_key = self
+                        .secret_key
+                        .take()
+                        .or_else(|| std::env::var("ELEVENLABS_API_KEY").ok());
+                }
+                "azure" => {
+                    self.secret_key = self
+                        .secret_key
+                        .take()
+                        .or_else(|| std::env::var("AZURE_SPEECH_KEY").ok());
+                    if let Ok(region) = std::env::var("AZURE_SPEECH_REGION") {
+                        self.extra
+                            .get_or_insert_default()
+                            .entry("region".to_string())
+                            .or_insert(region);
+                    }
+                }
                 _ => {}
             }
         }
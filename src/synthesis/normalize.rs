@@ -0,0 +1,1005 @@
+//! Text normalization for TTS.
+//!
+//! Digits, phone numbers, dates and times, currency amounts, percentages,
+//! units and common abbreviations are expanded into words before synthesis,
+//! in English or Chinese, so every provider reads them the same way. Word
+//! overrides from the playbook are applied first. Markup is kept, only the
+//! spoken text is rewritten, see [`ssml::map_text`].
+
+use super::{MarkupSupport, SynthesisOption, ssml};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const NUMBER: &str = r"(?P<sign>-)?(?P<int>\d{1,3}(?:,\d{3})+|\d+)(?P<frac>\.\d+)?";
+
+static EN_ABBREVIATIONS: Lazy<Vec<(Regex, &'static str)>> = Lazy::new(|| {
+    [
+        (r"\bMr\.", "Mister"),
+        (r"\bMrs\.", "Missus"),
+        (r"\bMs\.", "Miz"),
+        (r"\bDr\.", "Doctor"),
+        (r"\bProf\.", "Professor"),
+        (r"\bJr\.", "Junior"),
+        (r"\bSr\.", "Senior"),
+        (r"\bvs\b\.?", "versus"),
+        (r"\betc\.", "et cetera"),
+        (r"\be\.g\.", "for example"),
+        (r"\bi\.e\.", "that is"),
+        (r"\bapprox\.", "approximately"),
+        (r"\bNo\.\s?(\d)", "number $1"),
+        (r"#(\d)", "number $1"),
+        (r"\s&\s", " and "),
+    ]
+    .into_iter()
+    .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), replacement))
+    .collect()
+});
+static ISO_DATE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d{4})[-/.](\d{1,2})[-/.](\d{1,2})").unwrap());
+static US_DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d{1,2})/(\d{1,2})/(\d{4})\b").unwrap());
+static EN_MONTH_DATE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!(
+        r"\b({}) (\d{{1,2}})(?:st|nd|rd|th)?\b(?:(,? )(\d{{4}})\b)?",
+        ssml::MONTHS.join("|")
+    ))
+    .unwrap()
+});
+static TIME: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(\d{1,2}):(\d{2})(?::(\d{2}))?(?:\s?(?P<meridiem>[AaPp])\.?[Mm]\b\.?)?").unwrap()
+});
+static EN_HOUR: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(\d{1,2})\s?(?P<meridiem>[AaPp])\.?[Mm]\b\.?").unwrap());
+static EN_PHONE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(\+\d{1,3}[ .-]?)?(?:\(\d{2,4}\)[ .-]?|\b\d{2,4}[.-])\d{2,4}[.-]\d{3,4}\b")
+        .unwrap()
+});
+static ZH_PHONE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?P<cc>\+?86[ -]?)?(?P<a>1[3-9]\d)[ -]?(?P<b>\d{4})[ -]?(?P<c>\d{4})|0\d{2,3}-\d{7,8}",
+    )
+    .unwrap()
+});
+static ZH_DATE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d{4})[-/.年](\d{1,2})[-/.月](\d{1,2})[日号]?").unwrap());
+static CURRENCY_SYMBOL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?P<sign>-)?(?P<cur>[$€£¥￥])\s?(?P<int>\d{1,3}(?:,\d{3})+|\d+)(?P<frac>\.\d+)?(?:\s?(?P<scale>thousand|million|billion|trillion|[kKMB]|bn)\b|(?P<zhscale>万|亿))?",
+    )
+    .unwrap()
+});
+static CURRENCY_CODE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(&format!(r"{}\s?(?P<cur>USD|EUR|GBP|JPY|CNY|RMB)\b", NUMBER)).unwrap());
+static ZH_YUAN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?P<int>\d+)(?:\.(?P<frac>\d{1,2}))?\s?(?P<unit>元|块)").unwrap());
+static PERCENT: Lazy<Regex> = Lazy::new(|| Regex::new(&format!(r"{}\s?[%％]", NUMBER)).unwrap());
+static UNIT: Lazy<Regex> = Lazy::new(|| {
+    let symbols: Vec<String> = UNITS
+        .iter()
+        .map(|(symbol, ..)| {
+            let escaped = regex::escape(symbol);
+            if symbol.ends_with(|c: char| c.is_ascii_alphanumeric()) {
+                format!(r"{}\b", escaped)
+            } else {
+                escaped
+            }
+        })
+        .collect();
+    Regex::new(&format!(r"{}\s?(?P<unit>{})", NUMBER, symbols.join("|"))).unwrap()
+});
+static EN_ORDINAL: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d+)(?:st|nd|rd|th)\b").unwrap());
+static ZH_YEAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d{4})\s?年").unwrap());
+static NUMBER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(NUMBER).unwrap());
+
+/// Unit symbol, English singular and plural, Chinese. Longer symbols first.
+const UNITS: &[(&str, &str, &str, &str)] = &[
+    (
+        "km/h",
+        "kilometer per hour",
+        "kilometers per hour",
+        "公里每小时",
+    ),
+    ("mph", "mile per hour", "miles per hour", "英里每小时"),
+    ("kWh", "kilowatt hour", "kilowatt hours", "千瓦时"),
+    ("km", "kilometer", "kilometers", "公里"),
+    ("cm", "centimeter", "centimeters", "厘米"),
+    ("mm", "millimeter", "millimeters", "毫米"),
+    ("kg", "kilogram", "kilograms", "公斤"),
+    ("mg", "milligram", "milligrams", "毫克"),
+    ("ml", "milliliter", "milliliters", "毫升"),
+    ("mL", "milliliter", "milliliters", "毫升"),
+    ("TB", "terabyte", "terabytes", "T"),
+    ("GB", "gigabyte", "gigabytes", "G"),
+    ("MB", "megabyte", "megabytes", "兆"),
+    ("KB", "kilobyte", "kilobytes", "K"),
+    ("°C", "degree Celsius", "degrees Celsius", "摄氏度"),
+    ("℃", "degree Celsius", "degrees Celsius", "摄氏度"),
+    ("°F", "degree Fahrenheit", "degrees Fahrenheit", "华氏度"),
+    ("℉", "degree Fahrenheit", "degrees Fahrenheit", "华氏度"),
+    ("min", "minute", "minutes", "分钟"),
+    ("m", "meter", "meters", "米"),
+    ("g", "gram", "grams", "克"),
+    ("L", "liter", "liters", "升"),
+    ("h", "hour", "hours", "小时"),
+];
+
+const EN_ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const EN_TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const ZH_DIGITS: [&str; 10] = ["零", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+/// Chinese measure words after which 2 is read as 两.
+const ZH_MEASURE_WORDS: &str = "个位只条件次本张台天岁句家辆瓶杯份";
+
+/// Normalize TTS text according to `option`.
+///
+/// Expansion is on by default for the offline Supertonic model, which reads
+/// raw numerals poorly, and opt-in for cloud providers via `normalize`.
+pub fn normalize(text: &str, option: &SynthesisOption) -> String {
+    let expand = option.normalize.unwrap_or_else(|| {
+        option
+            .provider
+            .as_ref()
+            .is_some_and(|provider| provider.to_string() == "supertonic")
+    });
+    let overrides = option.word_overrides.clone().unwrap_or_default();
+    if !expand && overrides.is_empty() {
+        return text.to_string();
+    }
+    let chinese = ssml::is_chinese(text, option.language.as_deref());
+    let normalizer = Normalizer::new(chinese, expand, &overrides);
+    ssml::map_text(text, chinese, expand, |text| normalizer.normalize(text))
+}
+
+/// How a word is spoken: a phoneme string for providers that take SSML, or a
+/// respelling for the others.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Pronunciation {
+    pub word: String,
+    pub respelling: Option<String>,
+    pub phoneme: Option<String>,
+    /// Phoneme alphabet, `py` (pinyin) when not set
+    pub alphabet: Option<String>,
+}
+
+/// Apply `option.pronunciations` to `text`, wrapping words in `<phoneme>`
+/// for clients with native SSML and respelling them otherwise. The first
+/// entry for a word wins.
+pub fn pronounce(text: &str, option: &SynthesisOption, support: MarkupSupport) -> String {
+    let native = matches!(
+        support,
+        MarkupSupport::Aliyun | MarkupSupport::Tencent | MarkupSupport::Azure
+    );
+    // Word to (replacement, whether the replacement is markup)
+    let mut words: HashMap<String, (String, bool)> = HashMap::new();
+    for entry in option.pronunciations.iter().flatten() {
+        let replacement = match (&entry.phoneme, &entry.respelling) {
+            (Some(phoneme), _) if native => (
+                format!(
+                    "<phoneme alphabet=\"{}\" ph=\"{}\">{}</phoneme>",
+                    ssml::escape(entry.alphabet.as_deref().unwrap_or("py")),
+                    ssml::escape(phoneme),
+                    ssml::escape(&entry.word)
+                ),
+                true,
+            ),
+            (_, Some(respelling)) => (respelling.clone(), false),
+            _ => continue,
+        };
+        words.entry(entry.word.clone()).or_insert(replacement);
+    }
+    let Some(re) = override_pattern(&words) else {
+        return text.to_string();
+    };
+    let has_markup = ssml::has_markup(text);
+    if !has_markup && !re.find_iter(text).any(|m| words[m.as_str()].1) {
+        return re
+            .replace_all(text, |caps: &Captures| words[&caps[0]].0.clone())
+            .into_owned();
+    }
+    // Phonemes are markup, so plain text is turned into markup first
+    let text = if has_markup {
+        text.to_string()
+    } else {
+        format!("<speak>{}</speak>", ssml::escape(text))
+    };
+    ssml::map_raw_text(&text, |gap| {
+        re.replace_all(gap, |caps: &Captures| match &words[&caps[0]] {
+            (phoneme, true) => phoneme.clone(),
+            (respelling, false) => ssml::escape(respelling),
+        })
+        .into_owned()
+    })
+}
+
+/// Rewrites plain text into speakable words for one language.
+pub struct Normalizer {
+    chinese: bool,
+    expand: bool,
+    overrides: Option<(Regex, HashMap<String, String>)>,
+}
+
+impl Normalizer {
+    pub fn new(chinese: bool, expand: bool, overrides: &HashMap<String, String>) -> Self {
+        Self {
+            chinese,
+            expand,
+            overrides: override_pattern(overrides).map(|re| (re, overrides.clone())),
+        }
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        let text = match &self.overrides {
+            Some((re, words)) => re
+                .replace_all(text, |caps: &Captures| words[&caps[0]].clone())
+                .into_owned(),
+            None => text.to_string(),
+        };
+        if !self.expand {
+            text
+        } else if self.chinese {
+            normalize_zh(&text)
+        } else {
+            normalize_en(&text)
+        }
+    }
+}
+
+/// Whole-word, case-sensitive match of any override, longest first.
+fn override_pattern<V>(overrides: &HashMap<String, V>) -> Option<Regex> {
+    let mut words: Vec<&String> = overrides.keys().filter(|w| !w.is_empty()).collect();
+    if words.is_empty() {
+        return None;
+    }
+    words.sort_by_key(|w| std::cmp::Reverse(w.len()));
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let alternatives: Vec<String> = words
+        .iter()
+        .map(|word| {
+            let mut pattern = regex::escape(word);
+            if word.starts_with(is_word) {
+                pattern.insert_str(0, r"\b");
+            }
+            if word.ends_with(is_word) {
+                pattern.push_str(r"\b");
+            }
+            pattern
+        })
+        .collect();
+    Regex::new(&alternatives.join("|")).ok()
+}
+
+/// Replace every match of `re` with `f`, keeping the match when `f` declines.
+fn rewrite(text: &str, re: &Regex, f: impl Fn(&Captures) -> Option<String>) -> String {
+    re.replace_all(text, |caps: &Captures| {
+        f(caps).unwrap_or_else(|| caps[0].to_string())
+    })
+    .into_owned()
+}
+
+/// How a `-` right before a number reads: `(text before the number, negative)`.
+/// Between two numbers it is a range, after a letter a hyphen.
+fn read_sign(text: &str, caps: &Captures, chinese: bool) -> (&'static str, bool) {
+    let Some(sign) = caps.name("sign") else {
+        return ("", false);
+    };
+    match text[..sign.start()].chars().next_back() {
+        Some(c) if c.is_ascii_digit() => (if chinese { "到" } else { " to " }, false),
+        Some(c) if c.is_ascii_alphabetic() => ("-", false),
+        _ => ("", true),
+    }
+}
+
+fn number_of(caps: &Captures) -> String {
+    format!(
+        "{}{}",
+        &caps["int"],
+        caps.name("frac").map_or("", |m| m.as_str())
+    )
+}
+
+fn normalize_en(text: &str) -> String {
+    let mut text = text.to_string();
+    for (re, replacement) in EN_ABBREVIATIONS.iter() {
+        text = re.replace_all(&text, *replacement).into_owned();
+    }
+    let text = rewrite(&text, &ISO_DATE, |caps| {
+        en_date(&caps[1], &caps[2], &caps[3])
+    });
+    let text = rewrite(&text, &US_DATE, |caps| {
+        en_date(&caps[3], &caps[1], &caps[2])
+    });
+    let text = rewrite(&text, &EN_MONTH_DATE, |caps| {
+        let day: u64 = caps[2].parse().ok().filter(|d| (1..=31).contains(d))?;
+        let mut out = format!("{} {}", &caps[1], en_ordinal(day));
+        if let (Some(sep), Some(year)) = (caps.get(3), caps.get(4)) {
+            out.push_str(sep.as_str());
+            out.push_str(&en_year(year.as_str().parse().ok()?));
+        }
+        Some(out)
+    });
+    let text = rewrite(&text, &TIME, en_time);
+    let text = rewrite(&text, &EN_HOUR, |caps| {
+        let hour: u64 = caps[1].parse().ok().filter(|h| (1..=12).contains(h))?;
+        Some(format!("{} {}", en_cardinal(hour), meridiem(caps)?))
+    });
+    let text = rewrite(&text, &EN_PHONE, |caps| Some(read_phone(&caps[0], false)));
+    let text = rewrite(&text, &CURRENCY_SYMBOL, |caps| {
+        read_money_match(&text, caps, false)
+    });
+    let text = rewrite(&text, &CURRENCY_CODE, |caps| {
+        read_money_match(&text, caps, false)
+    });
+    let text = rewrite(&text, &PERCENT, |caps| {
+        let (prefix, negative) = read_sign(&text, caps, false);
+        Some(format!(
+            "{}{} percent",
+            prefix,
+            read_number(&number_of(caps), negative, false)
+        ))
+    });
+    let text = rewrite(&text, &UNIT, |caps| {
+        let (prefix, negative) = read_sign(&text, caps, false);
+        let number = number_of(caps);
+        let (_, singular, plural, _) = UNITS.iter().find(|u| u.0 == &caps["unit"])?;
+        let unit = if number == "1" { singular } else { plural };
+        Some(format!(
+            "{}{} {}",
+            prefix,
+            read_number(&number, negative, false),
+            unit
+        ))
+    });
+    let text = rewrite(&text, &EN_ORDINAL, |caps| {
+        Some(en_ordinal(caps[1].parse().ok()?))
+    });
+    rewrite(&text, &NUMBER_RE, |caps| {
+        let (prefix, negative) = read_sign(&text, caps, false);
+        let int = &caps["int"];
+        // A bare four digit number in this range is almost always a year
+        if !negative
+            && caps.name("frac").is_none()
+            && int.len() == 4
+            && let Ok(year @ 1900..=2099) = int.parse::<u64>()
+        {
+            return Some(format!("{}{}", prefix, en_year(year)));
+        }
+        Some(format!(
+            "{}{}",
+            prefix,
+            read_number(&number_of(caps), negative, false)
+        ))
+    })
+}
+
+fn normalize_zh(text: &str) -> String {
+    let text = rewrite(text, &ZH_DATE, |caps| {
+        let month: u64 = caps[2].parse().ok().filter(|m| (1..=12).contains(m))?;
+        let day: u64 = caps[3].parse().ok().filter(|d| (1..=31).contains(d))?;
+        Some(format!(
+            "{}年{}月{}日",
+            read_digits(&caps[1], true),
+            zh_cardinal(month),
+            zh_cardinal(day)
+        ))
+    });
+    let text = rewrite(&text, &ZH_YEAR, |caps| {
+        Some(format!("{}年", read_digits(&caps[1], true)))
+    });
+    let text = rewrite(&text, &TIME, zh_time);
+    let text = rewrite(&text, &ZH_PHONE, |caps| {
+        let m = caps.get(0)?;
+        let isolated = !text[..m.start()].ends_with(|c: char| c.is_ascii_digit())
+            && !text[m.end()..].starts_with(|c: char| c.is_ascii_digit());
+        if !isolated {
+            return None;
+        }
+        // Mobile numbers are read in groups of 3, 4 and 4 digits
+        Some(match (caps.name("a"), caps.name("b"), caps.name("c")) {
+            (Some(a), Some(b), Some(c)) => {
+                let cc = caps.name("cc").map_or("", |m| m.as_str().trim());
+                read_phone(
+                    &format!("{} {} {} {}", cc, a.as_str(), b.as_str(), c.as_str()),
+                    true,
+                )
+            }
+            _ => read_phone(m.as_str(), true),
+        })
+    });
+    let text = rewrite(&text, &EN_PHONE, |caps| Some(read_phone(&caps[0], true)));
+    let text = rewrite(&text, &CURRENCY_SYMBOL, |caps| {
+        read_money_match(&text, caps, true)
+    });
+    let text = rewrite(&text, &CURRENCY_CODE, |caps| {
+        read_money_match(&text, caps, true)
+    });
+    let text = rewrite(&text, &ZH_YUAN, |caps| {
+        let dime = if &caps["unit"] == "块" { "毛" } else { "角" };
+        Some(zh_yuan(
+            &caps["int"],
+            caps.name("frac").map(|m| m.as_str()),
+            &caps["unit"],
+            dime,
+        ))
+    });
+    let text = rewrite(&text, &PERCENT, |caps| {
+        let (prefix, negative) = read_sign(&text, caps, true);
+        Some(format!(
+            "{}{}百分之{}",
+            prefix,
+            if negative { "负" } else { "" },
+            read_number(&number_of(caps), false, true)
+        ))
+    });
+    let text = rewrite(&text, &UNIT, |caps| {
+        let (prefix, negative) = read_sign(&text, caps, true);
+        let (.., chinese) = UNITS.iter().find(|u| u.0 == &caps["unit"])?;
+        Some(format!(
+            "{}{}{}",
+            prefix,
+            read_number(&number_of(caps), negative, true),
+            chinese
+        ))
+    });
+    rewrite(&text, &NUMBER_RE, |caps| {
+        let (prefix, negative) = read_sign(&text, caps, true);
+        let number = number_of(caps);
+        let end = caps.get(0)?.end();
+        if number == "2"
+            && !negative
+            && text[end..].starts_with(|c: char| ZH_MEASURE_WORDS.contains(c))
+        {
+            return Some(format!("{}两", prefix));
+        }
+        Some(format!(
+            "{}{}",
+            prefix,
+            read_number(&number, negative, true)
+        ))
+    })
+}
+
+fn en_date(year: &str, month: &str, day: &str) -> Option<String> {
+    let month: usize = month.parse().ok().filter(|m| (1..=12).contains(m))?;
+    let day: u64 = day.parse().ok().filter(|d| (1..=31).contains(d))?;
+    Some(format!(
+        "{} {}, {}",
+        ssml::MONTHS[month - 1],
+        en_ordinal(day),
+        en_year(year.parse().ok()?)
+    ))
+}
+
+fn meridiem(caps: &Captures) -> Option<&'static str> {
+    caps.name("meridiem").map(|m| match m.as_str() {
+        "a" | "A" => "AM",
+        _ => "PM",
+    })
+}
+
+fn en_time(caps: &Captures) -> Option<String> {
+    let hour: u64 = caps[1].parse().ok().filter(|h| *h <= 24)?;
+    let minute: u64 = caps[2].parse().ok().filter(|m| *m < 60)?;
+    let mut out = en_cardinal(hour);
+    match minute {
+        0 if caps.name("meridiem").is_some() => {}
+        0 if hour <= 12 => out.push_str(" o'clock"),
+        0 => out.push_str(" hundred"),
+        1..=9 => out.push_str(&format!(" oh {}", en_cardinal(minute))),
+        _ => out.push_str(&format!(" {}", en_cardinal(minute))),
+    }
+    if let Some(second) = caps.get(3) {
+        let second: u64 = second.as_str().parse().ok().filter(|s| *s < 60)?;
+        if second > 0 {
+            let unit = if second == 1 { "second" } else { "seconds" };
+            out.push_str(&format!(" and {} {}", en_cardinal(second), unit));
+        }
+    }
+    if let Some(meridiem) = meridiem(caps) {
+        out.push(' ');
+        out.push_str(meridiem);
+    }
+    Some(out)
+}
+
+fn zh_time(caps: &Captures) -> Option<String> {
+    let hour: u64 = caps[1].parse().ok().filter(|h| *h <= 24)?;
+    let minute: u64 = caps[2].parse().ok().filter(|m| *m < 60)?;
+    let mut out = match (hour, caps.name("meridiem").map(|m| m.as_str())) {
+        (_, Some("a" | "A")) => "上午".to_string(),
+        (_, Some(_)) => "下午".to_string(),
+        _ => String::new(),
+    };
+    out.push_str(&if hour == 2 {
+        "两".to_string()
+    } else {
+        zh_cardinal(hour)
+    });
+    out.push('点');
+    match minute {
+        0 => {}
+        1..=9 => out.push_str(&format!("零{}分", zh_cardinal(minute))),
+        _ => out.push_str(&format!("{}分", zh_cardinal(minute))),
+    }
+    if let Some(second) = caps.get(3) {
+        let second: u64 = second.as_str().parse().ok().filter(|s| *s < 60)?;
+        if second > 0 {
+            out.push_str(&format!("{}秒", zh_cardinal(second)));
+        }
+    }
+    Some(out)
+}
+
+/// Read a phone number digit by digit, its groups separated by pauses.
+fn read_phone(phone: &str, chinese: bool) -> String {
+    let groups: Vec<String> = phone
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            if chinese {
+                group
+                    .chars()
+                    .map(|c| if c == '1' { "幺" } else { ZH_DIGITS[digit(c)] })
+                    .collect()
+            } else {
+                read_digits(group, false)
+            }
+        })
+        .collect();
+    let plus = match (phone.starts_with('+'), chinese) {
+        (false, _) => "",
+        (true, true) => "加",
+        (true, false) => "plus ",
+    };
+    format!("{}{}", plus, groups.join(if chinese { " " } else { ", " }))
+}
+
+struct Currency {
+    major: (&'static str, &'static str),
+    minor: Option<(&'static str, &'static str)>,
+    chinese: &'static str,
+}
+
+fn currency(code: &str, chinese: bool) -> Option<Currency> {
+    let (major, minor, zh) = match code {
+        "$" | "USD" => (("dollar", "dollars"), Some(("cent", "cents")), "美元"),
+        "€" | "EUR" => (("euro", "euros"), Some(("cent", "cents")), "欧元"),
+        "£" | "GBP" => (("pound", "pounds"), Some(("penny", "pence")), "英镑"),
+        "JPY" => (("yen", "yen"), None, "日元"),
+        "¥" if !chinese => (("yen", "yen"), None, "日元"),
+        "¥" | "￥" | "CNY" | "RMB" => (("yuan", "yuan"), None, "元"),
+        _ => return None,
+    };
+    Some(Currency {
+        major,
+        minor,
+        chinese: zh,
+    })
+}
+
+fn read_money_match(text: &str, caps: &Captures, chinese: bool) -> Option<String> {
+    let currency = currency(&caps["cur"], chinese)?;
+    let (prefix, negative) = read_sign(text, caps, chinese);
+    let frac = caps.name("frac").map(|m| &m.as_str()[1..]);
+    let scale = caps
+        .name("scale")
+        .or(caps.name("zhscale"))
+        .map(|m| m.as_str());
+    let amount = read_money(&caps["int"], frac, scale, &currency, chinese);
+    Some(match (negative, chinese) {
+        (false, _) => format!("{}{}", prefix, amount),
+        (true, true) => format!("负{}", amount),
+        (true, false) => format!("minus {}", amount),
+    })
+}
+
+fn read_money(
+    int: &str,
+    frac: Option<&str>,
+    scale: Option<&str>,
+    currency: &Currency,
+    chinese: bool,
+) -> String {
+    let number = match frac {
+        Some(frac) => format!("{}.{}", int, frac),
+        None => int.to_string(),
+    };
+    if chinese {
+        let scale = match scale {
+            Some("k" | "K" | "thousand") => "千",
+            Some("M" | "million") => "百万",
+            Some("B" | "bn" | "billion") => "十亿",
+            Some("trillion") => "万亿",
+            Some(scale) => scale,
+            None if currency.chinese == "元" => return zh_yuan(int, frac, "元", "角"),
+            None => "",
+        };
+        return format!(
+            "{}{}{}",
+            read_number(&number, false, true),
+            scale,
+            currency.chinese
+        );
+    }
+
+    let plural = |n: u64, (singular, plural): (&str, &str)| {
+        if n == 1 { singular } else { plural }.to_string()
+    };
+    if let Some(scale) = scale {
+        let scale = match scale {
+            "k" | "K" => "thousand",
+            "M" => "million",
+            "B" | "bn" => "billion",
+            "万" => "ten thousand",
+            "亿" => "hundred million",
+            scale => scale,
+        };
+        return format!(
+            "{} {} {}",
+            read_number(&number, false, false),
+            scale,
+            currency.major.1
+        );
+    }
+    let whole: u64 = int.replace(',', "").parse().unwrap_or(0);
+    let (Some(minor), Some(frac)) = (currency.minor, frac) else {
+        return format!(
+            "{} {}",
+            read_number(&number, false, false),
+            plural(if number == "1" { 1 } else { 2 }, currency.major)
+        );
+    };
+    let cents: u64 = format!("{:0<2}", frac)[..2].parse().unwrap_or(0);
+    let whole_words = format!("{} {}", en_cardinal(whole), plural(whole, currency.major));
+    let cent_words = format!("{} {}", en_cardinal(cents), plural(cents, minor));
+    match (whole, cents) {
+        (_, 0) => whole_words,
+        (0, _) => cent_words,
+        _ => format!("{} and {}", whole_words, cent_words),
+    }
+}
+
+/// Chinese yuan amounts read with 角 and 分, e.g. 30.25 as 三十元二角五分.
+fn zh_yuan(int: &str, frac: Option<&str>, unit: &str, dime: &str) -> String {
+    let whole: u64 = int.replace(',', "").parse().unwrap_or(0);
+    let mut cents = frac.unwrap_or("").chars().map(digit).chain([0, 0]);
+    let (jiao, fen) = (cents.next().unwrap_or(0), cents.next().unwrap_or(0));
+    let mut out = String::new();
+    if whole > 0 || (jiao == 0 && fen == 0) {
+        out.push_str(&zh_cardinal(whole));
+        out.push_str(unit);
+    }
+    if jiao > 0 {
+        out.push_str(ZH_DIGITS[jiao]);
+        out.push_str(dime);
+    } else if fen > 0 && !out.is_empty() {
+        out.push('零');
+    }
+    if fen > 0 {
+        out.push_str(ZH_DIGITS[fen]);
+        out.push('分');
+    }
+    out
+}
+
+fn digit(c: char) -> usize {
+    c.to_digit(10).unwrap_or(0) as usize
+}
+
+fn read_digits(digits: &str, chinese: bool) -> String {
+    let words: Vec<&str> = digits
+        .chars()
+        .filter(char::is_ascii_digit)
+        .map(|c| {
+            if chinese {
+                ZH_DIGITS[digit(c)]
+            } else {
+                EN_ONES[digit(c)]
+            }
+        })
+        .collect();
+    words.join(if chinese { "" } else { " " })
+}
+
+/// Read a number such as `1,234.5`. Integers with a leading zero or too long
+/// to be quantities are read digit by digit.
+fn read_number(number: &str, negative: bool, chinese: bool) -> String {
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    let int = int.replace(',', "");
+    let mut out = match int.parse::<u64>() {
+        Ok(n) if int.len() <= 15 && !(int.len() > 1 && int.starts_with('0')) => {
+            if chinese {
+                zh_cardinal(n)
+            } else {
+                en_cardinal(n)
+            }
+        }
+        _ => read_digits(&int, chinese),
+    };
+    if !frac.is_empty() {
+        out.push_str(if chinese { "点" } else { " point " });
+        out.push_str(&read_digits(frac, chinese));
+    }
+    match (negative, chinese) {
+        (false, _) => out,
+        (true, true) => format!("负{}", out),
+        (true, false) => format!("minus {}", out),
+    }
+}
+
+fn en_cardinal(n: u64) -> String {
+    const SCALES: [(u64, &str); 4] = [
+        (1_000_000_000_000, "trillion"),
+        (1_000_000_000, "billion"),
+        (1_000_000, "million"),
+        (1_000, "thousand"),
+    ];
+    match n {
+        0..=19 => EN_ONES[n as usize].to_string(),
+        20..=99 if n.is_multiple_of(10) => EN_TENS[(n / 10) as usize].to_string(),
+        20..=99 => format!(
+            "{}-{}",
+            EN_TENS[(n / 10) as usize],
+            EN_ONES[(n % 10) as usize]
+        ),
+        100..=999 if n.is_multiple_of(100) => format!("{} hundred", EN_ONES[(n / 100) as usize]),
+        100..=999 => format!(
+            "{} hundred {}",
+            EN_ONES[(n / 100) as usize],
+            en_cardinal(n % 100)
+        ),
+        _ => {
+            let (scale, name) = SCALES.into_iter().find(|(scale, _)| n >= *scale).unwrap();
+            match n % scale {
+                0 => format!("{} {}", en_cardinal(n / scale), name),
+                rest => format!("{} {} {}", en_cardinal(n / scale), name, en_cardinal(rest)),
+            }
+        }
+    }
+}
+
+fn en_ordinal(n: u64) -> String {
+    let cardinal = en_cardinal(n);
+    let split = cardinal.rfind([' ', '-']).map_or(0, |i| i + 1);
+    let (head, last) = cardinal.split_at(split);
+    let last = match last {
+        "one" => "first".to_string(),
+        "two" => "second".to_string(),
+        "three" => "third".to_string(),
+        "five" => "fifth".to_string(),
+        "eight" => "eighth".to_string(),
+        "nine" => "ninth".to_string(),
+        "twelve" => "twelfth".to_string(),
+        word => match word.strip_suffix('y') {
+            Some(stem) => format!("{}ieth", stem),
+            None => format!("{}th", word),
+        },
+    };
+    format!("{}{}", head, last)
+}
+
+/// Years are read in pairs, 1999 as nineteen ninety-nine.
+fn en_year(year: u64) -> String {
+    match year {
+        2000 => "two thousand".to_string(),
+        2001..=2009 => format!("two thousand {}", en_cardinal(year - 2000)),
+        1000..=9999 => match (year / 100, year % 100) {
+            (high, 0) => format!("{} hundred", en_cardinal(high)),
+            (high, low @ 1..=9) => format!("{} oh {}", en_cardinal(high), en_cardinal(low)),
+            (high, low) => format!("{} {}", en_cardinal(high), en_cardinal(low)),
+        },
+        _ => en_cardinal(year),
+    }
+}
+
+fn zh_cardinal(n: u64) -> String {
+    const UNITS: [&str; 5] = ["", "万", "亿", "万亿", "亿亿"];
+    if n == 0 {
+        return ZH_DIGITS[0].to_string();
+    }
+    let mut groups = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        groups.push(rest % 10000);
+        rest /= 10000;
+    }
+    let mut out = String::new();
+    let mut zero = false;
+    for (i, group) in groups.into_iter().enumerate().rev() {
+        if group == 0 {
+            zero = !out.is_empty();
+            continue;
+        }
+        if !out.is_empty() && (zero || group < 1000) {
+            out.push('零');
+        }
+        out.push_str(&zh_group(group));
+        out.push_str(UNITS[i]);
+        zero = false;
+    }
+    match out.strip_prefix("一十") {
+        Some(rest) => format!("十{}", rest),
+        None => out,
+    }
+}
+
+fn zh_group(n: u64) -> String {
+    const PLACES: [&str; 4] = ["千", "百", "十", ""];
+    let digits = [n / 1000, n / 100 % 10, n / 10 % 10, n % 10];
+    let mut out = String::new();
+    let mut zero = false;
+    for (d, place) in digits.into_iter().zip(PLACES) {
+        if d == 0 {
+            zero = !out.is_empty();
+            continue;
+        }
+        if zero {
+            out.push('零');
+            zero = false;
+        }
+        out.push_str(ZH_DIGITS[d as usize]);
+        out.push_str(place);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesis::SynthesisType;
+
+    fn en(text: &str) -> String {
+        Normalizer::new(false, true, &HashMap::new()).normalize(text)
+    }
+
+    fn zh(text: &str) -> String {
+        Normalizer::new(true, true, &HashMap::new()).normalize(text)
+    }
+
+    #[test]
+    fn test_cardinals() {
+        assert_eq!(en_cardinal(0), "zero");
+        assert_eq!(en_cardinal(42), "forty-two");
+        assert_eq!(
+            en_cardinal(1_204_017),
+            "one million two hundred four thousand seventeen"
+        );
+        assert_eq!(en_ordinal(21), "twenty-first");
+        assert_eq!(en_ordinal(40), "fortieth");
+        assert_eq!(en_year(1999), "nineteen ninety-nine");
+        assert_eq!(en_year(2005), "two thousand five");
+        assert_eq!(zh_cardinal(10), "十");
+        assert_eq!(zh_cardinal(105), "一百零五");
+        assert_eq!(zh_cardinal(100_010), "十万零一十");
+        assert_eq!(zh_cardinal(20_000), "二万");
+        assert_eq!(zh_cardinal(120_003_000), "一亿二千万三千");
+    }
+
+    #[test]
+    fn test_normalize_english() {
+        assert_eq!(
+            en("Dr. Smith sees you on 2024-03-05 at 3:30pm, room No. 12."),
+            "Doctor Smith sees you on March fifth, twenty twenty-four at three thirty PM, room number twelve."
+        );
+        assert_eq!(
+            en("It's $12.50, or €1 and 20% off, 5km away at -3°C."),
+            "It's twelve dollars and fifty cents, or one euro and twenty percent off, five kilometers away at minus three degrees Celsius."
+        );
+        assert_eq!(
+            en("Call +1 (555) 123-4567 by July 4th, 1999 for 1,250 of 2.5kg."),
+            "Call plus one, five five five, one two three, four five six seven by July fourth, nineteen ninety-nine for one thousand two hundred fifty of two point five kilograms."
+        );
+        assert_eq!(
+            en("Raised $3.2M in 10-20 days, code 007."),
+            "Raised three point two million dollars in ten to twenty days, code zero zero seven."
+        );
+    }
+
+    #[test]
+    fn test_normalize_chinese() {
+        assert_eq!(
+            zh("2024-03-05 14:05出发，票价¥30.25，电话13812345678"),
+            "二零二四年三月五日 十四点零五分出发，票价三十元二角五分，电话幺三八 幺二三四 五六七八"
+        );
+        assert_eq!(
+            zh("打8折，优惠20%，气温-5℃，要2个，共3.5块，2:00见"),
+            "打八折，优惠百分之二十，气温负五摄氏度，要两个，共三块五毛，两点见"
+        );
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides = HashMap::from([
+            ("SQL".to_string(), "sequel".to_string()),
+            ("ACME".to_string(), "Acme".to_string()),
+        ]);
+        let normalizer = Normalizer::new(false, false, &overrides);
+        assert_eq!(
+            normalizer.normalize("ACME SQL, not MySQL 8"),
+            "Acme sequel, not MySQL 8"
+        );
+    }
+
+    #[test]
+    fn test_pronounce() {
+        let option = SynthesisOption {
+            pronunciations: Some(vec![
+                Pronunciation {
+                    word: "重庆".to_string(),
+                    respelling: None,
+                    phoneme: Some("chong2 qing4".to_string()),
+                    alphabet: None,
+                },
+                Pronunciation {
+                    word: "Acme".to_string(),
+                    respelling: Some("Ack-me".to_string()),
+                    phoneme: None,
+                    alphabet: None,
+                },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            pronounce("重庆 & Acme", &option, MarkupSupport::Tencent),
+            "<speak><phoneme alphabet=\"py\" ph=\"chong2 qing4\">重庆</phoneme> &amp; Ack-me</speak>"
+        );
+        // Words inside tags and sub are left alone, phonemes need native SSML
+        assert_eq!(
+            pronounce(
+                "<sub alias=\"Acme\">Acme</sub> Acme 重庆",
+                &option,
+                MarkupSupport::PlainText
+            ),
+            "<sub alias=\"Acme\">Acme</sub> Ack-me 重庆"
+        );
+        assert_eq!(
+            pronounce("Acme & co ", &option, MarkupSupport::PlainText),
+            "Ack-me & co "
+        );
+    }
+
+    #[test]
+    fn test_normalize_keeps_markup() {
+        let option = SynthesisOption {
+            normalize: Some(true),
+            language: Some("en".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(
+                "Order <say-as interpret-as=\"digits\">42</say-as> costs $5<break time=\"1s\"/>ok",
+                &option
+            ),
+            "<speak>Order four two costs five dollars<break time=\"1000ms\"/>ok</speak>"
+        );
+        // Providers that are not normalized by default are left alone
+        let option = SynthesisOption {
+            provider: Some(SynthesisType::Aliyun),
+            ..Default::default()
+        };
+        assert_eq!(normalize("Costs $5", &option), "Costs $5");
+    }
+}
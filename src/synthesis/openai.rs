@@ -0,0 +1,195 @@
+use crate::synthesis::{SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use audio_codec::BoxedResampler;
+use bytes::Bytes;
+use futures::{
+    FutureExt, Stream, StreamExt, TryStreamExt, future,
+    stream::{self, BoxStream},
+};
+use serde::Serialize;
+use serde_with::skip_serializing_none;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+// `response_format: pcm` is always 24kHz 16-bit mono
+const OPENAI_SAMPLE_RATE: usize = 24000;
+
+#[skip_serializing_none]
+#[derive(Serialize)]
+struct Payload {
+    model: String,
+    input: String,
+    voice: String,
+    response_format: &'static str,
+    speed: Option<f32>,
+    instructions: Option<String>,
+}
+
+impl Payload {
+    fn new(option: &SynthesisOption, text: String) -> Self {
+        let extra = option.extra.as_ref();
+        Self {
+            model: option
+                .model
+                .clone()
+                .unwrap_or_else(|| "gpt-4o-mini-tts".to_string()),
+            input: text,
+            voice: option
+                .speaker
+                .clone()
+                .unwrap_or_else(|| "alloy".to_string()),
+            response_format: "pcm",
+            speed: option.speed.map(|speed| speed.clamp(0.25, 4.0)),
+            instructions: extra.and_then(|e| e.get("instructions").cloned()),
+        }
+    }
+}
+
+// text, cmd_seq and option of each command, for clients that make a
+// request per text
+pub(super) type TextSender =
+    mpsc::UnboundedSender<(String, Option<usize>, Option<SynthesisOption>)>;
+
+/// Converts 16-bit PCM between rates, carrying odd bytes across chunks.
+pub(super) struct PcmResampler {
+    resampler: Option<BoxedResampler>,
+    pending: Option<u8>,
+}
+
+impl PcmResampler {
+    pub(super) fn new(input_rate: usize, output_rate: usize) -> Result<Self> {
+        let resampler = if input_rate != output_rate {
+            Some(BoxedResampler::new(input_rate, output_rate).map_err(anyhow::Error::from)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            resampler,
+            pending: None,
+        })
+    }
+
+    pub(super) fn process(&mut self, bytes: Bytes) -> Bytes {
+        let Some(resampler) = self.resampler.as_mut() else {
+            return bytes;
+        };
+        let mut data = Vec::with_capacity(bytes.len() + 1);
+        data.extend(self.pending.take());
+        data.extend_from_slice(&bytes);
+        if data.len() % 2 == 1 {
+            self.pending = data.pop();
+        }
+        let samples: Vec<i16> = data
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let resampled = resampler.resample(&samples);
+        let mut out = Vec::with_capacity(resampled.len() * 2);
+        for sample in resampled {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        Bytes::from(out)
+    }
+}
+
+async fn chunked_stream(
+    option: SynthesisOption,
+    text: String,
+) -> Result<impl Stream<Item = Result<Bytes>>> {
+    let base_url = option.endpoint.as_deref().unwrap_or(OPENAI_BASE_URL);
+    let url = format!("{}/audio/speech", base_url.trim_end_matches('/'));
+    let token = option
+        .secret_key
+        .as_ref()
+        .ok_or_else(|| anyhow!("OpenAI TTS: missing api key"))?;
+    let samplerate = option.samplerate.unwrap_or(16000) as usize;
+    let mut resampler = PcmResampler::new(OPENAI_SAMPLE_RATE, samplerate)?;
+    let resp = reqwest::Client::new()
+        .post(url)
+        .bearer_auth(token)
+        .json(&Payload::new(&option, text))
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(anyhow!("OpenAI TTS request failed: {} {}", status, body));
+    }
+    Ok(resp
+        .bytes_stream()
+        .map_err(anyhow::Error::from)
+        .map_ok(move |bytes| resampler.process(bytes))
+        .try_filter(|bytes| future::ready(!bytes.is_empty())))
+}
+
+// https://platform.openai.com/docs/api-reference/audio/createSpeech
+// `audio/speech` has no streaming input, every text is a request whose
+// chunked response body is played as it arrives.
+pub struct OpenAiTtsClient {
+    option: SynthesisOption,
+    tx: Option<TextSender>,
+}
+
+impl OpenAiTtsClient {
+    pub fn create(_streaming: bool, option: &SynthesisOption) -> Result<Box<dyn SynthesisClient>> {
+        Ok(Box::new(Self {
+            option: option.clone(),
+            tx: None,
+        }))
+    }
+}
+
+#[async_trait]
+impl SynthesisClient for OpenAiTtsClient {
+    fn provider(&self) -> SynthesisType {
+        SynthesisType::OpenAi
+    }
+
+    async fn start(
+        &mut self,
+    ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.tx = Some(tx);
+        let max_concurrent_tasks = self.option.max_concurrent_tasks.unwrap_or(1);
+        let client_option = self.option.clone();
+        let stream = UnboundedReceiverStream::new(rx).flat_map_unordered(
+            max_concurrent_tasks,
+            move |(text, cmd_seq, cmd_option)| {
+                let option = client_option.merge_with(cmd_option);
+                chunked_stream(option, text)
+                    .map(move |res| match res {
+                        Ok(stream) => stream
+                            .map_ok(SynthesisEvent::AudioChunk)
+                            .chain(stream::once(future::ready(Ok(SynthesisEvent::Finished))))
+                            .boxed(),
+                        Err(e) => stream::once(future::ready(Err(e))).boxed(),
+                    })
+                    .flatten_stream()
+                    .map(move |res| (cmd_seq, res))
+                    .boxed()
+            },
+        );
+        Ok(stream.boxed())
+    }
+
+    async fn synthesize(
+        &mut self,
+        text: &str,
+        cmd_seq: Option<usize>,
+        option: Option<SynthesisOption>,
+    ) -> Result<()> {
+        if let Some(tx) = &self.tx {
+            tx.send((text.to_string(), cmd_seq, option))?;
+        } else {
+            return Err(anyhow!("OpenAI TTS: missing client sender"));
+        };
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.tx.take();
+        Ok(())
+    }
+}
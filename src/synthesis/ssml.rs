@@ -0,0 +1,875 @@
+//! Provider-neutral speech markup.
+//!
+//! TTS text may carry a subset of SSML: `<speak>`, `<break>`, `<say-as>`
+//! (`digits`, `telephone`, `date`, `currency`), `<prosody>`, `<phoneme>` and
+//! `<sub>`. Before synthesis the markup is rendered into the native SSML of
+//! providers that accept it, or expanded to plain text for the others.
+//! Text without any of these tags is passed through untouched.
+
+use anyhow::{Result, anyhow};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{collections::HashMap, time::Duration};
+use tracing::warn;
+
+static MARKUP_TAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"</?(speak|break|say-as|prosody|phoneme|sub)[\s/>]").unwrap());
+static ANY_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+const MAX_BREAK: Duration = Duration::from_secs(10);
+pub(crate) const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// How a synthesis client consumes speech markup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkupSupport {
+    /// Expanded to plain text, breaks become punctuation pauses
+    PlainText,
+    /// Passed through, the client splits it with [`segments`] and inserts
+    /// real silence for breaks
+    Segments,
+    /// Aliyun CosyVoice SSML
+    Aliyun,
+    /// Tencent Cloud SSML
+    Tencent,
+    /// Azure Speech SSML, the client adds the `<voice>` element
+    Azure,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    Break(Duration),
+    Element {
+        name: String,
+        attrs: HashMap<String, String>,
+        children: Vec<Node>,
+    },
+}
+
+/// A piece of plain text to synthesize, or silence to insert.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Silence(Duration),
+}
+
+pub fn has_markup(text: &str) -> bool {
+    MARKUP_TAG.is_match(text)
+}
+
+/// Render `text` for a client with the given markup support.
+pub fn prepare(text: &str, support: MarkupSupport, language: Option<&str>) -> String {
+    if support == MarkupSupport::Segments || !has_markup(text) {
+        return text.to_string();
+    }
+    let nodes = match parse(text) {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            warn!("invalid speech markup, stripping tags: {}", e);
+            return decode_entities(&ANY_TAG.replace_all(text, ""));
+        }
+    };
+    match support {
+        MarkupSupport::Aliyun | MarkupSupport::Tencent | MarkupSupport::Azure => {
+            to_ssml(&nodes, support)
+        }
+        _ => to_plain_text(&nodes, is_chinese(text, language)),
+    }
+}
+
+/// Split `text` into plain text pieces and the silences between them.
+pub fn segments(text: &str, language: Option<&str>) -> Vec<Segment> {
+    if !has_markup(text) {
+        return vec![Segment::Text(text.to_string())];
+    }
+    let nodes = match parse(text) {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            warn!("invalid speech markup, stripping tags: {}", e);
+            return vec![Segment::Text(decode_entities(
+                &ANY_TAG.replace_all(text, ""),
+            ))];
+        }
+    };
+    let chinese = is_chinese(text, language);
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    collect_segments(&nodes, chinese, &mut current, &mut segments);
+    push_text(&mut segments, &mut current);
+    segments
+}
+
+/// Rewrite the spoken text of `text` with `f`, keeping the markup around it.
+///
+/// With `read_say_as`, `say-as` elements are read out first and the result is
+/// passed to `f` as well. Content of `phoneme` and `sub` is left alone.
+pub fn map_text(
+    text: &str,
+    chinese: bool,
+    read_say_as: bool,
+    f: impl Fn(&str) -> String,
+) -> String {
+    if !has_markup(text) {
+        return f(text);
+    }
+    match parse(text) {
+        Ok(nodes) => to_markup(&map_nodes(nodes, chinese, read_say_as, &f)),
+        Err(e) => {
+            warn!("invalid speech markup, stripping tags: {}", e);
+            f(&decode_entities(&ANY_TAG.replace_all(text, "")))
+        }
+    }
+}
+
+fn map_nodes(
+    nodes: Vec<Node>,
+    chinese: bool,
+    read_say_as: bool,
+    f: &impl Fn(&str) -> String,
+) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .map(|node| match node {
+            Node::Text(text) => Node::Text(f(&text)),
+            Node::Element { ref name, .. } if name == "say-as" && read_say_as => {
+                Node::Text(f(&plain_node(&node, chinese)))
+            }
+            Node::Element {
+                name,
+                attrs,
+                children,
+            } if !matches!(name.as_str(), "say-as" | "phoneme" | "sub") => Node::Element {
+                children: map_nodes(children, chinese, read_say_as, f),
+                name,
+                attrs,
+            },
+            node => node,
+        })
+        .collect()
+}
+
+/// Rewrite the text between the tags of raw markup with `f`, which may
+/// return markup itself. Content of `say-as`, `phoneme` and `sub` is left
+/// alone.
+pub fn map_raw_text(text: &str, f: impl Fn(&str) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut depth = 0usize;
+    let mut last = 0;
+    for tag in ANY_TAG.find_iter(text) {
+        let gap = &text[last..tag.start()];
+        out.push_str(&if depth == 0 { f(gap) } else { gap.to_string() });
+        out.push_str(tag.as_str());
+        last = tag.end();
+
+        let inner = tag.as_str()[1..tag.len() - 1].trim();
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let name = inner
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if matches!(name.as_str(), "say-as" | "phoneme" | "sub") && !inner.ends_with('/') {
+            if closing {
+                depth = depth.saturating_sub(1);
+            } else {
+                depth += 1;
+            }
+        }
+    }
+    let rest = &text[last..];
+    out.push_str(&if depth == 0 {
+        f(rest)
+    } else {
+        rest.to_string()
+    });
+    out
+}
+
+/// Serialize the tree back to markup, keeping every element and attribute.
+pub fn to_markup(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    let wrapped = matches!(nodes, [Node::Element { name, .. }] if name == "speak");
+    if !wrapped {
+        out.push_str("<speak>");
+    }
+    for node in nodes {
+        render_markup(node, &mut out);
+    }
+    if !wrapped {
+        out.push_str("</speak>");
+    }
+    out
+}
+
+fn render_markup(node: &Node, out: &mut String) {
+    match node {
+        Node::Text(text) => out.push_str(&escape(text)),
+        Node::Break(duration) => {
+            out.push_str(&format!("<break time=\"{}ms\"/>", duration.as_millis()))
+        }
+        Node::Element {
+            name,
+            attrs,
+            children,
+        } => {
+            let mut keys: Vec<&String> = attrs.keys().collect();
+            keys.sort();
+            out.push('<');
+            out.push_str(name);
+            for key in keys {
+                out.push_str(&format!(" {}=\"{}\"", key, escape(&attrs[key])));
+            }
+            out.push('>');
+            for child in children {
+                render_markup(child, out);
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+    }
+}
+
+fn collect_segments(nodes: &[Node], chinese: bool, current: &mut String, out: &mut Vec<Segment>) {
+    for node in nodes {
+        match node {
+            Node::Break(duration) => {
+                push_text(out, current);
+                if !duration.is_zero() {
+                    out.push(Segment::Silence(*duration));
+                }
+            }
+            Node::Element { name, children, .. }
+                if matches!(name.as_str(), "speak" | "prosody") || !is_known(name) =>
+            {
+                collect_segments(children, chinese, current, out)
+            }
+            _ => current.push_str(&plain_node(node, chinese)),
+        }
+    }
+}
+
+fn push_text(out: &mut Vec<Segment>, current: &mut String) {
+    let text = current.trim();
+    if !text.is_empty() {
+        out.push(Segment::Text(text.to_string()));
+    }
+    current.clear();
+}
+
+pub(crate) fn is_chinese(text: &str, language: Option<&str>) -> bool {
+    match language {
+        Some(language) => language.to_ascii_lowercase().starts_with("zh"),
+        None => text.chars().any(|c| ('\u{4e00}'..='\u{9fff}').contains(&c)),
+    }
+}
+
+fn is_known(name: &str) -> bool {
+    matches!(
+        name,
+        "speak" | "break" | "say-as" | "prosody" | "phoneme" | "sub"
+    )
+}
+
+enum Token {
+    Text(String),
+    Open(String, HashMap<String, String>),
+    Close(String),
+    Empty(String, HashMap<String, String>),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            tokens.push(Token::Text(decode_entities(rest)));
+            break;
+        };
+        if start > 0 {
+            tokens.push(Token::Text(decode_entities(&rest[..start])));
+        }
+        let end = rest[start..]
+            .find('>')
+            .ok_or_else(|| anyhow!("unterminated tag"))?
+            + start;
+        let inner = rest[start + 1..end].trim();
+        rest = &rest[end + 1..];
+
+        if let Some(name) = inner.strip_prefix('/') {
+            tokens.push(Token::Close(name.trim().to_ascii_lowercase()));
+            continue;
+        }
+        let (inner, empty) = match inner.strip_suffix('/') {
+            Some(inner) => (inner.trim(), true),
+            None => (inner, false),
+        };
+        let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+        let name = inner[..name_end].to_ascii_lowercase();
+        if name.is_empty() {
+            return Err(anyhow!("empty tag name"));
+        }
+        let attrs = parse_attrs(&inner[name_end..])?;
+        tokens.push(if empty {
+            Token::Empty(name, attrs)
+        } else {
+            Token::Open(name, attrs)
+        });
+    }
+    Ok(tokens)
+}
+
+fn parse_attrs(mut input: &str) -> Result<HashMap<String, String>> {
+    let mut attrs = HashMap::new();
+    loop {
+        input = input.trim_start();
+        if input.is_empty() {
+            return Ok(attrs);
+        }
+        let eq = input
+            .find('=')
+            .ok_or_else(|| anyhow!("attribute without value: {}", input))?;
+        let key = input[..eq].trim().to_ascii_lowercase();
+        input = input[eq + 1..].trim_start();
+        let quote = input
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| anyhow!("unquoted attribute: {}", key))?;
+        let close = input[1..]
+            .find(quote)
+            .ok_or_else(|| anyhow!("unterminated attribute: {}", key))?
+            + 1;
+        attrs.insert(key, decode_entities(&input[1..close]));
+        input = &input[close + 1..];
+    }
+}
+
+/// Parse speech markup into a tree. Unknown tags are kept as elements and
+/// read as their content.
+pub fn parse(input: &str) -> Result<Vec<Node>> {
+    let mut stack: Vec<(String, HashMap<String, String>, Vec<Node>)> =
+        vec![(String::new(), HashMap::new(), Vec::new())];
+    for token in tokenize(input)? {
+        match token {
+            Token::Text(text) => {
+                if !text.is_empty() {
+                    stack.last_mut().unwrap().2.push(Node::Text(text));
+                }
+            }
+            Token::Open(name, attrs) => stack.push((name, attrs, Vec::new())),
+            Token::Empty(name, attrs) => {
+                let node = element(name, attrs, Vec::new());
+                stack.last_mut().unwrap().2.push(node);
+            }
+            Token::Close(name) => {
+                if stack.len() < 2 || stack.last().unwrap().0 != name {
+                    return Err(anyhow!("unexpected closing tag </{}>", name));
+                }
+                let (name, attrs, children) = stack.pop().unwrap();
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .2
+                    .push(element(name, attrs, children));
+            }
+        }
+    }
+    if stack.len() != 1 {
+        return Err(anyhow!("unclosed tag <{}>", stack.last().unwrap().0));
+    }
+    Ok(stack.pop().unwrap().2)
+}
+
+fn element(name: String, attrs: HashMap<String, String>, children: Vec<Node>) -> Node {
+    if name == "break" {
+        return Node::Break(break_duration(&attrs));
+    }
+    Node::Element {
+        name,
+        attrs,
+        children,
+    }
+}
+
+fn break_duration(attrs: &HashMap<String, String>) -> Duration {
+    if let Some(time) = attrs.get("time").map(|t| t.trim().to_ascii_lowercase()) {
+        let parsed = if let Some(ms) = time.strip_suffix("ms") {
+            ms.trim().parse::<f64>().ok().map(|ms| ms / 1000.0)
+        } else if let Some(secs) = time.strip_suffix('s') {
+            secs.trim().parse::<f64>().ok()
+        } else {
+            None
+        };
+        if let Some(secs) = parsed.filter(|secs| secs.is_finite() && *secs >= 0.0) {
+            return Duration::from_secs_f64(secs).min(MAX_BREAK);
+        }
+    }
+    let ms = match attrs.get("strength").map(String::as_str) {
+        Some("none") => 0,
+        Some("x-weak") => 100,
+        Some("weak") => 250,
+        Some("strong") => 750,
+        Some("x-strong") => 1000,
+        _ => 500,
+    };
+    Duration::from_millis(ms)
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|n| n.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn inner_text(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Text(text) => text.clone(),
+            Node::Break(_) => " ".to_string(),
+            Node::Element { children, .. } => inner_text(children),
+        })
+        .collect()
+}
+
+/// Render the tree as the SSML dialect of a provider, wrapped in `<speak>`.
+pub fn to_ssml(nodes: &[Node], dialect: MarkupSupport) -> String {
+    let mut nodes = strip_speak(nodes);
+    let mut speak_attrs = String::new();
+    // CosyVoice takes rate, pitch and volume on <speak> only, so a prosody
+    // around the whole utterance is lifted onto it.
+    if dialect == MarkupSupport::Aliyun
+        && let [
+            Node::Element {
+                name,
+                attrs,
+                children,
+            },
+        ] = nodes
+        && name == "prosody"
+    {
+        for key in ["rate", "pitch", "volume"] {
+            if let Some(value) = attrs.get(key) {
+                speak_attrs.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+            }
+        }
+        nodes = children;
+    }
+    let mut out = format!("<speak{}>", speak_attrs);
+    for node in nodes {
+        render_ssml(node, dialect, &mut out);
+    }
+    out.push_str("</speak>");
+    out
+}
+
+fn strip_speak(nodes: &[Node]) -> &[Node] {
+    match nodes {
+        [Node::Element { name, children, .. }] if name == "speak" => children,
+        _ => nodes,
+    }
+}
+
+fn render_ssml(node: &Node, dialect: MarkupSupport, out: &mut String) {
+    match node {
+        Node::Text(text) => out.push_str(&escape(text)),
+        Node::Break(duration) => {
+            out.push_str(&format!("<break time=\"{}ms\"/>", duration.as_millis()))
+        }
+        Node::Element {
+            name,
+            attrs,
+            children,
+        } => {
+            let keys: &[&str] = match name.as_str() {
+                "say-as" => &["interpret-as", "format"],
+                "phoneme" => &["alphabet", "ph"],
+                "sub" => &["alias"],
+                "prosody" if matches!(dialect, MarkupSupport::Tencent | MarkupSupport::Azure) => {
+                    &["rate", "pitch", "volume"]
+                }
+                _ => {
+                    for child in children {
+                        render_ssml(child, dialect, out);
+                    }
+                    return;
+                }
+            };
+            out.push('<');
+            out.push_str(name);
+            for key in keys {
+                if let Some(value) = attrs.get(*key) {
+                    out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+                }
+            }
+            out.push('>');
+            if name == "prosody" {
+                for child in children {
+                    render_ssml(child, dialect, out);
+                }
+            } else {
+                out.push_str(&escape(&inner_text(children)));
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+    }
+}
+
+/// Expand the tree to plain text, reading `say-as` content out and turning
+/// breaks into punctuation.
+pub fn to_plain_text(nodes: &[Node], chinese: bool) -> String {
+    plain_nodes(nodes, chinese)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn plain_nodes(nodes: &[Node], chinese: bool) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        let after_punctuation = text.trim_end().ends_with([
+            '.', ',', '!', '?', ';', ':', '，', '。', '！', '？', '；', '：',
+        ]);
+        match node {
+            // A pause already follows punctuation
+            Node::Break(_) if after_punctuation => text.push(' '),
+            _ => text.push_str(&plain_node(node, chinese)),
+        }
+    }
+    text
+}
+
+fn plain_node(node: &Node, chinese: bool) -> String {
+    match node {
+        Node::Text(text) => text.clone(),
+        Node::Break(duration) if duration.is_zero() => String::new(),
+        Node::Break(duration) if *duration < Duration::from_millis(400) => {
+            if chinese { "，" } else { ", " }.to_string()
+        }
+        Node::Break(_) => if chinese { "。" } else { "... " }.to_string(),
+        Node::Element {
+            name,
+            attrs,
+            children,
+        } => match name.as_str() {
+            "sub" => attrs
+                .get("alias")
+                .cloned()
+                .unwrap_or_else(|| inner_text(children)),
+            "say-as" => {
+                let text = inner_text(children);
+                let format = attrs.get("format").map(String::as_str);
+                match attrs.get("interpret-as").map(String::as_str) {
+                    Some("digits") | Some("characters") => spell_digits(&text),
+                    Some("telephone") => read_telephone(&text, chinese),
+                    Some("date") => read_date(&text, format, chinese).unwrap_or(text),
+                    Some("currency") => read_currency(&text, chinese).unwrap_or(text),
+                    _ => text,
+                }
+            }
+            _ => plain_nodes(children, chinese),
+        },
+    }
+}
+
+fn spell_digits(text: &str) -> String {
+    let chars: Vec<String> = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_string())
+        .collect();
+    chars.join(" ")
+}
+
+/// Read a phone number digit by digit, pausing between its groups.
+fn read_telephone(text: &str, chinese: bool) -> String {
+    let mut groups: Vec<String> = text
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|group| !group.is_empty())
+        .map(str::to_string)
+        .collect();
+    if groups.len() == 1 {
+        let digits = &groups[0];
+        let sizes: &[usize] = match digits.len() {
+            10 => &[3, 3, 4],
+            11 => &[3, 4, 4],
+            _ => &[],
+        };
+        if !sizes.is_empty() {
+            let mut start = 0;
+            groups = sizes
+                .iter()
+                .map(|size| {
+                    let group = digits[start..start + size].to_string();
+                    start += size;
+                    group
+                })
+                .collect();
+        }
+    }
+    let separator = if chinese { "，" } else { ", " };
+    let mut out = groups
+        .iter()
+        .map(|group| spell_digits(group))
+        .collect::<Vec<_>>()
+        .join(separator);
+    if text.trim_start().starts_with('+') {
+        out = format!("{} {}", if chinese { "加" } else { "plus" }, out);
+    }
+    out
+}
+
+fn read_date(text: &str, format: Option<&str>, chinese: bool) -> Option<String> {
+    let parts: Vec<u32> = text
+        .split(['-', '/', '.'])
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let format = format.unwrap_or(match parts.first() {
+        Some(first) if *first > 31 => "ymd",
+        _ if chinese => "ymd",
+        _ => "mdy",
+    });
+    let (mut year, mut month, mut day) = (None, None, None);
+    if format.len() != parts.len() {
+        return None;
+    }
+    for (field, value) in format.chars().zip(parts) {
+        match field {
+            'y' => year = Some(value),
+            'm' => month = Some(value),
+            'd' => day = Some(value),
+            _ => return None,
+        }
+    }
+    if month.is_some_and(|m| !(1..=12).contains(&m)) || day.is_some_and(|d| !(1..=31).contains(&d))
+    {
+        return None;
+    }
+    if chinese {
+        let mut out = String::new();
+        if let Some(year) = year {
+            out.push_str(&format!("{}年", year));
+        }
+        if let Some(month) = month {
+            out.push_str(&format!("{}月", month));
+        }
+        if let Some(day) = day {
+            out.push_str(&format!("{}日", day));
+        }
+        return Some(out);
+    }
+    let month = month.map(|m| MONTHS[m as usize - 1]);
+    Some(match (month, day, year) {
+        (Some(m), Some(d), Some(y)) => format!("{} {}, {}", m, d, y),
+        (Some(m), Some(d), None) => format!("{} {}", m, d),
+        (Some(m), None, Some(y)) => format!("{} {}", m, y),
+        _ => return None,
+    })
+}
+
+fn read_currency(text: &str, chinese: bool) -> Option<String> {
+    let text = text.trim();
+    let symbols = [
+        ("$", "USD"),
+        ("€", "EUR"),
+        ("£", "GBP"),
+        ("¥", "CNY"),
+        ("￥", "CNY"),
+    ];
+    let mut code = None;
+    let mut amount = text;
+    for (symbol, symbol_code) in symbols {
+        if let Some(rest) = amount.strip_prefix(symbol) {
+            code = Some(symbol_code.to_string());
+            amount = rest.trim();
+        }
+    }
+    if code.is_none()
+        && let Some((value, unit)) = amount.rsplit_once(' ')
+    {
+        code = Some(unit.trim().to_ascii_uppercase());
+        amount = value.trim();
+    }
+    let amount = amount.replace(',', "");
+    let (whole, fraction) = match amount.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (amount.as_str(), ""),
+    };
+    let whole: u64 = whole.parse().ok()?;
+    let cents: u64 = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u64>().ok()? * 10,
+        2 => fraction.parse().ok()?,
+        _ => return None,
+    };
+    let code = code.unwrap_or_else(|| if chinese { "CNY" } else { "USD" }.to_string());
+
+    if chinese {
+        let unit = match code.as_str() {
+            "CNY" | "RMB" => "元",
+            "USD" => "美元",
+            "EUR" => "欧元",
+            "GBP" => "英镑",
+            _ => return None,
+        };
+        if code == "CNY" || code == "RMB" {
+            let (jiao, fen) = (cents / 10, cents % 10);
+            return Some(match (jiao, fen) {
+                (0, 0) => format!("{}元", whole),
+                (j, 0) => format!("{}元{}角", whole, j),
+                (0, f) => format!("{}元零{}分", whole, f),
+                (j, f) => format!("{}元{}角{}分", whole, j, f),
+            });
+        }
+        return Some(if cents == 0 {
+            format!("{}{}", whole, unit)
+        } else {
+            format!("{}.{:02}{}", whole, cents, unit)
+        });
+    }
+
+    let (major, minor) = match code.as_str() {
+        "USD" => (("dollar", "dollars"), ("cent", "cents")),
+        "EUR" => (("euro", "euros"), ("cent", "cents")),
+        "GBP" => (("pound", "pounds"), ("penny", "pence")),
+        "CNY" | "RMB" => (("yuan", "yuan"), ("fen", "fen")),
+        _ => return None,
+    };
+    let plural =
+        |n: u64, (one, many): (&str, &str)| format!("{} {}", n, if n == 1 { one } else { many });
+    Some(match (whole, cents) {
+        (w, 0) => plural(w, major),
+        (0, c) => plural(c, minor),
+        (w, c) => format!("{} and {}", plural(w, major), plural(c, minor)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text_passes_through() {
+        let text = "Total is 3 < 5 & fine";
+        assert!(!has_markup(text));
+        assert_eq!(prepare(text, MarkupSupport::Aliyun, None), text);
+    }
+
+    #[test]
+    fn test_plain_expansion() {
+        let text = "<speak>Your order <say-as interpret-as=\"digits\">4821</say-as> \
+                    ships <say-as interpret-as=\"date\">2024-03-05</say-as>.<break time=\"1s\"/>\
+                    It costs <say-as interpret-as=\"currency\">$12.50</say-as>, \
+                    call <say-as interpret-as=\"telephone\">+1 (555) 123-4567</say-as> \
+                    or <sub alias=\"World Wide Web Consortium\">W3C</sub>.</speak>";
+        assert_eq!(
+            prepare(text, MarkupSupport::PlainText, Some("en-US")),
+            "Your order 4 8 2 1 ships March 5, 2024. It costs 12 dollars and 50 cents, \
+             call plus 1, 5 5 5, 1 2 3, 4 5 6 7 or World Wide Web Consortium."
+        );
+    }
+
+    #[test]
+    fn test_plain_expansion_chinese() {
+        let text = "订单<say-as interpret-as=\"telephone\">13812345678</say-as>，\
+                    金额<say-as interpret-as=\"currency\">¥30.5</say-as>，\
+                    日期<say-as interpret-as=\"date\">2024/3/5</say-as>";
+        assert_eq!(
+            prepare(text, MarkupSupport::PlainText, None),
+            "订单1 3 8，1 2 3 4，5 6 7 8，金额30元5角，日期2024年3月5日"
+        );
+    }
+
+    #[test]
+    fn test_provider_ssml() {
+        let text = "<prosody rate=\"fast\">Hi <phoneme alphabet=\"py\" ph=\"zhong4\">重</phoneme>\
+                    <break strength=\"weak\"/>A &amp; B</prosody>";
+        assert_eq!(
+            prepare(text, MarkupSupport::Aliyun, None),
+            "<speak rate=\"fast\">Hi <phoneme alphabet=\"py\" ph=\"zhong4\">重</phoneme>\
+             <break time=\"250ms\"/>A &amp; B</speak>"
+        );
+        assert_eq!(
+            prepare(text, MarkupSupport::Tencent, None),
+            "<speak><prosody rate=\"fast\">Hi <phoneme alphabet=\"py\" ph=\"zhong4\">重</phoneme>\
+             <break time=\"250ms\"/>A &amp; B</prosody></speak>"
+        );
+    }
+
+    #[test]
+    fn test_segments_insert_silence() {
+        let text = "Hello.<break time=\"700ms\"/>Your code is \
+                    <say-as interpret-as=\"digits\">42</say-as>.";
+        assert_eq!(
+            segments(text, None),
+            vec![
+                Segment::Text("Hello.".to_string()),
+                Segment::Silence(Duration::from_millis(700)),
+                Segment::Text("Your code is 4 2.".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_markup_is_stripped() {
+        let text = "<speak>Hello <prosody rate=\"slow\">there</speak>";
+        assert!(parse(text).is_err());
+        assert_eq!(prepare(text, MarkupSupport::Tencent, None), "Hello there");
+    }
+}
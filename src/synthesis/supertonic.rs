@@ -1,5 +1,8 @@
-use crate::offline::get_offline_models;
-use crate::synthesis::{SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType};
+use crate::offline::{get_offline_models, supertonic::supertonic_language};
+use crate::synthesis::{
+    MarkupSupport, SynthesisClient, SynthesisEvent, SynthesisOption, SynthesisType,
+    ssml::{self, Segment},
+};
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
 use audio_codec::BoxedResampler;
@@ -13,6 +16,7 @@ use tracing::{debug, warn};
 pub struct SupertonicTtsClient {
     voice_style: String,
     speed: f32,
+    language: Option<String>,
     target_rate: i32,
     tx: Option<mpsc::UnboundedSender<(Option<usize>, Result<SynthesisEvent>)>>,
     token: CancellationToken,
@@ -27,6 +31,7 @@ impl SupertonicTtsClient {
         Ok(Box::new(Self {
             voice_style,
             speed,
+            language: option.language.clone(),
             target_rate,
             tx: None,
             token: CancellationToken::new(),
@@ -42,7 +47,14 @@ impl SupertonicTtsClient {
         Ok(())
     }
 
-    async fn synthesize_text(&self, text: String, cmd_seq: Option<usize>) -> Result<()> {
+    /// Speaks `text` with the voice, speed and language of `option` when
+    /// set, so scenes can switch voices on the loaded model
+    async fn synthesize_text(
+        &self,
+        text: String,
+        cmd_seq: Option<usize>,
+        option: Option<SynthesisOption>,
+    ) -> Result<()> {
         let Some(tx) = self.tx.as_ref() else {
             return Ok(());
         };
@@ -50,12 +62,15 @@ impl SupertonicTtsClient {
         let models =
             get_offline_models().ok_or_else(|| anyhow!("offline models not initialized"))?;
 
-        let voice_style = self.voice_style.clone();
-        let speed = self.speed;
+        let (speaker, speed, language) = match option {
+            Some(option) => (option.speaker, option.speed, option.language),
+            None => (None, None, None),
+        };
+        let voice_style = speaker.unwrap_or_else(|| self.voice_style.clone());
+        let speed = speed.unwrap_or(self.speed);
+        let language = supertonic_language(language.or_else(|| self.language.clone()).as_deref());
         let target_rate = self.target_rate;
         let tx_clone = tx.clone();
-        // Supertonic: en is hardcoded for now, or detect from text?
-        let language = "en".to_string();
 
         let tts_arc = models.get_supertonic().await?;
 
@@ -81,48 +96,59 @@ impl SupertonicTtsClient {
                     "Calling Supertonic TTS synthesis"
                 );
 
-                match tts.synthesize(&text, &language, Some(&voice_style), Some(speed)) {
-                    Ok(samples) => {
-                        if !samples.is_empty() {
-                            let mut samples_i16: Vec<i16> = samples
+                // Breaks in the markup become silence between synthesized pieces
+                let mut samples_i16: Vec<i16> = Vec::new();
+                for segment in ssml::segments(&text, None) {
+                    let text = match segment {
+                        Segment::Silence(duration) => {
+                            let silence = (target_rate as f64 * duration.as_secs_f64()) as usize;
+                            samples_i16.resize(samples_i16.len() + silence, 0);
+                            continue;
+                        }
+                        Segment::Text(text) => text,
+                    };
+                    match tts.synthesize(&text, &language, Some(&voice_style), Some(speed)) {
+                        Ok(samples) => {
+                            let mut segment_i16: Vec<i16> = samples
                                 .iter()
                                 .map(|&s| (s * 32768.0).max(-32768.0).min(32767.0) as i16)
                                 .collect();
 
                             // Resample if needed
-                            if tts.sample_rate() != target_rate {
+                            if tts.sample_rate() != target_rate && !segment_i16.is_empty() {
                                 let mut resampler = BoxedResampler::new(
                                     tts.sample_rate() as usize,
                                     target_rate as usize,
                                 )
                                 .expect("invalid sample rate");
-                                samples_i16 = resampler.resample(&samples_i16);
+                                segment_i16 = resampler.resample(&segment_i16);
                             }
-
-                            // Convert i16 samples to PCM bytes
-                            let mut bytes = Vec::with_capacity(samples_i16.len() * 2);
-                            for s in samples_i16 {
-                                bytes.extend_from_slice(&s.to_le_bytes());
-                            }
-
-                            // Send AudioChunk
-                            let _ = tx_clone.send((
-                                cmd_seq,
-                                Ok(SynthesisEvent::AudioChunk(Bytes::from(bytes))),
-                            ));
-
-                            // Send Finished
-                            let _ = tx_clone.send((cmd_seq, Ok(SynthesisEvent::Finished)));
-                        } else {
-                            warn!("Supertonic produced empty audio");
-                            let _ = tx_clone.send((cmd_seq, Ok(SynthesisEvent::Finished)));
+                            samples_i16.extend(segment_i16);
+                        }
+                        Err(e) => {
+                            warn!(error = %e, "Supertonic inference failed");
+                            let _ =
+                                tx_clone.send((cmd_seq, Err(anyhow!("Synthesis failed: {}", e))));
+                            return;
                         }
                     }
-                    Err(e) => {
-                        warn!(error = %e, "Supertonic inference failed");
-                        let _ = tx_clone.send((cmd_seq, Err(anyhow!("Synthesis failed: {}", e))));
+                }
+
+                if !samples_i16.is_empty() {
+                    // Convert i16 samples to PCM bytes
+                    let mut bytes = Vec::with_capacity(samples_i16.len() * 2);
+                    for s in samples_i16 {
+                        bytes.extend_from_slice(&s.to_le_bytes());
                     }
+
+                    // Send AudioChunk
+                    let _ = tx_clone
+                        .send((cmd_seq, Ok(SynthesisEvent::AudioChunk(Bytes::from(bytes)))));
+                } else {
+                    warn!("Supertonic produced empty audio");
                 }
+                // Send Finished
+                let _ = tx_clone.send((cmd_seq, Ok(SynthesisEvent::Finished)));
             } else {
                 warn!("Supertonic TTS not initialized");
                 let _ = tx_clone.send((cmd_seq, Err(anyhow!("TTS not initialized"))));
@@ -141,6 +167,10 @@ impl SynthesisClient for SupertonicTtsClient {
         SynthesisType::Supertonic
     }
 
+    fn markup_support(&self) -> MarkupSupport {
+        MarkupSupport::Segments
+    }
+
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
@@ -166,9 +196,10 @@ impl SynthesisClient for SupertonicTtsClient {
         &mut self,
         text: &str,
         cmd_seq: Option<usize>,
-        _option: Option<SynthesisOption>,
+        option: Option<SynthesisOption>,
     ) -> Result<()> {
-        self.synthesize_text(text.to_string(), cmd_seq).await
+        self.synthesize_text(text.to_string(), cmd_seq, option)
+            .await
     }
 
     async fn stop(&mut self) -> Result<()> {
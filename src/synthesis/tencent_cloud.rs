@@ -1,4 +1,4 @@
-use super::{SynthesisClient, SynthesisOption, SynthesisType};
+use super::{MarkupSupport, SynthesisClient, SynthesisOption, SynthesisType};
 use crate::synthesis::{Subtitle, SynthesisEvent};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -271,6 +271,10 @@ impl SynthesisClient for RealTimeClient {
         SynthesisType::TencentCloud
     }
 
+    fn markup_support(&self) -> MarkupSupport {
+        MarkupSupport::Tencent
+    }
+
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
@@ -1,4 +1,4 @@
-use super::{SynthesisClient, SynthesisOption, SynthesisType};
+use super::{MarkupSupport, SynthesisClient, SynthesisOption, SynthesisType};
 use crate::synthesis::{SynthesisEvent, tencent_cloud::TencentSubtitle};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -123,6 +123,10 @@ impl SynthesisClient for TencentCloudTtsBasicClient {
         SynthesisType::Other("tencent_basic".to_string())
     }
 
+    fn markup_support(&self) -> MarkupSupport {
+        MarkupSupport::Tencent
+    }
+
     async fn start(
         &mut self,
     ) -> Result<BoxStream<'static, (Option<usize>, Result<SynthesisEvent>)>> {
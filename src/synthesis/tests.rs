@@ -1,12 +1,22 @@
 use crate::synthesis::deepgram::DeepegramTtsClient;
 use crate::synthesis::{
-    AliyunTtsClient, SynthesisOption, SynthesisType, tencent_cloud::TencentCloudTtsClient,
+    AliyunTtsClient, AzureTtsClient, ElevenLabsTtsClient, GenericTtsClient, OpenAiTtsClient,
+    SynthesisOption, SynthesisType, TtsTemplate, tencent_cloud::TencentCloudTtsClient,
 };
 use crate::synthesis::{SynthesisClient, SynthesisEvent, TencentCloudTtsBasicClient};
 use dotenvy::dotenv;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
+use std::collections::HashMap;
 use std::env;
 use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use wiremock::matchers::{
+    body_json, body_partial_json, body_string_contains, header, method, path,
+};
+use wiremock::{Mock, MockServer, ResponseTemplate};
 
 async fn test_tts_basic(client: &mut dyn SynthesisClient) {
     let timeout = tokio::time::sleep(Duration::from_secs(5));
@@ -254,3 +264,376 @@ async fn test_deepgram_tts() {
     test_tts_basic(streaming_client.as_mut()).await;
     test_multiple_tts_commands_streaming(streaming_client.as_mut()).await;
 }
+
+struct Collected {
+    audio: usize,
+    finished: Vec<Option<usize>>,
+    subtitles: Vec<crate::synthesis::Subtitle>,
+    errors: usize,
+}
+
+// synthesize `texts` as numbered commands, or as a stream when not numbered
+async fn collect(client: &mut dyn SynthesisClient, texts: &[&str], numbered: bool) -> Collected {
+    let stream = client.start().await.expect("Failed to start TTS stream");
+    for (i, text) in texts.iter().enumerate() {
+        client
+            .synthesize(text, numbered.then_some(i), None)
+            .await
+            .expect("Failed to synthesize text");
+    }
+    client.stop().await.expect("Failed to stop TTS stream");
+    let mut collected = Collected {
+        audio: 0,
+        finished: Vec::new(),
+        subtitles: Vec::new(),
+        errors: 0,
+    };
+    let stream = stream.take_until(tokio::time::sleep(Duration::from_secs(5)));
+    tokio::pin!(stream);
+    while let Some((cmd_seq, event)) = stream.next().await {
+        match event {
+            Ok(SynthesisEvent::AudioChunk(audio)) => collected.audio += audio.len(),
+            Ok(SynthesisEvent::Subtitles(subtitles)) => collected.subtitles.extend(subtitles),
+            Ok(SynthesisEvent::Finished) => collected.finished.push(cmd_seq),
+            Err(_) => collected.errors += 1,
+        }
+    }
+    collected
+}
+
+/// Serve one WebSocket connection per text: read messages until `end`
+/// returns true, then reply with `replies` and close.
+async fn mock_ws_server(
+    end: fn(&str) -> bool,
+    replies: Vec<Message>,
+) -> (
+    String,
+    tokio::sync::mpsc::UnboundedReceiver<(String, Vec<String>)>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let replies = replies.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut path = String::new();
+                let callback = |request: &Request, response: Response| {
+                    path = request.uri().to_string();
+                    Ok(response)
+                };
+                let mut ws = accept_hdr_async(socket, callback).await.unwrap();
+                let mut received = Vec::new();
+                while let Some(Ok(message)) = ws.next().await {
+                    if let Message::Text(text) = message {
+                        received.push(text.to_string());
+                        if end(&text) {
+                            break;
+                        }
+                    }
+                }
+                for reply in replies {
+                    ws.send(reply).await.ok();
+                }
+                ws.close(None).await.ok();
+                tx.send((path, received)).ok();
+            });
+        }
+    });
+    (format!("ws://{}", addr), rx)
+}
+
+fn pcm(samples: usize) -> Vec<u8> {
+    vec![0u8; samples * 2]
+}
+
+#[tokio::test]
+async fn test_openai_tts() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/audio/speech"))
+        .and(header("authorization", "Bearer sk-test"))
+        .and(body_partial_json(serde_json::json!({
+            "model": "gpt-4o-mini-tts",
+            "voice": "alloy",
+            "response_format": "pcm",
+            "instructions": "Speak calmly"
+        })))
+        // one second at 24kHz
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(pcm(24000)))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let option = SynthesisOption {
+        provider: Some(SynthesisType::OpenAi),
+        secret_key: Some("sk-test".to_string()),
+        endpoint: Some(format!("{}/v1", server.uri())),
+        extra: Some(HashMap::from([(
+            "instructions".to_string(),
+            "Speak calmly".to_string(),
+        )])),
+        max_concurrent_tasks: Some(3),
+        ..Default::default()
+    };
+    let mut client = OpenAiTtsClient::create(false, &option).unwrap();
+    let collected = collect(client.as_mut(), &["one", "two", "three"], true).await;
+    assert_eq!(collected.errors, 0);
+    let mut finished = collected.finished.clone();
+    finished.sort();
+    assert_eq!(finished, vec![Some(0), Some(1), Some(2)]);
+    // resampled to 16kHz, about one second per command
+    let per_command = collected.audio / 3;
+    assert!((31000..=33000).contains(&per_command), "{}", per_command);
+}
+
+#[tokio::test]
+async fn test_openai_tts_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(401).set_body_string("invalid api key"))
+        .mount(&server)
+        .await;
+    let option = SynthesisOption {
+        secret_key: Some("bad".to_string()),
+        endpoint: Some(server.uri()),
+        ..Default::default()
+    };
+    let mut client = OpenAiTtsClient::create(false, &option).unwrap();
+    let collected = collect(client.as_mut(), &["hello"], true).await;
+    assert_eq!(collected.errors, 1);
+    assert_eq!(collected.audio, 0);
+}
+
+#[tokio::test]
+async fn test_azure_tts() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/cognitiveservices/v1"))
+        .and(header("Ocp-Apim-Subscription-Key", "azure-key"))
+        .and(header("X-Microsoft-OutputFormat", "raw-8khz-16bit-mono-pcm"))
+        .and(body_string_contains(
+            "xml:lang=\"zh-CN\"><voice name=\"zh-CN-XiaoxiaoNeural\"><prosody rate=\"1.2\">你好 &amp; 再见<break time=\"500ms\"/></prosody></voice></speak>",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(pcm(8000)))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let option = SynthesisOption {
+        provider: Some(SynthesisType::Azure),
+        secret_key: Some("azure-key".to_string()),
+        speaker: Some("zh-CN-XiaoxiaoNeural".to_string()),
+        endpoint: Some(format!("{}/cognitiveservices/v1", server.uri())),
+        samplerate: Some(8000),
+        speed: Some(1.2),
+        ..Default::default()
+    };
+    let mut client = AzureTtsClient::create(false, &option).unwrap();
+    let text = crate::synthesis::ssml::prepare(
+        "你好 & 再见<break time=\"500ms\"/>",
+        client.markup_support(),
+        None,
+    );
+    let collected = collect(client.as_mut(), &[&text], true).await;
+    assert_eq!(collected.errors, 0);
+    assert_eq!(collected.finished, vec![Some(0)]);
+    assert_eq!(collected.audio, 16000);
+}
+
+#[tokio::test]
+async fn test_elevenlabs_tts_alignment() {
+    let audio = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, pcm(8000));
+    let reply = |text: &str, starts: Vec<u32>| {
+        let chars: Vec<String> = text.chars().map(|c| c.to_string()).collect();
+        let durations = vec![50; chars.len()];
+        Message::text(
+            serde_json::json!({
+                "audio": audio,
+                "isFinal": null,
+                "alignment": {
+                    "chars": chars,
+                    "charStartTimesMs": starts,
+                    "charsDurationsMs": durations,
+                }
+            })
+            .to_string(),
+        )
+    };
+    let replies = vec![
+        // half a second of audio per message
+        reply("Hi all ", vec![0, 50, 100, 150, 200, 250, 300]),
+        reply("ok", vec![0, 50]),
+        Message::text(r#"{"isFinal":true}"#),
+    ];
+    let (url, mut requests) = mock_ws_server(|text| text == r#"{"text":""}"#, replies).await;
+
+    let option = SynthesisOption {
+        provider: Some(SynthesisType::ElevenLabs),
+        secret_key: Some("xi-test".to_string()),
+        speaker: Some("voice-1".to_string()),
+        endpoint: Some(url),
+        ..Default::default()
+    };
+
+    let mut client = ElevenLabsTtsClient::create(false, &option).unwrap();
+    let collected = collect(client.as_mut(), &["Hi all ok"], true).await;
+    assert_eq!(collected.errors, 0);
+    assert_eq!(collected.finished, vec![Some(0)]);
+    assert_eq!(collected.audio, 32000);
+    let words: Vec<(&str, u32, u32, u32, u32)> = collected
+        .subtitles
+        .iter()
+        .map(|s| {
+            (
+                s.text.as_str(),
+                s.begin_time,
+                s.end_time,
+                s.begin_index,
+                s.end_index,
+            )
+        })
+        .collect();
+    assert_eq!(
+        words,
+        vec![
+            ("Hi", 0, 100, 0, 2),
+            ("all", 150, 300, 3, 6),
+            ("ok", 500, 600, 7, 9),
+        ]
+    );
+    let (path, received) = requests.recv().await.unwrap();
+    assert!(path.starts_with("/v1/text-to-speech/voice-1/stream-input?"));
+    assert!(path.contains("output_format=pcm_16000"));
+    assert_eq!(
+        received,
+        vec![
+            r#"{"text":" "}"#,
+            r#"{"text":"Hi all ok ","flush":true}"#,
+            r#"{"text":""}"#
+        ]
+    );
+
+    // streaming: one connection, text flushed at sentence ends
+    let mut client = ElevenLabsTtsClient::create(true, &option).unwrap();
+    let collected = collect(client.as_mut(), &["Hi all.", " ok"], false).await;
+    assert_eq!(collected.finished, vec![None]);
+    assert_eq!(collected.subtitles.len(), 3);
+    let (_, received) = requests.recv().await.unwrap();
+    assert_eq!(
+        received,
+        vec![
+            r#"{"text":" "}"#,
+            r#"{"text":"Hi all. ","flush":true}"#,
+            r#"{"text":" ok "}"#,
+            r#"{"text":""}"#
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_generic_http_tts() {
+    let server = MockServer::start().await;
+    // raw chunked PCM
+    Mock::given(method("POST"))
+        .and(path("/raw"))
+        .and(header("x-token", "secret"))
+        .and(body_json(
+            serde_json::json!({"input": "say \"hi\"", "voice": "v1"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(pcm(1600)))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // server sent events with base64 audio
+    let audio = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, pcm(800));
+    let events = format!(
+        "data: {{\"audio\":\"{0}\"}}\n\ndata: {{\"audio\":\"{0}\"}}\n\ndata: {{\"event\":\"done\"}}\n\n",
+        audio
+    );
+    Mock::given(method("POST"))
+        .and(path("/sse"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(events))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let template: TtsTemplate = serde_json::from_value(serde_json::json!({
+        "url": format!("{}/raw", server.uri()),
+        "headers": {"x-token": "{{ secret_key }}"},
+        "body": "{\"input\": {{ text | tojson }}, \"voice\": \"{{ speaker }}\"}",
+    }))
+    .unwrap();
+    let option = SynthesisOption {
+        provider: Some(SynthesisType::Other("my-tts".to_string())),
+        secret_key: Some("secret".to_string()),
+        speaker: Some("v1".to_string()),
+        template: Some(template),
+        ..Default::default()
+    };
+    let engine = crate::media::engine::StreamEngine::default();
+    let mut client = engine.create_tts_client(false, &option).await.unwrap();
+    assert_eq!(client.provider().to_string(), "my-tts");
+    let collected = collect(client.as_mut(), &["say \"hi\""], true).await;
+    assert_eq!(collected.errors, 0);
+    assert_eq!(collected.finished, vec![Some(0)]);
+    assert_eq!(collected.audio, 3200);
+
+    let template: TtsTemplate = serde_json::from_value(serde_json::json!({
+        "url": format!("{}/sse", server.uri()),
+        "audioPointer": "/audio",
+        "donePointer": "/event",
+        "doneValue": "done",
+        // 8kHz audio played at 16kHz
+        "samplerate": 8000,
+    }))
+    .unwrap();
+    let option = SynthesisOption {
+        template: Some(template),
+        ..option
+    };
+    let mut client = GenericTtsClient::create(false, &option).unwrap();
+    let collected = collect(client.as_mut(), &["hello"], true).await;
+    assert_eq!(collected.errors, 0);
+    assert_eq!(collected.finished, vec![Some(0)]);
+    assert!(
+        (6000..=6600).contains(&collected.audio),
+        "{}",
+        collected.audio
+    );
+}
+
+#[tokio::test]
+async fn test_generic_websocket_tts() {
+    let replies = vec![
+        Message::binary(pcm(1600)),
+        Message::text(r#"{"status":"partial"}"#),
+        Message::binary(pcm(1600)),
+        Message::text(r#"{"status":"end"}"#),
+    ];
+    let (url, mut requests) = mock_ws_server(|text| text == "EOS", replies).await;
+    let template: TtsTemplate = serde_json::from_value(serde_json::json!({
+        "transport": "websocket",
+        "url": format!("{}/tts?voice={{{{ speaker }}}}", url),
+        "body": "{\"text\": {{ text | tojson }}}",
+        "endMessage": "EOS",
+        "donePointer": "/status",
+        "doneValue": "end",
+    }))
+    .unwrap();
+    let option = SynthesisOption {
+        provider: Some(SynthesisType::Other("ws-tts".to_string())),
+        speaker: Some("v2".to_string()),
+        template: Some(template),
+        ..Default::default()
+    };
+    let mut client = GenericTtsClient::create(true, &option).unwrap();
+    let collected = collect(client.as_mut(), &["hello"], false).await;
+    assert_eq!(collected.errors, 0);
+    assert_eq!(collected.finished, vec![None]);
+    assert_eq!(collected.audio, 6400);
+    let (path, received) = requests.recv().await.unwrap();
+    assert_eq!(path, "/tts?voice=v2");
+    assert_eq!(received, vec![r#"{"text": "hello"}"#, "EOS"]);
+}
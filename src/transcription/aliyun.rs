@@ -358,6 +358,7 @@ impl AliyunAsrClient {
                                         confidence: None,
                                         task_id: None,
                                         refer,
+                                        words: None,
                                     }
                                 } else {
                                     SessionEvent::AsrDelta {
@@ -0,0 +1,287 @@
+use super::{
+    TranscriptionClient, TranscriptionOption,
+    websocket::{Transcript, WsAsrClient, WsAsrProtocol},
+};
+use crate::{
+    event::EventSender,
+    media::{Sample, TrackId},
+};
+use anyhow::{Result, anyhow};
+use audio_codec::samples_to_bytes;
+use serde::Deserialize;
+use serde_json::json;
+use std::{future::Future, pin::Pin};
+use tokio_tungstenite::tungstenite::{
+    Message, client::IntoClientRequest, handshake::client::Request,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use url::Url;
+use uuid::Uuid;
+
+type TranscriptionClientFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>>;
+
+const AZURE_DEFAULT_REGION: &str = "eastus";
+// offsets and durations are in 100ns ticks
+const TICKS_PER_MS: u64 = 10_000;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Hypothesis {
+    text: String,
+    offset: Option<u64>,
+    duration: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Phrase {
+    recognition_status: String,
+    display_text: Option<String>,
+    offset: Option<u64>,
+    duration: Option<u64>,
+    #[serde(rename = "NBest")]
+    n_best: Option<Vec<NBest>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NBest {
+    confidence: Option<f32>,
+}
+
+// Speech service WebSocket protocol, as used by the Speech SDK: text
+// messages are HTTP-like headers and a JSON body, audio messages are a
+// 2-byte header length, the headers and the audio.
+// https://learn.microsoft.com/azure/ai-services/speech-service/speech-to-text
+struct AzureProtocol {
+    option: TranscriptionOption,
+    request_id: String,
+    wav_header_sent: bool,
+}
+
+impl AzureProtocol {
+    fn language(&self) -> String {
+        match self.option.language.as_deref() {
+            None | Some("auto") | Some("en") => "en-US".to_string(),
+            Some("zh") => "zh-CN".to_string(),
+            Some(language) => language.to_string(),
+        }
+    }
+
+    fn headers(&self, path: &str, content_type: &str) -> String {
+        format!(
+            "Path: {}\r\nX-RequestId: {}\r\nX-Timestamp: {}\r\nContent-Type: {}\r\n",
+            path,
+            self.request_id,
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            content_type
+        )
+    }
+
+    fn text_message(&self, path: &str, body: serde_json::Value) -> Message {
+        Message::text(format!(
+            "{}\r\n{}",
+            self.headers(path, "application/json"),
+            body
+        ))
+    }
+
+    fn audio_message(&self, audio: &[u8]) -> Message {
+        let headers = self.headers("audio", "audio/x-wav");
+        let mut data = Vec::with_capacity(2 + headers.len() + audio.len());
+        data.extend_from_slice(&(headers.len() as u16).to_be_bytes());
+        data.extend_from_slice(headers.as_bytes());
+        data.extend_from_slice(audio);
+        Message::binary(data)
+    }
+}
+
+// streaming WAV header, sizes are unknown so left at zero
+fn wav_header(samplerate: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&samplerate.to_le_bytes());
+    header.extend_from_slice(&(samplerate * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header
+}
+
+impl WsAsrProtocol for AzureProtocol {
+    fn name(&self) -> &'static str {
+        "azure"
+    }
+
+    fn request(&self) -> Result<Request> {
+        let key = self
+            .option
+            .secret_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("No AZURE_SPEECH_KEY provided"))?;
+        let extra = self.option.extra.as_ref();
+        let mut url = match self.option.endpoint.as_ref() {
+            Some(endpoint) => Url::parse(endpoint)?,
+            None => {
+                let region = extra
+                    .and_then(|e| e.get("region"))
+                    .map(String::as_str)
+                    .unwrap_or(AZURE_DEFAULT_REGION);
+                Url::parse(&format!(
+                    "wss://{}.stt.speech.microsoft.com/speech/recognition/conversation/cognitiveservices/v1",
+                    region
+                ))?
+            }
+        };
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("language", &self.language());
+            query.append_pair("format", "detailed");
+            for (key, value) in extra.iter().flat_map(|e| e.iter()) {
+                if key != "region" {
+                    query.append_pair(key, value);
+                }
+            }
+        }
+        let mut request = url.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        headers.insert("Ocp-Apim-Subscription-Key", key.parse()?);
+        headers.insert("X-ConnectionId", self.request_id.parse()?);
+        Ok(request)
+    }
+
+    fn start(&mut self) -> Result<Vec<Message>> {
+        let config = json!({
+            "context": {
+                "system": { "name": "active-call", "version": env!("CARGO_PKG_VERSION") },
+                "os": { "platform": std::env::consts::OS, "name": "active-call", "version": "" },
+            }
+        });
+        let mut messages = vec![self.text_message("speech.config", config)];
+        // hotwords become a phrase list
+        let items: Vec<_> = self
+            .option
+            .hotwords
+            .iter()
+            .flatten()
+            .map(|hotword| json!({ "Text": hotword.word() }))
+            .collect();
+        if !items.is_empty() {
+            let context = json!({
+                "dgi": { "Groups": [{ "Type": "Generic", "Items": items }] }
+            });
+            messages.push(self.text_message("speech.context", context));
+        }
+        Ok(messages)
+    }
+
+    fn audio(&mut self, samples: &[Sample]) -> Result<Vec<Message>> {
+        let mut audio = Vec::new();
+        if !self.wav_header_sent {
+            self.wav_header_sent = true;
+            audio = wav_header(self.option.samplerate.unwrap_or(16000));
+        }
+        audio.extend(samples_to_bytes(samples));
+        Ok(vec![self.audio_message(&audio)])
+    }
+
+    // an empty audio message ends the stream
+    fn stop(&mut self) -> Vec<Message> {
+        vec![self.audio_message(&[])]
+    }
+
+    fn decode(&mut self, message: Message) -> Result<Vec<Transcript>> {
+        let Message::Text(text) = message else {
+            return Ok(vec![]);
+        };
+        let Some((headers, body)) = text.split_once("\r\n\r\n") else {
+            warn!("invalid Azure speech message: {}", text);
+            return Ok(vec![]);
+        };
+        let path = headers
+            .lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.eq_ignore_ascii_case("path").then(|| value.trim())
+            })
+            .unwrap_or_default();
+        let times = |offset: Option<u64>, duration: Option<u64>| {
+            let start_ms = offset.map(|offset| offset / TICKS_PER_MS);
+            let end_ms = offset
+                .zip(duration)
+                .map(|(offset, duration)| (offset + duration) / TICKS_PER_MS);
+            (start_ms, end_ms)
+        };
+        let transcript = match path.to_ascii_lowercase().as_str() {
+            "speech.hypothesis" => {
+                let hypothesis: Hypothesis = serde_json::from_str(body)?;
+                let (start_ms, end_ms) = times(hypothesis.offset, hypothesis.duration);
+                Transcript {
+                    text: hypothesis.text,
+                    is_final: false,
+                    start_ms,
+                    end_ms,
+                    task_id: Some(self.request_id.clone()),
+                    ..Default::default()
+                }
+            }
+            "speech.phrase" => {
+                let phrase: Phrase = serde_json::from_str(body)?;
+                match phrase.recognition_status.as_str() {
+                    "Success" => {}
+                    "NoMatch" | "InitialSilenceTimeout" | "BabbleTimeout" | "EndOfDictation" => {
+                        return Ok(vec![]);
+                    }
+                    status => return Err(anyhow!("Azure speech recognition failed: {}", status)),
+                }
+                let (start_ms, end_ms) = times(phrase.offset, phrase.duration);
+                Transcript {
+                    text: phrase.display_text.unwrap_or_default(),
+                    is_final: true,
+                    confidence: phrase
+                        .n_best
+                        .as_ref()
+                        .and_then(|n_best| n_best.first())
+                        .and_then(|best| best.confidence),
+                    start_ms,
+                    end_ms,
+                    task_id: Some(self.request_id.clone()),
+                }
+            }
+            path => {
+                debug!("ignoring Azure speech message: {}", path);
+                return Ok(vec![]);
+            }
+        };
+        Ok(vec![transcript])
+    }
+}
+
+pub struct AzureAsrClientBuilder;
+
+impl AzureAsrClientBuilder {
+    pub fn create(
+        track_id: TrackId,
+        token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> TranscriptionClientFuture {
+        Box::pin(async move {
+            let protocol = AzureProtocol {
+                option: option.clone(),
+                request_id: Uuid::new_v4().simple().to_string().to_uppercase(),
+                wav_header_sent: false,
+            };
+            let client = WsAsrClient::spawn(protocol, option, track_id, token, event_sender);
+            Ok(Box::new(client) as Box<dyn TranscriptionClient>)
+        })
+    }
+}
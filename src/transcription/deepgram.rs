@@ -275,6 +275,26 @@ impl DeepgramAsrClientInner {
             if extra.map(|e| !e.contains_key("smart_format")).unwrap_or(true) {
                 query.append_pair("smart_format", "true");
             }
+            // Nova-3 takes key terms, older models boosted keywords
+            let model = extra
+                .and_then(|e| e.get("model").map(String::as_str))
+                .or(self.option.model_type.as_deref())
+                .unwrap_or("nova-3");
+            let (param, keyterms) = if model.starts_with("nova-3") {
+                ("keyterm", true)
+            } else {
+                ("keywords", false)
+            };
+            if extra.is_none_or(|e| !e.contains_key(param)) {
+                for hotword in self.option.hotwords.iter().flatten() {
+                    match hotword.boost() {
+                        Some(boost) if !keyterms => {
+                            query.append_pair(param, &format!("{}:{}", hotword.word(), boost))
+                        }
+                        _ => query.append_pair(param, hotword.word()),
+                    };
+                }
+            }
             if let Some(extra) = extra {
                 for (key, value) in extra {
                     query.append_pair(key, value);
@@ -435,6 +455,7 @@ impl DeepgramAsrClient {
                                             confidence: alternative.confidence,
                                             task_id,
                                             refer,
+                                            words: None,
                                         }
                                     } else {
                                         SessionEvent::AsrDelta {
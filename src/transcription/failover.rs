@@ -0,0 +1,695 @@
+//! Keeps a call transcribed when its recognizer drops. The audio since the
+//! last final is buffered; a provider that errors, stops or never answers
+//! speech after connecting is reconnected with backoff and the buffer
+//! replayed, then replaced by the next of its `fallbacks`. When the last one
+//! fails too, the first provider is tried again.
+use super::{TranscriptionClient, TranscriptionOption, handle_wait_for_answer_with_audio_drop};
+use crate::{
+    event::{EventReceiver, EventSender, SessionEvent, create_event_sender},
+    media::{Sample, SourcePacket, TrackId},
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+const DEFAULT_RESULT_TIMEOUT_MS: u64 = 10000;
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 8000;
+/// Longest audio kept for replay, older audio of a long utterance is lost
+const MAX_REPLAY_SECS: usize = 30;
+const REPLAY_CHUNK_MS: usize = 100;
+/// Frames above this RMS are speech a recognizer is expected to answer
+const VOICED_RMS: f64 = 500.0;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct AsrFailoverOption {
+    /// Milliseconds after speech without any event from a freshly connected
+    /// provider before it counts as stalled, default 10000
+    pub result_timeout: Option<u64>,
+    /// Reconnects to a failed provider before moving to the next, default 2
+    pub max_retries: Option<u32>,
+    /// Milliseconds before the first reconnect, doubled for each further
+    /// attempt, default 500
+    pub backoff: Option<u64>,
+}
+
+pub type AsrClientFactory = Arc<
+    dyn Fn(
+            TranscriptionOption,
+            CancellationToken,
+            EventSender,
+        ) -> Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>>
+        + Send
+        + Sync,
+>;
+
+pub struct FailoverAsrClient {
+    audio_tx: mpsc::UnboundedSender<Vec<Sample>>,
+}
+
+impl FailoverAsrClient {
+    /// Recognizes with `option` first and its `fallbacks` in order, each
+    /// created by `factory`
+    pub fn spawn(
+        track_id: TrackId,
+        cancel_token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+        factory: AsrClientFactory,
+    ) -> Self {
+        let (audio_tx, audio_rx) = mpsc::unbounded_channel();
+        let supervisor = Supervisor::new(track_id, cancel_token, option, event_sender, factory);
+        crate::spawn(supervisor.run(audio_rx));
+        Self { audio_tx }
+    }
+}
+
+impl TranscriptionClient for FailoverAsrClient {
+    fn send_audio(&self, samples: &[Sample], _src_packet: Option<&SourcePacket>) -> Result<()> {
+        self.audio_tx.send(samples.to_vec()).ok();
+        Ok(())
+    }
+}
+
+fn provider_options(mut primary: TranscriptionOption) -> Vec<TranscriptionOption> {
+    let fallbacks = primary.fallbacks.take().unwrap_or_default();
+    primary.failover = None;
+    let mut providers = vec![primary];
+    for mut option in fallbacks {
+        let primary = &providers[0];
+        option.fallbacks = None;
+        option.failover = None;
+        option.samplerate = primary.samplerate;
+        option.language = option.language.or_else(|| primary.language.clone());
+        option.hotwords = option.hotwords.or_else(|| primary.hotwords.clone());
+        option.check_default();
+        providers.push(option);
+    }
+    providers
+}
+
+fn is_voiced(samples: &[Sample]) -> bool {
+    if samples.is_empty() {
+        return false;
+    }
+    let energy: f64 = samples.iter().map(|&s| (s as f64) * (s as f64)).sum();
+    (energy / samples.len() as f64).sqrt() > VOICED_RMS
+}
+
+/// The recognizer in use, stopped when dropped
+struct Active {
+    client: Box<dyn TranscriptionClient>,
+    token: CancellationToken,
+    events: EventReceiver,
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+struct Supervisor {
+    track_id: TrackId,
+    cancel_token: CancellationToken,
+    event_sender: EventSender,
+    factory: AsrClientFactory,
+    providers: Vec<TranscriptionOption>,
+    start_when_answer: bool,
+    result_timeout: Duration,
+    max_retries: u32,
+    backoff: Duration,
+    samplerate: usize,
+    current: usize,
+    attempt: u32,
+    buffer: Vec<Sample>,
+    /// Whether the recognizer sent any event since it connected
+    answered: bool,
+    /// First speech sent to a recognizer that has not answered yet
+    waiting_since: Option<Instant>,
+    /// When the outage began and the provider that failed first
+    outage: Option<(Instant, String)>,
+}
+
+impl Supervisor {
+    fn new(
+        track_id: TrackId,
+        cancel_token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+        factory: AsrClientFactory,
+    ) -> Self {
+        let failover = option.failover.clone().unwrap_or_default();
+        let start_when_answer = option.start_when_answer.unwrap_or(false);
+        let mut providers = provider_options(option);
+        for option in providers.iter_mut() {
+            option.start_when_answer = None;
+        }
+        Self {
+            track_id,
+            cancel_token,
+            event_sender,
+            factory,
+            start_when_answer,
+            result_timeout: Duration::from_millis(
+                failover.result_timeout.unwrap_or(DEFAULT_RESULT_TIMEOUT_MS),
+            ),
+            max_retries: failover.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            backoff: Duration::from_millis(failover.backoff.unwrap_or(DEFAULT_BACKOFF_MS)),
+            samplerate: providers[0].samplerate.unwrap_or(16000) as usize,
+            providers,
+            current: 0,
+            attempt: 0,
+            buffer: Vec::new(),
+            answered: false,
+            waiting_since: None,
+            outage: None,
+        }
+    }
+
+    fn provider(&self) -> String {
+        match self.providers.get(self.current) {
+            Some(TranscriptionOption {
+                provider: Some(provider),
+                ..
+            }) => provider.to_string(),
+            _ => "unknown".to_string(),
+        }
+    }
+
+    async fn run(mut self, mut audio_rx: mpsc::UnboundedReceiver<Vec<Sample>>) {
+        if self.start_when_answer {
+            handle_wait_for_answer_with_audio_drop(
+                Some(self.event_sender.subscribe()),
+                &mut audio_rx,
+                &self.cancel_token,
+            )
+            .await;
+        }
+        let mut active: Option<Active> = None;
+        loop {
+            let current = match active {
+                Some(ref mut current) => current,
+                None => match self.connect(&mut audio_rx).await {
+                    Some(connected) => active.insert(connected),
+                    None => break,
+                },
+            };
+            let deadline = self.waiting_since.map(|since| since + self.result_timeout);
+            let failure = tokio::select! {
+                _ = self.cancel_token.cancelled() => break,
+                samples = audio_rx.recv() => match samples {
+                    Some(samples) => self
+                        .send(current.client.as_ref(), samples)
+                        .err()
+                        .map(|e| e.to_string()),
+                    None => break,
+                },
+                event = current.events.recv() => match event {
+                    Ok(event) => self.forward(event),
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(track_id = self.track_id, "ASR events lagged by {}", n);
+                        None
+                    }
+                    Err(RecvError::Closed) => Some("recognizer stopped".to_string()),
+                },
+                _ = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => Some(format!("no result within {}ms of speech", self.result_timeout.as_millis())),
+            };
+            if let Some(reason) = failure {
+                active = None;
+                self.fail(reason);
+            }
+        }
+    }
+
+    /// Creates the current provider's recognizer and replays the buffer,
+    /// moving on through retries and fallbacks, and back to the first
+    /// provider, until one works. Returns None only when the call ends
+    async fn connect(
+        &mut self,
+        audio_rx: &mut mpsc::UnboundedReceiver<Vec<Sample>>,
+    ) -> Option<Active> {
+        loop {
+            let mut delay = None;
+            if self.current >= self.providers.len() {
+                self.current = 0;
+                self.attempt = 0;
+                let provider = self.provider();
+                self.event_sender
+                    .send(SessionEvent::Error {
+                        track_id: self.track_id.clone(),
+                        timestamp: crate::media::get_timestamp(),
+                        sender: "AsrFailover".to_string(),
+                        error: format!("all ASR providers failed, retrying {}", provider),
+                        code: None,
+                    })
+                    .ok();
+                delay = Some(self.backoff * 2u32.saturating_pow(self.max_retries));
+            } else if self.attempt > 0 {
+                delay = Some(self.backoff * 2u32.saturating_pow(self.attempt - 1));
+            }
+            if let Some(delay) = delay {
+                let delay = delay.min(Duration::from_millis(MAX_BACKOFF_MS));
+                if !self.wait(delay, audio_rx).await {
+                    return None;
+                }
+            }
+            let option = self.providers[self.current].clone();
+            let token = self.cancel_token.child_token();
+            let sender = create_event_sender();
+            let events = sender.subscribe();
+            match (self.factory)(option, token.clone(), sender).await {
+                Ok(client) => {
+                    let active = Active {
+                        client,
+                        token,
+                        events,
+                    };
+                    match self.replay(active.client.as_ref()) {
+                        Ok(()) => {
+                            self.recovered();
+                            return Some(active);
+                        }
+                        Err(e) => self.fail(e.to_string()),
+                    }
+                }
+                Err(e) => {
+                    token.cancel();
+                    self.fail(e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Sleeps out a backoff, keeping the audio that arrives meanwhile for
+    /// replay. Returns false when the call ends
+    async fn wait(
+        &mut self,
+        delay: Duration,
+        audio_rx: &mut mpsc::UnboundedReceiver<Vec<Sample>>,
+    ) -> bool {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = self.cancel_token.cancelled() => return false,
+                _ = &mut sleep => return true,
+                samples = audio_rx.recv() => match samples {
+                    Some(samples) => self.keep(&samples),
+                    None => return false,
+                },
+            }
+        }
+    }
+
+    fn send(&mut self, client: &dyn TranscriptionClient, samples: Vec<Sample>) -> Result<()> {
+        if !self.answered && self.waiting_since.is_none() && is_voiced(&samples) {
+            self.waiting_since = Some(Instant::now());
+        }
+        client.send_audio(&samples, None)?;
+        self.keep(&samples);
+        Ok(())
+    }
+
+    fn keep(&mut self, samples: &[Sample]) {
+        self.buffer.extend_from_slice(samples);
+        let max = self.samplerate * MAX_REPLAY_SECS;
+        if self.buffer.len() > max {
+            self.buffer.drain(..self.buffer.len() - max);
+        }
+    }
+
+    fn replay(&mut self, client: &dyn TranscriptionClient) -> Result<()> {
+        let chunk = (self.samplerate * REPLAY_CHUNK_MS / 1000).max(1);
+        for samples in self.buffer.chunks(chunk) {
+            client.send_audio(samples, None)?;
+        }
+        self.answered = false;
+        self.waiting_since = self.buffer.chunks(chunk).any(is_voiced).then(Instant::now);
+        Ok(())
+    }
+
+    /// Passes a recognizer event on, returning why it failed if it did. Any
+    /// event shows the recognizer is alive, so noise or music it has nothing
+    /// to say about is not taken for a stall
+    fn forward(&mut self, event: SessionEvent) -> Option<String> {
+        self.answered = true;
+        self.waiting_since = None;
+        let failure = match &event {
+            SessionEvent::AsrDelta { .. } => {
+                self.attempt = 0;
+                None
+            }
+            SessionEvent::AsrFinal { .. } => {
+                self.attempt = 0;
+                self.buffer.clear();
+                None
+            }
+            SessionEvent::Error { error, .. } => Some(error.clone()),
+            _ => None,
+        };
+        self.event_sender.send(event).ok();
+        failure
+    }
+
+    fn fail(&mut self, reason: String) {
+        let provider = self.provider();
+        warn!(
+            track_id = self.track_id,
+            provider, reason, "ASR provider failed"
+        );
+        self.event_sender
+            .send(SessionEvent::Error {
+                track_id: self.track_id.clone(),
+                timestamp: crate::media::get_timestamp(),
+                sender: "AsrFailover".to_string(),
+                error: format!("ASR provider {} failed: {}", provider, reason),
+                code: None,
+            })
+            .ok();
+        self.outage.get_or_insert((Instant::now(), provider));
+        self.attempt += 1;
+        if self.attempt > self.max_retries {
+            self.current += 1;
+            self.attempt = 0;
+        }
+    }
+
+    fn recovered(&mut self) {
+        let Some((since, from)) = self.outage.take() else {
+            return;
+        };
+        let to = self.provider();
+        let replayed_ms = self.buffer.len() * 1000 / self.samplerate.max(1);
+        info!(
+            track_id = self.track_id,
+            from, to, replayed_ms, "ASR recognition resumed"
+        );
+        self.event_sender
+            .send(SessionEvent::Metrics {
+                timestamp: crate::media::get_timestamp(),
+                key: format!("failover.asr.{}", to),
+                duration: since.elapsed().as_millis() as u32,
+                data: serde_json::json!({
+                    "track_id": self.track_id,
+                    "from": from,
+                    "to": to,
+                    "attempt": self.attempt,
+                    "replayed_ms": replayed_ms,
+                }),
+            })
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcription::TranscriptionType;
+    use std::sync::Mutex;
+
+    type Created = Arc<Mutex<Vec<(String, EventSender, Arc<Mutex<Vec<Sample>>>)>>>;
+
+    struct FakeClient {
+        audio: Arc<Mutex<Vec<Sample>>>,
+    }
+
+    impl TranscriptionClient for FakeClient {
+        fn send_audio(&self, samples: &[Sample], _: Option<&SourcePacket>) -> Result<()> {
+            self.audio.lock().unwrap().extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    /// Fake recognizers, failing to connect for providers named "down"
+    fn factory(created: Created) -> AsrClientFactory {
+        Arc::new(move |option, _token, sender| {
+            let created = created.clone();
+            Box::pin(async move {
+                let provider = option.provider.unwrap().to_string();
+                anyhow::ensure!(provider != "down", "connection refused");
+                let audio = Arc::new(Mutex::new(Vec::new()));
+                created
+                    .lock()
+                    .unwrap()
+                    .push((provider, sender, audio.clone()));
+                Ok(Box::new(FakeClient { audio }) as Box<dyn TranscriptionClient>)
+            })
+        })
+    }
+
+    fn option(providers: &[&str], failover: AsrFailoverOption) -> TranscriptionOption {
+        let option = |name: &str| TranscriptionOption {
+            provider: Some(TranscriptionType::Other(name.to_string())),
+            samplerate: Some(16000),
+            ..Default::default()
+        };
+        TranscriptionOption {
+            fallbacks: Some(providers[1..].iter().map(|name| option(name)).collect()),
+            failover: Some(failover),
+            ..option(providers[0])
+        }
+    }
+
+    fn final_event(text: &str) -> SessionEvent {
+        SessionEvent::AsrFinal {
+            track_id: "track".to_string(),
+            timestamp: 0,
+            index: 0,
+            start_time: None,
+            end_time: None,
+            text: text.to_string(),
+            is_filler: None,
+            confidence: None,
+            task_id: None,
+            refer: None,
+            words: None,
+        }
+    }
+
+    async fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..300 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    fn audio(created: &Created, index: usize) -> Vec<Sample> {
+        match created.lock().unwrap().get(index) {
+            Some((_, _, audio)) => audio.lock().unwrap().clone(),
+            None => Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_switch_replays_since_final() {
+        let created: Created = Default::default();
+        let event_sender = create_event_sender();
+        let mut events = event_sender.subscribe();
+        let failover = AsrFailoverOption {
+            max_retries: Some(0),
+            ..Default::default()
+        };
+        let client = FailoverAsrClient::spawn(
+            "track".to_string(),
+            CancellationToken::new(),
+            option(&["primary", "down", "fallback"], failover),
+            event_sender,
+            factory(created.clone()),
+        );
+        eventually(|| created.lock().unwrap().len() == 1).await;
+        client.send_audio(&[1, 2], None).unwrap();
+        eventually(|| audio(&created, 0).len() == 2).await;
+        let primary = created.lock().unwrap()[0].1.clone();
+        primary.send(final_event("hello")).unwrap();
+        let mut seen = Vec::new();
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(3), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let is_final = matches!(event, SessionEvent::AsrFinal { .. });
+            seen.push(event);
+            if is_final {
+                break;
+            }
+        }
+        client.send_audio(&[3, 4], None).unwrap();
+        eventually(|| audio(&created, 0).len() == 4).await;
+        primary
+            .send(SessionEvent::Error {
+                track_id: "track".to_string(),
+                timestamp: 0,
+                sender: "primary".to_string(),
+                error: "socket closed".to_string(),
+                code: None,
+            })
+            .unwrap();
+        eventually(|| audio(&created, 1) == vec![3, 4]).await;
+        client.send_audio(&[5], None).unwrap();
+        eventually(|| audio(&created, 1) == vec![3, 4, 5]).await;
+        assert_eq!(created.lock().unwrap()[1].0, "fallback");
+
+        let mut failures = Vec::new();
+        let mut metrics = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            seen.push(event);
+        }
+        for event in seen {
+            match event {
+                SessionEvent::Error { sender, error, .. } if sender == "AsrFailover" => {
+                    failures.push(error)
+                }
+                SessionEvent::Metrics { key, data, .. } => metrics.push((key, data)),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            failures,
+            vec![
+                "ASR provider primary failed: socket closed",
+                "ASR provider down failed: connection refused",
+            ]
+        );
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].0, "failover.asr.fallback");
+        assert_eq!(metrics[0].1["from"], "primary");
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_when_stalled() {
+        let created: Created = Default::default();
+        let event_sender = create_event_sender();
+        let failover = AsrFailoverOption {
+            result_timeout: Some(50),
+            max_retries: Some(1),
+            backoff: Some(10),
+        };
+        let client = FailoverAsrClient::spawn(
+            "track".to_string(),
+            CancellationToken::new(),
+            option(&["primary"], failover),
+            event_sender,
+            factory(created.clone()),
+        );
+        eventually(|| created.lock().unwrap().len() == 1).await;
+        client.send_audio(&[0; 160], None).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(created.lock().unwrap().len(), 1, "silence is not a stall");
+
+        client.send_audio(&[4000; 160], None).unwrap();
+        eventually(|| audio(&created, 1).len() == 320).await;
+        assert_eq!(created.lock().unwrap()[1].0, "primary");
+    }
+
+    #[tokio::test]
+    async fn test_noise_after_answer_is_not_a_stall() {
+        let created: Created = Default::default();
+        let failover = AsrFailoverOption {
+            result_timeout: Some(50),
+            max_retries: Some(0),
+            backoff: Some(10),
+        };
+        let client = FailoverAsrClient::spawn(
+            "track".to_string(),
+            CancellationToken::new(),
+            option(&["primary", "fallback"], failover),
+            create_event_sender(),
+            factory(created.clone()),
+        );
+        eventually(|| created.lock().unwrap().len() == 1).await;
+        let primary = created.lock().unwrap()[0].1.clone();
+        primary
+            .send(SessionEvent::Metrics {
+                timestamp: 0,
+                key: "ttfb.asr.primary".to_string(),
+                duration: 0,
+                data: serde_json::json!({}),
+            })
+            .unwrap();
+        for _ in 0..10 {
+            client.send_audio(&[4000; 160], None).unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        eventually(|| audio(&created, 0).len() == 1600).await;
+        assert_eq!(created.lock().unwrap().len(), 1, "noise is not a stall");
+    }
+
+    #[tokio::test]
+    async fn test_starts_over_after_last_provider() {
+        let created: Created = Default::default();
+        let event_sender = create_event_sender();
+        let mut events = event_sender.subscribe();
+        let failover = AsrFailoverOption {
+            max_retries: Some(0),
+            backoff: Some(10),
+            ..Default::default()
+        };
+        let client = FailoverAsrClient::spawn(
+            "track".to_string(),
+            CancellationToken::new(),
+            option(&["primary", "down"], failover),
+            event_sender,
+            factory(created.clone()),
+        );
+        eventually(|| created.lock().unwrap().len() == 1).await;
+        let primary = created.lock().unwrap()[0].1.clone();
+        primary
+            .send(SessionEvent::Error {
+                track_id: "track".to_string(),
+                timestamp: 0,
+                sender: "primary".to_string(),
+                error: "socket closed".to_string(),
+                code: None,
+            })
+            .unwrap();
+        client.send_audio(&[1, 2], None).unwrap();
+        eventually(|| audio(&created, 1) == vec![1, 2]).await;
+        assert_eq!(created.lock().unwrap()[1].0, "primary");
+
+        let mut failures = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let SessionEvent::Error { sender, error, .. } = event
+                && sender == "AsrFailover"
+            {
+                failures.push(error);
+            }
+        }
+        assert_eq!(
+            failures,
+            vec![
+                "ASR provider primary failed: socket closed",
+                "ASR provider down failed: connection refused",
+                "all ASR providers failed, retrying primary",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fallbacks_inherit() {
+        let mut primary = option(&["primary", "fallback"], AsrFailoverOption::default());
+        primary.language = Some("en".to_string());
+        let providers = provider_options(primary);
+        assert_eq!(providers.len(), 2);
+        assert!(providers[0].fallbacks.is_none());
+        assert_eq!(providers[1].language.as_deref(), Some("en"));
+        assert_eq!(providers[1].samplerate, Some(16000));
+    }
+}
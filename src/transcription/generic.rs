@@ -0,0 +1,264 @@
+//! Adapter for streaming recognizers without a dedicated client, such as
+//! FunASR, Vosk or sherpa-onnx servers, driven by a declarative
+//! [`AsrTemplate`] in the transcription option.
+//!
+//! Start, audio and stop messages are minijinja templates rendered with the
+//! option, results are read from JSON text frames with JSON pointers.
+use super::{
+    TranscriptionClient, TranscriptionOption,
+    websocket::{Transcript, WsAsrClient, WsAsrProtocol, float32_bytes},
+};
+use crate::{
+    event::EventSender,
+    media::{Sample, TrackId},
+    synthesis::generic::truthy,
+};
+use anyhow::{Result, anyhow};
+use audio_codec::samples_to_bytes;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::HashMap, future::Future, pin::Pin};
+use tokio_tungstenite::tungstenite::{
+    Message, client::IntoClientRequest, handshake::client::Request, http::HeaderName,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+use uuid::Uuid;
+
+type TranscriptionClientFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>>;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioEncoding {
+    /// 16-bit little-endian PCM
+    #[default]
+    Pcm16,
+    /// 32-bit float little-endian PCM in [-1, 1]
+    Float32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    #[default]
+    #[serde(alias = "s")]
+    Seconds,
+    #[serde(alias = "ms")]
+    Milliseconds,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct AsrTemplate {
+    /// Server URL, `endpoint` when not set
+    pub url: Option<String>,
+    pub headers: HashMap<String, String>,
+    /// Message sent once connected
+    pub start_message: Option<String>,
+    /// Text message carrying base64 audio as `{{ audio }}`, audio is sent
+    /// as binary frames when not set
+    pub audio_message: Option<String>,
+    pub audio_encoding: AudioEncoding,
+    /// Message sent when the audio ends
+    pub stop_message: Option<String>,
+    /// JSON pointer to the text of results
+    pub text_pointer: String,
+    /// JSON pointer to partial text, for servers that send it in another field
+    pub partial_pointer: Option<String>,
+    /// JSON pointer to the field marking a final result, all results
+    /// at `text_pointer` are final when not set
+    pub final_pointer: Option<String>,
+    /// Value of `final_pointer` for final results, any truthy value when not set
+    pub final_value: Option<Value>,
+    pub confidence_pointer: Option<String>,
+    pub start_pointer: Option<String>,
+    pub end_pointer: Option<String>,
+    /// Unit of the start and end times
+    pub time_unit: TimeUnit,
+    /// JSON pointer to an error message
+    pub error_pointer: Option<String>,
+}
+
+struct GenericProtocol {
+    template: AsrTemplate,
+    option: TranscriptionOption,
+    uid: String,
+    env: minijinja::Environment<'static>,
+}
+
+impl GenericProtocol {
+    fn new(template: AsrTemplate, option: TranscriptionOption) -> Self {
+        Self {
+            template,
+            option,
+            uid: Uuid::new_v4().to_string(),
+            env: minijinja::Environment::new(),
+        }
+    }
+
+    fn render(&self, template: &str, audio: Option<&str>) -> Result<String> {
+        let hotwords: Vec<&str> = self
+            .option
+            .hotwords
+            .iter()
+            .flatten()
+            .map(|hotword| hotword.word())
+            .collect();
+        let context = json!({
+            "uid": self.uid,
+            "language": self.option.language,
+            "model": self.option.model_type,
+            "samplerate": self.option.samplerate.unwrap_or(16000),
+            "app_id": self.option.app_id,
+            "secret_id": self.option.secret_id,
+            "secret_key": self.option.secret_key,
+            "endpoint": self.option.endpoint,
+            "extra": self.option.extra,
+            "hotwords": hotwords,
+            "audio": audio,
+        });
+        self.env
+            .render_str(template, &context)
+            .map_err(|e| anyhow!("generic ASR: invalid template: {}", e))
+    }
+
+    fn time_ms(&self, value: &Value) -> Option<u64> {
+        let value = match value {
+            Value::Number(n) => n.as_f64()?,
+            Value::String(s) => s.parse().ok()?,
+            _ => return None,
+        };
+        let ms = match self.template.time_unit {
+            TimeUnit::Seconds => value * 1000.0,
+            TimeUnit::Milliseconds => value,
+        };
+        Some(ms.max(0.0) as u64)
+    }
+}
+
+impl WsAsrProtocol for GenericProtocol {
+    fn name(&self) -> &'static str {
+        "generic"
+    }
+
+    fn request(&self) -> Result<Request> {
+        let url = self
+            .template
+            .url
+            .as_ref()
+            .or(self.option.endpoint.as_ref())
+            .ok_or_else(|| anyhow!("generic ASR: missing url"))?;
+        let mut request = self.render(url, None)?.into_client_request()?;
+        for (key, value) in &self.template.headers {
+            request.headers_mut().insert(
+                HeaderName::from_bytes(key.as_bytes())?,
+                self.render(value, None)?.parse()?,
+            );
+        }
+        Ok(request)
+    }
+
+    fn start(&mut self) -> Result<Vec<Message>> {
+        match self.template.start_message.as_ref() {
+            Some(message) => Ok(vec![Message::text(self.render(message, None)?)]),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn audio(&mut self, samples: &[Sample]) -> Result<Vec<Message>> {
+        let audio = match self.template.audio_encoding {
+            AudioEncoding::Pcm16 => samples_to_bytes(samples),
+            AudioEncoding::Float32 => float32_bytes(samples),
+        };
+        let message = match self.template.audio_message.as_ref() {
+            Some(message) => Message::text(self.render(message, Some(&STANDARD.encode(audio)))?),
+            None => Message::binary(audio),
+        };
+        Ok(vec![message])
+    }
+
+    fn stop(&mut self) -> Vec<Message> {
+        let Some(message) = self.template.stop_message.as_ref() else {
+            return vec![];
+        };
+        match self.render(message, None) {
+            Ok(message) => vec![Message::text(message)],
+            Err(e) => {
+                warn!("{}", e);
+                vec![]
+            }
+        }
+    }
+
+    fn decode(&mut self, message: Message) -> Result<Vec<Transcript>> {
+        let Message::Text(text) = message else {
+            return Ok(vec![]);
+        };
+        let value: Value = match serde_json::from_str(&text) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("generic ASR: invalid message: {} {}", e, text);
+                return Ok(vec![]);
+            }
+        };
+        let pointer = |pointer: &Option<String>| pointer.as_deref().and_then(|p| value.pointer(p));
+        if let Some(error) = pointer(&self.template.error_pointer).filter(|e| truthy(e)) {
+            return Err(anyhow!("generic ASR: {}", error));
+        }
+        let string = |value: Option<&Value>| match value {
+            Some(Value::String(s)) if !s.trim().is_empty() => Some(s.clone()),
+            _ => None,
+        };
+        let (text, is_final) = match string(value.pointer(&self.template.text_pointer)) {
+            Some(text) => {
+                let is_final = match (
+                    pointer(&self.template.final_pointer),
+                    &self.template.final_value,
+                ) {
+                    (Some(value), Some(expected)) => value == expected,
+                    (Some(value), None) => truthy(value),
+                    (None, _) => self.template.final_pointer.is_none(),
+                };
+                (text, is_final)
+            }
+            None => match string(pointer(&self.template.partial_pointer)) {
+                Some(text) => (text, false),
+                None => return Ok(vec![]),
+            },
+        };
+        Ok(vec![Transcript {
+            text,
+            is_final,
+            confidence: pointer(&self.template.confidence_pointer)
+                .and_then(Value::as_f64)
+                .map(|c| c as f32),
+            start_ms: pointer(&self.template.start_pointer).and_then(|v| self.time_ms(v)),
+            end_ms: pointer(&self.template.end_pointer).and_then(|v| self.time_ms(v)),
+            task_id: Some(self.uid.clone()),
+        }])
+    }
+}
+
+pub struct GenericAsrClientBuilder;
+
+impl GenericAsrClientBuilder {
+    pub fn create(
+        track_id: TrackId,
+        token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> TranscriptionClientFuture {
+        Box::pin(async move {
+            let template = option
+                .template
+                .clone()
+                .ok_or_else(|| anyhow!("generic ASR: missing template"))?;
+            let protocol = GenericProtocol::new(template, option.clone());
+            let client = WsAsrClient::spawn(protocol, option, track_id, token, event_sender);
+            Ok(Box::new(client) as Box<dyn TranscriptionClient>)
+        })
+    }
+}
@@ -11,26 +11,48 @@ use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
 mod aliyun;
+mod azure;
 mod deepgram;
+mod failover;
+pub mod generic;
+mod openai;
 mod tencent_cloud;
+mod vocabulary;
+mod websocket;
+mod whisper;
 
+#[cfg(feature = "offline")]
+mod offline;
 #[cfg(feature = "offline")]
 mod sensevoice;
+#[cfg(feature = "offline")]
+mod whisper_offline;
 
 pub use aliyun::AliyunAsrClient;
 pub use aliyun::AliyunAsrClientBuilder;
+pub use azure::AzureAsrClientBuilder;
 pub use deepgram::DeepgramAsrClient;
 pub use deepgram::DeepgramAsrClientBuilder;
+pub use failover::{AsrClientFactory, AsrFailoverOption, FailoverAsrClient};
+pub use generic::{AsrTemplate, GenericAsrClientBuilder};
+pub use openai::OpenAiAsrClientBuilder;
 pub use tencent_cloud::TencentCloudAsrClient;
 pub use tencent_cloud::TencentCloudAsrClientBuilder;
+pub use vocabulary::{Hotword, Replacer};
+pub use websocket::WsAsrClient;
+pub use whisper::WhisperAsrClientBuilder;
 
+#[cfg(feature = "offline")]
+pub use offline::OfflineAsrClient;
 #[cfg(feature = "offline")]
 pub use sensevoice::{SensevoiceAsrClient, SensevoiceAsrClientBuilder};
+#[cfg(feature = "offline")]
+pub use whisper_offline::WhisperOfflineAsrClientBuilder;
 
 /// Common helper function for handling wait_for_answer logic with audio dropping
-pub async fn handle_wait_for_answer_with_audio_drop(
+pub async fn handle_wait_for_answer_with_audio_drop<T>(
     event_rx: Option<crate::event::EventReceiver>,
-    audio_rx: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+    audio_rx: &mut mpsc::UnboundedReceiver<T>,
     token: &CancellationToken,
 ) {
     tokio::select! {
@@ -64,9 +86,18 @@ pub enum TranscriptionType {
     Aliyun,
     #[serde(rename = "deepgram")]
     Deepgram,
+    #[serde(rename = "openai")]
+    OpenAi,
+    #[serde(rename = "azure")]
+    Azure,
+    #[serde(rename = "whisper")]
+    Whisper,
     #[cfg(feature = "offline")]
     #[serde(rename = "sensevoice")]
     Sensevoice,
+    #[cfg(feature = "offline")]
+    #[serde(rename = "whisper_offline")]
+    WhisperOffline,
     Other(String),
 }
 
@@ -87,6 +118,17 @@ pub struct TranscriptionOption {
     pub endpoint: Option<String>,
     pub extra: Option<HashMap<String, String>>,
     pub start_when_answer: Option<bool>,
+    /// Words the recognizer should favor, sent to providers that support it
+    pub hotwords: Option<Vec<Hotword>>,
+    /// Corrections applied to recognized text, e.g. "active coal" to "active-call"
+    pub replacements: Option<HashMap<String, String>>,
+    /// Message template for a WebSocket recognizer without a built-in
+    /// client, see [`generic`]
+    pub template: Option<AsrTemplate>,
+    /// Providers to switch to, in order, when this one keeps failing
+    pub fallbacks: Option<Vec<TranscriptionOption>>,
+    /// Reconnect and switch timing, see [`failover`]
+    pub failover: Option<AsrFailoverOption>,
     #[serde(skip)]
     pub refer: Option<bool>,
 }
@@ -97,8 +139,13 @@ impl std::fmt::Display for TranscriptionType {
             TranscriptionType::TencentCloud => write!(f, "tencent"),
             TranscriptionType::Aliyun => write!(f, "aliyun"),
             TranscriptionType::Deepgram => write!(f, "deepgram"),
+            TranscriptionType::OpenAi => write!(f, "openai"),
+            TranscriptionType::Azure => write!(f, "azure"),
+            TranscriptionType::Whisper => write!(f, "whisper"),
             #[cfg(feature = "offline")]
             TranscriptionType::Sensevoice => write!(f, "sensevoice"),
+            #[cfg(feature = "offline")]
+            TranscriptionType::WhisperOffline => write!(f, "whisper_offline"),
             TranscriptionType::Other(provider) => write!(f, "{}", provider),
         }
     }
@@ -114,8 +161,13 @@ impl<'de> Deserialize<'de> for TranscriptionType {
             "tencent" => Ok(TranscriptionType::TencentCloud),
             "aliyun" => Ok(TranscriptionType::Aliyun),
             "deepgram" => Ok(TranscriptionType::Deepgram),
+            "openai" => Ok(TranscriptionType::OpenAi),
+            "azure" => Ok(TranscriptionType::Azure),
+            "whisper" => Ok(TranscriptionType::Whisper),
             #[cfg(feature = "offline")]
             "sensevoice" => Ok(TranscriptionType::Sensevoice),
+            #[cfg(feature = "offline")]
+            "whisper_offline" => Ok(TranscriptionType::WhisperOffline),
             _ => Ok(TranscriptionType::Other(value)),
         }
     }
@@ -145,8 +197,40 @@ impl TranscriptionOption {
                     self.secret_key = std::env::var("DEEPGRAM_API_KEY").ok();
                 }
             }
+            Some(TranscriptionType::OpenAi) => {
+                self.secret_key = self
+                    .secret_key
+                    .take()
+                    .or_else(|| std::env::var("OPENAI_API_KEY").ok());
+            }
+            Some(TranscriptionType::Azure) => {
+                self.secret_key = self
+                    .secret_key
+                    .take()
+                    .or_else(|| std::env::var("AZURE_SPEECH_KEY").ok());
+                if let Ok(region) = std::env::var("AZURE_SPEECH_REGION") {
+                    self.extra
+                        .get_or_insert_default()
+                        .entry("region".to_string())
+                        .or_insert(region);
+                }
+            }
+            Some(TranscriptionType::Whisper) => {
+                self.endpoint = self
+                    .endpoint
+                    .take()
+                    .or_else(|| std::env::var("WHISPER_ENDPOINT").ok());
+            }
             _ => {}
         }
+        for fallback in self.fallbacks.iter_mut().flatten() {
+            fallback.check_default();
+        }
+    }
+
+    /// Whether recognition goes through a [`FailoverAsrClient`]
+    pub fn has_failover(&self) -> bool {
+        self.failover.is_some() || self.fallbacks.as_ref().is_some_and(|f| !f.is_empty())
     }
 }
 pub type TranscriptionSender = mpsc::UnboundedSender<AudioFrame>;
@@ -0,0 +1,566 @@
+//! Streaming runner shared by the offline recognizers: audio is resampled
+//! to 16kHz, cut into utterances with the Silero VAD, and each utterance is
+//! decoded by an [`OfflineRecognizer`]. The open utterance can be
+//! re-decoded on a cadence for partial results, and is force-endpointed
+//! once it reaches the maximum segment length.
+use crate::event::{AsrWord, EventSender, SessionEvent};
+use crate::media::SourcePacket;
+use crate::media::vad::{TinySilero, VADOption, VadEngine};
+use crate::media::{AudioFrame, INTERNAL_SAMPLERATE, Sample, Samples, TrackId};
+use crate::offline::Recognition;
+use crate::transcription::{TranscriptionClient, TranscriptionOption};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use audio_codec::BoxedResampler;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+// TinySilero decides on 512-sample chunks
+const VAD_CHUNK_SAMPLES: usize = 512;
+const DEFAULT_PARTIAL_INTERVAL_MS: u64 = 500;
+
+/// Decodes one utterance of 16kHz audio.
+#[async_trait]
+pub(super) trait OfflineRecognizer: Send + 'static {
+    fn name(&self) -> &'static str;
+    async fn recognize(&mut self, samples: &[i16]) -> Option<Recognition>;
+}
+
+pub struct OfflineAsrClient {
+    audio_tx: mpsc::UnboundedSender<Vec<Sample>>,
+}
+
+impl OfflineAsrClient {
+    /// Starts decoding the audio sent to the client with `recognizer`.
+    /// Segments are cut at `max_segment_ms` at most.
+    pub(super) fn spawn<R: OfflineRecognizer>(
+        recognizer: R,
+        track_id: TrackId,
+        option: &TranscriptionOption,
+        event_sender: EventSender,
+        max_segment_ms: Option<u64>,
+    ) -> Self {
+        let (audio_tx, audio_rx) = mpsc::unbounded_channel::<Vec<Sample>>();
+        let mut config = StreamConfig::from_option(option);
+        if let Some(max_segment_ms) = max_segment_ms {
+            config.max_segment_ms = config.max_segment_ms.min(max_segment_ms);
+        }
+        let stream = Stream {
+            recognizer,
+            track_id,
+            event_sender,
+            refer: option.refer,
+            begin_time: crate::media::get_timestamp(),
+            index: 0,
+            last_partial: String::new(),
+        };
+        // Default input sample rate to 8000Hz (standard SIP) if not specified
+        let input_rate = option.samplerate.unwrap_or(8000);
+        tokio::spawn(stream.run(audio_rx, input_rate, config));
+        Self { audio_tx }
+    }
+}
+
+impl TranscriptionClient for OfflineAsrClient {
+    fn send_audio(&self, samples: &[Sample], _src_packet: Option<&SourcePacket>) -> Result<()> {
+        self.audio_tx
+            .send(samples.to_vec())
+            .map_err(|_| anyhow!("Failed to send audio data"))?;
+        Ok(())
+    }
+}
+
+/// Segmentation settings, read from `extra`.
+#[derive(Debug, Clone)]
+struct StreamConfig {
+    vad: VADOption,
+    /// Re-decode the open segment on this cadence to emit partials,
+    /// finals only when not set
+    partial_interval_ms: Option<u64>,
+    /// Force an endpoint when a segment reaches this length
+    max_segment_ms: u64,
+}
+
+impl StreamConfig {
+    fn from_option(option: &TranscriptionOption) -> Self {
+        let mut vad = VADOption {
+            samplerate: INTERNAL_SAMPLERATE,
+            ..Default::default()
+        };
+        let extra = option.extra.as_ref();
+        let get = |key: &str| extra.and_then(|e| e.get(key));
+        if let Some(v) = get("vad_voice_threshold").and_then(|s| s.parse::<f32>().ok()) {
+            vad.voice_threshold = v;
+        }
+        if let Some(v) = get("vad_speech_padding").and_then(|s| s.parse::<u64>().ok()) {
+            vad.speech_padding = v;
+        }
+        if let Some(v) = get("vad_silence_padding").and_then(|s| s.parse::<u64>().ok()) {
+            vad.silence_padding = v;
+        }
+        if let Some(v) = get("vad_max_buffer_duration_secs").and_then(|s| s.parse::<u64>().ok()) {
+            vad.max_buffer_duration_secs = v;
+        }
+        let partial_interval_ms = get("partial_interval_ms")
+            .and_then(|s| s.parse::<u64>().ok())
+            .or_else(|| {
+                get("streaming")
+                    .filter(|s| s.as_str() == "true")
+                    .map(|_| DEFAULT_PARTIAL_INTERVAL_MS)
+            })
+            .filter(|ms| *ms > 0);
+        let max_segment_ms = get("max_segment_ms")
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(vad.max_buffer_duration_secs * 1000);
+        Self {
+            vad,
+            partial_interval_ms,
+            max_segment_ms,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decode {
+    /// Re-decode the open segment for a partial result
+    Partial,
+    /// The segment ended, take it for the final result
+    Final,
+}
+
+/// Cuts the stream into utterances from VAD decisions. The open segment
+/// starts a little before the speech onset and ends after enough silence,
+/// or when it reaches the maximum length.
+struct Segmenter {
+    min_silence_samples: usize,
+    min_speech_samples: usize,
+    preroll_samples: usize,
+    max_segment_samples: usize,
+    partial_interval_samples: Option<usize>,
+    buffer: Vec<i16>,
+    /// Offset of the buffer from the start of the stream
+    start: usize,
+    speaking: bool,
+    speech_samples: usize,
+    silence_samples: usize,
+    /// Buffer length at the last partial
+    decoded_len: usize,
+}
+
+impl Segmenter {
+    fn new(config: &StreamConfig, sample_rate: usize) -> Self {
+        let samples = |ms: u64| (ms as usize * sample_rate) / 1000;
+        Self {
+            min_silence_samples: samples(config.vad.silence_padding),
+            min_speech_samples: samples(config.vad.speech_padding),
+            preroll_samples: samples(config.vad.speech_padding),
+            max_segment_samples: samples(config.max_segment_ms).max(sample_rate),
+            partial_interval_samples: config.partial_interval_ms.map(samples),
+            buffer: Vec::with_capacity(sample_rate * 10),
+            start: 0,
+            speaking: false,
+            speech_samples: 0,
+            silence_samples: 0,
+            decoded_len: 0,
+        }
+    }
+
+    fn push(&mut self, samples: &[i16], voice: &[bool]) -> Option<Decode> {
+        self.buffer.extend_from_slice(samples);
+        for is_voice in voice {
+            if *is_voice {
+                self.speaking = true;
+                self.speech_samples += VAD_CHUNK_SAMPLES;
+                self.silence_samples = 0;
+            } else if self.speaking {
+                self.silence_samples += VAD_CHUNK_SAMPLES;
+            }
+        }
+
+        if !self.speaking {
+            // keep a little audio before the speech onset
+            if self.buffer.len() > self.preroll_samples {
+                let excess = self.buffer.len() - self.preroll_samples;
+                self.buffer.drain(..excess);
+                self.start += excess;
+            }
+            return None;
+        }
+        if self.silence_samples >= self.min_silence_samples {
+            if self.speech_samples < self.min_speech_samples {
+                // too short to be speech, wait for the next onset
+                self.speaking = false;
+                self.speech_samples = 0;
+                self.silence_samples = 0;
+                self.decoded_len = 0;
+                return None;
+            }
+            return Some(Decode::Final);
+        }
+        if self.buffer.len() >= self.max_segment_samples {
+            return Some(Decode::Final);
+        }
+        let interval = self.partial_interval_samples?;
+        if self.buffer.len() - self.decoded_len >= interval {
+            self.decoded_len = self.buffer.len();
+            return Some(Decode::Partial);
+        }
+        None
+    }
+
+    /// Decodes what is left once the audio ends
+    fn finish(&mut self) -> Option<Decode> {
+        (self.speaking && self.speech_samples >= self.min_speech_samples).then_some(Decode::Final)
+    }
+
+    fn window(&self) -> &[i16] {
+        &self.buffer
+    }
+
+    /// Takes the open segment and its offset. A forced endpoint leaves the
+    /// speaker talking, so the next segment starts right away.
+    fn take(&mut self) -> (Vec<i16>, usize) {
+        let start = self.start;
+        let segment = std::mem::take(&mut self.buffer);
+        self.start += segment.len();
+        self.decoded_len = 0;
+        if self.silence_samples >= self.min_silence_samples {
+            self.speaking = false;
+            self.silence_samples = 0;
+            self.speech_samples = 0;
+        }
+        (segment, start)
+    }
+}
+
+struct Stream<R> {
+    recognizer: R,
+    track_id: TrackId,
+    event_sender: EventSender,
+    refer: Option<bool>,
+    begin_time: u64,
+    index: u32,
+    last_partial: String,
+}
+
+impl<R: OfflineRecognizer> Stream<R> {
+    fn time(&self, samples: usize) -> u64 {
+        self.begin_time + (samples * 1000 / INTERNAL_SAMPLERATE as usize) as u64
+    }
+
+    fn send(&self, event: SessionEvent) {
+        if let Err(e) = self.event_sender.send(event) {
+            warn!(error = %e, "Failed to send transcription event");
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut audio_rx: mpsc::UnboundedReceiver<Vec<Sample>>,
+        input_rate: u32,
+        config: StreamConfig,
+    ) {
+        let sample_rate = INTERNAL_SAMPLERATE as usize;
+        let mut resampler = if input_rate != INTERNAL_SAMPLERATE {
+            BoxedResampler::new(input_rate as usize, sample_rate).ok()
+        } else {
+            None
+        };
+        let mut vad = match TinySilero::new(config.vad.clone()) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(error = %e, "Failed to create TinySilero VAD");
+                return;
+            }
+        };
+        let mut segmenter = Segmenter::new(&config, sample_rate);
+        let mut position = 0;
+        let name = self.recognizer.name();
+        debug!(track_id = %self.track_id, ?config, "{} processing loop started", name);
+
+        loop {
+            let samples = audio_rx.recv().await;
+            let ended = samples.is_none();
+            let decode = match samples {
+                Some(samples) => {
+                    let samples = match resampler.as_mut() {
+                        Some(resampler) => resampler.resample(&samples),
+                        None => samples,
+                    };
+                    if samples.is_empty() {
+                        continue;
+                    }
+                    let mut frame = AudioFrame {
+                        track_id: self.track_id.clone(),
+                        samples: Samples::PCM {
+                            samples: samples.clone(),
+                        },
+                        timestamp: self.time(position),
+                        sample_rate: sample_rate as u32,
+                        channels: 1,
+                        ..Default::default()
+                    };
+                    let voice: Vec<bool> = vad
+                        .process(&mut frame)
+                        .into_iter()
+                        .map(|(is_voice, _)| is_voice)
+                        .collect();
+                    position += samples.len();
+                    segmenter.push(&samples, &voice)
+                }
+                None => segmenter.finish(),
+            };
+
+            match decode {
+                Some(Decode::Partial) => {
+                    let start = segmenter.start;
+                    self.partial(segmenter.window(), start, position).await;
+                }
+                Some(Decode::Final) => {
+                    let (segment, start) = segmenter.take();
+                    self.final_result(&segment, start).await;
+                }
+                None => {}
+            }
+            if ended {
+                break;
+            }
+        }
+        debug!(track_id = %self.track_id, "{} processing loop ended", name);
+    }
+
+    async fn partial(&mut self, window: &[i16], start: usize, end: usize) {
+        let Some(recognition) = self.recognizer.recognize(window).await else {
+            return;
+        };
+        if recognition.text.is_empty() || recognition.text == self.last_partial {
+            return;
+        }
+        self.last_partial = recognition.text.clone();
+        self.send(SessionEvent::AsrDelta {
+            track_id: self.track_id.clone(),
+            index: self.index,
+            text: recognition.text,
+            timestamp: crate::media::get_timestamp(),
+            start_time: Some(self.time(start)),
+            end_time: Some(self.time(end)),
+            is_filler: None,
+            confidence: None,
+            task_id: None,
+            refer: self.refer,
+        });
+    }
+
+    async fn final_result(&mut self, segment: &[i16], start: usize) {
+        self.last_partial.clear();
+        let start_time = self.time(start);
+        let end_time = Some(self.time(start + segment.len()));
+        let started_at = std::time::Instant::now();
+        let Some(recognition) = self.recognizer.recognize(segment).await else {
+            return;
+        };
+        info!(track_id = %self.track_id, text = %recognition.text, language = ?recognition.language,
+             emotion = ?recognition.emotion, event = ?recognition.audio_event,
+             elapsed_ms = %started_at.elapsed().as_millis(), "{} transcription", self.recognizer.name());
+
+        let has_text = !recognition.text.is_empty();
+        let has_sound = recognition
+            .audio_event
+            .as_deref()
+            .is_some_and(|event| event != "speech");
+        if !has_text && !has_sound {
+            return;
+        }
+        let timestamp = crate::media::get_timestamp();
+        if recognition.language.is_some()
+            || recognition.emotion.is_some()
+            || recognition.audio_event.is_some()
+        {
+            self.send(SessionEvent::AsrTags {
+                track_id: self.track_id.clone(),
+                timestamp,
+                index: self.index,
+                start_time: Some(start_time),
+                end_time,
+                language: recognition.language,
+                emotion: recognition.emotion,
+                audio_event: recognition.audio_event,
+                refer: self.refer,
+            });
+        }
+        if has_text {
+            let words = recognition
+                .words
+                .into_iter()
+                .map(|word| AsrWord {
+                    word: word.word,
+                    start_time: start_time + word.start_ms,
+                    end_time: start_time + word.end_ms,
+                    confidence: word.confidence,
+                })
+                .collect::<Vec<_>>();
+            self.send(SessionEvent::AsrFinal {
+                track_id: self.track_id.clone(),
+                index: self.index,
+                text: recognition.text,
+                timestamp,
+                start_time: Some(start_time),
+                end_time,
+                is_filler: None,
+                confidence: Some(1.0),
+                task_id: None,
+                refer: self.refer,
+                words: (!words.is_empty()).then_some(words),
+            });
+        }
+        self.index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK: usize = VAD_CHUNK_SAMPLES;
+
+    fn config(partial_interval_ms: Option<u64>, max_segment_ms: u64) -> StreamConfig {
+        StreamConfig {
+            vad: VADOption {
+                speech_padding: 64,
+                silence_padding: 96,
+                ..Default::default()
+            },
+            partial_interval_ms,
+            max_segment_ms,
+        }
+    }
+
+    // one 32ms VAD chunk of audio
+    fn push(segmenter: &mut Segmenter, is_voice: bool) -> Option<Decode> {
+        segmenter.push(&[1; CHUNK], &[is_voice])
+    }
+
+    #[test]
+    fn test_segmenter_endpoints_after_silence() {
+        let mut segmenter = Segmenter::new(&config(None, 10_000), 16000);
+        for _ in 0..10 {
+            assert_eq!(push(&mut segmenter, false), None);
+        }
+        // leading silence is trimmed to the preroll
+        assert_eq!(segmenter.window().len(), 1024);
+        for _ in 0..4 {
+            assert_eq!(push(&mut segmenter, true), None);
+        }
+        assert_eq!(push(&mut segmenter, false), None);
+        assert_eq!(push(&mut segmenter, false), None);
+        assert_eq!(push(&mut segmenter, false), Some(Decode::Final));
+
+        let (segment, start) = segmenter.take();
+        assert_eq!(start, 8 * CHUNK);
+        assert_eq!(segment.len(), 9 * CHUNK);
+        assert_eq!(segmenter.finish(), None);
+        assert_eq!(push(&mut segmenter, false), None);
+    }
+
+    #[test]
+    fn test_segmenter_ignores_blips() {
+        let mut segmenter = Segmenter::new(&config(None, 10_000), 16000);
+        assert_eq!(push(&mut segmenter, true), None);
+        for _ in 0..3 {
+            assert_eq!(push(&mut segmenter, false), None);
+        }
+        assert_eq!(segmenter.finish(), None);
+    }
+
+    #[test]
+    fn test_segmenter_partials_and_forced_endpoint() {
+        // partials every 64ms, segments of at most 1s
+        let mut segmenter = Segmenter::new(&config(Some(64), 1000), 16000);
+        let mut decodes = Vec::new();
+        while decodes.len() < 40 {
+            let decode = push(&mut segmenter, true);
+            decodes.push(decode);
+            if decode == Some(Decode::Final) {
+                break;
+            }
+        }
+        // 16000 samples reached on the 32nd chunk, with a partial every 2
+        assert_eq!(decodes.len(), 32);
+        assert_eq!(decodes[31], Some(Decode::Final));
+        let partials = decodes
+            .iter()
+            .filter(|d| **d == Some(Decode::Partial))
+            .count();
+        assert_eq!(partials, 15);
+        let (segment, start) = segmenter.take();
+        assert_eq!((segment.len(), start), (32 * CHUNK, 0));
+        // still speaking: the next segment continues right away
+        assert_eq!(push(&mut segmenter, true), None);
+        assert_eq!(push(&mut segmenter, true), Some(Decode::Partial));
+        assert_eq!(segmenter.finish(), Some(Decode::Final));
+    }
+
+    struct Fixed(Recognition);
+
+    #[async_trait]
+    impl OfflineRecognizer for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn recognize(&mut self, _samples: &[i16]) -> Option<Recognition> {
+            Some(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_final_result_events() {
+        let event_sender = crate::event::create_event_sender();
+        let mut receiver = event_sender.subscribe();
+        let recognition = Recognition {
+            text: "hello world".to_string(),
+            language: Some("en".to_string()),
+            words: vec![crate::offline::RecognizedWord {
+                word: "hello".to_string(),
+                start_ms: 100,
+                end_ms: 400,
+                confidence: Some(0.9),
+            }],
+            ..Default::default()
+        };
+        let mut stream = Stream {
+            recognizer: Fixed(recognition),
+            track_id: "track".to_string(),
+            event_sender,
+            refer: None,
+            begin_time: 1000,
+            index: 0,
+            last_partial: String::new(),
+        };
+        // one second of audio, two seconds into the stream
+        stream.final_result(&[0; 16000], 32000).await;
+
+        match receiver.recv().await.unwrap() {
+            SessionEvent::AsrTags {
+                language, index, ..
+            } => assert_eq!((language.as_deref(), index), (Some("en"), 0)),
+            event => panic!("unexpected event {:?}", event),
+        }
+        match receiver.recv().await.unwrap() {
+            SessionEvent::AsrFinal {
+                text,
+                start_time,
+                end_time,
+                words,
+                ..
+            } => {
+                assert_eq!(text, "hello world");
+                assert_eq!((start_time, end_time), (Some(3000), Some(4000)));
+                let word = &words.unwrap()[0];
+                assert_eq!((word.start_time, word.end_time), (3100, 3400));
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(stream.index, 1);
+    }
+}
@@ -0,0 +1,235 @@
+use super::{
+    TranscriptionClient, TranscriptionOption,
+    websocket::{Transcript, WsAsrClient, WsAsrProtocol},
+};
+use crate::{
+    event::EventSender,
+    media::{Sample, TrackId},
+};
+use anyhow::{Result, anyhow};
+use audio_codec::{BoxedResampler, samples_to_bytes};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::HashMap, future::Future, pin::Pin};
+use tokio_tungstenite::tungstenite::{
+    Message, client::IntoClientRequest, handshake::client::Request,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+type TranscriptionClientFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>>;
+
+const OPENAI_REALTIME_URL: &str = "wss://api.openai.com/v1/realtime?intent=transcription";
+// realtime sessions take 24kHz 16-bit mono
+const OPENAI_SAMPLE_RATE: usize = 24000;
+
+// https://platform.openai.com/docs/guides/realtime-transcription
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ServerEvent {
+    #[serde(rename = "input_audio_buffer.speech_started")]
+    SpeechStarted {
+        item_id: String,
+        audio_start_ms: u64,
+    },
+    #[serde(rename = "input_audio_buffer.speech_stopped")]
+    SpeechStopped { item_id: String, audio_end_ms: u64 },
+    #[serde(rename = "conversation.item.input_audio_transcription.delta")]
+    Delta { item_id: String, delta: String },
+    #[serde(rename = "conversation.item.input_audio_transcription.completed")]
+    Completed { item_id: String, transcript: String },
+    #[serde(rename = "error")]
+    Error { error: ServerError },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerError {
+    message: String,
+}
+
+#[derive(Default)]
+struct Item {
+    text: String,
+    start_ms: Option<u64>,
+    end_ms: Option<u64>,
+}
+
+struct OpenAiProtocol {
+    option: TranscriptionOption,
+    resampler: Option<BoxedResampler>,
+    // speech turns by item id, until their transcript completes
+    items: HashMap<String, Item>,
+}
+
+impl WsAsrProtocol for OpenAiProtocol {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn request(&self) -> Result<Request> {
+        let api_key = self
+            .option
+            .secret_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("No OPENAI_API_KEY provided"))?;
+        let url = self
+            .option
+            .endpoint
+            .as_deref()
+            .unwrap_or(OPENAI_REALTIME_URL);
+        let mut request = url.into_client_request()?;
+        let headers = request.headers_mut();
+        headers.insert("Authorization", format!("Bearer {}", api_key).parse()?);
+        headers.insert("OpenAI-Beta", "realtime=v1".parse()?);
+        Ok(request)
+    }
+
+    fn start(&mut self) -> Result<Vec<Message>> {
+        let extra = self.option.extra.as_ref();
+        let get = |key: &str| extra.and_then(|e| e.get(key));
+        // hotwords have no dedicated field, the prompt biases spelling
+        let prompt = get("prompt").cloned().or_else(|| {
+            let words: Vec<&str> = self
+                .option
+                .hotwords
+                .iter()
+                .flatten()
+                .map(|hotword| hotword.word())
+                .collect();
+            (!words.is_empty()).then(|| words.join(", "))
+        });
+        let silence_duration_ms: u64 = get("silence_duration_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(500);
+        let language = self
+            .option
+            .language
+            .as_deref()
+            .filter(|language| *language != "auto")
+            .map(|language| language.split(['-', '_']).next().unwrap_or(language));
+        let session = json!({
+            "type": "transcription_session.update",
+            "session": {
+                "input_audio_format": "pcm16",
+                "input_audio_transcription": {
+                    "model": self.option.model_type.as_deref().unwrap_or("gpt-4o-transcribe"),
+                    "language": language,
+                    "prompt": prompt.unwrap_or_default(),
+                },
+                "turn_detection": {
+                    "type": "server_vad",
+                    "silence_duration_ms": silence_duration_ms,
+                },
+                "input_audio_noise_reduction": get("noise_reduction").map(|t| json!({ "type": t })),
+            }
+        });
+        Ok(vec![Message::text(session.to_string())])
+    }
+
+    fn audio(&mut self, samples: &[Sample]) -> Result<Vec<Message>> {
+        let samples = match self.resampler.as_mut() {
+            Some(resampler) => resampler.resample(samples),
+            None => samples.to_vec(),
+        };
+        let audio = STANDARD.encode(samples_to_bytes(&samples));
+        let append = json!({ "type": "input_audio_buffer.append", "audio": audio });
+        Ok(vec![Message::text(append.to_string())])
+    }
+
+    // flush the turn still open when the audio ends
+    fn stop(&mut self) -> Vec<Message> {
+        let commit = json!({ "type": "input_audio_buffer.commit" });
+        vec![Message::text(commit.to_string())]
+    }
+
+    fn decode(&mut self, message: Message) -> Result<Vec<Transcript>> {
+        let Message::Text(text) = message else {
+            return Ok(vec![]);
+        };
+        let event = match serde_json::from_str::<ServerEvent>(&text) {
+            Ok(event) => event,
+            Err(e) => {
+                debug!("ignoring OpenAI realtime event: {} {}", e, text);
+                return Ok(vec![]);
+            }
+        };
+        let transcript = match event {
+            ServerEvent::SpeechStarted {
+                item_id,
+                audio_start_ms,
+            } => {
+                self.items.entry(item_id).or_default().start_ms = Some(audio_start_ms);
+                return Ok(vec![]);
+            }
+            ServerEvent::SpeechStopped {
+                item_id,
+                audio_end_ms,
+            } => {
+                self.items.entry(item_id).or_default().end_ms = Some(audio_end_ms);
+                return Ok(vec![]);
+            }
+            ServerEvent::Delta { item_id, delta } => {
+                let item = self.items.entry(item_id.clone()).or_default();
+                item.text.push_str(&delta);
+                Transcript {
+                    text: item.text.clone(),
+                    is_final: false,
+                    start_ms: item.start_ms,
+                    end_ms: item.end_ms,
+                    task_id: Some(item_id),
+                    ..Default::default()
+                }
+            }
+            ServerEvent::Completed {
+                item_id,
+                transcript,
+            } => {
+                let item = self.items.remove(&item_id).unwrap_or_default();
+                Transcript {
+                    text: transcript,
+                    is_final: true,
+                    start_ms: item.start_ms,
+                    end_ms: item.end_ms,
+                    task_id: Some(item_id),
+                    ..Default::default()
+                }
+            }
+            ServerEvent::Error { error } => {
+                return Err(anyhow!("OpenAI realtime error: {}", error.message));
+            }
+            ServerEvent::Other => return Ok(vec![]),
+        };
+        Ok(vec![transcript])
+    }
+}
+
+pub struct OpenAiAsrClientBuilder;
+
+impl OpenAiAsrClientBuilder {
+    pub fn create(
+        track_id: TrackId,
+        token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> TranscriptionClientFuture {
+        Box::pin(async move {
+            let samplerate = option.samplerate.unwrap_or(16000) as usize;
+            let resampler = if samplerate != OPENAI_SAMPLE_RATE {
+                Some(BoxedResampler::new(samplerate, OPENAI_SAMPLE_RATE)?)
+            } else {
+                None
+            };
+            let protocol = OpenAiProtocol {
+                option: option.clone(),
+                resampler,
+                items: HashMap::new(),
+            };
+            let client = WsAsrClient::spawn(protocol, option, track_id, token, event_sender);
+            Ok(Box::new(client) as Box<dyn TranscriptionClient>)
+        })
+    }
+}
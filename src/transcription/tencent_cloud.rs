@@ -1,7 +1,7 @@
 use crate::event::{EventSender, SessionEvent};
 use crate::media::{SourcePacket, TrackId};
 use crate::transcription::{
-    TranscriptionClient, TranscriptionOption, handle_wait_for_answer_with_audio_drop,
+    Hotword, TranscriptionClient, TranscriptionOption, handle_wait_for_answer_with_audio_drop,
 };
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
@@ -25,6 +25,21 @@ use tracing::{debug, info, warn};
 use urlencoding;
 use uuid::Uuid;
 
+/// Tencent temporary hotwords: `word|weight` pairs, weights from 1 to 11
+/// where 11 makes a super hotword.
+pub(super) fn hotword_list(hotwords: &[Hotword]) -> String {
+    hotwords
+        .iter()
+        .map(|hotword| {
+            let weight = hotword
+                .boost()
+                .map_or(10, |boost| boost.round().clamp(1.0, 11.0) as u32);
+            format!("{}|{}", hotword.word(), weight)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 /// Type alias to simplify complex return type
 type TranscriptionClientFuture =
     Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>>;
@@ -292,6 +307,25 @@ impl TencentCloudAsrClientInner {
             query_params.push(option);
         }
 
+        // Hotword table id, and temporary hotwords unless given as-is
+        let extra = self.option.extra.as_ref();
+        let hotwords = self
+            .option
+            .hotwords
+            .as_deref()
+            .filter(|hotwords| !hotwords.is_empty())
+            .map(hotword_list);
+        for key in ["hotword_id", "hotword_list"] {
+            if let Some(value) = extra.and_then(|extra| extra.get(key)) {
+                query_params.push((key, value));
+            }
+        }
+        if let Some(hotwords) = hotwords.as_deref()
+            && extra.is_none_or(|extra| !extra.contains_key("hotword_list"))
+        {
+            query_params.push(("hotword_list", hotwords));
+        }
+
         // Sort query parameters by key
         query_params.sort_by(|a, b| a.0.cmp(b.0));
 
@@ -312,9 +346,15 @@ impl TencentCloudAsrClientInner {
             &format!("{}?{}", url_path, query_string),
         )?;
 
+        // The signature covers the raw values, the URL carries them encoded
+        let url_query = query_params
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, urlencoding::encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
         let ws_url = format!(
             "wss://{}{}?{}&signature={}",
-            host, url_path, query_string, signature
+            host, url_path, url_query, signature
         );
         let request = Request::builder()
             .uri(ws_url.parse::<Uri>()?)
@@ -391,6 +431,7 @@ impl TencentCloudAsrClient {
                                             confidence: None,
                                             task_id: response.task_id.take(),
                                             refer,
+                                            words: None,
                                         }
                                     } else {
                                         SessionEvent::AsrDelta {
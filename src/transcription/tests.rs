@@ -2,15 +2,28 @@ use crate::{
     event::SessionEvent,
     media::track::file::read_wav_file,
     transcription::{
-        TranscriptionClient, TranscriptionOption, aliyun::AliyunAsrClientBuilder,
+        AsrTemplate, AzureAsrClientBuilder, GenericAsrClientBuilder, Hotword,
+        OpenAiAsrClientBuilder, TranscriptionClient, TranscriptionOption, TranscriptionType,
+        WhisperAsrClientBuilder, aliyun::AliyunAsrClientBuilder,
         tencent_cloud::TencentCloudAsrClientBuilder,
     },
 };
+use base64::{Engine, engine::general_purpose::STANDARD};
 use dotenvy::dotenv;
+use futures::{SinkExt, StreamExt};
 use once_cell::sync::OnceCell;
 use rustls::crypto::aws_lc_rs::default_provider;
 use std::{collections::HashMap, env};
+use tokio::net::TcpListener;
 use tokio::time::{Duration, timeout};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        Message,
+        handshake::server::{Request, Response},
+    },
+};
+use tokio_util::sync::CancellationToken;
 
 static CRYPTO_PROVIDER: OnceCell<()> = OnceCell::new();
 
@@ -249,3 +262,410 @@ async fn test_aliyun_asr() {
         "Expected some transcription result from Aliyun ASR"
     );
 }
+
+struct MockSession {
+    uri: String,
+    headers: HashMap<String, String>,
+    texts: Vec<String>,
+    binaries: Vec<Vec<u8>>,
+}
+
+// Accepts one session, records frames until `end` matches, then sends the
+// replies and closes.
+async fn mock_ws_server(
+    end: fn(&Message) -> bool,
+    replies: Vec<Message>,
+) -> (String, tokio::sync::oneshot::Receiver<MockSession>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut uri = String::new();
+        let mut headers = HashMap::new();
+        let callback = |request: &Request, response: Response| {
+            uri = request.uri().to_string();
+            for (key, value) in request.headers() {
+                headers.insert(
+                    key.as_str().to_string(),
+                    value.to_str().unwrap_or_default().to_string(),
+                );
+            }
+            Ok(response)
+        };
+        let mut ws = accept_hdr_async(socket, callback).await.unwrap();
+        let mut session = MockSession {
+            uri,
+            headers,
+            texts: Vec::new(),
+            binaries: Vec::new(),
+        };
+        while let Some(Ok(message)) = ws.next().await {
+            let done = end(&message);
+            match message {
+                Message::Text(text) => session.texts.push(text.to_string()),
+                Message::Binary(data) => session.binaries.push(data.to_vec()),
+                _ => {}
+            }
+            if done {
+                break;
+            }
+        }
+        for reply in replies {
+            ws.send(reply).await.ok();
+        }
+        ws.close(None).await.ok();
+        tx.send(session).ok();
+    });
+    (format!("ws://{}", addr), rx)
+}
+
+// Sends 100ms of audio, ends the stream and collects results until the
+// server closes.
+async fn recognize(
+    client: Box<dyn TranscriptionClient>,
+    mut event_receiver: tokio::sync::broadcast::Receiver<SessionEvent>,
+    session: tokio::sync::oneshot::Receiver<MockSession>,
+) -> (MockSession, Vec<SessionEvent>) {
+    let samples: Vec<i16> = (0..1600).map(|i| (i % 100) as i16 * 100).collect();
+    client.send_audio(&samples, None).unwrap();
+    drop(client);
+    let session = timeout(Duration::from_secs(5), session)
+        .await
+        .expect("mock server timed out")
+        .unwrap();
+    let mut events = Vec::new();
+    while let Ok(Ok(event)) = timeout(Duration::from_millis(200), event_receiver.recv()).await {
+        match event {
+            SessionEvent::AsrDelta { .. }
+            | SessionEvent::AsrFinal { .. }
+            | SessionEvent::Error { .. } => events.push(event),
+            _ => {}
+        }
+    }
+    (session, events)
+}
+
+fn results(events: &[SessionEvent]) -> Vec<(bool, u32, String)> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            SessionEvent::AsrDelta { index, text, .. } => Some((false, *index, text.clone())),
+            SessionEvent::AsrFinal { index, text, .. } => Some((true, *index, text.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn hotwords() -> Option<Vec<Hotword>> {
+    Some(vec![
+        Hotword::Word("RustPBX".to_string()),
+        Hotword::Word("SIP".to_string()),
+    ])
+}
+
+#[tokio::test]
+async fn test_openai_asr_mock() {
+    let replies = [
+        r#"{"type":"transcription_session.created","session":{}}"#,
+        r#"{"type":"input_audio_buffer.speech_started","item_id":"item_1","audio_start_ms":100}"#,
+        r#"{"type":"conversation.item.input_audio_transcription.delta","item_id":"item_1","delta":"Hello"}"#,
+        r#"{"type":"conversation.item.input_audio_transcription.delta","item_id":"item_1","delta":" world"}"#,
+        r#"{"type":"input_audio_buffer.speech_stopped","item_id":"item_1","audio_end_ms":1000}"#,
+        r#"{"type":"conversation.item.input_audio_transcription.completed","item_id":"item_1","transcript":"Hello world."}"#,
+    ]
+    .into_iter()
+    .map(Message::text)
+    .collect();
+    let (url, session) = mock_ws_server(
+        |message| matches!(message, Message::Text(t) if t.contains("input_audio_buffer.commit")),
+        replies,
+    )
+    .await;
+
+    let option = TranscriptionOption {
+        provider: Some(TranscriptionType::OpenAi),
+        secret_key: Some("sk-test".to_string()),
+        endpoint: Some(url),
+        language: Some("en-US".to_string()),
+        samplerate: Some(16000),
+        hotwords: hotwords(),
+        ..Default::default()
+    };
+    let (event_sender, event_receiver) = tokio::sync::broadcast::channel(64);
+    let client = OpenAiAsrClientBuilder::create(
+        "track-1".to_string(),
+        CancellationToken::new(),
+        option,
+        event_sender,
+    )
+    .await
+    .unwrap();
+    let (session, events) = recognize(client, event_receiver, session).await;
+
+    assert_eq!(session.headers["authorization"], "Bearer sk-test");
+    assert_eq!(session.headers["openai-beta"], "realtime=v1");
+    let update: serde_json::Value = serde_json::from_str(&session.texts[0]).unwrap();
+    assert_eq!(update["type"], "transcription_session.update");
+    let transcription = &update["session"]["input_audio_transcription"];
+    assert_eq!(transcription["model"], "gpt-4o-transcribe");
+    assert_eq!(transcription["language"], "en");
+    assert_eq!(transcription["prompt"], "RustPBX, SIP");
+    // 100ms at 16kHz resampled to 24kHz
+    let append: serde_json::Value = serde_json::from_str(&session.texts[1]).unwrap();
+    let audio = STANDARD.decode(append["audio"].as_str().unwrap()).unwrap();
+    assert!((4600..=4800).contains(&audio.len()), "{}", audio.len());
+
+    assert_eq!(
+        results(&events),
+        vec![
+            (false, 0, "Hello".to_string()),
+            (false, 0, "Hello world".to_string()),
+            (true, 0, "Hello world.".to_string()),
+        ]
+    );
+    let SessionEvent::AsrFinal {
+        start_time: Some(start_time),
+        end_time: Some(end_time),
+        task_id,
+        ..
+    } = &events[2]
+    else {
+        panic!("expected timed final result: {:?}", events[2]);
+    };
+    assert_eq!(end_time - start_time, 900);
+    assert_eq!(task_id.as_deref(), Some("item_1"));
+}
+
+fn azure_message(path: &str, body: &str) -> Message {
+    Message::text(format!(
+        "X-RequestId: 0\r\nPath: {}\r\nContent-Type: application/json\r\n\r\n{}",
+        path, body
+    ))
+}
+
+// audio frames start with the header length, an empty body ends the stream
+fn azure_audio_body(data: &[u8]) -> &[u8] {
+    let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+    &data[2 + len..]
+}
+
+#[tokio::test]
+async fn test_azure_asr_mock() {
+    let replies = vec![
+        azure_message("turn.start", r#"{"context":{}}"#),
+        azure_message(
+            "speech.hypothesis",
+            r#"{"Text":"hello","Offset":5000000,"Duration":3000000}"#,
+        ),
+        azure_message(
+            "speech.phrase",
+            r#"{"RecognitionStatus":"Success","DisplayText":"Hello there.","Offset":5000000,"Duration":8000000,
+                "NBest":[{"Confidence":0.91,"Display":"Hello there."}]}"#,
+        ),
+        azure_message("speech.phrase", r#"{"RecognitionStatus":"NoMatch"}"#),
+        azure_message("turn.end", "{}"),
+    ];
+    let (url, session) = mock_ws_server(
+        |message| matches!(message, Message::Binary(data) if azure_audio_body(data).is_empty()),
+        replies,
+    )
+    .await;
+
+    let option = TranscriptionOption {
+        provider: Some(TranscriptionType::Azure),
+        secret_key: Some("azure-key".to_string()),
+        endpoint: Some(url),
+        language: Some("zh".to_string()),
+        samplerate: Some(16000),
+        hotwords: hotwords(),
+        ..Default::default()
+    };
+    let (event_sender, event_receiver) = tokio::sync::broadcast::channel(64);
+    let client = AzureAsrClientBuilder::create(
+        "track-1".to_string(),
+        CancellationToken::new(),
+        option,
+        event_sender,
+    )
+    .await
+    .unwrap();
+    let (session, events) = recognize(client, event_receiver, session).await;
+
+    assert!(session.uri.contains("language=zh-CN"), "{}", session.uri);
+    assert!(session.uri.contains("format=detailed"), "{}", session.uri);
+    assert_eq!(session.headers["ocp-apim-subscription-key"], "azure-key");
+    assert!(session.texts[0].contains("Path: speech.config"));
+    assert!(session.texts[1].contains("Path: speech.context"));
+    assert!(session.texts[1].contains(r#"{"Text":"RustPBX"}"#));
+    // a WAV header leads the first audio frame
+    let first = azure_audio_body(&session.binaries[0]);
+    assert_eq!(&first[..4], b"RIFF");
+    assert_eq!(first.len(), 44 + 3200);
+    assert_eq!(session.binaries.len(), 2);
+
+    assert_eq!(
+        results(&events),
+        vec![
+            (false, 0, "hello".to_string()),
+            (true, 0, "Hello there.".to_string()),
+        ]
+    );
+    let SessionEvent::AsrFinal {
+        start_time: Some(start_time),
+        end_time: Some(end_time),
+        confidence,
+        ..
+    } = &events[1]
+    else {
+        panic!("expected timed final result: {:?}", events[1]);
+    };
+    assert_eq!(end_time - start_time, 800);
+    assert_eq!(*confidence, Some(0.91));
+}
+
+#[tokio::test]
+async fn test_whisper_asr_mock() {
+    let replies = [
+        r#"{"uid":"x","message":"SERVER_READY","backend":"faster_whisper"}"#,
+        r#"{"uid":"x","segments":[{"start":"0.000","end":"1.000","text":" Good","completed":false}]}"#,
+        r#"{"uid":"x","segments":[{"start":"0.000","end":"1.000","text":" Good","completed":false}]}"#,
+        r#"{"uid":"x","segments":[{"start":"0.000","end":"1.500","text":" Good morning.","completed":true},
+            {"start":"1.500","end":"2.000","text":" How","completed":false}]}"#,
+        r#"{"uid":"x","segments":[{"start":"0.000","end":"1.500","text":" Good morning.","completed":true},
+            {"start":"1.500","end":"2.500","text":" How are you?","completed":true}]}"#,
+    ]
+    .into_iter()
+    .map(Message::text)
+    .collect();
+    let (url, session) = mock_ws_server(
+        |message| matches!(message, Message::Binary(data) if data.as_ref() == b"END_OF_AUDIO"),
+        replies,
+    )
+    .await;
+
+    let option = TranscriptionOption {
+        provider: Some(TranscriptionType::Whisper),
+        endpoint: Some(url),
+        language: Some("en".to_string()),
+        samplerate: Some(16000),
+        hotwords: hotwords(),
+        extra: Some(HashMap::from([(
+            "no_speech_thresh".to_string(),
+            "0.6".to_string(),
+        )])),
+        ..Default::default()
+    };
+    let (event_sender, event_receiver) = tokio::sync::broadcast::channel(64);
+    let client = WhisperAsrClientBuilder::create(
+        "track-1".to_string(),
+        CancellationToken::new(),
+        option,
+        event_sender,
+    )
+    .await
+    .unwrap();
+    let (session, events) = recognize(client, event_receiver, session).await;
+
+    let options: serde_json::Value = serde_json::from_str(&session.texts[0]).unwrap();
+    assert_eq!(options["language"], "en");
+    assert_eq!(options["model"], "small");
+    assert_eq!(options["initial_prompt"], "RustPBX, SIP");
+    assert_eq!(options["no_speech_thresh"], 0.6);
+    // float32 samples
+    assert_eq!(session.binaries[0].len(), 1600 * 4);
+
+    assert_eq!(
+        results(&events),
+        vec![
+            (false, 0, "Good".to_string()),
+            (true, 0, "Good morning.".to_string()),
+            (false, 1, "How".to_string()),
+            (true, 1, "How are you?".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_generic_asr_mock() {
+    let replies = [
+        r#"{"type":"ready"}"#,
+        r#"{"result":{"text":"turn on","final":false}}"#,
+        r#"{"result":{"text":"turn on the light","final":true,"conf":0.8,"begin":200,"end":1400}}"#,
+        r#"{"error":"quota exceeded"}"#,
+    ]
+    .into_iter()
+    .map(Message::text)
+    .collect();
+    let (url, session) = mock_ws_server(
+        |message| matches!(message, Message::Text(t) if t.contains("eof")),
+        replies,
+    )
+    .await;
+
+    let template: AsrTemplate = serde_json::from_value(serde_json::json!({
+        "url": format!("{}/asr?model={{{{ model }}}}", url),
+        "headers": { "X-Api-Key": "{{ secret_key }}" },
+        "startMessage": r#"{"lang":"{{ language }}","rate":{{ samplerate }},"words":{{ hotwords | tojson }}}"#,
+        "stopMessage": r#"{"eof":true}"#,
+        "textPointer": "/result/text",
+        "finalPointer": "/result/final",
+        "confidencePointer": "/result/conf",
+        "startPointer": "/result/begin",
+        "endPointer": "/result/end",
+        "timeUnit": "ms",
+        "errorPointer": "/error",
+    }))
+    .unwrap();
+    let option = TranscriptionOption {
+        secret_key: Some("generic-key".to_string()),
+        model_type: Some("paraformer".to_string()),
+        language: Some("zh".to_string()),
+        samplerate: Some(16000),
+        hotwords: hotwords(),
+        template: Some(template),
+        ..Default::default()
+    };
+    let (event_sender, event_receiver) = tokio::sync::broadcast::channel(64);
+    let client = GenericAsrClientBuilder::create(
+        "track-1".to_string(),
+        CancellationToken::new(),
+        option,
+        event_sender,
+    )
+    .await
+    .unwrap();
+    let (session, events) = recognize(client, event_receiver, session).await;
+
+    assert_eq!(session.uri, "/asr?model=paraformer");
+    assert_eq!(session.headers["x-api-key"], "generic-key");
+    assert_eq!(
+        session.texts[0],
+        r#"{"lang":"zh","rate":16000,"words":["RustPBX","SIP"]}"#
+    );
+    assert_eq!(session.binaries[0].len(), 3200);
+
+    assert_eq!(
+        results(&events),
+        vec![
+            (false, 0, "turn on".to_string()),
+            (true, 0, "turn on the light".to_string()),
+        ]
+    );
+    let SessionEvent::AsrFinal {
+        start_time: Some(start_time),
+        end_time: Some(end_time),
+        confidence,
+        ..
+    } = &events[1]
+    else {
+        panic!("expected timed final result: {:?}", events[1]);
+    };
+    assert_eq!(end_time - start_time, 1200);
+    assert_eq!(*confidence, Some(0.8));
+    assert!(matches!(
+        &events[2],
+        SessionEvent::Error { sender, error, .. }
+            if sender == "generic_asr" && error.contains("quota exceeded")
+    ));
+}
@@ -0,0 +1,179 @@
+//! Hotwords and post-recognition corrections.
+//!
+//! Hotwords are pushed into providers that can bias recognition towards
+//! them. Replacements are applied to the text of every `AsrDelta` and
+//! `AsrFinal`, whatever the provider.
+
+use crate::event::{EventSender, SessionEvent, create_event_sender};
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+
+/// A word the recognizer should favor, either a bare string or
+/// `{ word, boost }`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Hotword {
+    Word(String),
+    Boosted { word: String, boost: Option<f32> },
+}
+
+impl Hotword {
+    pub fn word(&self) -> &str {
+        match self {
+            Hotword::Word(word) | Hotword::Boosted { word, .. } => word,
+        }
+    }
+
+    pub fn boost(&self) -> Option<f32> {
+        match self {
+            Hotword::Word(_) => None,
+            Hotword::Boosted { boost, .. } => *boost,
+        }
+    }
+}
+
+/// Case-insensitive, whole-word corrections of recognized text, longest
+/// phrase first.
+pub struct Replacer {
+    pattern: Regex,
+    replacements: HashMap<String, String>,
+}
+
+impl Replacer {
+    /// None when there is nothing to replace.
+    pub fn new(replacements: &HashMap<String, String>) -> Option<Self> {
+        let mut phrases: Vec<&String> = replacements.keys().filter(|p| !p.is_empty()).collect();
+        if phrases.is_empty() {
+            return None;
+        }
+        phrases.sort_by_key(|phrase| std::cmp::Reverse(phrase.len()));
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let alternatives: Vec<String> = phrases
+            .iter()
+            .map(|phrase| {
+                let mut pattern = regex::escape(phrase);
+                if phrase.starts_with(is_word) {
+                    pattern.insert_str(0, r"\b");
+                }
+                if phrase.ends_with(is_word) {
+                    pattern.push_str(r"\b");
+                }
+                pattern
+            })
+            .collect();
+        let pattern = RegexBuilder::new(&alternatives.join("|"))
+            .case_insensitive(true)
+            .build()
+            .ok()?;
+        Some(Self {
+            pattern,
+            replacements: replacements
+                .iter()
+                .map(|(phrase, replacement)| (phrase.to_lowercase(), replacement.clone()))
+                .collect(),
+        })
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        self.pattern
+            .replace_all(text, |caps: &Captures| {
+                self.replacements
+                    .get(&caps[0].to_lowercase())
+                    .cloned()
+                    .unwrap_or_else(|| caps[0].to_string())
+            })
+            .into_owned()
+    }
+
+    /// Correct the text of ASR events, other events are returned as is.
+    pub fn rewrite(&self, mut event: SessionEvent) -> SessionEvent {
+        if let SessionEvent::AsrFinal { text, .. } | SessionEvent::AsrDelta { text, .. } =
+            &mut event
+        {
+            *text = self.apply(text);
+        }
+        event
+    }
+
+    /// Put the replacer between an ASR client and `event_sender`: the
+    /// returned sender is handed to the client, and its events are forwarded
+    /// corrected until the client drops it or `cancel_token` fires.
+    pub fn forward(
+        self,
+        event_sender: EventSender,
+        cancel_token: CancellationToken,
+    ) -> EventSender {
+        let sender = create_event_sender();
+        let mut receiver = sender.subscribe();
+        crate::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    event = receiver.recv() => event,
+                };
+                match event {
+                    Ok(event) => {
+                        event_sender.send(self.rewrite(event)).ok();
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+        sender
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hotword_forms() {
+        let hotwords: Vec<Hotword> =
+            serde_yaml::from_str("- active-call\n- { word: Acme, boost: 5 }").unwrap();
+        assert_eq!(hotwords[0].word(), "active-call");
+        assert_eq!(hotwords[0].boost(), None);
+        assert_eq!(hotwords[1].word(), "Acme");
+        assert_eq!(hotwords[1].boost(), Some(5.0));
+    }
+
+    #[tokio::test]
+    async fn test_replacer_rewrites_asr_events() {
+        let replacer = Replacer::new(&HashMap::from([
+            ("active coal".to_string(), "active-call".to_string()),
+            ("coal".to_string(), "call".to_string()),
+        ]))
+        .unwrap();
+        assert_eq!(
+            replacer.apply("Active Coal is a coalition, one coal"),
+            "active-call is a coalition, one call"
+        );
+
+        let event_sender = create_event_sender();
+        let mut receiver = event_sender.subscribe();
+        let client_sender = replacer.forward(event_sender, CancellationToken::new());
+        client_sender
+            .send(SessionEvent::AsrFinal {
+                track_id: "t".to_string(),
+                timestamp: 0,
+                index: 0,
+                start_time: None,
+                end_time: None,
+                text: "try active coal".to_string(),
+                is_filler: None,
+                confidence: None,
+                task_id: None,
+                refer: None,
+                words: None,
+            })
+            .unwrap();
+        match receiver.recv().await.unwrap() {
+            SessionEvent::AsrFinal { text, .. } => assert_eq!(text, "try active-call"),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+}
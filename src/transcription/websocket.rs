@@ -0,0 +1,266 @@
+//! Shared runner for streaming recognizers spoken to over a WebSocket.
+//!
+//! A provider only implements [`WsAsrProtocol`]: how to connect, how to
+//! frame audio and how to read results. Waiting for the answer, event
+//! numbering, timestamps and metrics are handled here.
+use super::{TranscriptionClient, TranscriptionOption, handle_wait_for_answer_with_audio_drop};
+use crate::{
+    event::{EventSender, SessionEvent},
+    media::{Sample, SourcePacket, TrackId},
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{Message, handshake::client::Request},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// A recognition result, times are offsets from the start of the audio.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Transcript {
+    pub text: String,
+    pub is_final: bool,
+    pub confidence: Option<f32>,
+    pub start_ms: Option<u64>,
+    pub end_ms: Option<u64>,
+    pub task_id: Option<String>,
+}
+
+pub(super) trait WsAsrProtocol: Send + 'static {
+    /// Provider name for logs, error events and metrics keys
+    fn name(&self) -> &'static str;
+
+    fn request(&self) -> Result<Request>;
+
+    /// Messages sent once connected
+    fn start(&mut self) -> Result<Vec<Message>> {
+        Ok(vec![])
+    }
+
+    fn audio(&mut self, samples: &[Sample]) -> Result<Vec<Message>>;
+
+    /// Messages sent when the audio ends, the server should then close
+    fn stop(&mut self) -> Vec<Message> {
+        vec![]
+    }
+
+    /// Results in a server message, an error ends the session
+    fn decode(&mut self, message: Message) -> Result<Vec<Transcript>>;
+}
+
+pub struct WsAsrClient {
+    audio_tx: mpsc::UnboundedSender<Vec<Sample>>,
+    is_closed: Arc<AtomicBool>,
+}
+
+impl WsAsrClient {
+    pub(super) fn spawn<P: WsAsrProtocol>(
+        mut protocol: P,
+        option: TranscriptionOption,
+        track_id: TrackId,
+        token: CancellationToken,
+        event_sender: EventSender,
+    ) -> Self {
+        let (audio_tx, mut audio_rx) = mpsc::unbounded_channel();
+        let event_sender_rx = match option.start_when_answer {
+            Some(true) => Some(event_sender.subscribe()),
+            _ => None,
+        };
+        let is_closed = Arc::new(AtomicBool::new(false));
+        let client = Self {
+            audio_tx,
+            is_closed: Arc::clone(&is_closed),
+        };
+        let name = protocol.name();
+
+        crate::spawn(async move {
+            if event_sender_rx.is_some() {
+                handle_wait_for_answer_with_audio_drop(event_sender_rx, &mut audio_rx, &token)
+                    .await;
+                if token.is_cancelled() {
+                    debug!("Cancelled during wait for answer");
+                    is_closed.store(true, Ordering::SeqCst);
+                    return;
+                }
+            }
+            info!(%track_id, "Starting {} ASR client", name);
+            let res = run(
+                &mut protocol,
+                &track_id,
+                audio_rx,
+                &event_sender,
+                &token,
+                option.refer,
+            )
+            .await;
+            is_closed.store(true, Ordering::SeqCst);
+            match res {
+                Ok(_) => debug!(track_id, "{} ASR websocket handling completed", name),
+                Err(e) => {
+                    info!(track_id, "Error in {} ASR websocket handling: {}", name, e);
+                    event_sender
+                        .send(SessionEvent::Error {
+                            track_id,
+                            timestamp: crate::media::get_timestamp(),
+                            sender: format!("{}_asr", name),
+                            error: e.to_string(),
+                            code: None,
+                        })
+                        .ok();
+                }
+            }
+        });
+        client
+    }
+}
+
+async fn run<P: WsAsrProtocol>(
+    protocol: &mut P,
+    track_id: &TrackId,
+    mut audio_rx: mpsc::UnboundedReceiver<Vec<Sample>>,
+    event_sender: &EventSender,
+    token: &CancellationToken,
+    refer: Option<bool>,
+) -> Result<()> {
+    let name = protocol.name();
+    let (ws_stream, response) = connect_async(protocol.request()?)
+        .await
+        .map_err(|e| anyhow!("failed to connect to {} ASR: {}", name, e))?;
+    debug!(
+        track_id,
+        "{} ASR connection established: {}",
+        name,
+        response.status()
+    );
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    for message in protocol.start()? {
+        ws_sender.send(message).await?;
+    }
+
+    let begin_time = crate::media::get_timestamp();
+    let mut index = 0u32;
+    let mut first_result_time = None;
+    let mut audio_ended = false;
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            samples = audio_rx.recv(), if !audio_ended => {
+                let messages = match samples {
+                    Some(samples) if samples.is_empty() => continue,
+                    Some(samples) => protocol.audio(&samples)?,
+                    None => {
+                        audio_ended = true;
+                        protocol.stop()
+                    }
+                };
+                for message in messages {
+                    ws_sender.send(message).await?;
+                }
+            }
+            message = ws_receiver.next() => {
+                let message = match message {
+                    Some(Ok(Message::Close(frame))) => {
+                        info!(track_id, "{} ASR connection closed: {:?}", name, frame);
+                        break;
+                    }
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => return Err(anyhow!("{} ASR websocket error: {}", name, e)),
+                    None => break,
+                };
+                for transcript in protocol.decode(message)? {
+                    let text = transcript.text.trim();
+                    if text.is_empty() {
+                        continue;
+                    }
+                    let timestamp = crate::media::get_timestamp();
+                    let start_time = transcript.start_ms.map(|start| begin_time + start);
+                    let end_time = transcript.end_ms.map(|end| begin_time + end);
+                    let event = if transcript.is_final {
+                        SessionEvent::AsrFinal {
+                            track_id: track_id.clone(),
+                            index,
+                            text: text.to_string(),
+                            timestamp,
+                            start_time,
+                            end_time,
+                            is_filler: None,
+                            confidence: transcript.confidence,
+                            task_id: transcript.task_id,
+                            refer,
+                            words: None,
+                        }
+                    } else {
+                        SessionEvent::AsrDelta {
+                            track_id: track_id.clone(),
+                            index,
+                            text: text.to_string(),
+                            timestamp,
+                            start_time,
+                            end_time,
+                            is_filler: None,
+                            confidence: transcript.confidence,
+                            task_id: transcript.task_id,
+                            refer,
+                        }
+                    };
+                    event_sender.send(event).ok();
+
+                    let first_time = first_result_time.get_or_insert(timestamp);
+                    let metrics_key = if transcript.is_final {
+                        format!("completed.asr.{}", name)
+                    } else {
+                        format!("ttfb.asr.{}", name)
+                    };
+                    event_sender
+                        .send(SessionEvent::Metrics {
+                            timestamp,
+                            key: metrics_key,
+                            data: serde_json::json!({ "index": index }),
+                            duration: (timestamp - *first_time) as u32,
+                        })
+                        .ok();
+                    if transcript.is_final {
+                        index += 1;
+                        first_result_time = None;
+                    }
+                }
+            }
+        }
+    }
+    if let Err(e) = ws_sender.close().await {
+        warn!(track_id, "failed to close {} ASR connection: {}", name, e);
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl TranscriptionClient for WsAsrClient {
+    fn send_audio(&self, samples: &[Sample], _src_packet: Option<&SourcePacket>) -> Result<()> {
+        if self.is_closed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.audio_tx
+            .send(samples.to_vec())
+            .map_err(|_| {
+                self.is_closed.store(true, Ordering::SeqCst);
+            })
+            .ok();
+        Ok(())
+    }
+}
+
+/// 32-bit float little-endian PCM in [-1, 1]
+pub(super) fn float32_bytes(samples: &[Sample]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|s| (*s as f32 / 32768.0).to_le_bytes())
+        .collect()
+}
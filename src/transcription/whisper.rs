@@ -0,0 +1,210 @@
+use super::{
+    TranscriptionClient, TranscriptionOption,
+    websocket::{Transcript, WsAsrClient, WsAsrProtocol, float32_bytes},
+};
+use crate::{
+    event::EventSender,
+    media::{Sample, TrackId},
+};
+use anyhow::{Result, anyhow};
+use audio_codec::BoxedResampler;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{future::Future, pin::Pin};
+use tokio_tungstenite::tungstenite::{
+    Message, client::IntoClientRequest, handshake::client::Request,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+type TranscriptionClientFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>>;
+
+const WHISPER_DEFAULT_URL: &str = "ws://127.0.0.1:9090";
+// whisper models take 16kHz float samples
+const WHISPER_SAMPLE_RATE: usize = 16000;
+
+#[derive(Debug, Deserialize)]
+struct ServerMessage {
+    status: Option<String>,
+    message: Option<String>,
+    segments: Option<Vec<Segment>>,
+}
+
+// times are seconds, sent as strings by some versions
+#[derive(Debug, Deserialize)]
+struct Segment {
+    start: Value,
+    end: Value,
+    text: String,
+    completed: Option<bool>,
+}
+
+fn seconds_to_ms(value: &Value) -> Option<u64> {
+    let seconds = match value {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => s.parse().ok()?,
+        _ => return None,
+    };
+    Some((seconds.max(0.0) * 1000.0) as u64)
+}
+
+// WhisperLive protocol (faster-whisper and TensorRT backends): a JSON
+// options message, then float32 audio. The server resends recent segments,
+// completed ones are final.
+// https://github.com/collabora/WhisperLive
+struct WhisperProtocol {
+    option: TranscriptionOption,
+    uid: String,
+    resampler: Option<BoxedResampler>,
+    // end of the last final segment
+    finalized_ms: Option<u64>,
+    last_partial: String,
+}
+
+impl WsAsrProtocol for WhisperProtocol {
+    fn name(&self) -> &'static str {
+        "whisper"
+    }
+
+    fn request(&self) -> Result<Request> {
+        let url = self
+            .option
+            .endpoint
+            .as_deref()
+            .unwrap_or(WHISPER_DEFAULT_URL);
+        let mut request = url.into_client_request()?;
+        if let Some(key) = self.option.secret_key.as_ref() {
+            request
+                .headers_mut()
+                .insert("Authorization", format!("Bearer {}", key).parse()?);
+        }
+        Ok(request)
+    }
+
+    fn start(&mut self) -> Result<Vec<Message>> {
+        let words: Vec<&str> = self
+            .option
+            .hotwords
+            .iter()
+            .flatten()
+            .map(|hotword| hotword.word())
+            .collect();
+        let mut options = json!({
+            "uid": self.uid,
+            "language": self.option.language.as_deref().filter(|l| *l != "auto"),
+            "task": "transcribe",
+            "model": self.option.model_type.as_deref().unwrap_or("small"),
+            "use_vad": true,
+            "initial_prompt": (!words.is_empty()).then(|| words.join(", ")),
+        });
+        for (key, value) in self.option.extra.iter().flatten() {
+            let value = serde_json::from_str(value).unwrap_or(Value::String(value.clone()));
+            options[key] = value;
+        }
+        Ok(vec![Message::text(options.to_string())])
+    }
+
+    fn audio(&mut self, samples: &[Sample]) -> Result<Vec<Message>> {
+        let audio = match self.resampler.as_mut() {
+            Some(resampler) => float32_bytes(&resampler.resample(samples)),
+            None => float32_bytes(samples),
+        };
+        Ok(vec![Message::binary(audio)])
+    }
+
+    fn stop(&mut self) -> Vec<Message> {
+        vec![Message::binary(b"END_OF_AUDIO".to_vec())]
+    }
+
+    fn decode(&mut self, message: Message) -> Result<Vec<Transcript>> {
+        let Message::Text(text) = message else {
+            return Ok(vec![]);
+        };
+        let message: ServerMessage = match serde_json::from_str(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("invalid Whisper server message: {} {}", e, text);
+                return Ok(vec![]);
+            }
+        };
+        match message.status.as_deref() {
+            Some("ERROR") => {
+                let reason = message.message.unwrap_or_default();
+                return Err(anyhow!("Whisper server error: {}", reason));
+            }
+            Some("WAIT") => warn!("Whisper server busy: {:?}", message.message),
+            _ => {}
+        }
+        let Some(segments) = message.segments else {
+            debug!("Whisper server message: {:?}", message.message);
+            return Ok(vec![]);
+        };
+
+        let mut transcripts = Vec::new();
+        for segment in &segments {
+            let start_ms = seconds_to_ms(&segment.start);
+            let end_ms = seconds_to_ms(&segment.end);
+            if segment.completed == Some(true) {
+                // already sent in an earlier message
+                if self.finalized_ms.is_some() && start_ms < self.finalized_ms {
+                    continue;
+                }
+                self.finalized_ms = end_ms.or(start_ms);
+                self.last_partial.clear();
+                transcripts.push(Transcript {
+                    text: segment.text.clone(),
+                    is_final: true,
+                    start_ms,
+                    end_ms,
+                    task_id: Some(self.uid.clone()),
+                    ..Default::default()
+                });
+            }
+        }
+        if let Some(segment) = segments.last().filter(|s| s.completed != Some(true))
+            && segment.text.trim() != self.last_partial
+        {
+            self.last_partial = segment.text.trim().to_string();
+            transcripts.push(Transcript {
+                text: segment.text.clone(),
+                is_final: false,
+                start_ms: seconds_to_ms(&segment.start),
+                end_ms: seconds_to_ms(&segment.end),
+                task_id: Some(self.uid.clone()),
+                ..Default::default()
+            });
+        }
+        Ok(transcripts)
+    }
+}
+
+pub struct WhisperAsrClientBuilder;
+
+impl WhisperAsrClientBuilder {
+    pub fn create(
+        track_id: TrackId,
+        token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> TranscriptionClientFuture {
+        Box::pin(async move {
+            let samplerate = option.samplerate.unwrap_or(16000) as usize;
+            let resampler = if samplerate != WHISPER_SAMPLE_RATE {
+                Some(BoxedResampler::new(samplerate, WHISPER_SAMPLE_RATE)?)
+            } else {
+                None
+            };
+            let protocol = WhisperProtocol {
+                option: option.clone(),
+                uid: Uuid::new_v4().to_string(),
+                resampler,
+                finalized_ms: None,
+                last_partial: String::new(),
+            };
+            let client = WsAsrClient::spawn(protocol, option, track_id, token, event_sender);
+            Ok(Box::new(client) as Box<dyn TranscriptionClient>)
+        })
+    }
+}
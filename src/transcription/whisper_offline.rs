@@ -0,0 +1,83 @@
+use super::offline::{OfflineAsrClient, OfflineRecognizer};
+use crate::event::EventSender;
+use crate::media::TrackId;
+use crate::offline::downloader::DEFAULT_WHISPER_VARIANT;
+use crate::offline::{Recognition, WhisperModel, get_offline_models, whisper::CHUNK_SAMPLES};
+use crate::transcription::{TranscriptionClient, TranscriptionOption};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+type TranscriptionClientFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>>;
+
+// the model sees at most 30s, leave room for the preroll
+const MAX_SEGMENT_MS: u64 = 29_000;
+
+struct WhisperRecognizer {
+    model: Arc<Mutex<WhisperModel>>,
+    language: Option<String>,
+}
+
+#[async_trait]
+impl OfflineRecognizer for WhisperRecognizer {
+    fn name(&self) -> &'static str {
+        "Whisper"
+    }
+
+    async fn recognize(&mut self, samples: &[i16]) -> Option<Recognition> {
+        let samples: Vec<f32> = samples
+            .iter()
+            .take(CHUNK_SAMPLES)
+            .map(|&x| x as f32 / 32768.0)
+            .collect();
+        let mut model = self.model.lock().await;
+        match model.transcribe(&samples, self.language.as_deref()) {
+            Ok(recognition) => Some(recognition),
+            Err(e) => {
+                warn!(error = %e, "Whisper inference failed");
+                None
+            }
+        }
+    }
+}
+
+/// Whisper run locally; `modelType` picks the variant, e.g. "small" or
+/// "distil-small.en", downloaded with `--download-models whisper-<variant>`.
+pub struct WhisperOfflineAsrClientBuilder;
+
+impl WhisperOfflineAsrClientBuilder {
+    pub fn create(
+        track_id: TrackId,
+        _token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> TranscriptionClientFuture {
+        Box::pin(async move {
+            let models = get_offline_models().ok_or_else(|| {
+                anyhow!(
+                    "Offline models not initialized. Please initialize with init_offline_models()"
+                )
+            })?;
+            let variant = option
+                .model_type
+                .as_deref()
+                .unwrap_or(DEFAULT_WHISPER_VARIANT);
+            let recognizer = WhisperRecognizer {
+                model: models.get_whisper(variant).await?,
+                language: option.language.clone().filter(|l| l != "auto"),
+            };
+            let client = OfflineAsrClient::spawn(
+                recognizer,
+                track_id,
+                &option,
+                event_sender,
+                Some(MAX_SEGMENT_MS),
+            );
+            Ok(Box::new(client) as Box<dyn TranscriptionClient>)
+        })
+    }
+}
@@ -0,0 +1,619 @@
+use crate::config::{InboundConfig, InboundRateLimit};
+use ipnet::IpNet;
+use md5::{Digest, Md5};
+use rsipstack::rsip::headers::auth::{Algorithm, Qop, Scheme};
+use rsipstack::rsip::prelude::{HeadersExt, ToTypedHeader};
+use rsipstack::rsip::{DigestGenerator, Header, Method, Request, StatusCode, typed};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+const DEFAULT_NONCE_TTL_SECS: u64 = 300;
+
+/// What to do with an out-of-dialog request.
+#[derive(Debug)]
+pub enum InboundDecision {
+    Accept {
+        /// Authenticated digest username
+        username: Option<String>,
+        /// Trunk the source address belongs to
+        trunk: Option<String>,
+    },
+    /// Reply 401/407 with the given authenticate header.
+    Challenge(StatusCode, Header),
+    Reject(StatusCode, &'static str),
+    /// Do not answer at all; scanners get nothing to work with.
+    Drop(&'static str),
+}
+
+struct Trunk {
+    name: String,
+    networks: Vec<IpNet>,
+    require_auth: bool,
+}
+
+#[derive(Default)]
+struct SourceState {
+    window_start: Option<Instant>,
+    count: u32,
+    blocked_until: Option<Instant>,
+}
+
+/// Guards the public SIP port: scanner User-Agent filtering, per-source rate
+/// limiting, trunk CIDR allowlists and digest authentication of the requests
+/// that start calls, chats or subscriptions: INVITE, MESSAGE and SUBSCRIBE.
+pub struct InboundGuard {
+    config: InboundConfig,
+    trunks: Vec<Trunk>,
+    scanner_user_agents: Vec<String>,
+    secret: String,
+    sources: Mutex<HashMap<IpAddr, SourceState>>,
+}
+
+impl InboundGuard {
+    pub fn new(config: InboundConfig) -> Self {
+        let trunks = config
+            .trunks
+            .iter()
+            .map(|trunk| Trunk {
+                name: trunk.name.clone(),
+                networks: trunk
+                    .cidrs
+                    .iter()
+                    .filter_map(|cidr| match parse_network(cidr) {
+                        Some(network) => Some(network),
+                        None => {
+                            warn!(trunk = trunk.name, cidr, "invalid trunk cidr, ignored");
+                            None
+                        }
+                    })
+                    .collect(),
+                require_auth: trunk.require_auth,
+            })
+            .collect();
+        let scanner_user_agents = config
+            .scanner_user_agents
+            .iter()
+            .map(|ua| ua.to_lowercase())
+            .collect();
+        Self {
+            config,
+            trunks,
+            scanner_user_agents,
+            secret: format!("{:032x}", rand::random::<u128>()),
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn inspect(&self, request: &Request) -> InboundDecision {
+        let source = request_source_ip(request);
+        self.inspect_at(request, source, Instant::now(), unix_secs())
+    }
+
+    pub(crate) fn inspect_at(
+        &self,
+        request: &Request,
+        source: Option<IpAddr>,
+        now: Instant,
+        unix_now: u64,
+    ) -> InboundDecision {
+        let trunk = source.and_then(|ip| self.find_trunk(&ip));
+
+        if trunk.is_none() {
+            if let Some(ip) = source {
+                if self.is_blocked(&ip, now) {
+                    return InboundDecision::Drop("source blocked");
+                }
+                if self.is_scanner(request) {
+                    self.block(ip, now);
+                    info!(%ip, "blocking sip scanner");
+                    return InboundDecision::Drop("scanner user agent");
+                }
+                if !self.allow_rate(ip, now) {
+                    info!(%ip, "sip request rate exceeded, blocking source");
+                    return InboundDecision::Drop("rate limit exceeded");
+                }
+            } else if self.is_scanner(request) {
+                return InboundDecision::Drop("scanner user agent");
+            }
+        }
+
+        if !matches!(
+            request.method,
+            Method::Invite | Method::Message | Method::Subscribe
+        ) {
+            return InboundDecision::Accept {
+                username: None,
+                trunk: trunk.map(|t| t.name.clone()),
+            };
+        }
+
+        let needs_auth = match trunk {
+            Some(trunk) => trunk.require_auth,
+            None => {
+                if self.config.users.is_empty() {
+                    if self.trunks.is_empty() {
+                        return InboundDecision::Accept {
+                            username: None,
+                            trunk: None,
+                        };
+                    }
+                    return InboundDecision::Reject(StatusCode::Forbidden, "source not allowed");
+                }
+                true
+            }
+        };
+        if !needs_auth {
+            return InboundDecision::Accept {
+                username: None,
+                trunk: trunk.map(|t| t.name.clone()),
+            };
+        }
+
+        match self.authenticate(request, unix_now) {
+            Ok(username) => InboundDecision::Accept {
+                username: Some(username),
+                trunk: trunk.map(|t| t.name.clone()),
+            },
+            Err(AuthFailure::Missing) => self.challenge(unix_now, false),
+            Err(AuthFailure::Stale) => self.challenge(unix_now, true),
+            Err(AuthFailure::Invalid) => {
+                InboundDecision::Reject(StatusCode::Forbidden, "invalid credentials")
+            }
+        }
+    }
+
+    /// Forget rate-limit state that no longer matters.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let window = self
+            .rate_limit()
+            .map(|r| Duration::from_secs(r.window_secs));
+        let mut sources = self.sources.lock().unwrap();
+        sources.retain(|_, state| {
+            let blocked = state.blocked_until.is_some_and(|until| until > now);
+            let in_window = match (state.window_start, window) {
+                (Some(start), Some(window)) => now.duration_since(start) < window,
+                _ => false,
+            };
+            blocked || in_window
+        });
+    }
+
+    fn rate_limit(&self) -> Option<&InboundRateLimit> {
+        self.config.rate_limit.as_ref()
+    }
+
+    fn find_trunk(&self, ip: &IpAddr) -> Option<&Trunk> {
+        self.trunks
+            .iter()
+            .find(|trunk| trunk.networks.iter().any(|net| net.contains(ip)))
+    }
+
+    fn is_scanner(&self, request: &Request) -> bool {
+        let Some(user_agent) = request.user_agent_header() else {
+            return false;
+        };
+        let user_agent = user_agent.value().to_lowercase();
+        self.scanner_user_agents
+            .iter()
+            .any(|scanner| user_agent.contains(scanner.as_str()))
+    }
+
+    fn is_blocked(&self, ip: &IpAddr, now: Instant) -> bool {
+        self.sources
+            .lock()
+            .unwrap()
+            .get(ip)
+            .and_then(|state| state.blocked_until)
+            .is_some_and(|until| until > now)
+    }
+
+    fn block(&self, ip: IpAddr, now: Instant) {
+        let block_secs = self
+            .rate_limit()
+            .map(|r| r.block_secs)
+            .unwrap_or_else(|| InboundRateLimit::default().block_secs);
+        self.sources
+            .lock()
+            .unwrap()
+            .entry(ip)
+            .or_default()
+            .blocked_until = Some(now + Duration::from_secs(block_secs));
+    }
+
+    fn allow_rate(&self, ip: IpAddr, now: Instant) -> bool {
+        let Some(limit) = self.rate_limit() else {
+            return true;
+        };
+        let window = Duration::from_secs(limit.window_secs);
+        let mut sources = self.sources.lock().unwrap();
+        let state = sources.entry(ip).or_default();
+        match state.window_start {
+            Some(start) if now.duration_since(start) < window => state.count += 1,
+            _ => {
+                state.window_start = Some(now);
+                state.count = 1;
+            }
+        }
+        if state.count > limit.max_requests {
+            state.blocked_until = Some(now + Duration::from_secs(limit.block_secs));
+            return false;
+        }
+        true
+    }
+
+    fn challenge(&self, unix_now: u64, stale: bool) -> InboundDecision {
+        let nonce = self.make_nonce(unix_now);
+        let stale = stale.then(|| "true".to_string());
+        if self.config.proxy_auth {
+            let header = typed::ProxyAuthenticate {
+                scheme: Scheme::Digest,
+                realm: self.config.realm.clone(),
+                nonce,
+                stale,
+                algorithm: Some(Algorithm::Md5),
+                qop: Some(Qop::Auth),
+                ..Default::default()
+            };
+            InboundDecision::Challenge(StatusCode::ProxyAuthenticationRequired, header.into())
+        } else {
+            let header = typed::WwwAuthenticate {
+                scheme: Scheme::Digest,
+                realm: self.config.realm.clone(),
+                nonce,
+                stale,
+                algorithm: Some(Algorithm::Md5),
+                qop: Some(Qop::Auth),
+                ..Default::default()
+            };
+            InboundDecision::Challenge(StatusCode::Unauthorized, header.into())
+        }
+    }
+
+    fn authenticate(&self, request: &Request, unix_now: u64) -> Result<String, AuthFailure> {
+        let value = request
+            .headers
+            .iter()
+            .find_map(|header| match header {
+                Header::Authorization(h) if !self.config.proxy_auth => Some(h.value()),
+                Header::ProxyAuthorization(h) if self.config.proxy_auth => Some(h.value()),
+                _ => None,
+            })
+            .ok_or(AuthFailure::Missing)?;
+        let auth = typed::Authorization::parse(value).map_err(|_| AuthFailure::Invalid)?;
+        if auth.realm != self.config.realm {
+            return Err(AuthFailure::Missing);
+        }
+        let user = self
+            .config
+            .users
+            .iter()
+            .find(|user| user.username == auth.username)
+            .ok_or(AuthFailure::Invalid)?;
+        if !DigestGenerator::from(&auth, &user.password, &request.method).verify(&auth.response) {
+            return Err(AuthFailure::Invalid);
+        }
+        if !self.nonce_is_fresh(&auth.nonce, unix_now) {
+            return Err(AuthFailure::Stale);
+        }
+        Ok(user.username.clone())
+    }
+
+    fn make_nonce(&self, unix_now: u64) -> String {
+        format!("{:x}.{}", unix_now, self.nonce_signature(unix_now))
+    }
+
+    fn nonce_signature(&self, issued: u64) -> String {
+        let mut hasher = Md5::new();
+        hasher.update(format!("{}:{:x}", self.secret, issued).as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    fn nonce_is_fresh(&self, nonce: &str, unix_now: u64) -> bool {
+        let Some((issued, signature)) = nonce.split_once('.') else {
+            return false;
+        };
+        let Ok(issued) = u64::from_str_radix(issued, 16) else {
+            return false;
+        };
+        let ttl = self.config.nonce_ttl_secs.unwrap_or(DEFAULT_NONCE_TTL_SECS);
+        signature == self.nonce_signature(issued) && unix_now.saturating_sub(issued) <= ttl
+    }
+}
+
+enum AuthFailure {
+    Missing,
+    Stale,
+    Invalid,
+}
+
+fn parse_network(cidr: &str) -> Option<IpNet> {
+    let cidr = cidr.trim();
+    cidr.parse::<IpNet>()
+        .ok()
+        .or_else(|| cidr.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Source address of a request: the `received` parameter the transport layer
+/// stamps on the top Via, or the Via host when it already is the source.
+pub fn request_source_ip(request: &Request) -> Option<IpAddr> {
+    let via = request.top_via_header().ok()?.typed().ok()?;
+    if let Some(Ok(received)) = via.received() {
+        return Some(received);
+    }
+    match via.uri.host_with_port.host {
+        rsipstack::rsip::Host::IpAddr(ip) => Some(ip),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{InboundTrunk, InboundUser};
+
+    fn request(method: &str, extra_headers: &str) -> Request {
+        format!(
+            concat!(
+                "{} sip:100@198.51.100.1 SIP/2.0\r\n",
+                "Via: SIP/2.0/UDP 203.0.113.9:5060;branch=z9hG4bK-1\r\n",
+                "From: <sip:alice@203.0.113.9>;tag=1\r\n",
+                "To: <sip:100@198.51.100.1>\r\n",
+                "Call-ID: guard-test\r\n",
+                "CSeq: 1 {}\r\n",
+                "{}",
+                "Content-Length: 0\r\n",
+                "\r\n"
+            ),
+            method, method, extra_headers
+        )
+        .as_str()
+        .try_into()
+        .unwrap()
+    }
+
+    fn invite(extra_headers: &str) -> Request {
+        request("INVITE", extra_headers)
+    }
+
+    fn config_with_user() -> InboundConfig {
+        InboundConfig {
+            users: vec![InboundUser {
+                username: "alice".to_string(),
+                password: "secret".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn authorization(guard: &InboundGuard, nonce: &str, password: &str) -> String {
+        let uri = rsipstack::rsip::Uri::try_from("sip:100@198.51.100.1").unwrap();
+        let qop = rsipstack::rsip::headers::auth::AuthQop::Auth {
+            cnonce: "abc".to_string(),
+            nc: 1,
+        };
+        let response = DigestGenerator {
+            username: "alice",
+            password,
+            nonce,
+            uri: &uri,
+            realm: &guard.config.realm,
+            method: &Method::Invite,
+            qop: Some(&qop),
+            algorithm: Algorithm::Md5,
+        }
+        .compute();
+        format!(
+            "Authorization: Digest username=\"alice\", realm=\"{}\", nonce=\"{}\", uri=\"sip:100@198.51.100.1\", response=\"{}\", algorithm=MD5, qop=auth, cnonce=\"abc\", nc=00000001\r\n",
+            guard.config.realm, nonce, response
+        )
+    }
+
+    fn source() -> Option<IpAddr> {
+        Some("203.0.113.9".parse().unwrap())
+    }
+
+    #[test]
+    fn test_request_source_ip_prefers_received() {
+        let request = invite("");
+        assert_eq!(request_source_ip(&request), source());
+
+        let mut request = invite("");
+        request.headers.retain(|h| !matches!(h, Header::Via(_)));
+        request.headers.push(Header::Via(
+            "SIP/2.0/UDP 10.0.0.1:5060;branch=z9hG4bK-2;received=192.0.2.7".into(),
+        ));
+        assert_eq!(
+            request_source_ip(&request),
+            Some("192.0.2.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_digest_challenge_and_verify() {
+        let guard = InboundGuard::new(config_with_user());
+        let now = Instant::now();
+
+        let decision = guard.inspect_at(&invite(""), source(), now, 1000);
+        let header = match decision {
+            InboundDecision::Challenge(StatusCode::Unauthorized, header) => header,
+            other => panic!("expected challenge, got {:?}", other),
+        };
+        let challenge = typed::WwwAuthenticate::parse(&header.to_string().replacen(
+            "WWW-Authenticate: ",
+            "",
+            1,
+        ))
+        .unwrap();
+        assert_eq!(challenge.realm, "active-call");
+
+        let good = authorization(&guard, &challenge.nonce, "secret");
+        assert!(matches!(
+            guard.inspect_at(&invite(&good), source(), now, 1010),
+            InboundDecision::Accept { username: Some(ref u), .. } if u == "alice"
+        ));
+
+        let bad = authorization(&guard, &challenge.nonce, "wrong");
+        assert!(matches!(
+            guard.inspect_at(&invite(&bad), source(), now, 1010),
+            InboundDecision::Reject(StatusCode::Forbidden, _)
+        ));
+
+        // Expired nonce is re-challenged as stale.
+        assert!(matches!(
+            guard.inspect_at(&invite(&good), source(), now, 1000 + 301),
+            InboundDecision::Challenge(StatusCode::Unauthorized, _)
+        ));
+
+        // A nonce not issued by us is never fresh.
+        let forged = authorization(&guard, "3e8.0000", "secret");
+        assert!(matches!(
+            guard.inspect_at(&invite(&forged), source(), now, 1010),
+            InboundDecision::Challenge(..)
+        ));
+    }
+
+    #[test]
+    fn test_message_and_subscribe_need_auth() {
+        let guard = InboundGuard::new(config_with_user());
+        let now = Instant::now();
+        for method in ["MESSAGE", "SUBSCRIBE"] {
+            assert!(matches!(
+                guard.inspect_at(&request(method, ""), source(), now, 1000),
+                InboundDecision::Challenge(StatusCode::Unauthorized, _)
+            ));
+        }
+        // OPTIONS keepalives are not challenged
+        assert!(matches!(
+            guard.inspect_at(&request("OPTIONS", ""), source(), now, 1000),
+            InboundDecision::Accept { username: None, .. }
+        ));
+
+        let guard = InboundGuard::new(InboundConfig {
+            trunks: vec![InboundTrunk {
+                name: "carrier".to_string(),
+                cidrs: vec!["192.0.2.1".to_string()],
+                require_auth: false,
+            }],
+            ..Default::default()
+        });
+        assert!(matches!(
+            guard.inspect_at(&request("MESSAGE", ""), source(), now, 1000),
+            InboundDecision::Reject(StatusCode::Forbidden, _)
+        ));
+    }
+
+    #[test]
+    fn test_proxy_auth_challenge() {
+        let guard = InboundGuard::new(InboundConfig {
+            proxy_auth: true,
+            ..config_with_user()
+        });
+        assert!(matches!(
+            guard.inspect_at(&invite(""), source(), Instant::now(), 1000),
+            InboundDecision::Challenge(StatusCode::ProxyAuthenticationRequired, _)
+        ));
+    }
+
+    #[test]
+    fn test_trunk_allowlist() {
+        let guard = InboundGuard::new(InboundConfig {
+            trunks: vec![InboundTrunk {
+                name: "carrier".to_string(),
+                cidrs: vec!["203.0.113.0/24".to_string(), "192.0.2.1".to_string()],
+                require_auth: false,
+            }],
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(matches!(
+            guard.inspect_at(&invite(""), source(), now, 1000),
+            InboundDecision::Accept { trunk: Some(ref t), .. } if t == "carrier"
+        ));
+        assert!(matches!(
+            guard.inspect_at(&invite(""), Some("192.0.2.1".parse().unwrap()), now, 1000),
+            InboundDecision::Accept { .. }
+        ));
+        assert!(matches!(
+            guard.inspect_at(
+                &invite(""),
+                Some("198.51.100.20".parse().unwrap()),
+                now,
+                1000
+            ),
+            InboundDecision::Reject(StatusCode::Forbidden, _)
+        ));
+    }
+
+    #[test]
+    fn test_scanner_is_dropped_and_blocked() {
+        let guard = InboundGuard::new(InboundConfig::default());
+        let now = Instant::now();
+        assert!(matches!(
+            guard.inspect_at(
+                &invite("User-Agent: friendly-scanner\r\n"),
+                source(),
+                now,
+                0
+            ),
+            InboundDecision::Drop(_)
+        ));
+        // The source stays blocked even with an innocent user agent.
+        assert!(matches!(
+            guard.inspect_at(&invite("User-Agent: Linphone\r\n"), source(), now, 0),
+            InboundDecision::Drop(_)
+        ));
+        assert!(matches!(
+            guard.inspect_at(
+                &invite("User-Agent: Linphone\r\n"),
+                Some("198.51.100.20".parse().unwrap()),
+                now,
+                0
+            ),
+            InboundDecision::Accept { .. }
+        ));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let guard = InboundGuard::new(InboundConfig {
+            rate_limit: Some(InboundRateLimit {
+                max_requests: 3,
+                window_secs: 10,
+                block_secs: 60,
+            }),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(matches!(
+                guard.inspect_at(&invite(""), source(), now, 0),
+                InboundDecision::Accept { .. }
+            ));
+        }
+        assert!(matches!(
+            guard.inspect_at(&invite(""), source(), now, 0),
+            InboundDecision::Drop(_)
+        ));
+        let later = now + Duration::from_secs(30);
+        assert!(matches!(
+            guard.inspect_at(&invite(""), source(), later, 0),
+            InboundDecision::Drop(_)
+        ));
+        let unblocked = now + Duration::from_secs(61);
+        assert!(matches!(
+            guard.inspect_at(&invite(""), source(), unblocked, 0),
+            InboundDecision::Accept { .. }
+        ));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    app::AppState,
+    config::InviteHandlerConfig,
+    playbook::{PLAYBOOK_DIR, Playbook, chat::ChatSession},
+    useragent::PlaybookInvitationHandler,
+};
+use anyhow::{Result, anyhow};
+use rsipstack::{
+    rsip::{
+        Header, Method, Param, Request, SipMessage, StatusCode, StatusCodeKind, Uri,
+        prelude::HeadersExt, typed,
+    },
+    transaction::{
+        Transaction,
+        endpoint::EndpointInnerRef,
+        key::{TransactionKey, TransactionRole},
+        make_tag,
+    },
+    transport::{SipAddr, SipConnection},
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Chats without a message for this long are forgotten.
+const CHAT_IDLE_TTL: Duration = Duration::from_secs(30 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(32);
+
+/// Send a non-INVITE request and wait for its final status code.
+///
+/// `destination` overrides where the request is sent, e.g. the source address
+/// of the request being answered when the peer sits behind NAT.
+pub(crate) async fn send_request(
+    endpoint: &EndpointInnerRef,
+    request: Request,
+    destination: Option<SipAddr>,
+    timeout: Duration,
+) -> Result<u16> {
+    let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, request, endpoint.clone(), None);
+    tx.destination = destination;
+    tx.send().await?;
+
+    let response = tokio::time::timeout(timeout, async {
+        while let Some(msg) = tx.receive().await {
+            if let SipMessage::Response(resp) = msg
+                && resp.status_code.kind() != StatusCodeKind::Provisional
+            {
+                return Some(resp.status_code.code());
+            }
+        }
+        None
+    })
+    .await;
+    match response {
+        Ok(Some(code)) => Ok(code),
+        Ok(None) => Err(anyhow!("transaction ended without response")),
+        Err(_) => Err(anyhow!("no response within {:?}", timeout)),
+    }
+}
+
+/// Where responses to `req` go: the received/rport address of its top Via.
+pub(crate) fn reply_destination(req: &Request) -> Option<SipAddr> {
+    let via = req.via_header().ok()?;
+    let (transport, addr) = SipConnection::parse_target_from_via(via).ok()?;
+    Some(SipAddr {
+        r#type: Some(transport),
+        addr,
+    })
+}
+
+/// Playbook answering chats between `caller` and `callee`, following the same
+/// rules as inbound calls.
+fn match_chat_playbook(app_state: &AppState, caller: &str, callee: &str) -> Option<String> {
+    match &app_state.config.handler {
+        Some(InviteHandlerConfig::Playbook { rules, default }) => PlaybookInvitationHandler::new(
+            rules.clone().unwrap_or_default(),
+            default.clone(),
+            app_state.clone(),
+        )
+        .ok()?
+        .match_playbook(caller, callee),
+        _ => None,
+    }
+}
+
+async fn load_chat_playbook(playbook: &str, caller: &str, callee: &str) -> Result<Playbook> {
+    let loaded = if playbook.trim().starts_with("---") {
+        let mut loaded = Playbook::parse(playbook)?;
+        loaded.load_lexicon(Path::new(PLAYBOOK_DIR)).await?;
+        loaded
+    } else if playbook.starts_with("config/playbook/") {
+        Playbook::load(PathBuf::from(playbook)).await?
+    } else {
+        Playbook::load(PathBuf::from("config/playbook").join(playbook)).await?
+    };
+    let vars = HashMap::from([
+        (
+            crate::playbook::BUILTIN_CALLER.to_string(),
+            serde_json::Value::String(caller.to_string()),
+        ),
+        (
+            crate::playbook::BUILTIN_CALLEE.to_string(),
+            serde_json::Value::String(callee.to_string()),
+        ),
+    ]);
+    Ok(loaded.render(&vars).unwrap_or(loaded))
+}
+
+struct ChatEntry {
+    session: Arc<tokio::sync::Mutex<ChatSession>>,
+    last_active: Instant,
+}
+
+/// Text chats held over out-of-dialog SIP MESSAGE, one per caller/callee pair.
+#[derive(Default)]
+pub struct ChatManager {
+    chats: Mutex<HashMap<String, ChatEntry>>,
+}
+
+impl ChatManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chats.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget chats that have been idle for too long.
+    pub fn prune(&self) {
+        self.chats
+            .lock()
+            .unwrap()
+            .retain(|_, entry| entry.last_active.elapsed() < CHAT_IDLE_TTL);
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<tokio::sync::Mutex<ChatSession>>> {
+        let mut chats = self.chats.lock().unwrap();
+        let entry = chats.get_mut(key)?;
+        entry.last_active = Instant::now();
+        Some(entry.session.clone())
+    }
+
+    /// Store `session` under `key` unless a concurrent MESSAGE already started
+    /// one, and return the session that ends up in the map.
+    fn insert(&self, key: &str, session: ChatSession) -> Arc<tokio::sync::Mutex<ChatSession>> {
+        let mut chats = self.chats.lock().unwrap();
+        let entry = chats.entry(key.to_string()).or_insert_with(|| ChatEntry {
+            session: Arc::new(tokio::sync::Mutex::new(session)),
+            last_active: Instant::now(),
+        });
+        entry.last_active = Instant::now();
+        entry.session.clone()
+    }
+
+    fn remove(&self, key: &str) {
+        self.chats.lock().unwrap().remove(key);
+    }
+}
+
+/// Answer an out-of-dialog MESSAGE and reply to its sender with a MESSAGE
+/// generated by the matching playbook.
+pub async fn handle_message(app_state: AppState, mut tx: Transaction) {
+    let req = tx.original.clone();
+    let content_type = req.headers.iter().find_map(|h| match h {
+        Header::ContentType(ct) => Some(ct.value().to_ascii_lowercase()),
+        _ => None,
+    });
+    if content_type
+        .as_ref()
+        .is_some_and(|ct| !ct.starts_with("text/plain"))
+    {
+        info!(?content_type, "unsupported MESSAGE content type");
+        tx.reply(StatusCode::UnsupportedMediaType).await.ok();
+        return;
+    }
+
+    let (caller, callee) = match (
+        req.from_header().and_then(|h| h.uri()),
+        req.to_header().and_then(|h| h.uri()),
+    ) {
+        (Ok(from), Ok(to)) => (from, to),
+        _ => {
+            tx.reply(StatusCode::BadRequest).await.ok();
+            return;
+        }
+    };
+    let (caller_str, callee_str) = (caller.to_string(), callee.to_string());
+    let Some(playbook) = match_chat_playbook(&app_state, &caller_str, &callee_str) else {
+        info!(caller_str, callee_str, "no playbook for MESSAGE");
+        tx.reply(StatusCode::TemporarilyUnavailable).await.ok();
+        return;
+    };
+    if let Err(e) = tx.reply(StatusCode::OK).await {
+        warn!("error replying to MESSAGE: {:?}", e);
+        return;
+    }
+
+    let text = String::from_utf8_lossy(&req.body).trim().to_string();
+    if text.is_empty() {
+        return;
+    }
+    let key = format!("{}|{}", caller_str, callee_str);
+    let session = match app_state.chats.get(&key) {
+        Some(session) => session,
+        None => match load_chat_playbook(&playbook, &caller_str, &callee_str)
+            .await
+            .and_then(ChatSession::new)
+        {
+            Ok(session) => {
+                info!(key, playbook, "chat started");
+                app_state.chats.insert(&key, session)
+            }
+            Err(e) => {
+                warn!(key, playbook, "failed to start chat: {}", e);
+                return;
+            }
+        },
+    };
+
+    let (reply, ended) = {
+        let mut session = session.lock().await;
+        match session.reply(&text).await {
+            Ok(reply) => (reply, session.is_ended()),
+            Err(e) => {
+                warn!(key, "chat reply failed: {}", e);
+                return;
+            }
+        }
+    };
+    if ended {
+        info!(key, "chat ended by playbook");
+        app_state.chats.remove(&key);
+    }
+    if reply.is_empty() {
+        return;
+    }
+
+    let endpoint = app_state.endpoint.inner.clone();
+    let request = match build_message(&endpoint, &callee, &caller, reply) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!(key, "failed to build MESSAGE: {}", e);
+            return;
+        }
+    };
+    match send_request(&endpoint, request, reply_destination(&req), REQUEST_TIMEOUT).await {
+        Ok(code) if code < 300 => {}
+        Ok(code) => warn!(key, code, "chat reply rejected"),
+        Err(e) => warn!(key, "failed to send chat reply: {}", e),
+    }
+}
+
+/// Build an out-of-dialog MESSAGE from `from` to `to` with a text body.
+fn build_message(
+    endpoint: &EndpointInnerRef,
+    from: &Uri,
+    to: &Uri,
+    text: String,
+) -> Result<Request> {
+    let via = endpoint.get_via(None, None)?;
+    let from = typed::From {
+        display_name: None,
+        uri: from.clone(),
+        params: vec![Param::Tag(make_tag())],
+    };
+    let to = typed::To {
+        display_name: None,
+        uri: to.clone(),
+        params: vec![],
+    };
+    let mut request =
+        endpoint.make_request(Method::Message, to.uri.clone(), via, from, to, 1, None);
+    request
+        .headers
+        .push(Header::ContentType("text/plain;charset=utf-8".into()));
+    request.body = text.into_bytes();
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_destination_uses_received_and_rport() {
+        let req: Request = concat!(
+            "MESSAGE sip:ai@10.0.0.1 SIP/2.0\r\n",
+            "Via: SIP/2.0/UDP 192.168.1.20:5060;branch=z9hG4bK1;received=203.0.113.7;rport=40000\r\n",
+            "From: <sip:alice@example.com>;tag=a\r\n",
+            "To: <sip:ai@10.0.0.1>\r\n",
+            "Call-ID: c1\r\n",
+            "CSeq: 1 MESSAGE\r\n",
+            "Content-Length: 0\r\n\r\n"
+        )
+        .try_into()
+        .unwrap();
+        let destination = reply_destination(&req).unwrap();
+        assert_eq!(destination.addr.to_string(), "203.0.113.7:40000");
+    }
+
+    #[test]
+    fn test_chat_manager_prunes_idle_chats() {
+        let manager = ChatManager::new();
+        let playbook =
+            Playbook::parse("---\nllm:\n  provider: openai\n---\n# Scene: main\nHi").unwrap();
+        manager.insert("a|b", ChatSession::new(playbook).unwrap());
+        assert!(manager.get("a|b").is_some());
+        manager
+            .chats
+            .lock()
+            .unwrap()
+            .get_mut("a|b")
+            .unwrap()
+            .last_active = Instant::now() - CHAT_IDLE_TTL;
+        manager.prune();
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn test_chat_manager_keeps_first_session() {
+        let manager = ChatManager::new();
+        let playbook =
+            Playbook::parse("---\nllm:\n  provider: openai\n---\n# Scene: main\nHi").unwrap();
+        let first = manager.insert("a|b", ChatSession::new(playbook.clone()).unwrap());
+        let second = manager.insert("a|b", ChatSession::new(playbook).unwrap());
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(manager.len(), 1);
+    }
+}
@@ -1,7 +1,10 @@
 pub mod registration;
 pub use registration::RegisterOption;
+pub mod inbound_guard;
 pub mod invitation;
+pub mod messaging;
 pub mod playbook_handler;
 pub mod public_address;
+pub mod subscription;
 pub mod webhook;
 pub use playbook_handler::PlaybookInvitationHandler;
@@ -0,0 +1,536 @@
+use crate::{
+    app::AppState,
+    useragent::messaging::{reply_destination, send_request},
+};
+use anyhow::Result;
+use rsipstack::{
+    rsip::{
+        Header, Method, Param, Request, StatusCode, Uri,
+        headers::CallId,
+        prelude::{HeadersExt, ToTypedHeader},
+        typed,
+    },
+    transaction::{Transaction, endpoint::EndpointInnerRef},
+    transport::SipAddr,
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+const DEFAULT_EXPIRES: u32 = 3600;
+const MAX_EXPIRES: u32 = 7200;
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(32);
+
+/// Event packages a PBX can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionEvent {
+    /// RFC 3842 message waiting indication
+    MessageSummary,
+    /// RFC 4235 dialog state, i.e. whether the agent is on a call
+    Dialog,
+}
+
+impl SubscriptionEvent {
+    pub fn parse(event: &str) -> Option<Self> {
+        let package = event.split(';').next().unwrap_or_default().trim();
+        match package.to_ascii_lowercase().as_str() {
+            "message-summary" => Some(Self::MessageSummary),
+            "dialog" => Some(Self::Dialog),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MessageSummary => "message-summary",
+            Self::Dialog => "dialog",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::MessageSummary => "application/simple-message-summary",
+            Self::Dialog => "application/dialog-info+xml",
+        }
+    }
+}
+
+/// A call the subscribed agent takes part in, as reported by the dialog package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DialogSnapshot {
+    pub id: String,
+    /// True when the agent was called, false when it placed the call
+    pub recipient: bool,
+    pub confirmed: bool,
+}
+
+struct Subscription {
+    event: SubscriptionEvent,
+    /// The subscribed resource, i.e. the Request-URI/To of the SUBSCRIBE
+    entity: Uri,
+    subscriber: Uri,
+    target: Uri,
+    destination: Option<SipAddr>,
+    local_tag: String,
+    remote_tag: String,
+    cseq: u32,
+    version: u32,
+    expires_at: Instant,
+    last_state: Option<String>,
+}
+
+struct Notify {
+    call_id: String,
+    request: Request,
+    destination: Option<SipAddr>,
+}
+
+/// SUBSCRIBE dialogs we act as notifier for, keyed by Call-ID.
+#[derive(Default)]
+pub struct SubscriptionManager {
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+}
+
+impl SubscriptionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscriptions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn remove(&self, call_id: &str) {
+        self.subscriptions.lock().unwrap().remove(call_id);
+    }
+
+    /// Build the NOTIFY for `call_id` if its state changed since the last one,
+    /// or unconditionally when `force` is set.
+    fn next_notify(
+        &self,
+        endpoint: &EndpointInnerRef,
+        via: &typed::Via,
+        call_id: &str,
+        dialogs: &dyn Fn(&Uri) -> Vec<DialogSnapshot>,
+        force: bool,
+    ) -> Option<Notify> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let sub = subscriptions.get_mut(call_id)?;
+        let remaining = sub.expires_at.saturating_duration_since(Instant::now());
+        let terminated = remaining.is_zero();
+        // The dialog-info version changes on every NOTIFY, so changes are
+        // detected on the dialogs themselves.
+        let (state, state_key) = match sub.event {
+            SubscriptionEvent::MessageSummary => {
+                let body = message_summary_body(&sub.entity);
+                (body.clone(), body)
+            }
+            SubscriptionEvent::Dialog => {
+                let dialogs = dialogs(&sub.entity);
+                (
+                    dialog_info_body(&sub.entity, sub.version, &dialogs),
+                    format!("{:?}", dialogs),
+                )
+            }
+        };
+        if !force && !terminated && sub.last_state.as_ref() == Some(&state_key) {
+            return None;
+        }
+        sub.last_state = Some(state_key);
+        sub.version += 1;
+        sub.cseq += 1;
+        let subscription_state = if terminated {
+            "terminated;reason=timeout".to_string()
+        } else {
+            format!("active;expires={}", remaining.as_secs().max(1))
+        };
+        let request = build_notify(endpoint, via, call_id, sub, &subscription_state, state).ok()?;
+        let destination = sub.destination.clone();
+        if terminated {
+            subscriptions.remove(call_id);
+        }
+        Some(Notify {
+            call_id: call_id.to_string(),
+            request,
+            destination,
+        })
+    }
+
+    /// Send NOTIFYs for subscriptions whose state changed and end expired ones.
+    pub fn notify_changes(&self, app_state: &AppState) {
+        let call_ids: Vec<String> = self.subscriptions.lock().unwrap().keys().cloned().collect();
+        let endpoint = app_state.endpoint.inner.clone();
+        let dialogs = |entity: &Uri| agent_dialogs(app_state, entity);
+        for call_id in call_ids {
+            let via = match endpoint.get_via(None, None) {
+                Ok(via) => via,
+                Err(e) => {
+                    warn!("failed to get via for NOTIFY: {}", e);
+                    return;
+                }
+            };
+            if let Some(notify) = self.next_notify(&endpoint, &via, &call_id, &dialogs, false) {
+                send_notify(app_state.clone(), notify);
+            }
+        }
+    }
+}
+
+fn send_notify(app_state: AppState, notify: Notify) {
+    crate::spawn(async move {
+        let endpoint = app_state.endpoint.inner.clone();
+        match send_request(
+            &endpoint,
+            notify.request,
+            notify.destination,
+            NOTIFY_TIMEOUT,
+        )
+        .await
+        {
+            Ok(code) if code < 300 => {}
+            Ok(code) => {
+                info!(
+                    call_id = notify.call_id,
+                    code, "NOTIFY rejected, ending subscription"
+                );
+                app_state.subscriptions.remove(&notify.call_id);
+            }
+            Err(e) => {
+                info!(
+                    call_id = notify.call_id,
+                    "NOTIFY failed, ending subscription: {}", e
+                );
+                app_state.subscriptions.remove(&notify.call_id);
+            }
+        }
+    });
+}
+
+fn build_notify(
+    endpoint: &EndpointInnerRef,
+    via: &typed::Via,
+    call_id: &str,
+    sub: &Subscription,
+    subscription_state: &str,
+    body: String,
+) -> Result<Request> {
+    let contact = format!("<sip:{}>", via.uri.host_with_port);
+    let from = typed::From {
+        display_name: None,
+        uri: sub.entity.clone(),
+        params: vec![Param::Tag(sub.local_tag.clone().into())],
+    };
+    let to = typed::To {
+        display_name: None,
+        uri: sub.subscriber.clone(),
+        params: vec![Param::Tag(sub.remote_tag.clone().into())],
+    };
+    let mut request = endpoint.make_request(
+        Method::Notify,
+        sub.target.clone(),
+        via.clone(),
+        from,
+        to,
+        sub.cseq,
+        Some(CallId::from(call_id)),
+    );
+    request.headers.push(Header::Event(sub.event.name().into()));
+    request
+        .headers
+        .push(Header::SubscriptionState(subscription_state.into()));
+    request.headers.push(Header::Contact(contact.into()));
+    request
+        .headers
+        .push(Header::ContentType(sub.event.content_type().into()));
+    request.body = body.into_bytes();
+    Ok(request)
+}
+
+/// Agents never hold voicemail, so the summary always reports none waiting.
+pub fn message_summary_body(entity: &Uri) -> String {
+    format!(
+        "Messages-Waiting: no\r\nMessage-Account: {}\r\nVoice-Message: 0/0 (0/0)\r\n",
+        entity
+    )
+}
+
+pub fn dialog_info_body(entity: &Uri, version: u32, dialogs: &[DialogSnapshot]) -> String {
+    let mut body = format!(
+        "<?xml version=\"1.0\"?>\n<dialog-info xmlns=\"urn:ietf:params:xml:ns:dialog-info\" version=\"{}\" state=\"full\" entity=\"{}\">\n",
+        version, entity
+    );
+    for dialog in dialogs {
+        body.push_str(&format!(
+            "  <dialog id=\"{}\" direction=\"{}\">\n    <state>{}</state>\n  </dialog>\n",
+            dialog.id,
+            if dialog.recipient {
+                "recipient"
+            } else {
+                "initiator"
+            },
+            if dialog.confirmed {
+                "confirmed"
+            } else {
+                "early"
+            },
+        ));
+    }
+    body.push_str("</dialog-info>\n");
+    body
+}
+
+fn uri_user(uri: &str) -> Option<String> {
+    let uri = uri.trim().trim_start_matches('<').trim_end_matches('>');
+    Uri::try_from(uri)
+        .ok()
+        .and_then(|uri| uri.user().map(|user| user.to_string()))
+}
+
+/// Active calls the agent named by `entity` takes part in.
+fn agent_dialogs(app_state: &AppState, entity: &Uri) -> Vec<DialogSnapshot> {
+    let Some(user) = entity.user().map(|user| user.to_string()) else {
+        return vec![];
+    };
+    let mut dialogs: Vec<DialogSnapshot> = app_state
+        .active_calls
+        .lock()
+        .unwrap()
+        .values()
+        .filter_map(|call| {
+            let state = call.call_state.try_read().ok()?;
+            let option = state.option.as_ref()?;
+            let recipient = option.callee.as_deref().and_then(uri_user) == Some(user.clone());
+            let initiator = option.caller.as_deref().and_then(uri_user) == Some(user.clone());
+            if !recipient && !initiator {
+                return None;
+            }
+            Some(DialogSnapshot {
+                id: call.session_id.clone(),
+                recipient,
+                confirmed: state.answer_time.is_some(),
+            })
+        })
+        .collect();
+    dialogs.sort_by(|a, b| a.id.cmp(&b.id));
+    dialogs
+}
+
+/// Accept a SUBSCRIBE for one of the supported event packages, or refresh an
+/// existing subscription, and send the current state in a NOTIFY.
+pub async fn handle_subscribe(app_state: AppState, mut tx: Transaction) {
+    let req = tx.original.clone();
+    let event = req.headers.iter().find_map(|h| match h {
+        Header::Event(event) => SubscriptionEvent::parse(event.value()),
+        _ => None,
+    });
+    let Some(event) = event else {
+        info!("unsupported SUBSCRIBE event");
+        tx.reply(StatusCode::BadEvent).await.ok();
+        return;
+    };
+    let (Ok(call_id), Ok(from), Ok(to)) = (
+        req.call_id_header().map(|h| h.value().to_string()),
+        req.from_header(),
+        req.to_header(),
+    ) else {
+        tx.reply(StatusCode::BadRequest).await.ok();
+        return;
+    };
+    let expires = req
+        .headers
+        .iter()
+        .find_map(|h| match h {
+            Header::Expires(expires) => expires.value().trim().parse::<u32>().ok(),
+            _ => None,
+        })
+        .unwrap_or(DEFAULT_EXPIRES)
+        .min(MAX_EXPIRES);
+    let existing_tag = to.tag().ok().flatten().map(|tag| tag.to_string());
+    let subscriptions = &app_state.subscriptions;
+
+    if let Some(tag) = &existing_tag {
+        let known = subscriptions
+            .subscriptions
+            .lock()
+            .unwrap()
+            .get(&call_id)
+            .is_some_and(|sub| &sub.local_tag == tag);
+        if !known {
+            tx.reply(StatusCode::CallTransactionDoesNotExist).await.ok();
+            return;
+        }
+    }
+
+    let via = match app_state.endpoint.inner.get_via(None, None) {
+        Ok(via) => via,
+        Err(e) => {
+            warn!("failed to get via for SUBSCRIBE reply: {}", e);
+            tx.reply(StatusCode::ServerInternalError).await.ok();
+            return;
+        }
+    };
+    let headers = vec![
+        Header::Expires(expires.into()),
+        Header::Contact(format!("<sip:{}>", via.uri.host_with_port).into()),
+    ];
+    if let Err(e) = tx.reply_with(StatusCode::OK, headers, None).await {
+        warn!("error replying to SUBSCRIBE: {:?}", e);
+        return;
+    }
+
+    // The reply carries the To tag identifying our side of the subscription.
+    let local_tag = tx
+        .original
+        .to_header()
+        .ok()
+        .and_then(|h| h.tag().ok().flatten());
+    let (Ok(entity), Ok(subscriber), Ok(Some(remote_tag)), Some(local_tag)) =
+        (to.uri(), from.uri(), from.tag(), local_tag)
+    else {
+        return;
+    };
+    let target = req
+        .contact_header()
+        .ok()
+        .and_then(|contact| contact.typed().ok())
+        .map(|contact| contact.uri)
+        .unwrap_or_else(|| subscriber.clone());
+    {
+        let mut subs = subscriptions.subscriptions.lock().unwrap();
+        let cseq = subs.get(&call_id).map(|sub| sub.cseq).unwrap_or(0);
+        let version = subs.get(&call_id).map(|sub| sub.version).unwrap_or(0);
+        info!(call_id, event = event.name(), %entity, expires, "subscription accepted");
+        subs.insert(
+            call_id.clone(),
+            Subscription {
+                event,
+                entity,
+                subscriber,
+                target,
+                destination: reply_destination(&req),
+                local_tag: local_tag.to_string(),
+                remote_tag: remote_tag.to_string(),
+                cseq,
+                version,
+                expires_at: Instant::now() + Duration::from_secs(expires as u64),
+                last_state: None,
+            },
+        );
+    }
+
+    // Every NOTIFY is a new transaction, so it gets its own Via branch.
+    let Ok(via) = app_state.endpoint.inner.get_via(None, None) else {
+        return;
+    };
+    let endpoint = app_state.endpoint.inner.clone();
+    let dialogs = |entity: &Uri| agent_dialogs(&app_state, entity);
+    if let Some(notify) = subscriptions.next_notify(&endpoint, &via, &call_id, &dialogs, true) {
+        send_notify(app_state.clone(), notify);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_event_parse() {
+        assert_eq!(
+            SubscriptionEvent::parse("message-summary"),
+            Some(SubscriptionEvent::MessageSummary)
+        );
+        assert_eq!(
+            SubscriptionEvent::parse("Dialog;id=1"),
+            Some(SubscriptionEvent::Dialog)
+        );
+        assert_eq!(SubscriptionEvent::parse("presence"), None);
+    }
+
+    #[test]
+    fn test_dialog_info_body() {
+        let entity = Uri::try_from("sip:agent1@example.com").unwrap();
+        let idle = dialog_info_body(&entity, 0, &[]);
+        assert!(idle.contains("entity=\"sip:agent1@example.com\""));
+        assert!(!idle.contains("<dialog "));
+
+        let busy = dialog_info_body(
+            &entity,
+            3,
+            &[DialogSnapshot {
+                id: "s1".to_string(),
+                recipient: true,
+                confirmed: true,
+            }],
+        );
+        assert!(busy.contains("version=\"3\""));
+        assert!(busy.contains("<dialog id=\"s1\" direction=\"recipient\">"));
+        assert!(busy.contains("<state>confirmed</state>"));
+    }
+
+    #[test]
+    fn test_next_notify_only_on_change() {
+        let manager = SubscriptionManager::new();
+        let entity = Uri::try_from("sip:agent1@example.com").unwrap();
+        let subscriber = Uri::try_from("sip:pbx@example.com").unwrap();
+        manager.subscriptions.lock().unwrap().insert(
+            "c1".to_string(),
+            Subscription {
+                event: SubscriptionEvent::Dialog,
+                entity,
+                subscriber: subscriber.clone(),
+                target: subscriber,
+                destination: None,
+                local_tag: "l".to_string(),
+                remote_tag: "r".to_string(),
+                cseq: 0,
+                version: 0,
+                expires_at: Instant::now() + Duration::from_secs(60),
+                last_state: None,
+            },
+        );
+        let state: Mutex<Vec<DialogSnapshot>> = Mutex::new(vec![]);
+        let dialogs = |_: &Uri| state.lock().unwrap().clone();
+        let matches = |notify: &Option<Notify>| notify.is_some();
+
+        let endpoint = rsipstack::EndpointBuilder::new().build().inner.clone();
+        let via = || {
+            rsipstack::rsip::headers::Via::new("SIP/2.0/UDP 127.0.0.1:5060;branch=z9hG4bKtest")
+                .typed()
+                .unwrap()
+        };
+        assert!(matches(&manager.next_notify(
+            &endpoint,
+            &via(),
+            "c1",
+            &dialogs,
+            true
+        )));
+        assert!(!matches(&manager.next_notify(
+            &endpoint,
+            &via(),
+            "c1",
+            &dialogs,
+            false
+        )));
+
+        state.lock().unwrap().push(DialogSnapshot {
+            id: "s1".to_string(),
+            recipient: false,
+            confirmed: false,
+        });
+        let notify = manager
+            .next_notify(&endpoint, &via(), "c1", &dialogs, false)
+            .unwrap();
+        assert_eq!(notify.request.method, Method::Notify);
+        assert_eq!(notify.request.cseq_header().unwrap().seq().unwrap(), 2);
+        let body = String::from_utf8(notify.request.body).unwrap();
+        assert!(body.contains("<state>early</state>"));
+    }
+}
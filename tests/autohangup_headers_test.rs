@@ -110,6 +110,7 @@ async fn test_autohangup_headers_stored_in_extras() -> Result<()> {
         task_id: None,
         timestamp: 0,
         refer: None,
+        words: None,
     };
 
     let _ = handler.on_event(&event).await?;
@@ -203,6 +204,7 @@ async fn test_autohangup_without_sip_config() -> Result<()> {
         task_id: None,
         timestamp: 0,
         refer: None,
+        words: None,
     };
 
     let _ = handler.on_event(&event).await?;
@@ -307,6 +309,7 @@ async fn test_autohangup_headers_with_template_variables() -> Result<()> {
         task_id: None,
         timestamp: 0,
         refer: None,
+        words: None,
     };
 
     let _ = handler.on_event(&event).await?;
@@ -0,0 +1,177 @@
+#![cfg(feature = "cdr-sql")]
+use active_call::app::AppStateBuilder;
+use active_call::callrecord::*;
+use active_call::config::{CallHistoryConfig, CallRecordConfig, Config};
+use active_call::handler::history_router;
+use chrono::{Duration, Utc};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use tokio::net::TcpListener;
+
+fn media(path: &std::path::Path, format: &str) -> CallRecordMedia {
+    CallRecordMedia {
+        track_id: "c1".to_string(),
+        path: path.to_string_lossy().to_string(),
+        size: std::fs::metadata(path).unwrap().len(),
+        extra: Some(HashMap::from([("format".to_string(), json!(format))])),
+    }
+}
+
+#[tokio::test]
+async fn test_call_history_api() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let cdr_root = dir.path().join("cdr");
+
+    // Saved by the local sink before the index existed
+    let old_call = CallRecord {
+        call_id: "old-call".to_string(),
+        caller: "sip:carol@example.com".to_string(),
+        callee: "sip:support@example.com".to_string(),
+        start_time: Utc::now() - Duration::days(1),
+        end_time: Utc::now() - Duration::days(1) + Duration::seconds(20),
+        status_code: 200,
+        ..Default::default()
+    };
+    std::fs::create_dir_all(cdr_root.join("20250101"))?;
+    std::fs::write(
+        cdr_root
+            .join("20250101")
+            .join(default_cdr_file_name(&old_call)),
+        serde_json::to_string(&old_call)?,
+    )?;
+
+    let recording = dir.path().join("c1.wav");
+    std::fs::write(&recording, b"RIFF0000WAVE")?;
+    let transcript = dir.path().join("c1.transcript.json");
+    std::fs::write(
+        &transcript,
+        json!({
+            "callId": "c1",
+            "segments": [
+                { "speaker": "agent", "start": 0, "end": 1200, "text": "How can I help?" },
+                { "speaker": "user", "start": 1500, "end": 2600, "text": "My order is late" },
+            ],
+        })
+        .to_string(),
+    )?;
+
+    let mut config = Config::default();
+    config.udp_port = 0;
+    config.callrecord = Some(CallRecordConfig::Local {
+        root: cdr_root.to_string_lossy().to_string(),
+    });
+    config.call_history = Some(CallHistoryConfig {
+        url: Some(format!(
+            "sqlite://{}?mode=rwc",
+            dir.path().join("history.db").display()
+        )),
+    });
+    let app_state = AppStateBuilder::new().with_config(config).build().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let base = format!("http://{}", listener.local_addr()?);
+    let router = history_router().with_state(app_state.clone());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    app_state
+        .callrecord_sender
+        .as_ref()
+        .unwrap()
+        .send(CallRecord {
+            call_id: "c1".to_string(),
+            caller: "sip:alice@example.com".to_string(),
+            callee: "sip:support@example.com".to_string(),
+            start_time: Utc::now() - Duration::seconds(95),
+            end_time: Utc::now(),
+            status_code: 200,
+            hangup_reason: Some(CallRecordHangupReason::ByCallee),
+            extras: Some(HashMap::from([
+                ("playbook".to_string(), json!("support.md")),
+                ("tier".to_string(), json!("gold")),
+            ])),
+            recorder: vec![media(&recording, "wav")],
+            transcript: vec![media(&transcript, "json")],
+            ..Default::default()
+        })?;
+
+    let client = reqwest::Client::new();
+    let get = |path: String| {
+        let client = client.clone();
+        let url = format!("{}{}", base, path);
+        async move { client.get(url).send().await.unwrap() }
+    };
+
+    let mut calls = Value::Null;
+    for _ in 0..50 {
+        calls = get("/api/calls".to_string()).await.json().await?;
+        if calls["calls"].as_array().is_some_and(|c| c.len() == 2) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(calls["calls"][0]["callId"], "c1");
+    assert_eq!(calls["calls"][0]["playbook"], "support.md");
+    assert_eq!(calls["calls"][0]["hangupReason"], "callee");
+    assert_eq!(calls["calls"][1]["callId"], "old-call");
+    assert!(calls["nextCursor"].is_null());
+
+    let page: Value = get("/api/calls?limit=1".to_string()).await.json().await?;
+    assert_eq!(page["calls"][0]["callId"], "c1");
+    let cursor = page["nextCursor"].as_str().unwrap();
+    let page: Value = get(format!("/api/calls?limit=1&cursor={}", cursor))
+        .await
+        .json()
+        .await?;
+    assert_eq!(page["calls"][0]["callId"], "old-call");
+
+    let filtered: Value =
+        get("/api/calls?extra=tier%3Dgold&minDuration=60&callee=support".to_string())
+            .await
+            .json()
+            .await?;
+    assert_eq!(filtered["calls"].as_array().unwrap().len(), 1);
+    let filtered: Value = get("/api/calls?playbook=sales.md".to_string())
+        .await
+        .json()
+        .await?;
+    assert!(filtered["calls"].as_array().unwrap().is_empty());
+    assert_eq!(
+        get("/api/calls?cursor=bogus".to_string()).await.status(),
+        400
+    );
+
+    let call: Value = get("/api/calls/c1".to_string()).await.json().await?;
+    assert_eq!(call["record"]["callId"], "c1");
+    assert_eq!(call["transcript"][1]["text"], "My order is late");
+    let recording_url = call["recordings"][0]["url"].as_str().unwrap();
+    assert_eq!(recording_url, "/api/calls/c1/recordings/0");
+    let response = client
+        .get(format!("{}{}", base, recording_url))
+        .header("Range", "bytes=0-3")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes().await?.as_ref(), b"RIFF");
+    let response = get(call["transcripts"][0]["url"].as_str().unwrap().to_string()).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        get("/api/calls/c1/recordings/1".to_string()).await.status(),
+        404
+    );
+    assert_eq!(get("/api/calls/missing".to_string()).await.status(), 404);
+
+    let response = get("/api/calls/export?playbook=support.md".to_string()).await;
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await?;
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(
+        lines[1].starts_with("c1,sip,sip:alice@example.com,"),
+        "{}",
+        csv
+    );
+    Ok(())
+}
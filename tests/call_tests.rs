@@ -1,6 +1,6 @@
 use active_call::app::AppStateBuilder;
 use active_call::call::Command;
-use active_call::config::Config;
+use active_call::config::{Config, RecordingPolicy};
 use active_call::event::EventSender;
 use active_call::handler::call_router;
 use active_call::media::SourcePacket;
@@ -69,6 +69,7 @@ impl MockAsrClientBuilder {
                             confidence: None,
                             task_id: None,
                             refer: None,
+                            words: None,
                         };
                         let _ = event_sender.send(event);
                     }
@@ -144,9 +145,14 @@ async fn test_webrtc_call_workflow() -> Result<()> {
         listener.local_addr()?.port()
     };
 
+    let recorder_dir = tempfile::tempdir()?;
     let mut config = Config::default();
     config.http_addr = format!("127.0.0.1:{}", port);
     config.udp_port = 0;
+    config.recording = Some(RecordingPolicy {
+        path: Some(recorder_dir.path().to_string_lossy().to_string()),
+        ..Default::default()
+    });
     let http_addr = config.http_addr.clone();
 
     let app_state = AppStateBuilder::new()
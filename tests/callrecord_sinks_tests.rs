@@ -0,0 +1,443 @@
+use active_call::callrecord::kafka::{self, API_METADATA, API_PRODUCE};
+use active_call::callrecord::*;
+use active_call::config::CallRecordConfig;
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::Utc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc;
+
+fn record(call_id: &str) -> CallRecord {
+    CallRecord {
+        call_id: call_id.to_string(),
+        caller: "sip:alice@example.com".to_string(),
+        callee: "sip:bob@example.com".to_string(),
+        start_time: Utc::now(),
+        end_time: Utc::now(),
+        status_code: 200,
+        hangup_reason: Some(CallRecordHangupReason::ByCaller),
+        hangup_messages: vec![CallRecordHangupMessage {
+            code: 200,
+            reason: Some("OK".to_string()),
+            target: Some("sip:bob@example.com".to_string()),
+            trunk: None,
+        }],
+        recorder: vec![CallRecordMedia {
+            track_id: call_id.to_string(),
+            path: format!("/recordings/{}.wav", call_id),
+            size: 1024,
+            extra: None,
+        }],
+        ..Default::default()
+    }
+}
+
+async fn save_to_sink(
+    config: &CallRecordConfig,
+    record: &mut CallRecord,
+) -> anyhow::Result<String> {
+    save_with(&SinkClients::default(), config, record).await
+}
+
+async fn save_with(
+    clients: &SinkClients,
+    config: &CallRecordConfig,
+    record: &mut CallRecord,
+) -> anyhow::Result<String> {
+    let formatter = Arc::new(DefaultCallRecordFormatter::new_with_config(config));
+    CallRecordManager::save_to(formatter, clients, config, record).await
+}
+
+type Produced = mpsc::UnboundedReceiver<(i32, Vec<u8>, Vec<u8>)>;
+
+/// Answers Metadata with itself as the leader of partitions 0 and 1 and
+/// forwards the key and value of every produced record, after rejecting the
+/// first `not_leader` produces with NOT_LEADER_OR_FOLLOWER. Also counts the
+/// connections it accepted.
+async fn mock_kafka_broker(not_leader: usize) -> (String, Produced, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    let not_leader = Arc::new(AtomicUsize::new(not_leader));
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let tx = tx.clone();
+            let not_leader = not_leader.clone();
+            tokio::spawn(async move {
+                while let Ok(size) = stream.read_i32().await {
+                    let mut request = vec![0u8; size as usize];
+                    stream.read_exact(&mut request).await.unwrap();
+                    let mut request = Bytes::from(request);
+                    let api_key = kafka::get_i16(&mut request).unwrap();
+                    let _api_version = kafka::get_i16(&mut request).unwrap();
+                    let correlation_id = kafka::get_i32(&mut request).unwrap();
+                    let _client_id = kafka::get_nullable_string(&mut request).unwrap();
+
+                    let mut response = BytesMut::new();
+                    response.put_i32(correlation_id);
+                    match api_key {
+                        API_METADATA => {
+                            assert_eq!(kafka::get_i32(&mut request).unwrap(), 1);
+                            let topic = kafka::get_string(&mut request).unwrap();
+                            response.put_i32(1);
+                            response.put_i32(7);
+                            kafka::put_string(&mut response, &address.ip().to_string());
+                            response.put_i32(address.port() as i32);
+                            response.put_i16(-1);
+                            response.put_i32(7);
+                            response.put_i32(1);
+                            response.put_i16(0);
+                            kafka::put_string(&mut response, &topic);
+                            response.put_i8(0);
+                            response.put_i32(2);
+                            for partition in 0..2 {
+                                response.put_i16(0);
+                                response.put_i32(partition);
+                                response.put_i32(7);
+                                response.put_i32(1);
+                                response.put_i32(7);
+                                response.put_i32(1);
+                                response.put_i32(7);
+                            }
+                        }
+                        API_PRODUCE => {
+                            let _transactional_id =
+                                kafka::get_nullable_string(&mut request).unwrap();
+                            assert_eq!(kafka::get_i16(&mut request).unwrap(), -1);
+                            let _timeout = kafka::get_i32(&mut request).unwrap();
+                            assert_eq!(kafka::get_i32(&mut request).unwrap(), 1);
+                            let topic = kafka::get_string(&mut request).unwrap();
+                            assert_eq!(kafka::get_i32(&mut request).unwrap(), 1);
+                            let partition = kafka::get_i32(&mut request).unwrap();
+                            let len = kafka::get_i32(&mut request).unwrap();
+                            let batch = request.split_to(len as usize);
+                            let (key, value) = kafka::parse_record_batch(batch).unwrap();
+                            let rejected = not_leader
+                                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                                    n.checked_sub(1)
+                                })
+                                .is_ok();
+                            if !rejected {
+                                tx.send((partition, key, value)).unwrap();
+                            }
+
+                            response.put_i32(1);
+                            kafka::put_string(&mut response, &topic);
+                            response.put_i32(1);
+                            response.put_i32(partition);
+                            response.put_i16(if rejected { 6 } else { 0 });
+                            response.put_i64(42);
+                            response.put_i64(-1);
+                            response.put_i32(0);
+                        }
+                        other => panic!("unexpected kafka api {}", other),
+                    }
+                    stream.write_i32(response.len() as i32).await.unwrap();
+                    stream.write_all(&response).await.unwrap();
+                }
+            });
+        }
+    });
+    (address.to_string(), rx, connections)
+}
+
+/// Checks the credentials of each client, forwards the subject and payload
+/// of every PUB and counts the connections it accepted
+async fn mock_nats_server() -> (
+    String,
+    mpsc::UnboundedReceiver<(String, Vec<u8>)>,
+    Arc<AtomicUsize>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                stream
+                    .get_mut()
+                    .write_all(b"INFO {\"server_id\":\"mock\",\"max_payload\":1048576}\r\n")
+                    .await
+                    .unwrap();
+                let mut line = String::new();
+                while stream.read_line(&mut line).await.unwrap() > 0 {
+                    let command = line.trim_end().to_string();
+                    line.clear();
+                    if let Some(connect) = command.strip_prefix("CONNECT ") {
+                        let connect: serde_json::Value = serde_json::from_str(connect).unwrap();
+                        if connect["user"] != "cdr" || connect["pass"] != "secret" {
+                            stream
+                                .get_mut()
+                                .write_all(b"-ERR 'Authorization Violation'\r\n")
+                                .await
+                                .unwrap();
+                            return;
+                        }
+                    } else if let Some(publish) = command.strip_prefix("PUB ") {
+                        let (subject, len) = publish.split_once(' ').unwrap();
+                        let mut payload = vec![0u8; len.parse::<usize>().unwrap() + 2];
+                        stream.read_exact(&mut payload).await.unwrap();
+                        payload.truncate(payload.len() - 2);
+                        tx.send((subject.to_string(), payload)).unwrap();
+                    } else if command == "PING" {
+                        stream.get_mut().write_all(b"PONG\r\n").await.unwrap();
+                    }
+                }
+            });
+        }
+    });
+    (address, rx, connections)
+}
+
+#[tokio::test]
+async fn test_kafka_sink_with_mock_broker() {
+    let (broker, mut produced, _) = mock_kafka_broker(0).await;
+    let config = CallRecordConfig::Kafka {
+        brokers: vec!["127.0.0.1:1".to_string(), broker],
+        topic: "cdr".to_string(),
+        acks: None,
+        timeout_ms: Some(2000),
+    };
+    save_to_sink(&config, &mut record("kafka-call"))
+        .await
+        .unwrap();
+    let (partition, key, value) = produced.recv().await.unwrap();
+    assert_eq!(key, b"kafka-call");
+    assert_eq!(
+        partition,
+        (kafka::murmur2(b"kafka-call") & 0x7fff_ffff) % 2,
+        "partitioned like the Java client"
+    );
+    let cdr: serde_json::Value = serde_json::from_slice(&value).unwrap();
+    assert_eq!(cdr["callId"], "kafka-call");
+}
+
+#[tokio::test]
+async fn test_kafka_sink_retries_moved_leader_on_kept_connection() {
+    let (broker, mut produced, connections) = mock_kafka_broker(1).await;
+    let config = CallRecordConfig::Kafka {
+        brokers: vec![broker],
+        topic: "cdr".to_string(),
+        acks: None,
+        timeout_ms: Some(2000),
+    };
+    let clients = SinkClients::default();
+    for call_id in ["kafka-1", "kafka-2"] {
+        save_with(&clients, &config, &mut record(call_id))
+            .await
+            .unwrap();
+        let (_, key, _) = produced.recv().await.unwrap();
+        assert_eq!(key, call_id.as_bytes());
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_nats_sink_with_mock_server() {
+    let (address, mut published, connections) = mock_nats_server().await;
+    let config = CallRecordConfig::Nats {
+        url: format!("nats://cdr:secret@{}", address),
+        subject: "calls.cdr".to_string(),
+        token: None,
+    };
+    let clients = SinkClients::default();
+    for call_id in ["nats-call", "nats-call-2"] {
+        save_with(&clients, &config, &mut record(call_id))
+            .await
+            .unwrap();
+        let (subject, payload) = published.recv().await.unwrap();
+        assert_eq!(subject, "calls.cdr");
+        let cdr: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(cdr["callId"], call_id);
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    let rejected = CallRecordConfig::Nats {
+        url: format!("nats://cdr:wrong@{}", address),
+        subject: "calls.cdr".to_string(),
+        token: None,
+    };
+    let err = save_to_sink(&rejected, &mut record("nats-call"))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("Authorization Violation"),
+        "{}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn test_syslog_sink_udp_and_tcp() {
+    let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let config = CallRecordConfig::Syslog {
+        address: format!("udp://{}", udp.local_addr().unwrap()),
+        facility: Some(23),
+        hostname: Some("pbx1".to_string()),
+        app_name: None,
+    };
+    save_to_sink(&config, &mut record("syslog-call"))
+        .await
+        .unwrap();
+    let mut datagram = vec![0u8; 65536];
+    let len = udp.recv(&mut datagram).await.unwrap();
+    let message = String::from_utf8(datagram[..len].to_vec()).unwrap();
+    assert!(
+        message.starts_with("<190>1 "),
+        "local7.info priority: {}",
+        message
+    );
+    assert!(message.contains(" pbx1 active-call - cdr [cdr@32473 callId=\"syslog-call\""));
+    let (_, body) = message.split_once('\u{feff}').unwrap();
+    let cdr: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(cdr["callId"], "syslog-call");
+
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = CallRecordConfig::Syslog {
+        address: format!("tcp://{}", tcp.local_addr().unwrap()),
+        facility: None,
+        hostname: None,
+        app_name: None,
+    };
+    let receive = tokio::spawn(async move {
+        let (mut stream, _) = tcp.accept().await.unwrap();
+        let mut framed = String::new();
+        stream.read_to_string(&mut framed).await.unwrap();
+        framed
+    });
+    save_to_sink(&config, &mut record("syslog-tcp"))
+        .await
+        .unwrap();
+    let framed = receive.await.unwrap();
+    let (len, message) = framed.split_once(' ').unwrap();
+    assert_eq!(len.parse::<usize>().unwrap(), message.len());
+    assert!(message.starts_with("<134>1 "));
+}
+
+#[cfg(feature = "cdr-sql")]
+#[tokio::test]
+async fn test_database_sink_sqlite() {
+    use sqlx::Row;
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}?mode=rwc", dir.path().join("cdr.db").display());
+    let config = CallRecordConfig::Database { url: url.clone() };
+
+    let mut first = record("db-call");
+    save_to_sink(&config, &mut first).await.unwrap();
+    // A retried delivery replaces the rows of the call
+    first.status_code = 486;
+    first.hangup_messages.push(CallRecordHangupMessage {
+        code: 486,
+        reason: Some("Busy Here".to_string()),
+        target: None,
+        trunk: Some("carrier-a".to_string()),
+    });
+    save_to_sink(&config, &mut first).await.unwrap();
+    save_to_sink(&config, &mut record("db-call-2"))
+        .await
+        .unwrap();
+
+    let pool = sqlx::SqlitePool::connect(&url).await.unwrap();
+    let row = sqlx::query(
+        "SELECT call_type, status_code, hangup_reason, record FROM call_records WHERE call_id = 'db-call'",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(row.get::<String, _>(0), "sip");
+    assert_eq!(row.get::<i64, _>(1), 486);
+    assert_eq!(row.get::<String, _>(2), "caller");
+    let cdr: serde_json::Value = serde_json::from_str(&row.get::<String, _>(3)).unwrap();
+    assert_eq!(cdr["callId"], "db-call");
+
+    let count = |sql: &'static str| {
+        let pool = pool.clone();
+        async move {
+            sqlx::query(sql)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .get::<i64, _>(0)
+        }
+    };
+    assert_eq!(count("SELECT COUNT(*) FROM call_records").await, 2);
+    assert_eq!(
+        count(
+            "SELECT COUNT(*) FROM call_record_media WHERE call_id = 'db-call' AND kind = 'recorder'"
+        )
+        .await,
+        1
+    );
+    assert_eq!(
+        count("SELECT COUNT(*) FROM call_record_hangup_messages WHERE call_id = 'db-call'").await,
+        2
+    );
+    assert_eq!(
+        count("SELECT code FROM call_record_hangup_messages WHERE call_id = 'db-call' AND trunk = 'carrier-a'").await,
+        486
+    );
+}
+
+#[tokio::test]
+async fn test_fanout_reports_failed_sinks() {
+    let (address, mut published, _) = mock_nats_server().await;
+    let dir = tempfile::tempdir().unwrap();
+    let config = CallRecordConfig::Fanout {
+        sinks: vec![
+            CallRecordConfig::Local {
+                root: dir.path().to_string_lossy().to_string(),
+            },
+            CallRecordConfig::Kafka {
+                brokers: vec!["127.0.0.1:1".to_string()],
+                topic: "cdr".to_string(),
+                acks: None,
+                timeout_ms: Some(500),
+            },
+            CallRecordConfig::Nats {
+                url: format!("nats://cdr:secret@{}", address),
+                subject: "cdr".to_string(),
+                token: None,
+            },
+        ],
+    };
+    let err = save_to_sink(&config, &mut record("fanout-call"))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("1 of 3 call record sinks failed"),
+        "{}",
+        err
+    );
+    assert_eq!(err.downcast_ref::<FanoutError>().unwrap().failed, vec![1]);
+
+    // The sinks after the failed one still got the record
+    let (_, payload) = published.recv().await.unwrap();
+    let cdr: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+    assert_eq!(cdr["callId"], "fanout-call");
+    let files: Vec<_> = walk(dir.path());
+    assert!(files.iter().any(|f| f.ends_with(".json")), "{:?}", files);
+}
+
+fn walk(dir: &std::path::Path) -> Vec<String> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap().flatten() {
+        if entry.path().is_dir() {
+            files.extend(walk(&entry.path()));
+        } else {
+            files.push(entry.path().to_string_lossy().to_string());
+        }
+    }
+    files
+}
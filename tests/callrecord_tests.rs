@@ -351,3 +351,146 @@ async fn test_save_with_s3_like_with_media() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_spooled_record_retried_after_failure() {
+    use active_call::callrecord::spool::{CallRecordSpool, SpoolState};
+    use active_call::config::CallRecordSpoolConfig;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let dir = tempfile::tempdir().unwrap();
+    let spool = Arc::new(
+        CallRecordSpool::new(&CallRecordSpoolConfig {
+            root: Some(dir.path().to_string_lossy().to_string()),
+            initial_backoff_secs: Some(0),
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let attempts_ref = attempts.clone();
+    let saver: FnSaveCallRecord = Arc::new(Box::new(move |_, _, _, record| {
+        let attempt = attempts_ref.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move {
+            assert_eq!(record.call_id, "spooled_call");
+            if attempt == 0 {
+                Err(anyhow::anyhow!("endpoint down"))
+            } else {
+                Ok(())
+            }
+        })
+    }));
+    let token = tokio_util::sync::CancellationToken::new();
+    let mut manager = CallRecordManagerBuilder::new()
+        .with_cancel_token(token.clone())
+        .with_saver(saver)
+        .with_spool(spool.clone())
+        .build();
+    manager
+        .sender
+        .send(CallRecord {
+            call_id: "spooled_call".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+    tokio::spawn(async move { manager.serve().await });
+
+    let delivered = async {
+        while attempts.load(Ordering::SeqCst) < 2
+            || !spool.list(SpoolState::Pending).await.is_empty()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(15), delivered)
+        .await
+        .expect("spooled record was not retried");
+    assert!(spool.list(SpoolState::Dead).await.is_empty());
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_spooled_fanout_retries_failed_sinks_only() {
+    use active_call::callrecord::spool::{CallRecordSpool, SpoolState};
+    use active_call::config::{CallRecordConfig, CallRecordSpoolConfig};
+    use std::sync::Mutex;
+
+    let dir = tempfile::tempdir().unwrap();
+    let spool = Arc::new(
+        CallRecordSpool::new(&CallRecordSpoolConfig {
+            root: Some(dir.path().to_string_lossy().to_string()),
+            initial_backoff_secs: Some(0),
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let sink = |root: &str| CallRecordConfig::Local {
+        root: root.to_string(),
+    };
+    let config = CallRecordConfig::Fanout {
+        sinks: vec![sink("first"), sink("second"), sink("third")],
+    };
+    // Sinks each attempt was given, the second one failing the first time
+    let attempts = Arc::new(Mutex::new(Vec::<Vec<String>>::new()));
+    let attempts_ref = attempts.clone();
+    let saver: FnSaveCallRecord = Arc::new(Box::new(move |_, _, config, _| {
+        let CallRecordConfig::Fanout { sinks } = config.as_ref() else {
+            panic!("not a fanout");
+        };
+        let roots: Vec<String> = sinks
+            .iter()
+            .filter_map(|sink| sink.local_root().map(str::to_string))
+            .collect();
+        let mut attempts = attempts_ref.lock().unwrap();
+        let result = if attempts.is_empty() {
+            Err(FanoutError {
+                failed: vec![1],
+                total: roots.len(),
+                errors: vec!["endpoint down".to_string()],
+            }
+            .into())
+        } else {
+            Ok(())
+        };
+        attempts.push(roots);
+        Box::pin(async move { result })
+    }));
+    let token = tokio_util::sync::CancellationToken::new();
+    let mut manager = CallRecordManagerBuilder::new()
+        .with_cancel_token(token.clone())
+        .with_config(config)
+        .with_saver(saver)
+        .with_spool(spool.clone())
+        .build();
+    manager
+        .sender
+        .send(CallRecord {
+            call_id: "fanout_call".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+    tokio::spawn(async move { manager.serve().await });
+
+    let delivered = async {
+        while attempts.lock().unwrap().len() < 2
+            || !spool.list(SpoolState::Pending).await.is_empty()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(15), delivered)
+        .await
+        .expect("spooled record was not retried");
+    assert_eq!(
+        *attempts.lock().unwrap(),
+        vec![
+            vec![
+                "first".to_string(),
+                "second".to_string(),
+                "third".to_string()
+            ],
+            vec!["second".to_string()],
+        ]
+    );
+    token.cancel();
+}
@@ -168,6 +168,7 @@ async fn test_scene_transition_logic() -> Result<()> {
         confidence: Some(1.0),
         task_id: None,
         refer: None,
+        words: None,
     };
 
     let commands = handler.on_event(&event).await?;
@@ -102,7 +102,7 @@ async fn test_offline_integration() -> anyhow::Result<()> {
 
     println!("Inferencing ASR...");
     println!("Running ASR inference...");
-    let recognized_text = asr.run_and_decode(feats.view(), language_code, true)?;
+    let recognized_text = asr.run_and_decode(feats.view(), language_code, true)?.text;
 
     println!("Recognized: '{}'", recognized_text);
 
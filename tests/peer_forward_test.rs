@@ -4,7 +4,7 @@
 use active_call::{
     app::AppStateBuilder,
     call::active_call::CallParams,
-    config::Config,
+    config::{Config, RecordingPolicy},
     handler::peer::{try_forward, tunnel},
 };
 use axum::{
@@ -155,6 +155,7 @@ async fn try_forward_skips_rejecting_peer_and_accepts_hosting_peer() {
         server_side_track: None,
         forward: None,
         visited: None,
+        protocol: None,
     };
 
     let ws = try_forward(&app_state, "sess-1", &params).await;
@@ -187,6 +188,7 @@ async fn try_forward_returns_none_when_all_peers_reject() {
         server_side_track: None,
         forward: None,
         visited: None,
+        protocol: None,
     };
 
     assert!(
@@ -221,6 +223,7 @@ async fn try_forward_only_when_forward_is_empty() {
             server_side_track: None,
             forward,
             visited: None,
+            protocol: None,
         };
         assert!(
             try_forward(&app_state, "sess-nested", &params)
@@ -232,12 +235,17 @@ async fn try_forward_only_when_forward_is_empty() {
 }
 
 /// Helper: build a full AppState-backed call router with no peers configured.
-async fn local_node_app_state() -> active_call::app::AppState {
+/// Session event dumps go to `recorder_dir`.
+async fn local_node_app_state(recorder_dir: &std::path::Path) -> active_call::app::AppState {
     let mut config = Config::default();
     config.addr = "127.0.0.1".to_string();
     config.udp_port = 0;
     config.http_addr = "0.0.0.0:8080".to_string();
     config.media_cache_path = "./target/tmp_media_test".to_string();
+    config.recording = Some(RecordingPolicy {
+        path: Some(recorder_dir.to_string_lossy().to_string()),
+        ..Default::default()
+    });
     AppStateBuilder::new()
         .with_config(config)
         .build()
@@ -249,7 +257,8 @@ async fn local_node_app_state() -> active_call::app::AppState {
 /// 404 (so the originator polls its next peer) and must NOT create the call.
 #[tokio::test]
 async fn forward_true_gets_404_and_does_not_create_call() {
-    let app_state = local_node_app_state().await;
+    let recorder_dir = tempfile::tempdir().unwrap();
+    let app_state = local_node_app_state(recorder_dir.path()).await;
     let router = active_call::handler::call_router().with_state(app_state.clone());
     let (_task, port) = spawn_axum(router).await;
 
@@ -274,7 +283,8 @@ async fn forward_true_gets_404_and_does_not_create_call() {
 /// back to creating the call locally (pre-forwarding behaviour preserved).
 #[tokio::test]
 async fn no_forward_creates_call_locally_when_no_peers() {
-    let app_state = local_node_app_state().await;
+    let recorder_dir = tempfile::tempdir().unwrap();
+    let app_state = local_node_app_state(recorder_dir.path()).await;
     let router = active_call::handler::call_router().with_state(app_state.clone());
     let (_task, port) = spawn_axum(router).await;
 
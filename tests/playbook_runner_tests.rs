@@ -166,6 +166,7 @@ async fn test_playbook_run_flow() -> Result<()> {
         confidence: None,
         task_id: None,
         refer: None,
+        words: None,
     };
 
     // Send event
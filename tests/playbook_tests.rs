@@ -137,6 +137,7 @@ async fn test_aliyun_llm_integration() {
         scenes: Default::default(),
         initial_scene_id: None,
         raw_content: String::new(),
+        lexicon: None,
     };
 
     let mut history = Vec::new();
@@ -1,8 +1,12 @@
 /// Test WebRTC audio reception to diagnose the issue where browser audio is not received
-use active_call::{app::AppStateBuilder, config::Config};
+use active_call::{
+    app::AppStateBuilder,
+    config::{Config, RecordingPolicy},
+};
 use anyhow::Result;
 use futures::{SinkExt, StreamExt};
 use serde_json::json;
+use std::path::Path;
 use std::time::Duration;
 use tokio::time::timeout;
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
@@ -38,7 +42,7 @@ a=ssrc:12345 cname:test-cname
 a=ssrc:12345 msid:test-stream audio-track-1
 "#;
 
-fn create_test_config(http_port: u16, sip_port: u16) -> Config {
+fn create_test_config(http_port: u16, sip_port: u16, recorder_dir: &Path) -> Config {
     Config {
         http_addr: format!("127.0.0.1:{}", http_port),
         addr: "0.0.0.0".to_string(),
@@ -50,6 +54,10 @@ fn create_test_config(http_port: u16, sip_port: u16) -> Config {
         rtp_start_port: Some(40000),
         rtp_end_port: Some(40100),
         media_cache_path: "./target/tmp_media_test".to_string(),
+        recording: Some(RecordingPolicy {
+            path: Some(recorder_dir.to_string_lossy().to_string()),
+            ..Default::default()
+        }),
         ..Default::default()
     }
 }
@@ -74,7 +82,8 @@ async fn test_webrtc_audio_reception_with_hello_playbook() -> Result<()> {
         http_port, sip_port
     );
 
-    let config = create_test_config(http_port, sip_port);
+    let recorder_dir = tempfile::tempdir()?;
+    let config = create_test_config(http_port, sip_port, recorder_dir.path());
     let builder = AppStateBuilder::new().with_config(config.clone());
     let app_state = builder.build().await?;
 
@@ -253,7 +262,8 @@ async fn test_webrtc_track_creation() -> Result<()> {
 
     info!("Test: Verifying WebRTC track creation");
 
-    let config = create_test_config(http_port, sip_port);
+    let recorder_dir = tempfile::tempdir()?;
+    let config = create_test_config(http_port, sip_port, recorder_dir.path());
     let builder = AppStateBuilder::new().with_config(config.clone());
     let app_state = builder.build().await?;
 
@@ -1,5 +1,5 @@
 use active_call::app::AppStateBuilder;
-use active_call::config::Config;
+use active_call::config::{Config, RecordingPolicy};
 use active_call::event::SessionEvent;
 use active_call::handler::call_router;
 use active_call::media::track::file::read_wav_file;
@@ -30,9 +30,14 @@ async fn test_webrtc_vad_link() -> Result<()> {
         listener.local_addr()?.port()
     };
 
+    let recorder_dir = tempfile::tempdir()?;
     let mut config = Config::default();
     config.http_addr = format!("127.0.0.1:{}", port);
     config.udp_port = 0;
+    config.recording = Some(RecordingPolicy {
+        path: Some(recorder_dir.path().to_string_lossy().to_string()),
+        ..Default::default()
+    });
     let http_addr = config.http_addr.clone();
 
     let app_state = AppStateBuilder::new().with_config(config).build().await?;